@@ -0,0 +1,194 @@
+#![forbid(unsafe_code)]
+// Push-style tree building, for data that arrives one block at a time
+
+use crate::{fill_child_hash, HashArray, HelperOptions, StreamingNodeHash};
+use crate::merkle_utils::{BlockRange, HashData, HashRange, TreeOptions};
+use crate::merkle_utils::branch_t;
+
+use std::convert::{TryFrom, TryInto};
+
+use digest::Digest;
+
+// Finished node whose parent does not have all of its children yet
+struct PendingNode<D: Digest> {
+    start_block: u64,
+    start_byte: u64,
+    byte_len: u64,
+    hash: HashArray<D>
+}
+
+/// Builds a Merkle tree from blocks pushed one at a time.
+///
+/// Each pushed block is one leaf, so the tree matches the one that
+/// [`merkle_hash_file_with_options`](crate::merkle_hash_file_with_options)
+/// computes over the concatenated blocks, as long as every block except
+/// the last has the same length. Only the nodes whose parents are still
+/// incomplete are kept, so memory use grows with the height of the tree.
+///
+/// `content_defined` and `stride` in [`TreeOptions`] have no effect, as
+/// the blocks are used as given. Pushing content-defined chunks gives the
+/// tree that `content_defined` would give for the same chunks.
+///
+/// ```
+/// use merkle_tree::MerkleTreeBuilder;
+/// use sha2::{Digest, Sha256};
+///
+/// let mut builder = MerkleTreeBuilder::<Sha256>::new(2);
+/// builder.push_block(b"abcd");
+/// builder.push_block(b"1234");
+/// let (root, nodes) = builder.finish();
+///
+/// let leaf0_hash = Sha256::digest(b"\x00abcd");
+/// let leaf1_hash = Sha256::digest(b"\x001234");
+/// let root_hash = Sha256::digest([b"\x01", leaf0_hash.as_slice(),
+///     leaf1_hash.as_slice()].concat());
+/// assert_eq!(root.as_ref(), root_hash.as_slice());
+/// // Nodes are in the order of merkle_block_generator, with the root last
+/// assert_eq!(nodes.len(), 3);
+/// assert_eq!(nodes[2].hash_result(), root.as_ref());
+/// ```
+pub struct MerkleTreeBuilder<D: Digest> {
+    branch: branch_t,
+    options: HelperOptions,
+    // Indexed by level, with leaves at level 0
+    pending: Vec<Vec<PendingNode<D>>>,
+    leaf_count: u64,
+    byte_count: u64,
+    nodes: Vec<HashRange>
+}
+impl<D: Digest> MerkleTreeBuilder<D> {
+    /// Starts an empty tree with the default [`TreeOptions`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `branch` is less than 2.
+    pub fn new(branch: branch_t) -> Self {
+        Self::with_options(branch, &TreeOptions::default())
+    }
+    /// Starts an empty tree with all [`TreeOptions`] given.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `branch` is less than 2.
+    pub fn with_options(branch: branch_t, options: &TreeOptions) -> Self {
+        assert!(branch >= 2);
+        assert!(<D as Digest>::output_size() <= crate::merkle_utils::MAX_HASH_LEN);
+        MerkleTreeBuilder {
+            branch,
+            options: HelperOptions {
+                node_encoding: options.node_encoding,
+                salt: options.salt.as_slice().into(),
+                pad_tree: options.pad_tree,
+                promote_lone_children: options.promote_lone_children,
+                duplicate_last: options.duplicate_last,
+                // Blocks pushed to the builder have no fixed size to round up to
+                nominal_block_size: None,
+                empty_leaf_range: options.empty_leaf_range,
+                // Blocks are pushed rather than read, so there is no position
+                // to check or read to retry
+                verify_positions: false,
+                read_retries: 0
+            },
+            pending: vec![Vec::new()],
+            leaf_count: 0,
+            byte_count: 0,
+            nodes: Vec::new()
+        }
+    }
+    /// Hashes the next leaf of the tree.
+    ///
+    /// Every internal node that this block completes is hashed as well.
+    pub fn push_block(&mut self, block: &[u8]) {
+        let mut digest_obj = D::new_with_prefix([0x00]);
+        digest_obj.update(&self.options.salt);
+        digest_obj.update(block);
+        let leaf = PendingNode {
+            start_block: self.leaf_count,
+            start_byte: self.byte_count,
+            byte_len: block.len() as u64,
+            hash: digest_obj.finalize()
+        };
+        self.leaf_count += 1;
+        self.byte_count += leaf.byte_len;
+        self.push_node(0, 1, leaf);
+
+        let mut level = 0;
+        while self.pending[level].len() == usize::from(self.branch) {
+            self.fold_level(level);
+            level += 1;
+        }
+    }
+    /// Hashes the remaining internal nodes and returns the root hash
+    /// with every node of the tree.
+    ///
+    /// Nodes are in the order of [`merkle_block_generator`](crate::merkle_block_generator),
+    /// as with a `thread_count` of 0. A tree with no blocks has a single
+    /// empty leaf, as for an empty file.
+    pub fn finish(mut self) -> (HashData<64>, Vec<HashRange>) {
+        if self.leaf_count == 0 {
+            self.push_block(&[]);
+        }
+        let mut height = 0;
+        let mut level_blocks: u64 = 1;
+        while level_blocks < self.leaf_count {
+            level_blocks *= u64::from(self.branch);
+            height += 1;
+        }
+        // Nodes on the right edge of the tree may be missing children
+        for level in 0..height {
+            if !self.pending[level].is_empty() {
+                self.fold_level(level);
+            }
+        }
+        let root = self.pending[height].pop().unwrap();
+        debug_assert!(self.pending.iter().all(|level_nodes| level_nodes.is_empty()));
+        (HashData::try_new(&root.hash).unwrap(), self.nodes)
+    }
+
+    // Records a finished node covering block_interval blocks
+    fn push_node(&mut self, level: usize, block_interval: u64, node: PendingNode<D>) {
+        let byte_range = match self.options.empty_leaf_range && node.byte_len == 0 {
+            true => BlockRange::empty(node.start_byte),
+            false => BlockRange::new(node.start_byte,
+                (node.start_byte + node.byte_len).saturating_sub(1), true)
+        };
+        self.nodes.push(HashRange::new(
+            BlockRange::new(node.start_block, node.start_block + block_interval - 1, true),
+            byte_range,
+            HashData::try_new(&node.hash).unwrap()));
+        if self.pending.len() == level {
+            self.pending.push(Vec::new());
+        }
+        self.pending[level].push(node);
+    }
+    // Hashes the nodes at level into their parent at the next level
+    fn fold_level(&mut self, level: usize) {
+        let children = std::mem::take(&mut self.pending[level]);
+        let child_interval = u64::from(self.branch).pow(level.try_into().unwrap());
+        let file_children = branch_t::try_from(children.len()).unwrap();
+        let child_count = match self.options.pad_tree || self.options.duplicate_last {
+            true => self.branch,
+            false => file_children
+        };
+        let mut node_hash = StreamingNodeHash::<D>::new(self.options.node_encoding,
+            child_count, self.options.promote_lone_children);
+        for child in children.iter() {
+            node_hash.add_child(child.byte_len, &child.hash);
+        }
+        if child_count > file_children {
+            // Padding and repeated children do not cover any bytes
+            let child_hash = fill_child_hash::<D>(children.last().unwrap().hash.clone(),
+                child_interval, self.branch, &self.options);
+            for _ in file_children..child_count {
+                node_hash.add_child(0, &child_hash);
+            }
+        }
+        let node = PendingNode {
+            start_block: children[0].start_block,
+            start_byte: children[0].start_byte,
+            byte_len: children.iter().map(|child| child.byte_len).sum(),
+            hash: node_hash.finalize()
+        };
+        self.push_node(level + 1, child_interval * u64::from(self.branch), node);
+    }
+}
@@ -0,0 +1,151 @@
+#![forbid(unsafe_code)]
+// Content-defined chunk boundaries from a gear rolling hash
+
+use crate::merkle_utils::block_t;
+
+use std::io::{ErrorKind, Read, Result as IOResult};
+
+// splitmix64, so the gear table can be regenerated from the format spec
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (state, z ^ (z >> 31))
+}
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state = 0;
+    let mut i = 0;
+    while i < 256 {
+        let (next_state, value) = splitmix64(state);
+        table[i] = value;
+        state = next_state;
+        i += 1;
+    }
+    table
+}
+
+const GEAR: [u64; 256] = gear_table();
+
+const READ_BUF_LEN: usize = 64 * 1024;
+
+/// Smallest and largest chunk lengths for a target chunk size.
+///
+/// Chunks are at least a quarter and at most four times `avg_size`,
+/// except for a shorter last chunk.
+pub fn chunk_len_bounds(avg_size: block_t) -> (u64, u64) {
+    let avg_size = u64::from(avg_size);
+    ((avg_size / 4).max(1), avg_size * 4)
+}
+
+/// Finds content-defined chunk boundaries in the data read from `reader`.
+///
+/// Each byte updates a gear hash as `hash = (hash << 1) + GEAR[byte]`,
+/// and a chunk ends after a byte that leaves the top `log2(avg_size)`
+/// bits of the hash zero, so boundaries only depend on the last 64 bytes.
+/// The hash restarts at 0 with each chunk, bytes before the minimum chunk
+/// length are not checked, and chunks are cut at the maximum length
+/// regardless of the hash. Inserting bytes into the data only moves the
+/// boundaries near the insertion.
+///
+/// Returns the end offset of each chunk, which is empty for empty data.
+///
+/// # Panics
+///
+/// Panics if `avg_size` is not a power of 2.
+pub fn chunk_boundaries<R: Read>(mut reader: R, avg_size: block_t) -> IOResult<Vec<u64>> {
+    assert!(avg_size.is_power_of_two());
+    let (min_len, max_len) = chunk_len_bounds(avg_size);
+    let boundary_mask = match avg_size.trailing_zeros() {
+        0 => 0,
+        bits => u64::MAX << (64 - bits)
+    };
+
+    let mut boundaries: Vec<u64> = Vec::new();
+    let mut buf = vec![0x00; READ_BUF_LEN];
+    let mut pos: u64 = 0;
+    let mut chunk_start: u64 = 0;
+    let mut hash: u64 = 0;
+    loop {
+        let read_len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        };
+        for byte in &buf[..read_len] {
+            pos += 1;
+            hash = (hash << 1).wrapping_add(GEAR[usize::from(*byte)]);
+            let chunk_len = pos - chunk_start;
+            if (chunk_len >= min_len && hash & boundary_mask == 0) || chunk_len == max_len {
+                boundaries.push(pos);
+                chunk_start = pos;
+                hash = 0;
+            }
+        }
+    }
+    if pos > chunk_start {
+        boundaries.push(pos);
+    }
+    Ok(boundaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic data without long runs, so boundaries come from the hash
+    fn test_data(len: usize) -> Vec<u8> {
+        let mut state = 1;
+        (0..len).map(|_| {
+            let (next_state, value) = splitmix64(state);
+            state = next_state;
+            value as u8
+        }).collect()
+    }
+
+    #[test]
+    fn gear_table_start() {
+        // First outputs of splitmix64 seeded with 0
+        assert_eq!(GEAR[0], 0xe220a8397b1dcdaf);
+        assert_eq!(GEAR[1], 0x6e789e6aa1b965f4);
+    }
+    #[test]
+    fn chunk_lengths_in_bounds() {
+        let data = test_data(200_000);
+        let boundaries = chunk_boundaries(data.as_slice(), 1024).unwrap();
+        let (min_len, max_len) = chunk_len_bounds(1024);
+        assert_eq!(*boundaries.last().unwrap(), data.len() as u64);
+        let mut chunk_start = 0;
+        for (index, chunk_end) in boundaries.iter().enumerate() {
+            let chunk_len = chunk_end - chunk_start;
+            assert!(chunk_len <= max_len);
+            if index + 1 < boundaries.len() {
+                assert!(chunk_len >= min_len);
+            }
+            chunk_start = *chunk_end;
+        }
+        // Boundaries come from the hash rather than the maximum length
+        assert!(boundaries.len() > 100);
+    }
+    #[test]
+    fn insertion_moves_nearby_boundaries() {
+        let data = test_data(200_000);
+        let mut inserted = data.clone();
+        inserted.insert(1000, 0x5a);
+        let boundaries = chunk_boundaries(data.as_slice(), 1024).unwrap();
+        let inserted_boundaries = chunk_boundaries(inserted.as_slice(), 1024).unwrap();
+        // Past the insertion, boundaries resynchronize and shift by one byte
+        let shifted: Vec<u64> = boundaries.iter()
+            .filter(|end| **end > 10_000)
+            .map(|end| end + 1)
+            .collect();
+        assert!(inserted_boundaries.ends_with(&shifted));
+    }
+    #[test]
+    fn empty_data_has_no_chunks() {
+        assert!(chunk_boundaries(&[][..], 1024).unwrap().is_empty());
+    }
+}
@@ -63,10 +63,10 @@ impl Iterator for TreeBlockIter {
                     div_tester *= branch_as_u64;
                 }
                 // Add the root if we haven't already walked it back from a non-truncated tree
-                if let Some(last) = self.iter_stash.back() {
-                    if single_end_pt >= self.leaf_block_count && last.start() != 0 {
-                        self.iter_stash.push_back(BlockRange::new(0, div_tester, false));
-                    }
+                // With fewer blocks than the branch factor, no other nodes get stashed
+                if single_end_pt >= self.leaf_block_count && self.leaf_block_count > 1
+                        && self.iter_stash.back().is_none_or(|last| last.start() != 0) {
+                    self.iter_stash.push_back(BlockRange::new(0, div_tester, false));
                 }
                 self.iter_block_ctr += 1;
                 return Some(next_single);
@@ -75,7 +75,9 @@ impl Iterator for TreeBlockIter {
     }
 }
 
-pub fn merkle_block_generator(file_len: u64, block_size: block_t, branch: branch_t) -> impl IntoIterator<Item = BlockRange> {
+// The iterator is Send so that it can be the reference of a StrictOrderConsumer
+pub fn merkle_block_generator(file_len: u64, block_size: block_t, branch: branch_t)
+        -> impl IntoIterator<Item = BlockRange, IntoIter: Send> {
     assert!(block_size != 0);
     assert!(branch >= 2);
 
@@ -83,8 +85,81 @@ pub fn merkle_block_generator(file_len: u64, block_size: block_t, branch: branch
     TreeBlockIter::new(block_count, branch)
 }
 
+/// Byte range covered by a range of blocks, truncated at the end of the file.
+///
+/// The single leaf of an empty file covers the byte range `[0, 0]`.
+pub fn block_byte_range(block_range: BlockRange, block_size: block_t, file_len: u64) -> BlockRange {
+    let block_size = u64::from(block_size);
+    let end_block = match block_range.include_end() {
+        true => block_range.end().saturating_add(1),
+        false => block_range.end()
+    };
+    let start_byte = block_range.start()*block_size;
+    let end_byte = end_block.saturating_mul(block_size).min(file_len).saturating_sub(1);
+    BlockRange::new(start_byte, end_byte, true)
+}
+
+/// Number of leaves of a file whose leaves start `stride` bytes apart.
+///
+/// Each leaf covers `block_size` bytes from its start, so consecutive
+/// leaves overlap when `stride` is less than `block_size`. The last leaf
+/// is the first one that reaches the end of the file, and an empty file
+/// has a single leaf.
+pub fn strided_leaf_count(file_len: u64, block_size: block_t, stride: block_t) -> u64 {
+    assert!(stride != 0 && stride <= block_size);
+    match file_len.checked_sub(block_size.into()) {
+        Some(past_first_leaf) if past_first_leaf > 0 =>
+            past_first_leaf.div_ceil(stride.into()) + 1,
+        _ => 1
+    }
+}
+
+/// Node ranges of a file whose leaves start `stride` bytes apart, in the
+/// order of [`merkle_block_generator`].
+///
+/// Block ranges count the leaves of [`strided_leaf_count`] rather than
+/// blocks, so the tree has the shape of one byte blocks over as many
+/// bytes as there are leaves.
+pub fn strided_block_generator(file_len: u64, block_size: block_t, stride: block_t,
+        branch: branch_t) -> impl IntoIterator<Item = BlockRange, IntoIter: Send> {
+    assert!(branch >= 2);
+
+    TreeBlockIter::new(strided_leaf_count(file_len, block_size, stride), branch)
+}
+
+/// Byte range covered by a range of leaves that start `stride` bytes apart,
+/// truncated at the end of the file.
+///
+/// This is [`block_byte_range`] when `stride` is `block_size`.
+pub fn strided_byte_range(block_range: BlockRange, block_size: block_t, stride: block_t,
+        file_len: u64) -> BlockRange {
+    let last_leaf = match block_range.include_end() {
+        true => block_range.end(),
+        false => block_range.end().saturating_sub(1)
+    };
+    let start_byte = block_range.start()*u64::from(stride);
+    let end_byte = last_leaf.saturating_mul(stride.into()).saturating_add(block_size.into())
+        .min(file_len).saturating_sub(1);
+    BlockRange::new(start_byte, end_byte, true)
+}
+
+/// Node ranges of several files one after another, as written in a long hash file.
+///
+/// Each item is the index of the file in `file_lens`, the block range of the node,
+/// and the byte range of the node. Nodes of each file are in the order given by
+/// [`merkle_block_generator`].
+pub fn multi_file_block_generator(file_lens: &[u64], block_size: block_t, branch: branch_t)
+        -> impl Iterator<Item = (usize, BlockRange, BlockRange)> + '_ {
+    file_lens.iter().copied().enumerate().flat_map(move |(file_index, file_len)| {
+        merkle_block_generator(file_len, block_size, branch).into_iter()
+            .map(move |block_range| (file_index, block_range,
+                block_byte_range(block_range, block_size, file_len)))
+    })
+}
+
 // Iterator that reorders iterator I_B with type B and extractable key type A to match iterator I_A
 // The iterators should be the same length, and I_A should never repeat
+// If I_B repeats a key while it is held back, the first item with that key is kept
 // TODO: a binary heap would be better but I don't know how to impl Ord for BlockRange;
 #[derive(Debug, Clone)]
 struct ReorderHashIter<A, B, IterA, IterB, F>
@@ -97,7 +172,9 @@ where
     iter_ordered: IterA,
     iter_to_reorder: IterB,
     func_extract: F,
-    reorder_hashmap: HashMap<A, B>
+    reorder_hashmap: HashMap<A, B>,
+    // Most items held back at once before panicking
+    max_buffered: usize
 }
 impl<A, B, IterA, IterB, F> ReorderHashIter<A, B, IterA, IterB, F>
 where
@@ -106,7 +183,8 @@ where
     IterA: Iterator<Item = A>,
     IterB: Iterator<Item = B>
 {
-    pub fn new<IntoIterA, IntoIterB>(ref_ordered_iter: IntoIterA, reordered_iter: IntoIterB, extractor_func: F) -> Self
+    pub fn new<IntoIterA, IntoIterB>(ref_ordered_iter: IntoIterA, reordered_iter: IntoIterB,
+            extractor_func: F, max_buffered: usize) -> Self
     where
         IntoIterA: IntoIterator<Item = A, IntoIter = IterA>,
         IntoIterB: IntoIterator<Item = B, IntoIter = IterB>
@@ -115,7 +193,8 @@ where
             iter_ordered: ref_ordered_iter.into_iter(),
             iter_to_reorder: reordered_iter.into_iter(),
             func_extract: extractor_func,
-            reorder_hashmap: HashMap::new()
+            reorder_hashmap: HashMap::new(),
+            max_buffered
         }
     }
 }
@@ -145,8 +224,11 @@ where
                 let unordered_key = (self.func_extract)(&unordered_next);
                 if unordered_key == next_expected_key {
                     return Some(unordered_next);
-                } else {
-                    assert!(self.reorder_hashmap.insert(unordered_key, unordered_next).is_none());
+                } else if !self.reorder_hashmap.contains_key(&unordered_key) {
+                    assert!(self.reorder_hashmap.len() < self.max_buffered,
+                        "more than {} items arrived ahead of the next expected item",
+                        self.max_buffered);
+                    self.reorder_hashmap.insert(unordered_key, unordered_next);
                 }
             }
         }
@@ -158,7 +240,58 @@ where
     T: Iterator<Item = BlockRange>,
     U: Iterator<Item = HashRange>
 {
-    ReorderHashIter::new(ref_ordered_iter, hashrange_iter, |hashrange| hashrange.block_range())
+    reorder_hashrange_iter_capped(ref_ordered_iter, hashrange_iter, usize::MAX)
+}
+
+/// Reorders `hashrange_iter` to match `ref_ordered_iter`, holding back
+/// at most `max_buffered` nodes that arrive early.
+///
+/// Nodes hashed by the thread pool only arrive slightly out of order,
+/// so a small cap bounds the memory used when `hashrange_iter` comes
+/// from an untrusted source. A stream in reverse order needs one less
+/// than its length.
+///
+/// If several nodes with the same block range arrive early, the first
+/// is kept and the rest are dropped. A repeat of a node that was
+/// already returned is held back like any other early node.
+///
+/// # Panics
+///
+/// The returned iterator panics if more than `max_buffered` nodes
+/// arrive ahead of the next node in `ref_ordered_iter`.
+pub fn reorder_hashrange_iter_capped<T, U>(ref_ordered_iter: T, hashrange_iter: U,
+        max_buffered: usize) -> impl IntoIterator<Item = HashRange>
+where
+    T: Iterator<Item = BlockRange>,
+    U: Iterator<Item = HashRange>
+{
+    ReorderHashIter::new(ref_ordered_iter, hashrange_iter,
+        |hashrange| hashrange.block_range(), max_buffered)
+}
+
+/// Passes `hashrange_iter` through as it is, for streams that are already
+/// in the order of `ref_ordered_iter`.
+///
+/// Nodes hashed without a thread pool (a `thread_count` of 0) arrive in
+/// the order of [`merkle_block_generator`], so they need none of the
+/// buffering of [`reorder_hashrange_iter`]. The returned iterator ends
+/// when either of the iterators does.
+///
+/// # Panics
+///
+/// In debug builds, the returned iterator panics if a node does not have
+/// the next block range in `ref_ordered_iter`.
+pub fn assume_ordered_hashrange_iter<T, U>(ref_ordered_iter: T, hashrange_iter: U)
+        -> impl IntoIterator<Item = HashRange>
+where
+    T: Iterator<Item = BlockRange>,
+    U: Iterator<Item = HashRange>
+{
+    ref_ordered_iter.zip(hashrange_iter).map(|(expected_range, hashrange)| {
+        debug_assert_eq!(hashrange.block_range(), expected_range,
+            "node arrived out of order");
+        hashrange
+    })
 }
 
 #[cfg(test)]
@@ -253,10 +386,65 @@ mod test {
         }
     }
 
+    #[test]
+    fn block_iter_equivalences_branch_factors() {
+        // Includes odd branch factors, where ragged trees are most common
+        for branch in 2..=7 {
+            for i in 0..=64 {
+                let ref_vec: Vec<_> = merkle_block_generator_ref_impl(i, 1, branch);
+                let new_vec: Vec<_> = merkle_block_generator(i, 1, branch).into_iter().collect();
+                assert_eq!(ref_vec, new_vec, "branch {} length {}", branch, i);
+            }
+        }
+    }
+
     #[test]
     fn block_iter_equivalences_ragged_blocksize() {
         let ref_vec: Vec<_> = merkle_block_generator_ref_impl(21, 2, 4);
         let new_vec: Vec<_> = merkle_block_generator(21, 2, 4).into_iter().collect();
         assert_eq!(ref_vec, new_vec);
     }
+
+    #[test]
+    fn stride_of_block_size_matches_blocks() {
+        for file_len in 0..=40 {
+            let block_vec: Vec<_> = merkle_block_generator(file_len, 4, 3).into_iter().collect();
+            let strided_vec: Vec<_> = strided_block_generator(file_len, 4, 4, 3)
+                .into_iter().collect();
+            assert_eq!(block_vec, strided_vec);
+            for block_range in block_vec {
+                assert_eq!(block_byte_range(block_range, 4, file_len),
+                    strided_byte_range(block_range, 4, 4, file_len));
+            }
+        }
+    }
+
+    #[test]
+    fn strided_leaves_overlap() {
+        // Leaves of 4 bytes every 2 bytes, with the last one reaching the end
+        assert_eq!(strided_leaf_count(0, 4, 2), 1);
+        assert_eq!(strided_leaf_count(4, 4, 2), 1);
+        assert_eq!(strided_leaf_count(5, 4, 2), 2);
+        assert_eq!(strided_leaf_count(9, 4, 2), 4);
+        assert_eq!(strided_byte_range(BlockRange::new(1, 1, true), 4, 2, 9),
+            BlockRange::new(2, 5, true));
+        assert_eq!(strided_byte_range(BlockRange::new(2, 4, false), 4, 2, 9),
+            BlockRange::new(4, 8, true));
+    }
+
+    #[test]
+    fn multi_file_iter_matches_single_files() {
+        let file_lens = [16, 0, 21];
+        let multi_vec: Vec<_> = multi_file_block_generator(&file_lens, 2, 4).collect();
+        let single_vec: Vec<_> = file_lens.iter().enumerate()
+            .flat_map(|(file_index, file_len)| merkle_block_generator(*file_len, 2, 4)
+                .into_iter().map(move |block_range| (file_index, block_range)))
+            .collect();
+        assert_eq!(multi_vec.iter().map(|(file_index, block_range, _)| (*file_index, *block_range))
+            .collect::<Vec<_>>(), single_vec);
+        // Byte ranges are truncated to each file
+        assert_eq!(multi_vec.last().unwrap().2, BlockRange::new(0, 20, true));
+        assert_eq!(multi_vec.iter().find(|(file_index, _, _)| *file_index == 1).unwrap().2,
+            BlockRange::new(0, 0, true));
+    }
 }
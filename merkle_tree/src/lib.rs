@@ -3,6 +3,9 @@
 mod merkle_utils;
 mod iter_utils;
 mod thread_pool;
+mod proof;
+mod chunking;
+mod builder;
 
 use std::thread::Result as ThreadResult;
 use std::panic::UnwindSafe;
@@ -11,30 +14,59 @@ use thread_pool::{EagerThreadPool, Joinable};
 
 use std::io::prelude::*;
 use std::io::SeekFrom;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
+use std::sync::Arc;
+use std::fs::File;
+use std::path::Path;
+use std::collections::VecDeque;
+use std::thread::{self, JoinHandle};
 use num_iter::range_step;
 
 use digest::{Digest, OutputSizeUser};
 use generic_array::GenericArray;
 
 use merkle_utils::*;
-pub use merkle_utils::{node_count, seek_len, BlockRange, HashData, HashRange, Consumer};
+pub use merkle_utils::{node_count, checked_node_count, seek_len, BlockRange, HashData, HashRange, Consumer};
+pub use merkle_utils::{TeeConsumer, StrictOrderConsumer, OrderError};
+pub use merkle_utils::{StatsConsumer, BlockStats};
+pub use merkle_utils::{NodeEncoding, NodeEncodingFromStrErr, TreeOptions};
+pub use merkle_utils::{HashFileErr, PositionMismatch, MAX_HASH_LEN};
+pub use merkle_utils::internal_node_input;
 pub use merkle_utils::{branch_t, block_t};
 
 pub use iter_utils::*;
+pub use proof::{prove_block, BlockProof, ProofSibling};
+pub use chunking::{chunk_boundaries, chunk_len_bounds};
+pub use builder::MerkleTreeBuilder;
 use thread_pool::{DummyHandle, ThreadPoolTaskHandle};
+pub use thread_pool::ParallelBackend;
+#[cfg(feature = "testing")]
+pub use thread_pool::{set_task_hook, TaskHook};
+#[cfg(feature = "rayon")]
+use thread_pool::{enqueue_rayon_task, RayonTaskHandle};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum HelperErrSignal {
     FileEOF,
     FileReadErr,
-    ConsumerErr
+    ConsumerErr,
+    PositionErr(PositionMismatch)
+}
+impl From<HelperErrSignal> for HashFileErr {
+    fn from(signal: HelperErrSignal) -> Self {
+        match signal {
+            HelperErrSignal::PositionErr(mismatch) => mismatch.into(),
+            _ => HashFileErr::default()
+        }
+    }
 }
 
 #[derive(Debug)]
 enum EitherJoinable<T> {
     Dummy(DummyHandle<T>),
-    Thread(ThreadPoolTaskHandle<T>)
+    Thread(ThreadPoolTaskHandle<T>),
+    #[cfg(feature = "rayon")]
+    Rayon(RayonTaskHandle<T>)
 }
 impl<T> From<DummyHandle<T>> for EitherJoinable<T> {
     fn from(value: DummyHandle<T>) -> Self {
@@ -46,16 +78,58 @@ impl<T> From<ThreadPoolTaskHandle<T>> for EitherJoinable<T> {
         Self::Thread(value)
     }
 }
+#[cfg(feature = "rayon")]
+impl<T> From<RayonTaskHandle<T>> for EitherJoinable<T> {
+    fn from(value: RayonTaskHandle<T>) -> Self {
+        Self::Rayon(value)
+    }
+}
 impl<T> Joinable<T> for EitherJoinable<T> {
     fn join(self) -> T {
         match self {
             EitherJoinable::Dummy(d) => d.join(),
             EitherJoinable::Thread(t) => t.join(),
+            #[cfg(feature = "rayon")]
+            EitherJoinable::Rayon(r) => r.join(),
         }
     }
 }
 
-pub fn merkle_hash_file<F, D, C>(mut file: F,
+// Pool that merkle_tree_file_helper hands hashing tasks to
+#[derive(Debug)]
+enum TaskPool {
+    Eager(EagerThreadPool),
+    #[cfg(feature = "rayon")]
+    Rayon(rayon::ThreadPool)
+}
+impl TaskPool {
+    fn new(backend: ParallelBackend, thread_count: usize, stack_size: Option<usize>) -> Self {
+        match backend {
+            ParallelBackend::EagerThreadPool => {
+                Self::Eager(EagerThreadPool::new(thread_count, stack_size))
+            },
+            #[cfg(feature = "rayon")]
+            ParallelBackend::Rayon => {
+                let mut builder = rayon::ThreadPoolBuilder::new()
+                    .num_threads(thread_count);
+                if let Some(stack_size) = stack_size {
+                    builder = builder.stack_size(stack_size);
+                }
+                Self::Rayon(builder.build().expect("Unable to start Rayon thread pool"))
+            }
+        }
+    }
+    fn enqueue_task<T: Send+'static>(&self, func: impl FnOnce() -> T + UnwindSafe + Send + 'static)
+            -> EitherJoinable<ThreadResult<T>> {
+        match self {
+            TaskPool::Eager(pool) => pool.enqueue_task(func).into(),
+            #[cfg(feature = "rayon")]
+            TaskPool::Rayon(pool) => enqueue_rayon_task(pool, func).into()
+        }
+    }
+}
+
+pub fn merkle_hash_file<F, D, C>(file: F,
         block_size: block_t, branch: branch_t,
         hash_queue: C, thread_count: usize) -> Option<HashData<64>>
 where
@@ -63,40 +137,625 @@ where
     D: Digest + 'static,
     <D::OutputSize as generic_array::ArrayLength<u8>>::ArrayType: UnwindSafe,
     C: Consumer<HashRange> + Clone + Send + UnwindSafe + 'static
+{
+    merkle_hash_file_with_options::<F, D, C>(file, block_size, branch,
+        &TreeOptions::default(), hash_queue, thread_count).ok()
+}
+
+/// Computes the Merkle tree hash of a file with all [`TreeOptions`] given,
+/// hashing nodes in tree order on the calling thread if `thread_count` is 0.
+pub fn merkle_hash_file_with_options<F, D, C>(file: F,
+        block_size: block_t, branch: branch_t, options: &TreeOptions,
+        hash_queue: C, thread_count: usize) -> Result<HashData<64>, HashFileErr>
+where
+    F: Read + Seek,
+    D: Digest + 'static,
+    <D::OutputSize as generic_array::ArrayLength<u8>>::ArrayType: UnwindSafe,
+    C: Consumer<HashRange> + Clone + Send + UnwindSafe + 'static
+{
+    merkle_hash_reader::<F, D, C>(file, None, block_size, branch,
+        options, hash_queue, thread_count, ParallelBackend::default())
+}
+
+/// Computes the Merkle tree hash of a file, hashing nodes on `backend`.
+///
+/// This is [`merkle_hash_file_with_options`] with a choice of thread pool
+/// for a nonzero `thread_count`. The backend does not change the hashes.
+///
+/// # Panics
+///
+/// Panics under the same conditions as [`merkle_hash_file_with_options`].
+pub fn merkle_hash_file_with_backend<F, D, C>(file: F,
+        block_size: block_t, branch: branch_t, options: &TreeOptions,
+        hash_queue: C, thread_count: usize, backend: ParallelBackend)
+        -> Option<HashData<64>>
+where
+    F: Read + Seek,
+    D: Digest + 'static,
+    <D::OutputSize as generic_array::ArrayLength<u8>>::ArrayType: UnwindSafe,
+    C: Consumer<HashRange> + Clone + Send + UnwindSafe + 'static
+{
+    merkle_hash_reader::<F, D, C>(file, None, block_size, branch,
+        options, hash_queue, thread_count, backend).ok()
+}
+
+/// Computes the Merkle tree hash of the file at `path`, with leaves read in parallel.
+///
+/// [`merkle_hash_file_with_options`] reads every leaf through the one
+/// reader it is given, on the calling thread, before queueing its hashing
+/// task. Here, each task reads its own leaf at the offset of the leaf
+/// instead, through a handle to the file that no other task is using. At most one handle is opened
+/// per pool thread, so reads can proceed in parallel on storage that
+/// benefits from it. The hashes are the same as those of
+/// [`merkle_hash_file_with_options`] over the same file.
+///
+/// With a `thread_count` of 0, leaves are read on the calling thread
+/// through a single handle, as the nodes are hashed in order anyway.
+/// Otherwise, the tasks run on `backend`. Returns `None` if the file
+/// cannot be opened or read.
+///
+/// # Panics
+///
+/// Panics under the same conditions as [`merkle_hash_file_with_options`].
+pub fn merkle_hash_path<D, C>(path: &Path,
+        block_size: block_t, branch: branch_t, options: &TreeOptions,
+        hash_queue: C, thread_count: usize, backend: ParallelBackend)
+        -> Option<HashData<64>>
+where
+    D: Digest + 'static,
+    <D::OutputSize as generic_array::ArrayLength<u8>>::ArrayType: UnwindSafe,
+    C: Consumer<HashRange> + Clone + Send + UnwindSafe + 'static
+{
+    let file = File::open(path).ok()?;
+    merkle_hash_reader::<_, D, C>(file, Some(path), block_size, branch,
+        options, hash_queue, thread_count, backend).ok()
+}
+
+// Leaves are read from separate handles to file_path if given, and from file otherwise
+#[allow(clippy::too_many_arguments)]
+fn merkle_hash_reader<F, D, C>(mut file: F, file_path: Option<&Path>,
+        block_size: block_t, branch: branch_t, options: &TreeOptions,
+        hash_queue: C, thread_count: usize, backend: ParallelBackend)
+        -> Result<HashData<64>, HashFileErr>
+where
+    F: Read + Seek,
+    D: Digest + 'static,
+    <D::OutputSize as generic_array::ArrayLength<u8>>::ArrayType: UnwindSafe,
+    C: Consumer<HashRange> + Clone + Send + UnwindSafe + 'static
 {
     assert!(block_size != 0);
     assert!(branch >= 2);
     assert!(<D as Digest>::output_size() <= merkle_utils::MAX_HASH_LEN);
     file.seek(SeekFrom::Start(0)).unwrap();
     let file_len = seek_len(&mut file);
-    let block_count = match file_len.div_ceil(block_size.into()) {
-        0 => 1,
-        n => n
+    let leaf_layout = match options.content_defined {
+        true => {
+            let chunk_ends = chunk_boundaries(&mut file, block_size)
+                .map_err(|_| HashFileErr::default())?;
+            file.seek(SeekFrom::Start(0)).map_err(|_| HashFileErr::default())?;
+            LeafLayout::Chunked(chunk_ends)
+        },
+        false => match options.stride {
+            Some(stride) => {
+                assert!(stride != 0 && stride <= block_size);
+                LeafLayout::Strided {block_size, stride, file_len}
+            },
+            None => LeafLayout::Fixed(block_size)
+        }
     };
+    let block_count = leaf_layout.leaf_count(file_len);
     let effective_block_count = exp_ceil_log(block_count, branch);
     let block_range = BlockRange::new(0, effective_block_count, false);
 
-    let threadpool_obj = match thread_count {
-        0 => None,
-        n => Some(EagerThreadPool::new(n))
+    let helper_options = HelperOptions {
+        node_encoding: options.node_encoding,
+        salt: options.salt.as_slice().into(),
+        pad_tree: options.pad_tree,
+        promote_lone_children: options.promote_lone_children,
+        duplicate_last: options.duplicate_last,
+        nominal_block_size: match (options.nominal_ranges, &leaf_layout) {
+            (true, LeafLayout::Fixed(block_size)) => Some(u64::from(*block_size)),
+            _ => None
+        },
+        empty_leaf_range: options.empty_leaf_range,
+        verify_positions: options.verify_positions || cfg!(debug_assertions),
+        read_retries: options.read_retries
+    };
+    let hash_out_result = match thread_count {
+        0 => merkle_tree_file_helper_direct::<_, D, _>(&mut file,
+            &leaf_layout, block_count, block_range, branch, &helper_options,
+            &hash_queue),
+        n => {
+            let threadpool_obj = TaskPool::new(backend, n, options.stack_size);
+            let mut leaf_source = match file_path {
+                Some(file_path) => LeafSource::Pool(Arc::new(
+                    FilePool::new(file_path, options.read_retries))),
+                None => LeafSource::Shared(&mut file)
+            };
+            merkle_tree_file_helper::<_, D, _>(&mut leaf_source,
+                &leaf_layout, block_count, block_range, branch, &helper_options,
+                hash_queue, &threadpool_obj).join().unwrap()
+        }
     };
-    let hash_out_result = merkle_tree_file_helper::<_, D, _>(&mut file,
-        block_size, block_count, block_range, branch,
-        hash_queue, threadpool_obj.as_ref()).join().unwrap();
-    let hash_out = hash_out_result.ok()?;
+    let hash_out = hash_out_result?;
     debug_assert_eq!(file_len, hash_out.1);
-    return Some(HashData::try_new(&hash_out.0).unwrap());
+    Ok(HashData::try_new(&hash_out.0).unwrap())
+}
+
+/// Computes the Merkle tree hash of an in-memory byte slice.
+///
+/// This is a thin wrapper around [`merkle_hash_file`] that reads from
+/// a [`Cursor`](std::io::Cursor) over `data`.
+///
+/// ```
+/// use merkle_tree::merkle_hash_bytes;
+/// use sha2::{Digest, Sha256};
+///
+/// let (tx, rx) = crossbeam_channel::unbounded();
+/// let tree_hash = merkle_hash_bytes::<Sha256, _>(b"abcd1234", 4, 2, tx, 0)
+///     .unwrap();
+///
+/// // Leaves are prefixed with 0x00 and internal nodes with 0x01
+/// let leaf0_hash = Sha256::digest(b"\x00abcd");
+/// let leaf1_hash = Sha256::digest(b"\x001234");
+/// let root_hash = Sha256::digest([b"\x01", leaf0_hash.as_slice(),
+///     leaf1_hash.as_slice()].concat());
+/// assert_eq!(tree_hash.as_ref(), root_hash.as_slice());
+/// // Every node of the tree is also passed to the consumer
+/// assert_eq!(rx.len(), 3);
+/// ```
+pub fn merkle_hash_bytes<D, C>(data: &[u8],
+        block_size: block_t, branch: branch_t,
+        hash_queue: C, thread_count: usize) -> Option<HashData<64>>
+where
+    D: Digest + 'static,
+    <D::OutputSize as generic_array::ArrayLength<u8>>::ArrayType: UnwindSafe,
+    C: Consumer<HashRange> + Clone + Send + UnwindSafe + 'static
+{
+    let data_cursor = std::io::Cursor::new(data);
+    merkle_hash_file::<_, D, _>(data_cursor,
+        block_size, branch, hash_queue, thread_count)
+}
+
+/// Hashes a file and returns every node of its tree, in tree order.
+///
+/// The nodes are collected through a channel and reordered to match
+/// [`merkle_block_generator`], so the result does not depend on
+/// `thread_count`.
+///
+/// ```
+/// use merkle_tree::{hash_file_to_vec, merkle_block_generator};
+/// use sha2::Sha256;
+/// use std::io::Cursor;
+///
+/// let data = b"abcd1234ef";
+/// let nodes = hash_file_to_vec::<_, Sha256>(Cursor::new(data), 4, 2, 2)
+///     .unwrap();
+/// let block_ranges: Vec<_> = nodes.iter()
+///     .map(|node| node.block_range())
+///     .collect();
+/// let expected: Vec<_> = merkle_block_generator(10, 4, 2).into_iter().collect();
+/// assert_eq!(block_ranges, expected);
+/// // The root comes last
+/// assert_eq!(nodes.last().unwrap().byte_range().range(), 10);
+/// ```
+pub fn hash_file_to_vec<F, D>(mut file: F,
+        block_size: block_t, branch: branch_t,
+        thread_count: usize) -> Result<Vec<HashRange>, HashFileErr>
+where
+    F: Read + Seek,
+    D: Digest + 'static,
+    <D::OutputSize as generic_array::ArrayLength<u8>>::ArrayType: UnwindSafe
+{
+    let file_len = seek_len(&mut file);
+    let (tx, rx) = crossbeam_channel::unbounded();
+    merkle_hash_file::<F, D, _>(file, block_size, branch, tx, thread_count)
+        .ok_or(HashFileErr::default())?;
+    // Every node has been sent once the root hash is returned
+    let block_iter = merkle_block_generator(file_len, block_size, branch).into_iter();
+    // Without a thread pool, nodes are already sent in tree order
+    Ok(match thread_count {
+        0 => assume_ordered_hashrange_iter(block_iter, rx.try_iter()).into_iter().collect(),
+        _ => reorder_hashrange_iter(block_iter, rx.try_iter()).into_iter().collect()
+    })
+}
+
+// Nodes that can be hashed ahead of a hash_file_streaming iterator
+const STREAMING_CHANNEL_BOUND: usize = 16;
+
+/// Hashes a file on another thread, yielding each node as soon as it is hashed.
+///
+/// Returns a handle to the hashing thread, which gives the root hash as
+/// [`merkle_hash_file_with_options`] would, and an iterator over the nodes
+/// of the tree. The iterator ends once every node has been hashed, or
+/// early if hashing fails, so the handle should be joined to tell the two
+/// apart. At most a few nodes are hashed ahead of the iterator, so a slow
+/// consumer slows down hashing instead of buffering the whole tree.
+///
+/// Nodes are yielded in the order they finish, which is only the order of
+/// [`merkle_block_generator`] with a `thread_count` of 0. Pass the
+/// iterator to [`reorder_hashrange_iter`] to get them in tree order.
+/// Dropping the iterator early stops hashing, and the handle then gives `None`.
+///
+/// ```
+/// use merkle_tree::{hash_file_streaming, merkle_block_generator, reorder_hashrange_iter};
+/// use merkle_tree::TreeOptions;
+/// use sha2::Sha256;
+/// use std::io::Cursor;
+///
+/// let data = b"abcd1234ef";
+/// let (handle, nodes) = hash_file_streaming::<_, Sha256>(Cursor::new(data), 4, 2,
+///     &TreeOptions::default(), 2);
+/// let block_iter = merkle_block_generator(10, 4, 2).into_iter();
+/// let nodes: Vec<_> = reorder_hashrange_iter(block_iter, nodes).into_iter().collect();
+/// let root = handle.join().unwrap().unwrap();
+/// assert_eq!(nodes.last().unwrap().hash_result(), root.as_ref());
+/// ```
+///
+/// # Panics
+///
+/// Panics under the same conditions as [`merkle_hash_file_with_options`],
+/// when joining the handle.
+pub fn hash_file_streaming<F, D>(file: F,
+        block_size: block_t, branch: branch_t, options: &TreeOptions,
+        thread_count: usize)
+        -> (JoinHandle<Option<HashData<64>>>, impl Iterator<Item = HashRange>)
+where
+    F: Read + Seek + Send + 'static,
+    D: Digest + 'static,
+    <D::OutputSize as generic_array::ArrayLength<u8>>::ArrayType: UnwindSafe
+{
+    let (tx, rx) = crossbeam_channel::bounded(STREAMING_CHANNEL_BOUND);
+    let options = options.clone();
+    let handle = thread::spawn(move || {
+        merkle_hash_file_with_options::<F, D, _>(file, block_size, branch,
+            &options, tx, thread_count).ok()
+    });
+    (handle, rx.into_iter())
 }
 
 type HashArray<T> = GenericArray<u8, <T as OutputSizeUser>::OutputSize>;
+
+// TreeOptions with the salt shareable across hashing threads
+struct HelperOptions {
+    node_encoding: NodeEncoding,
+    salt: Arc<[u8]>,
+    pad_tree: bool,
+    promote_lone_children: bool,
+    duplicate_last: bool,
+    // Byte ranges are rounded up to a multiple of this
+    nominal_block_size: Option<u64>,
+    empty_leaf_range: bool,
+    // Always set in debug builds
+    verify_positions: bool,
+    read_retries: u32
+}
+
+// Checks that file is at current_pos, if options.verify_positions is set
+fn check_position<F: Seek>(file: &mut F, current_pos: u64,
+        options: &HelperOptions) -> Result<(), HelperErrSignal> {
+    if !options.verify_positions {
+        return Ok(());
+    }
+    let actual_pos = file.stream_position().map_err(|_| HelperErrSignal::FileReadErr)?;
+    match actual_pos == current_pos {
+        true => Ok(()),
+        false => Err(HelperErrSignal::PositionErr(
+            PositionMismatch {expected: current_pos, actual: actual_pos}))
+    }
+}
+
+// Checks that file is where the previous leaf ended, then moves back to the
+// start of the leaf if the two overlap
+fn seek_to_leaf<F: Seek>(file: &mut F, leaf_layout: &LeafLayout, leaf: u64,
+        options: &HelperOptions) -> Result<(), HelperErrSignal> {
+    let prev_leaf_end = leaf_layout.prev_leaf_end(leaf);
+    check_position(file, prev_leaf_end, options)?;
+    let leaf_start = leaf_layout.leaf_start(leaf);
+    if leaf_start != prev_leaf_end {
+        file.seek(SeekFrom::Start(leaf_start)).map_err(|_| HelperErrSignal::FileReadErr)?;
+    }
+    Ok(())
+}
+
+// Last byte of a node ending at current_pos, which may be the end of the file
+fn end_byte(current_pos: u64, nominal_block_size: Option<u64>) -> u64 {
+    match nominal_block_size {
+        Some(block_size) => current_pos.next_multiple_of(block_size),
+        None => current_pos
+    }.saturating_sub(1)
+}
+
+// Byte range of a leaf from start_byte to current_pos,
+// which is only empty for the leaf of an empty file
+fn leaf_byte_range(start_byte: u64, current_pos: u64,
+        nominal_block_size: Option<u64>, empty_leaf_range: bool) -> BlockRange {
+    match empty_leaf_range && current_pos == start_byte {
+        true => BlockRange::empty(start_byte),
+        false => BlockRange::new(start_byte, end_byte(current_pos, nominal_block_size), true)
+    }
+}
+
+// Where the leaves of the tree start in the file
+enum LeafLayout {
+    // Blocks of the block size, with a shorter last block
+    Fixed(block_t),
+    // End offset of each content-defined chunk
+    Chunked(Vec<u64>),
+    // Blocks of the block size starting every stride bytes
+    Strided {block_size: block_t, stride: block_t, file_len: u64}
+}
+impl LeafLayout {
+    // An empty file still has one empty leaf
+    fn leaf_count(&self, file_len: u64) -> u64 {
+        let count = match self {
+            LeafLayout::Fixed(block_size) => file_len.div_ceil((*block_size).into()),
+            LeafLayout::Chunked(chunk_ends) => chunk_ends.len() as u64,
+            LeafLayout::Strided {block_size, stride, ..} =>
+                strided_leaf_count(file_len, *block_size, *stride)
+        };
+        count.max(1)
+    }
+    // Leaves past the end of the file start at the end of the file
+    fn leaf_start(&self, leaf: u64) -> u64 {
+        match self {
+            LeafLayout::Fixed(block_size) => leaf*u64::from(*block_size),
+            LeafLayout::Chunked(chunk_ends) => match leaf.checked_sub(1) {
+                None => 0,
+                Some(prev_leaf) => usize::try_from(prev_leaf).ok()
+                    .and_then(|prev_leaf| chunk_ends.get(prev_leaf))
+                    .or(chunk_ends.last())
+                    .copied().unwrap_or(0)
+            },
+            LeafLayout::Strided {stride, file_len, ..} => (leaf*u64::from(*stride)).min(*file_len)
+        }
+    }
+    // Where a reader going through the leaves in order is before reading this leaf,
+    // which is before its start if it overlaps the previous leaf
+    fn prev_leaf_end(&self, leaf: u64) -> u64 {
+        match (self, leaf.checked_sub(1)) {
+            (LeafLayout::Strided {block_size, stride, file_len}, Some(prev_leaf)) =>
+                (prev_leaf*u64::from(*stride) + u64::from(*block_size)).min(*file_len),
+            _ => self.leaf_start(leaf)
+        }
+    }
+    // Number of bytes to read for a leaf, which may be past the end of the file
+    fn leaf_read_len(&self, leaf: u64) -> usize {
+        let read_len = match self {
+            LeafLayout::Fixed(block_size) => u64::from(*block_size),
+            LeafLayout::Strided {block_size, ..} => u64::from(*block_size),
+            LeafLayout::Chunked(_) => self.leaf_start(leaf + 1) - self.leaf_start(leaf)
+        };
+        read_len.try_into().unwrap()
+    }
+}
+
+// Where merkle_tree_file_helper reads the data of leaves from
+enum LeafSource<'a, F> {
+    // One reader, read on the calling thread before each leaf task is queued
+    Shared(&'a mut F),
+    // Handles to a file, read at the offset of each leaf within its task
+    Pool(Arc<FilePool>)
+}
+
+// Hash of a subtree that only covers padding leaves
+fn padding_hash<D: Digest>(block_interval: u64, branch: branch_t,
+        options: &HelperOptions) -> HashArray<D> {
+    if block_interval == 1 {
+        let mut digest_obj = D::new_with_prefix([0x00]);
+        digest_obj.update(&options.salt);
+        digest_obj.finalize()
+    } else {
+        // All children are identical, so only hash one of them
+        let child_hash = padding_hash::<D>(block_interval / u64::from(branch),
+            branch, options);
+        let mut node_hash = StreamingNodeHash::<D>::new(options.node_encoding,
+            branch, false);
+        for _ in 0..branch {
+            node_hash.add_child(0, &child_hash);
+        }
+        node_hash.finalize()
+    }
+}
+// Hash of the children added after the last one in the file
+// last_child_hash is repeated with duplicate_last, and padding is added otherwise
+fn fill_child_hash<D: Digest>(last_child_hash: HashArray<D>, block_interval: u64,
+        branch: branch_t, options: &HelperOptions) -> HashArray<D> {
+    match options.duplicate_last {
+        true => last_child_hash,
+        false => padding_hash::<D>(block_interval, branch, options)
+    }
+}
 // Second element of tuple is seek position
 type HashResult<T> = Result<(HashArray<T>, u64), HelperErrSignal>;
 
-fn merkle_tree_file_helper<F, D, C>(file: &mut F,
-        block_size: block_t, block_count: u64, block_range: BlockRange,
-        branch: branch_t,
-        hash_queue: C,
-        threadpool: Option<&EagerThreadPool>)
+// Hashes a leaf block and passes its entry to the consumer
+fn leaf_node_hash<D, C>(block_range: BlockRange, byte_range: BlockRange,
+        salt: &[u8], block_data: &[u8], current_pos: u64,
+        hash_queue: &C) -> HashResult<D>
+where
+    D: Digest,
+    C: Consumer<HashRange>
+{
+    // Prepend 0x00 and the salt to the data when hashing
+    let mut digest_obj = D::new_with_prefix([0x00]);
+    digest_obj.update(salt);
+    digest_obj.update(block_data);
+    let hash_result = digest_obj.finalize();
+    accept_node_hash::<D, C>(block_range, byte_range, hash_result,
+        current_pos, hash_queue)
+}
+
+// Hashes an internal node from its children and passes its entry to the consumer
+#[allow(clippy::too_many_arguments)]
+fn internal_node_hash<D, C>(block_range: BlockRange, byte_range: BlockRange,
+        node_encoding: NodeEncoding, promote_lone_children: bool,
+        child_hashes: &[(u64, HashArray<D>)], current_pos: u64,
+        hash_queue: &C) -> HashResult<D>
+where
+    D: Digest,
+    C: Consumer<HashRange>
+{
+    let hash_result = match child_hashes {
+        [(_, child_hash)] if promote_lone_children => child_hash.clone(),
+        _ => {
+            // Includes the 0x01 prefix
+            let hash_input = internal_node_input(node_encoding,
+                child_hashes.iter()
+                    .map(|(child_len, child_hash)| (*child_len, child_hash.as_slice())));
+            D::digest(hash_input.as_slice())
+        }
+    };
+    accept_node_hash::<D, C>(block_range, byte_range, hash_result,
+        current_pos, hash_queue)
+}
+
+// Passes the entry of a finished node to the consumer
+fn accept_node_hash<D, C>(block_range: BlockRange, byte_range: BlockRange,
+        hash_result: HashArray<D>, current_pos: u64, hash_queue: &C) -> HashResult<D>
+where
+    D: Digest,
+    C: Consumer<HashRange>
+{
+    let block_hash_result = HashRange::new(block_range, byte_range, HashData::try_new(&hash_result).unwrap());
+
+    match hash_queue.accept(block_hash_result) {
+        Ok(()) => Ok((hash_result, current_pos)),
+        Err(_) => Err(HelperErrSignal::ConsumerErr)
+    }
+}
+
+// Branch factors above this hash children in a sliding window instead of all at once
+const MAX_BUFFERED_CHILDREN: usize = 64;
+
+// Feeds children into an internal node digest as they finish
+// Gives the same hash as internal_node_input without buffering every child
+struct StreamingNodeHash<D: Digest> {
+    digest_obj: D,
+    node_encoding: NodeEncoding,
+    // Set for a promoted lone child, which is the node hash as is
+    promoted_hash: Option<HashArray<D>>,
+    is_promoted: bool
+}
+impl<D: Digest> StreamingNodeHash<D> {
+    // child_count must match the number of children that will be added
+    fn new(node_encoding: NodeEncoding, child_count: branch_t,
+            promote_lone_children: bool) -> Self {
+        let mut digest_obj = D::new_with_prefix([0x01]);
+        if node_encoding == NodeEncoding::ChildCount {
+            digest_obj.update(child_count.to_be_bytes());
+        }
+        let is_promoted = promote_lone_children && child_count == 1;
+        Self {digest_obj, node_encoding, promoted_hash: None, is_promoted}
+    }
+    fn add_child(&mut self, child_len: u64, child_hash: &[u8]) {
+        if self.is_promoted {
+            self.promoted_hash = Some(GenericArray::clone_from_slice(child_hash));
+            return;
+        }
+        if self.node_encoding == NodeEncoding::ChildCount {
+            self.digest_obj.update(child_len.to_be_bytes());
+        }
+        self.digest_obj.update(child_hash);
+    }
+    fn finalize(self) -> HashArray<D> {
+        match self.promoted_hash {
+            Some(child_hash) => child_hash,
+            None => self.digest_obj.finalize()
+        }
+    }
+}
+
+// Number of children of a node that cover blocks of the file
+// Children past the end of the file are padding or left out entirely
+fn file_child_count(start_block: u64, block_increment: u64, block_count: u64,
+        branch: branch_t) -> branch_t {
+    let file_children = block_count.saturating_sub(start_block).div_ceil(block_increment);
+    match branch_t::try_from(file_children) {
+        Ok(count) => count.min(branch),
+        Err(_) => branch
+    }
+}
+
+// Used with thread_count == 0, hashing each node on the calling thread
+// Emits nodes in the same order as merkle_tree_file_helper without a pool
+fn merkle_tree_file_helper_direct<F, D, C>(file: &mut F,
+        leaf_layout: &LeafLayout, block_count: u64, block_range: BlockRange,
+        branch: branch_t, options: &HelperOptions, hash_queue: &C) -> HashResult<D>
+where
+    F: Read + Seek,
+    D: Digest,
+    C: Consumer<HashRange>
+{
+    let start_block = block_range.start();
+    let end_block = block_range.end()-match block_range.include_end() {
+        true => 0,
+        false => 1
+    };
+    let block_interval = block_range.range();
+    let start_byte = leaf_layout.leaf_start(block_range.start());
+
+    if block_range.start() >= block_count {
+        return Err(HelperErrSignal::FileEOF);
+    }
+    let mut current_pos = start_byte;
+    if block_interval == 1 {
+        seek_to_leaf(file, leaf_layout, start_block, options)?;
+        let file_vec = read_exact_vec(file, Some(current_pos),
+                leaf_layout.leaf_read_len(start_block), options.read_retries)
+            .map_err(|_| HelperErrSignal::FileReadErr)?;
+        current_pos += file_vec.len() as u64;
+        let block_range = BlockRange::new(start_block, end_block, true);
+        let byte_range = leaf_byte_range(start_byte, current_pos,
+            options.nominal_block_size, options.empty_leaf_range);
+        return leaf_node_hash::<D, C>(block_range, byte_range, &options.salt,
+            &file_vec, current_pos, hash_queue);
+    }
+    // power-of-branch check
+    assert!(block_interval.is_multiple_of(branch as u64));
+    let block_increment = block_interval / (branch as u64);
+    // Children are added to the digest one at a time, so memory use
+    // does not grow with the branch factor
+    let file_children = file_child_count(start_block, block_increment,
+        block_count, branch);
+    let child_count = match options.pad_tree || options.duplicate_last {
+        true => branch,
+        false => file_children
+    };
+    let mut node_hash = StreamingNodeHash::<D>::new(options.node_encoding, child_count,
+        options.promote_lone_children);
+    let mut last_child_hash = None;
+    for slice_start in range_step(
+            block_range.start(),
+            block_range.start()+block_increment*u64::from(file_children),
+            block_increment) {
+        let slice_range = BlockRange::new(slice_start, slice_start+block_increment, false);
+        let subhash = merkle_tree_file_helper_direct::<F, D, C>(file, leaf_layout,
+            block_count, slice_range, branch, options, hash_queue)?;
+        node_hash.add_child(subhash.1 - current_pos, &subhash.0);
+        current_pos = subhash.1;
+        last_child_hash = Some(subhash.0);
+    }
+    if child_count > file_children {
+        // Padding and repeated children do not advance the file position
+        let child_hash = fill_child_hash::<D>(last_child_hash.unwrap(),
+            block_increment, branch, options);
+        for _ in file_children..child_count {
+            node_hash.add_child(0, &child_hash);
+        }
+    }
+    let block_range = BlockRange::new(start_block, end_block, true);
+    let byte_range = BlockRange::new(start_byte,
+        end_byte(current_pos, options.nominal_block_size), true);
+    accept_node_hash::<D, C>(block_range, byte_range, node_hash.finalize(),
+        current_pos, hash_queue)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn merkle_tree_file_helper<F, D, C>(leaf_source: &mut LeafSource<'_, F>,
+        leaf_layout: &LeafLayout, block_count: u64, block_range: BlockRange,
+        branch: branch_t, options: &HelperOptions, hash_queue: C,
+        threadpool: &TaskPool)
         -> EitherJoinable<ThreadResult<HashResult<D>>>
 where
     F: Read + Seek,
@@ -118,25 +777,40 @@ where
     };
     let block_interval = block_range.range();
 
-    let start_byte = block_range.start()*block_size as u64;
+    let start_byte = leaf_layout.leaf_start(block_range.start());
     // Defer end_byte calculation until after file read
 
     if block_range.start() < block_count {
-        let mut current_pos = block_range.start()*(block_size as u64);
-        // TODO: reduce indirection
+        let mut current_pos = start_byte;
         if block_interval == 1 {
-            let block_size_as_usize: usize = block_size.try_into().unwrap();
-
-            // Should be optimized out in release mode
-            #[cfg(debug_assertions)]
-            {
-                let current_pos_actual = file.stream_position().unwrap();
-                debug_assert_eq!(current_pos_actual, current_pos);
+            let file = match leaf_source {
+                LeafSource::Shared(file) => file,
+                LeafSource::Pool(file_pool) => {
+                    let file_pool = Arc::clone(file_pool);
+                    let read_len = leaf_layout.leaf_read_len(start_block);
+                    let salt = Arc::clone(&options.salt);
+                    let nominal_block_size = options.nominal_block_size;
+                    let empty_leaf_range = options.empty_leaf_range;
+                    let hash_closure = move || {
+                        let file_vec = file_pool.read_at(start_byte, read_len)
+                            .map_err(|_| HelperErrSignal::FileReadErr)?;
+                        let current_pos = start_byte + file_vec.len() as u64;
+                        let block_range = BlockRange::new(start_block, end_block, true);
+                        let byte_range = leaf_byte_range(start_byte, current_pos,
+                            nominal_block_size, empty_leaf_range);
+                        leaf_node_hash::<D, C>(block_range, byte_range, &salt,
+                            &file_vec, current_pos, &hash_queue)
+                    };
+                    return threadpool.enqueue_task(hash_closure);
+                }
+            };
+            if let Err(err) = seek_to_leaf(*file, leaf_layout, start_block, options) {
+                // Err() for returned error, Ok() for no panic
+                return DummyHandle::new(Ok(Err(err))).into();
             }
 
-
-            let file_vec = match read_exact_vec(file, Some(current_pos),
-                    block_size_as_usize) {
+            let file_vec = match read_exact_vec(*file, Some(current_pos),
+                    leaf_layout.leaf_read_len(start_block), options.read_retries) {
                 Ok(vec) => vec,
                 Err(_) => {
                     // Err() for returned error, Ok() for no panic
@@ -146,39 +820,29 @@ where
             };
 
             current_pos += file_vec.len() as u64;
-            let end_byte_file = current_pos.saturating_sub(1);
-            #[cfg(debug_assertions)]
-            {
-                let end_byte_file_actual = file.stream_position().unwrap().saturating_sub(1);
-                debug_assert_eq!(end_byte_file_actual, end_byte_file);
+            if let Err(err) = check_position(*file, current_pos, options) {
+                // Err() for returned error, Ok() for no panic
+                return DummyHandle::new(Ok(Err(err))).into();
             }
 
+            let salt = Arc::clone(&options.salt);
+            let byte_range = leaf_byte_range(start_byte, current_pos,
+                options.nominal_block_size, options.empty_leaf_range);
             let hash_closure = move || {
                 let block_range = BlockRange::new(start_block, end_block, true);
-                let byte_range = BlockRange::new(start_byte, end_byte_file, true);
-
-                // Prepend 0x00 to the data when hashing
-                let mut digest_obj = D::new_with_prefix([0x00]);
-                digest_obj.update(file_vec.as_slice());
-                let hash_result = digest_obj.finalize();
-                let block_hash_result = HashRange::new(block_range, byte_range,HashData::try_new(&hash_result).unwrap());
-
-                if hash_queue.accept(block_hash_result).is_ok() {
-                    return Ok((hash_result, current_pos));
-                } else {
-                    return Err(HelperErrSignal::ConsumerErr);
-                }
-            };
-            let hash_future = match threadpool {
-                Some(threadpool) => threadpool.enqueue_task(hash_closure).into(),
-                // Ok() for no panic
-                None => DummyHandle::new(Ok(hash_closure())).into()
+                leaf_node_hash::<D, C>(block_range, byte_range, &salt,
+                    &file_vec, current_pos, &hash_queue)
             };
-            return hash_future;
+            threadpool.enqueue_task(hash_closure)
         } else {
             // power-of-branch check
             assert!(block_interval % (branch as u64) == 0);
             let block_increment = block_interval / (branch as u64);
+            if usize::from(branch) > MAX_BUFFERED_CHILDREN {
+                return merkle_tree_file_helper_streaming::<F, D, C>(leaf_source,
+                    leaf_layout, block_count, block_range, branch, options,
+                    hash_queue, threadpool);
+            }
             let mut subhash_awaitables: Vec<_> = Vec::with_capacity(branch.into());
             for slice_start in range_step(
                     block_range.start(),
@@ -186,18 +850,29 @@ where
                     block_increment) {
                 let slice_end = slice_start+block_increment;
                 let slice_range = BlockRange::new(slice_start, slice_end, false);
-                subhash_awaitables.push(merkle_tree_file_helper::<F, D, C>(file, 
-                    block_size, block_count, slice_range, branch, 
+                subhash_awaitables.push(merkle_tree_file_helper::<F, D, C>(leaf_source,
+                    leaf_layout, block_count, slice_range, branch, options,
                     hash_queue.clone(), threadpool));
             }
-            let mut hash_input: Vec<u8> = Vec::with_capacity(
-                subhash_awaitables.len()*<D as Digest>::output_size());
+            // Byte length covered by each child along with its hash
+            let mut child_hashes: Vec<(u64, HashArray<D>)> = Vec::with_capacity(
+                subhash_awaitables.len());
             for awaitable in subhash_awaitables {
                 match awaitable.join().unwrap() {
                     Ok(subhash) => {
-                        hash_input.extend(subhash.0);
+                        child_hashes.push((subhash.1 - current_pos, subhash.0));
                         current_pos = subhash.1;
                     },
+                    Err(HelperErrSignal::FileEOF) if options.duplicate_last => {
+                        // The first child is never past the end of the file
+                        let last_child_hash = child_hashes.last().unwrap().1.clone();
+                        child_hashes.push((0, last_child_hash));
+                    },
+                    Err(HelperErrSignal::FileEOF) if options.pad_tree => {
+                        // Padding children do not advance the file position
+                        child_hashes.push((0, padding_hash::<D>(block_increment,
+                            branch, options)));
+                    },
                     Err(HelperErrSignal::FileEOF) => {
                         // None -> out of range, and so will the rest
                         // break drops awaitable and rest of subhash_awaitables
@@ -212,33 +887,22 @@ where
                     }
                 }
             }
-            let end_byte_file = current_pos.saturating_sub(1);
-            #[cfg(debug_assertions)]
-            {
-                let end_byte_file_actual = file.stream_position().unwrap().saturating_sub(1);
-                debug_assert_eq!(end_byte_file_actual, end_byte_file);
+            if let LeafSource::Shared(file) = leaf_source {
+                if let Err(err) = check_position(*file, current_pos, options) {
+                    // Err() for returned error, Ok() for no panic
+                    return DummyHandle::new(Ok(Err(err))).into();
+                }
             }
+            let node_encoding = options.node_encoding;
+            let promote_lone_children = options.promote_lone_children;
+            let end_byte_range = end_byte(current_pos, options.nominal_block_size);
             let hash_closure = move || {
                 let block_range = BlockRange::new(start_block, end_block, true);
-                let byte_range = BlockRange::new(start_byte, end_byte_file, true);
-
-                let mut digest_obj = D::new_with_prefix([0x01]);
-                digest_obj.update(hash_input.as_slice());
-                let hash_result = digest_obj.finalize();
-                let block_hash_result = HashRange::new(block_range, byte_range, HashData::try_new(&hash_result).unwrap());
-
-                if hash_queue.accept(block_hash_result).is_ok() {
-                    return Ok((hash_result, current_pos));
-                } else {
-                    return Err(HelperErrSignal::ConsumerErr);
-                }
-            };
-            let hash_future = match threadpool {
-                Some(threadpool) => threadpool.enqueue_task(hash_closure).into(),
-                // Ok() for no panic
-                None => DummyHandle::new(Ok(hash_closure())).into()
+                let byte_range = BlockRange::new(start_byte, end_byte_range, true);
+                internal_node_hash::<D, C>(block_range, byte_range, node_encoding,
+                    promote_lone_children, &child_hashes, current_pos, &hash_queue)
             };
-            return hash_future;
+            threadpool.enqueue_task(hash_closure)
         }
     } else {
         // Err() for returned error, Ok() for no panic
@@ -246,3 +910,91 @@ where
         return dummy_err.into();
     }
 }
+
+// Used by merkle_tree_file_helper for internal nodes with a large branch factor
+// At most MAX_BUFFERED_CHILDREN children are in flight at once, and the
+// node is hashed on the calling thread as they finish
+#[allow(clippy::too_many_arguments)]
+fn merkle_tree_file_helper_streaming<F, D, C>(leaf_source: &mut LeafSource<'_, F>,
+        leaf_layout: &LeafLayout, block_count: u64, block_range: BlockRange,
+        branch: branch_t, options: &HelperOptions, hash_queue: C,
+        threadpool: &TaskPool)
+        -> EitherJoinable<ThreadResult<HashResult<D>>>
+where
+    F: Read + Seek,
+    D: Digest + 'static,
+    <D::OutputSize as generic_array::ArrayLength<u8>>::ArrayType: UnwindSafe,
+    C: Consumer<HashRange> + Clone + Send + UnwindSafe + 'static,
+{
+    let start_block = block_range.start();
+    let end_block = block_range.end()-match block_range.include_end() {
+        true => 0,
+        false => 1
+    };
+    let start_byte = leaf_layout.leaf_start(block_range.start());
+    let block_increment = block_range.range() / (branch as u64);
+    let file_children = file_child_count(start_block, block_increment,
+        block_count, branch);
+    let child_count = match options.pad_tree || options.duplicate_last {
+        true => branch,
+        false => file_children
+    };
+
+    let mut current_pos = start_byte;
+    let mut node_hash = StreamingNodeHash::<D>::new(options.node_encoding, child_count,
+        options.promote_lone_children);
+    let mut last_child_hash = None;
+    let mut subhash_awaitables: VecDeque<_> = VecDeque::with_capacity(MAX_BUFFERED_CHILDREN);
+    let mut slice_starts = range_step(
+        block_range.start(),
+        block_range.start()+block_increment*u64::from(file_children),
+        block_increment);
+    loop {
+        // Keep the window full, then add the oldest child to the digest
+        while subhash_awaitables.len() < MAX_BUFFERED_CHILDREN {
+            let Some(slice_start) = slice_starts.next() else {
+                break;
+            };
+            let slice_range = BlockRange::new(slice_start, slice_start+block_increment, false);
+            subhash_awaitables.push_back(merkle_tree_file_helper::<F, D, C>(leaf_source,
+                leaf_layout, block_count, slice_range, branch, options,
+                hash_queue.clone(), threadpool));
+        }
+        let Some(awaitable) = subhash_awaitables.pop_front() else {
+            break;
+        };
+        match awaitable.join().unwrap() {
+            Ok(subhash) => {
+                node_hash.add_child(subhash.1 - current_pos, &subhash.0);
+                current_pos = subhash.1;
+                last_child_hash = Some(subhash.0);
+            },
+            Err(e) => {
+                // Err() for returned error, Ok() for no panic
+                let dummy_err = DummyHandle::new(Ok(Err(e)));
+                return dummy_err.into();
+            }
+        }
+    }
+    if child_count > file_children {
+        // Padding and repeated children do not advance the file position
+        let child_hash = fill_child_hash::<D>(last_child_hash.unwrap(),
+            block_increment, branch, options);
+        for _ in file_children..child_count {
+            node_hash.add_child(0, &child_hash);
+        }
+    }
+    if let LeafSource::Shared(file) = leaf_source {
+        if let Err(err) = check_position(*file, current_pos, options) {
+            // Err() for returned error, Ok() for no panic
+            return DummyHandle::new(Ok(Err(err))).into();
+        }
+    }
+    let block_range = BlockRange::new(start_block, end_block, true);
+    let byte_range = BlockRange::new(start_byte,
+        end_byte(current_pos, options.nominal_block_size), true);
+    let node_result = accept_node_hash::<D, C>(block_range, byte_range,
+        node_hash.finalize(), current_pos, &hash_queue);
+    // Ok() for no panic
+    DummyHandle::new(Ok(node_result)).into()
+}
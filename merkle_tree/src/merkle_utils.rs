@@ -4,8 +4,15 @@ use std::fmt;
 use std::io::{Read, Seek, SeekFrom};
 use std::io::Result as IOResult;
 use std::io::ErrorKind;
+use std::thread;
+use std::time::Duration;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
 
 use std::convert::TryFrom;
+use std::str::FromStr;
 use hex::{FromHex, FromHexError};
 use arrayvec::{ArrayVec, CapacityError};
 
@@ -28,17 +35,27 @@ pub(crate) const fn exp_ceil_log(number: u64, base: u16) -> u64 {
     result
 }
 pub const fn node_count(file_size: u64, block_size: block_t, branch: branch_t) -> u64 {
+    match checked_node_count(file_size, block_size, branch) {
+        Some(node_count) => node_count,
+        None => panic!("node count does not fit in a u64")
+    }
+}
+// node_count, or None for lengths whose node count does not fit in a u64
+pub const fn checked_node_count(file_size: u64, block_size: block_t, branch: branch_t) -> Option<u64> {
     let block_count = file_size.div_ceil(block_size as u64);
     let mut node_count = block_count;
     let mut node_at_layer_count = block_count;
     assert!(branch >= 2);
     while node_at_layer_count > 1 {
         node_at_layer_count = node_at_layer_count.div_ceil(branch as u64);
-        node_count += node_at_layer_count;
+        node_count = match node_count.checked_add(node_at_layer_count) {
+            Some(node_count) => node_count,
+            None => return None
+        };
     }
     match node_count {
-        0 => 1,
-        val => val
+        0 => Some(1),
+        val => Some(val)
     }
 }
 
@@ -51,13 +68,25 @@ pub fn seek_len(seekable: &mut dyn Seek) -> u64 {
     // return
     len
 }
-// Functions like normal `read`, but with additional guarantees:
-// - Slice is always filled when there is enough data left to read
-// - When not enough data is left, slice is filled up to returned length
-// - File cursor will be at its original position if an error occurs
-// expected_seek_loc provides a way to pass in current location, if available
-// When provided, this saves a seek operation if the given slice was full
-pub(crate) fn read_exact_vec<R: Read+Seek>(
+// Reads until buf is full or the reader reaches EOF, returning the length read
+// Readers such as TTYs and some network filesystems return fewer bytes
+// than asked for before EOF, so only Ok(0) is taken as EOF
+fn fill_buf<R: Read>(reader: &mut R, buf: &mut [u8]) -> IOResult<usize> {
+    let mut filled_len = 0;
+    while filled_len < buf.len() {
+        match reader.read(&mut buf[filled_len..]) {
+            Ok(0) => break,
+            Ok(read_len) => filled_len += read_len,
+            // Same as read_exact, which retries these without giving up
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(filled_len)
+}
+
+// Single attempt of read_exact_vec, see below for guarantees
+fn read_exact_vec_once<R: Read+Seek>(
         reader: &mut R, expected_seek_loc: Option<u64>, len: usize)
         -> IOResult<Vec<u8>> {
     // stream_position Result from seek, which only fails on negative locations
@@ -74,41 +103,91 @@ pub(crate) fn read_exact_vec<R: Read+Seek>(
         None => reader.stream_position().unwrap()
     };
     let mut vec_read_buf = vec![0x00; len];
-    let read_exact_result = reader.read_exact(vec_read_buf.as_mut_slice());
-    match read_exact_result {
-        Ok(()) => Ok(vec_read_buf),
+    match fill_buf(reader, vec_read_buf.as_mut_slice()) {
+        Ok(read_len) => {
+            // Only short at EOF
+            if read_len < len {
+                vec_read_buf.truncate(read_len);
+                vec_read_buf.shrink_to_fit();
+            }
+            Ok(vec_read_buf)
+        },
         Err(e) => {
-            if e.kind() == ErrorKind::UnexpectedEof {
-                // Read as much as possible
-                /*
-                 * - read guarantees same-pos-on-err but read_exact doesn't,
-                 *   so reset seek pos
-                 * - seek only fails on negative locations
-                 */
-                reader.seek(SeekFrom::Start(reader_pos_old)).unwrap();
-
-                vec_read_buf.clear();
-                let read_len_res = reader.read_to_end(&mut vec_read_buf);
-                match read_len_res {
-                    Ok(read_len) => {
-                        debug_assert!(read_len < len);
-                        vec_read_buf.resize(read_len, 0x00);
-                        vec_read_buf.shrink_to_fit();
-                        Ok(vec_read_buf)
-                    }
-                    Err(e) => {
-                        // read_to_end may read nonzero bytes even if error occured
-                        reader.seek(SeekFrom::Start(reader_pos_old)).unwrap();
-                        Err(e)
-                    }
-                }
-            } else {
-                Err(e)
+            // Nonzero bytes may have been read before the error, so reset seek pos
+            // seek only fails on negative locations
+            reader.seek(SeekFrom::Start(reader_pos_old)).unwrap();
+            Err(e)
+        }
+    }
+}
+
+const READ_RETRY_BASE_DELAY: Duration = Duration::from_millis(1);
+
+// Interrupted reads are already continued by fill_buf
+#[inline]
+fn is_transient_err(kind: ErrorKind) -> bool {
+    matches!(kind, ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+// Functions like normal `read`, but with additional guarantees:
+// - Slice is always filled when there is enough data left to read
+// - When not enough data is left, slice is filled up to returned length
+// - File cursor will be at its original position if an error occurs
+// expected_seek_loc provides a way to pass in current location, if available
+// When provided, this saves a seek operation if the given slice was full
+// Reads that fail with a transient error are started over up to max_retries times,
+// with exponential backoff
+pub(crate) fn read_exact_vec<R: Read+Seek>(
+        reader: &mut R, expected_seek_loc: Option<u64>, len: usize,
+        max_retries: u32) -> IOResult<Vec<u8>> {
+    let mut retry_delay = READ_RETRY_BASE_DELAY;
+    let mut retries_left = max_retries;
+    loop {
+        match read_exact_vec_once(reader, expected_seek_loc, len) {
+            Err(e) if is_transient_err(e.kind()) && retries_left > 0 => {
+                // Cursor was restored, so the read can start over
+                retries_left -= 1;
+                thread::sleep(retry_delay);
+                retry_delay *= 2;
             }
+            result => return result
         }
     }
 }
 
+// Separate handles to one file, so that reads from several threads
+// each get their own cursor instead of taking turns seeking one handle
+// A handle is opened whenever all others are in use, so there are at most
+// as many handles as reads that were ever in progress at once
+#[derive(Debug)]
+pub(crate) struct FilePool {
+    path: PathBuf,
+    idle_handles: Mutex<Vec<File>>,
+    read_retries: u32
+}
+impl FilePool {
+    pub fn new(path: &Path, read_retries: u32) -> Self {
+        Self {path: path.to_path_buf(), idle_handles: Mutex::new(Vec::new()), read_retries}
+    }
+    // read_exact_vec starting at pos, through a handle that no other read is using
+    pub fn read_at(&self, pos: u64, len: usize) -> IOResult<Vec<u8>> {
+        let idle_handle = self.idle_handles.lock().unwrap().pop();
+        let mut handle = match idle_handle {
+            Some(handle) => handle,
+            None => File::open(&self.path)?
+        };
+        handle.seek(SeekFrom::Start(pos))?;
+        let read_result = read_exact_vec(&mut handle, Some(pos), len, self.read_retries);
+        self.idle_handles.lock().unwrap().push(handle);
+        read_result
+    }
+    // Number of handles opened so far, all of which are idle between reads
+    #[cfg(test)]
+    fn handle_count(&self) -> usize {
+        self.idle_handles.lock().unwrap().len()
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct BlockRange {
     start: u64,
@@ -125,6 +204,16 @@ impl BlockRange {
         }
         BlockRange {start, end, include_end}
     }
+    // new rejects ranges with no elements, which need to be made explicitly
+    // This is [start-start), with the end excluded
+    #[inline]
+    pub const fn empty(start: u64) -> BlockRange {
+        BlockRange {start, end: start, include_end: false}
+    }
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.range() == 0
+    }
     #[inline]
     pub const fn range(&self) -> u64 {
         match self.include_end {
@@ -168,12 +257,12 @@ impl Eq for BlockRange {}
 
 impl std::hash::Hash for BlockRange {
     fn hash<H: std::hash::Hasher> (&self, hasher: &mut H) {
-        let range_end = match self.include_end {
-            true => self.end,
-            false => self.end-1
+        // Same exclusive end as in eq, which is None only for an inclusive u64::MAX
+        let end_exclusive = match self.include_end {
+            true => self.end.checked_add(1),
+            false => Some(self.end)
         };
-        let range_tuple = self.start..=range_end;
-        range_tuple.hash(hasher);
+        (self.start, end_exclusive).hash(hasher);
     }
 }
 
@@ -198,10 +287,13 @@ impl From<Range<u64>> for BlockRange {
             _ => unreachable!()
         };
         let end_val: u64 = match value.end_bound() {
-            Bound::Excluded(val) => val-1,
+            Bound::Excluded(val) => *val,
             _ => unreachable!()
         };
-        BlockRange::new(start_val, end_val, true)
+        match end_val == start_val {
+            true => BlockRange::empty(start_val),
+            false => BlockRange::new(start_val, end_val - 1, true)
+        }
     }
 }
 
@@ -263,7 +355,8 @@ impl<const CAP: usize> FromHex for HashData<CAP> {
     }
 }
 
-pub(crate) const MAX_HASH_LEN: usize = 512/8;
+/// Longest digest output, in bytes, that trees can be hashed with.
+pub const MAX_HASH_LEN: usize = 512/8;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct HashRange {
@@ -293,6 +386,139 @@ impl HashRange {
     }
 }
 
+// How child hashes are combined into the input of an internal node hash
+// Plain: 0x01 || child hashes
+// ChildCount: 0x01 || child count (u16) || (byte length (u64) || hash) per child
+// Integers are big-endian
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeEncoding {
+    #[default]
+    Plain,
+    ChildCount
+}
+impl fmt::Display for NodeEncoding {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeEncoding::Plain => fmt.write_str("plain"),
+            NodeEncoding::ChildCount => fmt.write_str("child-count")
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeEncodingFromStrErr(String);
+impl fmt::Display for NodeEncodingFromStrErr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "Invalid node encoding {}", self.0)
+    }
+}
+impl std::error::Error for NodeEncodingFromStrErr {}
+
+// Reader position found by TreeOptions::verify_positions, which should never differ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositionMismatch {
+    pub expected: u64,
+    pub actual: u64
+}
+impl fmt::Display for PositionMismatch {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "Reader was at byte {} instead of the expected byte {}",
+            self.actual, self.expected)
+    }
+}
+
+// Hashing stops early when the file cannot be read
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HashFileErr {
+    position_mismatch: Option<PositionMismatch>
+}
+impl HashFileErr {
+    // Set when hashing stopped because of a position check rather than a read error
+    pub fn position_mismatch(&self) -> Option<PositionMismatch> {
+        self.position_mismatch
+    }
+}
+impl From<PositionMismatch> for HashFileErr {
+    fn from(mismatch: PositionMismatch) -> Self {
+        Self {position_mismatch: Some(mismatch)}
+    }
+}
+impl fmt::Display for HashFileErr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position_mismatch {
+            Some(mismatch) => write!(fmt, "Unable to hash file: {}", mismatch),
+            None => fmt.write_str("Unable to read file for hashing")
+        }
+    }
+}
+impl std::error::Error for HashFileErr {}
+impl FromStr for NodeEncoding {
+    type Err = NodeEncodingFromStrErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(NodeEncoding::Plain),
+            "child-count" => Ok(NodeEncoding::ChildCount),
+            other => Err(NodeEncodingFromStrErr(other.to_owned()))
+        }
+    }
+}
+
+// Children are (byte length covered by child, child hash)
+// Public so that stored hashes can be rechecked without the hashed data
+pub fn internal_node_input<'a>(encoding: NodeEncoding,
+        children: impl ExactSizeIterator<Item = (u64, &'a [u8])>) -> Vec<u8> {
+    let mut node_input: Vec<u8> = vec![0x01];
+    if encoding == NodeEncoding::ChildCount {
+        let child_count = branch_t::try_from(children.len()).unwrap();
+        node_input.extend(child_count.to_be_bytes());
+    }
+    for (child_len, child_hash) in children {
+        if encoding == NodeEncoding::ChildCount {
+            node_input.extend(child_len.to_be_bytes());
+        }
+        node_input.extend_from_slice(child_hash);
+    }
+    // return
+    node_input
+}
+
+// Options that change the tree hash, besides block size and branch factor
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct TreeOptions {
+    // How internal nodes combine their children, as in internal_node_input
+    pub node_encoding: NodeEncoding,
+    // Mixed into every leaf hash after the 0x00 prefix, so equal blocks hash differently
+    pub salt: Vec<u8>,
+    // Include hashes of empty padding leaves so the tree is perfectly balanced
+    // Subtrees made only of padding are not passed to the consumer
+    pub pad_tree: bool,
+    // Internal nodes with one child take its hash, as in RFC 6962 trees
+    pub promote_lone_children: bool,
+    // Internal nodes repeat their last child up to the branch factor, as in Bitcoin trees
+    // Takes the place of padding if pad_tree is also set
+    pub duplicate_last: bool,
+    // Leaves are content-defined chunks with the block size as their target size,
+    // which must then be a power of 2
+    pub content_defined: bool,
+    // Leaves start this many bytes apart instead of one block apart, so they overlap
+    // Must be from 1 to the block size, and is ignored with content_defined
+    pub stride: Option<block_t>,
+    // Byte ranges end at a whole block even past the end of the file
+    // Has no effect on the hashes, and is ignored with content_defined or stride
+    pub nominal_ranges: bool,
+    // The leaf of an empty file covers the empty range [0x00-0x00) instead of [0x00-0x00]
+    // Off by default, as hash files record the inclusive range
+    pub empty_leaf_range: bool,
+    // Stop with an error if the reader is not where the tree expects before a read,
+    // as is always checked in debug builds
+    pub verify_positions: bool,
+    // Stack size in bytes of the pool threads, for trees too deep for the default
+    // Has no effect on the hashes, and is ignored without a thread pool
+    pub stack_size: Option<usize>,
+    // Times a read failing with WouldBlock or TimedOut is retried, with a doubling delay
+    // Has no effect on the hashes
+    pub read_retries: u32
+}
+
 pub trait Consumer<T> {
     fn accept(&self, var: T) -> Result<(), T>;
 }
@@ -306,21 +532,398 @@ impl<T> Consumer<T> for CrossbeamSender<T> {
     }
 }
 
+// Passes each value to both consumers, with the first getting a clone
+// Fails with the value if either consumer fails, and the second is skipped
+// if the first fails, but a value accepted by the first is not taken back
+#[derive(Debug, Clone)]
+pub struct TeeConsumer<A, B> {
+    first: A,
+    second: B
+}
+impl<A, B> TeeConsumer<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        TeeConsumer {first, second}
+    }
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+impl<T: Clone, A: Consumer<T>, B: Consumer<T>> Consumer<T> for TeeConsumer<A, B> {
+    fn accept(&self, var: T) -> Result<(), T> {
+        if self.first.accept(var.clone()).is_err() {
+            return Err(var);
+        }
+        self.second.accept(var)
+    }
+}
+
+// First ordering problem found by StrictOrderConsumer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderError {
+    // A node arrived in place of the next expected node
+    OutOfOrder {expected: BlockRange, found: BlockRange},
+    // A node arrived after every expected node
+    Unexpected(BlockRange),
+    // The next expected node never arrived
+    Missing(BlockRange)
+}
+impl fmt::Display for OrderError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderError::OutOfOrder {expected, found} =>
+                write!(fmt, "expected node {} but got node {}", expected, found),
+            OrderError::Unexpected(found) =>
+                write!(fmt, "got node {} after the last expected node", found),
+            OrderError::Missing(expected) =>
+                write!(fmt, "node {} never arrived", expected)
+        }
+    }
+}
+impl std::error::Error for OrderError {}
+
+struct StrictOrderState<I> {
+    expected: I,
+    error: Option<OrderError>
+}
+// Checks that nodes arrive in exactly the order of expected, such as
+// merkle_block_generator over the same file, as a debugging aid
+// Fails with the node on the first one out of order, and with every node after
+// Clones share their state, so that finish can be called after hashing
+// Combine with TeeConsumer to check the nodes passed to another consumer
+pub struct StrictOrderConsumer<I> {
+    state: Arc<Mutex<StrictOrderState<I>>>
+}
+impl<I> Clone for StrictOrderConsumer<I> {
+    fn clone(&self) -> Self {
+        StrictOrderConsumer {state: Arc::clone(&self.state)}
+    }
+}
+impl<I> fmt::Debug for StrictOrderConsumer<I> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("StrictOrderConsumer")
+            .field("error", &self.state.lock().unwrap().error)
+            .finish_non_exhaustive()
+    }
+}
+impl<I: Iterator<Item = BlockRange>> StrictOrderConsumer<I> {
+    pub fn new<T: IntoIterator<IntoIter = I>>(expected: T) -> Self {
+        StrictOrderConsumer {state: Arc::new(Mutex::new(StrictOrderState {
+            expected: expected.into_iter(),
+            error: None
+        }))}
+    }
+    // The first ordering error, or the first missing node once every node arrived
+    pub fn finish(&self) -> Result<(), OrderError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = state.error {
+            return Err(error);
+        }
+        match state.expected.next() {
+            Some(expected) => {
+                state.error = Some(OrderError::Missing(expected));
+                Err(OrderError::Missing(expected))
+            },
+            None => Ok(())
+        }
+    }
+}
+impl<I: Iterator<Item = BlockRange>> Consumer<HashRange> for StrictOrderConsumer<I> {
+    fn accept(&self, var: HashRange) -> Result<(), HashRange> {
+        let mut state = self.state.lock().unwrap();
+        if state.error.is_some() {
+            return Err(var);
+        }
+        let found = var.block_range();
+        state.error = match state.expected.next() {
+            Some(expected) if expected == found => return Ok(()),
+            Some(expected) => Some(OrderError::OutOfOrder {expected, found}),
+            None => Some(OrderError::Unexpected(found))
+        };
+        Err(var)
+    }
+}
+
+// Summary of the nodes passed to a StatsConsumer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockStats {
+    pub leaf_count: u64,
+    pub internal_count: u64,
+    // Number of different leaf hashes, which is less than leaf_count for repeated blocks
+    pub distinct_leaf_count: u64,
+    // Leaf hash shared by the most leaves, and the number of leaves with it
+    // Of hashes tied for the most leaves, the first to reach that count is kept
+    pub most_repeated_leaf: Option<(HashData<MAX_HASH_LEN>, u64)>
+}
+
+#[derive(Debug, Default)]
+struct StatsState {
+    stats: BlockStats,
+    leaf_hash_counts: HashMap<HashData<MAX_HASH_LEN>, u64>
+}
+// Counts leaf and internal nodes, and how often each leaf hash repeats,
+// such as to find runs of identical blocks in a disk image
+// Clones share their counts, so that finish can be called after hashing
+// Every distinct leaf hash is kept, so memory use grows with the number of distinct blocks
+#[derive(Debug, Clone, Default)]
+pub struct StatsConsumer {
+    state: Arc<Mutex<StatsState>>
+}
+impl StatsConsumer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // Stats of every node accepted so far, across every tree hashed with this consumer
+    pub fn finish(&self) -> BlockStats {
+        self.state.lock().unwrap().stats.clone()
+    }
+}
+impl Consumer<HashRange> for StatsConsumer {
+    fn accept(&self, var: HashRange) -> Result<(), HashRange> {
+        let mut state = self.state.lock().unwrap();
+        if var.block_range().range() != 1 {
+            state.stats.internal_count += 1;
+            return Ok(());
+        }
+        state.stats.leaf_count += 1;
+        let leaf_hash = var.hash_result;
+        let leaf_hash_count = state.leaf_hash_counts
+            .entry(leaf_hash.clone())
+            .or_default();
+        *leaf_hash_count += 1;
+        let leaf_hash_count = *leaf_hash_count;
+        if leaf_hash_count == 1 {
+            state.stats.distinct_leaf_count += 1;
+        }
+        let is_most_repeated = match &state.stats.most_repeated_leaf {
+            Some((_, most_count)) => leaf_hash_count > *most_count,
+            None => true
+        };
+        if is_most_repeated {
+            state.stats.most_repeated_leaf = Some((leaf_hash, leaf_hash_count));
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use std::io::Cursor;
 
+    #[derive(Default)]
+    struct VecConsumer<T>(Mutex<Vec<T>>);
+    impl<T> Consumer<T> for VecConsumer<T> {
+        fn accept(&self, var: T) -> Result<(), T> {
+            self.0.lock().unwrap().push(var);
+            Ok(())
+        }
+    }
+    // Counts values, and fails once it has reached its limit
+    struct CountingConsumer {
+        count: Mutex<usize>,
+        limit: usize
+    }
+    impl<T> Consumer<T> for CountingConsumer {
+        fn accept(&self, var: T) -> Result<(), T> {
+            let mut count = self.count.lock().unwrap();
+            if *count == self.limit {
+                return Err(var);
+            }
+            *count += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tee_consumer_forwards_to_both() {
+        let tee = TeeConsumer::new(VecConsumer::default(),
+            CountingConsumer {count: Mutex::new(0), limit: usize::MAX});
+        for var in 0..5 {
+            tee.accept(var).unwrap();
+        }
+        let (vec_consumer, counting_consumer) = tee.into_inner();
+        assert_eq!(vec_consumer.0.into_inner().unwrap(), [0, 1, 2, 3, 4]);
+        assert_eq!(counting_consumer.count.into_inner().unwrap(), 5);
+    }
+    #[test]
+    fn tee_consumer_fails_with_either() {
+        let first_full = TeeConsumer::new(
+            CountingConsumer {count: Mutex::new(0), limit: 1}, VecConsumer::default());
+        assert_eq!(first_full.accept(1), Ok(()));
+        assert_eq!(first_full.accept(2), Err(2));
+        // The second consumer is skipped when the first fails
+        assert_eq!(first_full.into_inner().1.0.into_inner().unwrap(), [1]);
+
+        let second_full = TeeConsumer::new(VecConsumer::default(),
+            CountingConsumer {count: Mutex::new(0), limit: 1});
+        assert_eq!(second_full.accept(1), Ok(()));
+        assert_eq!(second_full.accept(2), Err(2));
+        assert_eq!(second_full.into_inner().0.0.into_inner().unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn file_pool_reads_at_offsets() {
+        let path = std::env::temp_dir().join(format!("file_pool_test_{}", std::process::id()));
+        std::fs::write(&path, b"abcd1234ef").unwrap();
+        let file_pool = FilePool::new(&path, 0);
+        assert_eq!(file_pool.read_at(4, 4).unwrap(), b"1234");
+        assert_eq!(file_pool.read_at(8, 4).unwrap(), b"ef");
+        assert_eq!(file_pool.read_at(0, 4).unwrap(), b"abcd");
+        // Reads one after another share the same handle
+        assert_eq!(file_pool.handle_count(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn empty_block_range() {
+        let empty = BlockRange::empty(0);
+        assert!(empty.is_empty());
+        assert_eq!(empty.range(), 0);
+        assert_eq!(empty, BlockRange::from(0..0));
+        assert_ne!(empty, BlockRange::new(0, 0, true));
+        assert!(!BlockRange::new(0, 0, true).is_empty());
+        assert_eq!(empty.to_string(), "[0x00000000-0x00000000)");
+        // Equal ranges hash the same however their end is given
+        let hash_of = |range: &BlockRange| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            std::hash::Hash::hash(range, &mut hasher);
+            std::hash::Hasher::finish(&hasher)
+        };
+        assert_eq!(hash_of(&BlockRange::new(2, 5, true)), hash_of(&BlockRange::new(2, 6, false)));
+        assert_eq!(hash_of(&empty), hash_of(&BlockRange::from(0..0)));
+    }
+    #[test]
+    fn checked_node_count_overflow() {
+        assert_eq!(checked_node_count(0, 4, 2), Some(1));
+        assert_eq!(checked_node_count(16, 4, 2), Some(node_count(16, 4, 2)));
+        assert_eq!(checked_node_count(u64::MAX, 1, 2), None);
+        assert_eq!(checked_node_count(u64::MAX, 4, 4), Some(node_count(u64::MAX, 4, 4)));
+    }
     #[test]
     fn test_read_exact_full() {
         let mut read_obj = Cursor::new(b"12345678");
-        let read_result = read_exact_vec(&mut read_obj, Some(0), 4);
+        let read_result = read_exact_vec(&mut read_obj, Some(0), 4, 0);
         assert_eq!(read_result.unwrap(), Vec::from(*b"1234"));
     }
     #[test]
     fn test_read_exact_partial() {
         let mut read_obj = Cursor::new(b"abcde");
-        let read_result = read_exact_vec(&mut read_obj, Some(0), 16);
+        let read_result = read_exact_vec(&mut read_obj, Some(0), 16, 0);
         assert_eq!(read_result.unwrap(), Vec::from(*b"abcde"));
     }
+    // Returns at most chunk_len bytes per read, like a TTY or a network filesystem
+    struct ChunkedReader {
+        inner: Cursor<&'static [u8]>,
+        chunk_len: usize
+    }
+    impl Read for ChunkedReader {
+        fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+            let read_len = buf.len().min(self.chunk_len);
+            self.inner.read(&mut buf[..read_len])
+        }
+    }
+    impl Seek for ChunkedReader {
+        fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+            self.inner.seek(pos)
+        }
+    }
+    #[test]
+    fn test_read_exact_short_reads() {
+        let mut read_obj = ChunkedReader {inner: Cursor::new(b"abcdefghij"), chunk_len: 3};
+        // Short reads before EOF are continued until the block is full
+        assert_eq!(read_exact_vec(&mut read_obj, Some(0), 8, 0).unwrap(),
+            Vec::from(*b"abcdefgh"));
+        assert_eq!(read_exact_vec(&mut read_obj, Some(8), 8, 0).unwrap(),
+            Vec::from(*b"ij"));
+        assert_eq!(read_exact_vec(&mut read_obj, Some(10), 8, 0).unwrap(), Vec::new());
+    }
+    // Reads are given by outcomes in turn, then read normally
+    // Some(kind) fails without reading anything, and None reads one byte
+    struct FlakyReader {
+        inner: Cursor<&'static [u8]>,
+        outcomes: Vec<Option<ErrorKind>>,
+        read_calls: usize
+    }
+    impl FlakyReader {
+        fn new(data: &'static [u8], outcomes: &[Option<ErrorKind>]) -> Self {
+            FlakyReader {inner: Cursor::new(data),
+                outcomes: outcomes.iter().rev().copied().collect(), read_calls: 0}
+        }
+    }
+    impl Read for FlakyReader {
+        fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+            self.read_calls += 1;
+            match self.outcomes.pop() {
+                Some(Some(kind)) => Err(std::io::Error::new(kind, "flaky read")),
+                Some(None) => {
+                    let partial_len = buf.len().min(1);
+                    self.inner.read(&mut buf[..partial_len])
+                },
+                None => self.inner.read(buf)
+            }
+        }
+    }
+    impl Seek for FlakyReader {
+        fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+            self.inner.seek(pos)
+        }
+    }
+    #[test]
+    fn test_read_exact_interrupted() {
+        // Interrupted reads are continued without using up retries
+        let mut read_obj = FlakyReader::new(b"12345678",
+            &[Some(ErrorKind::Interrupted), Some(ErrorKind::Interrupted)]);
+        let read_result = read_exact_vec(&mut read_obj, Some(0), 4, 0);
+        assert_eq!(read_result.unwrap(), Vec::from(*b"1234"));
+        assert_eq!(read_obj.stream_position().unwrap(), 4);
+        assert_eq!(read_obj.read_calls, 3);
+    }
+    #[test]
+    fn test_read_exact_retry() {
+        let mut read_obj = FlakyReader::new(b"12345678",
+            &[Some(ErrorKind::WouldBlock), Some(ErrorKind::TimedOut)]);
+        let read_result = read_exact_vec(&mut read_obj, Some(0), 4, 2);
+        assert_eq!(read_result.unwrap(), Vec::from(*b"1234"));
+        assert_eq!(read_obj.stream_position().unwrap(), 4);
+        // Other errors are not retried
+        let mut read_obj = FlakyReader::new(b"12345678",
+            &[Some(ErrorKind::PermissionDenied)]);
+        let read_result = read_exact_vec(&mut read_obj, Some(0), 4, 2);
+        assert_eq!(read_result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(read_obj.read_calls, 1);
+    }
+    #[test]
+    fn test_read_exact_retry_exhausted() {
+        // The block is read again from its start after a byte was read before the error
+        let mut read_obj = FlakyReader::new(b"12345678",
+            &[None, Some(ErrorKind::WouldBlock), None, Some(ErrorKind::WouldBlock)]);
+        let read_result = read_exact_vec(&mut read_obj, Some(0), 4, 1);
+        assert_eq!(read_result.unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(read_obj.stream_position().unwrap(), 0);
+        assert_eq!(read_obj.read_calls, 4);
+        let mut read_obj = FlakyReader::new(b"12345678",
+            &[None, Some(ErrorKind::WouldBlock)]);
+        let read_result = read_exact_vec(&mut read_obj, Some(0), 4, 1);
+        assert_eq!(read_result.unwrap(), Vec::from(*b"1234"));
+    }
+
+    #[test]
+    fn test_child_count_binds_shape() {
+        let hash_a = [0xaa; 4];
+        let hash_b = [0xbb; 4];
+        // Same concatenated child hashes, but different child byte lengths
+        let even_split = [(4, &hash_a[..]), (4, &hash_b[..])];
+        let uneven_split = [(6, &hash_a[..]), (2, &hash_b[..])];
+
+        assert_eq!(internal_node_input(NodeEncoding::Plain, even_split.iter().copied()),
+            internal_node_input(NodeEncoding::Plain, uneven_split.iter().copied()));
+        assert_ne!(internal_node_input(NodeEncoding::ChildCount, even_split.iter().copied()),
+            internal_node_input(NodeEncoding::ChildCount, uneven_split.iter().copied()));
+
+        let mut expected_input = vec![0x01, 0x00, 0x02];
+        expected_input.extend([0, 0, 0, 0, 0, 0, 0, 4, 0xaa, 0xaa, 0xaa, 0xaa]);
+        expected_input.extend([0, 0, 0, 0, 0, 0, 0, 4, 0xbb, 0xbb, 0xbb, 0xbb]);
+        assert_eq!(internal_node_input(NodeEncoding::ChildCount, even_split.iter().copied()),
+            expected_input);
+    }
 }
@@ -0,0 +1,182 @@
+#![forbid(unsafe_code)]
+// Proofs for single blocks, without holding the hashes of the whole tree
+
+use crate::merkle_utils::{exp_ceil_log, internal_node_input, read_exact_vec};
+use crate::merkle_utils::{seek_len, HashData, HashFileErr, NodeEncoding};
+use crate::merkle_utils::{branch_t, block_t};
+
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom};
+
+use digest::Digest;
+
+/// Hash of a subtree next to a node on the path to the proven block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofSibling {
+    pub hash: HashData<64>,
+    /// Whether the sibling comes before the node on the path
+    pub is_left: bool
+}
+
+/// Proof that a block is part of the tree with the given root hash.
+///
+/// `levels` holds the siblings of the node on the path at each level,
+/// in child order, starting from the level of the leaf. A level is empty
+/// when the node on the path is an only child, but is still hashed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockProof {
+    pub leaf_hash: HashData<64>,
+    pub levels: Vec<Vec<ProofSibling>>,
+    pub root_hash: HashData<64>
+}
+impl BlockProof {
+    /// Recomputes the root hash from a leaf hash and the siblings in the proof.
+    ///
+    /// The result matches `root_hash` if `leaf_hash` is the hash of the
+    /// proven block.
+    pub fn root_from_leaf<D: Digest>(&self, leaf_hash: &[u8]) -> HashData<64> {
+        let mut node_hash = HashData::try_new(leaf_hash).unwrap();
+        for level in &self.levels {
+            let left_count = level.iter().take_while(|sibling| sibling.is_left).count();
+            let child_hashes: Vec<&[u8]> = level[..left_count].iter()
+                .map(|sibling| sibling.hash.as_ref())
+                .chain(std::iter::once(node_hash.as_ref()))
+                .chain(level[left_count..].iter().map(|sibling| sibling.hash.as_ref()))
+                .collect();
+            node_hash = internal_hash::<D>(&child_hashes);
+        }
+        node_hash
+    }
+}
+
+fn internal_hash<D: Digest>(child_hashes: &[&[u8]]) -> HashData<64> {
+    // Plain encoding does not use the child lengths
+    let hash_input = internal_node_input(NodeEncoding::Plain,
+        child_hashes.iter().map(|child_hash| (0, *child_hash)));
+    HashData::try_new(&D::digest(hash_input.as_slice())).unwrap()
+}
+
+// Parameters shared by every level of the traversal
+struct ProofReader<'a, F: Read+Seek> {
+    file: &'a mut F,
+    block_size: block_t,
+    block_count: u64,
+    branch: branch_t
+}
+impl<F: Read+Seek> ProofReader<'_, F> {
+    fn leaf_hash<D: Digest>(&mut self, block: u64) -> Result<HashData<64>, HashFileErr> {
+        let block_start = block*u64::from(self.block_size);
+        self.file.seek(SeekFrom::Start(block_start))
+            .map_err(|_| HashFileErr::default())?;
+        let block_data = read_exact_vec(self.file, Some(block_start),
+            usize::try_from(self.block_size).unwrap(), 0)
+            .map_err(|_| HashFileErr::default())?;
+        let mut digest_obj = D::new_with_prefix([0x00]);
+        digest_obj.update(&block_data);
+        Ok(HashData::try_new(&digest_obj.finalize()).unwrap())
+    }
+    // Start blocks of the children that are not past the end of the file
+    fn child_starts(&self, start_block: u64, interval: u64) -> impl Iterator<Item = u64> {
+        let child_interval = interval / u64::from(self.branch);
+        let block_count = self.block_count;
+        (0..u64::from(self.branch))
+            .map(move |child_index| start_block + child_index*child_interval)
+            .take_while(move |child_start| *child_start < block_count)
+    }
+    // Hashes a whole subtree, keeping only one hash per level
+    fn subtree_hash<D: Digest>(&mut self, start_block: u64,
+            interval: u64) -> Result<HashData<64>, HashFileErr> {
+        if interval == 1 {
+            return self.leaf_hash::<D>(start_block);
+        }
+        let child_interval = interval / u64::from(self.branch);
+        let child_starts: Vec<u64> = self.child_starts(start_block, interval).collect();
+        let mut child_hashes: Vec<HashData<64>> = Vec::with_capacity(child_starts.len());
+        for child_start in child_starts {
+            child_hashes.push(self.subtree_hash::<D>(child_start, child_interval)?);
+        }
+        let child_slices: Vec<&[u8]> = child_hashes.iter().map(|hash| hash.as_ref()).collect();
+        Ok(internal_hash::<D>(&child_slices))
+    }
+    // Returns the leaf hash and the subtree hash, adding levels leaf first
+    fn path_hash<D: Digest>(&mut self, start_block: u64, interval: u64,
+            target_block: u64, levels: &mut Vec<Vec<ProofSibling>>)
+            -> Result<(HashData<64>, HashData<64>), HashFileErr> {
+        if interval == 1 {
+            let leaf_hash = self.leaf_hash::<D>(start_block)?;
+            return Ok((leaf_hash.clone(), leaf_hash));
+        }
+        let child_interval = interval / u64::from(self.branch);
+        let child_starts: Vec<u64> = self.child_starts(start_block, interval).collect();
+        let path_index = child_starts.iter()
+            .rposition(|child_start| *child_start <= target_block)
+            .unwrap();
+        let mut child_hashes: Vec<HashData<64>> = Vec::with_capacity(child_starts.len());
+        let mut leaf_hash = None;
+        for (child_index, child_start) in child_starts.into_iter().enumerate() {
+            if child_index == path_index {
+                let (path_leaf_hash, child_hash) = self.path_hash::<D>(child_start,
+                    child_interval, target_block, levels)?;
+                leaf_hash = Some(path_leaf_hash);
+                child_hashes.push(child_hash);
+            } else {
+                child_hashes.push(self.subtree_hash::<D>(child_start, child_interval)?);
+            }
+        }
+        let child_slices: Vec<&[u8]> = child_hashes.iter().map(|hash| hash.as_ref()).collect();
+        let node_hash = internal_hash::<D>(&child_slices);
+        levels.push(child_hashes.into_iter().enumerate()
+            .filter(|(child_index, _)| *child_index != path_index)
+            .map(|(child_index, hash)| ProofSibling {hash, is_left: child_index < path_index})
+            .collect());
+        Ok((leaf_hash.unwrap(), node_hash))
+    }
+}
+
+/// Computes the proof for one block of a file.
+///
+/// Sibling subtrees are hashed as they are read, so memory use grows with
+/// the depth of the tree rather than the length of the file, although
+/// every block is still read once. Hashes use the plain node encoding
+/// without a salt, like [`merkle_hash_file`](crate::merkle_hash_file).
+///
+/// # Panics
+///
+/// Panics if `target_block` is past the last block of the file.
+///
+/// ```
+/// use merkle_tree::{prove_block, merkle_hash_bytes};
+/// use sha2::{Digest, Sha256};
+/// use std::io::Cursor;
+///
+/// let data = b"abcd1234ef";
+/// let proof = prove_block::<_, Sha256>(Cursor::new(data), 4, 2, 1).unwrap();
+/// let (tx, _rx) = crossbeam_channel::unbounded();
+/// let root_hash = merkle_hash_bytes::<Sha256, _>(data, 4, 2, tx, 0).unwrap();
+/// assert_eq!(proof.root_hash, root_hash);
+///
+/// // Block 1 is "1234", which the proof ties to the root
+/// let leaf_hash = Sha256::digest(b"\x001234");
+/// assert_eq!(proof.root_from_leaf::<Sha256>(&leaf_hash), root_hash);
+/// ```
+pub fn prove_block<F, D>(mut file: F, block_size: block_t, branch: branch_t,
+        target_block: u64) -> Result<BlockProof, HashFileErr>
+where
+    F: Read + Seek,
+    D: Digest
+{
+    assert!(block_size != 0);
+    assert!(branch >= 2);
+    let file_len = seek_len(&mut file);
+    // An empty file still has one (empty) block
+    let block_count = match file_len.div_ceil(block_size.into()) {
+        0 => 1,
+        n => n
+    };
+    assert!(target_block < block_count);
+    let mut proof_reader = ProofReader {file: &mut file, block_size, block_count, branch};
+    let mut levels = Vec::new();
+    let (leaf_hash, root_hash) = proof_reader.path_hash::<D>(0,
+        exp_ceil_log(block_count, branch), target_block, &mut levels)?;
+    Ok(BlockProof {leaf_hash, levels, root_hash})
+}
@@ -27,13 +27,64 @@ use crossbeam_deque::{Injector, Worker, Stealer};
 use std::fmt::Debug;
 use std::sync::atomic::{AtomicU32, Ordering};
 
+#[cfg(feature = "rayon")]
+use crossbeam_channel::{bounded, Receiver};
+
+#[cfg(feature = "testing")]
+use std::cell::RefCell;
+#[cfg(feature = "testing")]
+use std::sync::atomic::AtomicU64;
+
 #[cfg(feature = "hwlocality")]
 static TOPOLOGY: OnceLock<Result<Topology, RawHwlocError>> = OnceLock::new();
 
+/// Called by the workers of a thread pool around each task it runs.
+///
+/// Tasks are numbered in the order they were enqueued, starting from 0.
+/// A hook can block in [`before_task`](TaskHook::before_task) until other
+/// tasks are done, so that hashes reach the consumer in an order chosen by
+/// the test rather than by the scheduler. A task that waits on a task that
+/// is only enqueued after it finishes, such as its parent, never runs.
+#[cfg(feature = "testing")]
+pub trait TaskHook: Debug + Send + Sync {
+    /// Called on the worker before task_index runs.
+    fn before_task(&self, task_index: u64);
+    /// Called on the worker after task_index has run, including when it panicked.
+    fn after_task(&self, task_index: u64);
+}
+#[cfg(feature = "testing")]
+thread_local! {
+    static TASK_HOOK: RefCell<Option<Arc<dyn TaskHook>>> = const { RefCell::new(None) };
+}
+
+/// Sets the [`TaskHook`] of thread pools created afterwards on the calling thread.
+///
+/// `None` removes the hook. Only [`ParallelBackend::EagerThreadPool`] uses the
+/// hook, which runs in debug and release builds alike. The hook only exists
+/// with the `testing` feature, so other builds are unaffected.
+#[cfg(feature = "testing")]
+pub fn set_task_hook(hook: Option<Arc<dyn TaskHook>>) {
+    TASK_HOOK.with(|cell| *cell.borrow_mut() = hook);
+}
+
 pub(crate) trait Joinable<T> {
     fn join(self) -> T;
 }
 
+/// Thread pool used to hash nodes when the thread count is nonzero.
+///
+/// Either backend produces the same hashes, and the file is still read
+/// on the calling thread, so only the hashing itself is spread out.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParallelBackend {
+    /// The crate's own work-stealing pool, pinned to cores with hwlocality
+    #[default]
+    EagerThreadPool,
+    /// A dedicated Rayon pool with the requested number of threads
+    #[cfg(feature = "rayon")]
+    Rayon
+}
+
 #[derive(Debug)]
 pub(crate) struct ThreadPoolTaskHandle<T> {
     // Holds the result from the thread, when it's ready
@@ -76,6 +127,34 @@ impl<T> Joinable<T> for DummyHandle<T> {
     }
 }
 
+#[cfg(feature = "rayon")]
+#[derive(Debug)]
+pub(crate) struct RayonTaskHandle<T> {
+    // Receives the result once the task finishes, panicked or not
+    receiver: Receiver<T>
+}
+#[cfg(feature = "rayon")]
+impl<T> Joinable<T> for RayonTaskHandle<T> {
+    fn join(self) -> T {
+        // The task always sends before dropping the sender
+        self.receiver.recv().unwrap()
+    }
+}
+
+#[cfg(feature = "rayon")]
+pub(crate) fn enqueue_rayon_task<T: Send+'static>(pool: &rayon::ThreadPool,
+        func: impl FnOnce() -> T + UnwindSafe + Send + 'static)
+        -> RayonTaskHandle<ThreadResult<T>> {
+    let (sender, receiver) = bounded(1);
+    // Catch panics here so that they reach the joining thread like they do
+    // with EagerThreadPool, instead of aborting the process in Rayon
+    pool.spawn(move || {
+        // The handle may already be dropped after an earlier error
+        let _ = sender.send(catch_unwind(func));
+    });
+    RayonTaskHandle {receiver}
+}
+
 #[cfg(feature = "hwlocality")]
 fn get_cpu_affinities(topology: &Topology, thread_count: usize) -> Option<Vec<CpuSet>> {
     // Check that we have the required featureset
@@ -103,6 +182,11 @@ pub(crate) struct EagerThreadPool {
     // Highest bit set to signal pool shutdown
     // TODO: unsure if consistent total ordering is needed, using SeqCst just in case
     task_status: Arc<AtomicU32>,
+    // Test hook from set_task_hook, with the number of tasks enqueued so far
+    #[cfg(feature = "testing")]
+    task_hook: Option<Arc<dyn TaskHook>>,
+    #[cfg(feature = "testing")]
+    task_count: AtomicU64
 }
 impl EagerThreadPool {
     const TASK_WAITING_BITMASK: u32 = 0x00000001;
@@ -135,7 +219,8 @@ impl EagerThreadPool {
         // Extract the stolen task, if there is one.
         .and_then(|s| s.success())
     }
-    pub fn new(thread_count: usize) -> Self {
+    // Threads get the default stack size unless stack_size is given
+    pub fn new(thread_count: usize, stack_size: Option<usize>) -> Self {
         let injector: Arc<Injector<Box<dyn FnOnce() + Send>>> = Arc::new(Injector::new());
         let task_status = Arc::new(AtomicU32::new(0));
 
@@ -179,8 +264,12 @@ impl EagerThreadPool {
             let injector_thread = injector.clone();
             let stealer_vec_thread = stealer_vec.clone();
 
-            handle_vec.push(thread::Builder::new()
-                .name(format!("eager_threadpool-{}", i))
+            let mut thread_builder = thread::Builder::new()
+                .name(format!("eager_threadpool-{}", i));
+            if let Some(stack_size) = stack_size {
+                thread_builder = thread_builder.stack_size(stack_size);
+            }
+            handle_vec.push(thread_builder
                 .spawn(move || {
                     #[cfg(feature = "hwlocality")]
                     if let Some(cpuset) = thread_binding_info {
@@ -216,7 +305,15 @@ impl EagerThreadPool {
                 })
             .unwrap());
         }
-        Self {thread_handles: handle_vec, task_injector: injector, task_status}
+        Self {
+            thread_handles: handle_vec,
+            task_injector: injector,
+            task_status,
+            #[cfg(feature = "testing")]
+            task_hook: TASK_HOOK.with(|cell| cell.borrow().clone()),
+            #[cfg(feature = "testing")]
+            task_count: AtomicU64::new(0)
+        }
     }
     pub fn enqueue_task<T: Send+'static>(&self, func: impl FnOnce() -> T + UnwindSafe + Send + 'static) -> ThreadPoolTaskHandle<ThreadResult<T>> {
         let state_handle = Arc::new(Mutex::new(None));
@@ -224,8 +321,20 @@ impl EagerThreadPool {
         let state_handle_thread = state_handle.clone();
         let state_waiter_thread = state_waiter.clone();
 
+        #[cfg(feature = "testing")]
+        let task_hook = self.task_hook.clone()
+            .map(|hook| (hook, self.task_count.fetch_add(1, Ordering::SeqCst)));
+
         self.task_injector.push(Box::new(move || {
+            #[cfg(feature = "testing")]
+            if let Some((hook, task_index)) = &task_hook {
+                hook.before_task(*task_index);
+            }
             let return_value = catch_unwind(func);
+            #[cfg(feature = "testing")]
+            if let Some((hook, task_index)) = &task_hook {
+                hook.after_task(*task_index);
+            }
 
             // After calling user code, update the state accordingly
             let mut state_guard = state_handle_thread.lock().unwrap();
@@ -268,7 +377,7 @@ mod test {
 
     #[test]
     fn test_threadpool_basic() {
-        let threadpool = EagerThreadPool::new(3);
+        let threadpool = EagerThreadPool::new(3, None);
         let mut result_handles: Vec<_> = Vec::new();
 
         let start_instant = Instant::now();
@@ -291,4 +400,14 @@ mod test {
         // Time delay should be 100ms*(9.div_ceil(3))+overhead
         //assert!(time_duration < Duration::from_millis(310));
     }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_rayon_task_panic() {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        let ok_handle = enqueue_rayon_task(&pool, || 3);
+        let panic_handle = enqueue_rayon_task(&pool, || -> u32 { panic!("task panic") });
+        assert_eq!(3, ok_handle.join().unwrap());
+        assert!(panic_handle.join().is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,76 @@
+use merkle_tree::{BlockRange, HashData, HashRange, MerkleTreeBuilder};
+use merkle_tree::{merkle_hash_file_with_options, NodeEncoding, TreeOptions};
+
+use std::io::Cursor;
+use digest::Digest;
+use sha2::Sha256;
+
+use crossbeam_channel::unbounded as unbounded_channel;
+
+#[test]
+fn builder_test_tree() {
+    let ref_leaf0_hash = Sha256::digest(b"\x00abcd");
+    let ref_leaf1_hash = Sha256::digest(b"\x001234");
+    let ref_tree_in = [b"\x01",
+        ref_leaf0_hash.as_slice(),
+        ref_leaf1_hash.as_slice()].concat();
+    let ref_tree_hash = Sha256::digest(ref_tree_in.as_slice());
+
+    let mut builder = MerkleTreeBuilder::<Sha256>::new(2);
+    builder.push_block(b"abcd");
+    builder.push_block(b"1234");
+    let (tree_hash, nodes) = builder.finish();
+    assert_eq!(ref_tree_hash.as_slice(), tree_hash.as_ref());
+    assert_eq!(nodes, vec![
+        HashRange::new(BlockRange::new(0, 0, true), BlockRange::new(0, 3, true),
+            HashData::try_new(&ref_leaf0_hash).unwrap()),
+        HashRange::new(BlockRange::new(1, 1, true), BlockRange::new(4, 7, true),
+            HashData::try_new(&ref_leaf1_hash).unwrap()),
+        HashRange::new(BlockRange::new(0, 1, true), BlockRange::new(0, 7, true),
+            HashData::try_new(&ref_tree_hash).unwrap())
+    ]);
+}
+#[test]
+fn builder_empty_tree() {
+    let (tree_hash, nodes) = MerkleTreeBuilder::<Sha256>::new(2).finish();
+    assert_eq!(Sha256::digest(b"\x00").as_slice(), tree_hash.as_ref());
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(nodes[0].byte_range(), BlockRange::new(0, 0, true));
+
+    let options = TreeOptions {empty_leaf_range: true, ..TreeOptions::default()};
+    let (_, nodes) = MerkleTreeBuilder::<Sha256>::with_options(2, &options).finish();
+    assert_eq!(nodes[0].byte_range(), BlockRange::empty(0));
+}
+#[test]
+fn builder_matches_file_hash() {
+    let data: Vec<u8> = (0..=255).collect();
+    let option_list = [
+        TreeOptions::default(),
+        TreeOptions {node_encoding: NodeEncoding::ChildCount,
+            salt: b"salt".to_vec(), ..TreeOptions::default()},
+        TreeOptions {pad_tree: true, ..TreeOptions::default()},
+        TreeOptions {promote_lone_children: true, ..TreeOptions::default()},
+        TreeOptions {duplicate_last: true, ..TreeOptions::default()}
+    ];
+    for options in option_list.iter() {
+        for branch in [2, 3, 4, 16] {
+            for data_len in [1, 4, 5, 17, 64, 100, 256] {
+                let file_data = &data[..data_len];
+                let (tx, rx) = unbounded_channel();
+                let file_root = merkle_hash_file_with_options::<_, Sha256, _>(
+                    Cursor::new(file_data), 4, branch, options, tx, 0).unwrap();
+                let file_nodes: Vec<HashRange> = rx.try_iter().collect();
+
+                let mut builder = MerkleTreeBuilder::<Sha256>::with_options(branch, options);
+                for block in file_data.chunks(4) {
+                    builder.push_block(block);
+                }
+                let (builder_root, builder_nodes) = builder.finish();
+                assert_eq!(file_root, builder_root,
+                    "Root mismatch for {:?}, branch {}, {} bytes", options, branch, data_len);
+                assert_eq!(file_nodes, builder_nodes,
+                    "Node mismatch for {:?}, branch {}, {} bytes", options, branch, data_len);
+            }
+        }
+    }
+}
@@ -1,11 +1,18 @@
 use merkle_tree::{BlockRange, merkle_block_generator, reorder_hashrange_iter};
+use merkle_tree::{reorder_hashrange_iter_capped, assume_ordered_hashrange_iter};
 use merkle_tree::{HashData, HashRange, merkle_hash_file};
+use merkle_tree::{merkle_hash_file_with_options, node_count, TreeOptions};
+use merkle_tree::{hash_file_to_vec, hash_file_streaming, NodeEncoding};
+use merkle_tree::{set_task_hook, TaskHook};
+use merkle_tree::{Consumer, OrderError, StrictOrderConsumer};
 
 use sha2::Sha256;
 use permutohedron::Heap;
 
 use std::convert::TryInto;
-use std::io::Cursor;
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::sync::{Arc, Condvar, Mutex};
 
 use crossbeam_channel::unbounded as unbounded_channel;
 
@@ -32,6 +39,105 @@ fn test_tree_iter() {
     }
 }
 
+#[test]
+fn test_tree_iter_branch_factors() {
+    // Odd branch factors leave ragged trees for most lengths
+    let data: Vec<u8> = (0..64).collect();
+    for branch in 2..=7 {
+        for data_len in 0..=data.len() {
+            let data_slice = &data[..data_len];
+            let expected: Vec<_> = merkle_block_generator(data_len as u64, 1, branch)
+                .into_iter().collect();
+            assert_eq!(expected.len() as u64, node_count(data_len as u64, 1, branch));
+            for pad_tree in [false, true] {
+                let (tx, rx) = unbounded_channel();
+                let options = TreeOptions {pad_tree, ..TreeOptions::default()};
+                merkle_hash_file_with_options::<_, Sha256, _>
+                    (Cursor::new(data_slice), 1, branch, &options, tx, 0).unwrap();
+                let hashed: Vec<_> = rx.into_iter()
+                    .map(|hashrange| hashrange.block_range())
+                    .collect();
+                assert_eq!(expected, hashed, "branch {} length {}", branch, data_len);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_hash_file_to_vec_threads() {
+    let data: Vec<u8> = (0..=255).collect();
+    let data_len: u64 = data.len().try_into().unwrap();
+    let expected: Vec<_> = merkle_block_generator(data_len, 4, 3)
+        .into_iter().collect();
+    let single_thread = hash_file_to_vec::<_, Sha256>(Cursor::new(&data), 4, 3, 0)
+        .unwrap();
+    for thread_count in 0..=4 {
+        let nodes = hash_file_to_vec::<_, Sha256>(Cursor::new(&data), 4, 3, thread_count)
+            .unwrap();
+        let block_ranges: Vec<_> = nodes.iter()
+            .map(|hashrange| hashrange.block_range())
+            .collect();
+        assert_eq!(expected, block_ranges, "thread count {}", thread_count);
+        assert_eq!(single_thread, nodes);
+    }
+}
+
+#[test]
+fn test_hash_file_streaming() {
+    let data: Vec<u8> = (0..=255).collect();
+    let data_len: u64 = data.len().try_into().unwrap();
+    let expected = hash_file_to_vec::<_, Sha256>(Cursor::new(data.clone()), 4, 3, 0)
+        .unwrap();
+    for thread_count in [0, 3] {
+        let (handle, nodes) = hash_file_streaming::<_, Sha256>(Cursor::new(data.clone()),
+            4, 3, &TreeOptions::default(), thread_count);
+        let block_iter = merkle_block_generator(data_len, 4, 3).into_iter();
+        let nodes: Vec<HashRange> = reorder_hashrange_iter(block_iter, nodes)
+            .into_iter().collect();
+        let root = handle.join().unwrap().unwrap();
+        assert_eq!(expected, nodes, "thread count {}", thread_count);
+        assert_eq!(root.as_ref(), nodes.last().unwrap().hash_result());
+    }
+}
+
+#[test]
+fn test_hash_file_streaming_dropped_early() {
+    // Far more nodes than fit in the channel, so hashing has to wait on the iterator
+    let data = vec![0x5a; 4096];
+    let (handle, mut nodes) = hash_file_streaming::<_, Sha256>(Cursor::new(data),
+        1, 2, &TreeOptions::default(), 0);
+    assert!(nodes.next().is_some());
+    drop(nodes);
+    assert!(handle.join().unwrap().is_none());
+}
+
+#[test]
+fn test_direct_matches_thread_pool() {
+    // Without a pool, nodes are hashed on the calling thread in tree order
+    let data: Vec<u8> = (0..=200).collect();
+    let data_len: u64 = data.len().try_into().unwrap();
+    let option_list = [
+        TreeOptions::default(),
+        TreeOptions {node_encoding: NodeEncoding::ChildCount, ..TreeOptions::default()},
+        TreeOptions {salt: b"salt".to_vec(), pad_tree: true, ..TreeOptions::default()}
+    ];
+    for options in &option_list {
+        let (tx_direct, rx_direct) = unbounded_channel();
+        let direct_root = merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(&data), 4, 3, options, tx_direct, 0).unwrap();
+        let (tx_pool, rx_pool) = unbounded_channel();
+        let pool_root = merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(&data), 4, 3, options, tx_pool, 2).unwrap();
+        assert_eq!(direct_root, pool_root);
+
+        let direct_nodes: Vec<HashRange> = rx_direct.into_iter().collect();
+        let block_iter = merkle_block_generator(data_len, 4, 3).into_iter();
+        let pool_nodes: Vec<HashRange> = reorder_hashrange_iter(block_iter, rx_pool.into_iter())
+            .into_iter().collect();
+        assert_eq!(direct_nodes, pool_nodes);
+    }
+}
+
 #[test]
 fn reorder_already_ordered() {
     let blockrange_vec = vec![
@@ -48,8 +154,22 @@ fn reorder_already_ordered() {
         }).collect();
     let hashrange_vec_ref = hashrange_vec.clone();
     let sorted_hashrange_vec: Vec<_> = reorder_hashrange_iter(
-        blockrange_vec.into_iter(), hashrange_vec.into_iter()).into_iter().collect();
+        blockrange_vec.clone().into_iter(), hashrange_vec.clone().into_iter())
+        .into_iter().collect();
     assert_eq!(hashrange_vec_ref, sorted_hashrange_vec);
+    // Skipping the reordering gives the same nodes
+    let passed_hashrange_vec: Vec<_> = assume_ordered_hashrange_iter(
+        blockrange_vec.into_iter(), hashrange_vec.into_iter()).into_iter().collect();
+    assert_eq!(sorted_hashrange_vec, passed_hashrange_vec);
+}
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "node arrived out of order")]
+fn assume_ordered_scrambled() {
+    let blockrange_vec: Vec<_> = merkle_block_generator(64, 4, 2).into_iter().collect();
+    let hashrange_vec = reversed_hashranges(&blockrange_vec);
+    for _ in assume_ordered_hashrange_iter(blockrange_vec.into_iter(),
+        hashrange_vec.into_iter()) {}
 }
 #[test]
 fn reorder_scrambled() {
@@ -75,4 +195,192 @@ fn reorder_scrambled() {
             blockrange_vec_iter, hashrange_vec_iter).into_iter().collect();
         assert_eq!(hashrange_vec_ref, sorted_hashrange_vec);
     }
-}
\ No newline at end of file
+}
+
+// Every internal node arrives before any leaf, with the first leaf last
+fn reversed_hashranges(blockrange_vec: &[BlockRange]) -> Vec<HashRange> {
+    blockrange_vec.iter().rev()
+        .map(|blockrange| {
+            let dummy_byterange = BlockRange::new(16, 16, true);
+            let dummy_hash = HashData::try_new(&[0x00, 0xff]).unwrap();
+            HashRange::new(*blockrange, dummy_byterange, dummy_hash)
+        }).collect()
+}
+#[test]
+fn reorder_reversed_within_cap() {
+    let blockrange_vec: Vec<_> = merkle_block_generator(64, 4, 2).into_iter().collect();
+    let hashrange_vec = reversed_hashranges(&blockrange_vec);
+    // Everything but the first leaf is held back
+    let sorted_blockranges: Vec<_> = reorder_hashrange_iter_capped(
+            blockrange_vec.clone().into_iter(), hashrange_vec.into_iter(),
+            blockrange_vec.len() - 1)
+        .into_iter()
+        .map(|hashrange| hashrange.block_range())
+        .collect();
+    assert_eq!(sorted_blockranges, blockrange_vec);
+}
+#[test]
+#[should_panic(expected = "more than 8 items arrived ahead of the next expected item")]
+fn reorder_reversed_over_cap() {
+    let blockrange_vec: Vec<_> = merkle_block_generator(64, 4, 2).into_iter().collect();
+    let hashrange_vec = reversed_hashranges(&blockrange_vec);
+    reorder_hashrange_iter_capped(blockrange_vec.into_iter(), hashrange_vec.into_iter(), 8)
+        .into_iter()
+        .for_each(drop);
+}
+#[test]
+fn reorder_duplicates_keep_first() {
+    let blockrange_vec: Vec<_> = (0..3).map(|block| BlockRange::new(block, block, true)).collect();
+    let hashrange_of = |block: usize, hash: &[u8]| HashRange::new(blockrange_vec[block],
+        BlockRange::new(16, 16, true), HashData::try_new(hash).unwrap());
+    // The repeat of the first leaf comes after it was returned
+    let hashrange_vec = vec![hashrange_of(2, &[0x00, 0xff]), hashrange_of(2, &[0xff, 0x00]),
+        hashrange_of(0, &[0x00, 0xff]), hashrange_of(0, &[0xff, 0x00]),
+        hashrange_of(1, &[0x00, 0xff])];
+    let sorted_hashrange_vec: Vec<_> = reorder_hashrange_iter_capped(
+            blockrange_vec.clone().into_iter(), hashrange_vec.into_iter(), 2)
+        .into_iter().collect();
+    assert_eq!(sorted_hashrange_vec, [hashrange_of(0, &[0x00, 0xff]),
+        hashrange_of(1, &[0x00, 0xff]), hashrange_of(2, &[0x00, 0xff])]);
+}
+
+// Holds back each task until the task it waits for has run
+#[derive(Debug, Default)]
+struct OrderGate {
+    waits_for: HashMap<u64, u64>,
+    finished: Mutex<HashSet<u64>>,
+    task_done: Condvar
+}
+impl TaskHook for OrderGate {
+    fn before_task(&self, task_index: u64) {
+        if let Some(awaited_index) = self.waits_for.get(&task_index) {
+            let mut finished = self.finished.lock().unwrap();
+            while !finished.contains(awaited_index) {
+                finished = self.task_done.wait(finished).unwrap();
+            }
+        }
+    }
+    fn after_task(&self, task_index: u64) {
+        self.finished.lock().unwrap().insert(task_index);
+        self.task_done.notify_all();
+    }
+}
+
+// Adds the nodes of a full tree of leaf_count leaves to nodes, in the order the
+// thread pool enqueues them, with each child waiting for the sibling after it
+// Children are enqueued before their parent, and only the parent waits for them,
+// so a node may wait for any later node other than its ancestors
+fn push_reversed_siblings(start: u64, leaf_count: u64, branch: u64,
+        nodes: &mut Vec<BlockRange>, waits_for: &mut HashMap<u64, u64>) -> u64 {
+    if leaf_count > 1 {
+        let child_indices: Vec<u64> = (0..branch)
+            .map(|child| push_reversed_siblings(start + child*leaf_count/branch,
+                leaf_count/branch, branch, nodes, waits_for))
+            .collect();
+        for child_pair in child_indices.windows(2) {
+            waits_for.insert(child_pair[0], child_pair[1]);
+        }
+    }
+    nodes.push(BlockRange::new(start, start+leaf_count-1, true));
+    nodes.len() as u64 - 1
+}
+
+#[test]
+fn reorder_scrambled_pipeline() {
+    // 16 leaves in 4 levels of a binary tree or 2 levels of a 4-ary tree
+    let data: Vec<u8> = (0..64).collect();
+    let data_len: u64 = data.len().try_into().unwrap();
+    for (branch, levels) in [(2, 4), (4, 2)] {
+        let (tx_direct, rx_direct) = unbounded_channel();
+        let direct_root = merkle_hash_file::<_, Sha256, _>
+            (Cursor::new(&data), 4, branch, tx_direct, 0).unwrap();
+        let direct_nodes: Vec<HashRange> = rx_direct.into_iter().collect();
+
+        let mut gate = OrderGate::default();
+        let mut gate_nodes: Vec<BlockRange> = Vec::new();
+        push_reversed_siblings(0, 16, branch.into(), &mut gate_nodes, &mut gate.waits_for);
+        let sibling_pairs: Vec<(BlockRange, BlockRange)> = gate.waits_for.iter()
+            .map(|(waiting, awaited)|
+                (gate_nodes[*waiting as usize], gate_nodes[*awaited as usize]))
+            .collect();
+        // Each level can have all but one sibling waiting, besides the running task
+        let thread_count = levels * (usize::from(branch) - 1) + 1;
+        set_task_hook(Some(Arc::new(gate)));
+        let (tx_pool, rx_pool) = unbounded_channel();
+        let pool_root = merkle_hash_file::<_, Sha256, _>
+            (Cursor::new(&data), 4, branch, tx_pool, thread_count).unwrap();
+        set_task_hook(None);
+        assert_eq!(direct_root, pool_root);
+
+        // Siblings arrive in reverse, however the workers are scheduled
+        let arrived_nodes: Vec<HashRange> = rx_pool.into_iter().collect();
+        let arrival_index = |block_range: BlockRange| arrived_nodes.iter()
+            .position(|node| node.block_range() == block_range).unwrap();
+        for (waiting, awaited) in sibling_pairs {
+            assert!(arrival_index(awaited) < arrival_index(waiting));
+        }
+        let block_iter = merkle_block_generator(data_len, 4, branch).into_iter();
+        let pool_nodes: Vec<HashRange> = reorder_hashrange_iter(block_iter, arrived_nodes.into_iter())
+            .into_iter().collect();
+        assert_eq!(direct_nodes, pool_nodes);
+    }
+}
+
+// Returns at most 3 bytes per read, so reads of a block come back short
+struct ChunkedReader(Cursor<Vec<u8>>);
+impl Read for ChunkedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read_len = buf.len().min(3);
+        self.0.read(&mut buf[..read_len])
+    }
+}
+impl Seek for ChunkedReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+#[test]
+fn test_short_reads_hash_whole_file() {
+    let data: Vec<u8> = (0..=255).collect();
+    let expected = hash_file_to_vec::<_, Sha256>(Cursor::new(&data), 16, 2, 0).unwrap();
+    for thread_count in [0, 2] {
+        let chunked = ChunkedReader(Cursor::new(data.clone()));
+        let hashed = hash_file_to_vec::<_, Sha256>(chunked, 16, 2, thread_count).unwrap();
+        assert_eq!(hashed, expected, "{} threads", thread_count);
+    }
+}
+
+#[test]
+fn test_strict_order_single_threaded() {
+    // Nodes arrive in generator order without a thread pool
+    let data: Vec<u8> = (0..100).collect();
+    for branch in 2..=4 {
+        let consumer = StrictOrderConsumer::new(merkle_block_generator(100, 4, branch));
+        merkle_hash_file::<_, Sha256, _>
+            (Cursor::new(&data), 4, branch, consumer.clone(), 0).unwrap();
+        assert_eq!(consumer.finish(), Ok(()), "branch {}", branch);
+    }
+}
+
+#[test]
+fn test_strict_order_detects_gap() {
+    let hash = HashData::try_new(&[0x00; 4]).unwrap();
+    let node = |block_range: BlockRange| HashRange::new(block_range, block_range, hash.clone());
+    let expected: Vec<BlockRange> = merkle_block_generator(16, 4, 2).into_iter().collect();
+
+    // Skipping the second node is reported where the third arrives instead
+    let consumer = StrictOrderConsumer::new(expected.clone());
+    assert!(consumer.accept(node(expected[0])).is_ok());
+    assert!(consumer.accept(node(expected[2])).is_err());
+    assert!(consumer.accept(node(expected[3])).is_err());
+    assert_eq!(consumer.finish(), Err(OrderError::OutOfOrder {
+        expected: expected[1], found: expected[2]}));
+
+    // Nodes missing from the end are only found by finish
+    let consumer = StrictOrderConsumer::new(expected.clone());
+    for block_range in &expected[..expected.len()-1] {
+        assert!(consumer.accept(node(*block_range)).is_ok());
+    }
+    assert_eq!(consumer.finish(), Err(OrderError::Missing(*expected.last().unwrap())));
+}
@@ -1,8 +1,14 @@
 use merkle_tree::{BlockRange, HashData, HashRange, merkle_hash_file};
-use merkle_tree::{merkle_block_generator, reorder_hashrange_iter};
-use merkle_tree::Consumer;
+use merkle_tree::NodeEncoding;
+use merkle_tree::{merkle_hash_file_with_options, TreeOptions};
+#[cfg(feature = "rayon")]
+use merkle_tree::{merkle_hash_file_with_backend, ParallelBackend};
+use merkle_tree::{merkle_block_generator, reorder_hashrange_iter, chunk_boundaries};
+use merkle_tree::{strided_block_generator, strided_byte_range};
+use merkle_tree::{Consumer, StatsConsumer};
 
-use std::io::Cursor;
+use std::collections::HashSet;
+use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::convert::TryInto;
 use digest::Digest;
 use sha2::Sha256;
@@ -108,3 +114,640 @@ fn test_tree() {
 fn test_tree_threaded() {
     test_tree_helper(3);
 }
+#[cfg(feature = "rayon")]
+#[test]
+fn test_tree_rayon_matches_default() {
+    let data_list: [&[u8]; 3] = [b"abcd1234", b"yz", b"abcd1234efgh5678ij"];
+    for data in data_list.iter() {
+        let data_len: u64 = data.len().try_into().unwrap();
+        let (tx_default, rx_default) = unbounded_channel();
+        let default_root = merkle_hash_file_with_backend::<_, Sha256, _>
+            (Cursor::new(data), 4, 2, &TreeOptions::default(), tx_default, 3,
+            ParallelBackend::EagerThreadPool).unwrap();
+        let (tx_rayon, rx_rayon) = unbounded_channel();
+        let rayon_root = merkle_hash_file_with_backend::<_, Sha256, _>
+            (Cursor::new(data), 4, 2, &TreeOptions::default(), tx_rayon, 3,
+            ParallelBackend::Rayon).unwrap();
+        assert_eq!(default_root, rayon_root);
+
+        let default_nodes: Vec<HashRange> = reorder_hashrange_iter(
+            merkle_block_generator(data_len, 4, 2).into_iter(),
+            rx_default.into_iter()).into_iter().collect();
+        let rayon_nodes: Vec<HashRange> = reorder_hashrange_iter(
+            merkle_block_generator(data_len, 4, 2).into_iter(),
+            rx_rayon.into_iter()).into_iter().collect();
+        assert_eq!(default_nodes, rayon_nodes);
+    }
+}
+
+fn test_child_count_tree_helper(thread_count: usize) {
+    let ref_leaf0_hash = Sha256::digest(b"\x00abcd");
+    let ref_leaf1_hash = Sha256::digest(b"\x0012");
+    // Child count, then byte length and hash of each child
+    let ref_tree_in = [&b"\x01\x00\x02"[..],
+        &4u64.to_be_bytes(), ref_leaf0_hash.as_slice(),
+        &2u64.to_be_bytes(), ref_leaf1_hash.as_slice()].concat();
+    let ref_tree_hash = Sha256::digest(ref_tree_in.as_slice());
+
+    let options = TreeOptions {
+        node_encoding: NodeEncoding::ChildCount,
+        ..TreeOptions::default()
+    };
+    let tree_hash = merkle_hash_file_with_options::<_, Sha256, _>
+        (Cursor::new(b"abcd12"), 4, 2, &options,
+        ThrowawayConsumer::default(), thread_count).unwrap();
+    assert_eq!(ref_tree_hash.as_slice(), tree_hash.as_ref());
+
+    let plain_hash = merkle_hash_file::<_, Sha256, _>
+        (Cursor::new(b"abcd12"), 4, 2,
+        ThrowawayConsumer::default(), thread_count).unwrap();
+    assert_ne!(plain_hash, tree_hash);
+}
+#[test]
+fn test_child_count_tree() {
+    test_child_count_tree_helper(0);
+}
+#[test]
+fn test_child_count_tree_threaded() {
+    test_child_count_tree_helper(3);
+}
+
+fn test_salted_tree_helper(thread_count: usize) {
+    // Salt goes between the leaf prefix and the data, and not in internal nodes
+    let ref_leaf0_hash = Sha256::digest(b"\x00saltabcd");
+    let ref_leaf1_hash = Sha256::digest(b"\x00salt12");
+    let ref_tree_in = [&b"\x01"[..],
+        ref_leaf0_hash.as_slice(), ref_leaf1_hash.as_slice()].concat();
+    let ref_tree_hash = Sha256::digest(ref_tree_in.as_slice());
+
+    let options = TreeOptions {salt: b"salt".to_vec(), ..TreeOptions::default()};
+    let tree_hash = merkle_hash_file_with_options::<_, Sha256, _>
+        (Cursor::new(b"abcd12"), 4, 2, &options,
+        ThrowawayConsumer::default(), thread_count).unwrap();
+    assert_eq!(ref_tree_hash.as_slice(), tree_hash.as_ref());
+
+    let options = TreeOptions {salt: b"pepper".to_vec(), ..TreeOptions::default()};
+    let other_salt_hash = merkle_hash_file_with_options::<_, Sha256, _>
+        (Cursor::new(b"abcd12"), 4, 2, &options,
+        ThrowawayConsumer::default(), thread_count).unwrap();
+    assert_ne!(other_salt_hash, tree_hash);
+
+    let options = TreeOptions {salt: b"".to_vec(), ..TreeOptions::default()};
+    let unsalted_hash = merkle_hash_file_with_options::<_, Sha256, _>
+        (Cursor::new(b"abcd12"), 4, 2, &options,
+        ThrowawayConsumer::default(), thread_count).unwrap();
+    let plain_hash = merkle_hash_file::<_, Sha256, _>
+        (Cursor::new(b"abcd12"), 4, 2,
+        ThrowawayConsumer::default(), thread_count).unwrap();
+    assert_eq!(unsalted_hash, plain_hash);
+}
+#[test]
+fn test_salted_tree() {
+    test_salted_tree_helper(0);
+}
+#[test]
+fn test_salted_tree_threaded() {
+    test_salted_tree_helper(3);
+}
+
+fn test_padded_tree_helper(thread_count: usize) {
+    // Three blocks, so the tree has one padding leaf
+    let ref_leaf0_hash = Sha256::digest(b"\x00abcd");
+    let ref_leaf1_hash = Sha256::digest(b"\x001234");
+    let ref_leaf2_hash = Sha256::digest(b"\x00ef");
+    let ref_pad_hash = Sha256::digest(b"\x00");
+    let ref_left_hash = Sha256::digest([&b"\x01"[..],
+        ref_leaf0_hash.as_slice(), ref_leaf1_hash.as_slice()].concat());
+    let ref_right_padded_hash = Sha256::digest([&b"\x01"[..],
+        ref_leaf2_hash.as_slice(), ref_pad_hash.as_slice()].concat());
+    let ref_tree_hash = Sha256::digest([&b"\x01"[..],
+        ref_left_hash.as_slice(), ref_right_padded_hash.as_slice()].concat());
+
+    let padded_options = TreeOptions {
+        pad_tree: true,
+        ..TreeOptions::default()
+    };
+    let (tx, rx) = unbounded_channel();
+    let padded_hash = merkle_hash_file_with_options::<_, Sha256, _>
+        (Cursor::new(b"abcd1234ef"), 4, 2, &padded_options,
+        tx, thread_count).unwrap();
+    assert_eq!(ref_tree_hash.as_slice(), padded_hash.as_ref());
+    // Padding leaves are not passed to the consumer
+    let padded_ranges: Vec<BlockRange> = rx.iter()
+        .map(|hash_range| hash_range.block_range())
+        .collect();
+
+    let (tx, rx) = unbounded_channel();
+    let unpadded_hash = merkle_hash_file_with_options::<_, Sha256, _>
+        (Cursor::new(b"abcd1234ef"), 4, 2, &TreeOptions::default(),
+        tx, thread_count).unwrap();
+    assert_ne!(padded_hash, unpadded_hash);
+    let unpadded_ranges: Vec<BlockRange> = rx.iter()
+        .map(|hash_range| hash_range.block_range())
+        .collect();
+    assert_eq!(padded_ranges.len(), unpadded_ranges.len());
+    for range in unpadded_ranges {
+        assert!(padded_ranges.contains(&range));
+    }
+}
+#[test]
+fn test_padded_tree() {
+    test_padded_tree_helper(0);
+}
+#[test]
+fn test_padded_tree_threaded() {
+    test_padded_tree_helper(3);
+}
+
+// Merkle tree hash of RFC 6962 section 2.1, splitting at the largest power of 2 below n
+fn rfc6962_root(leaves: &[&[u8]]) -> Vec<u8> {
+    match leaves.len() {
+        0 => Sha256::digest(b"").to_vec(),
+        1 => Sha256::digest([b"\x00", leaves[0]].concat()).to_vec(),
+        n => {
+            let split = 1 << (usize::BITS - (n-1).leading_zeros() - 1);
+            Sha256::digest([&b"\x01"[..], &rfc6962_root(&leaves[..split]),
+                &rfc6962_root(&leaves[split..])].concat()).to_vec()
+        }
+    }
+}
+
+#[test]
+fn test_rfc6962_reference_vectors() {
+    // Test vectors from the Certificate Transparency reference implementation
+    let ct_leaves: [&[u8]; 8] = [b"", b"\x00", b"\x10", b"\x20\x21", b"\x30\x31",
+        b"\x40\x41\x42\x43", b"\x50\x51\x52\x53\x54\x55\x56\x57",
+        b"\x60\x61\x62\x63\x64\x65\x66\x67\x68\x69\x6a\x6b\x6c\x6d\x6e\x6f"];
+    let ct_roots = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328"
+    ];
+    for (leaf_count, ct_root) in ct_roots.iter().enumerate().map(|(i, root)| (i+1, root)) {
+        assert_eq!(hex::encode(rfc6962_root(&ct_leaves[..leaf_count])), *ct_root);
+    }
+}
+
+fn test_promoted_tree_helper(thread_count: usize) {
+    let promoted_options = TreeOptions {
+        promote_lone_children: true,
+        ..TreeOptions::default()
+    };
+    // Leaf counts that are not powers of 2 leave subtrees without a sibling
+    let data: Vec<u8> = (0..=255u8).collect();
+    for leaf_count in 1..=13 {
+        let file_data = &data[..4*leaf_count - 1];
+        let blocks: Vec<&[u8]> = file_data.chunks(4).collect();
+        let (tx, rx) = unbounded_channel();
+        let tree_hash = merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(file_data), 4, 2, &promoted_options,
+            tx, thread_count).unwrap();
+        assert_eq!(tree_hash.as_ref(), rfc6962_root(&blocks).as_slice(),
+            "{} leaves", leaf_count);
+        // Promoted nodes still have entries
+        let block_iter = merkle_block_generator(file_data.len() as u64, 4, 2).into_iter();
+        assert_eq!(reorder_hashrange_iter(block_iter, rx.try_iter()).into_iter().count(),
+            merkle_block_generator(file_data.len() as u64, 4, 2).into_iter().count());
+    }
+    // 201 blocks leave lone children with both branch factors
+    for branch in [3, 100] {
+        let tree_hash = merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(&data[..201]), 1, branch, &promoted_options,
+            ThrowawayConsumer::default(), thread_count).unwrap();
+        let direct_hash = merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(&data[..201]), 1, branch, &promoted_options,
+            ThrowawayConsumer::default(), 0).unwrap();
+        let unpromoted_hash = merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(&data[..201]), 1, branch, &TreeOptions::default(),
+            ThrowawayConsumer::default(), thread_count).unwrap();
+        assert_eq!(tree_hash, direct_hash);
+        assert_ne!(tree_hash, unpromoted_hash);
+    }
+}
+#[test]
+fn test_promoted_tree() {
+    test_promoted_tree_helper(0);
+}
+#[test]
+fn test_promoted_tree_threaded() {
+    test_promoted_tree_helper(3);
+}
+
+// Bitcoin-style Merkle root, repeating the last node of each level with an odd count
+fn duplicate_last_root(leaves: &[&[u8]]) -> Vec<u8> {
+    let mut level: Vec<Vec<u8>> = leaves.iter()
+        .map(|leaf| Sha256::digest([b"\x00", *leaf].concat()).to_vec())
+        .collect();
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(level.last().unwrap().clone());
+        }
+        level = level.chunks(2)
+            .map(|pair| Sha256::digest([&b"\x01"[..], &pair[0], &pair[1]].concat()).to_vec())
+            .collect();
+    }
+    level.pop().unwrap()
+}
+
+fn test_duplicate_last_tree_helper(thread_count: usize) {
+    let duplicate_options = TreeOptions {
+        duplicate_last: true,
+        ..TreeOptions::default()
+    };
+    // Three leaves: the third is paired with itself
+    let leaf_hashes: Vec<_> = [&b"abcd"[..], b"1234", b"ef"].iter()
+        .map(|leaf| Sha256::digest([b"\x00", *leaf].concat()))
+        .collect();
+    let ref_left_hash = Sha256::digest([&b"\x01"[..], &leaf_hashes[0], &leaf_hashes[1]].concat());
+    let ref_right_hash = Sha256::digest([&b"\x01"[..], &leaf_hashes[2], &leaf_hashes[2]].concat());
+    let ref_tree_hash = Sha256::digest([&b"\x01"[..], &ref_left_hash, &ref_right_hash].concat());
+    let tree_hash = merkle_hash_file_with_options::<_, Sha256, _>
+        (Cursor::new(b"abcd1234ef"), 4, 2, &duplicate_options,
+        ThrowawayConsumer::default(), thread_count).unwrap();
+    assert_eq!(tree_hash.as_ref(), ref_tree_hash.as_slice());
+
+    let data: Vec<u8> = (0..=255u8).collect();
+    for leaf_count in 1..=13 {
+        let file_data = &data[..4*leaf_count - 1];
+        let blocks: Vec<&[u8]> = file_data.chunks(4).collect();
+        let (tx, rx) = unbounded_channel();
+        let tree_hash = merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(file_data), 4, 2, &duplicate_options,
+            tx, thread_count).unwrap();
+        assert_eq!(tree_hash.as_ref(), duplicate_last_root(&blocks).as_slice(),
+            "{} leaves", leaf_count);
+        // Repeated children do not get entries
+        let block_iter = merkle_block_generator(file_data.len() as u64, 4, 2).into_iter();
+        assert_eq!(reorder_hashrange_iter(block_iter, rx.try_iter()).into_iter().count(),
+            merkle_block_generator(file_data.len() as u64, 4, 2).into_iter().count());
+    }
+    // 201 blocks leave incomplete nodes with both branch factors
+    for branch in [3, 100] {
+        let tree_hash = merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(&data[..201]), 1, branch, &duplicate_options,
+            ThrowawayConsumer::default(), thread_count).unwrap();
+        let direct_hash = merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(&data[..201]), 1, branch, &duplicate_options,
+            ThrowawayConsumer::default(), 0).unwrap();
+        let padded_hash = merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(&data[..201]), 1, branch,
+            &TreeOptions {pad_tree: true, ..TreeOptions::default()},
+            ThrowawayConsumer::default(), thread_count).unwrap();
+        assert_eq!(tree_hash, direct_hash);
+        assert_ne!(tree_hash, padded_hash);
+    }
+}
+#[test]
+fn test_duplicate_last_tree() {
+    test_duplicate_last_tree_helper(0);
+}
+#[test]
+fn test_duplicate_last_tree_threaded() {
+    test_duplicate_last_tree_helper(3);
+}
+
+// Leaf hashes of a file hashed with the given options, in file order
+fn leaf_hashes(data: &[u8], block_size: u32, options: &TreeOptions,
+        thread_count: usize) -> Vec<HashRange> {
+    let (tx, rx) = unbounded_channel();
+    merkle_hash_file_with_options::<_, Sha256, _>(Cursor::new(data), block_size, 4,
+        options, tx, thread_count).unwrap();
+    let mut leaves: Vec<HashRange> = rx.try_iter()
+        .filter(|hash_range| hash_range.block_range().range() == 1)
+        .collect();
+    leaves.sort_by_key(|hash_range| hash_range.block_range().start());
+    leaves
+}
+// Deterministic data without long runs
+fn pseudorandom_data(len: usize) -> Vec<u8> {
+    let mut state: u32 = 1;
+    (0..len).map(|_| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (state >> 16) as u8
+    }).collect()
+}
+fn test_content_defined_tree_helper(thread_count: usize) {
+    let cdc_options = TreeOptions {
+        content_defined: true,
+        ..TreeOptions::default()
+    };
+    let data = pseudorandom_data(100_000);
+    let leaves = leaf_hashes(&data, 1024, &cdc_options, thread_count);
+    // Leaves are the chunks, with the hash of each chunk as a block
+    let chunk_ends = chunk_boundaries(data.as_slice(), 1024).unwrap();
+    assert_eq!(leaves.len(), chunk_ends.len());
+    let mut chunk_start = 0;
+    for (leaf, chunk_end) in leaves.iter().zip(&chunk_ends) {
+        assert_eq!(leaf.byte_range(), BlockRange::new(chunk_start, chunk_end - 1, true));
+        let chunk = &data[chunk_start as usize..*chunk_end as usize];
+        assert_eq!(leaf.hash_result(),
+            Sha256::digest([b"\x00", chunk].concat()).as_slice());
+        chunk_start = *chunk_end;
+    }
+    // The tree has the shape of one byte blocks over the chunks
+    let (tx, rx) = unbounded_channel();
+    let tree_hash = merkle_hash_file_with_options::<_, Sha256, _>(Cursor::new(&data), 1024, 4,
+        &cdc_options, tx, thread_count).unwrap();
+    let block_iter = merkle_block_generator(chunk_ends.len() as u64, 1, 4).into_iter();
+    let nodes: Vec<HashRange> = reorder_hashrange_iter(block_iter, rx.try_iter())
+        .into_iter().collect();
+    assert_eq!(nodes.last().unwrap().hash_result(), tree_hash.as_ref());
+    assert_eq!(nodes.last().unwrap().byte_range(), BlockRange::new(0, 99_999, true));
+}
+#[test]
+fn test_content_defined_tree() {
+    test_content_defined_tree_helper(0);
+}
+#[test]
+fn test_content_defined_tree_threaded() {
+    test_content_defined_tree_helper(3);
+}
+#[test]
+fn test_content_defined_insertion_locality() {
+    let cdc_options = TreeOptions {
+        content_defined: true,
+        ..TreeOptions::default()
+    };
+    let data = pseudorandom_data(100_000);
+    let mut inserted = data.clone();
+    inserted.insert(2000, 0x5a);
+    let shared_count = |options: &TreeOptions| {
+        let before: Vec<Vec<u8>> = leaf_hashes(&data, 1024, options, 0).iter()
+            .map(|leaf| leaf.hash_result().to_vec()).collect();
+        let after = leaf_hashes(&inserted, 1024, options, 0);
+        let shared = after.iter()
+            .filter(|leaf| before.iter().any(|hash| hash == leaf.hash_result()))
+            .count();
+        (shared, after.len())
+    };
+    // Only the chunks around the insertion change
+    let (cdc_shared, cdc_total) = shared_count(&cdc_options);
+    assert!(cdc_total - cdc_shared <= 3, "{} of {} leaves changed",
+        cdc_total - cdc_shared, cdc_total);
+    // Every fixed block after the insertion changes
+    let (fixed_shared, _) = shared_count(&TreeOptions::default());
+    assert!(fixed_shared <= 2);
+    assert!(cdc_shared > 50);
+}
+#[test]
+fn test_content_defined_empty_file() {
+    let cdc_options = TreeOptions {
+        content_defined: true,
+        ..TreeOptions::default()
+    };
+    let empty_hash = merkle_hash_file_with_options::<_, Sha256, _>(Cursor::new(&[]), 1024, 4,
+        &cdc_options, ThrowawayConsumer::default(), 0).unwrap();
+    assert_eq!(empty_hash.as_ref(), Sha256::digest(b"\x00").as_slice());
+}
+
+fn test_nominal_ranges_helper(thread_count: usize) {
+    // The last block only has two of its four bytes
+    let data = b"abcd1234efghij";
+    let byte_ranges = |options: &TreeOptions| {
+        let (tx, rx) = unbounded_channel();
+        let tree_hash = merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(data), 4, 2, options, tx, thread_count).unwrap();
+        let block_iter = merkle_block_generator(14, 4, 2).into_iter();
+        let byte_ranges: Vec<BlockRange> = reorder_hashrange_iter(block_iter, rx.into_iter())
+            .into_iter()
+            .map(|hash_range| hash_range.byte_range())
+            .collect();
+        (tree_hash, byte_ranges)
+    };
+    let (actual_hash, actual_ranges) = byte_ranges(&TreeOptions::default());
+    let (nominal_hash, nominal_ranges) = byte_ranges(&TreeOptions {
+        nominal_ranges: true,
+        ..TreeOptions::default()
+    });
+    assert_eq!(actual_hash, nominal_hash);
+    let ranges = |ends: &[(u64, u64)]| ends.iter()
+        .map(|(start, end)| BlockRange::new(*start, *end, true))
+        .collect::<Vec<_>>();
+    assert_eq!(actual_ranges, ranges(&[(0x00, 0x03), (0x04, 0x07), (0x00, 0x07),
+        (0x08, 0x0b), (0x0c, 0x0d), (0x08, 0x0d), (0x00, 0x0d)]));
+    // Only nodes covering the last block change
+    assert_eq!(nominal_ranges, ranges(&[(0x00, 0x03), (0x04, 0x07), (0x00, 0x07),
+        (0x08, 0x0b), (0x0c, 0x0f), (0x08, 0x0f), (0x00, 0x0f)]));
+}
+#[test]
+fn test_nominal_ranges() {
+    test_nominal_ranges_helper(0);
+}
+#[test]
+fn test_nominal_ranges_threaded() {
+    test_nominal_ranges_helper(3);
+}
+
+fn test_strided_tree_helper(thread_count: usize) {
+    // Four byte leaves every two bytes
+    let data = b"abcdefghij";
+    let options = TreeOptions {stride: Some(2), ..TreeOptions::default()};
+    let (tx, rx) = unbounded_channel();
+    let tree_hash = merkle_hash_file_with_options::<_, Sha256, _>
+        (Cursor::new(data), 4, 2, &options, tx, thread_count).unwrap();
+    let block_iter = strided_block_generator(10, 4, 2, 2).into_iter();
+    let nodes: Vec<HashRange> = reorder_hashrange_iter(block_iter, rx.into_iter())
+        .into_iter().collect();
+    let byte_ranges: Vec<_> = nodes.iter()
+        .map(|hash_range| (hash_range.byte_range().start(), hash_range.byte_range().end()))
+        .collect();
+    // The leaves overlap by two bytes
+    assert_eq!(byte_ranges, [(0x0, 0x3), (0x2, 0x5), (0x0, 0x5),
+        (0x4, 0x7), (0x6, 0x9), (0x4, 0x9), (0x0, 0x9)]);
+    for hash_range in &nodes {
+        assert_eq!(hash_range.byte_range(),
+            strided_byte_range(hash_range.block_range(), 4, 2, 10));
+    }
+    let leaf_hash = |leaf: &[u8]| Sha256::digest([b"\x00", leaf].concat());
+    let node_hash = |left: &[u8], right: &[u8]| Sha256::digest([b"\x01", left, right].concat());
+    let left_hash = node_hash(&leaf_hash(b"abcd"), &leaf_hash(b"cdef"));
+    let right_hash = node_hash(&leaf_hash(b"efgh"), &leaf_hash(b"ghij"));
+    assert_eq!(nodes[1].hash_result(), leaf_hash(b"cdef").as_slice());
+    assert_eq!(tree_hash.as_ref(), node_hash(&left_hash, &right_hash).as_slice());
+}
+#[test]
+fn test_strided_tree() {
+    test_strided_tree_helper(0);
+}
+#[test]
+fn test_strided_tree_threaded() {
+    test_strided_tree_helper(3);
+}
+#[test]
+fn test_stride_of_block_size() {
+    let data = pseudorandom_data(1000);
+    let options = TreeOptions {stride: Some(64), ..TreeOptions::default()};
+    for thread_count in [0, 3] {
+        let strided_hash = merkle_hash_file_with_options::<_, Sha256, _>(Cursor::new(&data),
+            64, 4, &options, ThrowawayConsumer::default(), thread_count).unwrap();
+        let block_hash = merkle_hash_file::<_, Sha256, _>(Cursor::new(&data),
+            64, 4, ThrowawayConsumer::default(), thread_count).unwrap();
+        assert_eq!(strided_hash, block_hash);
+    }
+}
+
+#[test]
+fn test_verify_positions() {
+    let data = b"abcdefghijklmn";
+    let options = TreeOptions {verify_positions: true, ..TreeOptions::default()};
+    for thread_count in [0, 3] {
+        let checked_hash = merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(data), 4, 2, &options, ThrowawayConsumer::default(), thread_count)
+            .unwrap();
+        let unchecked_hash = merkle_hash_file::<_, Sha256, _>
+            (Cursor::new(data), 4, 2, ThrowawayConsumer::default(), thread_count)
+            .unwrap();
+        assert_eq!(checked_hash, unchecked_hash);
+    }
+}
+
+#[test]
+fn test_stack_size() {
+    // One byte blocks in a binary tree give one layer of recursion per doubling of the length
+    let data: Vec<u8> = (0..1u32 << 14).map(|i| (i % 251) as u8).collect();
+    let options = TreeOptions {stack_size: Some(16 << 20), ..TreeOptions::default()};
+    let default_hash = merkle_hash_file::<_, Sha256, _>
+        (Cursor::new(&data), 1, 2, ThrowawayConsumer::default(), 0).unwrap();
+    let large_stack_hash = merkle_hash_file_with_options::<_, Sha256, _>
+        (Cursor::new(&data), 1, 2, &options, ThrowawayConsumer::default(), 3).unwrap();
+    assert_eq!(large_stack_hash, default_hash);
+    #[cfg(feature = "rayon")]
+    {
+        let rayon_hash = merkle_hash_file_with_backend::<_, Sha256, _>
+            (Cursor::new(&data), 1, 2, &options, ThrowawayConsumer::default(), 3,
+            ParallelBackend::Rayon).unwrap();
+        assert_eq!(rayon_hash, default_hash);
+    }
+}
+
+// The first read from the start of each 4 byte block would block,
+// as a nonblocking network filesystem can
+#[derive(Debug)]
+struct BlockingReader {
+    inner: Cursor<Vec<u8>>,
+    blocked_positions: HashSet<u64>
+}
+impl BlockingReader {
+    fn new(data: &[u8]) -> Self {
+        BlockingReader {inner: Cursor::new(data.to_vec()), blocked_positions: HashSet::new()}
+    }
+}
+impl Read for BlockingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let position = self.inner.position();
+        match position % 4 == 0 && self.blocked_positions.insert(position) {
+            true => Err(std::io::ErrorKind::WouldBlock.into()),
+            false => self.inner.read(buf)
+        }
+    }
+}
+impl Seek for BlockingReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn test_read_retries() {
+    let data = b"abcdefghijklmn";
+    let options = TreeOptions {read_retries: 1, ..TreeOptions::default()};
+    for thread_count in [0, 3] {
+        let reader = BlockingReader::new(data);
+        let retried_hash = merkle_hash_file_with_options::<_, Sha256, _>
+            (reader, 4, 2, &options, ThrowawayConsumer::default(), thread_count)
+            .unwrap();
+        let expected_hash = merkle_hash_file::<_, Sha256, _>
+            (Cursor::new(data), 4, 2, ThrowawayConsumer::default(), thread_count)
+            .unwrap();
+        assert_eq!(retried_hash, expected_hash);
+        // Reads are not retried by default
+        let reader = BlockingReader::new(data);
+        assert!(merkle_hash_file_with_options::<_, Sha256, _>
+            (reader, 4, 2, &TreeOptions::default(), ThrowawayConsumer::default(),
+            thread_count).is_err());
+    }
+}
+
+#[test]
+fn test_stats_consumer() {
+    // 60 zero blocks, then 4 blocks that are each different
+    let mut data = vec![0x00; 60*4];
+    data.extend(1..=16u8);
+    let zero_leaf_hash = Sha256::digest([0x00; 5]);
+    for thread_count in [0, 3] {
+        let stats_consumer = StatsConsumer::new();
+        merkle_hash_file::<_, Sha256, _>(Cursor::new(&data), 4, 2,
+            stats_consumer.clone(), thread_count).unwrap();
+        let stats = stats_consumer.finish();
+        assert_eq!(stats.leaf_count, 64);
+        assert_eq!(stats.internal_count, 63);
+        assert_eq!(stats.distinct_leaf_count, 5);
+        let (most_repeated_hash, most_repeated_count) = stats.most_repeated_leaf.unwrap();
+        assert_eq!(*most_repeated_hash, *zero_leaf_hash);
+        assert_eq!(most_repeated_count, 60);
+    }
+    assert_eq!(StatsConsumer::new().finish().most_repeated_leaf, None);
+}
+
+// Reports its position one byte further along than it is, like a reader with a seek bug
+#[derive(Debug)]
+struct MisreportingReader(Cursor<Vec<u8>>);
+impl Read for MisreportingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+impl Seek for MisreportingReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.0.position() + 1),
+            pos => self.0.seek(pos)
+        }
+    }
+}
+
+#[test]
+fn test_verify_positions_mismatch() {
+    let options = TreeOptions {verify_positions: true, ..TreeOptions::default()};
+    for thread_count in [0, 3] {
+        let reader = MisreportingReader(Cursor::new(b"abcdefghijklmn".to_vec()));
+        let err = merkle_hash_file_with_options::<_, Sha256, _>
+            (reader, 4, 2, &options, ThrowawayConsumer::default(), thread_count)
+            .unwrap_err();
+        assert!(err.position_mismatch().is_some());
+    }
+}
+
+#[test]
+fn test_empty_leaf_range() {
+    let options = TreeOptions {empty_leaf_range: true, ..TreeOptions::default()};
+    for thread_count in [0, 3] {
+        let (tx, rx) = unbounded_channel::<HashRange>();
+        merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(b""), 4, 2, &TreeOptions::default(), tx, thread_count).unwrap();
+        // The leaf of an empty file covers [0x00-0x00] by default, as in hash files
+        let default_entry = rx.into_iter().next().unwrap();
+        assert_eq!(default_entry.byte_range(), BlockRange::new(0, 0, true));
+
+        let (tx, rx) = unbounded_channel::<HashRange>();
+        let tree_hash = merkle_hash_file_with_options::<_, Sha256, _>
+            (Cursor::new(b""), 4, 2, &options, tx, thread_count).unwrap();
+        let entries: Vec<HashRange> = rx.into_iter().collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].block_range(), BlockRange::new(0, 0, true));
+        assert_eq!(entries[0].byte_range(), BlockRange::empty(0));
+        assert_eq!(entries[0].byte_range().range(), 0);
+        assert_eq!(entries[0].hash_result(), tree_hash.as_ref());
+        assert_eq!(entries[0].hash_result(), default_entry.hash_result());
+    }
+    // Leaves with data are unaffected
+    let (tx, rx) = unbounded_channel::<HashRange>();
+    merkle_hash_file_with_options::<_, Sha256, _>
+        (Cursor::new(b"ab"), 4, 2, &options, tx, 0).unwrap();
+    assert_eq!(rx.into_iter().next().unwrap().byte_range(), BlockRange::new(0, 1, true));
+}
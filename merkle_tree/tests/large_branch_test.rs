@@ -0,0 +1,108 @@
+use merkle_tree::{merkle_hash_file_with_options, internal_node_input};
+use merkle_tree::{Consumer, NodeEncoding, TreeOptions};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Cursor;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use digest::Digest;
+use sha2::Sha256;
+
+// Records the largest single allocation made while tracking is enabled
+// Tracking is per thread, so tests running in parallel do not interfere
+struct MaxAllocTracker;
+
+thread_local! {
+    static TRACKING: Cell<bool> = const { Cell::new(false) };
+}
+static MAX_ALLOC: AtomicUsize = AtomicUsize::new(0);
+
+fn is_tracking() -> bool {
+    TRACKING.try_with(|tracking| tracking.get()).unwrap_or(false)
+}
+
+unsafe impl GlobalAlloc for MaxAllocTracker {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if is_tracking() {
+            MAX_ALLOC.fetch_max(layout.size(), Ordering::SeqCst);
+        }
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if is_tracking() {
+            MAX_ALLOC.fetch_max(new_size, Ordering::SeqCst);
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: MaxAllocTracker = MaxAllocTracker;
+
+#[derive(Default, Debug, Copy, Clone)]
+pub struct ThrowawayConsumer {}
+
+impl<T> Consumer<T> for ThrowawayConsumer {
+    fn accept(&self, _val: T) -> Result<(), T> {
+        // Throw away the value
+        Ok(())
+    }
+}
+
+// Root of a two-level tree, computed without the library's tree recursion
+fn reference_root(data: &[u8], block_size: usize, branch: usize,
+        node_encoding: NodeEncoding) -> Vec<u8> {
+    let leaf_hashes: Vec<(u64, Vec<u8>)> = data.chunks(block_size)
+        .map(|block| (block.len() as u64,
+            Sha256::digest([b"\x00", block].concat()).to_vec()))
+        .collect();
+    let level1_hashes: Vec<(u64, Vec<u8>)> = leaf_hashes.chunks(branch)
+        .map(|children| {
+            let children_len = children.iter().map(|(len, _)| len).sum();
+            let hash_input = internal_node_input(node_encoding,
+                children.iter().map(|(len, hash)| (*len, hash.as_slice())));
+            (children_len, Sha256::digest(hash_input).to_vec())
+        })
+        .collect();
+    let root_input = internal_node_input(node_encoding,
+        level1_hashes.iter().map(|(len, hash)| (*len, hash.as_slice())));
+    Sha256::digest(root_input).to_vec()
+}
+
+#[test]
+fn test_large_branch() {
+    // 300 blocks fill one child of the root and part of another
+    let data: Vec<u8> = (0..1200u32).map(|i| (i % 251) as u8).collect();
+    let branch = 256;
+    for node_encoding in [NodeEncoding::Plain, NodeEncoding::ChildCount].iter() {
+        let ref_root = reference_root(&data, 4, branch.into(), *node_encoding);
+        let options = TreeOptions {node_encoding: *node_encoding, ..TreeOptions::default()};
+        for thread_count in [0, 3].iter() {
+            let tree_hash = merkle_hash_file_with_options::<_, Sha256, _>
+                (Cursor::new(&data), 4, branch, &options,
+                ThrowawayConsumer::default(), *thread_count).unwrap();
+            assert_eq!(ref_root.as_slice(), tree_hash.as_ref(),
+                "{} encoding, thread count {}", node_encoding, thread_count);
+        }
+    }
+}
+
+#[test]
+fn test_large_branch_bounded_allocation() {
+    let data: Vec<u8> = (0..1200u32).map(|i| (i % 251) as u8).collect();
+    let branch = 256;
+    let options = TreeOptions {pad_tree: true, ..TreeOptions::default()};
+    // Without a pool, every node is hashed on this thread
+    TRACKING.with(|tracking| tracking.set(true));
+    let tree_hash = merkle_hash_file_with_options::<_, Sha256, _>
+        (Cursor::new(&data), 4, branch, &options,
+        ThrowawayConsumer::default(), 0);
+    TRACKING.with(|tracking| tracking.set(false));
+    assert!(tree_hash.is_ok());
+    // Buffering every child hash of a node would need at least this much
+    let all_children_len = usize::from(branch) * Sha256::output_size();
+    assert!(MAX_ALLOC.load(Ordering::SeqCst) < all_children_len);
+}
@@ -0,0 +1,104 @@
+use merkle_tree::{merkle_hash_file_with_options, merkle_hash_path, TreeOptions};
+use merkle_tree::ParallelBackend;
+use merkle_tree::{merkle_block_generator, reorder_hashrange_iter, HashRange};
+
+use std::fs::File;
+use std::path::PathBuf;
+use sha2::Sha256;
+
+use crossbeam_channel::unbounded as unbounded_channel;
+
+// Data file removed when the test finishes
+struct TempData(PathBuf);
+impl TempData {
+    fn new(name: &str, data: &[u8]) -> Self {
+        let path = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        Self(path)
+    }
+}
+impl Drop for TempData {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// Nodes in tree order, along with the root
+fn hash_both_ways(data_file: &TempData, data_len: u64, block_size: u32, branch: u16,
+        options: &TreeOptions, thread_count: usize) -> [(Vec<HashRange>, Vec<u8>); 2] {
+    let in_order = |rx: crossbeam_channel::Receiver<HashRange>| -> Vec<HashRange> {
+        let block_iter = merkle_block_generator(data_len, block_size, branch).into_iter();
+        reorder_hashrange_iter(block_iter, rx.try_iter()).into_iter().collect()
+    };
+    let (tx, rx) = unbounded_channel();
+    let reader_root = merkle_hash_file_with_options::<_, Sha256, _>(
+        File::open(&data_file.0).unwrap(), block_size, branch, options, tx, thread_count)
+        .unwrap();
+    let reader_nodes = in_order(rx);
+    let (tx, rx) = unbounded_channel();
+    let path_root = merkle_hash_path::<Sha256, _>(&data_file.0,
+        block_size, branch, options, tx, thread_count, ParallelBackend::default()).unwrap();
+    let path_nodes = in_order(rx);
+    [(reader_nodes, reader_root.as_ref().to_vec()), (path_nodes, path_root.as_ref().to_vec())]
+}
+
+#[test]
+fn path_matches_reader() {
+    let data: Vec<u8> = (0..1000u32).map(|i| (i*7 % 251) as u8).collect();
+    let data_file = TempData::new("path_matches_reader", &data);
+    for thread_count in [0, 1, 4] {
+        for (block_size, branch) in [(4, 2), (16, 4), (1, 3)] {
+            let [reader_result, path_result] = hash_both_ways(&data_file,
+                data.len() as u64, block_size, branch, &TreeOptions::default(), thread_count);
+            assert_eq!(reader_result, path_result,
+                "{} threads, block size {}, branch {}", thread_count, block_size, branch);
+        }
+    }
+}
+
+#[test]
+fn path_matches_reader_with_options() {
+    let data: Vec<u8> = (0..300u32).map(|i| (i % 13) as u8).collect();
+    let data_file = TempData::new("path_matches_reader_with_options", &data);
+    let option_list = [
+        TreeOptions {salt: b"salt".to_vec(), pad_tree: true, ..TreeOptions::default()},
+        TreeOptions {duplicate_last: true, ..TreeOptions::default()},
+        TreeOptions {promote_lone_children: true, ..TreeOptions::default()}
+    ];
+    for options in &option_list {
+        let [reader_result, path_result] = hash_both_ways(&data_file,
+            data.len() as u64, 8, 3, options, 4);
+        assert_eq!(reader_result, path_result, "{:?}", options);
+    }
+    // Branch factors this large hash children in a sliding window
+    let [reader_result, path_result] = hash_both_ways(&data_file,
+        data.len() as u64, 1, 200, &TreeOptions::default(), 4);
+    assert_eq!(reader_result, path_result);
+}
+
+#[test]
+fn path_of_empty_file() {
+    let data_file = TempData::new("path_of_empty_file", b"");
+    let [reader_result, path_result] = hash_both_ways(&data_file, 0, 4, 2,
+        &TreeOptions::default(), 2);
+    assert_eq!(reader_result, path_result);
+    let (tx, _rx) = unbounded_channel();
+    assert!(merkle_hash_path::<Sha256, _>(&data_file.0.with_extension("missing"),
+        4, 2, &TreeOptions::default(), tx, 2, ParallelBackend::default()).is_none());
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn path_rayon_matches_default() {
+    let data: Vec<u8> = (0..1000u32).map(|i| (i*7 % 251) as u8).collect();
+    let data_file = TempData::new("path_rayon_matches_default", &data);
+    let (tx_default, _rx_default) = unbounded_channel();
+    let default_root = merkle_hash_path::<Sha256, _>(&data_file.0, 16, 4,
+        &TreeOptions::default(), tx_default, 4,
+        ParallelBackend::EagerThreadPool).unwrap();
+    let (tx_rayon, _rx_rayon) = unbounded_channel();
+    let rayon_root = merkle_hash_path::<Sha256, _>(&data_file.0, 16, 4,
+        &TreeOptions::default(), tx_rayon, 4,
+        ParallelBackend::Rayon).unwrap();
+    assert_eq!(default_root, rayon_root);
+}
@@ -0,0 +1,61 @@
+use merkle_tree::{BlockRange, hash_file_to_vec, prove_block};
+
+use sha2::Sha256;
+
+use std::io::Cursor;
+
+#[test]
+fn proof_matches_full_tree() {
+    let data: Vec<u8> = (0..=255).collect();
+    for branch in 2..=5 {
+        for data_len in [0, 1, 4, 5, 17, 33, 64, 256] {
+            let data_slice = &data[..data_len];
+            let nodes = hash_file_to_vec::<_, Sha256>(Cursor::new(data_slice), 4, branch, 0)
+                .unwrap();
+            let root_hash = nodes.last().unwrap().hash_result();
+            let block_count = std::cmp::max(data_len.div_ceil(4), 1) as u64;
+            for target_block in 0..block_count {
+                let proof = prove_block::<_, Sha256>(Cursor::new(data_slice),
+                    4, branch, target_block).unwrap();
+                let leaf_node = nodes.iter()
+                    .find(|node| node.block_range() == BlockRange::new(target_block, target_block, true))
+                    .unwrap();
+                assert_eq!(proof.leaf_hash.as_ref(), leaf_node.hash_result());
+                assert_eq!(proof.root_hash.as_ref(), root_hash,
+                    "branch {} length {} block {}", branch, data_len, target_block);
+                assert_eq!(proof.root_from_leaf::<Sha256>(&proof.leaf_hash), proof.root_hash);
+            }
+        }
+    }
+}
+
+#[test]
+fn proof_siblings_in_order() {
+    // 5 blocks with branch factor 3: [0 1 2] [3 4]
+    let data = b"aaaabbbbccccddddeeee";
+    let nodes = hash_file_to_vec::<_, Sha256>(Cursor::new(data), 4, 3, 0).unwrap();
+    let node_hash = |range: BlockRange| nodes.iter()
+        .find(|node| node.block_range() == range)
+        .unwrap()
+        .hash_result()
+        .to_vec();
+
+    let proof = prove_block::<_, Sha256>(Cursor::new(data), 4, 3, 1).unwrap();
+    assert_eq!(proof.levels.len(), 2);
+    let leaf_level: Vec<_> = proof.levels[0].iter()
+        .map(|sibling| (sibling.hash.to_vec(), sibling.is_left))
+        .collect();
+    assert_eq!(leaf_level, vec![
+        (node_hash(BlockRange::new(0, 0, true)), true),
+        (node_hash(BlockRange::new(2, 2, true)), false)
+    ]);
+    let top_level: Vec<_> = proof.levels[1].iter()
+        .map(|sibling| (sibling.hash.to_vec(), sibling.is_left))
+        .collect();
+    assert_eq!(top_level, vec![(node_hash(BlockRange::new(3, 6, false)), false)]);
+
+    // A changed block no longer leads to the root
+    let wrong_proof = prove_block::<_, Sha256>(Cursor::new(b"aaaaXXXXccccddddeeee"), 4, 3, 1)
+        .unwrap();
+    assert_ne!(proof.root_from_leaf::<Sha256>(&wrong_proof.leaf_hash), proof.root_hash);
+}
@@ -0,0 +1,42 @@
+#![no_main]
+// The main crate has no library target, so the parser modules are compiled in directly
+// Only parse_hash_file is used from them
+#![allow(dead_code, unused_imports)]
+
+#[path = "../../src/crc32_utils.rs"]
+mod crc32_utils;
+#[path = "../../src/sha512t_utils.rs"]
+mod sha512t_utils;
+#[path = "../../src/k12_utils.rs"]
+mod k12_utils;
+#[path = "../../src/ascon_utils.rs"]
+mod ascon_utils;
+#[path = "../../src/hash_enum.rs"]
+mod hash_enum;
+#[path = "../../src/utils.rs"]
+mod utils;
+#[path = "../../src/error_types.rs"]
+mod error_types;
+#[path = "../../src/format_functions.rs"]
+mod format_functions;
+#[path = "../../src/parse_functions.rs"]
+mod parse_functions;
+#[path = "../../src/file_checksum.rs"]
+mod file_checksum;
+#[path = "../../src/tree_structure.rs"]
+mod tree_structure;
+#[path = "../../src/jsonl.rs"]
+mod jsonl;
+#[path = "../../src/format_version.rs"]
+mod format_version;
+#[path = "../../src/parity.rs"]
+mod parity;
+#[path = "../../src/hash_file_parser.rs"]
+mod hash_file_parser;
+
+use libfuzzer_sys::fuzz_target;
+
+// Any input must give either a parsed file or an error, and never a panic
+fuzz_target!(|data: &[u8]| {
+    let _ = hash_file_parser::parse_hash_file(data);
+});
@@ -0,0 +1,164 @@
+// Ascon-Hash256 from NIST SP 800-232, with its 256-bit output
+// The state is five 64-bit words, and bytes are loaded little-endian
+#![forbid(unsafe_code)]
+
+use digest::{FixedOutput, OutputSizeUser, Update, Reset, HashMarker};
+use generic_array::GenericArray;
+use generic_array::typenum::U32;
+
+const RATE: usize = 8;
+const ROUND_COUNT: usize = 12;
+
+// State after the permutation of the Ascon-Hash256 IV 0x0000080100cc0002
+const INITIAL_STATE: [u64; 5] = [0x9b1e5494e934d681, 0x4bc3a01e333751d2,
+    0xae65396c6b34b81a, 0x3c7fd4a4d56a4db3, 0x1a5c464906c5976d];
+
+// Ascon-p[12], the permutation with all 12 rounds
+fn permute(state: &mut [u64; 5]) {
+    let [mut x0, mut x1, mut x2, mut x3, mut x4] = *state;
+    for round in 0..ROUND_COUNT as u64 {
+        // Round constants are 0xf0, 0xe1, 0xd2, and so on
+        x2 ^= ((0x0f - round) << 4) | round;
+        // Substitution layer, with the 5-bit S-box applied to each bit slice
+        x0 ^= x4;
+        x4 ^= x3;
+        x2 ^= x1;
+        let t0 = !x0 & x1;
+        let t1 = !x1 & x2;
+        let t2 = !x2 & x3;
+        let t3 = !x3 & x4;
+        let t4 = !x4 & x0;
+        x0 ^= t1;
+        x1 ^= t2;
+        x2 ^= t3;
+        x3 ^= t4;
+        x4 ^= t0;
+        x1 ^= x0;
+        x0 ^= x4;
+        x3 ^= x2;
+        x2 = !x2;
+        // Linear diffusion layer
+        x0 ^= x0.rotate_right(19) ^ x0.rotate_right(28);
+        x1 ^= x1.rotate_right(61) ^ x1.rotate_right(39);
+        x2 ^= x2.rotate_right(1) ^ x2.rotate_right(6);
+        x3 ^= x3.rotate_right(10) ^ x3.rotate_right(17);
+        x4 ^= x4.rotate_right(7) ^ x4.rotate_right(41);
+    }
+    *state = [x0, x1, x2, x3, x4];
+}
+
+// Input is absorbed a block at a time, with a partial block held back
+// until either it fills up or the hash is finalized
+#[derive(Clone)]
+pub struct AsconHash256 {
+    state: [u64; 5],
+    block: [u8; RATE],
+    block_len: usize
+}
+impl Default for AsconHash256 {
+    fn default() -> Self {
+        AsconHash256 {
+            state: INITIAL_STATE,
+            block: [0x00; RATE],
+            block_len: 0
+        }
+    }
+}
+impl AsconHash256 {
+    fn absorb(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let fill_len = (RATE - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len+fill_len]
+                .copy_from_slice(&data[..fill_len]);
+            self.block_len += fill_len;
+            data = &data[fill_len..];
+            if self.block_len == RATE {
+                self.state[0] ^= u64::from_le_bytes(self.block);
+                permute(&mut self.state);
+                self.block_len = 0;
+            }
+        }
+    }
+}
+
+impl OutputSizeUser for AsconHash256 {
+    type OutputSize = U32;
+}
+impl HashMarker for AsconHash256 {}
+
+impl FixedOutput for AsconHash256 {
+    fn finalize_into(mut self, out: &mut GenericArray<u8, Self::OutputSize>) {
+        // The last block is padded with a single 0x01 byte and then zeros
+        let mut last_block = [0x00; RATE];
+        last_block[..self.block_len].copy_from_slice(&self.block[..self.block_len]);
+        last_block[self.block_len] = 0x01;
+        self.state[0] ^= u64::from_le_bytes(last_block);
+        permute(&mut self.state);
+        let mut out_chunks = out.chunks_exact_mut(RATE).peekable();
+        while let Some(out_chunk) = out_chunks.next() {
+            out_chunk.copy_from_slice(&self.state[0].to_le_bytes());
+            if out_chunks.peek().is_some() {
+                permute(&mut self.state);
+            }
+        }
+    }
+}
+
+impl Update for AsconHash256 {
+    #[inline]
+    fn update(&mut self, data: &[u8]) {
+        self.absorb(data);
+    }
+}
+
+impl Reset for AsconHash256 {
+    #[inline]
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use digest::Digest;
+
+    #[test]
+    fn test_initial_state() {
+        let mut state = [0x0000080100cc0002, 0, 0, 0, 0];
+        permute(&mut state);
+        assert_eq!(state, INITIAL_STATE);
+    }
+    #[test]
+    fn test_kat_vectors() {
+        // Messages of bytes counting up from 0x00, from the Ascon-Hash256 KATs
+        let vectors = [
+            (0, "0b3be5850f2f6b98caf29f8fdea89b64a1fa70aa249b8f839bd53baa304d92b2"),
+            (1, "0728621035af3ed2bca03bf6fde900f9456f5330e4b5ee23e7f6a1e70291bc80"),
+            // Around one and two blocks
+            (7, "3e4d273ba69b3b9c53216107e88b75cdbeedbcbf8faf0219c3928ab62b116577"),
+            (8, "b88e497ae8e6fb641b87ef622eb8f2fca0ed95383f7ffebe167acf1099ba764f"),
+            (9, "94269c30e0296e1ec86655041841823efa1927f520fd58c8e9bce6197878c1a6"),
+            (16, "3158c1940a2fbadbd68ab661777859b94a689e4efc375911467addd641835c38"),
+            (32, "bd9d3d60a66b53868eab2a5c74539a518a1f60f01eb176c60e43dee81680b33e"),
+            // Many blocks, ending in a partial block
+            (100, "a4bc453c84f824f10092e8e9031799957e984a29bbae5e84345e82f48dd71192")
+        ];
+        for (len, expected_hex) in vectors {
+            let input: Vec<u8> = (0..len).collect();
+            assert_eq!(hex::encode(AsconHash256::digest(input)), expected_hex, "length {}", len);
+        }
+    }
+    #[test]
+    fn test_split_updates() {
+        // Split around the block boundaries to exercise buffering across updates
+        let input: Vec<u8> = (0..3*RATE as u8 + 5).collect();
+        let expected = AsconHash256::digest(&input);
+        for split in [1, RATE - 1, RATE, RATE + 1, 2*RATE, 3*RATE] {
+            let mut hasher = AsconHash256::new();
+            Digest::update(&mut hasher, &input[..split]);
+            Digest::update(&mut hasher, &input[split..]);
+            assert_eq!(hasher.finalize(), expected, "split at {}", split);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+// BLAKE3 that hashes large updates, such as whole large leaf blocks,
+// with the multithreaded update_rayon of the blake3 crate
+#![forbid(unsafe_code)]
+
+use digest::{FixedOutput, OutputSizeUser, Update, Reset, HashMarker};
+use generic_array::GenericArray;
+use generic_array::typenum::U32;
+
+// Below this, splitting the input across threads costs more than it saves
+// The blake3 docs suggest 128 KiB as a rough break-even point
+const RAYON_MIN_LEN: usize = 128*1024;
+
+#[derive(Clone, Default)]
+pub struct Blake3Rayon(blake3::Hasher);
+
+impl OutputSizeUser for Blake3Rayon {
+    type OutputSize = U32;
+}
+impl HashMarker for Blake3Rayon {}
+
+impl FixedOutput for Blake3Rayon {
+    #[inline]
+    fn finalize_into(self, out: &mut GenericArray<u8, Self::OutputSize>) {
+        out.copy_from_slice(self.0.finalize().as_bytes());
+    }
+}
+
+impl Update for Blake3Rayon {
+    #[inline]
+    fn update(&mut self, data: &[u8]) {
+        if data.len() >= RAYON_MIN_LEN {
+            self.0.update_rayon(data);
+        } else {
+            self.0.update(data);
+        }
+    }
+}
+
+impl Reset for Blake3Rayon {
+    #[inline]
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use digest::Digest;
+
+    fn ptn(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_matches_blake3() {
+        for len in [0, 1, 1024, RAYON_MIN_LEN - 1, RAYON_MIN_LEN, 4*RAYON_MIN_LEN + 7] {
+            let input = ptn(len);
+            assert_eq!(Blake3Rayon::digest(&input), blake3::Hasher::digest(&input),
+                "length {}", len);
+        }
+    }
+    #[test]
+    fn test_split_updates() {
+        // Mix of updates on either side of the threshold
+        let input = ptn(3*RAYON_MIN_LEN + 5);
+        let expected = blake3::Hasher::digest(&input);
+        for split in [1, RAYON_MIN_LEN - 1, RAYON_MIN_LEN, 2*RAYON_MIN_LEN + 3] {
+            let mut hasher = Blake3Rayon::new();
+            Digest::update(&mut hasher, &input[..split]);
+            Digest::update(&mut hasher, &input[split..]);
+            assert_eq!(hasher.finalize(), expected, "split at {}", split);
+        }
+    }
+}
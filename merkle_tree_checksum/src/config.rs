@@ -0,0 +1,156 @@
+#![forbid(unsafe_code)]
+// Default argument values read from a --config TOML file
+
+use crate::error_types::ConfigErr;
+use crate::hash_enum::HashFunctions;
+use crate::parse_functions::size_str_to_num;
+
+use merkle_tree::{branch_t, NodeEncoding};
+
+use clap::Command;
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy)]
+enum ConfigValueKind {
+    HashFunction,
+    BlockSize,
+    BranchFactor,
+    NodeEncoding,
+    Jobs,
+    Flag
+}
+
+// Config keys are named after the long flags they set
+// (key, argument id, whether the argument is global, kind of value)
+const CONFIG_KEYS: [(&str, &str, bool, ConfigValueKind); 8] = [
+    ("jobs", "jobs", true, ConfigValueKind::Jobs),
+    ("hash-function", "hash", false, ConfigValueKind::HashFunction),
+    ("block-length", "blocksize", false, ConfigValueKind::BlockSize),
+    ("branch-factor", "branch", false, ConfigValueKind::BranchFactor),
+    ("node-encoding", "nodeencoding", false, ConfigValueKind::NodeEncoding),
+    ("short", "short", false, ConfigValueKind::Flag),
+    ("overwrite", "overwrite", false, ConfigValueKind::Flag),
+    ("self-checksum", "selfchecksum", false, ConfigValueKind::Flag)
+];
+
+// Path given to --config, found before clap parses the arguments
+// so that the config can supply default values to clap
+pub(crate) fn config_path_arg<I: IntoIterator<Item=OsString>>(args: I) -> Option<PathBuf> {
+    let mut arg_iter = args.into_iter();
+    while let Some(arg) = arg_iter.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--config" {
+            return arg_iter.next().map(PathBuf::from);
+        } else if let Some(path) = arg.to_str()
+                .and_then(|arg_str| arg_str.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct ConfigDefaults {
+    // (argument id, value) pairs
+    global_args: Vec<(&'static str, String)>,
+    generate_hash_args: Vec<(&'static str, String)>
+}
+impl ConfigDefaults {
+    pub fn parse(contents: &str) -> Result<Self, ConfigErr> {
+        let table = toml::Table::from_str(contents)
+            .map_err(|err| ConfigErr::MalformedToml(err.message().to_owned()))?;
+        let mut config_defaults = Self::default();
+        for (key, value) in table.iter() {
+            let (_, arg_id, is_global, value_kind) = CONFIG_KEYS.iter()
+                .find(|(config_key, ..)| config_key == key)
+                .ok_or_else(|| ConfigErr::UnknownKey(key.clone()))?;
+            let value_str = match (value, value_kind) {
+                (toml::Value::Boolean(flag), ConfigValueKind::Flag) => flag.to_string(),
+                (toml::Value::Boolean(_), _) | (_, ConfigValueKind::Flag) =>
+                    return Err(ConfigErr::InvalidValue(key.clone(), value.to_string())),
+                (toml::Value::String(value_str), _) => value_str.clone(),
+                (toml::Value::Integer(value_int), _) => value_int.to_string(),
+                _ => return Err(ConfigErr::InvalidValue(key.clone(), value.to_string()))
+            };
+            // Same parsers as the command line arguments
+            let is_valid = match value_kind {
+                ConfigValueKind::HashFunction =>
+                    HashFunctions::from_str(&value_str.to_lowercase()).is_ok(),
+                ConfigValueKind::BlockSize => size_str_to_num(&value_str).is_ok(),
+                ConfigValueKind::BranchFactor => branch_t::from_str(&value_str)
+                    .is_ok_and(|branch| branch >= 2),
+                ConfigValueKind::NodeEncoding => NodeEncoding::from_str(&value_str).is_ok(),
+                ConfigValueKind::Jobs => usize::from_str(&value_str).is_ok(),
+                ConfigValueKind::Flag => true
+            };
+            if !is_valid {
+                return Err(ConfigErr::InvalidValue(key.clone(), value.to_string()));
+            }
+            if *is_global {
+                config_defaults.global_args.push((arg_id, value_str));
+            } else {
+                config_defaults.generate_hash_args.push((arg_id, value_str));
+            }
+        }
+        Ok(config_defaults)
+    }
+    // Replaces the default values of the arguments set in the config
+    // Explicitly given arguments still take precedence
+    pub fn apply(&self, mut clap_app: Command, generate_hash_cmd_name: &str) -> Command {
+        for (arg_id, value) in self.global_args.iter() {
+            clap_app = clap_app.mut_arg(*arg_id, |arg| arg.default_value(value.clone()));
+        }
+        clap_app.mut_subcommand(generate_hash_cmd_name, |mut gen_hash_command| {
+            for (arg_id, value) in self.generate_hash_args.iter() {
+                gen_hash_command = gen_hash_command
+                    .mut_arg(*arg_id, |arg| arg.default_value(value.clone()));
+            }
+            gen_hash_command
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_path_found() {
+        let args = ["prog", "--config", "a.toml", "generate-hash"].map(OsString::from);
+        assert_eq!(config_path_arg(args), Some(PathBuf::from("a.toml")));
+        let args = ["prog", "--config=b.toml", "generate-hash"].map(OsString::from);
+        assert_eq!(config_path_arg(args), Some(PathBuf::from("b.toml")));
+        let args = ["prog", "generate-hash", "--", "--config"].map(OsString::from);
+        assert_eq!(config_path_arg(args), None);
+    }
+
+    #[test]
+    fn config_values_parsed() {
+        let config_defaults = ConfigDefaults::parse(concat!(
+            "hash-function = \"SHA512\"\n",
+            "block-length = \"64Ki\"\n",
+            "branch-factor = 8\n",
+            "jobs = 2\n",
+            "self-checksum = true\n")).unwrap();
+        assert_eq!(config_defaults.global_args, vec![("jobs", "2".to_owned())]);
+        assert_eq!(config_defaults.generate_hash_args.len(), 4);
+    }
+
+    #[test]
+    fn config_errors_detected() {
+        assert_eq!(ConfigDefaults::parse("block-length = \"64Qi\"\n"),
+            Err(ConfigErr::InvalidValue("block-length".to_owned(), "\"64Qi\"".to_owned())));
+        assert_eq!(ConfigDefaults::parse("branch-factor = 1\n"),
+            Err(ConfigErr::InvalidValue("branch-factor".to_owned(), "1".to_owned())));
+        assert_eq!(ConfigDefaults::parse("short = \"yes\"\n"),
+            Err(ConfigErr::InvalidValue("short".to_owned(), "\"yes\"".to_owned())));
+        assert_eq!(ConfigDefaults::parse("salt = \"00\"\n"),
+            Err(ConfigErr::UnknownKey("salt".to_owned())));
+        assert!(matches!(ConfigDefaults::parse("jobs = "),
+            Err(ConfigErr::MalformedToml(_))));
+    }
+}
@@ -0,0 +1,138 @@
+#![forbid(unsafe_code)]
+// The convert subcommand, which turns long hash files into short ones
+
+use crate::exit_codes::ExitCode;
+use crate::format_functions::unescape_chars;
+use crate::hash_file_input::read_parsed_hash_file;
+use crate::hash_file_parser::{HashEntry, ParsedHashFile};
+use crate::hash_sink::{HashSink, SinkHeader, TextSink};
+use crate::output_files::write_hash_output;
+use crate::utils::{self, TreeParams};
+
+use merkle_tree::{merkle_block_generator, HashData, HashRange};
+
+use clap::{Command, Arg, ArgAction, ArgMatches};
+
+use std::io::Write;
+use std::path::PathBuf;
+
+pub(crate) const CONVERT_CMD_NAME: &str = "convert";
+
+pub(crate) fn convert_command() -> Command {
+    Command::new(CONVERT_CMD_NAME)
+        .about("Convert a long hash file to a short hash file")
+        .long_about(concat!("Write a short hash file with the root hash ",
+            "of each file in a long hash file, as if it had been generated ",
+            "with --short. Short hash files cannot be converted to long ",
+            "hash files, as the other hashes need the file data."))
+        .arg(Arg::new("toshort").long("to-short")
+            .action(ArgAction::SetTrue)
+            .required_unless_present("tolong")
+            .conflicts_with("tolong")
+            .help("Convert a long hash file to a short hash file"))
+        .arg(Arg::new("tolong").long("to-long")
+            .action(ArgAction::SetTrue)
+            .hide(true)
+            .help("Unsupported, as the hashes of a long hash file need the file data"))
+        .arg(Arg::new("output").long("output").short('o')
+            .action(ArgAction::Set)
+            .help("Output file, instead of stdout"))
+        .arg(Arg::new("overwrite").long("overwrite")
+            .action(ArgAction::SetTrue)
+            .requires("output")
+            .help("Overwrite the output file if it already exists"))
+        .arg(Arg::new("FILE").required(true)
+            .action(ArgAction::Set)
+            .help("Hash file to convert"))
+}
+
+// Writes a short hash file with the root entry of each file in a long hash file
+// The root is the last entry of each file, as in verify-hash --root-only
+fn convert_to_short<W: Write+std::fmt::Debug>(writer: W,
+        parsed_file: &ParsedHashFile) -> Result<(), ExitCode> {
+    let header = &parsed_file.header;
+    let tree_params = &header.tree_params;
+    let unsupported = if header.is_short_hash {
+        Some("it is already a short hash file")
+    } else if tree_params.metadata_only {
+        Some("it has hashes of file metadata")
+    } else if tree_params.trim_zeros || tree_params.region_offset.is_some() {
+        Some("its roots do not cover the whole of each file")
+    } else if !parsed_file.empty_dirs.is_empty() {
+        Some("short hash files cannot record empty directories")
+    } else {
+        None
+    };
+    if let Some(reason) = unsupported {
+        eprintln!("Error: unable to convert hash file to short: {}", reason);
+        return Err(ExitCode::CmdlineErr);
+    }
+    let mut file_roots: Vec<Option<&HashRange>> = vec![None; parsed_file.files.len()];
+    for entry in &parsed_file.entries {
+        if let HashEntry::Long(file_index, hash_range) = entry {
+            file_roots[*file_index] = Some(hash_range);
+        }
+    }
+    let mut sink = TextSink::new(writer, false, false);
+    let sink_header = SinkHeader {
+        // Only the root of each file is left, so every node is listed as usual
+        tree_params: &TreeParams {leaves_and_root: false, ..*tree_params},
+        comments: &header.comments,
+        structure_hash: header.structure_hash.as_ref(),
+        file_entries: &[],
+        dir_list: &[],
+        short_output: true,
+        sectioned: false
+    };
+    if let Err(err) = sink.write_header(&sink_header) {
+        eprintln!("Error writing hash file: {}", err);
+        return Err(ExitCode::GenWriteErr);
+    }
+    for (file_entry, file_root) in parsed_file.files.iter().zip(file_roots) {
+        let paths: Vec<PathBuf> = file_entry.names.iter()
+            .map(|name| PathBuf::from(unescape_chars(name))).collect();
+        let Some(file_root) = file_root else {
+            eprintln!("Error: no hashes are listed for file {}",
+                utils::display_name_group(&paths));
+            return Err(ExitCode::VerifBadEntryErr);
+        };
+        // Content-defined trees have one leaf per chunk, so their roots cannot be computed
+        if !tree_params.content_defined {
+            let (tree_len, tree_block_size) = tree_params.tree_shape(file_entry.file_len);
+            let computed_root = merkle_block_generator(tree_len,
+                tree_block_size, tree_params.branch_factor).into_iter().last().unwrap();
+            if file_root.block_range() != computed_root {
+                eprintln!("Error: last entry of file {} has block range {} instead of its root {}",
+                    utils::display_name_group(&paths), file_root.block_range(), computed_root);
+                return Err(ExitCode::VerifBadEntryErr);
+            }
+        }
+        let root = HashData::try_new(file_root.hash_result()).unwrap();
+        if let Err(err) = sink.write_short(Some(&root), &paths) {
+            eprintln!("Error writing hash file: {}", err);
+            return Err(ExitCode::GenWriteErr);
+        }
+    }
+    let write_result = match &parsed_file.root {
+        Some(root) => sink.write_root(root),
+        None => Ok(())
+    };
+    write_result.and_then(|_| Box::new(sink).finish()).map_err(|err| {
+        eprintln!("Error writing hash file: {}", err);
+        ExitCode::GenWriteErr
+    })
+}
+
+pub(crate) fn run_convert(cmd_matches: &ArgMatches) -> ExitCode {
+    if cmd_matches.get_flag("tolong") {
+        eprintln!(concat!("Error: short hash files cannot be converted to long ",
+            "hash files without the file data, so use generate-hash instead"));
+        return ExitCode::CmdlineErr;
+    }
+    let file_name = cmd_matches.get_one::<String>("FILE").unwrap();
+    let parsed_file = match read_parsed_hash_file(file_name) {
+        Ok(parsed_file) => parsed_file,
+        Err(exit_code) => return exit_code
+    };
+    write_hash_output(cmd_matches, |writer| convert_to_short(writer, &parsed_file))
+}
@@ -8,41 +8,53 @@ use generic_array::GenericArray;
 
 pub use digest::Digest;
 
-#[derive(Clone, Default)]
-/// Wraps a [`Hasher`] and provides it with [`Digest`] and [`DynDigest`] implementations.
-///
-/// [`Digest`]: ../digest/trait.Digest.html
-/// [`DynDigest`]: ../digest/trait.DynDigest.html
-/// [`Hasher`]: ../crc32fast/struct.Hasher.html
-pub struct Crc32(Crc32Hasher);
-
-impl OutputSizeUser for Crc32 {
-    type OutputSize = U4;
-}
-impl HashMarker for Crc32 {}
+// Generates a wrapper whose output is the CRC32 in the given byte order
+macro_rules! crc32_digest {
+    ($(#[$attr:meta])* $name:ident, $to_bytes:ident) => {
+        #[derive(Clone, Default)]
+        $(#[$attr])*
+        pub struct $name(Crc32Hasher);
 
-impl FixedOutput for Crc32 {
-    #[inline]
-    fn finalize_into(self, out: &mut GenericArray<u8, Self::OutputSize>) {
-        let result = self.0.finalize();
-        out.copy_from_slice(&result.to_be_bytes());
-    }
-}
+        impl OutputSizeUser for $name {
+            type OutputSize = U4;
+        }
+        impl HashMarker for $name {}
 
-impl Update for Crc32 {
-    #[inline]
-    fn update(&mut self, data: &[u8]) {
-        self.0.update(data);
-    }
-}
+        impl FixedOutput for $name {
+            #[inline]
+            fn finalize_into(self, out: &mut GenericArray<u8, Self::OutputSize>) {
+                let result = self.0.finalize();
+                out.copy_from_slice(&result.$to_bytes());
+            }
+        }
 
-impl Reset for Crc32 {
-    #[inline]
-    fn reset(&mut self) {
-        self.0.reset();
-    }
+        impl Update for $name {
+            #[inline]
+            fn update(&mut self, data: &[u8]) {
+                self.0.update(data);
+            }
+        }
+
+        impl Reset for $name {
+            #[inline]
+            fn reset(&mut self) {
+                self.0.reset();
+            }
+        }
+    };
 }
 
+crc32_digest!(
+    /// Wraps a [`Hasher`] and provides it with [`Digest`] and [`DynDigest`] implementations.
+    ///
+    /// [`Digest`]: ../digest/trait.Digest.html
+    /// [`DynDigest`]: ../digest/trait.DynDigest.html
+    /// [`Hasher`]: ../crc32fast/struct.Hasher.html
+    Crc32, to_be_bytes);
+crc32_digest!(
+    /// Same as [`Crc32`], but with the CRC32 in little-endian order as written by zip and gzip
+    Crc32Le, to_le_bytes);
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -53,5 +65,14 @@ mod test {
     fn test_crc32_impl_digest() {
         let crc32 = Crc32::default();
         assert_impls_digest(&crc32);
+        assert_impls_digest(&Crc32Le::default());
+    }
+
+    // Check value of CRC32 as printed by e.g. `python3 -c "import zlib; print(hex(zlib.crc32(b'123456789')))"`,
+    // with the little-endian bytes as stored in gzip trailers
+    #[test]
+    fn test_crc32_byte_order() {
+        assert_eq!(Crc32::digest(b"123456789").as_slice(), [0xcb, 0xf4, 0x39, 0x26]);
+        assert_eq!(Crc32Le::digest(b"123456789").as_slice(), [0x26, 0x39, 0xf4, 0xcb]);
     }
 }
\ No newline at end of file
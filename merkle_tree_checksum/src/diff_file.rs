@@ -0,0 +1,246 @@
+#![forbid(unsafe_code)]
+// Byte ranges of a file that changed since its entries in a long hash file were generated
+
+use crate::error_types::DiffFileErr;
+use crate::parse_functions::{extract_empty_dir_name, extract_long_hash_parts};
+use crate::parse_functions::{extract_quoted_filename, unquote_name_group};
+use crate::utils::TreeParams;
+
+use merkle_tree::{block_t, BlockRange, HashData};
+
+use std::fmt;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+// Length and leaf hashes of one file, as recorded in a hash file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StoredFile {
+    pub file_len: u64,
+    pub leaf_hashes: Vec<HashData<64>>
+}
+
+// Changed byte ranges are within both versions of the file
+// Added and removed byte ranges are past the end of the shorter version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BlockChange {
+    Changed(BlockRange),
+    Added(BlockRange),
+    Removed(BlockRange)
+}
+impl fmt::Display for BlockChange {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Changed(range) => write!(fmt, "changed {}", range),
+            Self::Added(range) => write!(fmt, "added {}", range),
+            Self::Removed(range) => write!(fmt, "removed {}", range)
+        }
+    }
+}
+
+// reader should be positioned just after the Files: line
+// The file is the entry named data_file_name, or else the only entry of the hash file
+pub(crate) fn read_stored_file<R: BufRead>(mut reader: R, header_line_count: usize,
+        tree_params: &TreeParams, data_file_name: &str) -> Result<StoredFile, DiffFileErr> {
+    let hex_digit_count = 2*tree_params.hash_function.hash_len();
+    let mut line_number = header_line_count;
+
+    macro_rules! next_line {
+        () => {{
+            let mut line = String::new();
+            match reader.read_line(&mut line)? {
+                0 => None,
+                _ => {
+                    line_number += 1;
+                    Some(line)
+                }
+            }
+        }}
+    }
+
+    // Hashed lengths of the listed files, and the ID of the named file
+    let mut file_lens: Vec<u64> = Vec::new();
+    let mut named_file_id: Option<usize> = None;
+    loop {
+        let line = match next_line!() {
+            Some(line) => line,
+            None => return Err(DiffFileErr::UnexpectedEof)
+        };
+        if line == "Hashes:\n" || line == "Hashes:\r\n" {
+            break;
+        }
+        if extract_empty_dir_name(&line).is_ok() {
+            continue;
+        }
+        match extract_quoted_filename(&line) {
+            Ok((quoted_name, Some(file_len), hashed_len)) => {
+                let names = unquote_name_group(quoted_name)
+                    .map_err(|_| DiffFileErr::MalformedLine(line_number, line.clone()))?;
+                if named_file_id.is_none() && names.len() == 1 && names[0] == data_file_name {
+                    named_file_id = Some(file_lens.len());
+                }
+                file_lens.push(hashed_len.unwrap_or(file_len));
+            },
+            _ => return Err(DiffFileErr::MalformedLine(line_number, line))
+        }
+    }
+    let file_id = match (named_file_id, file_lens.len()) {
+        (Some(file_id), _) => file_id,
+        (None, 1) => 0,
+        (None, _) => return Err(DiffFileErr::FileNotListed(data_file_name.to_owned()))
+    };
+
+    let file_len = file_lens[file_id];
+    // An empty file still has a single leaf
+    let leaf_count = file_len.div_ceil(tree_params.block_size.into()).max(1);
+    let mut leaf_hashes: Vec<Option<HashData<64>>> = vec![None; leaf_count as usize];
+    while let Some(line) = next_line!() {
+        let (entry_id, hash_range) = extract_long_hash_parts(&line, hex_digit_count)
+            .map_err(|_| DiffFileErr::MalformedLine(line_number, line.clone()))?;
+        if entry_id > file_id {
+            break;
+        }
+        let block_range = hash_range.block_range();
+        if entry_id < file_id || block_range.range() != 1 {
+            continue;
+        }
+        let stored_hash = HashData::try_new(hash_range.hash_result()).unwrap();
+        match leaf_hashes.get_mut(block_range.start() as usize) {
+            Some(leaf_hash) => *leaf_hash = Some(stored_hash),
+            None => return Err(DiffFileErr::MalformedLine(line_number, line))
+        }
+    }
+    let leaf_hashes = leaf_hashes.into_iter().enumerate()
+        .map(|(block_index, leaf_hash)| leaf_hash.ok_or(DiffFileErr::MissingLeafEntry(
+            BlockRange::new(block_index as u64, block_index as u64, true))))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(StoredFile {file_len, leaf_hashes})
+}
+
+// Consecutive changed blocks are merged into one byte range
+pub(crate) fn diff_leaves(stored_file: &StoredFile, current_len: u64,
+        current_leaf_hashes: &[HashData<64>], block_size: block_t) -> Vec<BlockChange> {
+    let block_size = u64::from(block_size);
+    let stored_block_count = stored_file.file_len.div_ceil(block_size);
+    let current_block_count = current_len.div_ceil(block_size);
+    let common_block_count = stored_block_count.min(current_block_count);
+    let common_len = stored_file.file_len.min(current_len);
+
+    let mut changes: Vec<BlockChange> = Vec::new();
+    let mut run_start: Option<u64> = None;
+    for block_index in 0..=common_block_count {
+        let is_changed = block_index < common_block_count
+            && stored_file.leaf_hashes[block_index as usize]
+                != current_leaf_hashes[block_index as usize];
+        match (is_changed, run_start) {
+            (true, None) => run_start = Some(block_index),
+            (false, Some(start_block)) => {
+                let end_byte = (block_index*block_size).min(common_len) - 1;
+                changes.push(BlockChange::Changed(
+                    BlockRange::new(start_block*block_size, end_byte, true)));
+                run_start = None;
+            },
+            _ => {}
+        }
+    }
+    if current_len > common_len {
+        changes.push(BlockChange::Added(BlockRange::new(common_len, current_len - 1, true)));
+    } else if stored_file.file_len > common_len {
+        changes.push(BlockChange::Removed(
+            BlockRange::new(common_len, stored_file.file_len - 1, true)));
+    }
+    changes
+}
+
+// Reference copies of the parts of an entry, found under reference if it is a directory
+pub(crate) fn reference_parts(reference: &Path, file_parts: &[PathBuf]) -> Vec<PathBuf> {
+    match reference.is_dir() {
+        true => file_parts.iter().map(|part| reference.join(part)).collect(),
+        false => vec![reference.to_path_buf()]
+    }
+}
+
+// Offset of the first byte from start that differs between the two readers,
+// looking at most len bytes ahead
+// Reaching the end of only one reader counts as a difference
+pub(crate) fn first_differing_byte<R: Read+Seek, S: Read+Seek>(data: &mut R,
+        reference: &mut S, start: u64, len: u64) -> io::Result<Option<u64>> {
+    const CHUNK_LEN: u64 = 64*1024;
+    data.seek(SeekFrom::Start(start))?;
+    reference.seek(SeekFrom::Start(start))?;
+    let mut chunk_start = start;
+    let end = start.saturating_add(len);
+    while chunk_start < end {
+        let chunk_len = (end - chunk_start).min(CHUNK_LEN);
+        let mut data_chunk: Vec<u8> = Vec::new();
+        let mut reference_chunk: Vec<u8> = Vec::new();
+        data.by_ref().take(chunk_len).read_to_end(&mut data_chunk)?;
+        reference.by_ref().take(chunk_len).read_to_end(&mut reference_chunk)?;
+        let common_len = data_chunk.len().min(reference_chunk.len());
+        let differing_pos = data_chunk.iter().zip(&reference_chunk)
+            .position(|(data_byte, reference_byte)| data_byte != reference_byte);
+        match differing_pos {
+            Some(pos) => return Ok(Some(chunk_start + pos as u64)),
+            None if data_chunk.len() != reference_chunk.len() =>
+                return Ok(Some(chunk_start + common_len as u64)),
+            None if (common_len as u64) < chunk_len => break,
+            None => chunk_start += chunk_len
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn leaf(byte: u8) -> HashData<64> {
+        HashData::try_new(&[byte; 4]).unwrap()
+    }
+
+    #[test]
+    fn changed_blocks_merged() {
+        let stored_file = StoredFile {
+            file_len: 20,
+            leaf_hashes: vec![leaf(0), leaf(1), leaf(2), leaf(3), leaf(4)]
+        };
+        let current_leaves = [leaf(0), leaf(9), leaf(9), leaf(3), leaf(9)];
+        assert_eq!(diff_leaves(&stored_file, 18, &current_leaves, 4), vec![
+            BlockChange::Changed(BlockRange::new(4, 11, true)),
+            BlockChange::Changed(BlockRange::new(16, 17, true)),
+            BlockChange::Removed(BlockRange::new(18, 19, true))
+        ]);
+        assert!(diff_leaves(&stored_file, 20, &stored_file.leaf_hashes, 4).is_empty());
+    }
+
+    #[test]
+    fn length_changes_reported() {
+        let stored_file = StoredFile {
+            file_len: 10,
+            leaf_hashes: vec![leaf(0), leaf(1), leaf(2)]
+        };
+        let longer_leaves = [leaf(0), leaf(1), leaf(9), leaf(3)];
+        assert_eq!(diff_leaves(&stored_file, 16, &longer_leaves, 4), vec![
+            BlockChange::Changed(BlockRange::new(8, 9, true)),
+            BlockChange::Added(BlockRange::new(10, 15, true))
+        ]);
+        assert_eq!(diff_leaves(&stored_file, 4, &[leaf(0)], 4), vec![
+            BlockChange::Removed(BlockRange::new(4, 9, true))
+        ]);
+        assert_eq!(diff_leaves(&stored_file, 0, &[leaf(7)], 4), vec![
+            BlockChange::Removed(BlockRange::new(0, 9, true))
+        ]);
+    }
+
+    #[test]
+    fn first_differing_byte_found() {
+        let data = b"abcdefgh";
+        let first_diff = |reference: &[u8], start, len| first_differing_byte(
+            &mut Cursor::new(&data[..]), &mut Cursor::new(reference), start, len).unwrap();
+        assert_eq!(first_diff(b"abcdeXgh", 4, 4), Some(5));
+        // Differences outside of the range are not looked at
+        assert_eq!(first_diff(b"Xbcdefgh", 4, 4), None);
+        assert_eq!(first_diff(b"abcdef", 4, 4), Some(6));
+        assert_eq!(first_diff(b"abcdefgh", 0, 100), None);
+    }
+}
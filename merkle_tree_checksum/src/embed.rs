@@ -0,0 +1,155 @@
+#![forbid(unsafe_code)]
+
+use std::convert::{TryFrom, TryInto};
+use std::io::{Read, Seek, SeekFrom};
+
+use merkle_tree::{block_t, branch_t, seek_len, HashData, NodeEncoding};
+
+use crate::error_types::TrailerError;
+use crate::hash_enum::HashFunctions;
+use crate::utils::{HeaderElement, StoredAndComputed, TreeParams};
+
+/*
+ * Only the plain node encoding is supported
+ * Trailer appended to a file by generate-hash --embed:
+ * - root hash (hash length bytes)
+ * - footer of FOOTER_LEN bytes, with integers in big-endian:
+ *   - hash function id (u8, the stable HashFunctions discriminant)
+ *   - block size (u32)
+ *   - branch factor (u16)
+ *   - hash length (u8)
+ *   - EMBED_MAGIC
+ */
+const EMBED_MAGIC: &[u8; 8] = b"MRKLEMBD";
+const FOOTER_LEN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct EmbedTrailer {
+    pub tree_params: TreeParams,
+    pub root_hash: HashData<64>
+}
+impl EmbedTrailer {
+    #[inline]
+    pub fn len(&self) -> u64 {
+        (self.root_hash.len() + FOOTER_LEN) as u64
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut trailer = self.root_hash.to_vec();
+        trailer.push(u8::from(self.tree_params.hash_function));
+        trailer.extend_from_slice(&self.tree_params.block_size.to_be_bytes());
+        trailer.extend_from_slice(&self.tree_params.branch_factor.to_be_bytes());
+        trailer.push(u8::try_from(self.root_hash.len()).unwrap());
+        trailer.extend_from_slice(EMBED_MAGIC);
+        debug_assert_eq!(trailer.len() as u64, self.len());
+        trailer
+    }
+    // Reader position is unspecified afterwards
+    pub fn read_from<R: Read+Seek>(reader: &mut R) -> Result<Self, TrailerError> {
+        let file_len = seek_len(reader);
+        if file_len < FOOTER_LEN as u64 {
+            return Err(TrailerError::MissingTrailer);
+        }
+        let mut footer = [0x00; FOOTER_LEN];
+        reader.seek(SeekFrom::Start(file_len - FOOTER_LEN as u64))?;
+        reader.read_exact(&mut footer)?;
+        if &footer[8..] != EMBED_MAGIC {
+            return Err(TrailerError::MissingTrailer);
+        }
+
+        let hash_function = HashFunctions::try_from(footer[0])
+            .map_err(|_| TrailerError::UnknownHashFunction(footer[0]))?;
+        let block_size = block_t::from_be_bytes(footer[1..5].try_into().unwrap());
+        if block_size == 0 {
+            return Err(TrailerError::BadParameterValue(
+                HeaderElement::BlockSize, block_size.to_string()));
+        }
+        let branch_factor = branch_t::from_be_bytes(footer[5..7].try_into().unwrap());
+        if branch_factor < 2 {
+            return Err(TrailerError::BadParameterValue(
+                HeaderElement::BranchFactor, branch_factor.to_string()));
+        }
+        let hash_len = usize::from(footer[7]);
+        if hash_len != hash_function.hash_len() {
+            return Err(TrailerError::MismatchedHashLength(
+                StoredAndComputed::new(hash_len, hash_function.hash_len())));
+        }
+
+        let trailer_len = (hash_len + FOOTER_LEN) as u64;
+        if file_len < trailer_len {
+            return Err(TrailerError::MissingTrailer);
+        }
+        let mut root_hash = vec![0x00; hash_len];
+        reader.seek(SeekFrom::Start(file_len - trailer_len))?;
+        reader.read_exact(&mut root_hash)?;
+        Ok(EmbedTrailer {
+            tree_params: TreeParams {block_size, branch_factor, hash_function,
+                node_encoding: NodeEncoding::Plain, salted: false,
+                pad_tree: false, unbalanced: false, duplicate_last: false,
+                metadata_only: false,
+                trim_zeros: false, region_offset: None, absolute_ranges: false,
+                leaves_and_root: false, content_defined: false,
+                normalize_eol: false, relative_names: false, nominal_ranges: false,
+                stride: None, filtered: false},
+            root_hash: HashData::try_new(&root_hash).unwrap()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn example_trailer() -> EmbedTrailer {
+        EmbedTrailer {
+            tree_params: TreeParams {
+                block_size: 4096,
+                branch_factor: 4,
+                hash_function: HashFunctions::crc32,
+                node_encoding: NodeEncoding::Plain,
+                salted: false,
+                pad_tree: false,
+                unbalanced: false,
+                duplicate_last: false,
+                metadata_only: false,
+                trim_zeros: false,
+                region_offset: None,
+                absolute_ranges: false,
+                leaves_and_root: false,
+                content_defined: false,
+                normalize_eol: false,
+                relative_names: false,
+                nominal_ranges: false,
+                stride: None,
+                filtered: false
+            },
+            root_hash: HashData::try_new(&[0x12, 0x34, 0x56, 0x78]).unwrap()
+        }
+    }
+
+    #[test]
+    fn trailer_roundtrip() {
+        let trailer = example_trailer();
+        let mut file_data = b"file body".to_vec();
+        file_data.extend_from_slice(&trailer.to_bytes());
+        let mut file_cursor = Cursor::new(file_data);
+        assert_eq!(EmbedTrailer::read_from(&mut file_cursor).unwrap(), trailer);
+        assert_eq!(trailer.len(), 20);
+    }
+    #[test]
+    fn trailer_missing_or_bad() {
+        let mut short_cursor = Cursor::new(b"short".to_vec());
+        assert_eq!(EmbedTrailer::read_from(&mut short_cursor),
+            Err(TrailerError::MissingTrailer));
+        let mut plain_cursor = Cursor::new(b"no trailer in this file".to_vec());
+        assert_eq!(EmbedTrailer::read_from(&mut plain_cursor),
+            Err(TrailerError::MissingTrailer));
+
+        let mut bad_len_bytes = example_trailer().to_bytes();
+        // Hash length byte is right before the magic bytes
+        bad_len_bytes[4+7] = 32;
+        let mut bad_len_cursor = Cursor::new(bad_len_bytes);
+        assert_eq!(EmbedTrailer::read_from(&mut bad_len_cursor),
+            Err(TrailerError::MismatchedHashLength(StoredAndComputed::new(32, 4))));
+    }
+}
@@ -3,7 +3,9 @@ use crate::utils::{StoredAndComputed, HeaderElement};
 use merkle_tree::{BlockRange, HashData};
 
 use hex::ToHex;
+use semver::Version;
 use std::fmt;
+use std::io;
 
 #[derive(Default, Debug, Clone)]
 pub struct SizeStrToNumErr {}
@@ -14,12 +16,34 @@ impl fmt::Display for SizeStrToNumErr {
 }
 impl std::error::Error for SizeStrToNumErr {}
 
+#[derive(Default, Debug, Clone)]
+pub struct SaltStrToBytesErr {}
+impl fmt::Display for SaltStrToBytesErr {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("Salt must be a nonempty hex string with an even number of digits")
+    }
+}
+impl std::error::Error for SaltStrToBytesErr {}
+
 // No Copy to simplify refactoring if non-copy types get added later
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub(crate) enum PreHashError {
     FileNotFound,
     ReadPermissionError,
-    MismatchedLength(StoredAndComputed<u64>)
+    MismatchedLength(StoredAndComputed<u64>),
+    DirectoryNotFound,
+    DirectoryNotEmpty,
+    UnreadableDirEntry(String),
+    // Byte offset of the last nonzero byte in the trimmed region
+    NonzeroTrimmedData(u64),
+    // Reading failed partway, e.g. from corrupt compressed data
+    UnreadableData(String),
+    // Only from --error-on-empty
+    EmptyFile,
+    // Hash files store names as UTF-8, so other names could not be verified
+    NonUtf8Name(String),
+    // Only from --no-recurse
+    IsDirectory
 }
 impl fmt::Display for PreHashError {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -31,7 +55,18 @@ impl fmt::Display for PreHashError {
                     "  actual:   {}"),
                     s_c.stored(), s_c.computed())
             },
-            Self::ReadPermissionError => write!(fmt, "permission denied to read")
+            Self::ReadPermissionError => write!(fmt, "permission denied to read"),
+            Self::DirectoryNotFound => write!(fmt, "directory not found"),
+            Self::DirectoryNotEmpty => write!(fmt, "directory is no longer empty"),
+            Self::UnreadableDirEntry(err) => write!(fmt,
+                "unable to read directory entry: {}", err),
+            Self::NonzeroTrimmedData(offset) => write!(fmt,
+                "trimmed trailing zeros now have nonzero data at byte {:#x}", offset),
+            Self::UnreadableData(err) => write!(fmt, "unable to read data: {}", err),
+            Self::EmptyFile => write!(fmt, "file is empty"),
+            Self::NonUtf8Name(name) => write!(fmt,
+                "name {} is not valid UTF-8, so it cannot be stored in a hash file", name),
+            Self::IsDirectory => write!(fmt, "is a directory, which --no-recurse does not expand")
         }
     }
 }
@@ -72,11 +107,36 @@ impl fmt::Display for FilenameExtractionError {
 }
 impl std::error::Error for FilenameExtractionError {}
 
-#[derive(Default, Debug, Clone)]
-pub(crate) struct HashExtractionError {}
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub(crate) enum HashExtractionError {
+    #[default]
+    Malformed,
+    // Byte lengths of the hash in the entry and of the header's hash function
+    MismatchedHashLength(StoredAndComputed<usize>)
+}
+impl HashExtractionError {
+    // Error for the entry on line, as reported during verification
+    pub fn into_verification_error(self, line: String) -> VerificationError {
+        match self {
+            Self::Malformed => VerificationError::MalformedEntry(line),
+            Self::MismatchedHashLength(s_c) => VerificationError::MismatchedHashLength(s_c)
+        }
+    }
+    pub fn into_lint_issue(self, line: String) -> LintIssue {
+        match self {
+            Self::Malformed => LintIssue::MalformedEntry(line),
+            Self::MismatchedHashLength(s_c) => LintIssue::MismatchedHashLength(s_c)
+        }
+    }
+}
 impl fmt::Display for HashExtractionError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        fmt.write_str("Unable to extract expected hash")
+        match self {
+            Self::Malformed => fmt.write_str("Unable to extract expected hash"),
+            Self::MismatchedHashLength(s_c) => write!(fmt,
+                "entry hash is {} bytes but header declares {}-byte function",
+                s_c.stored(), s_c.computed())
+        }
     }
 }
 impl std::error::Error for HashExtractionError {}
@@ -86,9 +146,19 @@ pub(crate) enum VerificationError {
     MismatchedFileID, // No StoredAndComputed as this would not be helpful
     MismatchedBlockRange(StoredAndComputed<BlockRange>),
     MismatchedByteRange(StoredAndComputed<BlockRange>),
+    // Stored byte range and the one derived from the stored block range
+    InconsistentByteRange(StoredAndComputed<BlockRange>),
     // Range is byte range, which exists when verifying long hashes
     MismatchedHash(Option<BlockRange>, StoredAndComputed<HashData<64>>),
     MalformedEntry(String), // String is the malformed line
+    // Well-formed entry whose hash does not have the header's hash length
+    MismatchedHashLength(StoredAndComputed<usize>),
+    MismatchedMetadata, // Metadata hashes say nothing about what changed
+    // File marked empty by --mark-empty, with its current length
+    NoLongerEmpty(u64),
+    // Only from --self-consistent, with the block range of the parent
+    MismatchedParentHash(BlockRange, StoredAndComputed<HashData<64>>),
+    MissingChildEntries(BlockRange),
     UnexpectedEof
 }
 impl fmt::Display for VerificationError {
@@ -108,6 +178,12 @@ impl fmt::Display for VerificationError {
                     "  computed: {}"),
                     s_c.stored(), s_c.computed())
             }
+            Self::InconsistentByteRange(s_c) => {
+                write!(fmt, concat!("byte range in entry does not match its block range:\n",
+                    "  stored:   {}\n",
+                    "  derived:  {}"),
+                    s_c.stored(), s_c.computed())
+            }
             Self::MismatchedHash(range_option, s_c) => {
                 match range_option {
                     Some(range) => writeln!(fmt,
@@ -123,8 +199,448 @@ impl fmt::Display for VerificationError {
             Self::MalformedEntry(line) => {
                 write!(fmt, "found malformed entry {}", line)
             }
+            Self::MismatchedHashLength(s_c) => {
+                write!(fmt, "entry hash is {} bytes but header declares {}-byte function",
+                    s_c.stored(), s_c.computed())
+            }
+            Self::MismatchedMetadata => {
+                write!(fmt, "metadata hash mismatch: modification time or length changed")
+            }
+            Self::NoLongerEmpty(len) => {
+                write!(fmt, "file was recorded as empty but is now {:#x} bytes long", len)
+            }
+            Self::MismatchedParentHash(range, s_c) => {
+                write!(fmt, concat!(
+                    "parent hash over block range {} does not match its children:\n",
+                    "  stored:   {}\n",
+                    "  computed: {}"),
+                    range,
+                    Vec::<u8>::encode_hex::<String>(&s_c.stored().to_vec()),
+                    Vec::<u8>::encode_hex::<String>(&s_c.computed().to_vec()))
+            }
+            Self::MissingChildEntries(range) => {
+                write!(fmt, "no entries for the children of block range {}", range)
+            }
             Self::UnexpectedEof => write!(fmt, "unexpected EOF")
         }
     }
 }
 impl std::error::Error for VerificationError {}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum TrailerError {
+    MissingTrailer,
+    UnknownHashFunction(u8),
+    BadParameterValue(HeaderElement, String),
+    MismatchedHashLength(StoredAndComputed<usize>),
+    ReadError(io::ErrorKind)
+}
+impl From<io::Error> for TrailerError {
+    fn from(err: io::Error) -> Self {
+        Self::ReadError(err.kind())
+    }
+}
+impl fmt::Display for TrailerError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Will be printed as "Error reading trailer of file {name}: {err}\n"
+        match self {
+            Self::MissingTrailer => write!(fmt, "no embedded hash found"),
+            Self::UnknownHashFunction(id) => write!(fmt,
+                "unknown hash function id {:#04x}", id),
+            Self::BadParameterValue(p, val) => write!(fmt,
+                "parameter {} has invalid value {}", p, val),
+            Self::MismatchedHashLength(s_c) => {
+                write!(fmt, concat!("mismatched hash length:\n",
+                    "  stored:   {}\n",
+                    "  expected: {}"),
+                    s_c.stored(), s_c.computed())
+            },
+            Self::ReadError(kind) => write!(fmt, "read error: {}", kind)
+        }
+    }
+}
+impl std::error::Error for TrailerError {}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum PathListError {
+    MalformedName(usize, String), // 1-indexed line number and the line
+    // 1-indexed position of a NUL-delimited path that is not UTF-8
+    InvalidUtf8(usize),
+    ReadError(io::ErrorKind)
+}
+impl From<io::Error> for PathListError {
+    fn from(err: io::Error) -> Self {
+        Self::ReadError(err.kind())
+    }
+}
+impl fmt::Display for PathListError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Will be printed as "Error reading path list {name}: {err}\n"
+        match self {
+            Self::MalformedName(line_number, line) => write!(fmt,
+                "line {}: malformed quoted name {}", line_number, line),
+            Self::InvalidUtf8(path_number) => write!(fmt,
+                "path {}: not valid UTF-8", path_number),
+            Self::ReadError(kind) => write!(fmt, "read error: {}", kind)
+        }
+    }
+}
+impl std::error::Error for PathListError {}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum FileChecksumErr {
+    MalformedFooter(String),
+    MismatchedChecksum(StoredAndComputed<u32>),
+    ReadError(io::ErrorKind)
+}
+impl From<io::Error> for FileChecksumErr {
+    fn from(err: io::Error) -> Self {
+        Self::ReadError(err.kind())
+    }
+}
+impl fmt::Display for FileChecksumErr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Will be printed as "Error checking hash file {name}: {err}\n"
+        match self {
+            Self::MalformedFooter(line) => write!(fmt,
+                "malformed checksum line {:?}", line),
+            Self::MismatchedChecksum(s_c) => {
+                write!(fmt, concat!("hash file is damaged, mismatched checksum:\n",
+                    "  stored:   {:08x}\n",
+                    "  computed: {:08x}"),
+                    s_c.stored(), s_c.computed())
+            },
+            Self::ReadError(kind) => write!(fmt, "read error: {}", kind)
+        }
+    }
+}
+impl std::error::Error for FileChecksumErr {}
+
+// Byte ranges from verify-hash --check-coverage, with exclusive ends
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum CoverageErr {
+    Gap(BlockRange),
+    Overlap(BlockRange),
+    PastEnd(BlockRange)
+}
+impl fmt::Display for CoverageErr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Will be printed as "Error verifying file {name}: {err}\n"
+        match self {
+            Self::Gap(range) => write!(fmt,
+                "no leaf entry covers byte range {}", range),
+            Self::Overlap(range) => write!(fmt,
+                "leaf entries overlap over byte range {}", range),
+            Self::PastEnd(range) => write!(fmt,
+                "leaf entry covers byte range {} past the end of the file", range)
+        }
+    }
+}
+impl std::error::Error for CoverageErr {}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum ParityErr {
+    // 1-indexed line numbers
+    MissingParity(usize),
+    DamagedLine(usize),
+    UnrepairableLine(usize),
+    ReadError(io::ErrorKind)
+}
+impl From<io::Error> for ParityErr {
+    fn from(err: io::Error) -> Self {
+        Self::ReadError(err.kind())
+    }
+}
+impl fmt::Display for ParityErr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Will be printed as "Error checking hash file {name}: {err}\n"
+        match self {
+            Self::MissingParity(line_number) => write!(fmt,
+                "line {}: missing parity", line_number),
+            Self::DamagedLine(line_number) => write!(fmt,
+                "line {}: failed its parity check, and may be fixed with repair",
+                line_number),
+            Self::UnrepairableLine(line_number) => write!(fmt,
+                "line {}: has more damage than parity can correct", line_number),
+            Self::ReadError(kind) => write!(fmt, "read error: {}", kind)
+        }
+    }
+}
+impl std::error::Error for ParityErr {}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum DiffFileErr {
+    FileNotListed(String),
+    MalformedLine(usize, String), // 1-indexed line number and the line
+    MissingLeafEntry(BlockRange),
+    UnexpectedEof,
+    ReadError(io::ErrorKind)
+}
+impl From<io::Error> for DiffFileErr {
+    fn from(err: io::Error) -> Self {
+        Self::ReadError(err.kind())
+    }
+}
+impl fmt::Display for DiffFileErr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Will be printed as "Error reading hash file {name}: {err}\n"
+        match self {
+            Self::FileNotListed(name) => write!(fmt,
+                "{} is not listed, and there is more than one file to compare against", name),
+            Self::MalformedLine(line_number, line) => write!(fmt,
+                "line {}: found malformed entry {}", line_number, line),
+            Self::MissingLeafEntry(range) => write!(fmt,
+                "no entry for leaf with block range {}", range),
+            Self::UnexpectedEof => write!(fmt, "unexpected EOF"),
+            Self::ReadError(kind) => write!(fmt, "read error: {}", kind)
+        }
+    }
+}
+impl std::error::Error for DiffFileErr {}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum SectionIndexErr {
+    MalformedEntry(String),
+    UnknownFile(usize), // Entry index past the files in the header
+    SplitSection(usize), // File whose entries are not next to each other
+    MissingSection(usize),
+    ReadError(io::ErrorKind)
+}
+impl From<io::Error> for SectionIndexErr {
+    fn from(err: io::Error) -> Self {
+        Self::ReadError(err.kind())
+    }
+}
+impl fmt::Display for SectionIndexErr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Will be printed as "Error indexing hash file {name}: {err}\n"
+        match self {
+            Self::MalformedEntry(line) => write!(fmt, "found malformed entry {}", line),
+            Self::UnknownFile(file_index) => write!(fmt,
+                "found entry for file {}, which is not listed", file_index),
+            Self::SplitSection(file_index) => write!(fmt,
+                "entries for file {} are not next to each other", file_index),
+            Self::MissingSection(file_index) => write!(fmt,
+                "no entries for file {}", file_index),
+            Self::ReadError(kind) => write!(fmt, "read error: {}", kind)
+        }
+    }
+}
+impl std::error::Error for SectionIndexErr {}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum ConfigErr {
+    MalformedToml(String),
+    UnknownKey(String),
+    InvalidValue(String, String), // Key and the value as written in TOML
+    ReadError(io::ErrorKind)
+}
+impl From<io::Error> for ConfigErr {
+    fn from(err: io::Error) -> Self {
+        Self::ReadError(err.kind())
+    }
+}
+impl fmt::Display for ConfigErr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Will be printed as "error: invalid config file {name}: {err}\n"
+        match self {
+            Self::MalformedToml(msg) => write!(fmt, "malformed TOML: {}", msg),
+            Self::UnknownKey(key) => write!(fmt, "unknown key '{}'", key),
+            Self::InvalidValue(key, value) => write!(fmt,
+                "invalid value {} for '{}'", value, key),
+            Self::ReadError(kind) => write!(fmt, "read error: {}", kind)
+        }
+    }
+}
+impl std::error::Error for ConfigErr {}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum XattrParamsErr {
+    Unsupported,
+    Absent,
+    NotUtf8,
+    BadValue(Vec<HeaderParsingErr>),
+    ReadError(io::ErrorKind)
+}
+impl fmt::Display for XattrParamsErr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported => write!(fmt,
+                "extended attributes are not supported here"),
+            Self::Absent => write!(fmt, "no tree parameters are stored"),
+            Self::NotUtf8 => write!(fmt, "stored tree parameters are not UTF-8"),
+            // Only the first problem is shown, worded for an attribute rather than a hash file
+            Self::BadValue(errs) => match errs.first() {
+                Some(HeaderParsingErr::MissingParameter(p)) => write!(fmt,
+                    "stored tree parameters are missing {}", p),
+                Some(HeaderParsingErr::BadParameterValue(p, val)) => write!(fmt,
+                    "stored tree parameter {} has invalid value {}", p, val),
+                Some(HeaderParsingErr::UnexpectedParameter(p)) => write!(fmt,
+                    "stored tree parameters have unexpected parameter {}", p),
+                _ => write!(fmt, "stored tree parameters are malformed")
+            },
+            Self::ReadError(kind) => write!(fmt, "read error: {}", kind)
+        }
+    }
+}
+impl std::error::Error for XattrParamsErr {}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum LintIssue {
+    MalformedEntry(String), // String is the malformed line
+    MismatchedHashLength(StoredAndComputed<usize>),
+    MalformedName(String),
+    UnsupportedEmptyDir,
+    MissingHashList,
+    UnknownFileID(usize),
+    // Found ID, then the ID of the previous entry
+    NonMonotonicFileID(usize, usize),
+    // File ID, first missing range, and number of consecutive missing ranges
+    MissingBlockRanges(usize, BlockRange, u64),
+    UnexpectedBlockRange(usize, BlockRange),
+    MismatchedByteRange(usize, StoredAndComputed<BlockRange>),
+    MissingMetadataEntry(usize),
+    // Section markers, with the marker line or file ID
+    UnknownSectionMarker(String),
+    MisplacedSectionMarker(usize),
+    MissingSectionMarker(usize),
+    ReadError(io::ErrorKind)
+}
+impl fmt::Display for LintIssue {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Will be printed as "Error: line {number}: {issue}\n"
+        match self {
+            Self::MalformedEntry(line) => write!(fmt,
+                "malformed entry {:?}", line),
+            Self::MismatchedHashLength(s_c) => write!(fmt,
+                "entry hash is {} bytes but header declares {}-byte function",
+                s_c.stored(), s_c.computed()),
+            Self::MalformedName(name) => write!(fmt,
+                "unable to unquote name {}", name),
+            Self::UnsupportedEmptyDir => write!(fmt,
+                "empty directory entry not supported by this hash file"),
+            Self::MissingHashList => write!(fmt,
+                "file list is not followed by a hash list"),
+            Self::UnknownFileID(id) => write!(fmt,
+                "entry has file id {} not in the file list", id),
+            Self::NonMonotonicFileID(id, prev_id) => write!(fmt,
+                "entry has file id {} after entries for file id {}", id, prev_id),
+            Self::MissingBlockRanges(id, range, count) => write!(fmt,
+                "file id {} is missing {} entries starting at block range {}",
+                id, count, range),
+            Self::UnexpectedBlockRange(id, range) => write!(fmt,
+                "file id {} has unexpected entry for block range {}", id, range),
+            Self::MismatchedByteRange(id, s_c) => {
+                write!(fmt, concat!("file id {} has mismatched byte range in entry:\n",
+                    "  stored:   {}\n",
+                    "  computed: {}"),
+                    id, s_c.stored(), s_c.computed())
+            },
+            Self::MissingMetadataEntry(id) => write!(fmt,
+                "file id {} is missing its metadata entry", id),
+            Self::UnknownSectionMarker(line) => write!(fmt,
+                "section marker {:?} does not name a listed file", line),
+            Self::MisplacedSectionMarker(id) => write!(fmt,
+                "section marker for file id {} is not followed by its entries", id),
+            Self::MissingSectionMarker(id) => write!(fmt,
+                "entries of file id {} do not start with a section marker", id),
+            Self::ReadError(kind) => write!(fmt, "read error: {}", kind)
+        }
+    }
+}
+impl std::error::Error for LintIssue {}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum ParseError {
+    VersionReadError,
+    MalformedVersionLine,
+    MalformedVersion(String),
+    UnsupportedVersion(Version),
+    ParamReadError,
+    MissingList,
+    UnsupportedComment(Version),
+    MalformedComment(String),
+    UnsupportedStructureHash(Version),
+    MalformedStructureHash(String),
+    UnsupportedFeatures(Version),
+    UnknownFeature(String),
+    BadParameters(Vec<HeaderParsingErr>),
+    BadFooter(FileChecksumErr),
+    BadParity(ParityErr),
+    // 1-indexed line number and the line, for lines after the header
+    MalformedFileEntry(usize, String),
+    MalformedEntry(usize, String, HashExtractionError),
+    // Names a file that is not listed, or is not followed by an entry of its file
+    MalformedSectionMarker(usize, String),
+    MissingHashList,
+    ReadError(io::ErrorKind)
+}
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> Self {
+        Self::ReadError(err.kind())
+    }
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Will be printed as "Error: {err}\n"
+        match self {
+            Self::VersionReadError => write!(fmt, "unable to read in version line"),
+            Self::MalformedVersionLine => write!(fmt,
+                "hash file is malformed: unable to parse version line"),
+            Self::MalformedVersion(vers) => write!(fmt,
+                "hash file has malformed version {}", vers),
+            Self::UnsupportedVersion(vers) => write!(fmt,
+                "hash file has unsupported version {}", vers),
+            Self::ParamReadError => write!(fmt, "unable to read in parameter line"),
+            Self::MissingList => write!(fmt,
+                "hash file is malformed: file should have file list or hash list"),
+            Self::UnsupportedComment(vers) => write!(fmt,
+                "hash file version {} does not support comments", vers),
+            Self::MalformedComment(comment) => write!(fmt,
+                "hash file has malformed comment {}", comment),
+            Self::UnsupportedStructureHash(vers) => write!(fmt,
+                "hash file version {} does not support structure hashes", vers),
+            Self::MalformedStructureHash(line) => write!(fmt,
+                "hash file has malformed structure hash line {}", line),
+            Self::UnsupportedFeatures(vers) => write!(fmt,
+                "hash file version {} does not support a features line", vers),
+            Self::UnknownFeature(feature) => write!(fmt,
+                "hash file needs format feature {}, which this version does not support", feature),
+            Self::BadParameters(errors) => {
+                let error_strs: Vec<String> = errors.iter()
+                    .map(HeaderParsingErr::to_string).collect();
+                write!(fmt, "{}", error_strs.join("; "))
+            },
+            Self::BadFooter(err) => write!(fmt, "{}", err),
+            Self::BadParity(err) => write!(fmt, "{}", err),
+            Self::MalformedFileEntry(line_number, line) => write!(fmt,
+                "line {}: encountered malformed file entry {:?}", line_number, line),
+            Self::MalformedEntry(line_number, line, err) => write!(fmt,
+                "line {}: {} in entry {:?}", line_number, err, line),
+            Self::MalformedSectionMarker(line_number, line) => write!(fmt,
+                "line {}: section marker {:?} does not start the entries of the file it names",
+                line_number, line),
+            Self::MissingHashList => write!(fmt,
+                "file list is not followed by a hash list"),
+            Self::ReadError(kind) => write!(fmt, "read error: {}", kind)
+        }
+    }
+}
+impl std::error::Error for ParseError {}
+
+// generate-hash --tee reads stdin and writes a copy of it at the same time
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum TeeErr {
+    ReadError(io::ErrorKind),
+    WriteError(io::ErrorKind)
+}
+impl fmt::Display for TeeErr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Will be printed as "Error copying stdin to file {name}: {err}\n"
+        match self {
+            Self::ReadError(kind) => write!(fmt, "read error on stdin: {}", kind),
+            Self::WriteError(kind) => write!(fmt, "write error: {}", kind)
+        }
+    }
+}
+impl std::error::Error for TeeErr {}
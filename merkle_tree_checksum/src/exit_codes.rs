@@ -0,0 +1,69 @@
+#![forbid(unsafe_code)]
+
+use strum_macros::{IntoStaticStr, VariantArray};
+
+// Several variants share a numeric code, so codes are not discriminants
+// Stability: do not change the names or codes once committed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(IntoStaticStr, VariantArray)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub(crate) enum ExitCode {
+    Success,
+    CmdlineErr,
+    DataReadErr,
+    GenWriteErr,
+    VerifReadErr,
+    VerifBadHeaderErr,
+    VerifBadEntryErr,
+    // Files or directories in the hash file are missing or unreadable
+    PreHashErr,
+    Interrupted,
+    // Verification stopped early at --max-total-bytes, with no failures before then
+    BudgetExhausted
+}
+impl ExitCode {
+    #[inline]
+    pub const fn code(self) -> i32 {
+        match self {
+            Self::Success => 0,
+            Self::CmdlineErr => 1,
+            Self::DataReadErr => 2,
+            Self::GenWriteErr => 101, // Same exitcode as panic
+            Self::VerifReadErr => 101, // Same exitcode as panic
+            Self::VerifBadHeaderErr => 1,
+            Self::VerifBadEntryErr => 3,
+            Self::PreHashErr => 1,
+            Self::Interrupted => 130, // Same exitcode as shells use for SIGINT
+            Self::BudgetExhausted => 4
+        }
+    }
+    // Lines of the form NAME=code, for --print-exit-codes
+    pub fn listing() -> String {
+        <Self as strum::VariantArray>::VARIANTS.iter()
+            .map(|variant| {
+                let name: &str = variant.into();
+                format!("{}={}\n", name, variant.code())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_code_listing() {
+        assert_eq!(ExitCode::listing(), concat!(
+            "SUCCESS=0\n",
+            "CMDLINE_ERR=1\n",
+            "DATA_READ_ERR=2\n",
+            "GEN_WRITE_ERR=101\n",
+            "VERIF_READ_ERR=101\n",
+            "VERIF_BAD_HEADER_ERR=1\n",
+            "VERIF_BAD_ENTRY_ERR=3\n",
+            "PRE_HASH_ERR=1\n",
+            "INTERRUPTED=130\n",
+            "BUDGET_EXHAUSTED=4\n"));
+    }
+}
@@ -0,0 +1,114 @@
+#![forbid(unsafe_code)]
+// The extract subcommand, which splits the hashes of one file out of a hash file
+
+use crate::exit_codes::ExitCode;
+use crate::format_functions::unescape_chars;
+use crate::hash_file_input::read_parsed_hash_file;
+use crate::hash_file_parser::{HashEntry, ParsedHashFile};
+use crate::hash_sink::{HashSink, SinkHeader, TextSink};
+use crate::output_files::write_hash_output;
+
+use clap::{Command, Arg, ArgAction, ArgMatches};
+
+use std::io::Write;
+use std::path::PathBuf;
+
+pub(crate) const EXTRACT_CMD_NAME: &str = "extract";
+
+pub(crate) fn extract_command() -> Command {
+    Command::new(EXTRACT_CMD_NAME)
+        .about("Write a hash file for one of the files in a hash file")
+        .long_about(concat!("Write a standalone hash file with only the ",
+            "hashes of one file from a hash file of several files, ",
+            "so that it can be verified on its own. Its entries are ",
+            "renumbered to start from 0. The structure hash and combined ",
+            "root cover every file, so they are left out."))
+        .arg(Arg::new("file").long("file")
+            .action(ArgAction::Set)
+            .required(true)
+            .help("Name of the file to extract the hashes of")
+            .long_help(concat!("Name of the file to extract the hashes of, ",
+                "as given when hashing. Concatenated entries are named ",
+                "by their parts joined with \" + \".")))
+        .arg(Arg::new("output").long("output").short('o')
+            .action(ArgAction::Set)
+            .help("Output file, instead of stdout"))
+        .arg(Arg::new("overwrite").long("overwrite")
+            .action(ArgAction::SetTrue)
+            .requires("output")
+            .help("Overwrite the output file if it already exists"))
+        .arg(Arg::new("FILE").required(true)
+            .action(ArgAction::Set)
+            .help("Hash file to extract from"))
+}
+
+// Writes a standalone hash file with only the hashes of one file
+// The structure hash and combined root cover every file, so they are left out
+fn extract_file_hashes<W: Write+std::fmt::Debug>(writer: W, parsed_file: &ParsedHashFile,
+        file_name: &str) -> Result<(), ExitCode> {
+    let display_names = |names: &[String]| -> String {
+        names.iter().map(|name| unescape_chars(name)).collect::<Vec<_>>().join(" + ")
+    };
+    let header = &parsed_file.header;
+    let mut sink = TextSink::new(writer, false, false);
+    let sink_header = SinkHeader {
+        tree_params: &header.tree_params,
+        comments: &header.comments,
+        structure_hash: None,
+        file_entries: &[],
+        dir_list: &[],
+        short_output: false,
+        sectioned: header.sectioned
+    };
+    let write_result = match header.is_short_hash {
+        true => {
+            let Some((hash, names)) = parsed_file.entries.iter()
+                .find_map(|entry| match entry {
+                    HashEntry::Short(hash, names) if display_names(names) == file_name =>
+                        Some((hash, names)),
+                    _ => None
+                }) else {
+                eprintln!("Error: file {} is not in the hash file", file_name);
+                return Err(ExitCode::CmdlineErr);
+            };
+            let paths: Vec<PathBuf> = names.iter().map(PathBuf::from).collect();
+            sink.write_header(&SinkHeader {short_output: true, ..sink_header})
+                .and_then(|_| sink.write_short(hash.as_ref(), &paths))
+        },
+        false => {
+            let Some(file_index) = parsed_file.files.iter()
+                    .position(|file_entry| display_names(&file_entry.names) == file_name) else {
+                eprintln!("Error: file {} is not in the hash file", file_name);
+                return Err(ExitCode::CmdlineErr);
+            };
+            let file_entry = &parsed_file.files[file_index];
+            let paths: Vec<PathBuf> = file_entry.names.iter().map(PathBuf::from).collect();
+            let file_entries = [(paths.as_slice(), file_entry.file_len, file_entry.hashed_len)];
+            // Entries of the file are renumbered to index 0
+            sink.write_header(&SinkHeader {file_entries: &file_entries, ..sink_header})
+                .and_then(|_| parsed_file.entries.iter().try_for_each(|entry| match entry {
+                    HashEntry::Long(entry_index, hash_range) if *entry_index == file_index =>
+                        sink.write_entry(0, hash_range, 0),
+                    HashEntry::Metadata(entry_index, hash) if *entry_index == file_index =>
+                        sink.write_metadata(0, hash),
+                    _ => Ok(())
+                }))
+        }
+    };
+    write_result.and_then(|_| Box::new(sink).finish()).map_err(|err| {
+        eprintln!("Error writing hash file: {}", err);
+        ExitCode::GenWriteErr
+    })
+}
+
+// Writes the hashes of the file given with --file to --output, or to stdout
+pub(crate) fn run_extract(cmd_matches: &ArgMatches) -> ExitCode {
+    let file_name = cmd_matches.get_one::<String>("FILE").unwrap();
+    let parsed_file = match read_parsed_hash_file(file_name) {
+        Ok(parsed_file) => parsed_file,
+        Err(exit_code) => return exit_code
+    };
+    let extracted_name = cmd_matches.get_one::<String>("file").unwrap();
+    write_hash_output(cmd_matches,
+        |writer| extract_file_hashes(writer, &parsed_file, extracted_name))
+}
@@ -0,0 +1,149 @@
+#![forbid(unsafe_code)]
+// Optional last line of a hash file with a CRC32 of every byte before it
+
+use crate::error_types::FileChecksumErr;
+use crate::utils::StoredAndComputed;
+
+use crc32fast::Hasher as Crc32Hasher;
+
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+pub(crate) const FOOTER_PREFIX: &str = "File-Checksum: ";
+// A footer line always fits in this many bytes, including a CRLF line ending
+const FOOTER_MAX_LEN: usize = 32;
+
+// Computes the checksum of everything written through it
+pub(crate) struct ChecksumWriter<W: Write> {
+    writer: W,
+    hasher: Crc32Hasher,
+    write_footer: bool
+}
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(writer: W, write_footer: bool) -> Self {
+        Self {writer, hasher: Crc32Hasher::new(), write_footer}
+    }
+    // Writes the footer, if enabled, and flushes the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.write_footer {
+            let checksum = self.hasher.clone().finalize();
+            writeln!(self.writer, "{}{:08x}", FOOTER_PREFIX, checksum)?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes_written = self.writer.write(buf)?;
+        self.hasher.update(&buf[..bytes_written]);
+        Ok(bytes_written)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+// crc32fast::Hasher does not implement Debug
+impl<W: Write+fmt::Debug> fmt::Debug for ChecksumWriter<W> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ChecksumWriter")
+            .field("writer", &self.writer)
+            .field("write_footer", &self.write_footer)
+            .finish_non_exhaustive()
+    }
+}
+
+// Length of the hash file without its footer, after checking the checksum
+// Hash files without a footer are not checked, and their full length is returned
+// The reader is left at the start of the file
+pub(crate) fn check_footer<R: Read+Seek>(reader: &mut R) -> Result<u64, FileChecksumErr> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    let tail_start = file_len.saturating_sub(FOOTER_MAX_LEN as u64);
+    reader.seek(SeekFrom::Start(tail_start))?;
+    let mut tail: Vec<u8> = Vec::with_capacity(FOOTER_MAX_LEN);
+    reader.read_to_end(&mut tail)?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let tail_trimmed = tail.strip_suffix(b"\n").unwrap_or(&tail);
+    let tail_trimmed = tail_trimmed.strip_suffix(b"\r").unwrap_or(tail_trimmed);
+    let last_line_start = match tail_trimmed.iter().rposition(|byte| *byte == b'\n') {
+        Some(newline_pos) => newline_pos + 1,
+        // The last line is too long to be a footer
+        None if tail_start > 0 => return Ok(file_len),
+        None => 0
+    };
+    let last_line = &tail_trimmed[last_line_start..];
+    let Some(checksum_hex) = last_line.strip_prefix(FOOTER_PREFIX.as_bytes()) else {
+        return Ok(file_len);
+    };
+    let stored_checksum = match std::str::from_utf8(checksum_hex) {
+        Ok(hex_str) if hex_str.len() == 8
+                && hex_str.bytes().all(|digit| digit.is_ascii_hexdigit()) =>
+            u32::from_str_radix(hex_str, 16).unwrap(),
+        _ => return Err(FileChecksumErr::MalformedFooter(
+            String::from_utf8_lossy(last_line).into_owned()))
+    };
+
+    let content_len = tail_start + last_line_start as u64;
+    let mut hasher = Crc32Hasher::new();
+    let mut buf = vec![0u8; 1 << 16];
+    let mut content = reader.by_ref().take(content_len);
+    loop {
+        let bytes_read = content.read(&mut buf)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buf[..bytes_read]);
+    }
+    reader.seek(SeekFrom::Start(0))?;
+    let computed_checksum = hasher.finalize();
+    if stored_checksum != computed_checksum {
+        return Err(FileChecksumErr::MismatchedChecksum(
+            StoredAndComputed::new(stored_checksum, computed_checksum)));
+    }
+    Ok(content_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn with_footer(contents: &[u8]) -> Vec<u8> {
+        let mut writer = ChecksumWriter::new(Vec::new(), true);
+        writer.write_all(contents).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn footer_round_trip() {
+        let contents = b"Hashes:\n  0 0123abcd \"file\"\n";
+        let hash_file = with_footer(contents);
+        let footer = format!("File-Checksum: {:08x}\n", crc32fast::hash(contents));
+        assert_eq!([&contents[..], footer.as_bytes()].concat(), hash_file);
+        let content_len = check_footer(&mut Cursor::new(&hash_file)).unwrap();
+        assert_eq!(contents.len() as u64, content_len);
+    }
+
+    #[test]
+    fn missing_footer_not_checked() {
+        let contents = b"Hashes:\n  0 0123abcd \"file\"\n";
+        let content_len = check_footer(&mut Cursor::new(contents)).unwrap();
+        assert_eq!(contents.len() as u64, content_len);
+    }
+
+    #[test]
+    fn damaged_contents_detected() {
+        let mut hash_file = with_footer(b"Hashes:\n  0 0123abcd \"file\"\n");
+        hash_file[12] = b'f';
+        assert!(matches!(check_footer(&mut Cursor::new(&hash_file)),
+            Err(FileChecksumErr::MismatchedChecksum(_))));
+    }
+
+    #[test]
+    fn malformed_footer_detected() {
+        let hash_file = b"Hashes:\nFile-Checksum: xyz\n";
+        assert!(matches!(check_footer(&mut Cursor::new(hash_file)),
+            Err(FileChecksumErr::MalformedFooter(_))));
+    }
+}
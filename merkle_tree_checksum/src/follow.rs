@@ -0,0 +1,112 @@
+#![forbid(unsafe_code)]
+// Leaves already verified by verify-hash --follow, so that updates only read appended data
+
+use crate::utils::TreeParams;
+
+use merkle_tree::HashData;
+
+// Whole leaves keep their hashes when data is appended, unlike a partial last leaf
+// and the internal nodes, which are checked again on every update
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct VerifiedPrefix {
+    tree_params: TreeParams,
+    names: Vec<String>,
+    leaf_hashes: Vec<HashData<64>>,
+    // Length of the file when it was last verified
+    file_len: u64
+}
+impl VerifiedPrefix {
+    pub fn new(tree_params: TreeParams, names: Vec<String>) -> Self {
+        Self {tree_params, names, leaf_hashes: Vec::new(), file_len: 0}
+    }
+    pub fn file_len(&self) -> u64 {
+        self.file_len
+    }
+    // Number of whole leaves verified so far
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_hashes.len()
+    }
+    // Bytes covered by the whole leaves verified so far
+    pub fn byte_len(&self) -> u64 {
+        self.leaf_hashes.len() as u64 * u64::from(self.tree_params.block_size)
+    }
+    // Whether an updated hash file only adds to the verified leaves,
+    // rather than having been rewritten with other parameters, names, or leaves
+    pub fn is_extended_by(&self, tree_params: &TreeParams, names: &[String],
+            stored_leaves: &[HashData<64>]) -> bool {
+        self.tree_params == *tree_params && self.names == names
+            && stored_leaves.get(..self.leaf_hashes.len()) == Some(&self.leaf_hashes[..])
+    }
+    // Records the leaves of a file of file_len bytes once they are all verified,
+    // leaving out a partial last leaf
+    pub fn extend(&mut self, stored_leaves: &[HashData<64>], file_len: u64) {
+        let whole_leaf_count = (file_len / u64::from(self.tree_params.block_size)) as usize;
+        if whole_leaf_count > self.leaf_hashes.len() {
+            self.leaf_hashes.extend_from_slice(
+                &stored_leaves[self.leaf_hashes.len()..whole_leaf_count]);
+        }
+        self.file_len = file_len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_enum::HashFunctions;
+    use merkle_tree::NodeEncoding;
+
+    fn test_tree_params(block_size: u32) -> TreeParams {
+        TreeParams {
+            block_size,
+            branch_factor: 2,
+            hash_function: HashFunctions::sha256,
+            node_encoding: NodeEncoding::Plain,
+            salted: false,
+            pad_tree: false,
+            unbalanced: false,
+            duplicate_last: false,
+            metadata_only: false,
+            trim_zeros: false,
+            region_offset: None,
+            absolute_ranges: false,
+            leaves_and_root: false,
+            content_defined: false,
+            normalize_eol: false,
+            relative_names: false,
+            nominal_ranges: false,
+            stride: None,
+            filtered: false
+        }
+    }
+    fn leaf(byte: u8) -> HashData<64> {
+        HashData::try_new(&[byte; 32]).unwrap()
+    }
+
+    #[test]
+    fn partial_leaf_not_recorded() {
+        let tree_params = test_tree_params(4);
+        let names = vec!["log".to_owned()];
+        let mut prefix = VerifiedPrefix::new(tree_params, names.clone());
+        let stored_leaves = [leaf(1), leaf(2), leaf(3)];
+        prefix.extend(&stored_leaves, 10);
+        assert_eq!(prefix.leaf_count(), 2);
+        assert_eq!(prefix.byte_len(), 8);
+        assert_eq!(prefix.file_len(), 10);
+        // The partial last leaf changes as the file grows
+        assert!(prefix.is_extended_by(&tree_params, &names, &[leaf(1), leaf(2), leaf(4)]));
+        prefix.extend(&[leaf(1), leaf(2), leaf(4), leaf(5)], 16);
+        assert_eq!(prefix.leaf_count(), 4);
+    }
+    #[test]
+    fn rewrites_detected() {
+        let tree_params = test_tree_params(4);
+        let names = vec!["log".to_owned()];
+        let mut prefix = VerifiedPrefix::new(tree_params, names.clone());
+        prefix.extend(&[leaf(1), leaf(2)], 8);
+        assert!(!prefix.is_extended_by(&tree_params, &names, &[leaf(1), leaf(3)]));
+        assert!(!prefix.is_extended_by(&tree_params, &names, &[leaf(1)]));
+        assert!(!prefix.is_extended_by(&tree_params, &["other".to_owned()],
+            &[leaf(1), leaf(2)]));
+        assert!(!prefix.is_extended_by(&test_tree_params(8), &names, &[leaf(1), leaf(2)]));
+    }
+}
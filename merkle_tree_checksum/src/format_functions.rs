@@ -48,4 +48,29 @@ pub(crate) fn escape_chars(string: &str) -> String {
             l => l.to_string()
         }
     }).collect()
-}
\ No newline at end of file
+}
+
+// Inverse of escape_chars, for names that were unquoted already
+pub(crate) fn unescape_chars(string: &str) -> String {
+    string.replace(r"\t", "\t")
+        .replace(r"\r", "\r")
+        .replace(r"\n", "\n")
+}
+// Hex digits of a hash as written to hash files
+// Parsing accepts either case, so uppercase is only for other tools reading them
+pub(crate) fn hash_hex(hash: &[u8], uppercase: bool) -> String {
+    match uppercase {
+        true => hex::encode_upper(hash),
+        false => hex::encode(hash)
+    }
+}
+// Line in the format of sha256sum and similar tools
+// As with those tools, names with a backslash or newline are escaped,
+// and the line starts with a backslash to say so
+pub(crate) fn checksum_line(hash_hex: &str, name: &str) -> String {
+    match name.contains(['\\', '\n', '\r']) {
+        true => format!("\\{}  {}", hash_hex, name.replace('\\', r"\\")
+            .replace('\n', r"\n").replace('\r', r"\r")),
+        false => format!("{}  {}", hash_hex, name)
+    }
+}
@@ -0,0 +1,61 @@
+#![forbid(unsafe_code)]
+// Hash file format version, tracked separately from the tool version
+
+use semver::Version;
+
+// Written into the prelude line of generated hash files
+// Bump this whenever the hash file format changes
+pub(crate) const FORMAT_VERSION: Version = Version::new(0, 10, 0);
+
+// Oldest format version that can still be read
+pub(crate) const MIN_READABLE_VERSION: Version = Version::new(0, 5, 0);
+
+// Hash files older than this cannot contain empty directory entries
+pub(crate) const EMPTY_DIR_MIN_VERSION: Version = Version::new(0, 7, 0);
+
+// Hash files older than this cannot contain comment lines
+pub(crate) const COMMENT_MIN_VERSION: Version = Version::new(0, 8, 0);
+
+// Hash files older than this cannot contain structure hash and root lines
+pub(crate) const STRUCTURE_HASH_MIN_VERSION: Version = Version::new(0, 9, 0);
+
+// Hash files older than this cannot contain a features line
+pub(crate) const FEATURES_MIN_VERSION: Version = Version::new(0, 10, 0);
+
+pub(crate) const FEATURES_PREFIX: &str = "Features: ";
+
+// Format features that hash files can declare on their features line
+// Hash files declaring any other feature are rejected instead of misread,
+// so add every feature that changes how a hash file must be verified
+pub(crate) const KNOWN_FEATURES: &[&str] = &["salt", "node-encoding", "padded-tree",
+    "unbalanced", "duplicate-last", "metadata-only", "trim-zeros", "region",
+    "absolute-ranges", "leaves-and-root", "content-defined", "normalize-eol",
+    "structure-hash", "relative-names", "nominal-ranges", "stride", "sectioned",
+    "filtered"];
+
+#[inline]
+pub(crate) fn is_readable_version(version: &Version) -> bool {
+    *version >= MIN_READABLE_VERSION && *version <= FORMAT_VERSION
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readable_version_range() {
+        assert!(is_readable_version(&Version::new(0, 5, 0)));
+        assert!(is_readable_version(&FORMAT_VERSION));
+        assert!(!is_readable_version(&Version::new(0, 4, 9)));
+        let next_patch = Version::new(FORMAT_VERSION.major,
+            FORMAT_VERSION.minor, FORMAT_VERSION.patch + 1);
+        assert!(!is_readable_version(&next_patch));
+    }
+
+    #[test]
+    fn known_features_unique() {
+        for (index, feature) in KNOWN_FEATURES.iter().enumerate() {
+            assert!(!KNOWN_FEATURES[index+1..].contains(feature), "{} is repeated", feature);
+        }
+    }
+}
@@ -1,17 +1,117 @@
 use digest::Digest;
-use crate::crc32_utils::Crc32;
-use sha2::{Sha224, Sha256, Sha384, Sha512, Sha512_224, Sha512_256};
-use sha3::{Sha3_224, Sha3_256, Sha3_384, Sha3_512};
-use blake2::{Blake2b512, Blake2s256};
-use blake3::Hasher as Blake3;
+use merkle_tree::{HashData, MAX_HASH_LEN};
 
 use strum::VariantArray;
 use strum_macros::{IntoStaticStr, EnumString, VariantArray, FromRepr};
 
+use clap::{Arg, Command};
+use clap::builder::{EnumValueParser, PossibleValue, TypedValueParser};
+use clap::error::{ContextKind, ContextValue, ErrorKind};
+
 use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::io::{self, Read};
 
 use std::fmt;
 
+// Digest type of each hash function, named in with_digest!
+pub(crate) mod digests {
+    pub(crate) use crate::crc32_utils::{Crc32, Crc32Le};
+    pub(crate) use crate::sha512t_utils::Sha512_160;
+    pub(crate) use crate::k12_utils::K12;
+    pub(crate) use crate::ascon_utils::AsconHash256;
+    pub(crate) use sha2::{Sha224, Sha256, Sha384, Sha512, Sha512_224, Sha512_256};
+    pub(crate) use sha3::{Sha3_224, Sha3_256, Sha3_384, Sha3_512};
+    pub(crate) use blake2::{Blake2b512, Blake2s256};
+    // Leaves of large blocks get hashed across threads with the blake3_rayon feature
+    #[cfg(feature = "blake3_rayon")]
+    pub(crate) use crate::blake3_utils::Blake3Rayon as Blake3;
+    #[cfg(not(feature = "blake3_rayon"))]
+    pub(crate) use blake3::Hasher as Blake3;
+}
+
+// Evaluates $body with the type $digest set to the digest of the hash function,
+// so that generic code is written once for every hash function
+macro_rules! with_digest {
+    ($hash_enum:expr, $digest:ident => $body:expr) => {
+        match $hash_enum {
+            $crate::hash_enum::HashFunctions::crc32 => {
+                type $digest = $crate::hash_enum::digests::Crc32;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::crc32le => {
+                type $digest = $crate::hash_enum::digests::Crc32Le;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::sha224 => {
+                type $digest = $crate::hash_enum::digests::Sha224;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::sha256 => {
+                type $digest = $crate::hash_enum::digests::Sha256;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::sha384 => {
+                type $digest = $crate::hash_enum::digests::Sha384;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::sha512 => {
+                type $digest = $crate::hash_enum::digests::Sha512;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::sha512_224 => {
+                type $digest = $crate::hash_enum::digests::Sha512_224;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::sha512_256 => {
+                type $digest = $crate::hash_enum::digests::Sha512_256;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::sha512_160 => {
+                type $digest = $crate::hash_enum::digests::Sha512_160;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::sha3_224 => {
+                type $digest = $crate::hash_enum::digests::Sha3_224;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::sha3_256 => {
+                type $digest = $crate::hash_enum::digests::Sha3_256;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::sha3_384 => {
+                type $digest = $crate::hash_enum::digests::Sha3_384;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::sha3_512 => {
+                type $digest = $crate::hash_enum::digests::Sha3_512;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::blake2b_512 => {
+                type $digest = $crate::hash_enum::digests::Blake2b512;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::blake2s_256 => {
+                type $digest = $crate::hash_enum::digests::Blake2s256;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::blake3 => {
+                type $digest = $crate::hash_enum::digests::Blake3;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::k12 => {
+                type $digest = $crate::hash_enum::digests::K12;
+                $body
+            },
+            $crate::hash_enum::HashFunctions::ascon => {
+                type $digest = $crate::hash_enum::digests::AsconHash256;
+                $body
+            }
+        }
+    };
+}
+pub(crate) use with_digest;
+
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 #[derive(IntoStaticStr, EnumString, VariantArray, FromRepr, strum_macros::Display)]
 #[allow(non_camel_case_types)]
@@ -26,6 +126,8 @@ use std::fmt;
 // Stability: do not change these values once committed
 pub enum HashFunctions {
     crc32 = 0x40,
+    // Same CRC32 with its bytes in little-endian order, as other tools print it
+    crc32le = 0x41,
     // For sha2 family: set bit 0x04 to indicate sha512 base
     sha224 = 0xc0,
     sha256 = 0xc1,
@@ -35,6 +137,8 @@ pub enum HashFunctions {
     sha512_224 = 0xc6,
     #[strum(to_string = "sha512_256", serialize = "sha512trunc256")]
     sha512_256 = 0xc7,
+    // Only 80 bits of collision resistance, so not recommended
+    sha512_160 = 0x8f,
     sha3_224 = 0xc8,
     sha3_256 = 0xc9,
     sha3_384 = 0xca,
@@ -43,7 +147,11 @@ pub enum HashFunctions {
     blake2b_512 = 0xcc,
     #[strum(to_string = "blake2s256", serialize = "blake2s")]
     blake2s_256 = 0xcd,
-    blake3 = 0xce
+    blake3 = 0xce,
+    // KangarooTwelve, with its output fixed at 256 bits
+    k12 = 0xcf,
+    // Ascon-Hash256 from NIST SP 800-232
+    ascon = 0xd0
 }
 impl clap::ValueEnum for HashFunctions {
     fn value_variants<'a>() -> &'a [Self] {
@@ -75,25 +183,64 @@ impl fmt::Display for HashFunctionFromUIntErr {
 }
 impl std::error::Error for HashFunctionFromUIntErr {}
 
+// Hashes are stored as HashData<64>, so longer digests would not fit
+const _: () = assert!(MAX_HASH_LEN <= 64);
+
 impl HashFunctions {
+    // merkle_tree panics partway through hashing with a longer digest
+    pub fn hash_len_supported(&self) -> bool {
+        self.hash_len() <= MAX_HASH_LEN
+    }
     #[inline]
     pub fn hash_len(&self) -> usize {
+        with_digest!(*self, D => D::output_size())
+    }
+    // Name and OID for identifying the hash function outside of this program
+    // Functions without a registered OID have None, including the CRCs
+    pub fn algorithm_id(&self) -> (&'static str, Option<&'static str>) {
         match self {
-            HashFunctions::crc32 => Crc32::output_size(),
-            HashFunctions::sha224 => Sha224::output_size(),
-            HashFunctions::sha256 => Sha256::output_size(),
-            HashFunctions::sha384 => Sha384::output_size(),
-            HashFunctions::sha512 => Sha512::output_size(),
-            HashFunctions::sha512_224 => Sha512_224::output_size(),
-            HashFunctions::sha512_256 => Sha512_256::output_size(),
-            HashFunctions::sha3_224 => Sha3_224::output_size(),
-            HashFunctions::sha3_256 => Sha3_256::output_size(),
-            HashFunctions::sha3_384 => Sha3_384::output_size(),
-            HashFunctions::sha3_512 => Sha3_512::output_size(),
-            HashFunctions::blake2b_512 => Blake2b512::output_size(),
-            HashFunctions::blake2s_256 => Blake2s256::output_size(),
-            HashFunctions::blake3 => Blake3::output_size()
+            HashFunctions::crc32 => ("CRC-32/ISO-HDLC", None),
+            HashFunctions::crc32le => ("CRC-32/ISO-HDLC (little-endian)", None),
+            HashFunctions::sha224 => ("SHA-224", Some("2.16.840.1.101.3.4.2.4")),
+            HashFunctions::sha256 => ("SHA-256", Some("2.16.840.1.101.3.4.2.1")),
+            HashFunctions::sha384 => ("SHA-384", Some("2.16.840.1.101.3.4.2.2")),
+            HashFunctions::sha512 => ("SHA-512", Some("2.16.840.1.101.3.4.2.3")),
+            HashFunctions::sha512_224 => ("SHA-512/224", Some("2.16.840.1.101.3.4.2.5")),
+            HashFunctions::sha512_256 => ("SHA-512/256", Some("2.16.840.1.101.3.4.2.6")),
+            HashFunctions::sha512_160 => ("SHA-512/160", None),
+            HashFunctions::sha3_224 => ("SHA3-224", Some("2.16.840.1.101.3.4.2.7")),
+            HashFunctions::sha3_256 => ("SHA3-256", Some("2.16.840.1.101.3.4.2.8")),
+            HashFunctions::sha3_384 => ("SHA3-384", Some("2.16.840.1.101.3.4.2.9")),
+            HashFunctions::sha3_512 => ("SHA3-512", Some("2.16.840.1.101.3.4.2.10")),
+            // From RFC 7693
+            HashFunctions::blake2b_512 => ("BLAKE2b-512", Some("1.3.6.1.4.1.1722.12.2.1.16")),
+            HashFunctions::blake2s_256 => ("BLAKE2s-256", Some("1.3.6.1.4.1.1722.12.2.2.8")),
+            HashFunctions::blake3 => ("BLAKE3", None),
+            HashFunctions::k12 => ("KangarooTwelve-256", None),
+            HashFunctions::ascon => ("Ascon-Hash256", None)
+        }
+    }
+    // Plain hash of data, for hashes outside of a Merkle tree
+    pub fn digest(&self, data: &[u8]) -> HashData<64> {
+        // Reading from a slice never fails
+        self.digest_reader(data).unwrap()
+    }
+    // Plain hash of everything read from reader, for --coreutils
+    pub fn digest_reader<R: Read>(&self, reader: R) -> io::Result<HashData<64>> {
+        fn digest_with<D: Digest, R: Read>(mut reader: R) -> io::Result<HashData<64>> {
+            let mut digest_obj = D::new();
+            let mut buf = vec![0x00; 64*1024];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(read_len) => digest_obj.update(&buf[..read_len]),
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err)
+                }
+            }
+            Ok(HashData::try_new(&digest_obj.finalize()).unwrap())
         }
+        with_digest!(*self, D => digest_with::<D, _>(reader))
     }
 }
 // Future use for binary files (and Discriminant<T> lacks stability guarantees)
@@ -110,6 +257,63 @@ impl TryFrom<u8> for HashFunctions {
     }
 }
 
+// Levenshtein distance, counted in chars
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut prev_row: Vec<usize> = (0..=b_chars.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, b_char) in b_chars.iter().enumerate() {
+            let substitution = prev_row[j] + usize::from(a_char != *b_char);
+            row.push(substitution.min(prev_row[j + 1] + 1).min(row[j] + 1));
+        }
+        prev_row = row;
+    }
+    prev_row[b_chars.len()]
+}
+
+// Closest hash name or alias to an unrecognized name, if any is close enough
+// clap's own suggestion does not weigh the aliases, so sha512trunc gets sha512
+pub(crate) fn closest_hash_name(name: &str) -> Option<String> {
+    let name = name.to_ascii_lowercase();
+    let valid_names: Vec<String> = HashFunctions::VARIANTS.iter()
+        .filter_map(clap::ValueEnum::to_possible_value)
+        .flat_map(|value| value.get_name_and_aliases()
+            .map(String::from).collect::<Vec<_>>())
+        .collect();
+    // min_by_key keeps the first of equally close names
+    valid_names.into_iter()
+        .map(|valid_name| (edit_distance(&name, &valid_name), valid_name))
+        .min_by_key(|(distance, _)| *distance)
+        .filter(|(distance, _)| *distance <= name.chars().count() / 2)
+        .map(|(_, valid_name)| valid_name)
+}
+
+// EnumValueParser, with the suggestion replaced by closest_hash_name
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct HashFunctionParser;
+impl TypedValueParser for HashFunctionParser {
+    type Value = HashFunctions;
+
+    fn parse_ref(&self, cmd: &Command, arg: Option<&Arg>,
+            value: &OsStr) -> Result<Self::Value, clap::Error> {
+        EnumValueParser::<HashFunctions>::new().parse_ref(cmd, arg, value)
+            .map_err(|mut err| {
+                if err.kind() == ErrorKind::InvalidValue {
+                    if let Some(suggestion) = value.to_str().and_then(closest_hash_name) {
+                        err.insert(ContextKind::SuggestedValue,
+                            ContextValue::String(suggestion));
+                    }
+                }
+                err
+            })
+    }
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue>+'_>> {
+        Some(Box::new(HashFunctions::VARIANTS.iter()
+            .filter_map(clap::ValueEnum::to_possible_value)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -129,6 +333,25 @@ mod tests {
             HashFunctions::sha512_256);
     }
     #[test]
+    fn hash_lens_fit_hash_data() {
+        // Hashes are stored as HashData<64>, the same bound as MAX_HASH_LEN
+        for hash_function in HashFunctions::VARIANTS {
+            assert!(hash_function.hash_len() <= 64, "{} is too long", hash_function);
+            assert!(hash_function.hash_len_supported(), "{} is unsupported", hash_function);
+        }
+        assert_eq!(HashFunctions::k12.hash_len(), 32);
+        assert_eq!(HashFunctions::ascon.hash_len(), 32);
+    }
+    #[test]
+    fn algorithm_id_oids() {
+        assert_eq!(HashFunctions::sha256.algorithm_id(),
+            ("SHA-256", Some("2.16.840.1.101.3.4.2.1")));
+        assert_eq!(HashFunctions::sha3_512.algorithm_id(),
+            ("SHA3-512", Some("2.16.840.1.101.3.4.2.10")));
+        assert_eq!(HashFunctions::crc32.algorithm_id().1, None);
+        assert_eq!(HashFunctions::crc32le.algorithm_id().1, None);
+    }
+    #[test]
     fn hash_enum_blake2_backcompat() {
         assert_eq!(HashFunctions::from_str("blake2b").unwrap(),
             HashFunctions::blake2b_512);
@@ -139,4 +362,11 @@ mod tests {
         assert_eq!(HashFunctions::from_str("blake2s256").unwrap(),
             HashFunctions::blake2s_256);
     }
+    #[test]
+    fn near_miss_hash_names() {
+        assert_eq!(closest_hash_name("sha512trunc").as_deref(), Some("sha512trunc224"));
+        assert_eq!(closest_hash_name("SHA3-256").as_deref(), Some("sha3_256"));
+        assert_eq!(closest_hash_name("blak3").as_deref(), Some("blake3"));
+        assert_eq!(closest_hash_name("md5"), None);
+    }
 }
@@ -0,0 +1,128 @@
+#![forbid(unsafe_code)]
+// Opening and parsing hash files for the subcommands that read them
+
+use crate::error_types::{FileChecksumErr, ParityErr, ParseError};
+use crate::exit_codes::ExitCode;
+use crate::file_checksum;
+use crate::hash_file_parser::{parse_hash_file, parse_hash_header, HashFileHeader, ParsedHashFile};
+use crate::jsonl::JsonlReader;
+use crate::parity::HashFileSource;
+use crate::read_adapters::TruncatedReader;
+use crate::section_markers::SectionedReader;
+use crate::tree_structure;
+
+use merkle_tree::HashData;
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+
+// Errors are printed here, so callers only need to return the exit code
+pub(crate) fn read_hash_header<R: BufRead>(reader: &mut R) -> Result<HashFileHeader, ExitCode> {
+    parse_hash_header(reader).map_err(|err| {
+        match &err {
+            ParseError::BadParameters(errors) => for error in errors {
+                eprintln!("Error: {}", error);
+            },
+            _ => eprintln!("Error: {}", err)
+        }
+        match err {
+            ParseError::VersionReadError | ParseError::ParamReadError
+                | ParseError::ReadError(_) => ExitCode::VerifReadErr,
+            _ => ExitCode::VerifBadHeaderErr
+        }
+    })
+}
+
+// JSON Lines hash files are read as if they were text hash files,
+// and section markers are left out
+pub(crate) type HashFileReader = JsonlReader<SectionedReader<BufReader<TruncatedReader<HashFileSource>>>>;
+
+// Opens a hash file, checking and leaving out its checksum footer if present
+// A last Root: line is also left out, as it is only checked by verify-hash
+pub(crate) fn open_hash_file(file_name: &str) -> Result<HashFileReader, ExitCode> {
+    open_hash_file_with_root(file_name).map(|(reader, _)| reader)
+}
+
+// Also returns the combined root of the last Root: line
+pub(crate) fn open_hash_file_with_root(file_name: &str)
+        -> Result<(HashFileReader, Option<HashData<64>>), ExitCode> {
+    let hash_file = match File::open(file_name) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error opening hash file {}: {}", file_name, e);
+            return Err(ExitCode::VerifReadErr);
+        }
+    };
+    read_hash_source(HashFileSource::new(hash_file), file_name)
+}
+
+// Rest of open_hash_file_with_root, with file_name only used in messages
+pub(crate) fn read_hash_source(source: Result<HashFileSource, ParityErr>, file_name: &str)
+        -> Result<(HashFileReader, Option<HashData<64>>), ExitCode> {
+    let mut hash_file = match source {
+        Ok(source) => source,
+        Err(ParityErr::ReadError(kind)) => {
+            eprintln!("Error reading hash file {}: {}", file_name, kind);
+            return Err(ExitCode::VerifReadErr);
+        },
+        Err(err) => {
+            eprintln!("Error checking hash file {}: {}", file_name, err);
+            return Err(ExitCode::VerifBadHeaderErr);
+        }
+    };
+    let content_len = match file_checksum::check_footer(&mut hash_file) {
+        Ok(len) => len,
+        Err(FileChecksumErr::ReadError(kind)) => {
+            eprintln!("Error reading hash file {}: {}", file_name, kind);
+            return Err(ExitCode::VerifReadErr);
+        },
+        Err(err) => {
+            eprintln!("Error checking hash file {}: {}", file_name, err);
+            return Err(ExitCode::VerifBadHeaderErr);
+        }
+    };
+    let (content_len, stored_root) = match tree_structure::split_root_line(
+            &mut hash_file, content_len) {
+        Ok(split) => split,
+        Err(e) => {
+            eprintln!("Error reading hash file {}: {}", file_name, e);
+            return Err(ExitCode::VerifReadErr);
+        }
+    };
+    match TruncatedReader::new(hash_file, content_len)
+            .and_then(|reader| SectionedReader::new(BufReader::new(reader)))
+            .and_then(JsonlReader::new) {
+        Ok(reader) => Ok((reader, stored_root)),
+        Err(e) => {
+            eprintln!("Error reading hash file {}: {}", file_name, e);
+            Err(ExitCode::VerifReadErr)
+        }
+    }
+}
+
+// Reads and parses a whole hash file, printing any errors
+pub(crate) fn read_parsed_hash_file(file_name: &str) -> Result<ParsedHashFile, ExitCode> {
+    let hash_file_bytes = match fs::read(file_name) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Error opening hash file {}: {}", file_name, err);
+            return Err(ExitCode::VerifReadErr);
+        }
+    };
+    parse_hash_file_contents(&hash_file_bytes, file_name)
+}
+
+// Same as read_parsed_hash_file, for a hash file that was already read into memory
+pub(crate) fn parse_hash_file_contents(hash_file_bytes: &[u8], file_name: &str)
+        -> Result<ParsedHashFile, ExitCode> {
+    parse_hash_file(hash_file_bytes).map_err(|err| {
+        eprintln!("Error reading hash file {}: {}", file_name, err);
+        match err {
+            ParseError::VersionReadError | ParseError::ParamReadError
+                | ParseError::ReadError(_) => ExitCode::VerifReadErr,
+            ParseError::MalformedFileEntry(..) | ParseError::MalformedEntry(..)
+                => ExitCode::VerifBadEntryErr,
+            _ => ExitCode::VerifBadHeaderErr
+        }
+    })
+}
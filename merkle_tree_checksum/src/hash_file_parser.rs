@@ -0,0 +1,364 @@
+#![forbid(unsafe_code)]
+// Parsing of whole hash files from memory, with errors instead of panics on any input
+// This is the entry point of the parse_hash_file fuzz target
+
+use crate::error_types::{HeaderParsingErr, ParseError};
+use crate::file_checksum::check_footer;
+use crate::parity::{has_parity, strip_parity};
+use crate::format_version::{is_readable_version, EMPTY_DIR_MIN_VERSION};
+use crate::format_version::{COMMENT_MIN_VERSION, STRUCTURE_HASH_MIN_VERSION};
+use crate::format_version::{FEATURES_MIN_VERSION, FEATURES_PREFIX, KNOWN_FEATURES};
+use crate::jsonl::JsonlReader;
+use crate::parse_functions::{extract_empty_dir_name, extract_quoted_filename};
+use crate::parse_functions::{extract_long_hash_parts, extract_metadata_hash_parts};
+use crate::parse_functions::{extract_short_hash_parts, parse_version_line, unquote_name_group};
+use crate::parse_functions::extract_section_marker;
+use crate::tree_structure::{split_root_line, STRUCTURE_HASH_PREFIX};
+use crate::utils::TreeParams;
+
+use merkle_tree::{HashData, HashRange};
+
+use semver::Version;
+
+use std::io::{BufRead, Cursor};
+
+// Header of a hash file, up to and including the Files:/Hashes: line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HashFileHeader {
+    pub version: Version,
+    pub comments: Vec<String>,
+    pub tree_params: TreeParams,
+    pub structure_hash: Option<HashData<64>>,
+    pub is_short_hash: bool,
+    // The entries of each file start with a section marker line
+    pub sectioned: bool,
+    pub line_count: usize
+}
+
+// One line of the file list, with more than one name for concatenated entries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileEntry {
+    pub names: Vec<String>,
+    pub file_len: u64,
+    pub hashed_len: Option<u64>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum HashEntry {
+    Long(usize, HashRange),
+    // The hash is None for a file marked as empty
+    Short(Option<HashData<64>>, Vec<String>),
+    Metadata(usize, HashData<64>)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ParsedHashFile {
+    pub header: HashFileHeader,
+    pub root: Option<HashData<64>>,
+    pub files: Vec<FileEntry>,
+    pub empty_dirs: Vec<String>,
+    pub entries: Vec<HashEntry>
+}
+
+#[inline]
+fn trim_line_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+pub(crate) fn parse_hash_header<R: BufRead>(reader: &mut R) -> Result<HashFileHeader, ParseError> {
+    // Parse version number
+    let mut version_line = String::new();
+    reader.read_line(&mut version_line).map_err(|_| ParseError::VersionReadError)?;
+    // Some Windows editors save files with a UTF-8 BOM at the start
+    let version_line = version_line.strip_prefix('\u{feff}').unwrap_or(&version_line);
+    let version = match parse_version_line(version_line) {
+        Ok(version) if is_readable_version(&version) => version,
+        Ok(version) => return Err(ParseError::UnsupportedVersion(version)),
+        Err(HeaderParsingErr::MalformedVersion(version_str)) =>
+            return Err(ParseError::MalformedVersion(version_str)),
+        Err(_) => return Err(ParseError::MalformedVersionLine)
+    };
+    // Read in comment and parameter lines up to the file or hash list
+    let mut comments: Vec<String> = Vec::new();
+    let mut structure_hash: Option<HashData<64>> = None;
+    let mut has_features_line = false;
+    let mut sectioned = false;
+    let mut hash_param_vec: Vec<String> = Vec::new();
+    let is_short_hash = loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => return Err(ParseError::MissingList),
+            Ok(_) => {},
+            Err(_) => return Err(ParseError::ParamReadError)
+        }
+        match line.as_str() {
+            "Hashes:\n" | "Hashes:\r\n" => break true,
+            "Files:\n" | "Files:\r\n" => break false,
+            _ => {}
+        }
+        let trimmed_line = trim_line_ending(&line);
+        // Comments can contain colons, so they are not parameters
+        if let Some(quoted_comment) = trimmed_line.strip_prefix("Comment: ") {
+            if version < COMMENT_MIN_VERSION {
+                return Err(ParseError::UnsupportedComment(version));
+            }
+            let comment = enquote::unquote(quoted_comment)
+                .map_err(|_| ParseError::MalformedComment(quoted_comment.to_owned()))?;
+            comments.push(comment);
+            continue;
+        }
+        // Unknown features are rejected before the parameters they may have added
+        if let Some(feature_list) = trimmed_line.strip_prefix(FEATURES_PREFIX) {
+            if version < FEATURES_MIN_VERSION {
+                return Err(ParseError::UnsupportedFeatures(version));
+            }
+            if let Some(unknown_feature) = feature_list.split(", ")
+                    .find(|feature| !KNOWN_FEATURES.contains(feature)) {
+                return Err(ParseError::UnknownFeature(unknown_feature.to_owned()));
+            }
+            has_features_line = true;
+            sectioned = feature_list.split(", ").any(|feature| feature == "sectioned");
+            continue;
+        }
+        if let Some(structure_hex) = trimmed_line.strip_prefix(STRUCTURE_HASH_PREFIX) {
+            if version < STRUCTURE_HASH_MIN_VERSION {
+                return Err(ParseError::UnsupportedStructureHash(version));
+            }
+            match hex::decode(structure_hex).ok()
+                    .and_then(|hash_bytes| HashData::try_new(&hash_bytes).ok()) {
+                Some(hash) if structure_hash.is_none() => structure_hash = Some(hash),
+                _ => return Err(ParseError::MalformedStructureHash(trimmed_line.to_owned()))
+            }
+            continue;
+        }
+        hash_param_vec.push(trimmed_line.to_owned());
+    };
+    let tree_params = TreeParams::from_lines(&hash_param_vec)
+        .map_err(ParseError::BadParameters)?;
+    Ok(HashFileHeader {
+        version,
+        tree_params,
+        is_short_hash,
+        sectioned,
+        // Version line, features, comment, structure hash, and parameter lines, and the list line
+        line_count: usize::from(has_features_line) + comments.len()
+            + usize::from(structure_hash.is_some()) + hash_param_vec.len() + 2,
+        comments,
+        structure_hash
+    })
+}
+
+// Parses the file list of a long hash file, up to and including the Hashes: line
+// line_number is the number of lines read so far, and is advanced past the list
+pub(crate) fn parse_file_list<R: BufRead>(reader: &mut R, header: &HashFileHeader,
+        line_number: &mut usize) -> Result<(Vec<FileEntry>, Vec<String>), ParseError> {
+    let mut files: Vec<FileEntry> = Vec::new();
+    let mut empty_dirs: Vec<String> = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(ParseError::MissingHashList);
+        }
+        *line_number += 1;
+        if line == "Hashes:\n" || line == "Hashes:\r\n" {
+            break;
+        }
+        if let Ok(quoted_name) = extract_empty_dir_name(&line) {
+            match enquote::unquote(quoted_name) {
+                Ok(name) if header.version >= EMPTY_DIR_MIN_VERSION => empty_dirs.push(name),
+                _ => return Err(ParseError::MalformedFileEntry(*line_number, line))
+            }
+            continue;
+        }
+        let file_entry = match extract_quoted_filename(&line) {
+            Ok((quoted_name, Some(file_len), hashed_len)) => unquote_name_group(quoted_name)
+                .ok()
+                .filter(|_| hashed_len.is_none_or(|hashed_len|
+                    header.tree_params.trim_zeros && hashed_len <= file_len))
+                .map(|names| FileEntry {names, file_len, hashed_len}),
+            _ => None
+        };
+        match file_entry {
+            Some(file_entry) => files.push(file_entry),
+            None => return Err(ParseError::MalformedFileEntry(*line_number, line))
+        }
+    }
+    Ok((files, empty_dirs))
+}
+
+// Parses a complete hash file in either format, checking its footer if present
+// Entries are only parsed, and are not checked against each other or the file list
+pub(crate) fn parse_hash_file(bytes: &[u8]) -> Result<ParsedHashFile, ParseError> {
+    let stripped_bytes: Vec<u8>;
+    let bytes = match has_parity(bytes) {
+        true => {
+            stripped_bytes = strip_parity(bytes).map_err(ParseError::BadParity)?;
+            &stripped_bytes
+        },
+        false => bytes
+    };
+    let mut cursor = Cursor::new(bytes);
+    let content_len = check_footer(&mut cursor).map_err(ParseError::BadFooter)?;
+    let (content_len, root) = split_root_line(&mut cursor, content_len)?;
+    // Both lengths are within bytes, so this cannot truncate
+    let content_bytes = &bytes[..content_len as usize];
+    let mut reader = JsonlReader::new(Cursor::new(content_bytes))?;
+    let header = parse_hash_header(&mut reader)?;
+    let hex_digit_count = 2*header.tree_params.hash_function.hash_len();
+    let mut line_number = header.line_count;
+
+    let (files, empty_dirs) = match header.is_short_hash {
+        true => (Vec::new(), Vec::new()),
+        false => parse_file_list(&mut reader, &header, &mut line_number)?
+    };
+
+    let mut entries: Vec<HashEntry> = Vec::new();
+    // File index of the last section marker, with its line, until an entry follows it
+    let mut pending_marker: Option<(usize, usize, String)> = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        line_number += 1;
+        if header.sectioned && pending_marker.is_none() {
+            if let Some((file_index, names)) = extract_section_marker(&line) {
+                match files.get(file_index) {
+                    Some(file_entry) if file_entry.names == names => {
+                        pending_marker = Some((file_index, line_number, line));
+                        continue;
+                    },
+                    _ => return Err(ParseError::MalformedSectionMarker(line_number, line))
+                }
+            }
+        }
+        let entry = if header.is_short_hash {
+            extract_short_hash_parts(&line, hex_digit_count)
+                .and_then(|(hash, quoted_name)| unquote_name_group(quoted_name)
+                    .map(|names| HashEntry::Short(hash, names))
+                    .map_err(|_| Default::default()))
+        } else if header.tree_params.metadata_only {
+            extract_metadata_hash_parts(&line, hex_digit_count)
+                .map(|(file_id, hash)| HashEntry::Metadata(file_id, hash))
+        } else {
+            extract_long_hash_parts(&line, hex_digit_count)
+                .map(|(file_id, hash_range)| HashEntry::Long(file_id, hash_range))
+        };
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => return Err(ParseError::MalformedEntry(line_number, line, err))
+        };
+        if let Some((marker_index, marker_line_number, marker_line)) = pending_marker.take() {
+            match &entry {
+                HashEntry::Long(file_index, _) | HashEntry::Metadata(file_index, _)
+                    if *file_index == marker_index => {},
+                _ => return Err(ParseError::MalformedSectionMarker(
+                    marker_line_number, marker_line))
+            }
+        }
+        entries.push(entry);
+    }
+    if let Some((_, marker_line_number, marker_line)) = pending_marker {
+        return Err(ParseError::MalformedSectionMarker(marker_line_number, marker_line));
+    }
+    Ok(ParsedHashFile {header, root, files, empty_dirs, entries})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_types::HashExtractionError;
+    use crate::format_version::FORMAT_VERSION;
+    use clap::crate_name;
+    use merkle_tree::{multi_file_block_generator, BlockRange};
+
+    fn long_hash_file(body: &str) -> String {
+        format!(concat!("{} v{}\n",
+            "Hash function: crc32\n",
+            "Block size: 4\n",
+            "Branching factor: 2\n",
+            "Files:\n",
+            "\"a\" 0x5 bytes\n",
+            "Hashes:\n",
+            "{}"), crate_name!(), FORMAT_VERSION, body)
+    }
+
+    #[test]
+    fn long_hash_file_parsed() {
+        let parsed = parse_hash_file(long_hash_file(concat!(
+            "  0 [0x00000000-0x00000001] [0x00000000-0x00000004] 01020304\n",
+            "Root: 0a0b0c0d\n")).as_bytes()).unwrap();
+        assert_eq!(parsed.root, Some(HashData::try_new(&[0x0a, 0x0b, 0x0c, 0x0d]).unwrap()));
+        assert_eq!(parsed.files, [FileEntry {names: vec!["a".to_owned()],
+            file_len: 5, hashed_len: None}]);
+        assert_eq!(parsed.entries, [HashEntry::Long(0, HashRange::new(
+            BlockRange::new(0, 1, true), BlockRange::new(0, 4, true),
+            HashData::try_new(&[0x01, 0x02, 0x03, 0x04]).unwrap()))]);
+    }
+
+    #[test]
+    fn section_markers_parsed() {
+        let entry = "  0 [0x00000000-0x00000001] [0x00000000-0x00000004] 01020304\n";
+        let sectioned = |marker: &str| long_hash_file(&format!("{}{}", marker, entry))
+            .replacen("\n", "\nFeatures: sectioned\n", 1);
+        let parsed = parse_hash_file(sectioned("# file 0: \"a\"\n").as_bytes()).unwrap();
+        assert!(parsed.header.sectioned);
+        assert_eq!(parsed.entries.len(), 1);
+        for bad_marker in ["# file 0: \"b\"\n", "# file 1: \"a\"\n"] {
+            assert_eq!(parse_hash_file(sectioned(bad_marker).as_bytes()).unwrap_err(),
+                ParseError::MalformedSectionMarker(9, bad_marker.to_owned()));
+        }
+    }
+
+    #[test]
+    fn adversarial_entries_rejected() {
+        // Numbers too long for a u64, and ranges that would be empty
+        let bad_entries = [
+            "  0 [0x10000000000000000-0x1] [0x0-0x4] 01020304\n",
+            "  99999999999999999999999 [0x0-0x1] [0x0-0x4] 01020304\n",
+            "  0 [0x1-0x1) [0x0-0x4] 01020304\n",
+            "  0 [0x1-0x0] [0x0-0x4] 01020304\n",
+            "  0 [0x0-0xffffffffffffffff] [0x0-0x4] 01020304\n"
+        ];
+        for bad_entry in bad_entries {
+            assert_eq!(parse_hash_file(long_hash_file(bad_entry).as_bytes()).unwrap_err(),
+                ParseError::MalformedEntry(8, bad_entry.to_owned(), HashExtractionError::Malformed));
+        }
+        let long_file_len = long_hash_file("").replace("0x5 bytes", "0x10000000000000000 bytes");
+        assert!(matches!(parse_hash_file(long_file_len.as_bytes()),
+            Err(ParseError::MalformedFileEntry(6, _))));
+    }
+
+    #[test]
+    fn reference_entries_match_block_generator() {
+        let hash_file = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"),
+            "/tests/reference_files/hash_out")).unwrap();
+        let parsed = parse_hash_file(&hash_file).unwrap();
+        let file_lens: Vec<u64> = parsed.files.iter().map(|file| file.file_len).collect();
+        let tree_params = &parsed.header.tree_params;
+        let expected: Vec<_> = multi_file_block_generator(&file_lens,
+            tree_params.block_size, tree_params.branch_factor).collect();
+        let written: Vec<_> = parsed.entries.iter()
+            .map(|entry| match entry {
+                HashEntry::Long(file_id, hash_range) =>
+                    (*file_id, hash_range.block_range(), hash_range.byte_range()),
+                _ => panic!("reference hash file should only have long entries")
+            })
+            .collect();
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn truncated_files_rejected() {
+        let hash_file = long_hash_file("");
+        let hashes_start = hash_file.find("Hashes:").unwrap();
+        assert_eq!(parse_hash_file(&hash_file.as_bytes()[..hashes_start]).unwrap_err(),
+            ParseError::MissingHashList);
+        let files_start = hash_file.find("Files:").unwrap();
+        assert_eq!(parse_hash_file(&hash_file.as_bytes()[..files_start]).unwrap_err(),
+            ParseError::MissingList);
+        assert_eq!(parse_hash_file(b"").unwrap_err(), ParseError::MalformedVersionLine);
+        assert!(parse_hash_file(b"{\"version\": 5}\n\xff").is_err());
+    }
+}
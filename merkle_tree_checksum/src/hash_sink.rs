@@ -0,0 +1,492 @@
+#![forbid(unsafe_code)]
+// Destinations for generated hash files, so that hashing does not depend on the format
+// Each hash file format implements HashSink, and the text format is the default
+
+use crate::file_checksum::ChecksumWriter;
+use crate::parity::ParityWriter;
+use crate::jsonl::{write_jsonl_entry, write_jsonl_header, HashFileFormat};
+use crate::tree_structure::ROOT_PREFIX;
+use crate::utils::{offset_byte_range, quote_name_group, write_hash_header, TreeParams};
+use crate::utils::{display_name_group, path_relative_to, is_listed_node};
+use crate::format_functions::{checksum_line, hash_hex};
+use crate::parse_functions::{EMPTY_FILE_MARKER, SECTION_MARKER_PREFIX};
+
+use merkle_tree::{BlockRange, Consumer, HashData, HashRange};
+
+use indicatif::ProgressBar;
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+// Everything written before the hash entries
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SinkHeader<'a> {
+    pub tree_params: &'a TreeParams,
+    pub comments: &'a [String],
+    pub structure_hash: Option<&'a HashData<64>>,
+    // File entries are paired with the hashed length, if shorter than the file
+    pub file_entries: &'a [(&'a [PathBuf], u64, Option<u64>)],
+    pub dir_list: &'a [PathBuf],
+    pub short_output: bool,
+    // The entries of each file start with a section marker line naming it
+    pub sectioned: bool
+}
+
+pub(crate) trait HashSink: fmt::Debug {
+    fn write_header(&mut self, header: &SinkHeader<'_>) -> io::Result<()>;
+    // Byte ranges are offset by byte_offset before being written
+    fn write_entry(&mut self, entry_index: usize, hash_range: &HashRange,
+        byte_offset: u64) -> io::Result<()>;
+    // The hash is None for a file marked as empty
+    fn write_short(&mut self, hash: Option<&HashData<64>>,
+        paths: &[PathBuf]) -> io::Result<()>;
+    fn write_metadata(&mut self, entry_index: usize, hash: &HashData<64>) -> io::Result<()>;
+    // Combined root of every file, written after the last entry
+    fn write_root(&mut self, root: &HashData<64>) -> io::Result<()>;
+    // Passes on the lines written so far, for --checkpoint
+    fn flush(&mut self) -> io::Result<()>;
+    // Writes anything after the entries and flushes the hash file
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+// Lets generic code take a sink of a format chosen at runtime
+impl<S: HashSink+?Sized> HashSink for Box<S> {
+    fn write_header(&mut self, header: &SinkHeader<'_>) -> io::Result<()> {
+        (**self).write_header(header)
+    }
+    fn write_entry(&mut self, entry_index: usize, hash_range: &HashRange,
+            byte_offset: u64) -> io::Result<()> {
+        (**self).write_entry(entry_index, hash_range, byte_offset)
+    }
+    fn write_short(&mut self, hash: Option<&HashData<64>>,
+            paths: &[PathBuf]) -> io::Result<()> {
+        (**self).write_short(hash, paths)
+    }
+    fn write_metadata(&mut self, entry_index: usize, hash: &HashData<64>) -> io::Result<()> {
+        (**self).write_metadata(entry_index, hash)
+    }
+    fn write_root(&mut self, root: &HashData<64>) -> io::Result<()> {
+        (**self).write_root(root)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()
+    }
+}
+
+// Opens the sink of the given format over writer
+// Only the text format has a self-checksum footer
+// Parity is added below the footer, so the footer covers the lines without it
+pub(crate) fn new_sink<W>(writer: W, format: HashFileFormat,
+        self_checksum: bool, with_parity: bool, uppercase: bool) -> Box<dyn HashSink+Send>
+where
+    W: Write+Send+fmt::Debug+'static
+{
+    match with_parity {
+        true => format_sink(ParityWriter::new(writer), format, self_checksum, uppercase),
+        false => format_sink(writer, format, self_checksum, uppercase)
+    }
+}
+fn format_sink<W>(writer: W, format: HashFileFormat,
+        self_checksum: bool, uppercase: bool) -> Box<dyn HashSink+Send>
+where
+    W: Write+Send+fmt::Debug+'static
+{
+    match format {
+        HashFileFormat::Text => Box::new(TextSink::new(writer, self_checksum, uppercase)),
+        HashFileFormat::Jsonl => Box::new(JsonlSink::new(writer, uppercase))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TextSink<W: Write+fmt::Debug> {
+    writer: ChecksumWriter<W>,
+    // Hashes are written with uppercase hex digits
+    uppercase: bool,
+    // Quoted names of each file with a sectioned header, and the last file started
+    section_names: Option<Vec<String>>,
+    current_section: Option<usize>
+}
+impl<W: Write+fmt::Debug> TextSink<W> {
+    pub fn new(writer: W, self_checksum: bool, uppercase: bool) -> Self {
+        TextSink {writer: ChecksumWriter::new(writer, self_checksum), uppercase,
+            section_names: None, current_section: None}
+    }
+    // Entries arrive one file at a time, so a marker is written when the file changes
+    fn start_section(&mut self, entry_index: usize) -> io::Result<()> {
+        if let Some(section_names) = &self.section_names {
+            if self.current_section != Some(entry_index) {
+                writeln!(self.writer, "{}{}: {}", SECTION_MARKER_PREFIX,
+                    entry_index, section_names[entry_index])?;
+                self.current_section = Some(entry_index);
+            }
+        }
+        Ok(())
+    }
+}
+impl<W: Write+fmt::Debug> HashSink for TextSink<W> {
+    fn write_header(&mut self, header: &SinkHeader<'_>) -> io::Result<()> {
+        if header.sectioned {
+            self.section_names = Some(header.file_entries.iter()
+                .map(|(paths, _, _)| quote_name_group(paths))
+                .collect());
+        }
+        write_hash_header(&mut self.writer, header.tree_params, header.comments,
+            header.structure_hash, header.file_entries, header.dir_list,
+            header.short_output, header.sectioned, self.uppercase)
+    }
+    fn write_entry(&mut self, entry_index: usize, hash_range: &HashRange,
+            byte_offset: u64) -> io::Result<()> {
+        self.start_section(entry_index)?;
+        writeln!(self.writer, "{:3} {} {} {}",
+            entry_index,
+            hash_range.block_range(),
+            offset_byte_range(hash_range.byte_range(), byte_offset),
+            hash_hex(hash_range.hash_result(), self.uppercase))
+    }
+    fn write_short(&mut self, hash: Option<&HashData<64>>,
+            paths: &[PathBuf]) -> io::Result<()> {
+        let hash_str = hash.map_or(String::from(EMPTY_FILE_MARKER),
+            |hash| hash_hex(hash, self.uppercase));
+        // writeln always terminates the entry, including the last one
+        writeln!(self.writer, "{}  {}", hash_str, quote_name_group(paths))
+    }
+    fn write_metadata(&mut self, entry_index: usize, hash: &HashData<64>) -> io::Result<()> {
+        self.start_section(entry_index)?;
+        writeln!(self.writer, "{:3} {}", entry_index, hash_hex(hash, self.uppercase))
+    }
+    fn write_root(&mut self, root: &HashData<64>) -> io::Result<()> {
+        writeln!(self.writer, "{}{}", ROOT_PREFIX, hash_hex(root, self.uppercase))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.writer.finish().map(|_| ())
+    }
+}
+
+// For the methods of a format that has no such entries
+// The options that would call them conflict with the format, so this is not expected
+fn unsupported_entry(format_name: &str, entry_kind: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported,
+        format!("{} do not have {}", format_name, entry_kind))
+}
+
+// --format jsonl conflicts with the options that need the other methods
+#[derive(Debug)]
+pub(crate) struct JsonlSink<W: Write+fmt::Debug> {
+    writer: W,
+    uppercase: bool
+}
+impl<W: Write+fmt::Debug> JsonlSink<W> {
+    pub fn new(writer: W, uppercase: bool) -> Self {
+        JsonlSink {writer, uppercase}
+    }
+}
+impl<W: Write+fmt::Debug> HashSink for JsonlSink<W> {
+    fn write_header(&mut self, header: &SinkHeader<'_>) -> io::Result<()> {
+        write_jsonl_header(&mut self.writer, header.tree_params, header.comments,
+            header.file_entries, header.dir_list)
+    }
+    fn write_entry(&mut self, entry_index: usize, hash_range: &HashRange,
+            byte_offset: u64) -> io::Result<()> {
+        write_jsonl_entry(&mut self.writer, entry_index, hash_range, byte_offset,
+            self.uppercase)
+    }
+    fn write_short(&mut self, _hash: Option<&HashData<64>>,
+            _paths: &[PathBuf]) -> io::Result<()> {
+        Err(unsupported_entry("JSON Lines hash files", "short entries"))
+    }
+    fn write_metadata(&mut self, _entry_index: usize, _hash: &HashData<64>) -> io::Result<()> {
+        Err(unsupported_entry("JSON Lines hash files", "metadata entries"))
+    }
+    fn write_root(&mut self, _root: &HashData<64>) -> io::Result<()> {
+        Err(unsupported_entry("JSON Lines hash files", "a combined root"))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Lines of sha256sum and similar tools for --coreutils, without a header
+// Only short entries exist in this format, with plain hashes of whole files
+#[derive(Debug)]
+pub(crate) struct CoreutilsSink<W: Write+fmt::Debug> {
+    writer: W,
+    uppercase: bool
+}
+impl<W: Write+fmt::Debug> CoreutilsSink<W> {
+    pub fn new(writer: W, uppercase: bool) -> Self {
+        CoreutilsSink {writer, uppercase}
+    }
+}
+impl<W: Write+fmt::Debug> HashSink for CoreutilsSink<W> {
+    fn write_header(&mut self, _header: &SinkHeader<'_>) -> io::Result<()> {
+        Ok(())
+    }
+    fn write_entry(&mut self, _entry_index: usize, _hash_range: &HashRange,
+            _byte_offset: u64) -> io::Result<()> {
+        Err(unsupported_entry("coreutils hash files", "long entries"))
+    }
+    fn write_short(&mut self, hash: Option<&HashData<64>>,
+            paths: &[PathBuf]) -> io::Result<()> {
+        // --coreutils conflicts with --mark-empty
+        let hash = hash.expect("coreutils output has a hash for every file");
+        writeln!(self.writer, "{}", checksum_line(&hash_hex(hash, self.uppercase),
+            &display_name_group(paths)))
+    }
+    fn write_metadata(&mut self, _entry_index: usize, _hash: &HashData<64>) -> io::Result<()> {
+        Err(unsupported_entry("coreutils hash files", "metadata entries"))
+    }
+    fn write_root(&mut self, _root: &HashData<64>) -> io::Result<()> {
+        Err(unsupported_entry("coreutils hash files", "a combined root"))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Writes the names of files relative to base_dir, for --relative-to-output
+// Files are found through their canonical path, so they must still exist
+#[derive(Debug)]
+pub(crate) struct RelativePathSink<S: HashSink> {
+    inner: S,
+    base_dir: PathBuf
+}
+impl<S: HashSink> RelativePathSink<S> {
+    // base_dir should be canonical, as it is compared with canonical paths
+    pub fn new(inner: S, base_dir: PathBuf) -> Self {
+        RelativePathSink {inner, base_dir}
+    }
+    fn relative_paths(&self, paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+        paths.iter()
+            .map(|path| Ok(path_relative_to(&fs::canonicalize(path)?, &self.base_dir)))
+            .collect()
+    }
+}
+impl<S: HashSink> HashSink for RelativePathSink<S> {
+    fn write_header(&mut self, header: &SinkHeader<'_>) -> io::Result<()> {
+        let relative_entries = header.file_entries.iter()
+            .map(|(paths, total_len, hashed_len)|
+                Ok((self.relative_paths(paths)?, *total_len, *hashed_len)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let file_entries: Vec<(&[PathBuf], u64, Option<u64>)> = relative_entries.iter()
+            .map(|(paths, total_len, hashed_len)| (paths.as_slice(), *total_len, *hashed_len))
+            .collect();
+        let dir_list = self.relative_paths(header.dir_list)?;
+        self.inner.write_header(&SinkHeader {
+            file_entries: &file_entries,
+            dir_list: &dir_list,
+            ..*header
+        })
+    }
+    fn write_entry(&mut self, entry_index: usize, hash_range: &HashRange,
+            byte_offset: u64) -> io::Result<()> {
+        self.inner.write_entry(entry_index, hash_range, byte_offset)
+    }
+    fn write_short(&mut self, hash: Option<&HashData<64>>,
+            paths: &[PathBuf]) -> io::Result<()> {
+        let paths = self.relative_paths(paths)?;
+        self.inner.write_short(hash, &paths)
+    }
+    fn write_metadata(&mut self, entry_index: usize, hash: &HashData<64>) -> io::Result<()> {
+        self.inner.write_metadata(entry_index, hash)
+    }
+    fn write_root(&mut self, root: &HashData<64>) -> io::Result<()> {
+        self.inner.write_root(root)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        Box::new(self.inner).finish()
+    }
+}
+
+// Writes long hash entries as soon as they are hashed
+// Entries only arrive in order when hashing without a thread pool
+#[derive(Debug)]
+pub(crate) struct WriterConsumer<S: HashSink> {
+    sink: Arc<Mutex<S>>,
+    entry_index: usize,
+    byte_offset: u64,
+    listed_root: Option<BlockRange>,
+    pb: ProgressBar
+}
+impl<S: HashSink> WriterConsumer<S> {
+    pub fn new(sink: S, entry_index: usize, byte_offset: u64,
+            listed_root: Option<BlockRange>, pb: ProgressBar) -> Self {
+        WriterConsumer {
+            sink: Arc::new(Mutex::new(sink)),
+            entry_index,
+            byte_offset,
+            listed_root,
+            pb
+        }
+    }
+    // Panics if any clones are still alive
+    pub fn into_inner(self) -> S {
+        match Arc::try_unwrap(self.sink) {
+            Ok(sink) => sink.into_inner().unwrap(),
+            Err(_) => panic!("WriterConsumer still has live clones")
+        }
+    }
+}
+// derive(Clone) would require S: Clone
+impl<S: HashSink> Clone for WriterConsumer<S> {
+    fn clone(&self) -> Self {
+        WriterConsumer {
+            sink: Arc::clone(&self.sink),
+            entry_index: self.entry_index,
+            byte_offset: self.byte_offset,
+            listed_root: self.listed_root,
+            pb: self.pb.clone()
+        }
+    }
+}
+impl<S: HashSink> Consumer<HashRange> for WriterConsumer<S> {
+    fn accept(&self, var: HashRange) -> Result<(), HashRange> {
+        if is_listed_node(var.block_range(), self.listed_root) {
+            let mut sink = self.sink.lock().unwrap();
+            sink.write_entry(self.entry_index, &var, self.byte_offset).unwrap();
+        }
+        self.pb.inc(1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_enum::HashFunctions;
+    use merkle_tree::{BlockRange, NodeEncoding};
+    use clap::crate_name;
+    use crate::format_version::FORMAT_VERSION;
+
+    const TREE_PARAMS: TreeParams = TreeParams {
+        block_size: 4,
+        branch_factor: 2,
+        hash_function: HashFunctions::crc32,
+        node_encoding: NodeEncoding::Plain,
+        salted: false,
+        pad_tree: false,
+        unbalanced: false,
+        duplicate_last: false,
+        metadata_only: false,
+        trim_zeros: false,
+        region_offset: None,
+        absolute_ranges: false,
+        leaves_and_root: false,
+        content_defined: false,
+        normalize_eol: false,
+        relative_names: false,
+        nominal_ranges: false,
+        stride: None,
+        filtered: false
+    };
+
+    #[test]
+    fn text_sink_lines() {
+        let mut text: Vec<u8> = Vec::new();
+        let paths = [PathBuf::from("a b")];
+        let mut sink = TextSink::new(&mut text, false, false);
+        sink.write_header(&SinkHeader {
+            tree_params: &TREE_PARAMS,
+            comments: &[],
+            structure_hash: None,
+            file_entries: &[(&paths, 5, None)],
+            dir_list: &[],
+            short_output: false,
+            sectioned: false
+        }).unwrap();
+        let hash = HashData::try_new(&[0x01, 0x02, 0x03, 0x04]).unwrap();
+        sink.write_entry(0, &HashRange::new(BlockRange::new(0, 1, true),
+            BlockRange::new(0, 4, true), hash.clone()), 0x10).unwrap();
+        sink.write_metadata(1, &hash).unwrap();
+        Box::new(sink).finish().unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), format!(concat!(
+            "{} v{}\n",
+            "Hash function: crc32\n",
+            "Block size: 4\n",
+            "Branching factor: 2\n",
+            "Files:\n",
+            "\"a b\" 0x5 bytes\n",
+            "Hashes:\n",
+            "  0 [0x00000000-0x00000001] [0x00000010-0x00000014] 01020304\n",
+            "  1 01020304\n"),
+            crate_name!(), FORMAT_VERSION));
+    }
+    #[test]
+    fn text_sink_short_empty() {
+        let mut text: Vec<u8> = Vec::new();
+        let paths = [PathBuf::from("empty")];
+        let mut sink = TextSink::new(&mut text, false, false);
+        sink.write_short(None, &paths).unwrap();
+        Box::new(sink).finish().unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(),
+            format!("{}  \"empty\"\n", EMPTY_FILE_MARKER));
+    }
+    #[test]
+    fn jsonl_sink_unsupported_entries() {
+        let mut text: Vec<u8> = Vec::new();
+        let mut sink = JsonlSink::new(&mut text, false);
+        let hash = HashData::try_new(&[0x01, 0x02, 0x03, 0x04]).unwrap();
+        // Entries the format has no place for are errors, and nothing is written
+        let err = sink.write_short(Some(&hash), &[PathBuf::from("a")]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(err.to_string(), "JSON Lines hash files do not have short entries");
+        assert_eq!(sink.write_metadata(0, &hash).unwrap_err().kind(),
+            io::ErrorKind::Unsupported);
+        assert_eq!(sink.write_root(&hash).unwrap_err().kind(), io::ErrorKind::Unsupported);
+        Box::new(sink).finish().unwrap();
+        assert!(text.is_empty());
+    }
+    #[test]
+    fn coreutils_sink_lines() {
+        let mut text: Vec<u8> = Vec::new();
+        let mut sink = CoreutilsSink::new(&mut text, false);
+        sink.write_header(&SinkHeader {
+            tree_params: &TREE_PARAMS,
+            comments: &[],
+            structure_hash: None,
+            file_entries: &[],
+            dir_list: &[],
+            short_output: true,
+            sectioned: false
+        }).unwrap();
+        let hash = HashData::try_new(&[0x01, 0x02, 0x03, 0x04]).unwrap();
+        sink.write_short(Some(&hash), &[PathBuf::from("a b")]).unwrap();
+        sink.write_short(Some(&hash), &[PathBuf::from("c\nd\\e")]).unwrap();
+        Box::new(sink).finish().unwrap();
+        // Names with a newline or backslash are escaped as coreutils does
+        assert_eq!(String::from_utf8(text).unwrap(),
+            "01020304  a b\n\\01020304  c\\nd\\\\e\n");
+    }
+    #[test]
+    fn coreutils_sink_unsupported_entries() {
+        let mut text: Vec<u8> = Vec::new();
+        let mut sink = CoreutilsSink::new(&mut text, false);
+        let hash = HashData::try_new(&[0x01, 0x02, 0x03, 0x04]).unwrap();
+        let err = sink.write_entry(0, &HashRange::new(BlockRange::new(0, 1, true),
+            BlockRange::new(0, 4, true), hash.clone()), 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(err.to_string(), "coreutils hash files do not have long entries");
+        assert_eq!(sink.write_metadata(0, &hash).unwrap_err().kind(),
+            io::ErrorKind::Unsupported);
+        assert_eq!(sink.write_root(&hash).unwrap_err().kind(), io::ErrorKind::Unsupported);
+        Box::new(sink).finish().unwrap();
+        assert!(text.is_empty());
+    }
+}
@@ -0,0 +1,100 @@
+#![forbid(unsafe_code)]
+// The info subcommand, which lists what a hash file records
+
+use crate::exit_codes::ExitCode;
+use crate::hash_enum::HashFunctions;
+use crate::hash_file_input::read_parsed_hash_file;
+use crate::hash_file_parser::HashEntry;
+use crate::utils::quote_name_group;
+
+use clap::{Command, Arg, ArgAction};
+
+use std::io::Write;
+use std::path::PathBuf;
+
+pub(crate) const INFO_CMD_NAME: &str = "info";
+
+pub(crate) fn info_command() -> Command {
+    Command::new(INFO_CMD_NAME)
+        .about("List the files recorded in a hash file")
+        .long_about(concat!("List the files recorded in a hash file, ",
+            "one entry per line and quoted as in the hash file. ",
+            "Empty directory entries are left out."))
+        .arg(Arg::new("algorithmid").long("algorithm-id")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("files0")
+            .help("Print the name and OID of the hash function instead")
+            .long_help(concat!("Print the canonical name of the hash function ",
+                "and its OID, or none if it has no OID, instead of the files. ",
+                "These identify the hash function in manifests read by other tools.")))
+        .arg(Arg::new("files0").long("files0")
+            .action(ArgAction::SetTrue)
+            .help("List unquoted file names, each terminated by a NUL byte")
+            .long_help(concat!("List unquoted file names, each terminated ",
+                "by a NUL byte, for use with xargs -0 and --files0-from. ",
+                "Names are unescaped first, so they are output exactly ",
+                "as they were given when hashing. The parts of ",
+                "concatenated entries are listed separately.")))
+        .arg(Arg::new("FILE").required(true)
+            .action(ArgAction::Set)
+            .help("Hash file to list the files of"))
+}
+
+// Prints the names in the file list, or in the entries of short hash files
+pub(crate) fn list_recorded_files(file_name: &str, files0: bool) -> ExitCode {
+    let parsed_file = match read_parsed_hash_file(file_name) {
+        Ok(parsed_file) => parsed_file,
+        Err(exit_code) => return exit_code
+    };
+    let name_groups: Vec<&[String]> = match parsed_file.header.is_short_hash {
+        true => parsed_file.entries.iter()
+            .filter_map(|entry| match entry {
+                HashEntry::Short(_, names) => Some(names.as_slice()),
+                _ => None
+            })
+            .collect(),
+        false => parsed_file.files.iter()
+            .map(|file_entry| file_entry.names.as_slice())
+            .collect()
+    };
+    let mut stdout = std::io::stdout().lock();
+    for names in name_groups {
+        // Names are written as parsed, which are the paths that verify-hash opens
+        let write_result = match files0 {
+            true => names.iter()
+                .try_for_each(|name| write!(stdout, "{}\0", name)),
+            false => {
+                let paths: Vec<PathBuf> = names.iter().map(PathBuf::from).collect();
+                writeln!(stdout, "{}", quote_name_group(&paths))
+            }
+        };
+        if let Err(err) = write_result {
+            eprintln!("Error writing file list: {}", err);
+            return ExitCode::GenWriteErr;
+        }
+    }
+    ExitCode::Success
+}
+
+// Name and OID of the hash function, for info and generate-hash --algorithm-id
+pub(crate) fn algorithm_id_lines(hash_function: HashFunctions) -> [String; 2] {
+    let (name, oid) = hash_function.algorithm_id();
+    [format!("Hash function: {}", name), format!("OID: {}", oid.unwrap_or("none"))]
+}
+
+pub(crate) fn print_algorithm_id(file_name: &str) -> ExitCode {
+    let parsed_file = match read_parsed_hash_file(file_name) {
+        Ok(parsed_file) => parsed_file,
+        Err(exit_code) => return exit_code
+    };
+    let mut stdout = std::io::stdout().lock();
+    let write_result = algorithm_id_lines(parsed_file.header.tree_params.hash_function).iter()
+        .try_for_each(|line| writeln!(stdout, "{}", line));
+    match write_result {
+        Ok(()) => ExitCode::Success,
+        Err(err) => {
+            eprintln!("Error writing hash function: {}", err);
+            ExitCode::GenWriteErr
+        }
+    }
+}
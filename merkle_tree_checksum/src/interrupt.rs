@@ -0,0 +1,37 @@
+#![forbid(unsafe_code)]
+// Removes an incomplete hash file when generate-hash is interrupted
+
+use crate::exit_codes::ExitCode;
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+
+// Hash file created by this run that is still being written
+static PENDING_OUTPUT: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/*
+ * The handler holds the lock until the process exits,
+ * so the file cannot be marked complete while it is being removed
+ * Writes racing with the removal go to the unlinked file,
+ * and exit skips flushing the LineWriter, so nothing is left behind
+ */
+pub(crate) fn install_handler() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        let pending_output = PENDING_OUTPUT.lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(path) = pending_output.as_ref() {
+            if let Err(err) = fs::remove_file(path) {
+                eprintln!("Error removing incomplete file {}: {}",
+                    path.display(), err);
+            }
+        }
+        eprintln!("Interrupted");
+        std::process::exit(ExitCode::Interrupted.code());
+    })
+}
+
+// None marks the previous file as complete
+pub(crate) fn set_pending_output(path: Option<PathBuf>) {
+    *PENDING_OUTPUT.lock().unwrap_or_else(PoisonError::into_inner) = path;
+}
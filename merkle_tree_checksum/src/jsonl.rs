@@ -0,0 +1,286 @@
+#![forbid(unsafe_code)]
+// JSON Lines hash files, with the header as the first line and then one object per entry
+// Reading translates each line to the text format, so the text parsers read both formats
+
+use crate::format_functions::{escape_chars, hash_hex};
+use crate::format_version::{FORMAT_VERSION, FEATURES_PREFIX};
+use crate::utils::{offset_byte_range, quote_name_group, TreeParams};
+
+use merkle_tree::{BlockRange, HashRange};
+
+use clap::crate_name;
+use serde_json::{json, Map, Value};
+use strum_macros::EnumString;
+
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub(crate) enum HashFileFormat {
+    Text,
+    Jsonl
+}
+
+// Parameters keep the keys of the text header, with numbers and booleans unquoted
+fn param_value(value_str: &str) -> Value {
+    if let Ok(num) = value_str.parse::<u64>() {
+        json!(num)
+    } else if let Ok(flag) = value_str.parse::<bool>() {
+        json!(flag)
+    } else {
+        json!(value_str)
+    }
+}
+
+// JSON Lines counterpart of write_hash_header
+pub(crate) fn write_jsonl_header<W: Write>(writer: &mut W,
+        tree_params: &TreeParams, comments: &[String],
+        file_entries: &[(&[PathBuf], u64, Option<u64>)],
+        dir_list: &[PathBuf]) -> io::Result<()> {
+    let params: Map<String, Value> = tree_params.to_string().lines()
+        .filter_map(|line| line.split_once(": "))
+        .map(|(key, value_str)| (key.to_owned(), param_value(value_str)))
+        .collect();
+    let files: Vec<Value> = file_entries.iter()
+        .map(|(paths, total_len, hashed_len)| {
+            let names: Vec<_> = paths.iter().map(|path| path.to_string_lossy()).collect();
+            match hashed_len {
+                Some(hashed_len) => json!({"names": names, "length": total_len,
+                    "hashed_length": hashed_len}),
+                None => json!({"names": names, "length": total_len})
+            }
+        })
+        .collect();
+    let empty_dirs: Vec<_> = dir_list.iter().map(|path| path.to_string_lossy()).collect();
+    let mut header = json!({
+        "program": crate_name!(),
+        "version": FORMAT_VERSION.to_string(),
+        "comments": comments,
+        "parameters": params,
+        "files": files,
+        "empty_dirs": empty_dirs
+    });
+    let features = tree_params.features();
+    if !features.is_empty() {
+        header["features"] = json!(features);
+    }
+    writeln!(writer, "{}", header)?;
+    writer.flush()
+}
+
+// Byte ranges are offset like write_long_entry, and both ranges include their ends
+pub(crate) fn write_jsonl_entry<W: Write>(writer: &mut W, entry_index: usize,
+        hash_range: &HashRange, byte_offset: u64, uppercase: bool) -> io::Result<()> {
+    let block_range = hash_range.block_range();
+    let byte_range = offset_byte_range(hash_range.byte_range(), byte_offset);
+    let entry = json!({
+        "file_index": entry_index,
+        "block_range": [block_range.start(), inclusive_end(block_range)],
+        "byte_range": [byte_range.start(), inclusive_end(byte_range)],
+        "hash": hash_hex(hash_range.hash_result(), uppercase)
+    });
+    writeln!(writer, "{}", entry)
+}
+
+#[inline]
+pub(crate) fn inclusive_end(range: BlockRange) -> u64 {
+    match range.include_end() {
+        true => range.end(),
+        false => range.end() - 1
+    }
+}
+
+// Text header lines up to and including the Hashes: line
+fn header_to_text(header: &Value) -> Option<String> {
+    let mut text = format!("{} v{}\n", header["program"].as_str()?,
+        header["version"].as_str()?);
+    if let Some(features) = header.get("features") {
+        let features = features.as_array()?.iter()
+            .map(Value::as_str)
+            .collect::<Option<Vec<_>>>()?;
+        text += &format!("{}{}\n", FEATURES_PREFIX, features.join(", "));
+    }
+    for comment in header["comments"].as_array()? {
+        text += &format!("Comment: {}\n",
+            enquote::enquote('"', &escape_chars(comment.as_str()?)));
+    }
+    for (key, value) in header["parameters"].as_object()? {
+        let value_str = match value {
+            Value::String(value_str) => value_str.clone(),
+            Value::Number(_) | Value::Bool(_) => value.to_string(),
+            _ => return None
+        };
+        text += &format!("{}: {}\n", key, value_str);
+    }
+    text += "Files:\n";
+    for file in header["files"].as_array()? {
+        let paths = file["names"].as_array()?.iter()
+            .map(|name| name.as_str().map(PathBuf::from))
+            .collect::<Option<Vec<_>>>()?;
+        text += &match file.get("hashed_length") {
+            Some(hashed_len) => format!("{} {:#x} bytes ({:#x} hashed)\n",
+                quote_name_group(&paths), file["length"].as_u64()?, hashed_len.as_u64()?),
+            None => format!("{} {:#x} bytes\n",
+                quote_name_group(&paths), file["length"].as_u64()?)
+        };
+    }
+    for dir in header["empty_dirs"].as_array()? {
+        text += &format!("{} empty directory\n",
+            enquote::enquote('"', &escape_chars(dir.as_str()?)));
+    }
+    text += "Hashes:\n";
+    Some(text)
+}
+
+fn entry_to_text(entry: &Value) -> Option<String> {
+    let range = |key: &str| -> Option<BlockRange> {
+        match entry[key].as_array()?.as_slice() {
+            [start, end] if start.as_u64()? <= end.as_u64()? =>
+                Some(BlockRange::new(start.as_u64()?, end.as_u64()?, true)),
+            _ => None
+        }
+    };
+    Some(format!("{:3} {} {} {}\n", entry["file_index"].as_u64()?,
+        range("block_range")?, range("byte_range")?, entry["hash"].as_str()?))
+}
+
+// Lines that are not valid entries are passed through to be reported as malformed
+fn line_to_text(line: &[u8], is_first_line: bool) -> Vec<u8> {
+    let translated = serde_json::from_slice::<Value>(line).ok()
+        .and_then(|value| match is_first_line {
+            true => header_to_text(&value),
+            false => entry_to_text(&value)
+        });
+    match translated {
+        Some(text) => text.into_bytes(),
+        None => line.to_vec()
+    }
+}
+
+// Reads JSON Lines hash files as text hash files, and other hash files unchanged
+// Seeking is only accurate between lines, and discards any translated line
+#[derive(Debug)]
+pub(crate) struct JsonlReader<R: BufRead+Seek> {
+    reader: R,
+    is_jsonl: bool,
+    translated: Vec<u8>,
+    translated_pos: usize
+}
+impl<R: BufRead+Seek> JsonlReader<R> {
+    // The format is detected from the first byte of the reader
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let is_jsonl = reader.fill_buf()?.first() == Some(&b'{');
+        Ok(JsonlReader {reader, is_jsonl, translated: Vec::new(), translated_pos: 0})
+    }
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+}
+impl<R: BufRead+Seek> Read for JsonlReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read_len = available.len().min(buf.len());
+        buf[..read_len].copy_from_slice(&available[..read_len]);
+        self.consume(read_len);
+        Ok(read_len)
+    }
+}
+impl<R: BufRead+Seek> BufRead for JsonlReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if !self.is_jsonl {
+            return self.reader.fill_buf();
+        }
+        if self.translated_pos == self.translated.len() {
+            let is_first_line = self.reader.stream_position()? == 0;
+            let mut line: Vec<u8> = Vec::new();
+            self.reader.read_until(b'\n', &mut line)?;
+            self.translated = match line.is_empty() {
+                true => line,
+                false => line_to_text(&line, is_first_line)
+            };
+            self.translated_pos = 0;
+        }
+        Ok(&self.translated[self.translated_pos..])
+    }
+    fn consume(&mut self, amt: usize) {
+        match self.is_jsonl {
+            true => self.translated_pos += amt,
+            false => self.reader.consume(amt)
+        }
+    }
+}
+impl<R: BufRead+Seek> Seek for JsonlReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => {},
+            _ => {
+                self.translated.clear();
+                self.translated_pos = 0;
+            }
+        }
+        self.reader.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_enum::HashFunctions;
+    use merkle_tree::{HashData, NodeEncoding};
+    use std::io::Cursor;
+
+    const TREE_PARAMS: TreeParams = TreeParams {
+        block_size: 4,
+        branch_factor: 2,
+        hash_function: HashFunctions::crc32,
+        node_encoding: NodeEncoding::Plain,
+        salted: false,
+        pad_tree: false,
+        unbalanced: false,
+        duplicate_last: false,
+        metadata_only: false,
+        trim_zeros: false,
+        region_offset: None,
+        absolute_ranges: false,
+        leaves_and_root: false,
+        content_defined: false,
+        normalize_eol: false,
+        relative_names: false,
+        nominal_ranges: false,
+        stride: None,
+        filtered: false
+    };
+
+    #[test]
+    fn jsonl_read_as_text() {
+        let mut jsonl: Vec<u8> = Vec::new();
+        let paths = [PathBuf::from("a\tb")];
+        write_jsonl_header(&mut jsonl, &TREE_PARAMS, &["note".to_owned()],
+            &[(&paths, 5, None)], &[]).unwrap();
+        let hash = HashData::try_new(&[0x01, 0x02, 0x03, 0x04]).unwrap();
+        write_jsonl_entry(&mut jsonl, 0, &HashRange::new(BlockRange::new(0, 2, false),
+            BlockRange::new(0, 4, true), hash), 0, false).unwrap();
+
+        let mut text = String::new();
+        JsonlReader::new(Cursor::new(jsonl)).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, format!(concat!(
+            "{} v{}\n",
+            "Comment: \"note\"\n",
+            "Hash function: crc32\n",
+            "Block size: 4\n",
+            "Branching factor: 2\n",
+            "Files:\n",
+            "\"a\\\\tb\" 0x5 bytes\n",
+            "Hashes:\n",
+            "  0 [0x00000000-0x00000001] [0x00000000-0x00000004] 01020304\n"),
+            crate_name!(), FORMAT_VERSION));
+    }
+    #[test]
+    fn text_read_unchanged() {
+        let text = "merkle_tree_checksum v0.5.0\n{\"not\": \"translated\"}\n";
+        let mut read_text = String::new();
+        JsonlReader::new(Cursor::new(text)).unwrap().read_to_string(&mut read_text).unwrap();
+        assert_eq!(read_text, text);
+    }
+}
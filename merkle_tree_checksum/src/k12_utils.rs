@@ -0,0 +1,172 @@
+// KangarooTwelve (KT128 in RFC 9861) with an empty customization string
+// and a fixed 256-bit output, built on the TurboSHAKE128 of sha3
+#![forbid(unsafe_code)]
+
+use digest::{ExtendableOutput, FixedOutput, OutputSizeUser, Update, Reset, HashMarker};
+use digest::core_api::CoreWrapper;
+use generic_array::GenericArray;
+use generic_array::typenum::U32;
+use sha3::{TurboShake128, TurboShake128Core};
+
+const CHUNK_LEN: usize = 8192;
+const CHAINING_VALUE_LEN: usize = 32;
+
+// Domain separation bytes for a single chunk, leaf chunks, and the final node
+const SINGLE_NODE_DOMAIN: u8 = 0x07;
+const LEAF_NODE_DOMAIN: u8 = 0x0b;
+const FINAL_NODE_DOMAIN: u8 = 0x06;
+
+#[inline]
+fn turbo_shake_128(domain_separation: u8) -> TurboShake128 {
+    CoreWrapper::from_core(TurboShake128Core::new(domain_separation))
+}
+
+// Big-endian bytes of val without leading zeros, followed by their count
+fn length_encode(val: u64) -> Vec<u8> {
+    let val_bytes = val.to_be_bytes();
+    let leading_zeros = val_bytes.iter().take_while(|byte| **byte == 0x00).count();
+    let mut encoding = val_bytes[leading_zeros..].to_vec();
+    encoding.push(encoding.len() as u8);
+    encoding
+}
+
+// Leaf chunks only get started once there is input past the first chunk,
+// as a message that fits in the first chunk is hashed without a tree
+#[derive(Clone)]
+pub struct K12 {
+    first_chunk: Vec<u8>,
+    final_node: Option<TurboShake128>,
+    leaf_node: TurboShake128,
+    leaf_len: usize,
+    leaf_count: u64
+}
+impl Default for K12 {
+    fn default() -> Self {
+        K12 {
+            first_chunk: Vec::with_capacity(CHUNK_LEN),
+            final_node: None,
+            leaf_node: turbo_shake_128(LEAF_NODE_DOMAIN),
+            leaf_len: 0,
+            leaf_count: 0
+        }
+    }
+}
+impl K12 {
+    fn absorb(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            match &mut self.final_node {
+                None if self.first_chunk.len() < CHUNK_LEN => {
+                    let fill_len = (CHUNK_LEN - self.first_chunk.len()).min(data.len());
+                    self.first_chunk.extend_from_slice(&data[..fill_len]);
+                    data = &data[fill_len..];
+                },
+                None => {
+                    let mut final_node = turbo_shake_128(FINAL_NODE_DOMAIN);
+                    final_node.update(&self.first_chunk);
+                    final_node.update(&[0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+                    self.final_node = Some(final_node);
+                },
+                Some(final_node) if self.leaf_len == CHUNK_LEN => {
+                    let mut chaining_value = [0x00; CHAINING_VALUE_LEN];
+                    std::mem::replace(&mut self.leaf_node, turbo_shake_128(LEAF_NODE_DOMAIN))
+                        .finalize_xof_into(&mut chaining_value);
+                    final_node.update(&chaining_value);
+                    self.leaf_len = 0;
+                    self.leaf_count += 1;
+                },
+                Some(_) => {
+                    let fill_len = (CHUNK_LEN - self.leaf_len).min(data.len());
+                    self.leaf_node.update(&data[..fill_len]);
+                    self.leaf_len += fill_len;
+                    data = &data[fill_len..];
+                }
+            }
+        }
+    }
+}
+
+impl OutputSizeUser for K12 {
+    type OutputSize = U32;
+}
+impl HashMarker for K12 {}
+
+impl FixedOutput for K12 {
+    fn finalize_into(mut self, out: &mut GenericArray<u8, Self::OutputSize>) {
+        // Encoding of the empty customization string
+        self.absorb(&length_encode(0));
+        match self.final_node {
+            None => {
+                let mut single_node = turbo_shake_128(SINGLE_NODE_DOMAIN);
+                single_node.update(&self.first_chunk);
+                single_node.finalize_xof_into(out);
+            },
+            Some(mut final_node) => {
+                let mut chaining_value = [0x00; CHAINING_VALUE_LEN];
+                self.leaf_node.finalize_xof_into(&mut chaining_value);
+                final_node.update(&chaining_value);
+                final_node.update(&length_encode(self.leaf_count + 1));
+                final_node.update(&[0xff, 0xff]);
+                final_node.finalize_xof_into(out);
+            }
+        }
+    }
+}
+
+impl Update for K12 {
+    #[inline]
+    fn update(&mut self, data: &[u8]) {
+        self.absorb(data);
+    }
+}
+
+impl Reset for K12 {
+    #[inline]
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use digest::Digest;
+
+    // Repeating pattern of 0x00 to 0xfa from the RFC 9861 test vectors
+    fn ptn(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_length_encode() {
+        assert_eq!(length_encode(0), [0x00]);
+        assert_eq!(length_encode(12), [0x0c, 0x01]);
+        assert_eq!(length_encode(65538), [0x01, 0x00, 0x02, 0x03]);
+    }
+    #[test]
+    fn test_rfc9861_vectors() {
+        // Lengths of 17^i bytes, where 17^4 and above are hashed as a tree
+        let vectors = [
+            (0, "1ac2d450fc3b4205d19da7bfca1b37513c0803577ac7167f06fe2ce1f0ef39e5"),
+            (1, "2bda92450e8b147f8a7cb629e784a058efca7cf7d8218e02d345dfaa65244a1f"),
+            (17, "6bf75fa2239198db4772e36478f8e19b0f371205f6a9a93a273f51df37122888"),
+            (289, "0c315ebcdedbf61426de7dcf8fb725d1e74675d7f5327a5067f367b108ecb67c"),
+            (4913, "cb552e2ec77d9910701d578b457ddf772c12e322e4ee7fe417f92c758f0d59d0"),
+            (83521, "8701045e22205345ff4dda05555cbb5c3af1a771c2b89baef37db43d9998b9fe")
+        ];
+        for (len, expected_hex) in vectors {
+            assert_eq!(hex::encode(K12::digest(ptn(len))), expected_hex, "length {}", len);
+        }
+    }
+    #[test]
+    fn test_split_updates() {
+        // Split around the chunk boundaries to exercise buffering across updates
+        let input = ptn(3*CHUNK_LEN + 5);
+        let expected = K12::digest(&input);
+        for split in [1, CHUNK_LEN - 1, CHUNK_LEN, CHUNK_LEN + 1, 2*CHUNK_LEN, 3*CHUNK_LEN] {
+            let mut hasher = K12::new();
+            Digest::update(&mut hasher, &input[..split]);
+            Digest::update(&mut hasher, &input[split..]);
+            assert_eq!(hasher.finalize(), expected, "split at {}", split);
+        }
+    }
+}
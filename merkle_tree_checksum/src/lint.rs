@@ -0,0 +1,526 @@
+#![forbid(unsafe_code)]
+// Structural checks of hash file entries, without reading the hashed files
+
+use crate::error_types::{CoverageErr, LintIssue};
+use crate::parse_functions::{extract_quoted_filename, extract_empty_dir_name};
+use crate::parse_functions::{extract_long_hash_parts, extract_short_hash_parts};
+use crate::parse_functions::{extract_metadata_hash_parts, unquote_name_group};
+use crate::parse_functions::extract_section_marker;
+use crate::utils::{StoredAndComputed, TreeParams};
+use crate::utils::{end_exclusive, offset_byte_range, is_listed_node};
+
+use merkle_tree::{merkle_block_generator, BlockRange};
+
+use crate::exit_codes::ExitCode;
+use crate::format_version::EMPTY_DIR_MIN_VERSION;
+use crate::hash_file_input::{open_hash_file, read_hash_header};
+
+use clap::{Command, Arg, ArgAction};
+
+use std::io::BufRead;
+use std::iter::Peekable;
+
+// Issues paired with 1-indexed line numbers
+pub(crate) type LintReport = Vec<(usize, LintIssue)>;
+
+// merkle_block_generator emits nodes in post-order
+// Nodes ending past the last block all contain it, so clamping keeps the order
+#[inline]
+fn post_order_key(range: BlockRange, block_count: u64) -> (u64, u64) {
+    (end_exclusive(range).min(block_count), range.range())
+}
+
+// Consumes expected ranges ordered before until_key (or all if None)
+fn skip_missing<I>(expected: &mut Peekable<I>, file_id: usize, block_count: u64,
+        until_key: Option<(u64, u64)>) -> Option<LintIssue>
+where
+    I: Iterator<Item = BlockRange>
+{
+    let is_before = |range: &BlockRange| match until_key {
+        Some(key) => post_order_key(*range, block_count) < key,
+        None => true
+    };
+    let first_missing = expected.next_if(is_before)?;
+    let mut missing_count = 1;
+    while expected.next_if(is_before).is_some() {
+        missing_count += 1;
+    }
+    Some(LintIssue::MissingBlockRanges(file_id, first_missing, missing_count))
+}
+
+// Expected ranges for the entries of one file, in order
+struct FileCursor {
+    file_id: usize,
+    expected: Peekable<Box<dyn Iterator<Item = BlockRange>>>,
+    block_count: u64,
+    file_len: u64
+}
+impl FileCursor {
+    fn new(file_id: usize, file_len: u64, tree_params: &TreeParams) -> Self {
+        let listed_root = tree_params.listed_root(file_len);
+        let (tree_len, tree_block_size) = tree_params.tree_shape(file_len);
+        let block_iter = merkle_block_generator(tree_len,
+                tree_block_size, tree_params.branch_factor).into_iter()
+            .filter(move |range| is_listed_node(*range, listed_root));
+        FileCursor {
+            file_id,
+            expected: (Box::new(block_iter) as Box<dyn Iterator<Item = BlockRange>>)
+                .peekable(),
+            block_count: tree_len.div_ceil(tree_block_size.into()),
+            file_len
+        }
+    }
+    // Reports all remaining ranges as missing
+    fn finish(mut self) -> Option<LintIssue> {
+        skip_missing(&mut self.expected, self.file_id, self.block_count, None)
+    }
+}
+
+// Section markers of hash files written with --sectioned, which must name
+// the file of the entries right after them, and start the entries of every file
+struct MarkerCheck {
+    file_names: Vec<Vec<String>>,
+    // File ID and line number of the last marker, until an entry follows it
+    pending: Option<(usize, usize)>,
+    last_file_id: Option<usize>
+}
+impl MarkerCheck {
+    // Whether line is a marker, which is then checked against the next entry
+    fn read_marker(&mut self, line: &str, line_number: usize, issues: &mut LintReport) -> bool {
+        let Some((file_id, names)) = extract_section_marker(line) else {
+            return false;
+        };
+        if let Some((pending_id, pending_line)) = self.pending.take() {
+            issues.push((pending_line, LintIssue::MisplacedSectionMarker(pending_id)));
+        }
+        match self.file_names.get(file_id) == Some(&names) {
+            true => self.pending = Some((file_id, line_number)),
+            false => issues.push((line_number,
+                LintIssue::UnknownSectionMarker(line.trim_end().to_owned())))
+        }
+        true
+    }
+    fn check_entry(&mut self, file_id: usize, line_number: usize, issues: &mut LintReport) {
+        let starts_file = self.last_file_id != Some(file_id);
+        match self.pending.take() {
+            Some((pending_id, _)) if pending_id == file_id => {},
+            Some((pending_id, pending_line)) => {
+                issues.push((pending_line, LintIssue::MisplacedSectionMarker(pending_id)));
+            },
+            None if starts_file => {
+                issues.push((line_number, LintIssue::MissingSectionMarker(file_id)));
+            },
+            None => {}
+        }
+        self.last_file_id = Some(file_id);
+    }
+    fn finish(self, issues: &mut LintReport) {
+        if let Some((pending_id, pending_line)) = self.pending {
+            issues.push((pending_line, LintIssue::MisplacedSectionMarker(pending_id)));
+        }
+    }
+}
+
+// reader should be positioned just after the Files:/Hashes: line
+pub(crate) fn lint_hash_list<R: BufRead>(mut reader: R, header_line_count: usize,
+        tree_params: &TreeParams, is_short_hash: bool, sectioned: bool,
+        allow_empty_dirs: bool) -> LintReport {
+    let hex_digit_count = 2*tree_params.hash_function.hash_len();
+    let mut issues: LintReport = Vec::new();
+    let mut line_number = header_line_count;
+
+    // Returns None at EOF, and stops linting on read errors
+    macro_rules! next_line {
+        () => {{
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) => None,
+                Ok(_) => {
+                    line_number += 1;
+                    Some(line)
+                },
+                Err(err) => {
+                    issues.push((line_number+1, LintIssue::ReadError(err.kind())));
+                    return issues;
+                }
+            }
+        }}
+    }
+
+    if is_short_hash {
+        while let Some(line) = next_line!() {
+            match extract_short_hash_parts(&line, hex_digit_count) {
+                Ok((_, quoted_name)) => {
+                    if unquote_name_group(quoted_name).is_err() {
+                        issues.push((line_number,
+                            LintIssue::MalformedName(quoted_name.to_owned())));
+                    }
+                },
+                Err(err) => issues.push((line_number, err.into_lint_issue(line)))
+            }
+        }
+        return issues;
+    }
+
+    // Entries with malformed names still get an ID to keep later IDs aligned
+    let mut file_lens: Vec<u64> = Vec::new();
+    let mut file_names: Vec<Vec<String>> = Vec::new();
+    loop {
+        let line = match next_line!() {
+            Some(line) => line,
+            None => {
+                issues.push((line_number, LintIssue::MissingHashList));
+                return issues;
+            }
+        };
+        if line == "Hashes:\n" || line == "Hashes:\r\n" {
+            break;
+        }
+        if let Ok(quoted_name) = extract_empty_dir_name(&line) {
+            if !allow_empty_dirs {
+                issues.push((line_number, LintIssue::UnsupportedEmptyDir));
+            }
+            if enquote::unquote(quoted_name).is_err() {
+                issues.push((line_number,
+                    LintIssue::MalformedName(quoted_name.to_owned())));
+            }
+        } else if let Ok((quoted_name, Some(file_len), hashed_len)) = extract_quoted_filename(&line) {
+            match unquote_name_group(quoted_name) {
+                Ok(names) => file_names.push(names),
+                Err(_) => {
+                    issues.push((line_number,
+                        LintIssue::MalformedName(quoted_name.to_owned())));
+                    file_names.push(Vec::new());
+                }
+            }
+            // Entries cover only the hashed length of trimmed files
+            match hashed_len {
+                Some(hashed_len) if !tree_params.trim_zeros || hashed_len > file_len => {
+                    issues.push((line_number, LintIssue::MalformedEntry(line)));
+                    file_lens.push(hashed_len.min(file_len));
+                },
+                Some(hashed_len) => file_lens.push(hashed_len),
+                None => file_lens.push(file_len)
+            }
+        } else {
+            issues.push((line_number, LintIssue::MalformedEntry(line)));
+        }
+    }
+
+    let mut marker_check = sectioned.then_some(MarkerCheck {file_names,
+        pending: None, last_file_id: None});
+    // Metadata only hash files have one entry per file
+    if tree_params.metadata_only {
+        let mut next_id: usize = 0;
+        while let Some(line) = next_line!() {
+            if let Some(marker_check) = &mut marker_check {
+                if marker_check.read_marker(&line, line_number, &mut issues) {
+                    continue;
+                }
+            }
+            let file_id = match extract_metadata_hash_parts(&line, hex_digit_count) {
+                Ok((file_id, _)) => file_id,
+                Err(err) => {
+                    issues.push((line_number, err.into_lint_issue(line)));
+                    continue;
+                }
+            };
+            if let Some(marker_check) = &mut marker_check {
+                marker_check.check_entry(file_id, line_number, &mut issues);
+            }
+            if file_id >= file_lens.len() {
+                issues.push((line_number, LintIssue::UnknownFileID(file_id)));
+            } else if file_id < next_id {
+                issues.push((line_number,
+                    LintIssue::NonMonotonicFileID(file_id, next_id - 1)));
+            } else {
+                for skipped_id in next_id..file_id {
+                    issues.push((line_number, LintIssue::MissingMetadataEntry(skipped_id)));
+                }
+                next_id = file_id + 1;
+            }
+        }
+        for skipped_id in next_id..file_lens.len() {
+            issues.push((line_number, LintIssue::MissingMetadataEntry(skipped_id)));
+        }
+        if let Some(marker_check) = marker_check {
+            marker_check.finish(&mut issues);
+        }
+        return issues;
+    }
+
+    let mut current_file: Option<FileCursor> = None;
+    // Files before this ID have already had entries or been reported missing
+    let mut next_unstarted_id: usize = 0;
+    while let Some(line) = next_line!() {
+        if let Some(marker_check) = &mut marker_check {
+            if marker_check.read_marker(&line, line_number, &mut issues) {
+                continue;
+            }
+        }
+        let (file_id, hash_range) = match extract_long_hash_parts(&line, hex_digit_count) {
+            Ok(parts) => parts,
+            Err(err) => {
+                issues.push((line_number, err.into_lint_issue(line)));
+                continue;
+            }
+        };
+        if let Some(marker_check) = &mut marker_check {
+            marker_check.check_entry(file_id, line_number, &mut issues);
+        }
+        if file_id >= file_lens.len() {
+            issues.push((line_number, LintIssue::UnknownFileID(file_id)));
+            continue;
+        }
+        if file_id < next_unstarted_id {
+            let current_id = current_file.as_ref().unwrap().file_id;
+            if file_id != current_id {
+                issues.push((line_number,
+                    LintIssue::NonMonotonicFileID(file_id, current_id)));
+                continue;
+            }
+        } else {
+            let skipped_files = (next_unstarted_id..file_id)
+                .map(|skipped_id| FileCursor::new(skipped_id, file_lens[skipped_id], tree_params));
+            for finished_file in current_file.take().into_iter().chain(skipped_files) {
+                if let Some(issue) = finished_file.finish() {
+                    issues.push((line_number, issue));
+                }
+            }
+            current_file = Some(FileCursor::new(file_id, file_lens[file_id], tree_params));
+            next_unstarted_id = file_id + 1;
+        }
+
+        let cursor = current_file.as_mut().unwrap();
+        let block_count = cursor.block_count;
+        let entry_key = post_order_key(hash_range.block_range(), block_count);
+        if let Some(issue) = skip_missing(&mut cursor.expected, file_id,
+                block_count, Some(entry_key)) {
+            issues.push((line_number, issue));
+        }
+        match cursor.expected.next_if(|range| post_order_key(*range, block_count) == entry_key) {
+            Some(expected_range) => {
+                let expected_bytes = offset_byte_range(
+                    tree_params.node_byte_range(expected_range, cursor.file_len),
+                    tree_params.byte_range_offset());
+                if hash_range.byte_range() != expected_bytes {
+                    issues.push((line_number, LintIssue::MismatchedByteRange(file_id,
+                        StoredAndComputed::new(hash_range.byte_range(), expected_bytes))));
+                }
+            },
+            None => issues.push((line_number,
+                LintIssue::UnexpectedBlockRange(file_id, hash_range.block_range())))
+        }
+    }
+
+    let skipped_files = file_lens.iter().copied().enumerate().skip(next_unstarted_id)
+        .map(|(skipped_id, file_len)| FileCursor::new(skipped_id, file_len, tree_params));
+    for finished_file in current_file.into_iter().chain(skipped_files) {
+        if let Some(issue) = finished_file.finish() {
+            issues.push((line_number, issue));
+        }
+    }
+    if let Some(marker_check) = marker_check {
+        marker_check.finish(&mut issues);
+    }
+    issues
+}
+
+// First gap or overlap in the leaf byte ranges of one file, which must cover
+// the file_len bytes from byte_offset exactly once
+// Empty files have a single leaf covering no bytes, so are not checked
+pub(crate) fn check_leaf_coverage(mut leaf_byte_ranges: Vec<BlockRange>,
+        byte_offset: u64, file_len: u64) -> Result<(), CoverageErr> {
+    if file_len == 0 {
+        return Ok(());
+    }
+    leaf_byte_ranges.sort_by_key(|range| (range.start(), end_exclusive(*range)));
+    let file_end = byte_offset + file_len;
+    let mut next_byte = byte_offset;
+    for range in leaf_byte_ranges {
+        let range_end = end_exclusive(range);
+        if range.start() > next_byte {
+            return Err(CoverageErr::Gap(BlockRange::new(next_byte, range.start(), false)));
+        }
+        if range.start() < next_byte {
+            return Err(CoverageErr::Overlap(BlockRange::new(range.start(),
+                next_byte.min(range_end), false)));
+        }
+        if range_end > file_end {
+            return Err(CoverageErr::PastEnd(BlockRange::new(
+                range.start().max(file_end), range_end, false)));
+        }
+        next_byte = range_end;
+    }
+    match next_byte < file_end {
+        true => Err(CoverageErr::Gap(BlockRange::new(next_byte, file_end, false))),
+        false => Ok(())
+    }
+}
+
+pub(crate) const LINT_CMD_NAME: &str = "lint";
+
+pub(crate) fn lint_command() -> Command {
+    Command::new(LINT_CMD_NAME)
+        .about("Check the structure of a hash file")
+        .long_about(concat!("Check that the entries of a hash file ",
+            "are consistent with its header and recorded file lengths, ",
+            "reporting every problem found. ",
+            "The hashed files themselves are not read."))
+        .arg(Arg::new("FILE").required(true)
+            .action(ArgAction::Set)
+            .help("Hash file to check"))
+}
+
+// Reports every structural problem in a hash file
+pub(crate) fn lint_hash_file(file_name: &str, quiet_count: u8) -> ExitCode {
+    let mut hash_file_reader = match open_hash_file(file_name) {
+        Ok(reader) => reader,
+        Err(exit_code) => return exit_code
+    };
+    let header = match read_hash_header(&mut hash_file_reader) {
+        Ok(header) => header,
+        Err(exit_code) => return exit_code
+    };
+    // Entries can only be predicted from the file lengths with fixed blocks
+    if header.tree_params.content_defined {
+        eprintln!("Error: lint does not support hash files with content-defined chunks");
+        return ExitCode::CmdlineErr;
+    }
+    // Markers are checked by lint itself, which reports on bad ones
+    hash_file_reader.get_mut().keep_markers();
+    let issues = lint_hash_list(hash_file_reader, header.line_count,
+        &header.tree_params, header.is_short_hash, header.sectioned,
+        header.version >= EMPTY_DIR_MIN_VERSION);
+    for (line_number, issue) in &issues {
+        eprintln!("Error: line {}: {}", line_number, issue);
+    }
+    if issues.is_empty() {
+        if quiet_count < 2 {
+            eprintln!("Info: no problems found in {}", file_name);
+        }
+        ExitCode::Success
+    } else {
+        ExitCode::VerifBadEntryErr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_enum::HashFunctions;
+    use merkle_tree::NodeEncoding;
+
+    const TREE_PARAMS: TreeParams = TreeParams {
+        block_size: 4,
+        branch_factor: 2,
+        hash_function: HashFunctions::crc32,
+        node_encoding: NodeEncoding::Plain,
+        salted: false,
+        pad_tree: false,
+        unbalanced: false,
+        duplicate_last: false,
+        metadata_only: false,
+        trim_zeros: false,
+        region_offset: None,
+        absolute_ranges: false,
+        leaves_and_root: false,
+        content_defined: false,
+        normalize_eol: false,
+        relative_names: false,
+        nominal_ranges: false,
+        stride: None,
+        filtered: false
+    };
+    // 10 bytes with block size 4 and branch factor 2
+    const VALID_LIST: &str = concat!(
+        "\"ten_bytes\" 0xa bytes\n",
+        "Hashes:\n",
+        "  0 [0x00000000-0x00000000] [0x00000000-0x00000003] 00000000\n",
+        "  0 [0x00000001-0x00000001] [0x00000004-0x00000007] 00000000\n",
+        "  0 [0x00000000-0x00000002) [0x00000000-0x00000007] 00000000\n",
+        "  0 [0x00000002-0x00000002] [0x00000008-0x00000009] 00000000\n",
+        "  0 [0x00000002-0x00000004) [0x00000008-0x00000009] 00000000\n",
+        "  0 [0x00000000-0x00000004) [0x00000000-0x00000009] 00000000\n");
+
+    fn lint_str(list: &str) -> LintReport {
+        lint_hash_list(list.as_bytes(), 5, &TREE_PARAMS, false, false, true)
+    }
+
+    #[test]
+    fn lint_valid_list() {
+        assert_eq!(lint_str(VALID_LIST), vec![]);
+    }
+    #[test]
+    fn lint_missing_ranges() {
+        let mut lines: Vec<&str> = VALID_LIST.lines().collect();
+        lines.remove(3);
+        lines.remove(3);
+        let report = lint_str(&(lines.join("\n") + "\n"));
+        assert_eq!(report, vec![(9, LintIssue::MissingBlockRanges(0,
+            BlockRange::new(1, 1, true), 2))]);
+    }
+    #[test]
+    fn lint_length_mismatch() {
+        // The recorded length implies fewer entries than are present
+        let report = lint_str(&VALID_LIST.replace("0xa bytes", "0x8 bytes"));
+        assert_eq!(report.len(), 3);
+        assert!(matches!(report[0], (11, LintIssue::UnexpectedBlockRange(0, _))));
+    }
+    #[test]
+    fn lint_file_ids() {
+        let list = concat!(
+            "\"empty\" 0x0 bytes\n",
+            "\"empty_too\" 0x0 bytes\n",
+            "Hashes:\n",
+            "  1 [0x00000000-0x00000000] [0x00000000-0x00000000] 00000000\n",
+            "  0 [0x00000000-0x00000000] [0x00000000-0x00000000] 00000000\n",
+            "  2 [0x00000000-0x00000000] [0x00000000-0x00000000] 00000000\n");
+        assert_eq!(lint_str(list), vec![
+            (9, LintIssue::MissingBlockRanges(0, BlockRange::new(0, 0, true), 1)),
+            (10, LintIssue::NonMonotonicFileID(0, 1)),
+            (11, LintIssue::UnknownFileID(2))
+        ]);
+    }
+    #[test]
+    fn lint_section_markers() {
+        let list = concat!(
+            "\"empty\" 0x0 bytes\n",
+            "\"empty_too\" 0x0 bytes\n",
+            "Hashes:\n",
+            "# file 0: \"empty\"\n",
+            "  0 [0x00000000-0x00000000] [0x00000000-0x00000000] 00000000\n",
+            "# file 1: \"empty_too\"\n",
+            "  1 [0x00000000-0x00000000] [0x00000000-0x00000000] 00000000\n");
+        let lint_sectioned = |list: &str| {
+            lint_hash_list(list.as_bytes(), 5, &TREE_PARAMS, false, true, true)
+        };
+        assert_eq!(lint_sectioned(list), vec![]);
+        assert_eq!(lint_sectioned(&list.replace("# file 1: \"empty_too\"\n", "")),
+            vec![(11, LintIssue::MissingSectionMarker(1))]);
+        assert_eq!(lint_sectioned(&list.replace("# file 1: \"empty_too\"", "# file 1: \"c\"")),
+            vec![(11, LintIssue::UnknownSectionMarker("# file 1: \"c\"".to_owned())),
+                (12, LintIssue::MissingSectionMarker(1))]);
+        assert_eq!(lint_sectioned(&list.replace("# file 0", "# file 1")
+                .replace("# file 1: \"empty\"", "# file 1: \"empty_too\"")),
+            vec![(9, LintIssue::MisplacedSectionMarker(1))]);
+    }
+    #[test]
+    fn leaf_coverage_gaps_and_overlaps() {
+        let leaves = |ranges: &[(u64, u64)]| -> Vec<BlockRange> {
+            ranges.iter().map(|(start, end)| BlockRange::new(*start, *end, true)).collect()
+        };
+        // Leaves may be out of order
+        assert_eq!(check_leaf_coverage(leaves(&[(4, 7), (0, 3), (8, 9)]), 0, 10), Ok(()));
+        assert_eq!(check_leaf_coverage(leaves(&[(12, 15), (16, 17)]), 12, 6), Ok(()));
+        assert_eq!(check_leaf_coverage(leaves(&[(0, 0)]), 0, 0), Ok(()));
+        assert_eq!(check_leaf_coverage(leaves(&[(0, 3), (5, 9)]), 0, 10),
+            Err(CoverageErr::Gap(BlockRange::new(4, 5, false))));
+        assert_eq!(check_leaf_coverage(leaves(&[(0, 3), (4, 7)]), 0, 10),
+            Err(CoverageErr::Gap(BlockRange::new(8, 10, false))));
+        assert_eq!(check_leaf_coverage(leaves(&[(0, 4), (4, 9)]), 0, 10),
+            Err(CoverageErr::Overlap(BlockRange::new(4, 5, false))));
+        assert_eq!(check_leaf_coverage(leaves(&[(0, 3), (4, 11)]), 0, 10),
+            Err(CoverageErr::PastEnd(BlockRange::new(10, 12, false))));
+    }
+}
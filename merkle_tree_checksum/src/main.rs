@@ -1,43 +1,107 @@
 #![forbid(unsafe_code)]
 
 mod crc32_utils;
+mod sha512t_utils;
+mod k12_utils;
+mod ascon_utils;
+#[cfg(feature = "blake3_rayon")]
+mod blake3_utils;
 mod hash_enum;
 mod utils;
 mod error_types;
 mod format_functions;
 mod parse_functions;
+mod exit_codes;
+mod read_adapters;
+mod embed;
+mod lint;
+mod self_check;
+mod interrupt;
+mod verify_cache;
+mod file_checksum;
+mod config;
+mod diff_file;
+mod tree_structure;
+mod jsonl;
+mod format_version;
+mod hash_file_parser;
+mod stats;
+mod hash_sink;
+mod parity;
+mod tree_view;
+mod section_index;
+mod section_markers;
+mod ordered_jobs;
+mod xattr_params;
+mod follow;
+mod results;
+mod self_test;
+mod hash_file_input;
+mod output_files;
+mod repair;
+mod info;
+mod extract;
+mod convert;
+mod migrate;
 
 use std::thread;
 use crossbeam_channel::bounded as bounded_channel;
+use crossbeam_channel::unbounded as unbounded_channel;
 
-use std::fs::{File, OpenOptions};
-use std::io::{Write, Seek, SeekFrom, BufRead, BufReader, LineWriter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write, Seek, SeekFrom, BufRead, BufWriter, LineWriter, IsTerminal};
+use std::panic::UnwindSafe;
+use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use std::ops::Range;
 
-use semver::VersionReq;
 use parse_functions::{extract_long_hash_parts, extract_short_hash_parts, size_str_to_num};
-use std::path::PathBuf;
-use format_functions::{escape_chars, title_center, abbreviate_filename};
+use parse_functions::byte_count_str_to_num;
+use parse_functions::extract_metadata_hash_parts;
+use parse_functions::{extract_empty_dir_name, unquote_name_group, salt_str_to_bytes};
+use std::path::{Path, PathBuf};
+use format_functions::{title_center, abbreviate_filename, checksum_line, hash_hex};
 
-use indicatif::ProgressDrawTarget;
 
-use crc32_utils::Crc32;
-use sha2::{Sha224, Sha256, Sha384, Sha512, Sha512_224, Sha512_256};
-use sha3::{Sha3_224, Sha3_256, Sha3_384, Sha3_512};
-use blake2::{Blake2b512, Blake2s256};
-use blake3::Hasher as Blake3;
+use digest::Digest;
 
-use merkle_tree::{merkle_hash_file, merkle_block_generator};
-use merkle_tree::{HashData, HashRange};
-use merkle_tree::{branch_t, block_t};
-use merkle_tree::reorder_hashrange_iter;
+use merkle_tree::{merkle_hash_file_with_options, merkle_block_generator, block_byte_range};
+use merkle_tree::{NodeEncoding, TreeOptions, HashFileErr};
+use merkle_tree::{BlockRange, HashData, HashRange, Consumer, StatsConsumer, seek_len};
+use merkle_tree::{branch_t, block_t, MerkleTreeBuilder};
+use merkle_tree::{reorder_hashrange_iter, assume_ordered_hashrange_iter, MAX_HASH_LEN};
 
-use hash_enum::HashFunctions;
+use hash_enum::{HashFunctions, HashFunctionParser, with_digest};
 
-use utils::setup_pbs;
+use utils::{setup_pbs, ProgressOutput};
 use utils::StoredAndComputed;
-use utils::TreeParams;
-use utils::ChannelOrPb;
-use error_types::{PreHashError, HeaderParsingErr, VerificationError};
+use utils::{TreeParams, ByteRangeBase};
+use utils::{ChannelOrPb, offset_byte_range};
+use utils::{display_name_group, quote_name_group};
+use utils::{sidecar_paths, metadata_hash, is_listed_node};
+use utils::{long_format_size, LongFormatSize};
+use read_adapters::{ConcatReader, TruncatedReader, DataFile, Decompression,
+    ThrottledReader};
+use embed::EmbedTrailer;
+use verify_cache::VerifyCache;
+use follow::VerifiedPrefix;
+use results::{ResultsFile, FileResult, VerifyOutcome};
+use config::{config_path_arg, ConfigDefaults};
+use xattr_params::{read_xattr_params, PARAMS_XATTR_NAME};
+use diff_file::{read_stored_file, diff_leaves, reference_parts, first_differing_byte};
+use exit_codes::ExitCode;
+use format_version::FORMAT_VERSION;
+use jsonl::HashFileFormat;
+use hash_sink::{HashSink, SinkHeader, CoreutilsSink, RelativePathSink, WriterConsumer, new_sink};
+use parity::HashFileSource;
+use hash_file_parser::{HashFileHeader, HashEntry, FileEntry, parse_file_list};
+use hash_file_input::{read_hash_header, open_hash_file, open_hash_file_with_root, read_hash_source};
+use hash_file_input::{read_parsed_hash_file, parse_hash_file_contents, HashFileReader};
+use output_files::{open_output_file, create_output_file};
+use stats::{BlockStatsSummary, HashStats};
+use error_types::{PreHashError, VerificationError, PathListError};
+use error_types::{ConfigErr, DiffFileErr, ParseError};
+use error_types::{SectionIndexErr, TeeErr, XattrParamsErr};
 
 use std::convert::TryFrom;
 
@@ -45,25 +109,19 @@ use const_format::formatcp;
 
 use clap::{crate_authors, crate_description, crate_name, crate_version};
 use clap::{Command, Arg, ArgAction, ArgMatches};
-use clap::builder::EnumValueParser;
+use clap::parser::ValueSource;
+use clap::builder::{PossibleValuesParser, TypedValueParser};
 
 use git_version::git_version;
 
 const GENERATE_HASH_CMD_NAME: &str = "generate-hash";
 const VERIFY_HASH_CMD_NAME: &str = "verify-hash";
+const DIFF_FILE_CMD_NAME: &str = "diff-file";
 
-const EMPTY_STRING: String = String::new();
 
 const HELP_STR_HASH_LIST: &str = concat!("Supported hash functions are ",
     "the SHA2 family, the SHA3 family, Blake2b/Blake2s, Blake3, ",
-    "and CRC32.");
-
-const CMDLINE_ERR: i32 = 1;
-const DATA_READ_ERR: i32 = 2;
-const GEN_WRITE_ERR: i32 = 101; // Same exitcode as panic
-const VERIF_READ_ERR: i32 = 101; // Same exitcode as panic
-const VERIF_BAD_HEADER_ERR: i32 = 1;
-const VERIF_BAD_ENTRY_ERR: i32 = 3;
+    "KangarooTwelve, Ascon-Hash256, and CRC32.");
 
 const VERSION_STR: &str = formatcp!("{} ({}, rustc {})", crate_version!(),
             git_version!(prefix = "git:", fallback = "unknown"),
@@ -73,7 +131,7 @@ const VERSION_STR: &str = formatcp!("{} ({}, rustc {})", crate_version!(),
 #[derive(Debug)]
 enum HashCommand<W, R>
 where
-    W: Write+Send+std::fmt::Debug,
+    W: HashSink+Send,
     R: BufRead+Seek+Send+std::fmt::Debug
 {
     GenerateHash(Option<W>),
@@ -82,7 +140,7 @@ where
 
 fn main() {
     let status_code = run();
-    std::process::exit(status_code);
+    std::process::exit(status_code.code());
 }
 
 fn parse_cli() -> Result<ArgMatches, clap::Error> {
@@ -98,7 +156,7 @@ fn parse_cli() -> Result<ArgMatches, clap::Error> {
         .after_help(gen_hash_after_help)
         .arg(Arg::new("hash").long("hash-function").short('f')
             .action(ArgAction::Set)
-            .value_parser(EnumValueParser::<HashFunctions>::new())
+            .value_parser(HashFunctionParser)
             .default_value("sha256")
             .ignore_case(true)
             .help("Hash function to use"))
@@ -113,24 +171,555 @@ fn parse_cli() -> Result<ArgMatches, clap::Error> {
             .value_parser(size_str_to_num)
             .help("Block size to hash over, in bytes")
             .long_help(concat!("Block size to hash over, in bytes ",
-                "(SI prefixes K,M,G and IEC prefixes Ki,Mi,Gi accepted")))
+                "(SI prefixes K,M,G and IEC prefixes Ki,Mi,Gi accepted, ",
+                "optionally followed by B, as in 4096B or 4KiB)")))
+        .arg(Arg::new("paramsfromxattr").long("params-from-xattr")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["tee", "coreutils"])
+            .help("Read tree parameters from an extended attribute of the first file")
+            .long_help(concat!("Read the block length, branch factor, and hash function ",
+                "from the user.merkle.params extended attribute of the first file, ",
+                "which holds lines like those of a hash file header, ",
+                "such as \"Block size: 4096\". ",
+                "Flags given on the command line take precedence. ",
+                "Where extended attributes are unsupported or the attribute is absent, ",
+                "the flag values are used with a warning.")))
+        .arg(Arg::new("nodeencoding").long("node-encoding")
+            .action(ArgAction::Set)
+            .default_value("plain")
+            .value_parser(PossibleValuesParser::new(["plain", "child-count"])
+                .map(|s| s.parse::<NodeEncoding>().unwrap()))
+            .conflicts_with("embed")
+            .help("Encoding of internal tree nodes")
+            .long_help(concat!("Encoding of internal tree nodes. ",
+                "child-count also hashes the number of children ",
+                "and the length covered by each child, ",
+                "binding the shape of the tree to its hash.")))
+        .arg(Arg::new("padtree").long("pad-tree")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("embed")
+            .help("Hash padding leaves to make the tree perfectly balanced")
+            .long_help(concat!("Hash empty padding leaves past the end ",
+                "of each file, up to the next power of the branch factor, ",
+                "so that every internal node has exactly branch factor ",
+                "children. Padding leaves do not get their own entries.")))
+        .arg(Arg::new("unbalanced").long("unbalanced")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["embed", "padtree"])
+            .help("Promote lone children instead of hashing them again")
+            .long_help(concat!("Give internal nodes with a single child ",
+                "the hash of that child instead of hashing it again, ",
+                "so that a subtree without siblings is promoted ",
+                "rather than padded. With a branch factor of 2 and ",
+                "the plain node encoding, root hashes then match the ",
+                "RFC 6962 Merkle trees used by Certificate Transparency, ",
+                "with each block as a log entry.")))
+        .arg(Arg::new("duplicatelast").long("duplicate-last")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["embed", "padtree", "unbalanced"])
+            .help("Fill incomplete internal nodes by repeating their last child")
+            .long_help(concat!("Complete internal nodes with fewer than ",
+                "branch factor children by repeating the hash of the ",
+                "last child. With a branch factor of 2, this is how ",
+                "Bitcoin Merkle trees handle a level with an odd number ",
+                "of nodes. Repeated children do not get their own entries.")))
+        .arg(Arg::new("cdc").long("cdc")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["embed", "leavesandroot", "metadataonly"])
+            .help("Split files into content-defined chunks instead of blocks")
+            .long_help(concat!("Split files at boundaries chosen by a rolling hash ",
+                "of the data instead of into fixed-size blocks, ",
+                "so that inserting or removing bytes only changes ",
+                "the hashes of nearby chunks. The block length is the ",
+                "target chunk size and must be a power of 2, and chunks are ",
+                "between a quarter of and four times the block length long. ",
+                "Files are read once more to find the chunks. ",
+                "lint and diff-file do not support these hash files.")))
+        .arg(Arg::new("leavesandroot").long("leaves-and-root")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["short", "embed", "metadataonly"])
+            .help("List only leaf and root hashes")
+            .long_help(concat!("List only the hashes of leaves and of the root ",
+                "of each file, leaving out the other internal nodes. ",
+                "This makes the hash file smaller while still locating ",
+                "corruption to a single block.")))
+        .arg(Arg::new("trimzeros").long("trim-trailing-zeros")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["short", "embed"])
+            .help("Leave trailing zero bytes of each file out of the tree")
+            .long_help(concat!("Hash each file only up to the start of its ",
+                "trailing run of zero bytes, as is common in sparse disk images. ",
+                "Both the file length and the hashed length are recorded, ",
+                "and verification checks that the trimmed bytes are still zero.")))
+        .arg(Arg::new("salt").long("salt")
+            .action(ArgAction::Set)
+            .value_parser(salt_str_to_bytes)
+            .conflicts_with("embed")
+            .help("Hex-encoded salt to mix into each leaf hash")
+            .long_help(concat!("Hex-encoded salt to mix into each leaf hash, ",
+                "so that identical blocks have different hashes ",
+                "across hash files. The hash file only records that a salt ",
+                "was used, so verification needs the same --salt.")))
+        .arg(Arg::new("comment").long("comment")
+            .action(ArgAction::Append)
+            .conflicts_with("embed")
+            .help("Comment line to record in the hash file header")
+            .long_help(concat!("Comment line to record in the hash file header, ",
+                "e.g. a build ID. Can be given multiple times ",
+                "for multiple comment lines. ",
+                "Comments are shown during verification but are not checked.")))
+        .arg(Arg::new("selfchecksum").long("self-checksum")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("embed")
+            .help("End the hash file with a checksum of its contents")
+            .long_help(concat!("End the hash file with a File-Checksum line ",
+                "holding the CRC32 of every line before it. ",
+                "verify-hash checks this line first, if present, ",
+                "so that a damaged hash file is reported before any ",
+                "files are hashed.")))
+        .arg(Arg::new("withparity").long("with-parity")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("embed")
+            .help("End every line of the hash file with parity for repair")
+            .long_help(concat!("End every line of the hash file with parity, ",
+                "so that the repair subcommand can correct a single damaged ",
+                "character in each line. Hash files with parity are ",
+                "detected and checked when they are read.")))
+        .arg(Arg::new("decompress").long("decompress")
+            .action(ArgAction::Set)
+            .default_value("none")
+            .value_parser(PossibleValuesParser::new(["none", "gzip", "zstd", "auto"])
+                .map(|s| s.parse::<Decompression>().unwrap()))
+            .conflicts_with_all(["embed", "trimzeros", "metadataonly"])
+            .help("Decompress files before hashing them")
+            .long_help(concat!("Decompress files before hashing them, ",
+                "so that the hashes cover the decompressed data ",
+                "and do not depend on compression settings. ",
+                "auto decompresses files with a .gz or .zst extension or a gzip or zstd header. ",
+                "The decompressed length is recorded, and verification ",
+                "needs the same --decompress.")))
+        .arg(Arg::new("filtercommand").long("filter-command")
+            .action(ArgAction::Set)
+            .value_name("COMMAND")
+            .conflicts_with_all(["decompress", "embed", "trimzeros", "metadataonly"])
+            .help("Hash the output of a shell command given each file as stdin")
+            .long_help(concat!("Run COMMAND with sh -c for each file, with the file ",
+                "as its stdin, and hash its output instead of the file, ",
+                "such as to hash the plaintext of an encrypted file. ",
+                "The output is read twice, once for its length, so COMMAND ",
+                "must give the same output each time, and must exit successfully. ",
+                "The hash file records that a filter was used but not COMMAND, ",
+                "and verification needs an equivalent --filter-command.")))
+        .arg(Arg::new("normalizeeol").long("normalize-eol")
+            .visible_alias("text")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["embed", "trimzeros", "metadataonly"])
+            .help("Hash text files with CRLF line endings read as LF")
+            .long_help(concat!("Hash text files with CRLF line endings read as LF, ",
+                "so that checkouts with different line ending settings ",
+                "hash the same. Lengths and byte ranges are of the normalized data. ",
+                "This is recorded in the hash file, and verification ",
+                "normalizes line endings in the same way.")))
+        .arg(Arg::new("offset").long("offset")
+            .action(ArgAction::Set)
+            .value_parser(byte_count_str_to_num)
+            .conflicts_with_all(["short", "embed", "trimzeros", "metadataonly"])
+            .help("Hash only the region of each file starting at this byte")
+            .long_help(concat!("Hash only the region of each file starting ",
+                "at this byte, e.g. a partition in a disk image. ",
+                "The region extends to --length or to the end of the file. ",
+                "The offset is recorded, and verification hashes the same region ",
+                "without checking data outside of it.")))
+        .arg(Arg::new("length").long("length")
+            .action(ArgAction::Set)
+            .value_parser(byte_count_str_to_num)
+            .conflicts_with_all(["short", "embed", "trimzeros", "metadataonly"])
+            .help("Length in bytes of the region of each file to hash")
+            .long_help(concat!("Length in bytes of the region of each file to hash, ",
+                "starting at --offset or at the start of the file. ",
+                "Files must be long enough to contain the whole region.")))
+        .arg(Arg::new("byterangebase").long("byte-range-base")
+            .action(ArgAction::Set)
+            .value_name("BASE")
+            .default_value("relative")
+            .value_parser(PossibleValuesParser::new(["relative", "absolute"])
+                .map(|s| s.parse::<ByteRangeBase>().unwrap()))
+            .help("Where recorded byte ranges count from")
+            .long_help(concat!("Where recorded byte ranges count from. ",
+                "relative counts from the start of the region given by --offset, ",
+                "and absolute from the start of the file, or of the concatenated ",
+                "files with --concat. The base is recorded with the region offset, ",
+                "so that verification reads the ranges the same way. ",
+                "Without --offset or --length, both bases give the same ranges.")))
+        .arg(Arg::new("nominalranges").long("nominal-ranges")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["short", "metadataonly", "cdc", "nodeencoding"])
+            .help("Record byte ranges that end at a whole block")
+            .long_help(concat!("Record the byte range of the last block of a ",
+                "file as the full block, as if the file were padded to a ",
+                "multiple of the block length, instead of ending it at the ",
+                "last byte of the file. Nodes covering that block end there ",
+                "as well. The hashes are unchanged.")))
+        .arg(Arg::new("stride").long("stride")
+            .action(ArgAction::Set)
+            .value_name("BYTES")
+            .value_parser(size_str_to_num)
+            .conflicts_with_all(["embed", "metadataonly", "cdc", "nominalranges",
+                "nodeencoding", "tee", "coreutils", "dryrun"])
+            .help("Start consecutive blocks this many bytes apart")
+            .long_help(concat!("Start each block this many bytes after the start ",
+                "of the previous block instead of one block length after it, ",
+                "so that blocks overlap when the stride is less than the ",
+                "block length, as some error-correction layouts need. ",
+                "The stride must be at most the block length, and the last ",
+                "block is the first one to reach the end of the file. ",
+                "Byte ranges of overlapping blocks overlap as well. ",
+                "diff-file, --follow, and --check-coverage ",
+                "do not support these hash files.")))
+        .arg(Arg::new("metadataonly").long("metadata-only")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["short", "embed", "concat", "perfile",
+                "salt", "nodeencoding", "padtree", "unbalanced", "duplicatelast",
+                "trimzeros"])
+            .help("Hash file metadata instead of file contents")
+            .long_help(concat!("Hash the path, length, and modification time ",
+                "of each file instead of its contents, without reading it. ",
+                "This is a quick check for files that obviously changed, ",
+                "and cannot detect changes that preserve the modification time.")))
         .arg(Arg::new("output").long("output").short('o')
             .action(ArgAction::Set)
-            .required(true)
+            .required_unless_present_any(["perfile", "embed", "dryrun"])
+            .conflicts_with_all(["perfile", "embed", "dryrun"])
             .help("Output file"))
+        .arg(Arg::new("perfile").long("per-file")
+            .action(ArgAction::SetTrue)
+            .requires("outputdir")
+            .conflicts_with_all(["concat", "emptydirs"])
+            .help("Write a separate hash file for each file")
+            .long_help(concat!("Write a separate hash file for each file ",
+                "into the directory given by --output-dir, ",
+                "named after the file with a .mrkl extension. ",
+                "Files with the same name get a number appended.")))
+        .arg(Arg::new("embed").long("embed")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["perfile", "concat", "short", "emptydirs"])
+            .help("Append the summary hash to each file")
+            .long_help(concat!("Append the summary hash and tree parameters ",
+                "to the end of each file instead of writing a hash file. ",
+                "Use verify-hash --embedded to check these files.")))
+        .arg(Arg::new("outputdir").long("output-dir")
+            .action(ArgAction::Set)
+            .requires("perfile")
+            .help("Output directory for --per-file"))
         .arg(Arg::new("overwrite").long("overwrite")
             .action(ArgAction::SetTrue)
-            .help("Overwrite output file if it already exists"))
+            .help("Overwrite output files if they already exist"))
+        .arg(Arg::new("relativetooutput").long("relative-to-output")
+            .action(ArgAction::SetTrue)
+            .requires("output")
+            .conflicts_with("metadataonly")
+            .help("Store file names relative to the directory of the output file")
+            .long_help(concat!("Store file names relative to the directory ",
+                "of the output file instead of as they were given, ",
+                "and mark the hash file so that verify-hash resolves them ",
+                "against the directory of the hash file. The hash file ",
+                "can then be moved along with the files it lists.")))
+        .arg(Arg::new("signcommand").long("sign-command")
+            .action(ArgAction::Set)
+            .conflicts_with("embed")
+            .help("Command to run on each finished hash file")
+            .long_help(concat!("Shell command to run on each hash file ",
+                "after it is completely written, with the path of the ",
+                "hash file as its last argument, e.g. \"gpg --detach-sign\". ",
+                "If the command fails, the hash file is removed. ",
+                "The command runs with the privileges of this program, ",
+                "so only pass commands from trusted sources. ",
+                "A signature only vouches for the hash file, ",
+                "and must be checked separately before verify-hash.")))
+        .arg(Arg::new("rootout").long("root-out")
+            .action(ArgAction::Set)
+            .value_name("FILE")
+            .conflicts_with_all(["embed", "stream", "metadataonly", "dryrun"])
+            .help("Also write the root hash of each file to FILE")
+            .long_help(concat!("Also write the root hash and name of each file ",
+                "to FILE, one line per file in the \"<hex>  <name>\" format ",
+                "of sha256sum and similar tools, for publishing only the roots. ",
+                "The roots are Merkle tree roots rather than hashes ",
+                "of the whole file, so those tools can parse the file ",
+                "but its hashes will not match theirs.")))
+        .arg(Arg::new("algorithmid").long("algorithm-id")
+            .action(ArgAction::SetTrue)
+            .requires("rootout")
+            .help("Start the --root-out file with the name and OID of the hash function")
+            .long_help(concat!("Start the --root-out file with the canonical name ",
+                "of the hash function and its OID, or none if it has no OID, ",
+                "on lines starting with #. sha256sum and similar tools ",
+                "skip these lines when checking.")))
         .arg(Arg::new("short").long("short").short('s')
             .action(ArgAction::SetTrue)
             .help("Write only the summary hash")
             .long_help(concat!("Write only the summary hash to the output. ",
                 "This will make identifying corrupted locations impossible.")))
-        .arg(Arg::new("FILES").required(true)
+        .arg(Arg::new("markempty").long("mark-empty")
+            .action(ArgAction::SetTrue)
+            .requires("short")
+            .help("Write EMPTY instead of a hash for zero-length files")
+            .long_help(concat!("Write EMPTY instead of the summary hash ",
+                "for zero-length files, so that they do not all share ",
+                "the hash of empty data. Verification then checks that ",
+                "these files are still empty. Requires --short.")))
+        .arg(Arg::new("sectioned").long("sectioned")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["short", "format", "embed", "stream", "tee"])
+            .help("Start the entries of each file with a marker line")
+            .long_help(concat!("Start the entries of each file with a ",
+                "\"# file N: \"name\"\" marker line, to make long hash files ",
+                "easier to read and to find the entries of a file in. ",
+                "Readers check that each marker names the file of the ",
+                "entries after it, and otherwise skip it.")))
+        .arg(Arg::new("statsblocks").long("stats-blocks")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["short", "metadataonly", "stream", "coreutils", "tee",
+                "embed", "dedup"])
+            .help("Print counts of leaf, internal, and repeated leaf hashes")
+            .long_help(concat!("Print the number of leaf and internal nodes, ",
+                "the number of distinct leaf hashes, and the leaf hash shared by ",
+                "the most blocks, once all files are hashed, such as to find ",
+                "runs of identical blocks in a disk image. Every distinct leaf hash ",
+                "is kept in memory, and files are hashed one at a time. ",
+                "Printed to stderr even with --quiet.")))
+        .arg(Arg::new("mindepth").long("min-depth")
+            .action(ArgAction::Set)
+            .default_value("1")
+            .value_parser(clap::value_parser!(usize))
+            .help("Minimum depth of files to hash in directories"))
+        .arg(Arg::new("maxdepth").long("max-depth")
+            .action(ArgAction::Set)
+            .value_parser(clap::value_parser!(usize))
+            .help("Maximum depth of files to hash in directories")
+            .long_help(concat!("Maximum depth to descend into when ",
+                "expanding directories. Files directly in a given directory ",
+                "have depth 1, so --max-depth 1 hashes only files directly ",
+                "in the given directories. Unlimited if not specified.")))
+        .arg(Arg::new("norecurse").long("no-recurse")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["mindepth", "maxdepth", "skipunreadable", "emptydirs"])
+            .help("Report directories given as arguments as errors")
+            .long_help(concat!("Report directories given as arguments as ",
+                "errors instead of hashing the files in them, for when ",
+                "only files are expected. By default, directories are ",
+                "expanded to every file in them, at any depth.")))
+        .arg(Arg::new("skipunreadable").long("skip-unreadable")
+            .action(ArgAction::SetTrue)
+            .help("Skip unreadable entries when expanding directories")
+            .long_help(concat!("Report entries that cannot be read when ",
+                "expanding directories and continue without them. ",
+                "By default, hashing is aborted instead.")))
+        .arg(Arg::new("maxfilesize").long("max-file-size")
+            .action(ArgAction::Set)
+            .value_parser(byte_count_str_to_num)
+            .help("Skip files larger than this many bytes")
+            .long_help(concat!("Skip files larger than this many bytes, ",
+                "with a warning, so that one unexpectedly large file ",
+                "does not hold up hashing a directory. ",
+                "SI and IEC prefixes are accepted as in --block-length. ",
+                "Skipped files are left out of the hash file.")))
+        .arg(Arg::new("maxfilesizefatal").long("max-file-size-fatal")
+            .action(ArgAction::SetTrue)
+            .requires("maxfilesize")
+            .help("Abort instead of skipping files larger than --max-file-size"))
+        .arg(Arg::new("maxtotalbytes").long("max-total-bytes")
+            .action(ArgAction::Set)
+            .value_name("BYTES")
+            .value_parser(byte_count_str_to_num)
+            .conflicts_with_all(["stream", "coreutils", "tee", "hashtreestructure"])
+            .help("Stop hashing files once this many bytes have been read")
+            .long_help(concat!("Leave the remaining files out of the hash file ",
+                "once the files hashed so far add up to this many bytes, ",
+                "for quick spot checks of large sets of files. ",
+                "The file that reaches the limit is still hashed in full. ",
+                "SI and IEC prefixes are accepted as in --block-length. ",
+                "If any files were left out, the exit code is 4 ",
+                "to tell a partial hash file apart from a full one.")))
+        .arg(Arg::new("erroronempty").long("error-on-empty")
+            .action(ArgAction::SetTrue)
+            .help("Abort if any file to hash is empty")
+            .long_help(concat!("Abort if any file to hash is empty, ",
+                "for pipelines where an empty input means that an ",
+                "earlier step failed.")))
+        .arg(Arg::new("skipempty").long("skip-empty")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("erroronempty")
+            .help("Skip empty files with a warning")
+            .long_help(concat!("Skip empty files with a warning, ",
+                "instead of hashing them to the root of empty data. ",
+                "Skipped files are left out of the hash file.")))
+        .arg(Arg::new("dedup").long("dedup")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["concat", "embed", "metadataonly"])
+            .help("Hash identical files only once")
+            .long_help(concat!("Find files with identical contents before hashing, ",
+                "and hash each distinct content only once. ",
+                "Entries of the later copies are written from the first copy, ",
+                "so the hash file is the same as without --dedup.")))
+        .arg(Arg::new("stream").long("stream")
+            .action(ArgAction::SetTrue)
+            .requires("short")
+            .conflicts_with_all(["perfile", "concat", "sortentries", "dedup",
+                "hashtreestructure", "trimzeros", "emptydirs"])
+            .help("Hash files as directories are walked, for huge file lists")
+            .long_help(concat!("Hash each file as soon as it is found when ",
+                "walking directories and write its entry right away, ",
+                "instead of listing every file before hashing, ",
+                "so that memory use does not grow with the number of files. ",
+                "Entries are in the order the files were found. ",
+                "Only works with --short, which has no list of files ",
+                "before the hashes.")))
+        .arg(Arg::new("coreutils").long("coreutils")
+            .action(ArgAction::SetTrue)
+            .requires("short")
+            .conflicts_with_all(["branch", "blocksize", "nodeencoding", "padtree",
+                "unbalanced", "duplicatelast", "cdc", "leavesandroot", "trimzeros",
+                "salt", "comment", "selfchecksum", "withparity", "offset", "length",
+                "byterangebase", "perfile", "markempty", "concat", "sortentries",
+                "dedup", "hashtreestructure", "emptydirs", "format", "rootout",
+                "embed", "metadataonly", "dryrun", "filejobs"])
+            .help("Write plain file hashes in the format of sha256sum")
+            .long_help(concat!("Write a plain hash of each whole file ",
+                "in the \"<hex>  <name>\" format of sha256sum, b2sum, ",
+                "and similar tools, with no header, so that those tools ",
+                "can check the output with -c. ",
+                "These are not Merkle roots, so the tree options do not apply ",
+                "and verify-hash cannot read the output. ",
+                "Files are hashed as with --stream, which only works with --short.")))
+        .arg(Arg::new("tee").long("tee")
+            .action(ArgAction::Set)
+            .value_name("PATH")
+            .conflicts_with_all(["cdc", "trimzeros", "decompress", "normalizeeol",
+                "offset", "length", "nominalranges", "metadataonly", "perfile", "embed",
+                "rootout", "norecurse", "skipunreadable", "maxfilesize", "erroronempty",
+                "skipempty", "dedup", "stream", "coreutils", "dryrun", "checkpoint",
+                "emptydirs", "concat", "sortentries", "hashtreestructure", "files0from",
+                "filtercommand"])
+            .help("Hash stdin while saving it to PATH")
+            .long_help(concat!("Read the data from stdin, given as the file -, ",
+                "and write it to PATH while hashing it, so that data from a pipe ",
+                "is saved and hashed in one pass. The hash file lists PATH ",
+                "with the number of bytes read. The hash file is written ",
+                "once stdin ends, as its length is not known before then.")))
+        .arg(Arg::new("dryrun").long("dry-run")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["perfile", "embed", "stream", "cdc",
+                "metadataonly", "leavesandroot"])
+            .help("Print the size of the long format instead of hashing")
+            .long_help(concat!("Print the number of leaf and internal nodes ",
+                "of each file and the size of its entries in a long hash file ",
+                "with the given block size, branch factor, and hash function, ",
+                "followed by the totals, without hashing anything. ",
+                "Useful for choosing tree parameters, or between the short ",
+                "and long formats. Sizes assume that file indices ",
+                "have at most 3 digits.")))
+        .arg(Arg::new("filejobs").long("file-jobs")
+            .action(ArgAction::Set)
+            .value_name("N")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .default_value("1")
+            .conflicts_with_all(["perfile", "dedup", "checkpoint", "stream",
+                "embed", "cdc", "metadataonly", "dryrun"])
+            .help("Number of files to hash at once")
+            .long_help(concat!("Number of files to hash at once, ",
+                "each with its own --jobs threads. ",
+                "Entries of files that finish early are held in memory ",
+                "and written once every earlier file is written, ",
+                "so the hash file is the same as when hashing one file ",
+                "at a time. At most twice N files are held in memory. ",
+                "Progress bars are not shown with more than 1 file at once.")))
+        .arg(Arg::new("checkpoint").long("checkpoint")
+            .action(ArgAction::Set)
+            .value_name("ENTRIES")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .conflicts_with_all(["short", "embed", "metadataonly"])
+            .help("Sync the hash file to disk every ENTRIES entries")
+            .long_help(concat!("Flush the hash file and sync it to disk ",
+                "after every ENTRIES hash entries, so that the entries ",
+                "written so far survive a crash or power loss. ",
+                "Each line already reaches the OS as it is written, ",
+                "so this only guards against losing the OS cache, ",
+                "and smaller intervals slow down generation. ",
+                "A hash file left by a crash ends partway through a file, ",
+                "which verify-hash reports as an unexpected EOF. ",
+                "Nothing resumes it, so it has to be generated again.")))
+        .arg(Arg::new("emptydirs").long("record-empty-dirs")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("short")
+            .help("Record empty directories in the file list")
+            .long_help(concat!("Record empty directories found when ",
+                "expanding directories as entries in the file list, ",
+                "so that their absence is detected during verification.")))
+        .arg(Arg::new("concat").long("concat")
+            .action(ArgAction::SetTrue)
+            .help("Hash all files as one concatenated file")
+            .long_help(concat!("Hash all files, in the order given, ",
+                "as if they were concatenated into one file. ",
+                "Blocks may span file boundaries.")))
+        .arg(Arg::new("sortentries").long("sort-entries")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("concat")
+            .help("Sort entries by file name")
+            .long_help(concat!("Sort the entries of the hash file by file name ",
+                "instead of listing them in the order they were found, ",
+                "so that hash files of changed directories diff cleanly. ",
+                "Entries are numbered in the sorted order.")))
+        .arg(Arg::new("hashtreestructure").long("hash-tree-structure")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["concat", "perfile", "embed", "metadataonly"])
+            .help("Hash file names into a combined root of all files")
+            .long_help(concat!("Write a combined root covering every file ",
+                "and the sorted paths of the files relative to the arguments ",
+                "they were found under, so that renaming or moving a file ",
+                "changes the combined root even if its contents do not. ",
+                "verify-hash checks the combined root when every file matches.")))
+        .arg(Arg::new("format").long("format")
+            .action(ArgAction::Set)
+            .default_value("text")
+            .value_parser(PossibleValuesParser::new(["text", "jsonl"])
+                .map(|s| s.parse::<HashFileFormat>().unwrap()))
+            .conflicts_with_all(["short", "embed", "metadataonly",
+                "selfchecksum", "hashtreestructure"])
+            .help("Format of the hash file")
+            .long_help(concat!("Format of the hash file. ",
+                "jsonl writes the header as a JSON object on the first line, ",
+                "followed by a JSON object for each hash entry, ",
+                "so that entries can be streamed into other tools. ",
+                "verify-hash detects the format of the hash file.")))
+        .arg(Arg::new("uppercase").long("uppercase")
+            .action(ArgAction::SetTrue)
+            .help("Write hashes with uppercase hex digits")
+            .long_help(concat!("Write hashes with uppercase hex digits, ",
+                "for other tools that expect them. verify-hash reads ",
+                "hex digits of either case, so this is not recorded ",
+                "in the hash file.")))
+        .arg(Arg::new("files0from").long("files0-from")
+            .action(ArgAction::Set)
+            .help("Also hash the NUL-delimited paths in this file")
+            .long_help(concat!("Also hash the paths in this file, ",
+                "or in stdin if it is -, each terminated by a NUL byte ",
+                "as written by find -print0. Paths are taken literally, ",
+                "so they can contain newlines and quotes. ",
+                "FILES can be left out when this is given.")))
+        .arg(Arg::new("FILES").required_unless_present("files0from")
             .action(ArgAction::Append)
             .last(true)
             .num_args(1..=u16::MAX.into())
-            .help("Files to hash"));
+            .help("Files to hash, or @LIST to read paths from LIST")
+            .long_help(concat!("Files or directories to hash. ",
+                "Directories are expanded to the files in them, ",
+                "unless --no-recurse is given. ",
+                "An argument @LIST is replaced by the paths in the file LIST, ",
+                "one per line, or from stdin if LIST is -. ",
+                "Blank lines and lines starting with # are skipped, ",
+                "and quoted lines are unescaped like names in hash files. ",
+                "Use ./@name for a file whose name starts with @.")));
     let check_hash_command = Command::new(VERIFY_HASH_CMD_NAME)
         .about("Verify Merkle tree hashes")
         .arg(Arg::new("failfast").long("fail-fast")
@@ -138,21 +727,345 @@ fn parse_cli() -> Result<ArgMatches, clap::Error> {
             .help("Bail immediately on hash mismatch")
             .long_help(concat!("Skip checking the rest of the files ",
                 "when a hash mismatch is detected.")))
-        .arg(Arg::new("FILE").required(true)
+        .arg(Arg::new("maxfailures").long("max-failures")
+            .action(ArgAction::Set)
+            .value_name("N")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .conflicts_with("failfast")
+            .help("Stop once N files have failed verification")
+            .long_help(concat!("Stop checking the rest of the files once ",
+                "N files have failed verification, to bound the time spent ",
+                "on a badly damaged set of files. ",
+                "N counts files, not entries, and includes files ",
+                "that fail before hashing, such as missing files ",
+                "or files with the wrong length. ",
+                "Long hash files still stop at the first mismatched entry ",
+                "unless --root-only is given.")))
+        .arg(Arg::new("verifycommand").long("verify-command")
+            .action(ArgAction::Set)
+            .conflicts_with("embedded")
+            .help("Command to check the hash file before reading it")
+            .long_help(concat!("Shell command to run before the hash file ",
+                "is read, with the path of the hash file as its last argument, ",
+                "such as a script that checks a detached signature of it. ",
+                "Verification is aborted if the command fails. ",
+                "The command runs with the privileges of this program, ",
+                "so only pass commands from trusted sources.")))
+        .arg(Arg::new("maxfilesize").long("max-file-size")
+            .action(ArgAction::Set)
+            .value_parser(byte_count_str_to_num)
+            .help("Skip files larger than this many bytes")
+            .long_help(concat!("Skip files larger than this many bytes, ",
+                "with a warning, so that one unexpectedly large file ",
+                "does not hold up hashing a directory. ",
+                "SI and IEC prefixes are accepted as in --block-length. ",
+                "Skipped files are not verified.")))
+        .arg(Arg::new("maxfilesizefatal").long("max-file-size-fatal")
+            .action(ArgAction::SetTrue)
+            .requires("maxfilesize")
+            .help("Abort instead of skipping files larger than --max-file-size"))
+        .arg(Arg::new("maxtotalbytes").long("max-total-bytes")
+            .action(ArgAction::Set)
+            .value_name("BYTES")
+            .value_parser(byte_count_str_to_num)
+            .help("Stop verifying files once this many bytes have been read")
+            .long_help(concat!("Skip the remaining files once the files verified ",
+                "so far add up to this many bytes, for quick spot checks of ",
+                "large sets of files. The file that reaches the limit is still ",
+                "verified in full. SI and IEC prefixes are accepted as in --block-length. ",
+                "If no files failed before then, the exit code is 4 ",
+                "to tell a partial verification apart from a full one.")))
+        .arg(Arg::new("bytediff").long("byte-diff")
+            .action(ArgAction::SetTrue)
+            .requires("reference")
+            .help("Report the first byte of a mismatched block that differs from --reference")
+            .long_help(concat!("On a hash mismatch in a long hash file, ",
+                "compare the mismatched byte range against a reference copy ",
+                "of the file given with --reference, and report the first ",
+                "byte that differs. Has no effect on short hash files.")))
+        .arg(Arg::new("reference").long("reference")
+            .action(ArgAction::Set)
+            .help("Compare files with a reference copy instead of hashing them")
+            .long_help(concat!("Reference copy of the data, which is taken to ",
+                "match the hash file. Without --byte-diff, each file is ",
+                "compared byte for byte with its reference copy instead of ",
+                "being hashed, and the first differing byte is reported. ",
+                "Files are still hashed when the hash file has a structure hash. ",
+                "With --byte-diff, files are hashed and only mismatched ",
+                "byte ranges are compared. ",
+                "If this is a directory, the reference copy of each file ",
+                "is found at the same path under it.")))
+        .arg(Arg::new("allowappend").long("allow-append")
+            .action(ArgAction::SetTrue)
+            .help("Verify only the hashed prefix of files that grew")
+            .long_help(concat!("When a file is longer than its recorded length, ",
+                "verify only the first recorded-length bytes ",
+                "and warn that the appended data is unverified. ",
+                "Has no effect on short hash files, ",
+                "which do not record file lengths.")))
+        .arg(Arg::new("maxnodes").long("max-nodes")
+            .action(ArgAction::Set)
+            .default_value("1099511627776")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .conflicts_with("embedded")
+            .help("Reject recorded lengths that need more tree nodes than this")
+            .long_help(concat!("Reject a hash file if the recorded length ",
+                "of any file needs more tree nodes than this, ",
+                "so that a corrupted or malicious header cannot make ",
+                "verification size its progress and entry counts ",
+                "for an absurd length. The default of 2^40 nodes ",
+                "allows files of a few PiB with 4 KiB blocks.")))
+        .arg(Arg::new("embedded").long("embedded")
+            .action(ArgAction::SetTrue)
+            .help("Verify FILE against the hash appended by --embed"))
+        .arg(Arg::new("relativetocwd").long("relative-to-cwd")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("embedded")
+            .help("Resolve file names against the current directory")
+            .long_help(concat!("Resolve relative file names in hash files ",
+                "generated with --relative-to-output against the current ",
+                "directory instead of the directory of the hash file. ",
+                "Names in other hash files, and in hash files read from ",
+                "--hashes-from-command, are always resolved against ",
+                "the current directory.")))
+        .arg(Arg::new("exact").long("exact")
+            .action(ArgAction::SetTrue)
+            .requires("scandir")
+            .conflicts_with_all(["embedded", "selfconsistent"])
+            .help("Also report files that differ from those under --scan-dir")
+            .long_help(concat!("Also report files under the --scan-dir directory ",
+                "that are not in the hash file, and files in the hash file ",
+                "under that directory that are not on disk. ",
+                "Either makes verification fail.")))
+        .arg(Arg::new("scandir").long("scan-dir")
+            .action(ArgAction::Set)
+            .requires("exact")
+            .help("Directory to compare against the hash file with --exact"))
+        .arg(Arg::new("selfconsistent").long("self-consistent")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["embedded", "allowappend", "failfast"])
+            .help("Check parent hashes against their children without reading files")
+            .long_help(concat!("Check that every parent hash stored in a long ",
+                "hash file matches the hash of its stored children, ",
+                "up to the root. The hashed files are not read, so this ",
+                "detects inconsistent edits to the hash file, but not ",
+                "changes to the files themselves.")))
+        .arg(Arg::new("checkcoverage").long("check-coverage")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["embedded", "selfconsistent"])
+            .help("Check that leaf byte ranges cover each file exactly once")
+            .long_help(concat!("Before hashing, check that the byte ranges of ",
+                "the leaf entries in a long hash file cover each file from ",
+                "its first to its last byte, without gaps or overlaps, ",
+                "and report the first gap or overlap found. This does not ",
+                "depend on the hash function, so it catches broken ranges ",
+                "even where the hashes themselves match.")))
+        .arg(Arg::new("rootonly").long("root-only")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["embedded", "selfconsistent", "bytediff"])
+            .help("Only compare the root hash of each file in a long hash file")
+            .long_help(concat!("Hash each file and compare only its root hash ",
+                "against the root entry of a long hash file, ",
+                "without comparing the entries of its blocks. ",
+                "A mismatch then does not say which blocks changed, ",
+                "and block entries that do not match their root ",
+                "are not detected. Only works on long hash files.")))
+        .arg(Arg::new("anyorder").long("any-order")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["embedded", "selfconsistent"])
+            .help("Accept the entries of files in any order in a long hash file")
+            .long_help(concat!("Find where the entries of each file are ",
+                "in a long hash file before verifying, and read each file's ",
+                "entries from there, so that hash files whose file sections ",
+                "were reordered still verify. The entries of each file ",
+                "must still be next to each other and in their original order. ",
+                "Only works on long hash files.")))
+        .arg(Arg::new("follow").long("follow")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["embedded", "selfconsistent", "checkcoverage",
+                "rootonly", "anyorder", "allowappend", "bytediff", "reference", "exact",
+                "cache", "verifycommand", "maxfilesize", "failfast", "maxfailures",
+                "hashesfromcommand", "filtercommand"])
+            .help("Keep verifying a file as data is appended to it")
+            .long_help(concat!("Verify a long hash file of one file, ",
+                "then keep checking both files for changes and verify them again ",
+                "whenever either changes. Leaves that were already verified ",
+                "are not read again, so only appended data is hashed. ",
+                "If the hash file is rewritten with other parameters or leaves, ",
+                "the file is verified from the start. Data written before ",
+                "the hash file covers it is left for the next update. ",
+                "Runs until interrupted with Ctrl-C, or until a mismatch, ",
+                "which stops it with an error. Hash files should be replaced ",
+                "by renaming over them, so that they are never read half-written.")))
+        .arg(Arg::new("followinterval").long("follow-interval")
+            .action(ArgAction::Set)
+            .value_name("MS")
+            .default_value("1000")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .requires("follow")
+            .help("Milliseconds between checks for changes with --follow"))
+        .arg(Arg::new("followidleexit").long("follow-idle-exit")
+            .action(ArgAction::Set)
+            .value_name("MS")
+            .value_parser(clap::value_parser!(u64))
+            .requires("follow")
+            .help("Stop --follow once neither file has changed for this many milliseconds")
+            .long_help(concat!("Stop --follow once neither file has changed ",
+                "for this many milliseconds. Exits successfully if everything ",
+                "recorded in the hash file was verified, or with an error if ",
+                "the file is still shorter than its recorded length.")))
+        .arg(Arg::new("salt").long("salt")
+            .action(ArgAction::Set)
+            .value_parser(salt_str_to_bytes)
+            .conflicts_with("embedded")
+            .help("Hex-encoded salt used when generating the hashes"))
+        .arg(Arg::new("decompress").long("decompress")
+            .action(ArgAction::Set)
+            .default_value("none")
+            .value_parser(PossibleValuesParser::new(["none", "gzip", "zstd", "auto"])
+                .map(|s| s.parse::<Decompression>().unwrap()))
+            .conflicts_with_all(["embedded", "selfconsistent"])
+            .help("Decompress files before verifying them")
+            .long_help(concat!("Decompress files before verifying them, ",
+                "for hash files generated with --decompress. ",
+                "auto decompresses files with a .gz or .zst extension or a gzip or zstd header.")))
+        .arg(Arg::new("filtercommand").long("filter-command")
+            .action(ArgAction::Set)
+            .value_name("COMMAND")
+            .conflicts_with_all(["decompress", "embedded", "selfconsistent"])
+            .help("Verify the output of a shell command given each file as stdin")
+            .long_help(concat!("Run COMMAND with sh -c for each file, with the file ",
+                "as its stdin, and verify its output instead of the file, ",
+                "for hash files generated with --filter-command. ",
+                "COMMAND needs to give the same output as the one used to ",
+                "generate the hash file.")))
+        .arg(Arg::new("cache").long("cache")
+            .action(ArgAction::Set)
+            .conflicts_with_all(["embedded", "selfconsistent"])
+            .help("Skip files that are unchanged since they last verified")
+            .long_help(concat!("Record verification results in the given cache file, ",
+                "and skip files that verified successfully with the same hash file ",
+                "and have the same modification time and length as then. ",
+                "This trades thoroughness for speed: ",
+                "changes that keep the modification time and length ",
+                "are not detected for skipped files.")))
+        .arg(Arg::new("nocache").long("no-cache")
+            .action(ArgAction::SetTrue)
+            .help("Verify every file even if --cache would skip it")
+            .long_help(concat!("Verify every file even if --cache would skip it. ",
+                "Results are still recorded in the --cache file.")))
+        .arg(Arg::new("results").long("results")
+            .action(ArgAction::Set)
+            .value_name("RESULTS_FILE")
+            .conflicts_with_all(["embedded", "selfconsistent", "follow"])
+            .help("Write the result of each file to RESULTS_FILE as JSON Lines")
+            .long_help(concat!("Write a JSON object for each file in the hash file ",
+                "to RESULTS_FILE, replacing it if it exists. Each object has ",
+                "the file_index and names of the file, and an outcome of ok, failed, ",
+                "skipped, or unchanged (skipped by --cache). Failed files also have ",
+                "the error, and the mismatched_byte_range of the first mismatched entry ",
+                "of long hash files. With --reference, they also have ",
+                "the first_differing_byte. Each line is written once its file ",
+                "is verified, so files verified before an error that stops ",
+                "verification are still listed.")))
+        .arg(Arg::new("hashesfromcommand").long("hashes-from-command")
+            .action(ArgAction::Set)
+            .value_name("COMMAND")
+            .conflicts_with_all(["FILE", "embedded", "selfconsistent",
+                "checkcoverage", "cache"])
+            .help("Read the hash file from the output of COMMAND")
+            .long_help(concat!("Run the shell command COMMAND and read ",
+                "the hash file from its stdout instead of from FILE, ",
+                "such as a command that downloads it, ",
+                "without writing it to a temporary file. ",
+                "The output is held in memory. Verification is aborted ",
+                "if the command fails. With --verify-command, ",
+                "the hash file is written to the stdin of the verify command, ",
+                "and its last argument is - instead of a path. ",
+                "The command runs with the privileges of this program, ",
+                "so only pass commands from trusted sources.")))
+        .arg(Arg::new("FILE").required_unless_present("hashesfromcommand")
             .action(ArgAction::Set)
             .help("File containing the hashes to check"));
+    let diff_file_command = Command::new(DIFF_FILE_CMD_NAME)
+        .about("List the byte ranges of a file that changed since it was hashed")
+        .long_about(concat!("Hash the current version of a file with the ",
+            "tree parameters of a long hash file, and list the byte ranges ",
+            "whose leaf hashes differ from the stored ones, along with ",
+            "data added or removed at the end of the file. ",
+            "The old version of the file is not needed."))
+        .arg(Arg::new("salt").long("salt")
+            .action(ArgAction::Set)
+            .value_parser(salt_str_to_bytes)
+            .help("Hex-encoded salt used when generating the hashes"))
+        .arg(Arg::new("HASHFILE").required(true)
+            .action(ArgAction::Set)
+            .help("Long hash file with the old hashes"))
+        .arg(Arg::new("DATAFILE").required(true)
+            .action(ArgAction::Set)
+            .help("Current version of the file")
+            .long_help(concat!("Current version of the file. It is compared ",
+                "against the entry with the same name, or against the only ",
+                "entry if the hash file lists a single file.")));
 
     let clap_app = Command::new(crate_name!())
         .version(VERSION_STR)
         .author(crate_authors!())
         .about(crate_description!())
         .after_help(HELP_STR_HASH_LIST)
-        .subcommand_required(true)
+        // Checked below so that the query flags work on their own
+        .subcommand_required(false)
+        .arg(Arg::new("printexitcodes").long("print-exit-codes")
+            .action(ArgAction::SetTrue)
+            .exclusive(true)
+            .hide(true)
+            .help("Print exit codes as NAME=code lines and exit"))
+        .arg(Arg::new("formatversion").long("format-version")
+            .action(ArgAction::SetTrue)
+            .exclusive(true)
+            .help("Print the hash file format version written and exit")
+            .long_help(concat!("Print the hash file format version written ",
+                "by this binary and exit. Hash files with format versions ",
+                "newer than this cannot be verified.")))
         .arg(Arg::new("quiet").long("quiet").short('q')
             .action(ArgAction::Count)
             .help("Print less text")
             .long_help(concat!("Specify once to hide progress bars. ",
-                "Specify twice to suppress all output besides errors.")))
+                "Specify twice to suppress all output besides errors. ",
+                "Progress bars are also hidden when stderr is not a terminal, ",
+                "unless --progress-to is given.")))
+        .arg(Arg::new("progressto").long("progress-to")
+            .action(ArgAction::Set)
+            .help("Draw progress bars to a file instead of stderr")
+            .long_help(concat!("Draw progress bars to a file, such as /dev/null, ",
+                "instead of stderr. Errors and status messages are still ",
+                "printed to stderr. Each redraw is appended to the file. ",
+                "Has no effect with --quiet.")))
+        .arg(Arg::new("progresshz").long("progress-hz")
+            .action(ArgAction::Set)
+            .value_parser(clap::value_parser!(u8).range(0..=60))
+            .help("Number of times per second to redraw progress bars")
+            .long_help(concat!("Number of times per second to redraw progress bars, ",
+                "from 0 to 60. The default is 5, or 1 with --progress-to. ",
+                "With 0, each file's bars are only drawn when hashing starts ",
+                "and once more when it finishes, below the first drawing.")))
+        .arg(Arg::new("stats").long("stats")
+            .action(ArgAction::SetTrue)
+            .help("Print the hashing time and throughput of each file")
+            .long_help(concat!("Print the hashing time and throughput of each file, ",
+                "and their totals, once all files are hashed. ",
+                "The hash function and thread count are included ",
+                "so that benchmark logs are self-describing. ",
+                "Printed to stderr even with --quiet.")))
+        .arg(Arg::new("config").long("config")
+            .action(ArgAction::Set)
+            .help("TOML file with default values for hashing options")
+            .long_help(concat!("TOML file with default values for hashing options, ",
+                "so that teams can share tree parameters. ",
+                "Keys are named after the long options they set: ",
+                "jobs, hash-function, block-length, branch-factor, ",
+                "node-encoding, short, overwrite, and self-checksum. ",
+                "Options given on the command line take precedence.")))
         .arg(Arg::new("jobs").long("jobs").short('j')
             .action(ArgAction::Set)
             .default_value("4")
@@ -165,314 +1078,2586 @@ fn parse_cli() -> Result<ArgMatches, clap::Error> {
                 "Adding more than 2 threads does not improve performance ",
                 "when I/O is the program bottleneck."
             )))
+        .arg(Arg::new("throttle").long("throttle")
+            .action(ArgAction::Set)
+            .value_name("BYTES_PER_SEC")
+            .value_parser(|rate_str: &str| match byte_count_str_to_num(rate_str) {
+                Ok(0) => Err(String::from("rate must be greater than 0")),
+                Ok(rate) => Ok(rate),
+                Err(err) => Err(err.to_string())
+            })
+            .help("Limit the rate at which data files are read")
+            .long_help(concat!("Limit the average rate at which data files are read, ",
+                "in bytes per second (prefixes as in --block-length, ",
+                "so 50M is 50 MB/s), to leave disk bandwidth for other programs. ",
+                "Each file is limited separately. Reads are unlimited by default.")))
+        .arg(Arg::new("verifypositions").long("verify-positions")
+            .action(ArgAction::SetTrue)
+            .help("Check the read position before each read while hashing")
+            .long_help(concat!("Check that each data file is read from the position ",
+                "the tree expects, as debug builds always do, and stop with an error ",
+                "if it is not. This is for diagnosing seek bugs in release builds, ",
+                "and applies to the files hashed by generate-hash and verify-hash ",
+                "without --stream, --embed, --embedded, or --follow.")))
+        .arg(Arg::new("stacksize").long("stack-size")
+            .action(ArgAction::Set)
+            .value_name("BYTES")
+            .value_parser(|size_str: &str| match byte_count_str_to_num(size_str) {
+                Ok(0) => Err(String::from("stack size must be greater than 0")),
+                Ok(size) => usize::try_from(size).map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string())
+            })
+            .help("Stack size of the hashing threads")
+            .long_help(concat!("Stack size of the threads that hash data files, ",
+                "with prefixes as in --block-length. The default stack is ",
+                "enough for all but extremely deep trees, such as those of ",
+                "huge files with tiny blocks, which can overflow it. ",
+                "Applies to the same files as --verify-positions.")))
+        .arg(Arg::new("readretries").long("read-retries")
+            .action(ArgAction::Set)
+            .value_name("COUNT")
+            .value_parser(clap::value_parser!(u32))
+            .default_value("0")
+            .help("Times to retry reads of data files that would block or time out")
+            .long_help(concat!("Times to try a read of a data file again when it fails ",
+                "with an error that would block or a timeout, as network filesystems ",
+                "may return, waiting twice as long before each retry, starting at 1 ms. ",
+                "Other read errors are not retried. ",
+                "Applies to the same files as --verify-positions.")))
         .subcommand(gen_hash_command)
-        .subcommand(check_hash_command);
-    clap_app.try_get_matches()
+        .subcommand(check_hash_command)
+        .subcommand(lint::lint_command())
+        .subcommand(repair::repair_command())
+        .subcommand(diff_file_command)
+        .subcommand(info::info_command())
+        .subcommand(tree_view::tree_command())
+        .subcommand(extract::extract_command())
+        .subcommand(convert::convert_command())
+        .subcommand(migrate::migrate_command())
+        .subcommand(self_test::self_test_command());
+    let mut clap_app = clap_app;
+    // The config supplies defaults, so it is read before parsing the rest
+    if let Some(config_path) = config_path_arg(std::env::args_os().skip(1)) {
+        let config_result = fs::read_to_string(&config_path)
+            .map_err(ConfigErr::from)
+            .and_then(|contents| ConfigDefaults::parse(&contents));
+        match config_result {
+            Ok(config_defaults) => {
+                clap_app = config_defaults.apply(clap_app, GENERATE_HASH_CMD_NAME);
+            },
+            Err(err) => {
+                return Err(clap_app.error(clap::error::ErrorKind::InvalidValue,
+                    format!("invalid config file {}: {}", config_path.display(), err)));
+            }
+        }
+    }
+    let matches = clap_app.try_get_matches_from_mut(std::env::args_os())?;
+    if matches.subcommand().is_none() && !matches.get_flag("printexitcodes")
+            && !matches.get_flag("formatversion") {
+        return Err(clap_app.error(clap::error::ErrorKind::MissingSubcommand,
+            concat!("a subcommand is required: ",
+                "generate-hash, verify-hash, lint, diff-file, or info")));
+    }
+    if let Some((GENERATE_HASH_CMD_NAME, gen_matches)) = matches.subcommand() {
+        // unwrap always succeeds because "mindepth" has a default value
+        let min_depth = *gen_matches.get_one::<usize>("mindepth").unwrap();
+        if let Some(&max_depth) = gen_matches.get_one::<usize>("maxdepth") {
+            if min_depth > max_depth {
+                let gen_hash_command = clap_app.find_subcommand_mut(GENERATE_HASH_CMD_NAME)
+                    .unwrap();
+                return Err(gen_hash_command.error(clap::error::ErrorKind::ArgumentConflict,
+                    format!("--min-depth {} is greater than --max-depth {}",
+                        min_depth, max_depth)));
+            }
+        }
+    }
+    Ok(matches)
 }
 
-fn run() -> i32 {
-    let matches_result = parse_cli();
-    if let Err(e) = matches_result {
-        // Mirror e.exit, but use scoping to call destructors
-        e.print().expect("Failed to print CMD parse error");
-        return CMDLINE_ERR;
+type MerkleHashFn<F, C> = fn(F, block_t, branch_t, &TreeOptions, C, usize)
+    -> Result<HashData<64>, HashFileErr>;
+
+fn merkle_hash_fn<F, C>(hash_enum: HashFunctions) -> MerkleHashFn<F, C>
+where
+    F: Read+Seek,
+    C: Consumer<HashRange> + Clone + Send + UnwindSafe + 'static
+{
+    with_digest!(hash_enum, D => merkle_hash_file_with_options::<_, D, _> as MerkleHashFn<F, C>)
+}
+
+// Nodes in the order of block_iter, which nodes hashed without a thread pool already are
+fn tree_ordered<'a, T, U>(block_iter: T, hashrange_iter: U, thread_count: usize)
+        -> Box<dyn Iterator<Item = HashRange> + 'a>
+where
+    T: Iterator<Item = BlockRange> + 'a,
+    U: Iterator<Item = HashRange> + 'a
+{
+    match thread_count {
+        0 => Box::new(assume_ordered_hashrange_iter(block_iter, hashrange_iter).into_iter()),
+        _ => Box::new(reorder_hashrange_iter(block_iter, hashrange_iter).into_iter())
     }
-    let matches = matches_result.unwrap();
+}
 
-    let (mut cmd_chosen, cmd_matches): (HashCommand<_,_>, ArgMatches)
-            = match matches.subcommand() {
-        Some((GENERATE_HASH_CMD_NAME, gencmd_matches)) => 
-                (HashCommand::GenerateHash(None), gencmd_matches.clone()),
-        Some((VERIFY_HASH_CMD_NAME, verify_matches)) =>
-                (HashCommand::VerifyHash(None), verify_matches.clone()),
-        _ => panic!("Invalid or missing subcommand detected")
-    };
+// Copies reader to writer while hashing the copied bytes, for --tee
+// Returns the number of bytes copied, the root hash, and every node of the tree
+fn tee_hash<D, R, W>(mut reader: R, mut writer: W, block_size: block_t,
+        branch_factor: branch_t, tree_options: &TreeOptions)
+        -> Result<(u64, HashData<64>, Vec<HashRange>), TeeErr>
+where
+    D: Digest,
+    R: Read,
+    W: Write
+{
+    let mut builder = MerkleTreeBuilder::<D>::with_options(branch_factor, tree_options);
+    let mut block = vec![0x00; usize::try_from(block_size).unwrap()];
+    let mut copied_len: u64 = 0;
+    loop {
+        // Reads from a pipe can be shorter than a block before its end
+        let mut block_len = 0;
+        while block_len < block.len() {
+            match reader.read(&mut block[block_len..]) {
+                Ok(0) => break,
+                Ok(read_len) => block_len += read_len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(TeeErr::ReadError(err.kind()))
+            }
+        }
+        if block_len == 0 {
+            break;
+        }
+        writer.write_all(&block[..block_len])
+            .map_err(|err| TeeErr::WriteError(err.kind()))?;
+        builder.push_block(&block[..block_len]);
+        copied_len += block_len as u64;
+        if block_len < block.len() {
+            break;
+        }
+    }
+    writer.flush().map_err(|err| TeeErr::WriteError(err.kind()))?;
+    let (root, nodes) = builder.finish();
+    Ok((copied_len, root, nodes))
+}
 
-    let mut hashing_final_status = 0;
+type TeeHashFn<R, W> = fn(R, W, block_t, branch_t, &TreeOptions)
+    -> Result<(u64, HashData<64>, Vec<HashRange>), TeeErr>;
 
-    let (file_list_result, tree_params, short_output, verify_start_pos):
-            (Vec<(String, Option<PreHashError>)>, TreeParams, bool, Option<u64>)
-            = match cmd_chosen {
-        HashCommand::GenerateHash(None) => {
-            let file_vec: Vec<_> = cmd_matches.get_many::<String>("FILES").unwrap().collect();
-            // Validators should already have caught errors
-            (
-                {
-                    let mut collect_vec: Vec<_> = Vec::with_capacity(
-                        file_vec.len());
-                    for file_path in file_vec {
-                        match utils::str_to_files(file_path) {
-                            Some(paths) => {
-                                for path in paths {
-                                    match File::open(&path) {
-                                        Ok(_) => collect_vec.push((path.to_string_lossy().into_owned(), None)),
-                                        Err(_) => collect_vec.push((path.to_string_lossy().into_owned(), Some(PreHashError::ReadPermissionError)))
-                                    }
-                                }
-                            },
-                            None => collect_vec.push((file_path.to_owned(), Some(PreHashError::FileNotFound)))
-                        }
-                    };
-                    collect_vec
-                },
-                // unwraps will always succeed due to default values
-                TreeParams {
-                    // block_size has a special parser invoked in parse_cli
-                    block_size: *cmd_matches.get_one("blocksize").unwrap(),
-                    branch_factor: *cmd_matches.get_one("branch").unwrap(),
-                    hash_function: *cmd_matches.get_one("hash").unwrap()
-                },
-                cmd_matches.get_flag("short"),
-                None
-            )
+fn tee_hash_fn<R: Read, W: Write>(hash_enum: HashFunctions) -> TeeHashFn<R, W> {
+    with_digest!(hash_enum, D => tee_hash::<D, _, _> as TeeHashFn<R, W>)
+}
+
+// Hashes a file in the current thread, for --embed, --embedded, and --stream
+fn hash_single_file<R: Read+Seek>(reader: R, tree_params: &TreeParams, salt: &[u8],
+        progress_output: &ProgressOutput, thread_count: usize,
+        read_limit: Option<u64>) -> Option<HashData<64>> {
+    let mut reader = reader;
+    let file_size = seek_len(&mut reader);
+    let (tree_len, tree_block_size) = tree_params.tree_shape(file_size);
+    let pb_hash_len = merkle_tree::node_count(tree_len,
+        tree_block_size, tree_params.branch_factor);
+    let (pb_file, pb_hash) = setup_pbs(progress_output.draw_target(),
+        file_size, pb_hash_len);
+    let consumer: ChannelOrPb<HashRange> = pb_hash.into();
+    let merkle_tree_thunk = merkle_hash_fn(tree_params.hash_function);
+    let throttled_reader = ThrottledReader::new(reader, read_limit);
+    let hash_result = merkle_tree_thunk(pb_file.wrap_read(throttled_reader),
+        tree_params.block_size, tree_params.branch_factor,
+        &tree_params.tree_options(salt), consumer, thread_count);
+    pb_file.finish();
+    hash_result.ok()
+}
+
+// Plain hash of a whole file, for --coreutils
+fn hash_whole_file<R: Read>(reader: R, hash_enum: HashFunctions, file_size: u64,
+        progress_output: &ProgressOutput, read_limit: Option<u64>)
+        -> std::io::Result<HashData<64>> {
+    let (pb_file, pb_hash) = setup_pbs(progress_output.draw_target(), file_size, 1);
+    let hash_result = hash_enum.digest_reader(
+        pb_file.wrap_read(ThrottledReader::new(reader, read_limit)));
+    pb_file.finish();
+    pb_hash.inc(1);
+    pb_hash.finish();
+    hash_result
+}
+
+// Settings shared by every file hashed with --file-jobs
+struct FileJobParams<'a> {
+    tree_params: &'a TreeParams,
+    tree_options: &'a TreeOptions,
+    decompression: Decompression,
+    short_output: bool,
+    thread_count: usize,
+    read_limit: Option<u64>
+}
+
+// Everything written for a file hashed with --file-jobs, held until earlier files are written
+struct FileJobOutput {
+    file_size: u64,
+    // Empty for short output, which only needs the root
+    listed_entries: Vec<HashRange>,
+    root: HashData<64>,
+    hash_time: Duration
+}
+
+// Hashes a file for --file-jobs, keeping its listed entries in memory
+// Errors are returned as messages, to be printed when the file's turn comes
+fn hash_file_job(params: &FileJobParams, file_parts: &[PathBuf], hash_len: Option<u64>,
+        data_len: u64) -> Result<FileJobOutput, String> {
+    let tree_params = params.tree_params;
+    let filename_string = display_name_group(file_parts);
+    let mut part_files = Vec::with_capacity(file_parts.len());
+    for file_part in file_parts {
+        match DataFile::open(file_part, params.decompression, tree_params.normalize_eol) {
+            Ok(file) => part_files.push(file),
+            Err(err) => return Err(format!("Error opening file {} for reading: {}",
+                file_part.display(), err))
+        }
+    }
+    let concat_obj = ConcatReader::new(part_files);
+    // Same lengths as in the sequential loop, which never sees --allow-append here
+    let file_size = match (hash_len, tree_params.region_offset) {
+        (Some(hash_len), _) => hash_len,
+        (None, Some(_)) => data_len,
+        (None, None) => concat_obj.len()
+    };
+    let file_obj = TruncatedReader::with_offset(concat_obj,
+            tree_params.region_offset.unwrap_or(0), file_size)
+        .map_err(|err| format!("Error reading file {}: {}", filename_string, err))?;
+    let reader = ThrottledReader::new(file_obj, params.read_limit);
+    let (block_size, branch_factor) = (tree_params.block_size, tree_params.branch_factor);
+    let merkle_tree_thunk: MerkleHashFn<_, ChannelOrPb<HashRange>>
+        = merkle_hash_fn(tree_params.hash_function);
+    let hash_start = Instant::now();
+    let (root, listed_entries) = match params.short_output {
+        true => {
+            let (_, pb_hash) = setup_pbs(ProgressOutput::Hidden.draw_target(), 0, 0);
+            let root = merkle_tree_thunk(reader, block_size, branch_factor,
+                params.tree_options, pb_hash.into(), params.thread_count);
+            (root, Vec::new())
         },
-        HashCommand::VerifyHash(None) => {
-            let hash_file_str = cmd_matches.get_one::<String>("FILE").unwrap();
-            let hash_file = match File::open(hash_file_str) {
-                Ok(file) => file,
-                Err(e) => {
-                    eprintln!("Error opening hash file {}: {}",
-                            hash_file_str, e);
-                    return VERIF_READ_ERR;
-                }
+        false => {
+            // Unbounded because the whole file is buffered anyway
+            let (tx, rx) = unbounded_channel::<HashRange>();
+            let root = merkle_tree_thunk(reader, block_size, branch_factor,
+                params.tree_options, tx.into(), params.thread_count);
+            let listed_root = tree_params.listed_root(file_size);
+            let (tree_len, tree_block_size) = tree_params.tree_shape(file_size);
+            let block_iter = merkle_block_generator(
+                tree_len, tree_block_size, branch_factor).into_iter();
+            let listed_entries = match root {
+                Ok(_) => tree_ordered(block_iter, rx.into_iter(), params.thread_count)
+                    .filter(|entry| is_listed_node(entry.block_range(), listed_root))
+                    .collect(),
+                Err(_) => Vec::new()
             };
-            let mut hash_file_reader = BufReader::new(hash_file);
-
-            let mut file_vec: Vec<(String, Option<PreHashError>)> = Vec::new();
-            // Parse version number
-            let mut version_line = String::new();
-            let version_read_result = hash_file_reader.read_line(&mut version_line);
-            if version_read_result.is_err() {
-                eprintln!("Error: unable to read in version line");
-                return VERIF_READ_ERR;
-            }
-            match parse_functions::parse_version_line(&version_line) {
-                Ok(version) => {
-                    // TODO: Do more precise version checking later
-                    let range_str = ">=0.5, <0.8";
-                    let recognized_range = VersionReq::parse(range_str).unwrap();
-                    if !recognized_range.matches(&version) {
-                        eprintln!("Error: hash file has unsupported version {}", version);
-                        return VERIF_BAD_HEADER_ERR;
+            (root, listed_entries)
+        }
+    };
+    let root = root.map_err(|err| match err.position_mismatch() {
+        Some(mismatch) => format!("Error reading file {}: {}", filename_string, mismatch),
+        None => format!("Error reading file {}", filename_string)
+    })?;
+    Ok(FileJobOutput {
+        file_size,
+        listed_entries,
+        root,
+        hash_time: hash_start.elapsed()
+    })
+}
+
+// Appends the root hash of each file to the file itself
+fn generate_embedded(file_list: &[(Vec<PathBuf>, Option<u64>, bool)],
+        tree_params: &TreeParams, quiet_count: u8,
+        progress_output: &ProgressOutput, thread_count: usize,
+        read_limit: Option<u64>) -> ExitCode {
+    for (file_parts, _, _) in file_list {
+        // --embed conflicts with --concat, so there is one part each
+        let file_path = &file_parts[0];
+        let mut file_obj = match OpenOptions::new().read(true).write(true)
+                .open(file_path) {
+            Ok(file) => file,
+            Err(err) => {
+                eprintln!("Error opening file {} for writing: {}",
+                    file_path.display(), err);
+                return ExitCode::GenWriteErr;
+            }
+        };
+        // Hashing the old trailer along with the data would be confusing
+        if EmbedTrailer::read_from(&mut file_obj).is_ok() {
+            eprintln!("Error: file {} already has an embedded hash",
+                file_path.display());
+            return ExitCode::GenWriteErr;
+        }
+        if quiet_count < 2 {
+            eprintln!("Hashing {}...", file_path.display());
+        }
+        let root_hash = match hash_single_file(&mut file_obj, tree_params, &[],
+                progress_output, thread_count, read_limit) {
+            Some(hash) => hash,
+            None => {
+                eprintln!("Error reading file {}", file_path.display());
+                return ExitCode::DataReadErr;
+            }
+        };
+        let trailer = EmbedTrailer {
+            tree_params: *tree_params,
+            root_hash
+        };
+        let write_result = file_obj.seek(SeekFrom::End(0))
+            .and_then(|_| file_obj.write_all(&trailer.to_bytes()))
+            .and_then(|_| file_obj.sync_all());
+        if let Err(err) = write_result {
+            eprintln!("Error writing embedded hash to file {}: {}",
+                file_path.display(), err);
+            return ExitCode::GenWriteErr;
+        }
+    }
+    ExitCode::Success
+}
+
+// Hashes each file as it is found and writes its short entry right away,
+// so that the list of files is never held in memory, for --stream and --coreutils
+fn generate_streaming(cmd_matches: &ArgMatches, quiet_count: u8,
+        progress_output: &ProgressOutput, thread_count: usize,
+        read_limit: Option<u64>) -> ExitCode {
+    let file_args = match expand_file_args(cmd_matches) {
+        Ok(file_args) => file_args,
+        Err(exit_code) => return exit_code
+    };
+    let mut tree_params = generate_tree_params(cmd_matches);
+    if let Err(exit_code) = apply_xattr_params(&mut tree_params, cmd_matches,
+            &file_args, quiet_count) {
+        return exit_code;
+    }
+    if let Err(exit_code) = check_hash_len(tree_params.hash_function) {
+        return exit_code;
+    }
+    if tree_params.content_defined && !tree_params.block_size.is_power_of_two() {
+        eprintln!("Error: --cdc requires a block length that is a power of 2");
+        return ExitCode::CmdlineErr;
+    }
+    if tree_params.stride.is_some_and(|stride| stride > tree_params.block_size) {
+        eprintln!("Error: --stride must be at most the block length");
+        return ExitCode::CmdlineErr;
+    }
+    print_generate_warnings(&tree_params, quiet_count);
+    let salt: Vec<u8> = cmd_matches.get_one::<Vec<u8>>("salt")
+        .cloned().unwrap_or_default();
+    let decompression = data_decompression(cmd_matches);
+    // unwrap always succeeds because "mindepth" has a default value
+    let min_depth = *cmd_matches.get_one::<usize>("mindepth").unwrap();
+    let max_depth = cmd_matches.get_one::<usize>("maxdepth").copied();
+    let comments: Vec<String> = cmd_matches.get_many::<String>("comment")
+        .map(|comments| comments.cloned().collect())
+        .unwrap_or_default();
+
+    // --stream conflicts with --per-file, so --output is given
+    let write_file_name = cmd_matches.get_one::<String>("output").unwrap();
+    let output_path = PathBuf::from(write_file_name);
+    let coreutils = cmd_matches.get_flag("coreutils");
+    let mut sink = match open_output_file(&output_path, cmd_matches.get_flag("overwrite")) {
+        Ok(file) if coreutils => Box::new(CoreutilsSink::new(LineWriter::new(file),
+            cmd_matches.get_flag("uppercase"))),
+        Ok(file) => new_sink(LineWriter::new(file), HashFileFormat::Text,
+            cmd_matches.get_flag("selfchecksum"), cmd_matches.get_flag("withparity"),
+            cmd_matches.get_flag("uppercase")),
+        Err(err) => {
+            eprintln!("Error opening file {} for writing: {}", write_file_name, err);
+            return ExitCode::GenWriteErr;
+        }
+    };
+    if cmd_matches.get_flag("relativetooutput") {
+        sink = match relative_path_sink(sink, &output_path) {
+            Ok(sink) => sink,
+            Err(err) => {
+                eprintln!("Error finding directory of {}: {}", write_file_name, err);
+                return ExitCode::GenWriteErr;
+            }
+        };
+    }
+    if let Err(err) = sink.write_header(&SinkHeader {
+        tree_params: &tree_params,
+        comments: &comments,
+        structure_hash: None,
+        file_entries: &[],
+        dir_list: &[],
+        short_output: true,
+        sectioned: false
+    }) {
+        eprintln!("Error writing hash file: {}", err);
+        return ExitCode::GenWriteErr;
+    }
+
+    for file_arg in &file_args {
+        if cmd_matches.get_flag("norecurse") && Path::new(file_arg).is_dir() {
+            eprintln!("Error with file {}: {}", file_arg, PreHashError::IsDirectory);
+            return ExitCode::DataReadErr;
+        }
+        let entries = match utils::walk_files(file_arg, min_depth, max_depth) {
+            Ok(entries) => entries,
+            Err(err @ PreHashError::FileNotFound) => {
+                eprintln!("Error with file {}: {}", file_arg, err);
+                return ExitCode::DataReadErr;
+            },
+            Err(err) => {
+                eprintln!("Error with directory {}: {}", file_arg, err);
+                return ExitCode::DataReadErr;
+            }
+        };
+        for entry in entries {
+            let path = match entry {
+                Ok(path) => path,
+                Err(err) if cmd_matches.get_flag("skipunreadable") => {
+                    if quiet_count < 2 {
+                        eprintln!("Warning: skipping unreadable entry: {}", err);
                     }
+                    continue;
                 },
-                Err(e) => match e {
-                    HeaderParsingErr::MalformedFile => {
-                        eprintln!("Error: hash file is malformed: unable to parse version line");
-                        return VERIF_BAD_HEADER_ERR;
-                    },
-                    HeaderParsingErr::MalformedVersion(s) => {
-                        eprintln!("Error: hash file has malformed version {}",s);
-                        return VERIF_BAD_HEADER_ERR;
-                    }
-                    _ => unreachable!()
+                Err(err) => {
+                    eprintln!("Error with directory {}: {}", file_arg,
+                        PreHashError::UnreadableDirEntry(err.to_string()));
+                    return ExitCode::DataReadErr;
                 }
+            };
+            if let Err(err) = utils::check_utf8_name(&path) {
+                eprintln!("Error with directory {}: {}", file_arg, err);
+                return ExitCode::DataReadErr;
             }
-            // Read in the next three lines
-            let mut hash_param_arr = [EMPTY_STRING; 3];
-            for param_str in hash_param_arr.iter_mut() {
-                let mut line = String::new();
-                let line_result = hash_file_reader.read_line(&mut line);
-                if line_result.is_ok() {
-                    assert!(line.ends_with('\n'));
-                    if &line[line.len()-2..line.len()-1] == "\r" {
-                        // \r\n ending
-                        *param_str = line[..line.len()-2].to_string();
-                    } else {
-                        // \n ending
-                        *param_str = line[..line.len()-1].to_string();
-                    }
-                } else {
-                    eprintln!("Error: unable to read in parameter line");
-                    return VERIF_READ_ERR;
-                }
+            let file_parts = std::slice::from_ref(&path);
+            let filename_string = display_name_group(file_parts);
+            match check_file_size(file_parts, cmd_matches, quiet_count) {
+                Ok(true) => {},
+                Ok(false) => continue,
+                Err(exit_code) => return exit_code
             }
-            let tree_param_result = TreeParams::from_lines(&hash_param_arr);
-            if let Err(other_errors) = tree_param_result {
-                for error in other_errors {
-                    eprintln!("Error: {}", error);
+            let data_len = match read_adapters::data_len(file_parts, decompression,
+                    tree_params.normalize_eol) {
+                Ok(data_len) => data_len,
+                Err(err) => {
+                    eprintln!("Error reading file {}: {}", filename_string, err);
+                    return ExitCode::DataReadErr;
+                }
+            };
+            if data_len == 0 && cmd_matches.get_flag("skipempty") {
+                if quiet_count < 2 {
+                    eprintln!("Warning: skipping empty file {}", path.display());
                 }
-                return VERIF_BAD_HEADER_ERR;
+                continue;
             }
-
-            let mut format_line = String::new();
-            let format_line_result = hash_file_reader.read_line(&mut format_line);
-            if format_line_result.is_err() {
-                eprintln!("Error: hash file is malformed: unable to read hashes or file list");
-                return VERIF_READ_ERR;
+            if data_len == 0 && cmd_matches.get_flag("erroronempty") {
+                eprintln!("Error with file {}: {}", filename_string, PreHashError::EmptyFile);
+                return ExitCode::DataReadErr;
             }
-            let is_short_hash = match format_line.as_str() {
-                "Hashes:\n" | "Hashes:\r\n" => true,
-                "Files:\n" | "Files:\r\n" => false,
-                _ => {
-                    eprintln!("Error: hash file is malformed: file should have file list or hash list");
-                    return VERIF_BAD_HEADER_ERR;
+            let data_file = match DataFile::open(&path, decompression, tree_params.normalize_eol) {
+                Ok(file) => file,
+                Err(err) => {
+                    eprintln!("Error opening file {} for reading: {}", path.display(), err);
+                    return ExitCode::DataReadErr;
                 }
             };
-            let list_begin_pos: Option<u64> = match is_short_hash {
-                true => Some(
-                    hash_file_reader.stream_position().unwrap()
-                ),
-                false => None
-            };
-            loop {
-                let mut next_line = String::new();
-                let next_line_result = hash_file_reader.read_line(&mut next_line);
-                if let Err(read_err) = next_line_result {
-                    if read_err.kind() == std::io::ErrorKind::UnexpectedEof {
-                        if !is_short_hash {
-                            eprintln!("Error: unexpected EOF reading hashes");
-                            return VERIF_BAD_HEADER_ERR;
-                        }
-                    } else {
-                        eprintln!("Error: Error in reading file: {}", read_err);
-                        return VERIF_READ_ERR;
+            if quiet_count == 0 {
+                let base_name = display_name_group(&[path.file_name().unwrap()]);
+                eprintln!("{}", title_center(&abbreviate_filename(&base_name, 80-8)));
+            } else if quiet_count == 1 {
+                eprintln!("Hashing {}...", filename_string);
+            }
+            let root_hash = match coreutils {
+                true => match hash_whole_file(data_file, tree_params.hash_function,
+                        data_len, progress_output, read_limit) {
+                    Ok(hash) => hash,
+                    Err(err) => {
+                        eprintln!("Error reading file {}: {}", filename_string, err);
+                        return ExitCode::DataReadErr;
+                    }
+                },
+                false => match hash_single_file(ConcatReader::new(vec![data_file]),
+                        &tree_params, &salt, progress_output, thread_count, read_limit) {
+                    Some(hash) => hash,
+                    None => {
+                        eprintln!("Error reading file {}", filename_string);
+                        return ExitCode::DataReadErr;
                     }
                 }
-                if let Ok((quoted_name, len_option)) = parse_functions::extract_quoted_filename(&next_line) {
-                    assert_eq!(len_option.is_none(), is_short_hash);
-                    let unquoted_name = match enquote::unquote(quoted_name) {
-                        Ok(s) => s,
-                        Err(e) => {
-                            eprintln!("Error: unable to unquote file name {}: {}",
-                                quoted_name, e);
-                            if is_short_hash {
-                                return VERIF_BAD_ENTRY_ERR;
-                            } else {
-                                return VERIF_BAD_HEADER_ERR;
-                            }
-                        }
-                    };
-                    let path = PathBuf::from(unquoted_name);
-                    if path.is_file() {
-                        if File::open(&path).is_err() {
-                            // We already checked file existence
-                            file_vec.push((path.to_string_lossy().into_owned(),
-                                Some(PreHashError::ReadPermissionError)))
-                        } else if let Some(expected_len) = len_option {
-                            let actual_len = path.metadata().unwrap().len();
-                            if actual_len == expected_len {
-                                file_vec.push((path.to_string_lossy().into_owned(), None));
-                            } else {
-                                let mismatch_len_obj = StoredAndComputed::new
-                                    (expected_len, actual_len);
-                                file_vec.push((
-                                    path.to_string_lossy().into_owned(),
-                                    Some(PreHashError::MismatchedLength(
-                                        mismatch_len_obj
-                                    )))
-                                )
-                            }
-                        } else {
-                            file_vec.push((path.to_string_lossy().into_owned(), None))
-                        }
-                    } else {
-                        file_vec.push(
-                            (
-                                path.to_string_lossy().into_owned(),
-                                Some(PreHashError::FileNotFound))
-                            )
-                    }
-                } else if next_line == "Hashes:\n" || next_line == "Hashes:\r\n" {
-                    assert!(!is_short_hash);
-                    break;
-                } else if next_line.is_empty() {
-                    assert!(is_short_hash);
-                    break;
-                } else {
-                    eprintln!("Error: encountered malformed file entry {:?}",
-                        next_line);
-                    return VERIF_BAD_HEADER_ERR;
-                }
+            };
+            let stored_hash = match data_len == 0 && cmd_matches.get_flag("markempty") {
+                true => None,
+                false => Some(&root_hash)
+            };
+            if let Err(err) = sink.write_short(stored_hash, file_parts) {
+                eprintln!("Error writing hash file: {}", err);
+                return ExitCode::GenWriteErr;
+            }
+            if quiet_count == 1 {
+                eprintln!("Done");
+            }
+        }
+    }
+    if let Err(err) = sink.finish() {
+        eprintln!("Error writing hash file: {}", err);
+        return ExitCode::GenWriteErr;
+    }
+    if let Some(sign_command) = cmd_matches.get_one::<String>("signcommand") {
+        if let Err(exit_code) = sign_hash_file(sign_command, &output_path) {
+            return exit_code;
+        }
+    }
+    interrupt::set_pending_output(None);
+    ExitCode::Success
+}
+
+// Hashes stdin while copying it to the --tee file, which is the file in the hash file
+// The length of stdin is only known at its end, so the hash file is written then
+fn generate_tee(cmd_matches: &ArgMatches, quiet_count: u8,
+        read_limit: Option<u64>) -> ExitCode {
+    let file_args = match expand_file_args(cmd_matches) {
+        Ok(file_args) => file_args,
+        Err(exit_code) => return exit_code
+    };
+    if file_args != ["-"] {
+        eprintln!("Error: --tee reads the data from stdin, so the only file must be -");
+        return ExitCode::CmdlineErr;
+    }
+    let tree_params = generate_tree_params(cmd_matches);
+    if let Err(exit_code) = check_hash_len(tree_params.hash_function) {
+        return exit_code;
+    }
+    print_generate_warnings(&tree_params, quiet_count);
+    let salt: Vec<u8> = cmd_matches.get_one::<Vec<u8>>("salt")
+        .cloned().unwrap_or_default();
+    let comments: Vec<String> = cmd_matches.get_many::<String>("comment")
+        .map(|comments| comments.cloned().collect())
+        .unwrap_or_default();
+    let short_output = cmd_matches.get_flag("short");
+    let overwrite = cmd_matches.get_flag("overwrite");
+
+    // --tee conflicts with --per-file, so --output is given
+    let write_file_name = cmd_matches.get_one::<String>("output").unwrap();
+    let output_path = PathBuf::from(write_file_name);
+    // Opened first, so that nothing is read from stdin if it can't be written
+    let mut sink = match open_output_file(&output_path, overwrite) {
+        Ok(file) => new_sink(LineWriter::new(file),
+            *cmd_matches.get_one::<HashFileFormat>("format").unwrap(),
+            cmd_matches.get_flag("selfchecksum"), cmd_matches.get_flag("withparity"),
+            cmd_matches.get_flag("uppercase")),
+        Err(err) => {
+            eprintln!("Error opening file {} for writing: {}", write_file_name, err);
+            return ExitCode::GenWriteErr;
+        }
+    };
+    if cmd_matches.get_flag("relativetooutput") {
+        sink = match relative_path_sink(sink, &output_path) {
+            Ok(sink) => sink,
+            Err(err) => {
+                eprintln!("Error finding directory of {}: {}", write_file_name, err);
+                return ExitCode::GenWriteErr;
+            }
+        };
+    }
+    let tee_name = cmd_matches.get_one::<String>("tee").unwrap();
+    let tee_path = PathBuf::from(tee_name);
+    let tee_file = match create_output_file(&tee_path, overwrite) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Error opening file {} for writing: {}", tee_name, err);
+            return ExitCode::GenWriteErr;
+        }
+    };
+    let file_parts = std::slice::from_ref(&tee_path);
+    if quiet_count == 0 {
+        let base_name = display_name_group(&[tee_path.file_name().unwrap()]);
+        eprintln!("{}", title_center(&abbreviate_filename(&base_name, 80-8)));
+    } else if quiet_count == 1 {
+        eprintln!("Hashing {}...", tee_name);
+    }
+
+    let tee_hash_thunk = tee_hash_fn(tree_params.hash_function);
+    let tee_result = tee_hash_thunk(ThrottledReader::new(io::stdin().lock(), read_limit),
+        BufWriter::new(tee_file), tree_params.block_size, tree_params.branch_factor,
+        &tree_params.tree_options(&salt));
+    let (file_len, root, nodes) = match tee_result {
+        Ok(tee_output) => tee_output,
+        Err(err) => {
+            eprintln!("Error copying stdin to file {}: {}", tee_name, err);
+            return match err {
+                TeeErr::ReadError(_) => ExitCode::DataReadErr,
+                TeeErr::WriteError(_) => ExitCode::GenWriteErr
+            };
+        }
+    };
+    let write_result = sink.write_header(&SinkHeader {
+        tree_params: &tree_params,
+        comments: &comments,
+        structure_hash: None,
+        file_entries: &[(file_parts, file_len, None)],
+        dir_list: &[],
+        short_output,
+        sectioned: false
+    }).and_then(|_| match short_output {
+        true => {
+            let stored_hash = match file_len == 0 && cmd_matches.get_flag("markempty") {
+                true => None,
+                false => Some(&root)
+            };
+            sink.write_short(stored_hash, file_parts)
+        },
+        false => {
+            let listed_root = tree_params.listed_root(file_len);
+            nodes.iter()
+                .filter(|node| is_listed_node(node.block_range(), listed_root))
+                .try_for_each(|node| sink.write_entry(0, node, 0))
+        }
+    }).and_then(|_| sink.finish());
+    if let Err(err) = write_result {
+        eprintln!("Error writing hash file: {}", err);
+        return ExitCode::GenWriteErr;
+    }
+    if let Some(sign_command) = cmd_matches.get_one::<String>("signcommand") {
+        if let Err(exit_code) = sign_hash_file(sign_command, &output_path) {
+            return exit_code;
+        }
+    }
+    interrupt::set_pending_output(None);
+    if quiet_count == 1 {
+        eprintln!("Done");
+    }
+    ExitCode::Success
+}
+
+// Checks a file against the root hash in its trailer
+fn verify_embedded(file_name: &str, quiet_count: u8,
+        progress_output: &ProgressOutput, thread_count: usize,
+        read_limit: Option<u64>) -> ExitCode {
+    let mut file_obj = match File::open(file_name) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Error opening file {}: {}", file_name, err);
+            return ExitCode::VerifReadErr;
+        }
+    };
+    let trailer = match EmbedTrailer::read_from(&mut file_obj) {
+        Ok(trailer) => trailer,
+        Err(err) => {
+            eprintln!("Error reading trailer of file {}: {}", file_name, err);
+            return ExitCode::VerifBadHeaderErr;
+        }
+    };
+    let body_len = seek_len(&mut file_obj) - trailer.len();
+    let body_reader = match TruncatedReader::new(file_obj, body_len) {
+        Ok(reader) => reader,
+        Err(err) => {
+            eprintln!("Error reading file {}: {}", file_name, err);
+            return ExitCode::DataReadErr;
+        }
+    };
+    let computed_hash = match hash_single_file(body_reader,
+            &trailer.tree_params, &[], progress_output, thread_count, read_limit) {
+        Some(hash) => hash,
+        None => {
+            eprintln!("Error reading file {}", file_name);
+            return ExitCode::DataReadErr;
+        }
+    };
+    if computed_hash != trailer.root_hash {
+        let err = VerificationError::MismatchedHash(None,
+            StoredAndComputed::new(trailer.root_hash, computed_hash));
+        eprintln!("Error verifying file {}: {}", file_name, err);
+        return ExitCode::VerifBadEntryErr;
+    }
+    if quiet_count < 2 {
+        eprintln!("Info: {} hash matches", file_name);
+    }
+    ExitCode::Success
+}
+
+// Hash file checked by verify-hash
+enum VerifyHashInput<'a> {
+    Path(&'a str),
+    // Output of --hashes-from-command, which is only run once
+    CommandOutput(&'a str, Vec<u8>)
+}
+impl VerifyHashInput<'_> {
+    // Runs the command of --hashes-from-command, whose stderr is passed through
+    fn from_command(command: &str) -> Result<VerifyHashInput<'_>, ExitCode> {
+        let output = std::process::Command::new("sh").arg("-c").arg(command)
+            .stderr(std::process::Stdio::inherit())
+            .output();
+        match output {
+            Ok(output) if output.status.success() =>
+                Ok(VerifyHashInput::CommandOutput(command, output.stdout)),
+            Ok(output) => {
+                eprintln!("Error: hashes command {} exited with {}", command, output.status);
+                Err(ExitCode::VerifReadErr)
+            },
+            Err(err) => {
+                eprintln!("Error: hashes command {} could not be run: {}", command, err);
+                Err(ExitCode::VerifReadErr)
+            }
+        }
+    }
+    // Name of the hash file in messages
+    fn name(&self) -> String {
+        match self {
+            VerifyHashInput::Path(file_name) => String::from(*file_name),
+            VerifyHashInput::CommandOutput(command, _) => format!("from command {}", command)
+        }
+    }
+    fn open_with_root(&self) -> Result<(HashFileReader, Option<HashData<64>>), ExitCode> {
+        match self {
+            VerifyHashInput::Path(file_name) => open_hash_file_with_root(file_name),
+            VerifyHashInput::CommandOutput(_, contents) => read_hash_source(
+                HashFileSource::from_contents(contents.clone()), &self.name())
+        }
+    }
+    // Runs --verify-command, which reads command output from stdin as the file -
+    fn run_verify_command(&self, verify_command: &str) -> Result<(), String> {
+        match self {
+            VerifyHashInput::Path(file_name) =>
+                run_hash_file_command(verify_command, Path::new(file_name)),
+            VerifyHashInput::CommandOutput(_, contents) =>
+                run_hash_contents_command(verify_command, contents)
+        }
+    }
+}
+
+// Prints the node counts and long format size of each file, for --dry-run
+fn print_long_format_sizes(file_list: &[(Vec<PathBuf>, Option<u64>, bool)],
+        data_lens: &[u64], tree_params: &TreeParams) -> ExitCode {
+    let mut total_size = LongFormatSize {leaf_nodes: 0, internal_nodes: 0, entry_bytes: 0};
+    let mut stdout = std::io::stdout().lock();
+    for ((file_parts, hash_len, _), data_len) in file_list.iter().zip(data_lens) {
+        let size = long_format_size(hash_len.unwrap_or(*data_len), tree_params.block_size,
+            tree_params.branch_factor, tree_params.hash_function.hash_len());
+        total_size.leaf_nodes += size.leaf_nodes;
+        total_size.internal_nodes += size.internal_nodes;
+        total_size.entry_bytes += size.entry_bytes;
+        if let Err(err) = writeln!(stdout, "{}: {} leaf nodes, {} internal nodes, {} bytes",
+                quote_name_group(file_parts), size.leaf_nodes, size.internal_nodes,
+                size.entry_bytes) {
+            eprintln!("Error writing sizes: {}", err);
+            return ExitCode::GenWriteErr;
+        }
+    }
+    if let Err(err) = writeln!(stdout, "Total: {} leaf nodes, {} internal nodes, {} bytes",
+            total_size.leaf_nodes, total_size.internal_nodes, total_size.entry_bytes) {
+        eprintln!("Error writing sizes: {}", err);
+        return ExitCode::GenWriteErr;
+    }
+    ExitCode::Success
+}
+
+// Counts a failed file for --max-failures, and is true once no more files may fail
+fn count_failure(failures_left: &mut Option<u64>) -> bool {
+    match failures_left {
+        Some(failures_left) => {
+            *failures_left = failures_left.saturating_sub(1);
+            if *failures_left == 0 {
+                eprintln!("Error: stopping because --max-failures was reached");
+            }
+            *failures_left == 0
+        },
+        None => false
+    }
+}
+
+fn check_salt_given(tree_params: &TreeParams, salt: &[u8]) -> Result<(), ExitCode> {
+    if tree_params.salted && salt.is_empty() {
+        eprintln!("Error: hash file was generated with a salt, which must be given with --salt");
+        Err(ExitCode::CmdlineErr)
+    } else if !tree_params.salted && !salt.is_empty() {
+        eprintln!("Error: hash file was generated without a salt, but --salt was given");
+        Err(ExitCode::CmdlineErr)
+    } else {
+        Ok(())
+    }
+}
+
+// Checked before hashing starts, so that an unsupported digest is not a panic
+fn check_hash_len(hash_enum: HashFunctions) -> Result<(), ExitCode> {
+    if hash_enum.hash_len_supported() {
+        Ok(())
+    } else {
+        eprintln!("Error: hash function {} has {}-byte digests, but at most {} bytes are supported",
+            hash_enum, hash_enum.hash_len(), MAX_HASH_LEN);
+        Err(ExitCode::CmdlineErr)
+    }
+}
+
+// A hash file only protects against tampering if its hash function does,
+// so this is printed when verifying as well as when generating
+fn print_hash_function_warning(hash_enum: HashFunctions) {
+    if matches!(hash_enum, HashFunctions::crc32 | HashFunctions::crc32le) {
+        eprintln!("Warning: CRC32 is not cryptographically secure and will only prevent accidental corruption");
+    }
+}
+
+// Settings that are allowed but probably not what was intended, silenced by -qq
+fn print_generate_warnings(tree_params: &TreeParams, quiet_count: u8) {
+    if quiet_count >= 2 {
+        return;
+    }
+    print_hash_function_warning(tree_params.hash_function);
+    if !tree_params.block_size.is_power_of_two() {
+        eprintln!("Warning: block length {} is not a power of 2, so blocks will not line up with disk sectors or pages",
+            tree_params.block_size);
+    }
+}
+
+// Above this many tree nodes for one file, blocks smaller than the default get a warning
+const MANY_NODES_WARNING: u64 = 1 << 20;
+const DEFAULT_BLOCK_SIZE: block_t = 4096;
+
+// Warns when the block length makes the tree over the largest file huge,
+// and suggests the smallest power of 2 block length that avoids it
+// Tiny blocks are still allowed, as they are useful for testing
+fn print_block_size_warning(file_list: &[(Vec<PathBuf>, Option<u64>, bool)],
+        data_lens: &[u64], tree_params: &TreeParams, quiet_count: u8) {
+    if quiet_count >= 2 || tree_params.metadata_only
+            || tree_params.block_size >= DEFAULT_BLOCK_SIZE {
+        return;
+    }
+    let tree_nodes = |file_len: u64, block_size: block_t| merkle_tree::checked_node_count(
+        file_len, block_size, tree_params.branch_factor).unwrap_or(u64::MAX);
+    let Some((file_parts, file_len)) = file_list.iter().zip(data_lens)
+        .map(|((file_parts, hash_len, _), data_len)| (file_parts, hash_len.unwrap_or(*data_len)))
+        .max_by_key(|(_, file_len)| *file_len) else {
+        return;
+    };
+    let node_count = tree_nodes(file_len, tree_params.block_size);
+    if node_count <= MANY_NODES_WARNING {
+        return;
+    }
+    let mut suggested_size = tree_params.block_size.next_power_of_two();
+    while suggested_size < DEFAULT_BLOCK_SIZE
+            && tree_nodes(file_len, suggested_size) > MANY_NODES_WARNING {
+        suggested_size *= 2;
+    }
+    eprintln!(concat!("Warning: block length {} gives file {} {} tree nodes, ",
+        "which will be slow to hash; consider a block length of at least {}"),
+        tree_params.block_size, display_name_group(file_parts), node_count, suggested_size);
+}
+
+// Offset of the first byte from start that differs between the parts of an entry
+// and their reference copies, looking at most len bytes ahead
+fn reference_differing_byte(file_parts: &[PathBuf], reference_parts: &[PathBuf],
+        start: u64, len: u64, decompression: Decompression,
+        normalize_eol: bool) -> std::io::Result<Option<u64>> {
+    let open_parts = |paths: &[PathBuf]| paths.iter()
+        .map(|path| DataFile::open(path, decompression, normalize_eol))
+        .collect::<std::io::Result<Vec<_>>>()
+        .map(ConcatReader::new);
+    let mut data = open_parts(file_parts)?;
+    let mut reference = open_parts(reference_parts)?;
+    first_differing_byte(&mut data, &mut reference, start, len)
+}
+
+// Prints where a mismatched byte range first differs from the reference copy,
+// and returns that position if there is one
+// range_shift is added to the printed range to get positions in the file
+fn print_byte_diff(file_parts: &[PathBuf], reference: &Path, byte_range: BlockRange,
+        range_shift: u64, decompression: Decompression, normalize_eol: bool) -> Option<u64> {
+    let reference_parts = reference_parts(reference, file_parts);
+    let reference_name = display_name_group(&reference_parts);
+    let differing_byte = reference_differing_byte(file_parts, &reference_parts,
+        byte_range.start() + range_shift, byte_range.range(), decompression, normalize_eol);
+    match differing_byte {
+        Ok(Some(file_pos)) => {
+            eprintln!("Info: first byte differing from reference {} is at {:#x}",
+                reference_name, file_pos - range_shift);
+            return Some(file_pos - range_shift);
+        },
+        Ok(None) => eprintln!("Info: byte range {} is the same in reference {}",
+            byte_range, reference_name),
+        Err(err) => eprintln!("Warning: unable to compare against reference {}: {}",
+            reference_name, err)
+    }
+    None
+}
+
+// Whether a file is within --max-file-size, by its length on disk
+// Files that can't be read are kept, to be reported when they are opened
+// Paths given on the command line, with @list arguments and --files0-from expanded
+// Directories are expanded later
+fn expand_file_args(cmd_matches: &ArgMatches) -> Result<Vec<String>, ExitCode> {
+    let mut file_vec: Vec<String> = Vec::new();
+    for file_arg in cmd_matches.get_many::<String>("FILES").into_iter().flatten() {
+        match file_arg.strip_prefix('@') {
+            Some(list_name) => match utils::read_path_list(list_name) {
+                Ok(paths) => file_vec.extend(paths),
+                Err(err) => {
+                    eprintln!("Error reading path list {}: {}", list_name, err);
+                    return Err(match err {
+                        PathListError::ReadError(_) => ExitCode::DataReadErr,
+                        _ => ExitCode::CmdlineErr
+                    });
+                }
+            },
+            None => file_vec.push(file_arg.clone())
+        }
+    }
+    if let Some(list_name) = cmd_matches.get_one::<String>("files0from") {
+        match utils::read_nul_path_list(list_name) {
+            Ok(paths) => file_vec.extend(paths),
+            Err(err) => {
+                eprintln!("Error reading path list {}: {}", list_name, err);
+                return Err(match err {
+                    PathListError::ReadError(_) => ExitCode::DataReadErr,
+                    _ => ExitCode::CmdlineErr
+                });
+            }
+        }
+    }
+    Ok(file_vec)
+}
+
+// How data files are read before hashing, from --decompress or --filter-command
+fn data_decompression(cmd_matches: &ArgMatches) -> Decompression {
+    match cmd_matches.get_one::<String>("filtercommand") {
+        // The command is used until the process exits, so it is fine to leak
+        Some(command) => Decompression::Filter(Box::leak(command.clone().into_boxed_str())),
+        // Unwrap always succeeds because "decompress" has a default value
+        None => *cmd_matches.get_one::<Decompression>("decompress").unwrap()
+    }
+}
+
+// unwraps will always succeed due to default values
+fn generate_tree_params(cmd_matches: &ArgMatches) -> TreeParams {
+    TreeParams {
+        // block_size has a special parser invoked in parse_cli
+        block_size: *cmd_matches.get_one("blocksize").unwrap(),
+        branch_factor: *cmd_matches.get_one("branch").unwrap(),
+        hash_function: *cmd_matches.get_one("hash").unwrap(),
+        node_encoding: *cmd_matches.get_one("nodeencoding").unwrap(),
+        salted: cmd_matches.contains_id("salt"),
+        pad_tree: cmd_matches.get_flag("padtree"),
+        unbalanced: cmd_matches.get_flag("unbalanced"),
+        duplicate_last: cmd_matches.get_flag("duplicatelast"),
+        metadata_only: cmd_matches.get_flag("metadataonly"),
+        trim_zeros: cmd_matches.get_flag("trimzeros"),
+        // A length alone gives a region at the start of the file
+        region_offset: match (cmd_matches.get_one::<u64>("offset"),
+                cmd_matches.contains_id("length")) {
+            (Some(offset), _) => Some(*offset),
+            (None, true) => Some(0),
+            (None, false) => None
+        },
+        // unwrap always succeeds because "byterangebase" has a default value
+        absolute_ranges: *cmd_matches.get_one::<ByteRangeBase>("byterangebase").unwrap()
+            == ByteRangeBase::Absolute,
+        leaves_and_root: cmd_matches.get_flag("leavesandroot"),
+        content_defined: cmd_matches.get_flag("cdc"),
+        normalize_eol: cmd_matches.get_flag("normalizeeol"),
+        relative_names: cmd_matches.get_flag("relativetooutput"),
+        nominal_ranges: cmd_matches.get_flag("nominalranges"),
+        stride: cmd_matches.get_one::<block_t>("stride").copied(),
+        filtered: cmd_matches.contains_id("filtercommand")
+    }
+}
+
+// With --params-from-xattr, fills in the tree parameters not given as flags
+// from the extended attribute of the first file
+fn apply_xattr_params(tree_params: &mut TreeParams, cmd_matches: &ArgMatches,
+        file_args: &[String], quiet_count: u8) -> Result<(), ExitCode> {
+    if !cmd_matches.get_flag("paramsfromxattr") {
+        return Ok(());
+    }
+    let Some(first_file) = file_args.first() else {
+        return Ok(());
+    };
+    match read_xattr_params(Path::new(first_file)) {
+        Ok(xattr_params) => {
+            let from_flag = |id| cmd_matches.value_source(id) == Some(ValueSource::CommandLine);
+            if !from_flag("blocksize") {
+                tree_params.block_size = xattr_params.block_size;
+            }
+            if !from_flag("branch") {
+                tree_params.branch_factor = xattr_params.branch_factor;
+            }
+            if !from_flag("hash") {
+                tree_params.hash_function = xattr_params.hash_function;
+            }
+            Ok(())
+        },
+        Err(err @ (XattrParamsErr::Unsupported | XattrParamsErr::Absent)) => {
+            if quiet_count < 2 {
+                eprintln!("Warning: no {} attribute on {}: {}, using the flag values",
+                    PARAMS_XATTR_NAME, first_file, err);
+            }
+            Ok(())
+        },
+        Err(err) => {
+            eprintln!("Error reading {} attribute of {}: {}",
+                PARAMS_XATTR_NAME, first_file, err);
+            Err(match err {
+                XattrParamsErr::ReadError(_) => ExitCode::DataReadErr,
+                _ => ExitCode::CmdlineErr
+            })
+        }
+    }
+}
+
+fn check_file_size(paths: &[PathBuf], cmd_matches: &ArgMatches,
+        quiet_count: u8) -> Result<bool, ExitCode> {
+    let max_file_size = match cmd_matches.get_one::<u64>("maxfilesize") {
+        Some(max_file_size) => *max_file_size,
+        None => return Ok(true)
+    };
+    let file_size: u64 = paths.iter()
+        .filter_map(|path| path.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    if file_size <= max_file_size {
+        Ok(true)
+    } else if cmd_matches.get_flag("maxfilesizefatal") {
+        eprintln!("Error: file {} of {} bytes is larger than --max-file-size of {} bytes",
+            display_name_group(paths), file_size, max_file_size);
+        Err(ExitCode::PreHashErr)
+    } else {
+        if quiet_count < 2 {
+            eprintln!("Warning: skipping file {} of {} bytes, which is larger than --max-file-size of {} bytes",
+                display_name_group(paths), file_size, max_file_size);
+        }
+        Ok(false)
+    }
+}
+
+// Number of files at the start of file_list that are hashed with --max-total-bytes
+// Files are counted until their lengths reach the budget, so the last one may go past it
+fn budget_file_count(file_list: &[(Vec<PathBuf>, Option<u64>, bool)],
+        max_total_bytes: u64) -> usize {
+    let mut total_bytes: u64 = 0;
+    file_list.iter()
+        .take_while(|(file_parts, _, _)| {
+            let under_budget = total_bytes < max_total_bytes;
+            total_bytes = total_bytes.saturating_add(file_parts.iter()
+                .filter_map(|path| path.metadata().ok())
+                .map(|metadata| metadata.len())
+                .sum());
+            under_budget
+        })
+        .count()
+}
+
+// For each file, the index of an earlier file with the same contents, if any
+// Files are grouped by length, then by a fingerprint, before being compared in full
+fn duplicate_sources(file_list: &[(Vec<PathBuf>, Option<u64>, bool)], data_lens: &[u64],
+        decompression: Decompression, normalize_eol: bool) -> Result<Vec<Option<usize>>, ExitCode> {
+    let mut sources = vec![None; file_list.len()];
+    let mut len_counts: HashMap<u64, usize> = HashMap::new();
+    for (file_index, (_, _, process)) in file_list.iter().enumerate() {
+        if *process {
+            *len_counts.entry(data_lens[file_index]).or_default() += 1;
+        }
+    }
+    let open_data = |paths: &[PathBuf]| -> std::io::Result<ConcatReader<DataFile>> {
+        let part_files = paths.iter()
+            .map(|path| DataFile::open(path, decompression, normalize_eol))
+            .collect::<std::io::Result<_>>()?;
+        Ok(ConcatReader::new(part_files))
+    };
+    // Files not yet found to be duplicates, by length and fingerprint
+    let mut candidates: HashMap<(u64, Vec<u8>), Vec<usize>> = HashMap::new();
+    for (file_index, (file_parts, _, process)) in file_list.iter().enumerate() {
+        let data_len = data_lens[file_index];
+        if !*process || len_counts[&data_len] < 2 {
+            continue;
+        }
+        let compare_result = read_adapters::data_fingerprint(file_parts,
+                decompression, normalize_eol)
+            .and_then(|fingerprint| {
+                let same_key = candidates.entry((data_len, fingerprint)).or_default();
+                for &candidate in same_key.iter() {
+                    if read_adapters::contents_equal(open_data(&file_list[candidate].0)?,
+                            open_data(file_parts)?)? {
+                        return Ok(Some(candidate));
+                    }
+                }
+                same_key.push(file_index);
+                Ok(None)
+            });
+        match compare_result {
+            Ok(source) => sources[file_index] = source,
+            Err(err) => {
+                eprintln!("Error reading file {}: {}", display_name_group(file_parts), err);
+                return Err(ExitCode::DataReadErr);
+            }
+        }
+    }
+    Ok(sources)
+}
+
+// Checks that the leaf byte ranges of each file in a long hash file tile the file
+fn verify_leaf_coverage(file_name: &str) -> Result<(), ExitCode> {
+    let parsed_file = read_parsed_hash_file(file_name)?;
+    let tree_params = &parsed_file.header.tree_params;
+    if parsed_file.header.is_short_hash || tree_params.metadata_only {
+        eprintln!("Error: --check-coverage requires a hash file with the full tree");
+        return Err(ExitCode::CmdlineErr);
+    }
+    // Leaves with a stride below the block length overlap by design
+    if tree_params.stride.is_some() {
+        eprintln!("Error: --check-coverage does not support hash files with a stride");
+        return Err(ExitCode::CmdlineErr);
+    }
+    for (file_index, file_entry) in parsed_file.files.iter().enumerate() {
+        let leaf_byte_ranges: Vec<BlockRange> = parsed_file.entries.iter()
+            .filter_map(|entry| match entry {
+                HashEntry::Long(entry_index, hash_range) if *entry_index == file_index
+                        && hash_range.block_range().range() == 1 =>
+                    Some(hash_range.byte_range()),
+                _ => None
+            })
+            .collect();
+        // Entries only cover the hashed length when trailing zeros were trimmed
+        let covered_len = file_entry.hashed_len.unwrap_or(file_entry.file_len);
+        if let Err(err) = lint::check_leaf_coverage(leaf_byte_ranges,
+                tree_params.byte_range_offset(), tree_params.range_file_len(covered_len)) {
+            let paths: Vec<PathBuf> = file_entry.names.iter().map(PathBuf::from).collect();
+            eprintln!("Error verifying file {}: {}", display_name_group(&paths), err);
+            return Err(ExitCode::VerifBadEntryErr);
+        }
+    }
+    Ok(())
+}
+
+// Checks parent hashes against their children in a long hash file
+fn verify_self_consistent(file_name: &str, salt: &[u8], quiet_count: u8) -> ExitCode {
+    let mut hash_file_reader = match open_hash_file(file_name) {
+        Ok(reader) => reader,
+        Err(exit_code) => return exit_code
+    };
+    let header = match read_hash_header(&mut hash_file_reader) {
+        Ok(header) => header,
+        Err(exit_code) => return exit_code
+    };
+    if header.is_short_hash || header.tree_params.metadata_only {
+        eprintln!("Error: --self-consistent requires a hash file with the full tree");
+        return ExitCode::CmdlineErr;
+    }
+    if let Err(exit_code) = check_salt_given(&header.tree_params, salt) {
+        return exit_code;
+    }
+    match self_check::check_hash_list(hash_file_reader, header.line_count,
+            &header.tree_params, salt) {
+        None => {
+            if quiet_count < 2 {
+                eprintln!("Info: {} is self-consistent", file_name);
+            }
+            ExitCode::Success
+        },
+        Some((line_number, err)) => {
+            eprintln!("Error: line {}: {}", line_number, err);
+            ExitCode::VerifBadEntryErr
+        }
+    }
+}
+
+// Hashes the data of reader, of data_len bytes, and returns its leaf hashes in order
+#[allow(clippy::too_many_arguments)]
+fn hash_leaves<R: Read+Seek+Send+'static>(reader: R, data_len: u64,
+        tree_params: &TreeParams, salt: &[u8], thread_name: &str,
+        progress_output: &ProgressOutput, thread_count: usize,
+        read_limit: Option<u64>) -> Option<Vec<HashData<64>>> {
+    let pb_hash_len = merkle_tree::node_count(data_len,
+        tree_params.block_size, tree_params.branch_factor);
+    let (pb_file, pb_hash) = setup_pbs(progress_output.draw_target(),
+        data_len, pb_hash_len);
+    let (tx, rx) = bounded_channel::<HashRange>(16);
+    let merkle_tree_thunk = merkle_hash_fn(tree_params.hash_function);
+    let tree_options = tree_params.tree_options(salt);
+    let (block_size, branch_factor) = (tree_params.block_size, tree_params.branch_factor);
+    let thread_handle = thread::Builder::new()
+        .name(thread_name.to_owned())
+        .spawn(move || {
+            let throttled_reader = ThrottledReader::new(reader, read_limit);
+            let result = merkle_tree_thunk(pb_file.wrap_read(throttled_reader),
+                block_size, branch_factor, &tree_options,
+                ChannelOrPb::from(tx), thread_count);
+            pb_file.finish();
+            result
+        })
+        .unwrap();
+    // Leaves may arrive out of order from the thread pool
+    let mut leaves: Vec<(u64, HashData<64>)> = Vec::new();
+    for hash_range in rx {
+        pb_hash.inc(1);
+        if hash_range.block_range().range() == 1 {
+            leaves.push((hash_range.block_range().start(),
+                HashData::try_new(hash_range.hash_result()).unwrap()));
+        }
+    }
+    pb_hash.finish();
+    thread_handle.join().unwrap().ok()?;
+    leaves.sort_unstable_by_key(|(block_index, _)| *block_index);
+    Some(leaves.into_iter().map(|(_, leaf_hash)| leaf_hash).collect())
+}
+
+// Lists the byte ranges of a file whose leaf hashes differ from a long hash file
+fn diff_data_file(hash_file_name: &str, data_file_name: &str, salt: &[u8],
+        quiet_count: u8, progress_output: &ProgressOutput, thread_count: usize,
+        read_limit: Option<u64>) -> ExitCode {
+    let mut hash_file_reader = match open_hash_file(hash_file_name) {
+        Ok(reader) => reader,
+        Err(exit_code) => return exit_code
+    };
+    let header = match read_hash_header(&mut hash_file_reader) {
+        Ok(header) => header,
+        Err(exit_code) => return exit_code
+    };
+    let tree_params = header.tree_params;
+    if header.is_short_hash || tree_params.metadata_only {
+        eprintln!("Error: diff-file requires a hash file with leaf hashes");
+        return ExitCode::CmdlineErr;
+    }
+    if tree_params.region_offset.is_some() {
+        eprintln!("Error: diff-file does not support hash files of regions within files");
+        return ExitCode::CmdlineErr;
+    }
+    if tree_params.content_defined {
+        eprintln!("Error: diff-file does not support hash files with content-defined chunks");
+        return ExitCode::CmdlineErr;
+    }
+    if tree_params.stride.is_some() {
+        eprintln!("Error: diff-file does not support hash files with a stride");
+        return ExitCode::CmdlineErr;
+    }
+    if let Err(exit_code) = check_salt_given(&tree_params, salt) {
+        return exit_code;
+    }
+    let stored_file = match read_stored_file(hash_file_reader, header.line_count,
+            &tree_params, data_file_name) {
+        Ok(stored_file) => stored_file,
+        Err(err) => {
+            eprintln!("Error reading hash file {}: {}", hash_file_name, err);
+            return match err {
+                DiffFileErr::FileNotListed(_) => ExitCode::CmdlineErr,
+                DiffFileErr::ReadError(_) => ExitCode::VerifReadErr,
+                _ => ExitCode::VerifBadEntryErr
+            };
+        }
+    };
+
+    let mut file_obj = match DataFile::open(Path::new(data_file_name),
+            Decompression::None, tree_params.normalize_eol) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Error opening file {}: {}", data_file_name, err);
+            return ExitCode::DataReadErr;
+        }
+    };
+    let current_len = seek_len(&mut file_obj);
+    let current_leaf_hashes = match hash_leaves(file_obj, current_len, &tree_params,
+            salt, data_file_name, progress_output, thread_count, read_limit) {
+        Some(leaf_hashes) => leaf_hashes,
+        None => {
+            eprintln!("Error reading file {}", data_file_name);
+            return ExitCode::DataReadErr;
+        }
+    };
+
+    let changes = diff_leaves(&stored_file, current_len,
+        &current_leaf_hashes, tree_params.block_size);
+    for change in &changes {
+        println!("{}", change);
+    }
+    if quiet_count < 2 {
+        match changes.len() {
+            0 => eprintln!("Info: no changes found in {}", data_file_name),
+            change_count => eprintln!("Info: found {} changed byte ranges in {}",
+                change_count, data_file_name)
+        }
+    }
+    ExitCode::Success
+}
+
+// Settings of verify-hash --follow that stay the same between updates
+struct FollowParams<'a> {
+    hash_file_name: &'a str,
+    salt: &'a [u8],
+    relative_to_cwd: bool,
+    quiet_count: u8,
+    progress_output: &'a ProgressOutput,
+    thread_count: usize,
+    read_limit: Option<u64>
+}
+
+// Outcome of checking a followed hash file once
+enum FollowRound {
+    Verified,
+    // The file is shorter than its recorded length, with its current and recorded lengths
+    WaitingForData(u64, u64)
+}
+
+// Length and modification time, which change whenever a followed file is written
+type FileSignature = (u64, std::time::SystemTime);
+fn file_signature(metadata: io::Result<fs::Metadata>) -> Option<FileSignature> {
+    let metadata = metadata.ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+// Checks a followed hash file, hashing only the data past the verified whole leaves
+// data_path is set to the path of the hashed file once it is known,
+// and the signature of the hashed file is returned from when it was opened
+fn follow_round(params: &FollowParams, verified: &mut Option<VerifiedPrefix>,
+        data_path: &mut Option<PathBuf>)
+        -> Result<(FollowRound, Option<FileSignature>), ExitCode> {
+    let hash_file_name = params.hash_file_name;
+    // Read once, so that a hash file replaced partway through is not mixed with its old contents
+    let hash_file_bytes = match fs::read(hash_file_name) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Error opening hash file {}: {}", hash_file_name, err);
+            return Err(ExitCode::VerifReadErr);
+        }
+    };
+    let parsed_file = parse_hash_file_contents(&hash_file_bytes, hash_file_name)?;
+    let tree_params = parsed_file.header.tree_params;
+    if parsed_file.header.is_short_hash || tree_params.metadata_only {
+        eprintln!("Error: --follow requires a hash file with the full tree");
+        return Err(ExitCode::CmdlineErr);
+    }
+    if tree_params.trim_zeros || tree_params.region_offset.is_some()
+            || tree_params.content_defined || tree_params.normalize_eol
+            || tree_params.stride.is_some() || tree_params.filtered {
+        eprintln!(concat!("Error: --follow does not support hash files with ",
+            "trimmed zeros, regions, content-defined chunks, normalized line endings, ",
+            "a stride, or filtered data"));
+        return Err(ExitCode::CmdlineErr);
+    }
+    if parsed_file.header.structure_hash.is_some() {
+        eprintln!("Error: --follow does not support hash files with a tree structure hash");
+        return Err(ExitCode::CmdlineErr);
+    }
+    let file_entry = match parsed_file.files.as_slice() {
+        [file_entry] if file_entry.names.len() == 1 => file_entry,
+        _ => {
+            eprintln!("Error: --follow requires a hash file of exactly one file");
+            return Err(ExitCode::CmdlineErr);
+        }
+    };
+    check_salt_given(&tree_params, params.salt)?;
+
+    // Internal nodes change as data is appended, so they are all checked every time
+    let (mut hash_file_reader, _) = read_hash_source(
+        HashFileSource::from_contents(hash_file_bytes), hash_file_name)?;
+    let header = read_hash_header(&mut hash_file_reader)?;
+    if let Some((line_number, err)) = self_check::check_hash_list(hash_file_reader,
+            header.line_count, &tree_params, params.salt) {
+        eprintln!("Error: line {}: {}", line_number, err);
+        return Err(ExitCode::VerifBadEntryErr);
+    }
+
+    let name_base = match tree_params.relative_names && !params.relative_to_cwd {
+        true => Path::new(hash_file_name).parent().map(Path::to_path_buf).unwrap_or_default(),
+        false => PathBuf::new()
+    };
+    let file_path = name_base.join(&file_entry.names[0]);
+    let display_name = file_path.display().to_string();
+    *data_path = Some(file_path.clone());
+
+    let recorded_len = file_entry.file_len;
+    // An empty file still has a single leaf, and padding leaves are left out
+    let leaf_count = recorded_len.div_ceil(tree_params.block_size.into()).max(1);
+    let mut stored_leaves: Vec<Option<HashData<64>>> = vec![None; leaf_count as usize];
+    for entry in &parsed_file.entries {
+        if let HashEntry::Long(_, hash_range) = entry {
+            let block_range = hash_range.block_range();
+            if block_range.range() == 1 && block_range.start() < leaf_count {
+                stored_leaves[block_range.start() as usize] =
+                    Some(HashData::try_new(hash_range.hash_result()).unwrap());
+            }
+        }
+    }
+    let stored_leaves = match stored_leaves.into_iter().collect::<Option<Vec<_>>>() {
+        Some(leaves) => leaves,
+        None => {
+            let err = VerificationError::MissingChildEntries(
+                BlockRange::new(0, leaf_count - 1, true));
+            eprintln!("Error verifying file {}: {}", display_name, err);
+            return Err(ExitCode::VerifBadEntryErr);
+        }
+    };
+
+    if let Some(prefix) = verified {
+        if !prefix.is_extended_by(&tree_params, &file_entry.names, &stored_leaves) {
+            if params.quiet_count < 2 {
+                eprintln!("Info: hash file {} was rewritten, verifying {} from the start",
+                    hash_file_name, display_name);
+            }
+            *verified = None;
+        }
+    }
+    let previous_len = verified.as_ref().map(VerifiedPrefix::file_len);
+    let prefix = verified.get_or_insert_with(
+        || VerifiedPrefix::new(tree_params, file_entry.names.clone()));
+
+    let mut file_obj = match File::open(&file_path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Error opening file {}: {}", display_name, err);
+            return Err(ExitCode::DataReadErr);
+        }
+    };
+    let data_signature = file_signature(file_obj.metadata());
+    let current_len = seek_len(&mut file_obj);
+    if current_len < prefix.byte_len() {
+        eprintln!("Error verifying file {}: truncated to {} bytes after {} bytes were verified",
+            display_name, current_len, prefix.byte_len());
+        return Err(ExitCode::VerifBadEntryErr);
+    }
+    if current_len < recorded_len {
+        return Ok((FollowRound::WaitingForData(current_len, recorded_len), data_signature));
+    }
+    // Data past the recorded length is checked once the hash file covers it
+    let start_len = prefix.byte_len();
+    let reader = match TruncatedReader::with_offset(file_obj, start_len,
+            recorded_len - start_len) {
+        Ok(reader) => reader,
+        Err(err) => {
+            eprintln!("Error reading file {}: {}", display_name, err);
+            return Err(ExitCode::DataReadErr);
+        }
+    };
+    // Leaf hashes do not depend on their position, so the rest hashes like a file of its own
+    let computed_leaves = match hash_leaves(reader, recorded_len - start_len, &tree_params,
+            params.salt, &display_name, params.progress_output,
+            params.thread_count, params.read_limit) {
+        Some(leaf_hashes) => leaf_hashes,
+        None => {
+            eprintln!("Error reading file {}", display_name);
+            return Err(ExitCode::DataReadErr);
+        }
+    };
+    for (block_index, stored_leaf) in stored_leaves.iter().enumerate().skip(prefix.leaf_count()) {
+        let computed_leaf = &computed_leaves[block_index - prefix.leaf_count()];
+        if stored_leaf != computed_leaf {
+            let byte_range = block_byte_range(
+                BlockRange::new(block_index as u64, block_index as u64, true),
+                tree_params.block_size, recorded_len);
+            let err = VerificationError::MismatchedHash(Some(byte_range),
+                StoredAndComputed::new(stored_leaf.clone(), computed_leaf.clone()));
+            eprintln!("Error verifying file {}: {}", display_name, err);
+            return Err(ExitCode::VerifBadEntryErr);
+        }
+    }
+    prefix.extend(&stored_leaves, recorded_len);
+    // Appended data that the hash file does not cover yet is not worth reporting
+    if params.quiet_count < 2 && previous_len != Some(recorded_len) {
+        eprintln!("Info: verified the first {} bytes of {}, reading from byte {}",
+            recorded_len, display_name, start_len);
+    }
+    Ok((FollowRound::Verified, data_signature))
+}
+
+// Verifies a long hash file of one file, then verifies it again whenever
+// either file changes, until interrupted or idle for idle_exit
+fn verify_follow(params: &FollowParams, poll_interval: Duration,
+        idle_exit: Option<Duration>) -> ExitCode {
+    // Without the handler, an interrupt still ends the process, only without a message
+    let _ = interrupt::install_handler();
+    let mut verified: Option<VerifiedPrefix> = None;
+    let mut data_path: Option<PathBuf> = None;
+    let mut last_signatures = None;
+    let mut last_round = FollowRound::Verified;
+    let mut last_change = Instant::now();
+    loop {
+        let hash_file_signature = file_signature(fs::metadata(params.hash_file_name));
+        let data_signature = data_path.as_ref()
+            .and_then(|data_path| file_signature(fs::metadata(data_path)));
+        if last_signatures != Some((hash_file_signature, data_signature)) {
+            last_change = Instant::now();
+            let (round, data_signature) = match follow_round(params,
+                    &mut verified, &mut data_path) {
+                Ok(round) => round,
+                Err(exit_code) => return exit_code
+            };
+            last_round = round;
+            last_signatures = Some((hash_file_signature, data_signature));
+        } else if idle_exit.is_some_and(|idle_exit| last_change.elapsed() >= idle_exit) {
+            return match last_round {
+                FollowRound::Verified => ExitCode::Success,
+                FollowRound::WaitingForData(current_len, recorded_len) => {
+                    eprintln!("Error verifying file {}: only {} of its {} recorded bytes were written",
+                        data_path.unwrap().display(), current_len, recorded_len);
+                    ExitCode::VerifBadEntryErr
+                }
+            };
+        }
+        thread::sleep(poll_interval);
+    }
+}
+
+// Writes or checks one metadata hash entry per file, without reading contents
+#[allow(clippy::too_many_arguments)]
+fn metadata_entries<S, R>(cmd_chosen: HashCommand<Box<S>, R>,
+        file_list: &[(Vec<PathBuf>, Option<u64>, bool)],
+        hash_function: HashFunctions, quiet_count: u8, fail_fast: bool,
+        mut failures_left: Option<u64>, results_file: &mut Option<ResultsFile>,
+        pre_hash_errors: &[Option<String>]) -> ExitCode
+where
+    S: HashSink+Send+?Sized,
+    R: BufRead+Seek+Send+std::fmt::Debug
+{
+    let mut final_status = ExitCode::Success;
+    match cmd_chosen {
+        HashCommand::GenerateHash(Some(mut w)) => {
+            for (file_index, (file_parts, _, _)) in file_list.iter().enumerate() {
+                // --metadata-only conflicts with --concat, so there is one part each
+                let file_path = &file_parts[0];
+                match metadata_hash(file_path, hash_function) {
+                    Ok(hash) => w.write_metadata(file_index, &hash).unwrap(),
+                    Err(err) => {
+                        eprintln!("Error reading metadata of file {}: {}",
+                            file_path.display(), err);
+                        return ExitCode::DataReadErr;
+                    }
+                }
+            }
+            if let Err(err) = w.finish() {
+                eprintln!("Error writing hash file: {}", err);
+                return ExitCode::GenWriteErr;
+            }
+        },
+        HashCommand::VerifyHash(Some(mut r)) => {
+            let hex_digit_count = 2*hash_function.hash_len();
+            for (file_index, (file_parts, _, process)) in file_list.iter().enumerate() {
+                let filename_string = display_name_group(file_parts);
+                let mut line = String::new();
+                r.read_line(&mut line).unwrap();
+                let entry_result = match extract_metadata_hash_parts(&line, hex_digit_count) {
+                    Ok((file_id, _)) if file_id != file_index =>
+                        Err(VerificationError::MismatchedFileID),
+                    Ok((_, stored_hash)) => Ok(stored_hash),
+                    Err(err) => Err(err.into_verification_error(line))
+                };
+                // Errors found before hashing were already reported
+                if !process {
+                    if let Err(err) = entry_result {
+                        eprintln!("Error skipping file {}: {}", filename_string, err);
+                        return ExitCode::VerifBadEntryErr;
+                    }
+                    if quiet_count < 2 {
+                        eprintln!("Warning: skipping file {}", filename_string);
+                    }
+                    let result = match &pre_hash_errors[file_index] {
+                        Some(err) => FileResult::failed(err.clone()),
+                        None => FileResult::new(VerifyOutcome::Skipped)
+                    };
+                    if let Err(exit_code) = write_result(results_file, file_index,
+                            file_parts, &result) {
+                        return exit_code;
+                    }
+                    continue;
+                }
+                let verify_result = match entry_result {
+                    // File existence was checked earlier
+                    Ok(stored_hash) => match metadata_hash(&file_parts[0], hash_function) {
+                        Ok(computed_hash) if computed_hash == stored_hash => Ok(()),
+                        Ok(_) => Err(VerificationError::MismatchedMetadata),
+                        Err(err) => panic!("Unable to read metadata of file {}: {}",
+                            filename_string, err)
+                    },
+                    Err(err) => Err(err)
+                };
+                let result = match &verify_result {
+                    Ok(()) => FileResult::new(VerifyOutcome::Ok),
+                    Err(err) => FileResult::mismatched(err, None)
+                };
+                if let Err(exit_code) = write_result(results_file, file_index,
+                        file_parts, &result) {
+                    return exit_code;
+                }
+                match verify_result {
+                    Ok(()) => {
+                        if quiet_count < 2 {
+                            eprintln!("Info: {} metadata matches", filename_string);
+                        }
+                    },
+                    Err(err) => {
+                        eprintln!("Error verifying file {}: {}", filename_string, err);
+                        if fail_fast || err == VerificationError::MismatchedFileID
+                                || count_failure(&mut failures_left) {
+                            return ExitCode::VerifBadEntryErr;
+                        }
+                        final_status = ExitCode::VerifBadEntryErr;
+                    }
+                }
+            }
+            let current_pos = r.stream_position().unwrap();
+            let end_pos = r.seek(SeekFrom::End(0)).unwrap();
+            if current_pos != end_pos {
+                eprintln!("Error: hash file has extra lines left over");
+                return ExitCode::VerifBadEntryErr;
+            }
+        },
+        _ => unreachable!()
+    }
+    final_status
+}
+
+// Opened files are removed if interrupted before being marked complete
+// Reads the remaining long entries of file_index, returning the last one
+#[allow(clippy::result_large_err)]
+// With any_order, the entries of any other file end the entries of this one
+fn skip_long_entries<R: BufRead+Seek>(reader: &mut R, file_index: usize,
+        hex_digit_count: usize, any_order: bool) -> Result<Option<HashRange>, VerificationError> {
+    let mut last_entry: Option<HashRange> = None;
+    loop {
+        let mut hash_line = String::new();
+        let line_start = reader.stream_position().unwrap();
+        let chars_read = reader.read_line(&mut hash_line).unwrap();
+        match extract_long_hash_parts(&hash_line, hex_digit_count) {
+            Ok((read_index, _)) if read_index == file_index + 1
+                    || (any_order && read_index != file_index) => {
+                // Back to before any section marker of the next file
+                reader.seek(SeekFrom::Start(line_start)).unwrap();
+                return Ok(last_entry);
+            },
+            Ok((read_index, _)) if read_index != file_index => {
+                return Err(VerificationError::MismatchedFileID);
+            },
+            Ok((_, hash_range)) => last_entry = Some(hash_range),
+            Err(_) if chars_read == 0 => return Ok(last_entry), // EOF
+            Err(err) => return Err(err.into_verification_error(hash_line))
+        }
+    }
+}
+
+// With --any-order, the file before next_index must have read all of its entries
+fn check_section_end<R: Seek>(reader: &mut R, sections: &[Range<u64>],
+        next_index: usize) -> Result<(), ExitCode> {
+    let prev_section = match next_index.checked_sub(1) {
+        Some(prev_index) => &sections[prev_index],
+        None => return Ok(())
+    };
+    if reader.stream_position().unwrap() != prev_section.end {
+        eprintln!("Error: hash file has extra lines left over for file {}", next_index - 1);
+        return Err(ExitCode::VerifBadEntryErr);
+    }
+    Ok(())
+}
+
+// Writes the root of a file to the --root-out file, if there is one
+fn write_root_out(root_out: &mut Option<LineWriter<File>>, root: &HashData<64>,
+        file_parts: &[PathBuf], uppercase: bool) -> Result<(), ExitCode> {
+    if let Some(root_out) = root_out {
+        let line = checksum_line(&hash_hex(root, uppercase), &display_name_group(file_parts));
+        if let Err(err) = writeln!(root_out, "{}", line) {
+            eprintln!("Error writing root file: {}", err);
+            return Err(ExitCode::GenWriteErr);
+        }
+    }
+    Ok(())
+}
+
+// Writes the result of a file to the --results file, if there is one
+fn write_result(results_file: &mut Option<ResultsFile>, file_index: usize,
+        file_parts: &[PathBuf], result: &FileResult) -> Result<(), ExitCode> {
+    if let Some(results_file) = results_file {
+        if let Err(err) = results_file.record(file_index, file_parts, result) {
+            eprintln!("Error writing results file: {}", err);
+            return Err(ExitCode::GenWriteErr);
+        }
+    }
+    Ok(())
+}
+
+// Stored names of a verified file, resolved as when the file was opened
+fn resolve_names(name_base: &Path, quoted_name: &str) -> Vec<PathBuf> {
+    unquote_name_group(quoted_name).unwrap().into_iter()
+        .map(|name| name_base.join(name))
+        .collect()
+}
+
+// Wraps sink to write file names relative to the directory of output_path,
+// which must already exist
+fn relative_path_sink(sink: Box<dyn HashSink+Send>, output_path: &Path)
+        -> std::io::Result<Box<dyn HashSink+Send>> {
+    let output_path = fs::canonicalize(output_path)?;
+    let output_dir = output_path.parent().unwrap_or(&output_path).to_path_buf();
+    Ok(Box::new(RelativePathSink::new(sink, output_dir)))
+}
+
+// Runs a shell command with the hash file path as its last argument
+// Err describes how the command failed
+fn run_hash_file_command(command: &str, hash_file: &Path) -> Result<(), String> {
+    // The path is passed as a positional parameter so the shell never parses it
+    let status = std::process::Command::new("sh").arg("-c")
+        .arg(format!("{} \"$1\"", command))
+        .arg("sh").arg(hash_file)
+        .status();
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("exited with {}", status)),
+        Err(err) => Err(format!("could not be run: {}", err))
+    }
+}
+
+// Same as run_hash_file_command, for a hash file that only exists in memory
+// The hash file is written to stdin, and - is the last argument instead of a path
+fn run_hash_contents_command(command: &str, contents: &[u8]) -> Result<(), String> {
+    let child = std::process::Command::new("sh").arg("-c")
+        .arg(format!("{} -", command))
+        .stdin(std::process::Stdio::piped())
+        .spawn();
+    let mut child = child.map_err(|err| format!("could not be run: {}", err))?;
+    // A command that exits without reading everything is judged by its status
+    let _ = child.stdin.take().unwrap().write_all(contents);
+    match child.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("exited with {}", status)),
+        Err(err) => Err(format!("could not be run: {}", err))
+    }
+}
+
+// Runs --sign-command on a finished hash file, and removes the hash file on failure
+fn sign_hash_file(sign_command: &str, hash_file: &Path) -> Result<(), ExitCode> {
+    let failure = match run_hash_file_command(sign_command, hash_file) {
+        Ok(()) => return Ok(()),
+        Err(failure) => failure
+    };
+    eprintln!("Error: sign command for hash file {} {}",
+        hash_file.display(), failure);
+    if let Err(err) = fs::remove_file(hash_file) {
+        eprintln!("Error removing unsigned hash file {}: {}",
+            hash_file.display(), err);
+    }
+    interrupt::set_pending_output(None);
+    Err(ExitCode::GenWriteErr)
+}
+
+// Names of a file, its recorded length if any, and any error found before hashing it
+type FileListEntry = (Vec<PathBuf>, Option<u64>, Option<PreHashError>);
+// Name of an empty directory, and any error found when checking it
+type DirListEntry = (String, Option<PreHashError>);
+
+fn run() -> ExitCode {
+    let matches_result = parse_cli();
+    if let Err(e) = matches_result {
+        // Mirror e.exit, but use scoping to call destructors
+        e.print().expect("Failed to print CMD parse error");
+        return ExitCode::CmdlineErr;
+    }
+    let matches = matches_result.unwrap();
+    if matches.get_flag("printexitcodes") {
+        print!("{}", ExitCode::listing());
+        return ExitCode::Success;
+    }
+    if matches.get_flag("formatversion") {
+        println!("{}", FORMAT_VERSION);
+        return ExitCode::Success;
+    }
+
+    if let Some((lint::LINT_CMD_NAME, lint_matches)) = matches.subcommand() {
+        let file_name = lint_matches.get_one::<String>("FILE").unwrap();
+        return lint::lint_hash_file(file_name, matches.get_count("quiet"));
+    }
+
+    if let Some((self_test::SELF_TEST_CMD_NAME, _)) = matches.subcommand() {
+        return self_test::run_self_test(matches.get_count("quiet"));
+    }
+
+    if let Some((repair::REPAIR_CMD_NAME, repair_matches)) = matches.subcommand() {
+        let file_name = repair_matches.get_one::<String>("FILE").unwrap();
+        return repair::repair_hash_file(file_name, matches.get_count("quiet"));
+    }
+
+    if let Some((info::INFO_CMD_NAME, info_matches)) = matches.subcommand() {
+        let file_name = info_matches.get_one::<String>("FILE").unwrap();
+        return match info_matches.get_flag("algorithmid") {
+            true => info::print_algorithm_id(file_name),
+            false => info::list_recorded_files(file_name, info_matches.get_flag("files0"))
+        };
+    }
+
+    if let Some((tree_view::TREE_CMD_NAME, tree_matches)) = matches.subcommand() {
+        let file_name = tree_matches.get_one::<String>("FILE").unwrap();
+        return tree_view::show_hash_trees(file_name, *tree_matches.get_one("maxnodes").unwrap(),
+            *tree_matches.get_one("hashchars").unwrap(), matches.get_count("quiet"));
+    }
+
+    if let Some((extract::EXTRACT_CMD_NAME, extract_matches)) = matches.subcommand() {
+        return extract::run_extract(extract_matches);
+    }
+
+    if let Some((convert::CONVERT_CMD_NAME, convert_matches)) = matches.subcommand() {
+        return convert::run_convert(convert_matches);
+    }
+
+    let quiet_count = matches.get_count("quiet");
+
+    // unwrap always succeeds because "jobs" has a default value
+    let thread_count = *matches.get_one::<usize>("jobs")
+        .unwrap();
+    let read_limit = matches.get_one::<u64>("throttle").copied();
+
+    let progress_hz = matches.get_one::<u8>("progresshz").copied();
+    let progress_output = match matches.get_one::<String>("progressto") {
+        _ if quiet_count > 0 => ProgressOutput::Hidden,
+        Some(progress_file_name) => match File::create(progress_file_name) {
+            Ok(file) => ProgressOutput::to_file(file,
+                progress_hz.unwrap_or(utils::FILE_PROGRESS_HZ)),
+            Err(err) => {
+                eprintln!("Error opening file {} for writing: {}",
+                    progress_file_name, err);
+                return ExitCode::GenWriteErr;
+            }
+        },
+        // Redirected stderr would otherwise collect redraws and escape codes
+        None if !std::io::stderr().is_terminal() => ProgressOutput::Hidden,
+        None => ProgressOutput::Stderr(progress_hz.unwrap_or(utils::STDERR_PROGRESS_HZ))
+    };
+
+    if let Some((migrate::MIGRATE_CMD_NAME, migrate_matches)) = matches.subcommand() {
+        return migrate::run_migrate(migrate_matches);
+    }
+
+    if let Some((DIFF_FILE_CMD_NAME, diff_matches)) = matches.subcommand() {
+        let hash_file_name = diff_matches.get_one::<String>("HASHFILE").unwrap();
+        let data_file_name = diff_matches.get_one::<String>("DATAFILE").unwrap();
+        let salt: Vec<u8> = diff_matches.get_one::<Vec<u8>>("salt")
+            .cloned().unwrap_or_default();
+        return diff_data_file(hash_file_name, data_file_name, &salt, quiet_count,
+            &progress_output, thread_count, read_limit);
+    }
+
+    let (mut cmd_chosen, cmd_matches): (HashCommand<_,_>, ArgMatches)
+            = match matches.subcommand() {
+        Some((GENERATE_HASH_CMD_NAME, gencmd_matches)) => 
+                (HashCommand::GenerateHash(None), gencmd_matches.clone()),
+        Some((VERIFY_HASH_CMD_NAME, verify_matches)) =>
+                (HashCommand::VerifyHash(None), verify_matches.clone()),
+        _ => panic!("Invalid or missing subcommand detected")
+    };
+
+    // Only used for verify-hash, with the hash file being checked
+    let hash_input: Option<VerifyHashInput> = match cmd_chosen {
+        HashCommand::VerifyHash(_) => match cmd_matches.get_one::<String>("hashesfromcommand") {
+            Some(command) => match VerifyHashInput::from_command(command) {
+                Ok(hash_input) => Some(hash_input),
+                Err(exit_code) => return exit_code
+            },
+            None => Some(VerifyHashInput::Path(cmd_matches.get_one::<String>("FILE").unwrap()))
+        },
+        HashCommand::GenerateHash(_) => None
+    };
+
+    // Nothing in the hash file is trusted until its signature is checked
+    if let Some(hash_input) = &hash_input {
+        if let Some(verify_command) = cmd_matches.get_one::<String>("verifycommand") {
+            if let Err(failure) = hash_input.run_verify_command(verify_command) {
+                eprintln!("Error: verify command for hash file {} {}",
+                    hash_input.name(), failure);
+                return ExitCode::VerifBadHeaderErr;
+            }
+        }
+    }
+
+    // The file is both the data and the hash in this case
+    if matches!(cmd_chosen, HashCommand::VerifyHash(_))
+            && cmd_matches.get_flag("embedded") {
+        let file_name = cmd_matches.get_one::<String>("FILE").unwrap();
+        return verify_embedded(file_name, quiet_count,
+            &progress_output, thread_count, read_limit);
+    }
+    if matches!(cmd_chosen, HashCommand::VerifyHash(_))
+            && cmd_matches.get_flag("selfconsistent") {
+        let file_name = cmd_matches.get_one::<String>("FILE").unwrap();
+        let salt: Vec<u8> = cmd_matches.get_one::<Vec<u8>>("salt")
+            .cloned().unwrap_or_default();
+        return verify_self_consistent(file_name, &salt, quiet_count);
+    }
+
+    if matches!(cmd_chosen, HashCommand::VerifyHash(_))
+            && cmd_matches.get_flag("follow") {
+        let salt: Vec<u8> = cmd_matches.get_one::<Vec<u8>>("salt")
+            .cloned().unwrap_or_default();
+        let follow_params = FollowParams {
+            hash_file_name: cmd_matches.get_one::<String>("FILE").unwrap(),
+            salt: &salt,
+            relative_to_cwd: cmd_matches.get_flag("relativetocwd"),
+            quiet_count,
+            progress_output: &progress_output,
+            thread_count,
+            read_limit
+        };
+        // Unwrap always succeeds because "followinterval" has a default value
+        let poll_interval = Duration::from_millis(
+            *cmd_matches.get_one::<u64>("followinterval").unwrap());
+        let idle_exit = cmd_matches.get_one::<u64>("followidleexit")
+            .map(|idle_ms| Duration::from_millis(*idle_ms));
+        return verify_follow(&follow_params, poll_interval, idle_exit);
+    }
+
+    // Only reads the hash file, so broken ranges are reported before hashing
+    if matches!(cmd_chosen, HashCommand::VerifyHash(_))
+            && cmd_matches.get_flag("checkcoverage") {
+        let file_name = cmd_matches.get_one::<String>("FILE").unwrap();
+        if let Err(exit_code) = verify_leaf_coverage(file_name) {
+            return exit_code;
+        }
+    }
+
+    let mut hashing_final_status = ExitCode::Success;
+    let allow_append = matches!(cmd_chosen, HashCommand::VerifyHash(_))
+        && cmd_matches.get_flag("allowappend");
+    let decompression = data_decompression(&cmd_matches);
+
+    if matches!(cmd_chosen, HashCommand::GenerateHash(_))
+            && cmd_matches.contains_id("tee") {
+        return generate_tee(&cmd_matches, quiet_count, read_limit);
+    }
+    if matches!(cmd_chosen, HashCommand::GenerateHash(_))
+            && (cmd_matches.get_flag("stream") || cmd_matches.get_flag("coreutils")) {
+        return generate_streaming(&cmd_matches, quiet_count,
+            &progress_output, thread_count, read_limit);
+    }
+
+    // Only used for --hash-tree-structure, with the name of each found file
+    let mut relative_names: Vec<String> = Vec::new();
+    let mut structure_hash: Option<HashData<64>> = None;
+    // Directory that relative names in a hash file are resolved against
+    let mut name_base = PathBuf::new();
+    let (file_list_result, dir_list_result, tree_params, short_output, verify_start_pos):
+            (Vec<FileListEntry>, Vec<DirListEntry>, TreeParams, bool, Option<u64>)
+            = match cmd_chosen {
+        HashCommand::GenerateHash(None) => {
+            let file_vec = match expand_file_args(&cmd_matches) {
+                Ok(file_vec) => file_vec,
+                Err(exit_code) => return exit_code
+            };
+            let mut tree_params = generate_tree_params(&cmd_matches);
+            if let Err(exit_code) = apply_xattr_params(&mut tree_params, &cmd_matches,
+                    &file_vec, quiet_count) {
+                return exit_code;
+            }
+            // unwrap always succeeds because "mindepth" has a default value
+            let min_depth = *cmd_matches.get_one::<usize>("mindepth").unwrap();
+            let max_depth = cmd_matches.get_one::<usize>("maxdepth").copied();
+            let mut dir_vec: Vec<DirListEntry> = Vec::new();
+            if cmd_matches.get_flag("emptydirs") {
+                for file_path in &file_vec {
+                    match utils::str_to_empty_dirs(file_path, min_depth, max_depth) {
+                        Ok(dirs) => dir_vec.extend(dirs.into_iter()
+                            .map(|path| (path.to_string_lossy().into_owned(), None))),
+                        Err(err) => {
+                            eprintln!("Error with directory {}: {}", file_path, err);
+                            return ExitCode::DataReadErr;
+                        }
+                    }
+                }
+            }
+            // Validators should already have caught errors
+            (
+                {
+                    let mut collect_vec: Vec<_> = Vec::with_capacity(
+                        file_vec.len());
+                    for file_path in file_vec {
+                        if cmd_matches.get_flag("norecurse") && Path::new(&file_path).is_dir() {
+                            collect_vec.push((vec![PathBuf::from(file_path)], None, Some(PreHashError::IsDirectory)));
+                            continue;
+                        }
+                        match utils::str_to_files(&file_path, min_depth, max_depth,
+                                cmd_matches.get_flag("skipunreadable")) {
+                            Ok((paths, skipped)) => {
+                                if quiet_count < 2 {
+                                    for err in skipped {
+                                        eprintln!("Warning: skipping unreadable entry: {}", err);
+                                    }
+                                }
+                                for path in paths {
+                                    match check_file_size(std::slice::from_ref(&path), &cmd_matches, quiet_count) {
+                                        Ok(true) => {},
+                                        Ok(false) => continue,
+                                        Err(exit_code) => return exit_code
+                                    }
+                                    let is_empty = path.metadata()
+                                        .is_ok_and(|metadata| metadata.len() == 0);
+                                    if is_empty && cmd_matches.get_flag("skipempty") {
+                                        if quiet_count < 2 {
+                                            eprintln!("Warning: skipping empty file {}",
+                                                path.display());
+                                        }
+                                        continue;
+                                    }
+                                    relative_names.push(tree_structure::relative_name(
+                                        &path, Path::new(&file_path)));
+                                    match File::open(&path) {
+                                        Ok(_) if is_empty && cmd_matches.get_flag("erroronempty") =>
+                                            collect_vec.push((vec![path], None, Some(PreHashError::EmptyFile))),
+                                        Ok(_) => collect_vec.push((vec![path], None, None)),
+                                        Err(_) => collect_vec.push((vec![path], None, Some(PreHashError::ReadPermissionError)))
+                                    }
+                                }
+                            },
+                            Err(PreHashError::FileNotFound) => collect_vec.push((vec![PathBuf::from(file_path)], None, Some(PreHashError::FileNotFound))),
+                            Err(err) => {
+                                eprintln!("Error with directory {}: {}", file_path, err);
+                                return ExitCode::DataReadErr;
+                            }
+                        }
+                    };
+                    collect_vec
+                },
+                dir_vec,
+                tree_params,
+                cmd_matches.get_flag("short"),
+                None
+            )
+        },
+        HashCommand::VerifyHash(None) => {
+            // A damaged hash file is reported before anything else is read
+            let mut hash_file_reader = match hash_input.as_ref().unwrap().open_with_root() {
+                Ok((reader, _)) => reader,
+                Err(exit_code) => return exit_code
+            };
+
+            // Middle element is the stored length, which is what gets hashed
+            let mut file_vec: Vec<FileListEntry> = Vec::new();
+            let mut dir_vec: Vec<DirListEntry> = Vec::new();
+            let header = match read_hash_header(&mut hash_file_reader) {
+                Ok(header) => header,
+                Err(exit_code) => return exit_code
+            };
+            let HashFileHeader {tree_params, is_short_hash, ..} = header;
+            structure_hash = header.structure_hash.clone();
+            // Output of --hashes-from-command has no directory to resolve against
+            if let Some(VerifyHashInput::Path(hash_file_name)) = &hash_input {
+                if tree_params.relative_names && !cmd_matches.get_flag("relativetocwd") {
+                    name_base = Path::new(hash_file_name).parent()
+                        .map(Path::to_path_buf).unwrap_or_default();
+                }
+            }
+            if quiet_count < 2 {
+                for comment in &header.comments {
+                    eprintln!("Info: hash file comment: {}", comment);
+                }
+            }
+            // Short entries have no length, which would be the length of the region
+            if is_short_hash && tree_params.region_offset.is_some() {
+                eprintln!("Error: short hash files cannot hash a region of each file");
+                return ExitCode::VerifBadHeaderErr;
+            }
+            // The command is not recorded, so it is up to the user to give an equivalent one
+            match (tree_params.filtered, matches!(decompression, Decompression::Filter(_))) {
+                (true, false) => {
+                    eprintln!(concat!("Error: hash file was generated with --filter-command, ",
+                        "which verification also needs"));
+                    return ExitCode::CmdlineErr;
+                },
+                (false, true) => {
+                    eprintln!(concat!("Error: --filter-command cannot be used with hash files ",
+                        "generated without it"));
+                    return ExitCode::CmdlineErr;
+                },
+                _ => {}
+            }
+            // Checking trimmed zeros reads backwards, which decompressed streams can't do
+            if decompression != Decompression::None
+                    && (tree_params.trim_zeros || tree_params.metadata_only) {
+                eprintln!(concat!("Error: --decompress cannot be used with hash files ",
+                    "of metadata or with trimmed trailing zeros"));
+                return ExitCode::CmdlineErr;
+            }
+            // Checks a listed file before any of it is hashed
+            // Entries of short hash files have no length, so only their existence is checked
+            let check_listed_file = |paths: &[PathBuf], len_option: Option<u64>,
+                    hashed_len: Option<u64>| -> Result<Option<PreHashError>, ExitCode> {
+                if let Some(expected_len) = len_option {
+                    // unwrap always succeeds because "maxnodes" has a default value
+                    let max_nodes = *cmd_matches.get_one::<u64>("maxnodes").unwrap();
+                    let (tree_len, tree_block_size) = tree_params.tree_shape(expected_len);
+                    let tree_nodes = merkle_tree::checked_node_count(tree_len,
+                        tree_block_size, tree_params.branch_factor);
+                    // None is a count too large for a u64
+                    if tree_nodes.filter(|tree_nodes| *tree_nodes <= max_nodes).is_none() {
+                        eprintln!(concat!("Error: file {} has a recorded length of {:#x} bytes, ",
+                            "which needs more than --max-nodes {} tree nodes"),
+                            display_name_group(paths), expected_len, max_nodes);
+                        return Err(ExitCode::VerifBadHeaderErr);
+                    }
+                }
+                let pre_hash_err = if !paths.iter().all(|path| path.is_file()) {
+                    Some(PreHashError::FileNotFound)
+                } else if paths.iter().any(|path| File::open(path).is_err()) {
+                    // We already checked file existence
+                    Some(PreHashError::ReadPermissionError)
+                } else if let Some(expected_len) = len_option {
+                    // Data outside of a region is not hashed, so only the region must exist
+                    let region_end = tree_params.region_offset
+                        .map(|offset| offset.saturating_add(expected_len));
+                    match read_adapters::data_len(paths, decompression,
+                            tree_params.normalize_eol) {
+                        Err(err) => Some(PreHashError::UnreadableData(err.to_string())),
+                        Ok(actual_len) if region_end.is_some() => {
+                            let region_end = region_end.unwrap();
+                            match actual_len >= region_end {
+                                true => None,
+                                false => Some(PreHashError::MismatchedLength(
+                                    StoredAndComputed::new(region_end, actual_len)))
+                            }
+                        },
+                        Ok(actual_len) if actual_len == expected_len => None,
+                        Ok(actual_len) if actual_len > expected_len && allow_append => {
+                            if quiet_count < 2 {
+                                eprintln!(concat!("Warning: file {} grew from {} to {} bytes, ",
+                                    "and appended data will not be verified"),
+                                    display_name_group(paths), expected_len, actual_len);
+                            }
+                            None
+                        },
+                        Ok(actual_len) => {
+                            let mismatch_len_obj = StoredAndComputed::new
+                                (expected_len, actual_len);
+                            Some(PreHashError::MismatchedLength(mismatch_len_obj))
+                        }
+                    }
+                } else {
+                    None
+                };
+                // Trimmed bytes are not hashed, so check that they are still zero
+                Ok(match (pre_hash_err, hashed_len) {
+                    (None, Some(hashed_len)) => {
+                        let part_files: Vec<File> = paths.iter()
+                            .map(|path| File::open(path).unwrap())
+                            .collect();
+                        let mut concat_obj = ConcatReader::new(part_files);
+                        match read_adapters::trailing_zeros_start(&mut concat_obj,
+                                len_option.unwrap()) {
+                            Ok(zeros_start) if zeros_start <= hashed_len => None,
+                            Ok(zeros_start) => Some(PreHashError::NonzeroTrimmedData(zeros_start-1)),
+                            Err(err) => {
+                                eprintln!("Error reading file {}: {}",
+                                    display_name_group(paths), err);
+                                return Err(ExitCode::DataReadErr);
+                            }
+                        }
+                    },
+                    (pre_hash_err, _) => pre_hash_err
+                })
+            };
+            let list_begin_pos: Option<u64> = match is_short_hash {
+                true => Some(
+                    hash_file_reader.stream_position().unwrap()
+                ),
+                false => None
+            };
+            if !is_short_hash {
+                let mut line_number = header.line_count;
+                let (files, empty_dirs) = match parse_file_list(&mut hash_file_reader,
+                        &header, &mut line_number) {
+                    Ok(file_list) => file_list,
+                    Err(err) => {
+                        eprintln!("Error: {}", err);
+                        return match err {
+                            ParseError::ReadError(_) => ExitCode::VerifReadErr,
+                            _ => ExitCode::VerifBadHeaderErr
+                        };
+                    }
+                };
+                for dir_name in empty_dirs {
+                    let path = name_base.join(dir_name);
+                    let dir_err = if !path.is_dir() {
+                        Some(PreHashError::DirectoryNotFound)
+                    } else if !utils::is_empty_dir(&path) {
+                        Some(PreHashError::DirectoryNotEmpty)
+                    } else {
+                        None
+                    };
+                    dir_vec.push((path.to_string_lossy().into_owned(), dir_err));
+                }
+                for FileEntry {names, file_len, hashed_len} in files {
+                    // Concatenated entries have more than one part
+                    let paths: Vec<PathBuf> = names.into_iter()
+                        .map(|name| name_base.join(name)).collect();
+                    let pre_hash_err = match check_listed_file(&paths, Some(file_len), hashed_len) {
+                        Ok(pre_hash_err) => pre_hash_err,
+                        Err(exit_code) => return exit_code
+                    };
+                    // The hashed length is what the entries cover
+                    file_vec.push((paths, hashed_len.or(Some(file_len)), pre_hash_err));
+                }
+            }
+            // Short hash files list their files in their entries, which are read again below
+            if is_short_hash {
+                loop {
+                    let mut next_line = String::new();
+                    if let Err(read_err) = hash_file_reader.read_line(&mut next_line) {
+                        if read_err.kind() != std::io::ErrorKind::UnexpectedEof {
+                            eprintln!("Error: Error in reading file: {}", read_err);
+                            return ExitCode::VerifReadErr;
+                        }
+                    }
+                    if next_line.is_empty() {
+                        break;
+                    }
+                    if extract_empty_dir_name(&next_line).is_ok() {
+                        eprintln!("Error: short hash files do not support empty directory entries");
+                        return ExitCode::VerifBadHeaderErr;
+                    }
+                    // The malformed hash of an entry with a readable name is reported
+                    // when its file is reached, so only that file fails
+                    let name_entry = match parse_functions::extract_quoted_filename(&next_line) {
+                        Ok((quoted_name, None, None)) => Some((quoted_name, true)),
+                        // Only file list entries have a length
+                        Ok(_) => None,
+                        Err(_) => parse_functions::extract_short_entry_name(&next_line)
+                            .map(|quoted_name| (quoted_name, false))
+                    };
+                    let Some((quoted_name, hash_readable)) = name_entry else {
+                        eprintln!("Error: encountered malformed file entry {:?}",
+                            next_line);
+                        return ExitCode::VerifBadHeaderErr;
+                    };
+                    let paths: Vec<PathBuf> = match unquote_name_group(quoted_name) {
+                        Ok(names) => names.into_iter().map(|name| name_base.join(name)).collect(),
+                        Err(e) => {
+                            eprintln!("Error: unable to unquote file name {}: {}",
+                                quoted_name, e);
+                            return ExitCode::VerifBadEntryErr;
+                        }
+                    };
+                    let pre_hash_err = match hash_readable {
+                        true => match check_listed_file(&paths, None, None) {
+                            Ok(pre_hash_err) => pre_hash_err,
+                            Err(exit_code) => return exit_code
+                        },
+                        false => None
+                    };
+                    file_vec.push((paths, None, pre_hash_err));
+                }
             }
-            assert!(is_short_hash == list_begin_pos.is_some());
             if let Some(seek_pos) = list_begin_pos {
                 hash_file_reader.seek(SeekFrom::Start(seek_pos)).unwrap();
             }
 
             (
                 file_vec,
-                tree_param_result.unwrap(),
+                dir_vec,
+                tree_params,
                 is_short_hash,
                 // We want to ensure that the seek call succeeded
                 Some(hash_file_reader.stream_position().unwrap())
             )
         },
-        _ => unreachable!()
+        _ => unreachable!()
+    };
+    // Only used for verify-hash --max-failures, with how many more files may fail
+    let mut failures_left: Option<u64> = match cmd_chosen {
+        HashCommand::VerifyHash(_) => cmd_matches.get_one::<u64>("maxfailures").copied(),
+        HashCommand::GenerateHash(_) => None
+    };
+    // Only used for verify-hash --results, with a line for each file
+    let mut results_file: Option<ResultsFile> = match cmd_chosen {
+        HashCommand::VerifyHash(_) => match cmd_matches.get_one::<String>("results") {
+            Some(results_name) => match create_output_file(Path::new(results_name), true) {
+                Ok(file) => Some(ResultsFile::new(file)),
+                Err(err) => {
+                    eprintln!("Error opening file {} for writing: {}",
+                        results_name, err);
+                    return ExitCode::GenWriteErr;
+                }
+            },
+            None => None
+        },
+        HashCommand::GenerateHash(_) => None
+    };
+    // Errors reported before hashing, which are written to --results with the other files
+    let mut pre_hash_errors: Vec<Option<String>> = Vec::with_capacity(file_list_result.len());
+    let mut abort: Result<(), ExitCode> = Ok(());
+    // Bool is whether to process this file or not
+    // Middle element is the length to hash, if it should not be the file length
+    let mut file_list: Vec<(Vec<PathBuf>, Option<u64>, bool)> = file_list_result.into_iter().map(|(paths, hash_len, err_opt)| {
+        if let Some(err) = err_opt {
+            // Files after the last allowed failure are not reported
+            if failures_left == Some(0) {
+                pre_hash_errors.push(None);
+                return (paths, hash_len, false);
+            }
+            eprintln!("Error with file {}: {}",
+                    display_name_group(&paths), err);
+            pre_hash_errors.push(Some(err.to_string()));
+            hashing_final_status = ExitCode::PreHashErr;
+            if count_failure(&mut failures_left) {
+                abort = Err(ExitCode::VerifBadEntryErr);
+            }
+            match err {
+                PreHashError::MismatchedLength(_)
+                | PreHashError::NonzeroTrimmedData(_) => {
+                    assert!(matches!(cmd_chosen, HashCommand::VerifyHash(_)));
+                    if cmd_matches.get_flag("failfast") {
+                        abort = Err(ExitCode::VerifBadEntryErr);
+                    }
+                },
+                PreHashError::FileNotFound => {
+                    if !matches!(cmd_chosen, HashCommand::VerifyHash(_)) {
+                        abort = Err(ExitCode::DataReadErr);
+                    }
+                },
+                PreHashError::ReadPermissionError
+                | PreHashError::UnreadableData(_)
+                | PreHashError::EmptyFile
+                | PreHashError::IsDirectory => {
+                    abort = Err(ExitCode::DataReadErr);
+                },
+                PreHashError::DirectoryNotFound
+                | PreHashError::DirectoryNotEmpty
+                | PreHashError::UnreadableDirEntry(_)
+                | PreHashError::NonUtf8Name(_) => unreachable!()
+            };
+            (paths, hash_len, false)
+        } else {
+            pre_hash_errors.push(None);
+            (paths, hash_len, true)
+        }
+    }).collect();
+    if let Err(exit_code) = abort {
+        for (file_index, (file_parts, _, _)) in file_list.iter().enumerate() {
+            if let Some(err) = &pre_hash_errors[file_index] {
+                if let Err(exit_code) = write_result(&mut results_file, file_index,
+                        file_parts, &FileResult::failed(err.clone())) {
+                    return exit_code;
+                }
+            }
+        }
+        return exit_code;
+    }
+    // Oversized files go through the same skip path as files with errors,
+    // which still reads past their entries when verifying
+    if matches!(cmd_chosen, HashCommand::VerifyHash(_)) {
+        for (paths, _, process) in file_list.iter_mut().filter(|(_, _, process)| *process) {
+            match check_file_size(paths, &cmd_matches, quiet_count) {
+                Ok(keep) => *process = keep,
+                Err(exit_code) => return exit_code
+            }
+        }
+    }
+    // Any errors would have aborted generation by now, so all files are kept
+    if matches!(cmd_chosen, HashCommand::GenerateHash(_))
+            && cmd_matches.get_flag("concat") {
+        let all_parts = file_list.into_iter()
+            .flat_map(|(paths, _, _)| paths)
+            .collect();
+        file_list = vec![(all_parts, None, true)];
+    }
+    // Entries are numbered in list order, so sorting also renumbers them
+    let sort_entries = matches!(cmd_chosen, HashCommand::GenerateHash(_))
+        && cmd_matches.get_flag("sortentries");
+    if sort_entries {
+        file_list.sort_by(|(paths_a, _, _), (paths_b, _, _)| paths_a.cmp(paths_b));
+    }
+    // Files past the budget are left out of the hash file, as with --max-file-size
+    if let (HashCommand::GenerateHash(_), Some(max_total_bytes)) =
+            (&cmd_chosen, cmd_matches.get_one::<u64>("maxtotalbytes")) {
+        let kept_count = budget_file_count(&file_list, *max_total_bytes);
+        if kept_count < file_list.len() {
+            if quiet_count < 2 {
+                eprintln!(concat!("Warning: budget of {} bytes from --max-total-bytes ",
+                    "exhausted, leaving out the remaining files"), max_total_bytes);
+            }
+            if quiet_count <= 1 {
+                for (file_parts, _, _) in &file_list[kept_count..] {
+                    eprintln!("Warning: skipping file {}", display_name_group(file_parts));
+                }
+            }
+            file_list.truncate(kept_count);
+            pre_hash_errors.truncate(kept_count);
+            if hashing_final_status == ExitCode::Success {
+                hashing_final_status = ExitCode::BudgetExhausted;
+            }
+        }
+    }
+    if matches!(cmd_chosen, HashCommand::GenerateHash(_))
+            && cmd_matches.get_flag("hashtreestructure") {
+        structure_hash = Some(tree_structure::structure_hash(
+            tree_params.hash_function, &relative_names));
+    }
+    if matches!(cmd_chosen, HashCommand::GenerateHash(_)) && tree_params.trim_zeros {
+        for (file_parts, hash_len, _) in file_list.iter_mut() {
+            let mut part_files = Vec::with_capacity(file_parts.len());
+            for file_part in file_parts.iter() {
+                match File::open(file_part) {
+                    Ok(file) => part_files.push(file),
+                    Err(err) => {
+                        eprintln!("Error opening file {} for reading: {}",
+                            file_part.display(), err);
+                        return ExitCode::DataReadErr;
+                    }
+                }
+            }
+            let mut concat_obj = ConcatReader::new(part_files);
+            let file_len = concat_obj.len();
+            match read_adapters::trailing_zeros_start(&mut concat_obj, file_len) {
+                Ok(zeros_start) => *hash_len = Some(zeros_start),
+                Err(err) => {
+                    eprintln!("Error reading file {}: {}",
+                        display_name_group(file_parts), err);
+                    return ExitCode::DataReadErr;
+                }
+            }
+        }
+    }
+    // Lengths to record, which are the decompressed lengths with --decompress
+    // and the region lengths with --offset or --length
+    let mut data_lens: Vec<u64> = Vec::with_capacity(file_list.len());
+    if matches!(cmd_chosen, HashCommand::GenerateHash(_)) {
+        let region_len = cmd_matches.get_one::<u64>("length").copied();
+        for (file_parts, _, _) in file_list.iter() {
+            let data_len = match read_adapters::data_len(file_parts, decompression,
+                    tree_params.normalize_eol) {
+                Ok(data_len) => data_len,
+                Err(err) => {
+                    eprintln!("Error reading file {}: {}",
+                        display_name_group(file_parts), err);
+                    return ExitCode::DataReadErr;
+                }
+            };
+            match tree_params.region_offset {
+                Some(offset) => {
+                    let region_end = match region_len {
+                        Some(region_len) => offset.checked_add(region_len),
+                        None => Some(data_len)
+                    };
+                    match region_end {
+                        Some(region_end) if offset <= region_end && region_end <= data_len => {
+                            data_lens.push(region_end - offset);
+                        },
+                        _ => {
+                            eprintln!("Error: region does not fit in file {} of {} bytes",
+                                display_name_group(file_parts), data_len);
+                            return ExitCode::CmdlineErr;
+                        }
+                    }
+                },
+                None => data_lens.push(data_len)
+            }
+        }
+    }
+    if matches!(cmd_chosen, HashCommand::GenerateHash(_)) {
+        print_block_size_warning(&file_list, &data_lens, &tree_params, quiet_count);
+    }
+    if matches!(cmd_chosen, HashCommand::GenerateHash(_))
+            && cmd_matches.get_flag("dryrun") {
+        return print_long_format_sizes(&file_list, &data_lens, &tree_params);
+    }
+    // Later copies of identical files reuse the entries of the first copy
+    let dedup_sources = match matches!(cmd_chosen, HashCommand::GenerateHash(_))
+            && cmd_matches.get_flag("dedup") {
+        true => match duplicate_sources(&file_list, &data_lens,
+                decompression, tree_params.normalize_eol) {
+            Ok(dedup_sources) => dedup_sources,
+            Err(exit_code) => return exit_code
+        },
+        false => vec![None; file_list.len()]
     };
-    let mut abort: Result<(), i32> = Ok(());
-    // Bool is whether to process this file or not
-    let file_list: Vec<(PathBuf, bool)> = file_list_result.into_iter().map(|(path_str, err_opt)| {
+    // Empty directories only get checked during verification
+    let mut dir_list: Vec<PathBuf> = dir_list_result.into_iter().map(|(path_str, err_opt)| {
         if let Some(err) = err_opt {
-            eprintln!("Error with file {}: {}",
+            eprintln!("Error with directory {}: {}",
                     path_str, err);
-            hashing_final_status = 1;
-            match err {
-                PreHashError::MismatchedLength(_) => {
-                    assert!(matches!(cmd_chosen, HashCommand::VerifyHash(_)));
-                    if cmd_matches.get_flag("failfast") {
-                        abort = Err(VERIF_BAD_ENTRY_ERR);
-                    }
-                },
-                PreHashError::FileNotFound => {
-                    if !matches!(cmd_chosen, HashCommand::VerifyHash(_)) {
-                        abort = Err(DATA_READ_ERR);
-                    }
-                },
-                PreHashError::ReadPermissionError => {
-                    abort = Err(DATA_READ_ERR);
-                }
-            };
-            (PathBuf::from(path_str), false)
-        } else {
-            (PathBuf::from(path_str), true)
+            hashing_final_status = ExitCode::PreHashErr;
         }
+        PathBuf::from(path_str)
     }).collect();
-    if let Err(exit_code) = abort {
-        return exit_code;
+    if sort_entries {
+        dir_list.sort();
     }
 
-    let quiet_count = matches.get_count("quiet");
+    if matches!(cmd_chosen, HashCommand::VerifyHash(_))
+            && cmd_matches.get_flag("exact") {
+        let scan_dir = cmd_matches.get_one::<String>("scandir").unwrap();
+        if !Path::new(scan_dir).is_dir() {
+            eprintln!("Error: --scan-dir {} is not a directory", scan_dir);
+            return ExitCode::CmdlineErr;
+        }
+        let on_disk = match utils::str_to_files(scan_dir, 1, None, false) {
+            Ok((paths, _)) => paths,
+            Err(err) => {
+                eprintln!("Error with directory {}: {}", scan_dir, err);
+                return ExitCode::DataReadErr;
+            }
+        };
+        // The hash file itself is not an extra file
+        let hash_file_path = cmd_matches.get_one::<String>("FILE").map(PathBuf::from);
+        let recorded = file_list.iter()
+            .flat_map(|(paths, _, _)| paths)
+            .chain(hash_file_path.iter());
+        let (extra_files, missing_files) = utils::compare_scanned_files(recorded,
+            &on_disk, Path::new(scan_dir));
+        for path in &extra_files {
+            eprintln!("Error: extra file {} is not in the hash file", path.display());
+        }
+        for path in &missing_files {
+            eprintln!("Error: missing file {} is in the hash file", path.display());
+        }
+        if !extra_files.is_empty() || !missing_files.is_empty() {
+            hashing_final_status = ExitCode::PreHashErr;
+        }
+    }
 
-    // unwrap always succeeds because "jobs" has a default value
-    let thread_count = *matches.get_one::<usize>("jobs")
-        .unwrap();
+    if let Err(exit_code) = check_hash_len(tree_params.hash_function) {
+        return exit_code;
+    }
+    if matches!(cmd_chosen, HashCommand::GenerateHash(_))
+            && cmd_matches.get_flag("embed") {
+        return generate_embedded(&file_list, &tree_params,
+            quiet_count, &progress_output, thread_count, read_limit);
+    }
 
     let hash_enum: HashFunctions = tree_params.hash_function;
     let block_size: block_t = tree_params.block_size;
     let branch_factor: branch_t = tree_params.branch_factor;
-    // TODO: use the duplicate crate for macro-ing this?
-    let merkle_tree_thunk = match hash_enum {
-        HashFunctions::crc32 =>
-            merkle_hash_file::<_,Crc32,_>,
-        HashFunctions::sha224 =>
-            merkle_hash_file::<_,Sha224,_>,
-        HashFunctions::sha256 =>
-            merkle_hash_file::<_,Sha256,_>,
-        HashFunctions::sha384 =>
-            merkle_hash_file::<_,Sha384,_>,
-        HashFunctions::sha512 =>
-            merkle_hash_file::<_,Sha512,_>,
-        HashFunctions::sha512_224 =>
-            merkle_hash_file::<_,Sha512_224,_>,
-        HashFunctions::sha512_256 =>
-            merkle_hash_file::<_,Sha512_256,_>,
-        HashFunctions::sha3_224 => merkle_hash_file::<_,Sha3_224,_>,
-        HashFunctions::sha3_256 => merkle_hash_file::<_,Sha3_256,_>,
-        HashFunctions::sha3_384 => merkle_hash_file::<_,Sha3_384,_>,
-        HashFunctions::sha3_512 => merkle_hash_file::<_,Sha3_512,_>,
-        HashFunctions::blake2b_512 => merkle_hash_file::<_,Blake2b512,_>,
-        HashFunctions::blake2s_256 => merkle_hash_file::<_,Blake2s256,_>,
-        HashFunctions::blake3 => merkle_hash_file::<_,Blake3,_>
-    };
+    let byte_offset: u64 = tree_params.byte_range_offset();
+    let salt: Vec<u8> = cmd_matches.get_one::<Vec<u8>>("salt")
+        .cloned().unwrap_or_default();
+    if let Err(exit_code) = check_salt_given(&tree_params, &salt) {
+        return exit_code;
+    }
+    // Hash files with other block sizes are rejected when parsed
+    if tree_params.content_defined && !block_size.is_power_of_two() {
+        eprintln!("Error: --cdc requires a block length that is a power of 2");
+        return ExitCode::CmdlineErr;
+    }
+    if tree_params.stride.is_some_and(|stride| stride > block_size) {
+        eprintln!("Error: --stride must be at most the block length");
+        return ExitCode::CmdlineErr;
+    }
+    let mut tree_options = tree_params.tree_options(&salt);
+    tree_options.verify_positions = matches.get_flag("verifypositions");
+    tree_options.stack_size = matches.get_one::<usize>("stacksize").copied();
+    // unwrap always succeeds because "readretries" has a default value
+    tree_options.read_retries = *matches.get_one::<u32>("readretries").unwrap();
+    let merkle_tree_thunk = merkle_hash_fn(hash_enum);
+    let direct_tree_thunk = merkle_hash_fn(hash_enum);
     let expected_hash_len = hash_enum.hash_len();
 
-    if quiet_count < 2 && hash_enum == HashFunctions::crc32
-            && matches!(cmd_chosen, HashCommand::GenerateHash(_)) {
-        eprintln!("Warning: CRC32 is not cryptographically secure and will only prevent accidental corruption");
+    if matches!(cmd_chosen, HashCommand::GenerateHash(_)) {
+        print_generate_warnings(&tree_params, quiet_count);
+    } else if quiet_count < 2 {
+        print_hash_function_warning(hash_enum);
+    }
+    let root_only = matches!(cmd_chosen, HashCommand::VerifyHash(_))
+        && cmd_matches.get_flag("rootonly");
+    let mark_empty = matches!(cmd_chosen, HashCommand::GenerateHash(_))
+        && cmd_matches.get_flag("markempty");
+    let sectioned = matches!(cmd_chosen, HashCommand::GenerateHash(_))
+        && cmd_matches.get_flag("sectioned");
+    let any_order = matches!(cmd_chosen, HashCommand::VerifyHash(_))
+        && cmd_matches.get_flag("anyorder");
+    if any_order && (short_output || tree_params.metadata_only) {
+        eprintln!("Error: --any-order requires a long hash file of file contents");
+        return ExitCode::CmdlineErr;
+    }
+    if root_only {
+        if short_output || tree_params.metadata_only {
+            eprintln!("Error: --root-only requires a long hash file of file contents");
+            return ExitCode::CmdlineErr;
+        }
+        if quiet_count < 2 {
+            eprintln!("Warning: --root-only does not check the hashes of individual blocks");
+        }
     }
     if quiet_count < 2 && matches!(cmd_chosen, HashCommand::VerifyHash(_))
-            && !short_output && !cmd_matches.get_flag("failfast") {
+            && !short_output && !tree_params.metadata_only && !root_only
+            && !cmd_matches.get_flag("failfast") {
         eprintln!(
             concat!("Warning: Verification of long hashes may fail early ",
                 "if the hash file is malformed, ",
@@ -480,77 +3665,387 @@ fn run() -> i32 {
         );
     }
 
+    // Only used for --per-file, with one hash file per entry in file_list
+    let mut sidecar_list: Vec<PathBuf> = Vec::new();
+    // Hash file being written, to run --sign-command on once it is finished
+    let mut output_path: Option<PathBuf> = None;
+    // Only used for --checkpoint, to sync the hash file being written
+    let checkpoint_interval: Option<u64> = match cmd_chosen {
+        HashCommand::GenerateHash(_) => cmd_matches.get_one::<u64>("checkpoint").copied(),
+        HashCommand::VerifyHash(_) => None
+    };
+    let mut checkpoint_file: Option<File> = None;
+    let mut entries_since_checkpoint: u64 = 0;
+    let sign_command: Option<&String> = match cmd_chosen {
+        HashCommand::GenerateHash(_) => cmd_matches.get_one("signcommand"),
+        HashCommand::VerifyHash(_) => None
+    };
+    // Only used for verify-hash --cache
+    let mut verify_cache: Option<VerifyCache> = None;
+    // Only used for verify-hash of hash files with a structure hash
+    let mut stored_root: Option<HashData<64>> = None;
+    // Only used for verify-hash --any-order, with the entries of each file
+    let mut hash_sections: Option<Vec<Range<u64>>> = None;
+    let self_checksum = matches!(cmd_chosen, HashCommand::GenerateHash(_))
+        && cmd_matches.get_flag("selfchecksum");
+    let with_parity = matches!(cmd_chosen, HashCommand::GenerateHash(_))
+        && cmd_matches.get_flag("withparity");
+    let uppercase = matches!(cmd_chosen, HashCommand::GenerateHash(_))
+        && cmd_matches.get_flag("uppercase");
+    let output_format: HashFileFormat = match cmd_chosen {
+        // Unwrap always succeeds because "format" has a default value
+        HashCommand::GenerateHash(_) => *cmd_matches.get_one("format").unwrap(),
+        HashCommand::VerifyHash(_) => HashFileFormat::Text
+    };
+    let comments: Vec<String> = match cmd_chosen {
+        HashCommand::GenerateHash(_) => cmd_matches.get_many::<String>("comment")
+            .map(|comments| comments.cloned().collect())
+            .unwrap_or_default(),
+        HashCommand::VerifyHash(_) => Vec::new()
+    };
     match cmd_chosen {
         HashCommand::GenerateHash(None) => {
-            let write_file_name = cmd_matches.get_one::<String>("output").unwrap();
-            let overwrite = cmd_matches.get_flag("overwrite");
-            let open_result = match overwrite {
-                true => OpenOptions::new().write(true).create(true)
-                    .truncate(true).open(write_file_name),
-                false => OpenOptions::new().write(true)
-                    .create_new(true).open(write_file_name)
-            };
-            let mut file_handle = match open_result {
-                Ok(file) => LineWriter::new(file),
-                Err(err) => {
-                    eprintln!("Error opening file {} for writing: {}",
-                        write_file_name, err);
-                    return GEN_WRITE_ERR;
+            debug_assert!(verify_start_pos.is_none());
+            if let Err(err) = interrupt::install_handler() {
+                if quiet_count < 2 {
+                    eprintln!("Warning: incomplete hash files will not be removed on interrupt: {}", err);
                 }
-            };
-            // Write file prelude
-            writeln!(file_handle, "{} v{}", crate_name!(), crate_version!()).unwrap();
-            // tree_params Display impl includes ending newline
-            write!(file_handle, "{}", tree_params).unwrap();
-
-            if !short_output {
-                writeln!(file_handle, "Files:").unwrap();
-                let list_str: Vec<String> = file_list.iter()
-                    .filter_map(|(pathbuf, keep)| {
+            }
+            if let Some(output_dir) = cmd_matches.get_one::<String>("outputdir") {
+                if let Err(err) = fs::create_dir_all(output_dir) {
+                    eprintln!("Error creating output directory {}: {}",
+                        output_dir, err);
+                    return ExitCode::GenWriteErr;
+                }
+                // --per-file conflicts with --concat, so there is one part each
+                let inputs: Vec<&Path> = file_list.iter()
+                    .map(|(paths, _, _)| paths[0].as_path())
+                    .collect();
+                // Hash files are opened as each file gets hashed
+                sidecar_list = sidecar_paths(&inputs, Path::new(output_dir));
+            } else {
+                let write_file_name = cmd_matches.get_one::<String>("output").unwrap();
+                let mut file_handle = match open_output_file(
+                        Path::new(write_file_name), cmd_matches.get_flag("overwrite")) {
+                    Ok(file) => {
+                        output_path = Some(PathBuf::from(write_file_name));
+                        if checkpoint_interval.is_some() {
+                            checkpoint_file = match file.try_clone() {
+                                Ok(file) => Some(file),
+                                Err(err) => {
+                                    eprintln!("Error opening file {} for writing: {}",
+                                        write_file_name, err);
+                                    return ExitCode::GenWriteErr;
+                                }
+                            };
+                        }
+                        new_sink(LineWriter::new(file), output_format, self_checksum, with_parity, uppercase)
+                    },
+                    Err(err) => {
+                        eprintln!("Error opening file {} for writing: {}",
+                            write_file_name, err);
+                        return ExitCode::GenWriteErr;
+                    }
+                };
+                if cmd_matches.get_flag("relativetooutput") {
+                    file_handle = match relative_path_sink(file_handle, Path::new(write_file_name)) {
+                        Ok(sink) => sink,
+                        Err(err) => {
+                            eprintln!("Error finding directory of {}: {}", write_file_name, err);
+                            return ExitCode::GenWriteErr;
+                        }
+                    };
+                }
+                let file_entries: Vec<(&[PathBuf], u64, Option<u64>)> = file_list.iter()
+                    .zip(data_lens.iter())
+                    .filter_map(|((paths, hash_len, keep), data_len)| {
                         if *keep {
-                            Some(pathbuf)
+                            Some((paths.as_slice(), *data_len, *hash_len))
                         } else {
                             None
                         }
                     })
-                    .map(|path| {
-                        let path_metadata = path.metadata().unwrap();
-                        (path.to_str().unwrap(), path_metadata.len())
-                    })
-                    .map(|(string, len)| {
-                        let escaped_str = escape_chars(string);
-                        let quoted_str = enquote::enquote('"', &escaped_str);
-                        format!("{} {:#x} bytes", quoted_str, len)
-                    })
                     .collect();
-                writeln!(file_handle, "{}", list_str.join("\n")).unwrap();
-            }
-            writeln!(file_handle, "Hashes:").unwrap();
-            file_handle.flush().unwrap();
+                file_handle.write_header(&SinkHeader {
+                    tree_params: &tree_params,
+                    comments: &comments,
+                    structure_hash: structure_hash.as_ref(),
+                    file_entries: &file_entries,
+                    dir_list: &dir_list,
+                    short_output,
+                    sectioned
+                }).unwrap();
 
-            debug_assert!(verify_start_pos.is_none());
-            cmd_chosen = HashCommand::GenerateHash(Some(file_handle));
+                cmd_chosen = HashCommand::GenerateHash(Some(file_handle));
+            }
         },
         HashCommand::VerifyHash(None) => {
-            let read_file_name = cmd_matches.get_one::<String>("FILE").unwrap();
-            let mut hash_file = match File::open(read_file_name) {
-                Ok(file) => file,
-                Err(e) => {
-                    eprintln!("Error opening hash file {}: {}",
-                            read_file_name, e);
-                    return VERIF_READ_ERR;
+            if let Some(reference) = cmd_matches.get_one::<String>("reference") {
+                if !Path::new(reference).exists() {
+                    eprintln!("Error: reference file {} not found", reference);
+                    return ExitCode::CmdlineErr;
                 }
+            }
+            let hash_input = hash_input.as_ref().unwrap();
+            let read_file_name = hash_input.name();
+            let mut hash_file = match hash_input.open_with_root() {
+                Ok((reader, root)) => {
+                    stored_root = root;
+                    reader
+                },
+                Err(exit_code) => return exit_code
             };
+            // The root line is written last, so it is missing from incomplete hash files
+            match (&structure_hash, &stored_root) {
+                (Some(_), None) => {
+                    eprintln!("Error: hash file has a structure hash but no root line");
+                    return ExitCode::VerifBadHeaderErr;
+                },
+                (None, Some(_)) => {
+                    eprintln!("Error: hash file has a root line but no structure hash");
+                    return ExitCode::VerifBadHeaderErr;
+                },
+                _ => {}
+            }
             hash_file.seek(SeekFrom::Start(verify_start_pos.unwrap())).unwrap();
-            cmd_chosen = HashCommand::VerifyHash(Some(BufReader::new(hash_file)))
+            if any_order {
+                match section_index::index_sections(&mut hash_file, file_list.len(),
+                        2*expected_hash_len) {
+                    Ok(sections) => hash_sections = Some(sections),
+                    Err(SectionIndexErr::ReadError(kind)) => {
+                        eprintln!("Error reading hash file {}: {}", read_file_name, kind);
+                        return ExitCode::VerifReadErr;
+                    },
+                    Err(err) => {
+                        eprintln!("Error indexing hash file {}: {}", read_file_name, err);
+                        return ExitCode::VerifBadEntryErr;
+                    }
+                }
+            }
+            if let Some(cache_file_name) = cmd_matches.get_one::<String>("cache") {
+                // --cache conflicts with --hashes-from-command, so the name is a path
+                match VerifyCache::open(Path::new(cache_file_name), Path::new(&read_file_name)) {
+                    Ok(cache) => verify_cache = Some(cache),
+                    Err(err) => {
+                        eprintln!("Error opening cache file {}: {}",
+                            cache_file_name, err);
+                        return ExitCode::VerifReadErr;
+                    }
+                }
+            }
+            cmd_chosen = HashCommand::VerifyHash(Some(hash_file))
         },
         _ => unreachable!()
     };
 
-    for (file_index, (file_name, process)) in file_list.iter().enumerate() {
-        let filename_str = file_name.to_str().unwrap();
-        if !process {
-            if quiet_count <= 1 {
+    if tree_params.metadata_only {
+        let fail_fast = matches!(cmd_chosen, HashCommand::VerifyHash(_))
+            && cmd_matches.get_flag("failfast");
+        return match metadata_entries(cmd_chosen, &file_list, hash_enum,
+                quiet_count, fail_fast, failures_left,
+                &mut results_file, &pre_hash_errors) {
+            ExitCode::Success => {
+                if let (Some(sign_command), Some(output_path)) = (sign_command, &output_path) {
+                    if let Err(exit_code) = sign_hash_file(sign_command, output_path) {
+                        return exit_code;
+                    }
+                }
+                interrupt::set_pending_output(None);
+                hashing_final_status
+            },
+            metadata_status => metadata_status
+        };
+    }
+
+    // Roots of the files hashed or verified successfully, in entry order
+    let mut file_roots: Vec<HashData<64>> = Vec::new();
+    // Entries and root of each file that later copies are written from
+    let mut dedup_results: HashMap<usize, (Vec<HashRange>, Option<HashData<64>>)> = HashMap::new();
+    let mut hash_stats = match matches.get_flag("stats") {
+        true => Some(HashStats::new(hash_enum, thread_count)),
+        false => None
+    };
+    // Only used for --stats-blocks, which takes every node of every hashed file
+    let block_stats = match cmd_chosen {
+        HashCommand::GenerateHash(_) if cmd_matches.get_flag("statsblocks") => {
+            Some(StatsConsumer::new())
+        },
+        _ => None
+    };
+    // Only used for --root-out, with a line for each hashed file
+    let mut root_out: Option<LineWriter<File>> = match cmd_chosen {
+        HashCommand::GenerateHash(_) => match cmd_matches.get_one::<String>("rootout") {
+            Some(root_out_name) => match create_output_file(Path::new(root_out_name),
+                    cmd_matches.get_flag("overwrite")) {
+                Ok(file) => {
+                    let mut root_out = LineWriter::new(file);
+                    if cmd_matches.get_flag("algorithmid") {
+                        let write_result = info::algorithm_id_lines(hash_enum).iter()
+                            .try_for_each(|line| writeln!(root_out, "# {}", line));
+                        if let Err(err) = write_result {
+                            eprintln!("Error writing root file: {}", err);
+                            return ExitCode::GenWriteErr;
+                        }
+                    }
+                    Some(root_out)
+                },
+                Err(err) => {
+                    eprintln!("Error opening file {} for writing: {}",
+                        root_out_name, err);
+                    return ExitCode::GenWriteErr;
+                }
+            },
+            None => None
+        },
+        HashCommand::VerifyHash(_) => None
+    };
+    // unwrap always succeeds because "filejobs" has a default value
+    let file_jobs = match cmd_chosen {
+        HashCommand::GenerateHash(_) => usize::try_from(
+            *cmd_matches.get_one::<u64>("filejobs").unwrap()).unwrap_or(usize::MAX),
+        HashCommand::VerifyHash(_) => 1
+    };
+    // Files are hashed out of order, but written in order as if they were not
+    // Block stats are taken in the loop below, so files are hashed one at a time
+    let ordered_files = match (&mut cmd_chosen, file_jobs > 1) {
+        (HashCommand::GenerateHash(Some(w)), true) if block_stats.is_none() => {
+            let job_params = FileJobParams {
+                tree_params: &tree_params,
+                tree_options: &tree_options,
+                decompression,
+                short_output,
+                thread_count,
+                read_limit
+            };
+            let worker_count = file_jobs.min(file_list.len()).max(1);
+            let ordered_result = ordered_jobs::run_ordered(file_list.len(), worker_count,
+                file_jobs.saturating_mul(2), |file_index| {
+                    let (file_parts, hash_len, process) = &file_list[file_index];
+                    match process {
+                        true => hash_file_job(&job_params, file_parts, *hash_len,
+                            data_lens[file_index]).map(Some),
+                        false => Ok(None)
+                    }
+                }, |file_index, job_result| {
+                    let file_parts = &file_list[file_index].0;
+                    let filename_string = display_name_group(file_parts);
+                    let job_output = match job_result {
+                        Ok(Some(job_output)) => job_output,
+                        Ok(None) => {
+                            if quiet_count == 0 {
+                                eprintln!("{}", title_center(&filename_string));
+                                eprintln!("Warning: skipped");
+                            } else if quiet_count == 1 {
+                                eprintln!("Warning: skipping file {}", filename_string);
+                            }
+                            return Ok(());
+                        },
+                        Err(message) => {
+                            eprintln!("{}", message);
+                            return Err(ExitCode::DataReadErr);
+                        }
+                    };
+                    if quiet_count == 0 {
+                        let base_names: Vec<_> = file_parts.iter()
+                            .map(|part| part.file_name().unwrap())
+                            .collect();
+                        let file_part = display_name_group(&base_names);
+                        eprintln!("{}", title_center(&abbreviate_filename(&file_part, 80-8)));
+                    } else if quiet_count == 1 {
+                        eprintln!("Hashing {}...", filename_string);
+                    }
+                    let write_result = match short_output {
+                        true => {
+                            let stored_hash = match job_output.file_size == 0 && mark_empty {
+                                true => None,
+                                false => Some(&job_output.root)
+                            };
+                            w.write_short(stored_hash, file_parts)
+                        },
+                        false => job_output.listed_entries.iter()
+                            .try_for_each(|entry| w.write_entry(file_index, entry, byte_offset))
+                    };
+                    if let Err(err) = write_result {
+                        eprintln!("Error writing hash file: {}", err);
+                        return Err(ExitCode::GenWriteErr);
+                    }
+                    write_root_out(&mut root_out, &job_output.root, file_parts, uppercase)?;
+                    if structure_hash.is_some() {
+                        file_roots.push(job_output.root);
+                    }
+                    if let Some(hash_stats) = &mut hash_stats {
+                        hash_stats.record(file_parts, job_output.file_size, job_output.hash_time);
+                    }
+                    if quiet_count == 1 {
+                        eprintln!("Done");
+                    }
+                    Ok(())
+                });
+            if let Err(exit_code) = ordered_result {
+                return exit_code;
+            }
+            // Every file is written, so the loop below has nothing left to do
+            &file_list[..0]
+        },
+        _ => &file_list[..]
+    };
+    // With --reference alone, files are compared with the reference instead of hashed,
+    // unless their roots are needed for the structure hash
+    let compare_reference = match (&cmd_chosen, &structure_hash) {
+        (HashCommand::VerifyHash(_), None) if !cmd_matches.get_flag("bytediff") => {
+            cmd_matches.get_one::<String>("reference")
+        },
+        _ => None
+    };
+    // Bytes of the files verified so far, for --max-total-bytes
+    let max_total_bytes = match &cmd_chosen {
+        HashCommand::VerifyHash(_) => cmd_matches.get_one::<u64>("maxtotalbytes").copied(),
+        _ => None
+    };
+    let mut total_bytes: u64 = 0;
+    let mut budget_exhausted = false;
+    for (file_index, (file_parts, hash_len, process)) in ordered_files.iter().enumerate() {
+        let filename_string = display_name_group(file_parts);
+        let filename_str = filename_string.as_str();
+        // --any-order moves to the entries of each file after finishing the last
+        if let (Some(sections), HashCommand::VerifyHash(Some(r))) = (&hash_sections, &mut cmd_chosen) {
+            if let Err(exit_code) = check_section_end(r, sections, file_index) {
+                return exit_code;
+            }
+            r.seek(SeekFrom::Start(sections[file_index].start)).unwrap();
+        }
+        // Taken before hashing, so that changes during hashing invalidate the result
+        let cache_stamp = match (&verify_cache, process) {
+            (Some(_), true) => verify_cache::entry_stamp(file_parts).ok(),
+            _ => None
+        };
+        let is_cached = match (&verify_cache, &cache_stamp) {
+            (Some(cache), Some(stamp)) => !cmd_matches.get_flag("nocache")
+                && cache.is_verified(file_parts, stamp),
+            _ => false
+        };
+        // Checked before starting each file, so the file that reaches the limit is finished
+        let over_budget = *process && !is_cached
+            && max_total_bytes.is_some_and(|max_total_bytes| total_bytes >= max_total_bytes);
+        if over_budget && !budget_exhausted {
+            budget_exhausted = true;
+            if quiet_count < 2 {
+                eprintln!(concat!("Warning: budget of {} bytes from --max-total-bytes ",
+                    "exhausted, skipping the remaining files"), max_total_bytes.unwrap());
+            }
+            if hashing_final_status == ExitCode::Success {
+                hashing_final_status = ExitCode::BudgetExhausted;
+            }
+        }
+        if !process || is_cached || over_budget {
+            if is_cached {
+                if quiet_count == 0 {
+                    eprintln!("{}", title_center(filename_str));
+                    eprintln!("Info: unchanged since last verified, skipped");
+                } else if quiet_count == 1 {
+                    eprintln!("Info: {} unchanged since last verified, skipped", filename_str);
+                }
+            } else if quiet_count <= 1 {
                 if quiet_count == 0 {
                     eprintln!("{}", title_center(filename_str));
                     eprintln!("Warning: skipped");
@@ -566,84 +4061,293 @@ fn run() -> i32 {
                     // Still check line format, and warn if entry is malformed
                     let hash_parts = extract_short_hash_parts(&hash_line,
                         2*expected_hash_len);
-                    if let Ok((_, quoted_name)) = hash_parts {
-                        assert_eq!(filename_str,
-                            enquote::unquote(quoted_name).unwrap());
-                    } else {
-                        eprintln!("Warning skipping file {}: {}", filename_str,
-                            VerificationError::MalformedEntry(hash_line));
-                        if cmd_matches.get_flag("failfast") {
-                            return VERIF_BAD_ENTRY_ERR;
+                    match hash_parts {
+                        Ok((_, quoted_name)) => {
+                            assert_eq!(filename_str,
+                                display_name_group(&resolve_names(&name_base, quoted_name)));
+                        },
+                        Err(err) => {
+                            eprintln!("Warning skipping file {}: {}", filename_str,
+                                err.into_verification_error(hash_line));
+                            if cmd_matches.get_flag("failfast") {
+                                return ExitCode::VerifBadEntryErr;
+                            }
                         }
                     }
-                } else {
-                    loop {
-                        let mut hash_line = String::new();
-                        let chars_read = r.read_line(&mut hash_line).unwrap();
-                        let hash_parts = extract_long_hash_parts(&hash_line,
-                            2*expected_hash_len);
-                        if let Ok((read_index, _)) = hash_parts {
-                            if read_index == file_index + 1 {
-                                r.seek_relative(-i64::try_from(chars_read).unwrap()).unwrap();
-                                break;
-                            } else if read_index != file_index {
-                                eprintln!("Error skipping file {}: {}",
-                                    filename_str,
-                                    VerificationError::MismatchedFileID);
-                                return VERIF_BAD_ENTRY_ERR;
-                            }
-                        } else  if chars_read > 0 {
-                            eprintln!("Error skipping file {}: {}",
-                                filename_str,
-                                VerificationError::MalformedEntry(hash_line));
-                            return VERIF_BAD_ENTRY_ERR;
-                        } else {
-                            break; // EOF
+                } else if let Err(err) = skip_long_entries(r, file_index,
+                        2*expected_hash_len, any_order) {
+                    eprintln!("Error skipping file {}: {}", filename_str, err);
+                    return ExitCode::VerifBadEntryErr;
+                }
+            }
+            let result = match (is_cached, pre_hash_errors.get(file_index)) {
+                (true, _) => FileResult::new(VerifyOutcome::Unchanged),
+                (false, Some(Some(err))) => FileResult::failed(err.clone()),
+                (false, _) => FileResult::new(VerifyOutcome::Skipped)
+            };
+            if let Err(exit_code) = write_result(&mut results_file, file_index,
+                    file_parts, &result) {
+                return exit_code;
+            }
+            continue;
+        }
+        if max_total_bytes.is_some() {
+            // Short hash files have no recorded length, so the file length is used
+            total_bytes += hash_len.unwrap_or_else(|| file_parts.iter()
+                .filter_map(|path| path.metadata().ok())
+                .map(|metadata| metadata.len())
+                .sum());
+        }
+        if let (HashCommand::VerifyHash(Some(r)), Some(reference)) = (&mut cmd_chosen, compare_reference) {
+            let reference_parts = reference_parts(Path::new(reference), file_parts);
+            let reference_name = display_name_group(&reference_parts);
+            if quiet_count == 0 {
+                eprintln!("{}", title_center(filename_str));
+            } else if quiet_count == 1 {
+                eprintln!("Comparing {} with reference...", filename_str);
+            }
+            // Without a recorded length, a reference copy of
+            // another length differs at the end of the shorter one
+            let region_offset = tree_params.region_offset.unwrap_or(0);
+            let differing_byte = match reference_differing_byte(file_parts, &reference_parts,
+                    region_offset, hash_len.unwrap_or(u64::MAX), decompression, tree_params.normalize_eol) {
+                Ok(differing_byte) => differing_byte,
+                Err(err) => {
+                    eprintln!("Error comparing file {} with reference {}: {}",
+                        filename_str, reference_name, err);
+                    if let Err(exit_code) = write_result(&mut results_file, file_index,
+                            file_parts, &FileResult::failed(err.to_string())) {
+                        return exit_code;
+                    }
+                    return ExitCode::DataReadErr;
+                }
+            };
+            // The comparison stands in for the entries, so they are only skipped
+            if short_output {
+                r.read_line(&mut String::new()).unwrap();
+            } else if let Err(err) = skip_long_entries(r, file_index,
+                    2*expected_hash_len, any_order) {
+                eprintln!("Error skipping file {}: {}", filename_str, err);
+                return ExitCode::VerifBadEntryErr;
+            }
+            if let (Some(cache), Some(stamp)) = (&mut verify_cache, &cache_stamp) {
+                if let Err(err) = cache.record(file_parts, stamp, differing_byte.is_none()) {
+                    eprintln!("Warning: unable to update cache file: {}", err);
+                }
+            }
+            let Some(differing_byte) = differing_byte else {
+                if let Err(exit_code) = write_result(&mut results_file, file_index,
+                        file_parts, &FileResult::new(VerifyOutcome::Ok)) {
+                    return exit_code;
+                }
+                if quiet_count < 2 {
+                    eprintln!("Info: {} matches reference {}", filename_str, reference_name);
+                }
+                continue;
+            };
+            // Positions are printed relative to the region unless ranges are absolute
+            let differing_byte = differing_byte - (region_offset - byte_offset);
+            let err_str = format!("first byte differing from reference {} is at {:#x}",
+                reference_name, differing_byte);
+            eprintln!("Error verifying file {}: {}", filename_str, err_str);
+            if let Err(exit_code) = write_result(&mut results_file, file_index,
+                    file_parts, &FileResult::differing(err_str, differing_byte)) {
+                return exit_code;
+            }
+            if cmd_matches.get_flag("failfast") {
+                return ExitCode::VerifBadEntryErr;
+            }
+            hashing_final_status = ExitCode::VerifBadEntryErr;
+            if count_failure(&mut failures_left) {
+                return ExitCode::VerifBadEntryErr;
+            }
+            continue;
+        }
+        // Entry indices restart in each --per-file hash file
+        let entry_index = match sidecar_list.get(file_index) {
+            Some(sidecar_path) => {
+                // Finish the hash file of the previous entry first
+                if let HashCommand::GenerateHash(Some(w)) = std::mem::replace(
+                        &mut cmd_chosen, HashCommand::GenerateHash(None)) {
+                    if let Err(err) = w.finish() {
+                        eprintln!("Error writing hash file: {}", err);
+                        return ExitCode::GenWriteErr;
+                    }
+                    if let (Some(sign_command), Some(output_path)) = (sign_command, &output_path) {
+                        if let Err(exit_code) = sign_hash_file(sign_command, output_path) {
+                            return exit_code;
                         }
                     }
                 }
+                let mut file_handle = match open_output_file(sidecar_path,
+                        cmd_matches.get_flag("overwrite")) {
+                    Ok(file) => {
+                        output_path = Some(sidecar_path.clone());
+                        if checkpoint_interval.is_some() {
+                            checkpoint_file = match file.try_clone() {
+                                Ok(file) => Some(file),
+                                Err(err) => {
+                                    eprintln!("Error opening file {} for writing: {}",
+                                        sidecar_path.display(), err);
+                                    return ExitCode::GenWriteErr;
+                                }
+                            };
+                        }
+                        new_sink(LineWriter::new(file), output_format, self_checksum, with_parity, uppercase)
+                    },
+                    Err(err) => {
+                        eprintln!("Error opening file {} for writing: {}",
+                            sidecar_path.display(), err);
+                        return ExitCode::GenWriteErr;
+                    }
+                };
+                let file_entry = (file_parts.as_slice(), data_lens[file_index], *hash_len);
+                file_handle.write_header(&SinkHeader {
+                    tree_params: &tree_params,
+                    comments: &comments,
+                    structure_hash: None,
+                    file_entries: &[file_entry],
+                    dir_list: &[],
+                    short_output,
+                    sectioned
+                }).unwrap();
+                cmd_chosen = HashCommand::GenerateHash(Some(file_handle));
+                0
+            },
+            None => file_index
+        };
+        let dedup_result = dedup_sources[file_index]
+            .and_then(|source_index| dedup_results.get(&source_index)
+                .map(|result| (source_index, result)));
+        if let Some((source_index, (source_entries, source_root))) = dedup_result {
+            let source_name = display_name_group(&file_list[source_index].0);
+            if let HashCommand::GenerateHash(Some(w)) = &mut cmd_chosen {
+                if short_output {
+                    let file_size = hash_len.unwrap_or(data_lens[file_index]);
+                    let stored_hash = match file_size == 0 && mark_empty {
+                        true => None,
+                        false => source_root.as_ref()
+                    };
+                    w.write_short(stored_hash, file_parts).unwrap();
+                } else {
+                    for entry in source_entries {
+                        w.write_entry(entry_index, entry, byte_offset).unwrap();
+                    }
+                }
+            }
+            if let Some(source_root) = source_root {
+                if let Err(exit_code) = write_root_out(&mut root_out, source_root, file_parts, uppercase) {
+                    return exit_code;
+                }
+            }
+            if let (Some(_), Some(source_root)) = (&structure_hash, source_root) {
+                file_roots.push(source_root.clone());
+            }
+            if quiet_count == 0 {
+                eprintln!("{}", title_center(filename_str));
+                eprintln!("Info: same contents as {}, hashes reused", source_name);
+            } else if quiet_count == 1 {
+                eprintln!("Info: {} has the same contents as {}, hashes reused",
+                    filename_str, source_name);
             }
             continue;
         }
-        let file_obj = match File::open(file_name) {
-            Ok(file) => file,
+        let is_dedup_source = dedup_sources.contains(&Some(file_index));
+        let mut part_files = Vec::with_capacity(file_parts.len());
+        for file_part in file_parts {
+            match DataFile::open(file_part, decompression, tree_params.normalize_eol) {
+                Ok(file) => part_files.push(file),
+                Err(err) => {
+                    eprintln!("Error opening file {} for reading: {}",
+                        file_part.display(), err);
+                    if let Err(exit_code) = write_result(&mut results_file, file_index,
+                            file_parts, &FileResult::failed(err.to_string())) {
+                        return exit_code;
+                    }
+                    return ExitCode::DataReadErr;
+                }
+            }
+        }
+        let concat_obj = ConcatReader::new(part_files);
+        // Only differs from the actual length with --allow-append, trimmed zeros, or a region
+        let file_size = match (hash_len, tree_params.region_offset) {
+            (Some(hash_len), _) => *hash_len,
+            // Generation records the region length instead of a hashed length
+            (None, Some(_)) => data_lens[file_index],
+            (None, None) => concat_obj.len()
+        };
+        let region_offset = tree_params.region_offset.unwrap_or(0);
+        let mut file_obj = match TruncatedReader::with_offset(concat_obj, region_offset, file_size) {
+            Ok(reader) => reader,
             Err(err) => {
-                eprintln!("Error opening file {} for reading: {}",
-                    filename_str, err);
-                return DATA_READ_ERR;
+                eprintln!("Error reading file {}: {}", filename_str, err);
+                return ExitCode::DataReadErr;
             }
         };
-        let file_size = file_obj.metadata().unwrap().len();
-        let pb_hash_len = merkle_tree::node_count(file_size, block_size, branch_factor);
-
-        let pb_draw_target = match quiet_count {
-            0 => ProgressDrawTarget::hidden(),
-            _ => ProgressDrawTarget::stderr_with_hz(5)
+        // The tree over content-defined chunks has the shape of one byte
+        // blocks over the chunk count, and the library reads the file twice
+        let (tree_len, tree_block_size, pb_file_len) = match tree_params.content_defined {
+            true => match merkle_tree::chunk_boundaries(&mut file_obj, block_size) {
+                Ok(chunk_ends) => (chunk_ends.len() as u64, 1, 2*file_size),
+                Err(err) => {
+                    eprintln!("Error reading file {}: {}", filename_str, err);
+                    return ExitCode::DataReadErr;
+                }
+            },
+            false => {
+                let (tree_len, tree_block_size) = tree_params.tree_shape(file_size);
+                (tree_len, tree_block_size, file_size)
+            }
         };
+        let pb_hash_len = merkle_tree::node_count(tree_len, tree_block_size, branch_factor);
 
-        let (pb_file, pb_hash) = setup_pbs(pb_draw_target, file_size, pb_hash_len);
+        let (pb_file, pb_hash) = setup_pbs(progress_output.draw_target(),
+            pb_file_len, pb_hash_len);
 
         if quiet_count == 0 {
-            let file_part = file_name.file_name().unwrap()
-                    .to_str().unwrap();
+            let base_names: Vec<_> = file_parts.iter()
+                .map(|part| part.file_name().unwrap())
+                .collect();
+            let file_part = display_name_group(&base_names);
 
             // Leave a padding of at least 3 equal signs on each side
             // TODO: use fixed width, or scale with terminal size?
-            let abbreviated_msg = abbreviate_filename(file_part, 80-8);
+            let abbreviated_msg = abbreviate_filename(&file_part, 80-8);
             eprintln!("{}", title_center(&abbreviated_msg));
         } else if quiet_count == 1 {
             eprintln!("Hashing {}...", filename_str);
         }
 
-        let (tx, rx, pb_hash): (ChannelOrPb<_>, _, _) = match short_output {
-            true => (pb_hash.into(), None, None),
+        let listed_root = tree_params.listed_root(file_size);
+        // Without a thread pool, entries are hashed in order and skip the channel
+        let direct_writer = match &mut cmd_chosen {
+            HashCommand::GenerateHash(w) if thread_count == 0 && !short_output
+                    && !is_dedup_source && checkpoint_interval.is_none()
+                    && block_stats.is_none() => {
+                w.take().map(|w| WriterConsumer::new(w, entry_index, byte_offset,
+                    listed_root, pb_hash.clone()))
+            },
+            _ => None
+        };
+        // --root-only hashes like short output and reads the root entry afterwards
+        let (tx, rx, pb_hash): (Option<ChannelOrPb<_>>, _, _) = match short_output || root_only {
+            true => (Some(pb_hash.into()), None, None),
+            false if direct_writer.is_some() => (None, None, Some(pb_hash)),
             false => {
                 let (tx, rx) = bounded_channel::<HashRange>(16);
-                (tx.into(), Some(rx), Some(pb_hash))
+                (Some(tx.into()), Some(rx), Some(pb_hash))
             }
         };
-        let thread_handle = thread::Builder::new()
-            .name(String::from(filename_str))
+        let file_tree_options = tree_options.clone();
+        let thread_direct_writer = direct_writer.clone();
+        let hash_start = Instant::now();
+        // Without a thread pool, the whole tree is hashed on this thread
+        let mut thread_builder = thread::Builder::new()
+            .name(String::from(filename_str));
+        if let Some(stack_size) = tree_options.stack_size {
+            thread_builder = thread_builder.stack_size(stack_size);
+        }
+        let thread_handle = thread_builder
             .spawn(move || {
                 /*let buf_size: usize = (block_size*(branch_factor as block_t))
                     .clamp(4*1024, 256*1024).try_into().unwrap();
@@ -654,31 +4358,53 @@ fn run() -> i32 {
                 // Addition of the seek parameter *should* be a workaround 
                 // Do more testing and benchmarking later
                 // TODO: use rustversion cfg once this is fixed
-                let pb_wrap = pb_file.wrap_read(file_obj);
-                let result = merkle_tree_thunk(pb_wrap,
-                    block_size, branch_factor, tx, thread_count);
+                let pb_wrap = pb_file.wrap_read(ThrottledReader::new(file_obj, read_limit));
+                let result = match thread_direct_writer {
+                    Some(writer) => direct_tree_thunk(pb_wrap,
+                        block_size, branch_factor, &file_tree_options,
+                        writer, thread_count),
+                    None => merkle_tree_thunk(pb_wrap,
+                        block_size, branch_factor, &file_tree_options,
+                        tx.unwrap(), thread_count)
+                };
                 pb_file.finish();
                 result
             })
             .unwrap();
 
         let mut hash_loop_status: Result<(), VerificationError> = Ok(());
+        let mut listed_entries: Vec<HashRange> = Vec::new();
 
         if let Some(rx) = rx {
             let block_iter = merkle_block_generator(
-                file_size, block_size, branch_factor).into_iter();
-            for block_hash in reorder_hashrange_iter(block_iter, rx.into_iter()) {
+                tree_len, tree_block_size, branch_factor).into_iter();
+            for block_hash in tree_ordered(block_iter, rx.into_iter(), thread_count) {
                 if let Some(ref pb_hash) = pb_hash {
                     pb_hash.inc(1);
                 }
+                // Unlisted nodes are still counted
+                if let Some(block_stats) = &block_stats {
+                    block_stats.accept(block_hash.clone()).unwrap();
+                }
+                if !is_listed_node(block_hash.block_range(), listed_root) {
+                    continue;
+                }
                 match &mut cmd_chosen {
                     HashCommand::GenerateHash(Some(w)) => {
-                        writeln!(w, "{:3} {} {} {}",
-                            file_index,
-                            block_hash.block_range(),
-                            block_hash.byte_range(),
-                            hex::encode(block_hash.hash_result())
-                        ).unwrap();
+                        w.write_entry(entry_index, &block_hash, byte_offset).unwrap();
+                        if is_dedup_source {
+                            listed_entries.push(block_hash.clone());
+                        }
+                        entries_since_checkpoint += 1;
+                        if let (Some(interval), Some(file)) = (checkpoint_interval, &checkpoint_file) {
+                            if entries_since_checkpoint == interval {
+                                entries_since_checkpoint = 0;
+                                if let Err(err) = w.flush().and_then(|_| file.sync_data()) {
+                                    eprintln!("Error writing hash file: {}", err);
+                                    return ExitCode::GenWriteErr;
+                                }
+                            }
+                        }
                     }
                     HashCommand::VerifyHash(Some(r)) => {
                         let mut line = String::new();
@@ -699,17 +4425,31 @@ fn run() -> i32 {
                                 hash_loop_status = Err(VerificationError::MismatchedBlockRange(StoredAndComputed::new(file_hash_range.block_range(), block_hash.block_range())));
                                 break;
                             }
-                            if block_hash.byte_range() != file_hash_range.byte_range() {
-                                hash_loop_status = Err(VerificationError::MismatchedByteRange(StoredAndComputed::new(file_hash_range.byte_range(), block_hash.byte_range())))
+                            // The stored byte range must follow from the stored block range
+                            // Chunk byte ranges depend on the data, so are only compared below
+                            if !tree_params.content_defined {
+                                let derived_byte_range = offset_byte_range(
+                                    tree_params.node_byte_range(file_hash_range.block_range(),
+                                        file_size), byte_offset);
+                                if file_hash_range.byte_range() != derived_byte_range {
+                                    hash_loop_status = Err(VerificationError::InconsistentByteRange(StoredAndComputed::new(file_hash_range.byte_range(), derived_byte_range)));
+                                    break;
+                                }
+                            }
+                            let computed_byte_range = offset_byte_range(block_hash.byte_range(), byte_offset);
+                            if computed_byte_range != file_hash_range.byte_range() {
+                                hash_loop_status = Err(VerificationError::MismatchedByteRange(StoredAndComputed::new(file_hash_range.byte_range(), computed_byte_range)));
+                                break;
                             }
                             if block_hash.hash_result() != file_hash_range.hash_result() {
                                 let file_hash_data = HashData::try_new(file_hash_range.hash_result()).unwrap();
                                 let block_hash_data = HashData::try_new(block_hash.hash_result()).unwrap();
-                                hash_loop_status = Err(VerificationError::MismatchedHash(Some(block_hash.byte_range()), StoredAndComputed::new(file_hash_data,block_hash_data)));
+                                hash_loop_status = Err(VerificationError::MismatchedHash(Some(computed_byte_range), StoredAndComputed::new(file_hash_data,block_hash_data)));
                                 break;
                             }
                         } else {
-                            hash_loop_status = Err(VerificationError::MalformedEntry(line));
+                            hash_loop_status = Err(hash_parts.unwrap_err()
+                                .into_verification_error(line));
                             break;
                         }
                     }
@@ -719,15 +4459,48 @@ fn run() -> i32 {
             }
         }
 
-        if let Some(ref pb_hash) = pb_hash {
+        // The direct writer advances the pb until the hashing thread exits
+        let final_hash_option = match thread_handle.join().unwrap() {
+            Ok(final_hash) => Some(final_hash),
+            Err(err) => match err.position_mismatch() {
+                Some(mismatch) => {
+                    eprintln!("Error reading file {}: {}", filename_str, mismatch);
+                    if let Err(exit_code) = write_result(&mut results_file, file_index,
+                            file_parts, &FileResult::failed(mismatch.to_string())) {
+                        return exit_code;
+                    }
+                    return ExitCode::DataReadErr;
+                },
+                None => None
+            }
+        };
+        if is_dedup_source {
+            dedup_results.insert(file_index, (listed_entries, final_hash_option.clone()));
+        }
+        if let Some(hash_stats) = &mut hash_stats {
+            hash_stats.record(file_parts, file_size, hash_start.elapsed());
+        }
+        if let Some(direct_writer) = direct_writer {
+            cmd_chosen = HashCommand::GenerateHash(Some(direct_writer.into_inner()));
+        }
+        // Dropped here, as --progress-hz 0 draws the last frame once the bars are dropped
+        if let Some(pb_hash) = pb_hash {
             pb_hash.finish();
 
             if quiet_count == 0 && hash_loop_status.is_ok() {
                 assert_eq!(pb_hash.position(), pb_hash.length().unwrap());
             }
         }
-        let final_hash_option = thread_handle.join().unwrap();
 
+        if let Some(final_hash) = &final_hash_option {
+            if let Err(exit_code) = write_root_out(&mut root_out, final_hash, file_parts, uppercase) {
+                return exit_code;
+            }
+        }
+        let file_root = match structure_hash {
+            Some(_) => final_hash_option.clone(),
+            None => None
+        };
         if short_output {
             /*
              * Only using final result for short output
@@ -737,11 +4510,11 @@ fn run() -> i32 {
             let final_hash = final_hash_option.unwrap();
             match &mut cmd_chosen {
                 HashCommand::GenerateHash(Some(w)) => {
-                    let escaped_filename = escape_chars(filename_str);
-                    writeln!(w, "{}  {}",
-                        hex::encode(final_hash),
-                        enquote::enquote('"', &escaped_filename)).unwrap();
-                    w.flush().unwrap();
+                    let stored_hash = match file_size == 0 && mark_empty {
+                        true => None,
+                        false => Some(&final_hash)
+                    };
+                    w.write_short(stored_hash, file_parts).unwrap();
                 },
                 HashCommand::VerifyHash(Some(r)) => {
                     let mut line = String::new();
@@ -750,21 +4523,59 @@ fn run() -> i32 {
                     let hash_parts = extract_short_hash_parts(&line, 2*expected_hash_len);
                     if let Ok((file_hash_read, quoted_name)) = hash_parts {
                         assert_eq!(filename_str,
-                            enquote::unquote(quoted_name).unwrap());
-                        if final_hash == file_hash_read {
-                            hash_loop_status = Ok(());
-                        } else {
-                            hash_loop_status = Err(VerificationError::MismatchedHash(None, StoredAndComputed::new(file_hash_read, final_hash)));
-                        }
+                            display_name_group(&resolve_names(&name_base, quoted_name)));
+                        hash_loop_status = match file_hash_read {
+                            Some(file_hash_read) if final_hash != file_hash_read => {
+                                Err(VerificationError::MismatchedHash(None, StoredAndComputed::new(file_hash_read, final_hash)))
+                            },
+                            None if file_size != 0 => {
+                                Err(VerificationError::NoLongerEmpty(file_size))
+                            },
+                            _ => Ok(())
+                        };
                     } else {
-                        hash_loop_status = Err(VerificationError::MalformedEntry(line));
+                        hash_loop_status = Err(hash_parts.unwrap_err()
+                            .into_verification_error(line));
                     }
                 },
                 _ => unreachable!()
             }
+        } else if root_only {
+            let final_hash = final_hash_option.unwrap();
+            let computed_root = merkle_block_generator(
+                tree_len, tree_block_size, branch_factor).into_iter().last().unwrap();
+            if let HashCommand::VerifyHash(Some(r)) = &mut cmd_chosen {
+                // The root is the last entry of the file
+                hash_loop_status = match skip_long_entries(r, file_index, 2*expected_hash_len, any_order) {
+                    Ok(Some(stored_root)) if stored_root.block_range() != computed_root => {
+                        Err(VerificationError::MismatchedBlockRange(StoredAndComputed::new(
+                            stored_root.block_range(), computed_root)))
+                    },
+                    Ok(Some(stored_root)) if stored_root.hash_result() != final_hash.as_ref() => {
+                        let stored_hash = HashData::try_new(stored_root.hash_result()).unwrap();
+                        Err(VerificationError::MismatchedHash(None,
+                            StoredAndComputed::new(stored_hash, final_hash)))
+                    },
+                    Ok(Some(_)) => Ok(()),
+                    Ok(None) => Err(VerificationError::UnexpectedEof),
+                    Err(err) => Err(err)
+                };
+            }
+        }
+        if let (Some(file_root), true) = (file_root, hash_loop_status.is_ok()) {
+            file_roots.push(file_root);
+        }
+        if let (Some(cache), Some(stamp)) = (&mut verify_cache, &cache_stamp) {
+            if let Err(err) = cache.record(file_parts, stamp, hash_loop_status.is_ok()) {
+                eprintln!("Warning: unable to update cache file: {}", err);
+            }
         }
         match hash_loop_status {
             Ok(_) => {
+                if let Err(exit_code) = write_result(&mut results_file, file_index,
+                        file_parts, &FileResult::new(VerifyOutcome::Ok)) {
+                    return exit_code;
+                }
                 if quiet_count < 2 {
                     match cmd_chosen {
                         HashCommand::GenerateHash(_) => {
@@ -772,6 +4583,9 @@ fn run() -> i32 {
                                 eprintln!("Done")
                             }
                         },
+                        HashCommand::VerifyHash(_) if root_only => {
+                            eprintln!("Info: {} root hash matches", filename_str)
+                        },
                         HashCommand::VerifyHash(_) => {
                             eprintln!("Info: {} hash matches", filename_str)
                         }
@@ -780,31 +4594,110 @@ fn run() -> i32 {
             },
             Err(err) => {
                 eprintln!("Error verifying file {}: {}", filename_str, err);
+                let mut differing_byte = None;
+                if let VerificationError::MismatchedHash(Some(byte_range), _) = &err {
+                    if let Some(reference) = cmd_matches.get_one::<String>("reference") {
+                        // Ranges are printed relative to the region unless they are absolute
+                        differing_byte = print_byte_diff(file_parts, Path::new(reference),
+                            *byte_range, region_offset - byte_offset, decompression,
+                            tree_params.normalize_eol);
+                    }
+                }
+                if let Err(exit_code) = write_result(&mut results_file, file_index,
+                        file_parts, &FileResult::mismatched(&err, differing_byte)) {
+                    return exit_code;
+                }
                 // TODO: error recovery when not using failfast
-                if cmd_matches.get_flag("failfast") || !short_output {
-                    return VERIF_BAD_ENTRY_ERR;
+                // --root-only reads every entry of the file, so it can continue
+                if cmd_matches.get_flag("failfast") || !(short_output || root_only) {
+                    return ExitCode::VerifBadEntryErr;
                 }
                 // Long output and failfast not specified
                 match err {
                     VerificationError::MismatchedHash(..)
-                    | VerificationError::MalformedEntry(..) => {
-                        hashing_final_status = VERIF_BAD_ENTRY_ERR;
+                    | VerificationError::NoLongerEmpty(..)
+                    | VerificationError::MalformedEntry(..)
+                    | VerificationError::MismatchedHashLength(..) => {
+                        hashing_final_status = ExitCode::VerifBadEntryErr;
+                        if count_failure(&mut failures_left) {
+                            return ExitCode::VerifBadEntryErr;
+                        }
                         continue;
                     }
-                    _ => {return VERIF_BAD_ENTRY_ERR;}
+                    _ => {return ExitCode::VerifBadEntryErr;}
                 }
             }
         }
     }
+    if let Some(hash_stats) = &hash_stats {
+        eprintln!("{}", hash_stats);
+    }
+    if let Some(block_stats) = &block_stats {
+        eprintln!("{}", BlockStatsSummary::new(block_stats.finish(), uppercase));
+    }
     // Consume hash_file_handle to ensure it isn't used again
-    if let HashCommand::VerifyHash(Some(mut r)) = cmd_chosen {
-        // Check if at EOF
-        let current_pos = r.stream_position().unwrap();
-        let end_pos = r.seek(SeekFrom::End(0)).unwrap();
-        if current_pos != end_pos {
-            eprintln!("Error: hash file has extra lines left over");
-            return VERIF_BAD_ENTRY_ERR;
-        }
+    match cmd_chosen {
+        HashCommand::GenerateHash(Some(mut w)) => {
+            // Every file was hashed, as any errors would have aborted generation
+            if let Some(structure_hash) = &structure_hash {
+                let root = tree_structure::combined_root(hash_enum,
+                    structure_hash.as_ref(), &file_roots);
+                if let Err(err) = w.write_root(&root) {
+                    eprintln!("Error writing hash file: {}", err);
+                    return ExitCode::GenWriteErr;
+                }
+            }
+            // Flush once at the end instead of after every entry
+            if let Err(err) = w.finish() {
+                eprintln!("Error writing hash file: {}", err);
+                return ExitCode::GenWriteErr;
+            }
+            // Signed last so that the signature covers the whole hash file
+            if let (Some(sign_command), Some(output_path)) = (sign_command, &output_path) {
+                if let Err(exit_code) = sign_hash_file(sign_command, output_path) {
+                    return exit_code;
+                }
+            }
+            interrupt::set_pending_output(None);
+        },
+        HashCommand::VerifyHash(Some(mut r)) => {
+            if let Some(sections) = &hash_sections {
+                if let Err(exit_code) = check_section_end(&mut r, sections, file_list.len()) {
+                    return exit_code;
+                }
+                // The sections cover every line, so none are left over past them
+                r.seek(SeekFrom::End(0)).unwrap();
+            }
+            // Check if at EOF
+            // read_line consumes a last line without a newline up to EOF,
+            // so a missing final newline is not reported as left over
+            let current_pos = r.stream_position().unwrap();
+            let end_pos = r.seek(SeekFrom::End(0)).unwrap();
+            if current_pos != end_pos {
+                eprintln!("Error: hash file has extra lines left over");
+                return ExitCode::VerifBadEntryErr;
+            }
+            if let (Some(structure_hash), Some(stored_root)) = (&structure_hash, &stored_root) {
+                if file_roots.len() != file_list.len() {
+                    if quiet_count < 2 {
+                        eprintln!("Warning: combined root not checked because some files were not verified");
+                    }
+                } else {
+                    let root = tree_structure::combined_root(hash_enum,
+                        structure_hash.as_ref(), &file_roots);
+                    if root != *stored_root {
+                        let err = VerificationError::MismatchedHash(None,
+                            StoredAndComputed::new(stored_root.clone(), root));
+                        eprintln!("Error verifying combined root: {}", err);
+                        return ExitCode::VerifBadEntryErr;
+                    }
+                    if quiet_count < 2 {
+                        eprintln!("Info: combined root matches");
+                    }
+                }
+            }
+        },
+        _ => {}
     }
     return hashing_final_status;
 }
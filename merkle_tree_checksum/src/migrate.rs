@@ -0,0 +1,117 @@
+#![forbid(unsafe_code)]
+// The migrate subcommand, which rewrites hash files of older format versions
+
+use crate::error_types::ParseError;
+use crate::exit_codes::ExitCode;
+use crate::format_functions::unescape_chars;
+use crate::format_version::{FORMAT_VERSION, MIN_READABLE_VERSION};
+use crate::hash_file_input::parse_hash_file_contents;
+use crate::hash_file_parser::{parse_hash_file, HashEntry, ParsedHashFile};
+use crate::hash_sink::{HashSink, SinkHeader, TextSink};
+use crate::output_files::write_hash_output;
+
+use clap::{Command, Arg, ArgAction, ArgMatches};
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+pub(crate) const MIGRATE_CMD_NAME: &str = "migrate";
+
+pub(crate) fn migrate_command() -> Command {
+    Command::new(MIGRATE_CMD_NAME)
+        .about("Rewrite a hash file from an older format version in the current format")
+        .long_about(concat!("Rewrite a hash file written by an older version of ",
+            "this program in the current format version, with the same parameters, ",
+            "file list, and hashes, and a Features line for the parameters ",
+            "that need one. Any format version that can still be read can be migrated, ",
+            "as the current format only adds to them. Older format versions ",
+            "cannot be migrated, and their hash files have to be generated again. ",
+            "Checksum footers and parity are checked, but not carried over."))
+        .arg(Arg::new("output").long("output").short('o')
+            .action(ArgAction::Set)
+            .help("Output file, instead of stdout"))
+        .arg(Arg::new("overwrite").long("overwrite")
+            .action(ArgAction::SetTrue)
+            .requires("output")
+            .help("Overwrite the output file if it already exists"))
+        .arg(Arg::new("FILE").required(true)
+            .action(ArgAction::Set)
+            .help("Hash file to migrate"))
+}
+
+// Rewrites a hash file of a readable older format version in the current format
+// Names are unescaped so that the writer escapes them again as they were
+fn migrate_hash_file<W: Write+std::fmt::Debug>(writer: W,
+        parsed_file: &ParsedHashFile) -> Result<(), ExitCode> {
+    let header = &parsed_file.header;
+    let file_paths: Vec<Vec<PathBuf>> = parsed_file.files.iter()
+        .map(|file_entry| file_entry.names.iter()
+            .map(|name| PathBuf::from(unescape_chars(name))).collect())
+        .collect();
+    let file_entries: Vec<(&[PathBuf], u64, Option<u64>)> = file_paths.iter()
+        .zip(&parsed_file.files)
+        .map(|(paths, file_entry)| (paths.as_slice(), file_entry.file_len, file_entry.hashed_len))
+        .collect();
+    let dir_list: Vec<PathBuf> = parsed_file.empty_dirs.iter()
+        .map(|name| PathBuf::from(unescape_chars(name))).collect();
+    let mut sink = TextSink::new(writer, false, false);
+    let sink_header = SinkHeader {
+        tree_params: &header.tree_params,
+        comments: &header.comments,
+        structure_hash: header.structure_hash.as_ref(),
+        file_entries: &file_entries,
+        dir_list: &dir_list,
+        short_output: header.is_short_hash,
+        sectioned: header.sectioned
+    };
+    let write_result = sink.write_header(&sink_header).and_then(|_| {
+        parsed_file.entries.iter().try_for_each(|entry| match entry {
+            // Byte ranges are written as stored, already offset if they were absolute
+            HashEntry::Long(file_index, hash_range) =>
+                sink.write_entry(*file_index, hash_range, 0),
+            HashEntry::Short(hash, names) => {
+                let paths: Vec<PathBuf> = names.iter()
+                    .map(|name| PathBuf::from(unescape_chars(name))).collect();
+                sink.write_short(hash.as_ref(), &paths)
+            },
+            HashEntry::Metadata(file_index, hash) => sink.write_metadata(*file_index, hash)
+        })
+    }).and_then(|_| match &parsed_file.root {
+        Some(root) => sink.write_root(root),
+        None => Ok(())
+    });
+    write_result.and_then(|_| Box::new(sink).finish()).map_err(|err| {
+        eprintln!("Error writing hash file: {}", err);
+        ExitCode::GenWriteErr
+    })
+}
+
+pub(crate) fn run_migrate(cmd_matches: &ArgMatches) -> ExitCode {
+    let file_name = cmd_matches.get_one::<String>("FILE").unwrap();
+    let hash_file_bytes = match fs::read(file_name) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Error opening hash file {}: {}", file_name, err);
+            return ExitCode::VerifReadErr;
+        }
+    };
+    // Explain why a version cannot be migrated before reporting it as unsupported
+    if let Err(ParseError::UnsupportedVersion(version)) = parse_hash_file(&hash_file_bytes) {
+        match version < MIN_READABLE_VERSION {
+            true => eprintln!(concat!("Error: unable to migrate hash file {}: ",
+                "format version {} is older than {}, the oldest format version ",
+                "that can be read, so generate the hash file again instead"),
+                file_name, version, MIN_READABLE_VERSION),
+            false => eprintln!(concat!("Error: unable to migrate hash file {}: ",
+                "format version {} is newer than {}, the format version ",
+                "this program writes"), file_name, version, FORMAT_VERSION)
+        }
+        return ExitCode::VerifBadHeaderErr;
+    }
+    let parsed_file = match parse_hash_file_contents(&hash_file_bytes, file_name) {
+        Ok(parsed_file) => parsed_file,
+        Err(exit_code) => return exit_code
+    };
+    write_hash_output(cmd_matches, |writer| migrate_hash_file(writer, &parsed_file))
+}
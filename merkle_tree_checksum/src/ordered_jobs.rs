@@ -0,0 +1,114 @@
+#![forbid(unsafe_code)]
+// Runs jobs on several threads while handling their results in order,
+// for generate-hash --file-jobs
+
+use crossbeam_channel::{bounded, unbounded};
+
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+// Runs job for every index below job_count on worker_count threads, and passes
+// the results to handle_result on the calling thread in index order
+// At most max_pending jobs are running or waiting to be handled at once,
+// which bounds the memory held by results that arrived early
+// Once handle_result returns an error, no more jobs are started,
+// and the error is returned after the running jobs finish
+pub(crate) fn run_ordered<T, E, J, H>(job_count: usize, worker_count: usize,
+        max_pending: usize, job: J, mut handle_result: H) -> Result<(), E>
+where
+    T: Send,
+    J: Fn(usize) -> T + Sync,
+    H: FnMut(usize, T) -> Result<(), E>
+{
+    assert!(worker_count >= 1 && max_pending >= 1);
+    let next_job = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+    thread::scope(|scope| {
+        // Each job holds a slot from when it starts until its result is handled
+        // Dropping slot_tx lets workers waiting for a slot exit, even when unwinding
+        let (slot_tx, slot_rx) = bounded::<()>(max_pending);
+        for _ in 0..max_pending {
+            slot_tx.send(()).unwrap();
+        }
+        let (result_tx, result_rx) = unbounded();
+        for _ in 0..worker_count {
+            let slot_rx = slot_rx.clone();
+            let result_tx = result_tx.clone();
+            let (job, next_job, stopped) = (&job, &next_job, &stopped);
+            scope.spawn(move || {
+                while slot_rx.recv().is_ok() && !stopped.load(Ordering::Relaxed) {
+                    let job_index = next_job.fetch_add(1, Ordering::Relaxed);
+                    if job_index >= job_count {
+                        break;
+                    }
+                    // Panics are passed on so that the calling thread never waits on them
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| job(job_index)));
+                    if result_tx.send((job_index, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(result_tx);
+        let mut pending = HashMap::new();
+        for next_index in 0..job_count {
+            while !pending.contains_key(&next_index) {
+                let (job_index, result) = result_rx.recv()
+                    .expect("every job sends a result");
+                pending.insert(job_index, result);
+            }
+            let result = match pending.remove(&next_index).unwrap() {
+                Ok(result) => result,
+                Err(panic_payload) => {
+                    stopped.store(true, Ordering::Relaxed);
+                    panic::resume_unwind(panic_payload);
+                }
+            };
+            if let Err(err) = handle_result(next_index, result) {
+                stopped.store(true, Ordering::Relaxed);
+                return Err(err);
+            }
+            // Workers may already have exited if there are no jobs left
+            let _ = slot_tx.send(());
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn results_handled_in_order() {
+        let mut handled = Vec::new();
+        // Later jobs finish first
+        let result: Result<(), ()> = run_ordered(20, 4, 8, |job_index| {
+            thread::sleep(Duration::from_millis(20 - job_index as u64));
+            job_index * 2
+        }, |job_index, result| {
+            handled.push((job_index, result));
+            Ok(())
+        });
+        assert_eq!(result, Ok(()));
+        assert_eq!(handled, (0..20).map(|i| (i, i * 2)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn error_stops_new_jobs() {
+        let started = AtomicUsize::new(0);
+        let result = run_ordered(100, 2, 2, |job_index| {
+            started.fetch_add(1, Ordering::Relaxed);
+            job_index
+        }, |job_index, _| match job_index {
+            3 => Err(job_index),
+            _ => Ok(())
+        });
+        assert_eq!(result, Err(3));
+        // Only jobs holding one of the 2 slots can start after the error
+        assert!(started.load(Ordering::Relaxed) <= 6);
+    }
+}
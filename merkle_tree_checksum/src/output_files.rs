@@ -0,0 +1,84 @@
+#![forbid(unsafe_code)]
+// Output files written by subcommands, which are removed again if writing them fails
+
+use crate::exit_codes::ExitCode;
+use crate::interrupt;
+
+use clap::ArgMatches;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+// Opened output files are removed if the program is interrupted
+pub(crate) fn open_output_file(path: &Path, overwrite: bool) -> std::io::Result<File> {
+    let file = create_output_file(path, overwrite)?;
+    interrupt::set_pending_output(Some(path.to_path_buf()));
+    Ok(file)
+}
+
+// Like open_output_file, for files that are not removed on interrupt
+pub(crate) fn create_output_file(path: &Path, overwrite: bool) -> std::io::Result<File> {
+    match overwrite {
+        true => OpenOptions::new().write(true).create(true)
+            .truncate(true).open(path),
+        false => OpenOptions::new().write(true)
+            .create_new(true).open(path)
+    }
+}
+
+// Hash file written to --output, or to stdout without it
+#[derive(Debug)]
+pub(crate) enum OutputWriter {
+    File(File),
+    Stdout(io::StdoutLock<'static>)
+}
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.write(buf),
+            Self::Stdout(stdout) => stdout.write(buf)
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::File(file) => file.flush(),
+            Self::Stdout(stdout) => stdout.flush()
+        }
+    }
+}
+
+// Runs write_fn on the --output file, or on stdout without --output,
+// for the subcommands that rewrite a hash file
+// Nothing else is written, so a failed output file is removed instead of left partial
+pub(crate) fn write_hash_output<F>(cmd_matches: &ArgMatches, write_fn: F) -> ExitCode
+where
+    F: FnOnce(OutputWriter) -> Result<(), ExitCode>
+{
+    let write_result = match cmd_matches.get_one::<String>("output") {
+        Some(output_name) => {
+            let output_file = match open_output_file(Path::new(output_name),
+                    cmd_matches.get_flag("overwrite")) {
+                Ok(file) => file,
+                Err(err) => {
+                    eprintln!("Error opening file {} for writing: {}",
+                        output_name, err);
+                    return ExitCode::GenWriteErr;
+                }
+            };
+            let write_result = write_fn(OutputWriter::File(output_file));
+            if write_result.is_err() {
+                if let Err(err) = fs::remove_file(output_name) {
+                    eprintln!("Error removing incomplete file {}: {}", output_name, err);
+                }
+            }
+            interrupt::set_pending_output(None);
+            write_result
+        },
+        None => write_fn(OutputWriter::Stdout(io::stdout().lock()))
+    };
+    match write_result {
+        Ok(()) => ExitCode::Success,
+        Err(exit_code) => exit_code
+    }
+}
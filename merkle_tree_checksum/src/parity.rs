@@ -0,0 +1,313 @@
+#![forbid(unsafe_code)]
+// Optional parity at the end of every line of a hash file, which locates and
+// corrects a single damaged byte in the line
+// The suffix is " ~", then the sum and position-weighted sum of the line bytes
+// modulo PARITY_MODULUS and the CRC32 of the line, each as 8 hex digits
+
+use crate::error_types::ParityErr;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+
+const PARITY_MARKER: &[u8] = b" ~";
+pub(crate) const PARITY_SUFFIX_LEN: usize = PARITY_MARKER.len() + 3*8;
+// Largest prime below 2^32, so every nonzero sum difference has an inverse
+const PARITY_MODULUS: u64 = 4_294_967_291;
+
+// Sum and weighted sum of line, with the first byte weighted by 1
+fn line_sums(line: &[u8]) -> (u64, u64) {
+    line.iter().enumerate().fold((0, 0), |(sum, weighted_sum), (i, byte)| {
+        let byte = u64::from(*byte);
+        ((sum + byte) % PARITY_MODULUS,
+            (weighted_sum + (i as u64 + 1) % PARITY_MODULUS * byte) % PARITY_MODULUS)
+    })
+}
+
+fn parity_suffix(line: &[u8]) -> Vec<u8> {
+    let (sum, weighted_sum) = line_sums(line);
+    let mut suffix = PARITY_MARKER.to_vec();
+    suffix.extend(format!("{:08x}{:08x}{:08x}", sum, weighted_sum,
+        crc32fast::hash(line)).into_bytes());
+    suffix
+}
+
+// Stored sum, weighted sum and CRC32 of a suffix, if it is well-formed
+fn parse_suffix(suffix: &[u8]) -> Option<(u64, u64, u32)> {
+    let digits = std::str::from_utf8(suffix.strip_prefix(PARITY_MARKER)?).ok()?;
+    if digits.len() != 24 || !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some((u64::from_str_radix(&digits[..8], 16).ok()?,
+        u64::from_str_radix(&digits[8..16], 16).ok()?,
+        u32::from_str_radix(&digits[16..], 16).ok()?))
+}
+
+// Splits a line without its line ending into its contents and parity suffix
+fn split_suffix(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    line.len().checked_sub(PARITY_SUFFIX_LEN)
+        .map(|content_len| line.split_at(content_len))
+}
+
+// Splits the line ending off a line, keeping a CR before the LF
+fn split_line_ending(line: &[u8]) -> (&[u8], &[u8]) {
+    let line_len = line.strip_suffix(b"\n")
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .unwrap_or(line).len();
+    line.split_at(line_len)
+}
+
+fn mod_pow(mut base: u64, mut exp: u64) -> u64 {
+    let mut result = 1;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result * base % PARITY_MODULUS;
+        }
+        base = base * base % PARITY_MODULUS;
+        exp >>= 1;
+    }
+    result
+}
+
+// Whether the first line of a hash file ends with parity
+// Neither the version line nor a JSON object can end this way
+pub(crate) fn has_parity(contents: &[u8]) -> bool {
+    let first_line = contents.split(|byte| *byte == b'\n').next().unwrap_or(contents);
+    let (first_line, _) = split_line_ending(first_line);
+    split_suffix(first_line)
+        .and_then(|(_, suffix)| parse_suffix(suffix))
+        .is_some()
+}
+
+// Contents of a hash file with parity, with every line checked and its parity left out
+pub(crate) fn strip_parity(contents: &[u8]) -> Result<Vec<u8>, ParityErr> {
+    let mut stripped = Vec::with_capacity(contents.len());
+    for (line_index, line) in contents.split_inclusive(|byte| *byte == b'\n').enumerate() {
+        let (line, line_ending) = split_line_ending(line);
+        let (content, suffix) = split_suffix(line)
+            .filter(|(_, suffix)| suffix.starts_with(PARITY_MARKER))
+            .ok_or(ParityErr::MissingParity(line_index + 1))?;
+        if suffix != parity_suffix(content) {
+            return Err(ParityErr::DamagedLine(line_index + 1));
+        }
+        stripped.extend_from_slice(content);
+        stripped.extend_from_slice(line_ending);
+    }
+    Ok(stripped)
+}
+
+// Line without its line ending and with a single damaged byte corrected,
+// or None if it has more damage than that
+fn repair_line(line: &[u8]) -> Option<Vec<u8>> {
+    let (content, suffix) = split_suffix(line)?;
+    let computed_suffix = parity_suffix(content);
+    let suffix_diff_count = suffix.iter().zip(computed_suffix.iter())
+        .filter(|(stored, computed)| stored != computed)
+        .count();
+    if suffix_diff_count <= 1 {
+        // The damage, if any, is in the suffix itself
+        return Some([content, &computed_suffix].concat());
+    }
+
+    let (stored_sum, stored_weighted_sum, stored_crc) = parse_suffix(suffix)?;
+    let (sum, weighted_sum) = line_sums(content);
+    // Changing the byte at index i by diff adds diff and (i+1)*diff to the sums
+    let diff = (sum + PARITY_MODULUS - stored_sum) % PARITY_MODULUS;
+    if diff == 0 {
+        return None;
+    }
+    let weighted_diff = (weighted_sum + PARITY_MODULUS - stored_weighted_sum) % PARITY_MODULUS;
+    let position = weighted_diff * mod_pow(diff, PARITY_MODULUS - 2) % PARITY_MODULUS;
+    let index = usize::try_from(position.checked_sub(1)?).ok()
+        .filter(|index| *index < content.len())?;
+    let damaged_byte = u64::from(content[index]);
+    let original_byte = if diff < 256 {
+        damaged_byte.checked_sub(diff)?
+    } else {
+        damaged_byte + (PARITY_MODULUS - diff)
+    };
+    let mut repaired = content.to_vec();
+    repaired[index] = u8::try_from(original_byte).ok()?;
+    // The CRC32 rules out guesses from damage to more than one byte
+    if crc32fast::hash(&repaired) != stored_crc {
+        return None;
+    }
+    let repaired_suffix = parity_suffix(&repaired);
+    repaired.extend_from_slice(&repaired_suffix);
+    Some(repaired)
+}
+
+// Contents of a hash file with parity, with single damaged bytes corrected,
+// and the 1-indexed line numbers that were corrected
+pub(crate) fn repair_parity(contents: &[u8]) -> Result<(Vec<u8>, Vec<usize>), ParityErr> {
+    let mut repaired = Vec::with_capacity(contents.len());
+    let mut repaired_lines: Vec<usize> = Vec::new();
+    for (line_index, line) in contents.split_inclusive(|byte| *byte == b'\n').enumerate() {
+        let (line, line_ending) = split_line_ending(line);
+        let repaired_line = repair_line(line)
+            .ok_or(ParityErr::UnrepairableLine(line_index + 1))?;
+        if repaired_line != line {
+            repaired_lines.push(line_index + 1);
+        }
+        repaired.extend_from_slice(&repaired_line);
+        repaired.extend_from_slice(line_ending);
+    }
+    Ok((repaired, repaired_lines))
+}
+
+// Hash file being read, with any parity checked and left out
+// Hash files with parity are read into memory, as every line is checked first,
+// as is the output of --hashes-from-command, which cannot be seeked
+#[derive(Debug)]
+pub(crate) enum HashFileSource {
+    File(File),
+    Buffered(Cursor<Vec<u8>>)
+}
+impl HashFileSource {
+    pub fn new(mut file: File) -> Result<Self, ParityErr> {
+        let mut first_line: Vec<u8> = Vec::new();
+        BufReader::new(&mut file).read_until(b'\n', &mut first_line)?;
+        file.seek(SeekFrom::Start(0))?;
+        if !has_parity(&first_line) {
+            return Ok(Self::File(file));
+        }
+        let mut contents: Vec<u8> = Vec::new();
+        file.read_to_end(&mut contents)?;
+        Ok(Self::Buffered(Cursor::new(strip_parity(&contents)?)))
+    }
+    // Same as new, for a hash file that was already read into memory
+    pub fn from_contents(contents: Vec<u8>) -> Result<Self, ParityErr> {
+        match has_parity(&contents) {
+            true => Ok(Self::Buffered(Cursor::new(strip_parity(&contents)?))),
+            false => Ok(Self::Buffered(Cursor::new(contents)))
+        }
+    }
+}
+impl Read for HashFileSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.read(buf),
+            Self::Buffered(cursor) => cursor.read(buf)
+        }
+    }
+}
+impl Seek for HashFileSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::File(file) => file.seek(pos),
+            Self::Buffered(cursor) => cursor.seek(pos)
+        }
+    }
+}
+
+// Appends parity to every line written through it
+// Lines are passed on once their newline is written
+pub(crate) struct ParityWriter<W: Write> {
+    writer: W,
+    line: Vec<u8>
+}
+impl<W: Write> ParityWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {writer, line: Vec::new()}
+    }
+}
+impl<W: Write> Write for ParityWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line_len = match buf.iter().position(|byte| *byte == b'\n') {
+            Some(newline_pos) => newline_pos + 1,
+            None => {
+                self.line.extend_from_slice(buf);
+                return Ok(buf.len());
+            }
+        };
+        self.line.extend_from_slice(&buf[..line_len - 1]);
+        let suffix = parity_suffix(&self.line);
+        self.line.extend_from_slice(&suffix);
+        self.line.push(b'\n');
+        self.writer.write_all(&self.line)?;
+        self.line.clear();
+        Ok(line_len)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+impl<W: Write> Drop for ParityWriter<W> {
+    // Hash files end with a newline, so this is only for unfinished lines
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            let suffix = parity_suffix(&self.line);
+            self.line.extend_from_slice(&suffix);
+            let _ = self.writer.write_all(&self.line);
+        }
+    }
+}
+impl<W: Write+fmt::Debug> fmt::Debug for ParityWriter<W> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ParityWriter")
+            .field("writer", &self.writer)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENTS: &[u8] = concat!("merkle_tree_checksum v0.9.0\n",
+        "Hashes:\n",
+        "  0 [0x00000000-0x00000000] [0x00000000-0x00000003] 0123abcd\n").as_bytes();
+
+    fn with_parity(contents: &[u8]) -> Vec<u8> {
+        let mut writer = ParityWriter::new(Vec::new());
+        writer.write_all(contents).unwrap();
+        std::mem::take(&mut writer.writer)
+    }
+
+    #[test]
+    fn parity_round_trip() {
+        let hash_file = with_parity(CONTENTS);
+        assert_eq!(hash_file.len(), CONTENTS.len() + 3*PARITY_SUFFIX_LEN);
+        assert!(has_parity(&hash_file));
+        assert!(!has_parity(CONTENTS));
+        assert_eq!(strip_parity(&hash_file).unwrap(), CONTENTS);
+        assert_eq!(strip_parity(CONTENTS).unwrap_err(), ParityErr::MissingParity(1));
+    }
+
+    #[test]
+    fn single_damaged_byte_repaired() {
+        let hash_file = with_parity(CONTENTS);
+        for damaged_index in 0..hash_file.len() {
+            if hash_file[damaged_index] == b'\n' {
+                continue;
+            }
+            for damaged_byte in [b'0', b'x', b' ', 0x00, 0xff] {
+                if hash_file[damaged_index] == damaged_byte {
+                    continue;
+                }
+                let mut damaged = hash_file.clone();
+                damaged[damaged_index] = damaged_byte;
+                assert!(strip_parity(&damaged).is_err());
+                let (repaired, repaired_lines) = repair_parity(&damaged).unwrap();
+                assert_eq!(repaired, hash_file, "byte {} not repaired", damaged_index);
+                assert_eq!(repaired_lines.len(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn undamaged_file_unchanged() {
+        let hash_file = with_parity(CONTENTS);
+        assert_eq!(repair_parity(&hash_file).unwrap(), (hash_file, Vec::new()));
+    }
+
+    #[test]
+    fn two_damaged_bytes_rejected() {
+        let mut damaged = with_parity(CONTENTS);
+        // Both in the Hashes: line
+        damaged[54] = b'X';
+        damaged[56] = b'Y';
+        assert_eq!(repair_parity(&damaged).unwrap_err(), ParityErr::UnrepairableLine(2));
+    }
+}
@@ -1,21 +1,31 @@
 #![forbid(unsafe_code)]
 
 use clap::crate_name;
+use const_format::formatcp;
 
 use semver::Version;
 
-use std::sync::{Arc, OnceLock};
-use cached::cached;
+use std::sync::OnceLock;
 
 use std::str::FromStr;
+use std::convert::TryFrom;
 use regex::Regex;
 use hex::FromHex;
 
 use merkle_tree::{BlockRange, HashData, HashRange, block_t};
 use crate::error_types::{FilenameExtractionError, HashExtractionError, HeaderParsingErr, SizeStrToNumErr};
+use crate::error_types::{PathListError, SaltStrToBytesErr};
+use crate::utils::StoredAndComputed;
+use std::io::{BufRead, Read};
 
 const QUOTED_STR_REGEX: &str = "(\"(?:[^\"]|\\\\\")*\")";
 const NEWLINE_REGEX: &str = "(?:\\n|\\r\\n)?";
+// Stricter than QUOTED_STR_REGEX so that the group separator is unambiguous
+const QUOTED_NAME_REGEX: &str = "\"(?:[^\"\\\\]|\\\\.)*\"";
+// One or more quoted strings joined by " + ", for concatenated entries
+const QUOTED_GROUP_REGEX: &str = formatcp!("({0}(?: \\+ {0})*)", QUOTED_NAME_REGEX);
+// Written by --mark-empty in place of the hash of a zero-length file
+pub(crate) const EMPTY_FILE_MARKER: &str = "EMPTY";
 
 static SIZE_REGEX: OnceLock<Regex> = OnceLock::new();
 #[inline]
@@ -26,69 +36,94 @@ fn get_size_regex() -> &'static Regex {
      * 0: entire thing
      * 1: nonzero integer
      * 2: decimal number
-     * 3: multiplier prefix
+     * 3: multiplier prefix, if any
      * 4: whether prefix is base-10 or base-2
+     * A trailing B for bytes is allowed after the prefix or the number
      */
     SIZE_REGEX.get_or_init(|| Regex::new(concat!(
         "^",
         "(?:([1-9][0-9]*)|([0-9]+\\.[0-9]+))",
-        "(K|M|G)(i)?",
+        "(?:(K|M|G)(i)?)?B?",
         "$")).unwrap())
 }
 static QUOTED_FILENAME_REGEX: OnceLock<Regex> = OnceLock::new();
 #[inline]
 fn get_quoted_filename_regex() -> &'static Regex {
-    let hash_regex = "(?:[[:xdigit:]][[:xdigit:]])+";
-    let length_regex = "0x([[:xdigit:]]+) bytes";
+    let hash_regex = formatcp!("(?:(?:[[:xdigit:]][[:xdigit:]])+|{})", EMPTY_FILE_MARKER);
+    let length_regex = "0x([[:xdigit:]]+) bytes(?: \\(0x([[:xdigit:]]+) hashed\\))?";
     /*
      * Capture groups:
      * 0: entire thing
      * 1: first branch of the |
-     * 2: quoted string for first branch
+     * 2: quoted string(s) for first branch
      * 3: second branch of the |
-     * 4: quoted string for the second branch
+     * 4: quoted string(s) for the second branch
      * 5: file length for the second branch
+     * 6: hashed length for the second branch, if trimmed
      */
     let combined_regex = format!("^(?:({0} +{1})|({1} {2})){3}$",
-        hash_regex, QUOTED_STR_REGEX, length_regex, NEWLINE_REGEX);
+        hash_regex, QUOTED_GROUP_REGEX, length_regex, NEWLINE_REGEX);
     QUOTED_FILENAME_REGEX.get_or_init(|| Regex::new(&combined_regex).unwrap())
 }
 
-pub(crate) fn size_str_to_num(input_str: &str) -> Result<block_t, SizeStrToNumErr> {
-    match input_str.parse::<block_t>() {
+static QUOTED_NAME_ONLY_REGEX: OnceLock<Regex> = OnceLock::new();
+#[inline]
+fn get_quoted_name_regex() -> &'static Regex {
+    QUOTED_NAME_ONLY_REGEX.get_or_init(|| Regex::new(QUOTED_NAME_REGEX).unwrap())
+}
+static EMPTY_DIR_REGEX: OnceLock<Regex> = OnceLock::new();
+#[inline]
+fn get_empty_dir_regex() -> &'static Regex {
+    /*
+     * Capture groups:
+     * 0: entire thing
+     * 1: quoted directory name
+     */
+    let combined_regex = format!("^{} empty directory{}$",
+        QUOTED_STR_REGEX, NEWLINE_REGEX);
+    EMPTY_DIR_REGEX.get_or_init(|| Regex::new(&combined_regex).unwrap())
+}
+
+// Byte counts such as region offsets, which can exceed the block size type
+pub(crate) fn byte_count_str_to_num(input_str: &str) -> Result<u64, SizeStrToNumErr> {
+    match input_str.parse::<u64>() {
         Ok(val) => Ok(val),
         Err(_) => {
             let number_parts_res = get_size_regex().captures(input_str);
             if let Some(captures) = number_parts_res {
                 debug_assert!(captures.len() == 5);
                 assert!(captures.get(1).is_some() ^ captures.get(2).is_some());
-                let base_mult: block_t = match captures.get(4) {
+                let base_mult: u64 = match captures.get(4) {
                     Some(_) => 1024,
                     None => 1000
                 };
-                let exponent = match &captures[3] {
-                    "K" => 1,
-                    "M" => 2,
-                    "G" => 3,
+                let exponent = match captures.get(3).map(|prefix| prefix.as_str()) {
+                    Some("K") => 1,
+                    Some("M") => 2,
+                    Some("G") => 3,
+                    // Fractions of a byte need a prefix to make a whole number
+                    None if captures.get(2).is_some() => return Err(SizeStrToNumErr::default()),
+                    None => 0,
                     _ => unreachable!()
                 };
                 let unit_mult = base_mult.checked_pow(exponent)
                     .ok_or(SizeStrToNumErr::default())?;
-                let final_val: block_t;
+                let final_val: u64;
                 if captures.get(1).is_some() {
-                    let text_val: block_t = captures[1].parse().unwrap();
+                    let text_val: u64 = captures[1].parse()
+                        .map_err(|_| SizeStrToNumErr::default())?;
                     final_val = unit_mult.checked_mul(text_val)
                         .ok_or(SizeStrToNumErr::default())?;
                 } else if captures.get(2).is_some() {
-                    // f64 can represent integers beyond u32 so no issue here
                     let mut text_val: f64 = captures[2].parse::<f64>().unwrap();
                     text_val *= unit_mult as f64;
                     assert!(text_val >= 0.0);
-                    if text_val > block_t::MAX.into() {
+                    // Rounding makes u64::MAX itself overflow the conversion below
+                    if text_val >= u64::MAX as f64 {
                         return Err(SizeStrToNumErr::default());
                     }
                     // Overflow was previously checked-for
-                    final_val = text_val.trunc() as block_t;
+                    final_val = text_val.trunc() as u64;
                 } else {
                     unreachable!();
                 }
@@ -100,17 +135,88 @@ pub(crate) fn size_str_to_num(input_str: &str) -> Result<block_t, SizeStrToNumEr
     }
 }
 
-// (String, Option<u64>) is (quoted_filename, file_len_if_present)
-pub(crate) fn extract_quoted_filename(line: &str) -> Result<(&str, Option<u64>), FilenameExtractionError> {
+pub(crate) fn size_str_to_num(input_str: &str) -> Result<block_t, SizeStrToNumErr> {
+    let size = byte_count_str_to_num(input_str)?;
+    block_t::try_from(size).map_err(|_| SizeStrToNumErr::default())
+}
+
+// An empty salt would silently give the same hashes as no salt
+pub(crate) fn salt_str_to_bytes(input_str: &str) -> Result<Vec<u8>, SaltStrToBytesErr> {
+    match hex::decode(input_str) {
+        Ok(salt) if !salt.is_empty() => Ok(salt),
+        _ => Err(SaltStrToBytesErr::default())
+    }
+}
+
+// (String, Option<u64>, Option<u64>) is
+// (quoted_filename, file_len_if_present, hashed_len_if_trimmed)
+pub(crate) fn extract_quoted_filename(line: &str) -> Result<(&str, Option<u64>, Option<u64>), FilenameExtractionError> {
     let line_portions = get_quoted_filename_regex().captures(line).ok_or(FilenameExtractionError::default())?;
-    debug_assert!(line_portions.len() == 6);
+    debug_assert!(line_portions.len() == 7);
     if line_portions.get(1).is_some() {
-        Ok((line_portions.get(2).unwrap().as_str(), None))
+        Ok((line_portions.get(2).unwrap().as_str(), None, None))
     } else {
         debug_assert!(line_portions.get(3).is_some());
-        Ok((line_portions.get(4).unwrap().as_str(),
-            Some(u64::from_str_radix(&line_portions[5], 16).unwrap())))
+        // Lengths can still be too long to fit in a u64
+        let hashed_len = line_portions.get(6)
+            .map(|len_str| u64::from_str_radix(len_str.as_str(), 16))
+            .transpose().map_err(|_| FilenameExtractionError::default())?;
+        let file_len = u64::from_str_radix(&line_portions[5], 16)
+            .map_err(|_| FilenameExtractionError::default())?;
+        Ok((line_portions.get(4).unwrap().as_str(), Some(file_len), hashed_len))
+    }
+}
+
+// Inverse of utils::quote_name_group
+// quoted_group must have been matched by QUOTED_GROUP_REGEX already
+pub(crate) fn unquote_name_group(quoted_group: &str) -> Result<Vec<String>, enquote::Error> {
+    get_quoted_name_regex().find_iter(quoted_group)
+        .map(|quoted_name| enquote::unquote(quoted_name.as_str()))
+        .collect()
+}
+
+// Paths in a --files0-from list, each terminated by a NUL byte
+// Paths are taken literally, so empty paths are the only ones skipped
+pub(crate) fn parse_nul_path_list<R: Read>(mut reader: R) -> Result<Vec<String>, PathListError> {
+    let mut list_bytes: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut list_bytes)?;
+    list_bytes.split(|byte| *byte == b'\0')
+        .filter(|path_bytes| !path_bytes.is_empty())
+        .enumerate()
+        .map(|(path_index, path_bytes)| String::from_utf8(path_bytes.to_vec())
+            .map_err(|_| PathListError::InvalidUtf8(path_index+1)))
+        .collect()
+}
+
+// Paths in @list arguments, one per line
+// Blank lines and lines starting with # are skipped
+// Lines starting with a quote are unquoted like names in hash files
+pub(crate) fn parse_path_list<R: BufRead>(reader: R) -> Result<Vec<String>, PathListError> {
+    let mut path_list = Vec::new();
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        let trimmed_line = line.trim_start();
+        if trimmed_line.is_empty() || trimmed_line.starts_with('#') {
+            continue;
+        }
+        if trimmed_line.starts_with('"') {
+            match enquote::unquote(trimmed_line.trim_end()) {
+                Ok(path) => path_list.push(path),
+                Err(_) => return Err(PathListError::MalformedName(line_index+1,
+                    line.to_owned()))
+            }
+        } else {
+            path_list.push(line.to_owned());
+        }
     }
+    Ok(path_list)
+}
+
+pub(crate) fn extract_empty_dir_name(line: &str) -> Result<&str, FilenameExtractionError> {
+    let line_portions = get_empty_dir_regex().captures(line).ok_or(FilenameExtractionError::default())?;
+    debug_assert!(line_portions.len() == 2);
+    Ok(line_portions.get(1).unwrap().as_str())
 }
 
 pub(crate) fn parse_version_line(version_line: &str)
@@ -150,96 +256,194 @@ pub(crate) fn parse_version_line(version_line: &str)
     }
 }
 
-// Using cache instead of once-cell for future flexibility
-cached!{
-    SHORT_REGEX_CACHE;
-    fn short_hash_regex(hex_digit_count: usize) -> Arc<Regex> = {
-        // hex_digits{count}  "(anything except quote | escaped quote)+" optional_newline
-        let hash_regex = format!("([[:xdigit:]]{{{}}})", hex_digit_count);
-        /*
-         * Capture groups:
-         * 0: entire thing
-         * 1: hexadecimal hash
-         * 2: quoted filename
-         */
-        let regex_str = format!("^{} +{}{}$",
-            hash_regex, QUOTED_STR_REGEX, NEWLINE_REGEX);
-        Arc::new(Regex::new(&regex_str).unwrap())
+// Hashes are matched with any length, so that a hash of the wrong length
+// can be reported as such rather than as a malformed entry
+fn entry_hash(hash_str: &str, hex_digit_count: usize) -> Result<HashData<64>, HashExtractionError> {
+    if hash_str.len() != hex_digit_count && hash_str.len().is_multiple_of(2) {
+        return Err(HashExtractionError::MismatchedHashLength(
+            StoredAndComputed::new(hash_str.len()/2, hex_digit_count/2)));
     }
+    HashData::from_hex(hash_str).map_err(|_| HashExtractionError::default())
 }
-pub(crate) fn extract_short_hash_parts(line: &str, hex_digit_count: usize) -> Result<(HashData<64>, &str), HashExtractionError> {
-    let parsing_regex = short_hash_regex(hex_digit_count);
-    let portions = parsing_regex.captures(line)
+
+static SHORT_HASH_REGEX: OnceLock<Regex> = OnceLock::new();
+#[inline]
+fn short_hash_regex() -> &'static Regex {
+    // hex_digits  "(anything except quote | escaped quote)+" optional_newline
+    /*
+     * Capture groups:
+     * 0: entire thing
+     * 1: hexadecimal hash, or the empty file marker
+     * 2: quoted filename(s)
+     */
+    let regex_str = formatcp!("^([[:xdigit:]]+|{}) +{}{}$",
+        EMPTY_FILE_MARKER, QUOTED_GROUP_REGEX, NEWLINE_REGEX);
+    SHORT_HASH_REGEX.get_or_init(|| Regex::new(regex_str).unwrap())
+}
+static SHORT_ENTRY_NAME_REGEX: OnceLock<Regex> = OnceLock::new();
+// Names at the end of a short hash entry, which can still be found when
+// the hash is malformed, so that only the file of that entry fails
+pub(crate) fn extract_short_entry_name(line: &str) -> Option<&str> {
+    let regex_str = formatcp!("^[^ \"]* +{}{}$", QUOTED_GROUP_REGEX, NEWLINE_REGEX);
+    let captures = SHORT_ENTRY_NAME_REGEX.get_or_init(|| Regex::new(regex_str).unwrap())
+        .captures(line)?;
+    Some(&line[captures.get(1).unwrap().range()])
+}
+// The hash is None for a file marked as empty
+pub(crate) fn extract_short_hash_parts(line: &str, hex_digit_count: usize) -> Result<(Option<HashData<64>>, &str), HashExtractionError> {
+    let portions = short_hash_regex().captures(line)
         .ok_or(HashExtractionError::default())?;
     debug_assert!(portions.len() == 3);
-    let hash_hex = HashData::from_hex(&portions[1])
-        .map_err(|_| HashExtractionError::default())?;
+    let hash_hex = match &portions[1] {
+        EMPTY_FILE_MARKER => None,
+        hash_str => Some(entry_hash(hash_str, hex_digit_count)?)
+    };
     let quoted_name = portions.get(2).unwrap();
     Ok((hash_hex, &line[quoted_name.range()]))
 }
 
-cached!{
-    LONG_REGEX_CACHE;
-    fn long_hash_regex(hex_digit_count: usize) -> Arc<Regex> = {
-        let file_id_regex = " *([[:digit:]]+)";
-        let blockrange_regex = "\\[0x([[:xdigit:]]+)-0x([[:xdigit:]]+)(\\]|\\))";
-        let hash_regex = format!("([[:xdigit:]]{{{}}})", hex_digit_count);
-        // rfile_id hexrange hexrange hex_digits{count} optional_newline
-        /*
-         * Capture groups:
-         * 0: entire thing
-         * 1: file id counter
-         * 2: start block, in hexadecimal
-         * 3: end block, in hexadecimal
-         * 4: whether the block range includes the end
-         * 5: start file byte, in hexadecimal
-         * 6: end file byte, in hexadecimal
-         * 7: whether the byte range includes the end
-         * 8: hexadecimal hash
-         */
-        let regex_str = format!("^{0} {1} {1} {2}{3}$",
-            file_id_regex, blockrange_regex, hash_regex, NEWLINE_REGEX);
-        Arc::new(Regex::new(&regex_str).unwrap())
-    }
+static LONG_HASH_REGEX: OnceLock<Regex> = OnceLock::new();
+#[inline]
+fn long_hash_regex() -> &'static Regex {
+    let file_id_regex = " *([[:digit:]]+)";
+    let blockrange_regex = "\\[0x([[:xdigit:]]+)-0x([[:xdigit:]]+)(\\]|\\))";
+    // rfile_id hexrange hexrange hex_digits optional_newline
+    /*
+     * Capture groups:
+     * 0: entire thing
+     * 1: file id counter
+     * 2: start block, in hexadecimal
+     * 3: end block, in hexadecimal
+     * 4: whether the block range includes the end
+     * 5: start file byte, in hexadecimal
+     * 6: end file byte, in hexadecimal
+     * 7: whether the byte range includes the end
+     * 8: hexadecimal hash
+     */
+    LONG_HASH_REGEX.get_or_init(|| {
+        let regex_str = format!("^{0} {1} {1} ([[:xdigit:]]+){2}$",
+            file_id_regex, blockrange_regex, NEWLINE_REGEX);
+        Regex::new(&regex_str).unwrap()
+    })
 }
-pub(crate) fn extract_long_hash_parts(line: &str, hex_digit_count: usize) -> Result<(usize, HashRange), HashExtractionError> {
-    let parsing_regex = long_hash_regex(hex_digit_count);
-    let portions = parsing_regex.captures(line)
-        .ok_or(HashExtractionError::default())?;
-    debug_assert!(portions.len() == 9);
-    // Use unwraps+panics as regex should ensure validity already
-    let file_id = usize::from_str(&portions[1]).unwrap();
-    let block_start = u64::from_str_radix(&portions[2], 16).unwrap();
-    let block_end = u64::from_str_radix(&portions[3], 16).unwrap();
-    let block_end_incl = match &portions[4] {
+// BlockRange::new panics on empty ranges, and BlockRange::range overflows
+// on an inclusive range ending at u64::MAX, neither of which is ever written
+fn entry_range(start: u64, end: u64, end_bracket: &str) -> Result<BlockRange, HashExtractionError> {
+    let include_end = match end_bracket {
         "]" => true,
         ")" => false,
         _ => unreachable!()
     };
-    let block_range = BlockRange::new(block_start, block_end, block_end_incl);
-
-    let byte_start = u64::from_str_radix(&portions[5], 16).unwrap();
-    let byte_end = u64::from_str_radix(&portions[6], 16).unwrap();
-    let byte_end_incl = match &portions[7] {
-        "]" => true,
-        ")" => false,
-        _ => unreachable!()
-    };
-    let byte_range = BlockRange::new(byte_start, byte_end, byte_end_incl);
+    match (include_end && end >= start && end < u64::MAX) || (!include_end && end > start) {
+        true => Ok(BlockRange::new(start, end, include_end)),
+        false => Err(HashExtractionError::default())
+    }
+}
+pub(crate) fn extract_long_hash_parts(line: &str, hex_digit_count: usize) -> Result<(usize, HashRange), HashExtractionError> {
+    let portions = long_hash_regex().captures(line)
+        .ok_or(HashExtractionError::default())?;
+    debug_assert!(portions.len() == 9);
+    // The regex only ensures that numbers are made of digits, not that they fit
+    let parse_hex = |hex_str: &str| u64::from_str_radix(hex_str, 16)
+        .map_err(|_| HashExtractionError::default());
+    let file_id = usize::from_str(&portions[1])
+        .map_err(|_| HashExtractionError::default())?;
+    let block_range = entry_range(parse_hex(&portions[2])?,
+        parse_hex(&portions[3])?, &portions[4])?;
+    let byte_range = entry_range(parse_hex(&portions[5])?,
+        parse_hex(&portions[6])?, &portions[7])?;
 
-    let hash_hex = HashData::from_hex(&portions[8]).map_err(|_| HashExtractionError::default())?;
+    let hash_hex = entry_hash(&portions[8], hex_digit_count)?;
 
     let hash_range = HashRange::new(block_range, byte_range, hash_hex);
     Ok((file_id, hash_range))
 }
 
+static METADATA_HASH_REGEX: OnceLock<Regex> = OnceLock::new();
+#[inline]
+fn metadata_hash_regex() -> &'static Regex {
+    /*
+     * Capture groups:
+     * 0: entire thing
+     * 1: file id counter
+     * 2: hexadecimal hash
+     */
+    let regex_str = formatcp!("^ *([[:digit:]]+) ([[:xdigit:]]+){}$", NEWLINE_REGEX);
+    METADATA_HASH_REGEX.get_or_init(|| Regex::new(regex_str).unwrap())
+}
+pub(crate) fn extract_metadata_hash_parts(line: &str, hex_digit_count: usize) -> Result<(usize, HashData<64>), HashExtractionError> {
+    let portions = metadata_hash_regex().captures(line)
+        .ok_or(HashExtractionError::default())?;
+    debug_assert!(portions.len() == 3);
+    let file_id = usize::from_str(&portions[1])
+        .map_err(|_| HashExtractionError::default())?;
+    let hash_hex = entry_hash(&portions[2], hex_digit_count)?;
+    Ok((file_id, hash_hex))
+}
+
+// Starts the entries of each file in hash files written with --sectioned
+pub(crate) const SECTION_MARKER_PREFIX: &str = "# file ";
+
+static SECTION_MARKER_REGEX: OnceLock<Regex> = OnceLock::new();
+// File index and names of a section marker line
+pub(crate) fn extract_section_marker(line: &str) -> Option<(usize, Vec<String>)> {
+    let regex_str = formatcp!("^{}([[:digit:]]+): {}{}$",
+        SECTION_MARKER_PREFIX, QUOTED_GROUP_REGEX, NEWLINE_REGEX);
+    let portions = SECTION_MARKER_REGEX.get_or_init(|| Regex::new(regex_str).unwrap())
+        .captures(line)?;
+    let file_index = usize::from_str(&portions[1]).ok()?;
+    let names = unquote_name_group(&portions[2]).ok()?;
+    Some((file_index, names))
+}
+// File index at the start of a long or metadata entry, without checking the rest of it
+pub(crate) fn entry_file_index(line: &str) -> Option<usize> {
+    line.trim_start_matches(' ').split(' ').next()
+        .and_then(|file_index| usize::from_str(file_index).ok())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn path_list_lines() {
+        let list = concat!("# comment\n",
+            "plain name\r\n",
+            "\n",
+            "   \n",
+            "\"quoted\\\" name\"\n",
+            "  # indented comment\n",
+            "last");
+        assert_eq!(parse_path_list(list.as_bytes()).unwrap(),
+            ["plain name", "quoted\" name", "last"]);
+        assert_eq!(parse_path_list("ok\n\"unterminated\n".as_bytes()),
+            Err(PathListError::MalformedName(2, String::from("\"unterminated"))));
+    }
+
+    #[test]
+    fn nul_path_list() {
+        let list = b"name with\nnewline\0\"quoted\"\0\0last";
+        assert_eq!(parse_nul_path_list(&list[..]).unwrap(),
+            ["name with\nnewline", "\"quoted\"", "last"]);
+        assert_eq!(parse_nul_path_list(&b"ok\0\xff\0"[..]),
+            Err(PathListError::InvalidUtf8(2)));
+    }
+
+    #[test]
+    fn size_str_byte_suffix() {
+        assert_eq!(size_str_to_num("4096B").unwrap(), 4096);
+        assert_eq!(size_str_to_num("4KiB").unwrap(), 4096);
+        assert_eq!(size_str_to_num("4KB").unwrap(), 4000);
+        assert_eq!(size_str_to_num("1.5KiB").unwrap(), 1536);
+        assert_eq!(size_str_to_num("4Ki").unwrap(), 4096);
+        assert!(size_str_to_num("1.5B").is_err());
+        assert!(size_str_to_num("4iB").is_err());
+        assert!(size_str_to_num("B").is_err());
+    }
+
     #[test]
     fn short_hash_regex_examples() {
-        let short_regex = short_hash_regex(8);
+        let short_regex = short_hash_regex();
         let captures_base = short_regex.captures("1f2e3d4c  \"filename_text\"\n").unwrap();
         assert_eq!(captures_base.len(), 3);
         assert_eq!(&captures_base[1], "1f2e3d4c");
@@ -249,11 +453,23 @@ mod tests {
         assert_eq!(captures_base.len(), 3);
         assert_eq!(&captures_w_quote[1], "5b6a7988");
         assert_eq!(&captures_w_quote[2], "\"filename with\\\" quotes\"");
+
+        let (hash, quoted_empty) = extract_short_hash_parts("EMPTY  \"empty\"\n", 8).unwrap();
+        assert_eq!(hash, None);
+        assert_eq!(quoted_empty, "\"empty\"");
+    }
+    #[test]
+    fn short_entry_name_of_malformed_hash() {
+        assert_eq!(extract_short_entry_name("1f2e3z4c  \"filename\"\n"), Some("\"filename\""));
+        assert_eq!(extract_short_entry_name("  \"a\" + \"b\"\r\n"), Some("\"a\" + \"b\""));
+        // Without a quoted name, the file of the entry is unknown
+        assert_eq!(extract_short_entry_name("1f2e3d4c  filename\n"), None);
+        assert_eq!(extract_short_entry_name("1f2e \"x\" \"filename\"\n"), None);
     }
 
     #[test]
     fn long_hash_regex_examples() {
-        let long_regex = long_hash_regex(4);
+        let long_regex = long_hash_regex();
         let captures_base = long_regex.captures("  1 [0x12-0x34] [0x56-0x78] 7f8a\n").unwrap();
         assert_eq!(captures_base.len(), 9);
         assert_eq!(&captures_base[1], "1");
@@ -265,4 +481,60 @@ mod tests {
         assert_eq!(&captures_base[7], "]");
         assert_eq!(&captures_base[8], "7f8a");
     }
+
+    #[test]
+    fn hash_length_mismatch() {
+        assert_eq!(extract_long_hash_parts("  1 [0x12-0x34] [0x56-0x78] 7f8a\n", 8).unwrap_err(),
+            HashExtractionError::MismatchedHashLength(StoredAndComputed::new(2, 4)));
+        assert_eq!(extract_short_hash_parts("1f2e3d4c5b  \"name\"\n", 8).unwrap_err(),
+            HashExtractionError::MismatchedHashLength(StoredAndComputed::new(5, 4)));
+        // An odd number of digits cannot be a hash of any length
+        assert_eq!(extract_long_hash_parts("  1 [0x12-0x34] [0x56-0x78] 7f8a9\n", 4).unwrap_err(),
+            HashExtractionError::Malformed);
+    }
+
+    #[test]
+    fn metadata_hash_examples() {
+        let (file_id, hash) = extract_metadata_hash_parts("  3 7f8a\n", 4).unwrap();
+        assert_eq!(file_id, 3);
+        assert_eq!(hash, HashData::try_new(&[0x7f, 0x8a]).unwrap());
+        assert_eq!(extract_metadata_hash_parts("  3 7f8a9b\n", 4).unwrap_err(),
+            HashExtractionError::MismatchedHashLength(StoredAndComputed::new(3, 2)));
+        assert!(extract_metadata_hash_parts(
+            "  1 [0x12-0x34] [0x56-0x78] 7f8a\n", 4).is_err());
+    }
+
+    #[test]
+    fn quoted_group_examples() {
+        let (quoted_group, len, hashed_len) = extract_quoted_filename(
+            "\"part.000\" + \"with \\\" + \\\" inside\" 0x20 bytes\n").unwrap();
+        assert_eq!(len, Some(0x20));
+        assert_eq!(hashed_len, None);
+        assert_eq!(unquote_name_group(quoted_group).unwrap(),
+            ["part.000", "with \" + \" inside"]);
+
+        let (_, quoted_single) = extract_short_hash_parts(
+            "1f2e3d4c  \"single\"\n", 8).unwrap();
+        assert_eq!(unquote_name_group(quoted_single).unwrap(), ["single"]);
+    }
+
+    #[test]
+    fn empty_dir_entry_examples() {
+        assert_eq!(extract_empty_dir_name("\"some dir\" empty directory\n").unwrap(),
+            "\"some dir\"");
+        assert!(extract_empty_dir_name("\"some_file\" 0x10 bytes\n").is_err());
+        assert!(extract_quoted_filename("\"some dir\" empty directory\n").is_err());
+    }
+
+    #[test]
+    fn section_marker_examples() {
+        assert_eq!(extract_section_marker("# file 12: \"a\" + \"b \\\"c\\\"\"\r\n"),
+            Some((12, vec!["a".to_owned(), "b \"c\"".to_owned()])));
+        assert_eq!(extract_section_marker("# file 1: unquoted\n"), None);
+        assert_eq!(extract_section_marker("# file x: \"a\"\n"), None);
+        assert_eq!(entry_file_index(
+            "  7 [0x00000000-0x00000000] [0x00000000-0x00000003] 01020304\n"), Some(7));
+        assert_eq!(entry_file_index("  3 7f8a\n"), Some(3));
+        assert_eq!(entry_file_index("# file 3: \"a\"\n"), None);
+    }
 }
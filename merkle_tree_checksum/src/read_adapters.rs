@@ -0,0 +1,728 @@
+#![forbid(unsafe_code)]
+
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::io::Result as IOResult;
+use std::io::{Error, ErrorKind};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use flate2::read::MultiGzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+use strum_macros::EnumString;
+
+use merkle_tree::seek_len;
+
+// Presents several readers as one logical stream, in the given order
+#[derive(Debug)]
+pub(crate) struct ConcatReader<R: Read+Seek> {
+    readers: Vec<R>,
+    // Starting offset of each reader within the logical stream
+    offsets: Vec<u64>,
+    lens: Vec<u64>,
+    pos: u64,
+    // (reader index, position within that reader) of the last read
+    // Saves a seek on sequential reads within the same reader
+    inner_pos: Option<(usize, u64)>
+}
+impl<R: Read+Seek> ConcatReader<R> {
+    pub fn new(mut readers: Vec<R>) -> Self {
+        let mut offsets = Vec::with_capacity(readers.len());
+        let mut lens = Vec::with_capacity(readers.len());
+        let mut total_len: u64 = 0;
+        for reader in readers.iter_mut() {
+            let reader_len = seek_len(reader);
+            offsets.push(total_len);
+            lens.push(reader_len);
+            total_len += reader_len;
+        }
+        Self {readers, offsets, lens, pos: 0, inner_pos: None}
+    }
+    #[inline]
+    pub fn len(&self) -> u64 {
+        match self.offsets.last() {
+            Some(last_offset) => last_offset + self.lens.last().unwrap(),
+            None => 0
+        }
+    }
+}
+impl<R: Read+Seek> Read for ConcatReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        if buf.is_empty() || self.pos >= self.len() {
+            return Ok(0);
+        }
+        // Last reader starting at or before pos, which skips empty readers
+        let reader_idx = self.offsets.partition_point(|&offset| offset <= self.pos) - 1;
+        let offset_in_reader = self.pos - self.offsets[reader_idx];
+        let remaining_in_reader = self.lens[reader_idx] - offset_in_reader;
+        debug_assert!(remaining_in_reader > 0);
+
+        let reader = &mut self.readers[reader_idx];
+        if self.inner_pos != Some((reader_idx, offset_in_reader)) {
+            reader.seek(SeekFrom::Start(offset_in_reader))?;
+        }
+        // Avoid reading past what this reader had when we computed its length
+        let read_len = match usize::try_from(remaining_in_reader) {
+            Ok(remaining) => buf.len().min(remaining),
+            Err(_) => buf.len()
+        };
+        let bytes_read = match reader.read(&mut buf[..read_len]) {
+            Ok(n) => n,
+            Err(e) => {
+                self.inner_pos = None;
+                return Err(e);
+            }
+        };
+        if bytes_read == 0 {
+            // The underlying reader shrank since we measured it
+            self.inner_pos = None;
+            return Err(Error::new(ErrorKind::UnexpectedEof,
+                "concatenated file shrank while reading"));
+        }
+        self.pos += bytes_read as u64;
+        self.inner_pos = Some((reader_idx, offset_in_reader + bytes_read as u64));
+        Ok(bytes_read)
+    }
+}
+impl<R: Read+Seek> Seek for ConcatReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset)
+        };
+        match new_pos {
+            Some(new_pos) => {
+                self.pos = new_pos;
+                Ok(new_pos)
+            },
+            None => Err(Error::new(ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position"))
+        }
+    }
+}
+
+// Presents only the first len bytes of a reader, e.g. to leave out a trailer
+// With an offset, presents the len bytes starting there instead
+#[derive(Debug)]
+pub(crate) struct TruncatedReader<R: Read+Seek> {
+    reader: R,
+    offset: u64,
+    len: u64,
+    pos: u64
+}
+impl<R: Read+Seek> TruncatedReader<R> {
+    pub fn new(reader: R, len: u64) -> IOResult<Self> {
+        Self::with_offset(reader, 0, len)
+    }
+    pub fn with_offset(mut reader: R, offset: u64, len: u64) -> IOResult<Self> {
+        reader.seek(SeekFrom::Start(offset))?;
+        Ok(Self {reader, offset, len, pos: 0})
+    }
+}
+impl<R: Read+Seek> Read for TruncatedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        if self.pos >= self.len {
+            return Ok(0);
+        }
+        let read_len = match usize::try_from(self.len - self.pos) {
+            Ok(remaining) => buf.len().min(remaining),
+            Err(_) => buf.len()
+        };
+        let bytes_read = self.reader.read(&mut buf[..read_len])?;
+        self.pos += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+impl<R: Read+Seek> Seek for TruncatedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset)
+        };
+        match new_pos {
+            Some(new_pos) => {
+                self.reader.seek(SeekFrom::Start(self.offset + new_pos))?;
+                self.pos = new_pos;
+                Ok(new_pos)
+            },
+            None => Err(Error::new(ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position"))
+        }
+    }
+}
+
+// Seekable view of a stream with a known length, such as decompressed data
+// The stream is only read forwards, so seeking back is limited to as many
+// bytes as the largest read so far, which covers rereading a short last block
+#[derive(Debug)]
+pub(crate) struct ForwardReader<R: Read> {
+    reader: R,
+    len: u64,
+    pos: u64,
+    // Bytes most recently read from reader, ending at reader_pos
+    history: Vec<u8>,
+    history_cap: usize,
+    reader_pos: u64
+}
+impl<R: Read> ForwardReader<R> {
+    pub fn new(reader: R, len: u64) -> Self {
+        Self {reader, len, pos: 0, history: Vec::new(), history_cap: 0, reader_pos: 0}
+    }
+    #[inline]
+    fn history_start(&self) -> u64 {
+        self.reader_pos - self.history.len() as u64
+    }
+}
+impl<R: Read> Read for ForwardReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+        self.history_cap = self.history_cap.max(buf.len());
+        if self.pos < self.reader_pos {
+            // Replay bytes that were read before seeking back
+            let history_offset = usize::try_from(self.pos - self.history_start()).unwrap();
+            let replay_len = buf.len().min(self.history.len() - history_offset);
+            buf[..replay_len].copy_from_slice(
+                &self.history[history_offset..history_offset+replay_len]);
+            self.pos += replay_len as u64;
+            return Ok(replay_len);
+        }
+        if self.pos > self.reader_pos {
+            let skip_len = self.pos - self.reader_pos;
+            let skipped_len = std::io::copy(&mut (&mut self.reader).take(skip_len),
+                &mut std::io::sink())?;
+            self.reader_pos += skipped_len;
+            self.history.clear();
+            if skipped_len < skip_len {
+                return Ok(0);
+            }
+        }
+        let bytes_read = self.reader.read(buf)?;
+        self.history.extend_from_slice(&buf[..bytes_read]);
+        if self.history.len() > self.history_cap {
+            self.history.drain(..self.history.len()-self.history_cap);
+        }
+        self.reader_pos += bytes_read as u64;
+        self.pos += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+impl<R: Read> Seek for ForwardReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset)
+        };
+        match new_pos {
+            Some(new_pos) if new_pos < self.history_start() => Err(Error::new(
+                ErrorKind::Unsupported, "unable to seek back in a stream")),
+            Some(new_pos) => {
+                // Bytes are skipped on the next read, so seeking to the end is cheap
+                self.pos = new_pos;
+                Ok(new_pos)
+            },
+            None => Err(Error::new(ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position"))
+        }
+    }
+}
+
+// Converts CRLF line endings to LF while reading, leaving lone CRs in place
+// A CR at the end of the buffered data is held back until the next byte is seen
+#[derive(Debug)]
+pub(crate) struct EolNormalizer<R: Read> {
+    reader: BufReader<R>,
+    pending_cr: bool
+}
+impl<R: Read> EolNormalizer<R> {
+    pub fn new(reader: R) -> Self {
+        Self {reader: BufReader::new(reader), pending_cr: false}
+    }
+}
+impl<R: Read> Read for EolNormalizer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        let mut out_len = 0;
+        while out_len < buf.len() {
+            let available = self.reader.fill_buf()?;
+            if available.is_empty() {
+                if self.pending_cr {
+                    buf[out_len] = b'\r';
+                    out_len += 1;
+                    self.pending_cr = false;
+                }
+                break;
+            }
+            if self.pending_cr {
+                self.pending_cr = false;
+                // The CR of a CRLF is dropped, and the LF is copied below
+                if available[0] != b'\n' {
+                    buf[out_len] = b'\r';
+                    out_len += 1;
+                    continue;
+                }
+            }
+            let chunk = &available[..available.len().min(buf.len() - out_len)];
+            let (copy_len, consume_len) = match chunk.iter().position(|&byte| byte == b'\r') {
+                Some(cr_pos) => {
+                    self.pending_cr = true;
+                    (cr_pos, cr_pos + 1)
+                },
+                None => (chunk.len(), chunk.len())
+            };
+            buf[out_len..out_len+copy_len].copy_from_slice(&chunk[..copy_len]);
+            out_len += copy_len;
+            self.reader.consume(consume_len);
+        }
+        Ok(out_len)
+    }
+}
+
+// Caps the average read rate by sleeping whenever reads get ahead of it
+// Without a limit, reads and seeks are passed through unchanged
+#[derive(Debug)]
+pub(crate) struct ThrottledReader<R: Read> {
+    reader: R,
+    bytes_per_sec: Option<u64>,
+    start: Instant,
+    bytes_read: u64
+}
+impl<R: Read> ThrottledReader<R> {
+    pub fn new(reader: R, bytes_per_sec: Option<u64>) -> Self {
+        Self {reader, bytes_per_sec, start: Instant::now(), bytes_read: 0}
+    }
+}
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        let bytes_read = self.reader.read(buf)?;
+        if let Some(bytes_per_sec) = self.bytes_per_sec {
+            self.bytes_read += bytes_read as u64;
+            let target_elapsed = Duration::from_secs_f64(
+                self.bytes_read as f64 / bytes_per_sec as f64);
+            if let Some(ahead) = target_elapsed.checked_sub(self.start.elapsed()) {
+                thread::sleep(ahead);
+            }
+        }
+        Ok(bytes_read)
+    }
+}
+impl<R: Read+Seek> Seek for ThrottledReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+        self.reader.seek(pos)
+    }
+}
+
+// Output of a shell command given a data file as its stdin, for --filter-command
+// The command has to exit successfully once its output is read to the end
+#[derive(Debug)]
+pub(crate) struct FilterOutput {
+    child: Child,
+    stdout: Option<ChildStdout>
+}
+impl FilterOutput {
+    pub fn spawn(command: &str, path: &Path) -> IOResult<Self> {
+        let mut child = Command::new("sh").arg("-c").arg(command)
+            .stdin(File::open(path)?)
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take();
+        Ok(Self {child, stdout})
+    }
+}
+impl Read for FilterOutput {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        let Some(stdout) = &mut self.stdout else {
+            return Ok(0);
+        };
+        let bytes_read = stdout.read(buf)?;
+        if bytes_read == 0 && !buf.is_empty() {
+            self.stdout = None;
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(Error::other(format!("filter command exited with {}", status)));
+            }
+        }
+        Ok(bytes_read)
+    }
+}
+impl Drop for FilterOutput {
+    fn drop(&mut self) {
+        // Commands whose output is not read to the end are stopped
+        if self.stdout.take().is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+// Decompression applied to data files before hashing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub(crate) enum Decompression {
+    None,
+    Gzip,
+    Zstd,
+    // Gzip or zstd for files with a .gz or .zst extension
+    // or starting with the corresponding magic bytes
+    Auto,
+    // Output of the shell command of --filter-command, given each file as stdin
+    #[strum(disabled)]
+    Filter(&'static str)
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+impl Decompression {
+    // Resolves Auto to the format of the given file, or None if it isn't compressed
+    fn resolve(self, path: &Path) -> IOResult<Self> {
+        if self != Self::Auto {
+            return Ok(self);
+        }
+        match path.extension() {
+            Some(ext) if ext == "gz" => return Ok(Self::Gzip),
+            Some(ext) if ext == "zst" => return Ok(Self::Zstd),
+            _ => {}
+        }
+        let mut magic_buf = Vec::with_capacity(ZSTD_MAGIC.len());
+        File::open(path)?.take(ZSTD_MAGIC.len() as u64).read_to_end(&mut magic_buf)?;
+        if magic_buf.starts_with(&GZIP_MAGIC) {
+            Ok(Self::Gzip)
+        } else if magic_buf.starts_with(&ZSTD_MAGIC) {
+            Ok(Self::Zstd)
+        } else {
+            Ok(Self::None)
+        }
+    }
+}
+
+// Zstd decoder over a data file, since the decoder itself doesn't implement Debug
+pub(crate) struct ZstdReader(ZstdDecoder<'static, BufReader<File>>);
+impl ZstdReader {
+    fn open(path: &Path) -> IOResult<Self> {
+        ZstdDecoder::new(File::open(path)?).map(Self)
+    }
+}
+impl std::fmt::Debug for ZstdReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZstdReader").finish_non_exhaustive()
+    }
+}
+impl Read for ZstdReader {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        self.0.read(buf)
+    }
+}
+
+// Data file as it gets hashed, which may be decompressed, filtered,
+// or have its line endings normalized while reading
+#[derive(Debug)]
+pub(crate) enum DataFile {
+    Plain(File),
+    Gzip(Box<ForwardReader<MultiGzDecoder<BufReader<File>>>>),
+    Zstd(Box<ForwardReader<ZstdReader>>),
+    Filtered(Box<ForwardReader<FilterOutput>>),
+    Normalized(Box<ForwardReader<EolNormalizer<DataFile>>>)
+}
+impl DataFile {
+    pub fn open(path: &Path, decompression: Decompression, normalize_eol: bool) -> IOResult<Self> {
+        if normalize_eol {
+            // Normalizing changes the length, so finding it also takes a separate pass
+            let mut len_normalizer = EolNormalizer::new(Self::open(path, decompression, false)?);
+            let len = std::io::copy(&mut len_normalizer, &mut std::io::sink())?;
+            let normalizer = EolNormalizer::new(Self::open(path, decompression, false)?);
+            return Ok(Self::Normalized(Box::new(ForwardReader::new(normalizer, len))));
+        }
+        if let Decompression::Filter(command) = decompression {
+            // The output can't seek either, so the command is run once for the length
+            let len = std::io::copy(&mut FilterOutput::spawn(command, path)?,
+                &mut std::io::sink())?;
+            let output = FilterOutput::spawn(command, path)?;
+            return Ok(Self::Filtered(Box::new(ForwardReader::new(output, len))));
+        }
+        // Decompressed streams can't seek, so finding the length takes a separate pass
+        match decompression.resolve(path)? {
+            Decompression::Gzip => {
+                let mut len_decoder = MultiGzDecoder::new(BufReader::new(File::open(path)?));
+                let len = std::io::copy(&mut len_decoder, &mut std::io::sink())?;
+                let decoder = MultiGzDecoder::new(BufReader::new(File::open(path)?));
+                Ok(Self::Gzip(Box::new(ForwardReader::new(decoder, len))))
+            },
+            Decompression::Zstd => {
+                let mut len_decoder = ZstdReader::open(path)?;
+                let len = std::io::copy(&mut len_decoder, &mut std::io::sink())?;
+                let decoder = ZstdReader::open(path)?;
+                Ok(Self::Zstd(Box::new(ForwardReader::new(decoder, len))))
+            },
+            _ => File::open(path).map(Self::Plain)
+        }
+    }
+}
+impl Read for DataFile {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        match self {
+            Self::Plain(file) => file.read(buf),
+            Self::Gzip(reader) => reader.read(buf),
+            Self::Zstd(reader) => reader.read(buf),
+            Self::Filtered(reader) => reader.read(buf),
+            Self::Normalized(reader) => reader.read(buf)
+        }
+    }
+}
+impl Seek for DataFile {
+    fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+        match self {
+            Self::Plain(file) => file.seek(pos),
+            Self::Gzip(reader) => reader.seek(pos),
+            Self::Zstd(reader) => reader.seek(pos),
+            Self::Filtered(reader) => reader.seek(pos),
+            Self::Normalized(reader) => reader.seek(pos)
+        }
+    }
+}
+
+// Total length of the given files as they get hashed
+pub(crate) fn data_len(paths: &[PathBuf], decompression: Decompression,
+        normalize_eol: bool) -> IOResult<u64> {
+    let mut total_len = 0;
+    for path in paths {
+        total_len += match (decompression, normalize_eol) {
+            (Decompression::None, false) => path.metadata()?.len(),
+            _ => seek_len(&mut DataFile::open(path, decompression, normalize_eol)?)
+        };
+    }
+    Ok(total_len)
+}
+
+// Chunk size for reading backwards, since trailing zero runs can be long
+const ZERO_SCAN_CHUNK_LEN: u64 = 64*1024;
+
+// Start of the run of zero bytes ending at len, read backwards from len
+// Returns len if the byte before len is nonzero, or 0 if all bytes are zero
+pub(crate) fn trailing_zeros_start<R: Read+Seek>(reader: &mut R, len: u64) -> IOResult<u64> {
+    let mut chunk_buf = vec![0x00; usize::try_from(ZERO_SCAN_CHUNK_LEN.min(len)).unwrap()];
+    let mut chunk_end = len;
+    while chunk_end > 0 {
+        let chunk_start = chunk_end.saturating_sub(ZERO_SCAN_CHUNK_LEN);
+        let chunk = &mut chunk_buf[..usize::try_from(chunk_end - chunk_start).unwrap()];
+        reader.seek(SeekFrom::Start(chunk_start))?;
+        reader.read_exact(chunk)?;
+        if let Some(last_nonzero) = chunk.iter().rposition(|&byte| byte != 0x00) {
+            return Ok(chunk_start + last_nonzero as u64 + 1);
+        }
+        chunk_end = chunk_start;
+    }
+    Ok(0)
+}
+
+// Length of the leading bytes that make up a quick fingerprint
+const FINGERPRINT_LEN: u64 = 4*1024;
+
+// Leading bytes of the data of the given files, to cheaply tell apart
+// most files of the same length before comparing them in full
+pub(crate) fn data_fingerprint(paths: &[PathBuf], decompression: Decompression,
+        normalize_eol: bool) -> IOResult<Vec<u8>> {
+    let mut part_files = Vec::with_capacity(paths.len());
+    for path in paths {
+        part_files.push(DataFile::open(path, decompression, normalize_eol)?);
+    }
+    let mut fingerprint = Vec::new();
+    ConcatReader::new(part_files).take(FINGERPRINT_LEN).read_to_end(&mut fingerprint)?;
+    Ok(fingerprint)
+}
+
+// Chunk size for comparing contents
+const COMPARE_CHUNK_LEN: u64 = 64*1024;
+
+// Whether both readers produce the same bytes, compared a chunk at a time
+pub(crate) fn contents_equal<A: Read, B: Read>(mut reader_a: A, mut reader_b: B) -> IOResult<bool> {
+    let mut chunk_a = Vec::new();
+    let mut chunk_b = Vec::new();
+    loop {
+        chunk_a.clear();
+        chunk_b.clear();
+        let len_a = reader_a.by_ref().take(COMPARE_CHUNK_LEN).read_to_end(&mut chunk_a)?;
+        reader_b.by_ref().take(COMPARE_CHUNK_LEN).read_to_end(&mut chunk_b)?;
+        if chunk_a != chunk_b {
+            return Ok(false);
+        }
+        if (len_a as u64) < COMPARE_CHUNK_LEN {
+            return Ok(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use merkle_tree::merkle_hash_file;
+    use sha2::Sha256;
+
+    #[derive(Default, Debug, Copy, Clone)]
+    struct ThrowawayConsumer {}
+    impl<T> merkle_tree::Consumer<T> for ThrowawayConsumer {
+        fn accept(&self, _val: T) -> Result<(), T> {
+            Ok(())
+        }
+    }
+
+    fn concat_of(parts: &[&'static [u8]]) -> ConcatReader<Cursor<&'static [u8]>> {
+        ConcatReader::new(parts.iter().map(|part| Cursor::new(*part)).collect())
+    }
+
+    #[test]
+    fn concat_read_all() {
+        let mut reader = concat_of(&[b"abcd", b"", b"ef", b"ghijk"]);
+        assert_eq!(reader.len(), 11);
+        let mut read_buf = Vec::new();
+        reader.read_to_end(&mut read_buf).unwrap();
+        assert_eq!(read_buf, b"abcdefghijk");
+    }
+
+    #[test]
+    fn concat_seek_across_parts() {
+        let mut reader = concat_of(&[b"abcd", b"ef", b"ghijk"]);
+        reader.seek(SeekFrom::Start(3)).unwrap();
+        let mut read_buf = [0x00; 4];
+        reader.read_exact(&mut read_buf).unwrap();
+        assert_eq!(&read_buf, b"defg");
+        assert_eq!(reader.seek(SeekFrom::End(-1)).unwrap(), 10);
+        assert_eq!(reader.seek(SeekFrom::Current(-4)).unwrap(), 6);
+        reader.read_exact(&mut read_buf).unwrap();
+        assert_eq!(&read_buf, b"ghij");
+    }
+
+    #[test]
+    fn truncated_hides_tail() {
+        let mut reader = TruncatedReader::new(Cursor::new(b"abcdefTRAILER"), 6)
+            .unwrap();
+        assert_eq!(seek_len(&mut reader), 6);
+        let mut read_buf = Vec::new();
+        reader.read_to_end(&mut read_buf).unwrap();
+        assert_eq!(read_buf, b"abcdef");
+        reader.seek(SeekFrom::End(-2)).unwrap();
+        read_buf.clear();
+        reader.read_to_end(&mut read_buf).unwrap();
+        assert_eq!(read_buf, b"ef");
+    }
+
+    #[test]
+    fn truncated_with_offset_shows_region() {
+        let mut reader = TruncatedReader::with_offset(Cursor::new(b"HEADERabcdefTRAILER"), 6, 6)
+            .unwrap();
+        assert_eq!(seek_len(&mut reader), 6);
+        let mut read_buf = Vec::new();
+        reader.read_to_end(&mut read_buf).unwrap();
+        assert_eq!(read_buf, b"abcdef");
+        assert_eq!(reader.seek(SeekFrom::Start(2)).unwrap(), 2);
+        read_buf.clear();
+        reader.read_to_end(&mut read_buf).unwrap();
+        assert_eq!(read_buf, b"cdef");
+    }
+
+    #[test]
+    fn trailing_zeros_across_chunks() {
+        let mut data = vec![0x00; 3*ZERO_SCAN_CHUNK_LEN as usize];
+        assert_eq!(trailing_zeros_start(&mut Cursor::new(&data), 100).unwrap(), 0);
+        data[5] = 0x01;
+        let data_len = data.len() as u64;
+        assert_eq!(trailing_zeros_start(&mut Cursor::new(&data), data_len).unwrap(), 6);
+        // Bytes at or past len are not read
+        assert_eq!(trailing_zeros_start(&mut Cursor::new(&data), 6).unwrap(), 6);
+        assert_eq!(trailing_zeros_start(&mut Cursor::new(&data), 5).unwrap(), 0);
+        assert_eq!(trailing_zeros_start(&mut Cursor::new(b"ab"), 0).unwrap(), 0);
+    }
+
+    #[test]
+    fn concat_hash_matches_joined() {
+        // Middle part is shorter than the block size
+        let parts: &[&'static [u8]] = &[b"abcdefg", b"hi", b"jklmnopqrs"];
+        let joined = parts.concat();
+
+        let concat_hash = merkle_hash_file::<_, Sha256, _>(concat_of(parts),
+            4, 2, ThrowawayConsumer::default(), 0).unwrap();
+        let joined_hash = merkle_hash_file::<_, Sha256, _>(Cursor::new(joined),
+            4, 2, ThrowawayConsumer::default(), 0).unwrap();
+        assert_eq!(concat_hash, joined_hash);
+    }
+
+    #[test]
+    fn forward_seek_back_within_last_read() {
+        let mut reader = ForwardReader::new(Cursor::new(b"abcdefghij"), 10);
+        assert_eq!(seek_len(&mut reader), 10);
+        let mut read_buf = [0x00; 4];
+        reader.read_exact(&mut read_buf).unwrap();
+        reader.read_exact(&mut read_buf).unwrap();
+        assert_eq!(&read_buf, b"efgh");
+        // Only the last 4 bytes can be read again
+        assert!(reader.seek(SeekFrom::Start(3)).is_err());
+        reader.seek(SeekFrom::Start(5)).unwrap();
+        let mut rest_buf = Vec::new();
+        reader.read_to_end(&mut rest_buf).unwrap();
+        assert_eq!(rest_buf, b"fghij");
+    }
+
+    #[test]
+    fn forward_seek_ahead_skips() {
+        let mut reader = ForwardReader::new(Cursor::new(b"abcdefghij"), 10);
+        reader.seek(SeekFrom::Current(6)).unwrap();
+        let mut rest_buf = Vec::new();
+        reader.read_to_end(&mut rest_buf).unwrap();
+        assert_eq!(rest_buf, b"ghij");
+    }
+
+    #[test]
+    fn forward_hash_matches_seekable() {
+        // Last block is short, so it gets read again after hitting the end
+        let data: Vec<u8> = (0..=100).collect();
+        let forward_hash = merkle_hash_file::<_, Sha256, _>(
+            ForwardReader::new(Cursor::new(&data), data.len() as u64),
+            8, 2, ThrowawayConsumer::default(), 0).unwrap();
+        let seekable_hash = merkle_hash_file::<_, Sha256, _>(Cursor::new(&data),
+            8, 2, ThrowawayConsumer::default(), 0).unwrap();
+        assert_eq!(forward_hash, seekable_hash);
+    }
+
+    #[test]
+    fn contents_compared_across_chunks() {
+        let data: Vec<u8> = (0..3*COMPARE_CHUNK_LEN).map(|i| (i % 251) as u8).collect();
+        assert!(contents_equal(Cursor::new(&data), Cursor::new(&data)).unwrap());
+        let mut changed = data.clone();
+        changed[2*COMPARE_CHUNK_LEN as usize + 1] ^= 0x01;
+        assert!(!contents_equal(Cursor::new(&data), Cursor::new(&changed)).unwrap());
+        assert!(!contents_equal(Cursor::new(&data), Cursor::new(&data[..data.len()-1])).unwrap());
+    }
+
+    #[test]
+    fn throttled_reads_take_minimum_time() {
+        let data = vec![0x5a; 1000];
+        let start = Instant::now();
+        let mut throttled = Vec::new();
+        ThrottledReader::new(Cursor::new(&data), Some(4000)).read_to_end(&mut throttled).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(250));
+        assert_eq!(throttled, data);
+    }
+
+    #[test]
+    fn eol_normalized_across_reads() {
+        let mut normalized = Vec::new();
+        EolNormalizer::new(Cursor::new(b"ab\r\ncd\rx\r\r\n\r")).read_to_end(&mut normalized).unwrap();
+        assert_eq!(normalized, b"ab\ncd\rx\r\n\r");
+        // One byte at a time, so that every CR ends a read
+        let mut reader = EolNormalizer::new(Cursor::new(b"a\r\nb\r\n"));
+        let mut byte_buf = [0x00; 1];
+        let mut bytewise = Vec::new();
+        while reader.read(&mut byte_buf).unwrap() == 1 {
+            bytewise.push(byte_buf[0]);
+        }
+        assert_eq!(bytewise, b"a\nb\n");
+    }
+}
@@ -0,0 +1,62 @@
+#![forbid(unsafe_code)]
+// The repair subcommand, which corrects hash files written with --with-parity
+
+use crate::error_types::ParityErr;
+use crate::exit_codes::ExitCode;
+use crate::parity;
+
+use clap::{Command, Arg, ArgAction};
+
+use std::fs;
+
+pub(crate) const REPAIR_CMD_NAME: &str = "repair";
+
+pub(crate) fn repair_command() -> Command {
+    Command::new(REPAIR_CMD_NAME)
+        .about("Correct damaged characters in a hash file with parity")
+        .long_about(concat!("Correct a single damaged character in each line ",
+            "of a hash file written with --with-parity, in place. ",
+            "The hash file is left unchanged if any line has more damage ",
+            "than that."))
+        .arg(Arg::new("FILE").required(true)
+            .action(ArgAction::Set)
+            .help("Hash file to repair"))
+}
+
+// Corrects single damaged characters in the lines of a hash file with parity
+pub(crate) fn repair_hash_file(file_name: &str, quiet_count: u8) -> ExitCode {
+    let hash_file_bytes = match fs::read(file_name) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Error opening hash file {}: {}", file_name, err);
+            return ExitCode::VerifReadErr;
+        }
+    };
+    let (repaired_bytes, repaired_lines) = match parity::repair_parity(&hash_file_bytes) {
+        Ok(repair) => repair,
+        Err(err) => {
+            // A damaged first line is repaired if the rest of the file has parity
+            let err = match parity::has_parity(&hash_file_bytes) {
+                true => err,
+                false => ParityErr::MissingParity(1)
+            };
+            eprintln!("Error checking hash file {}: {}", file_name, err);
+            return ExitCode::VerifBadHeaderErr;
+        }
+    };
+    if !repaired_lines.is_empty() {
+        if let Err(err) = fs::write(file_name, &repaired_bytes) {
+            eprintln!("Error writing hash file {}: {}", file_name, err);
+            return ExitCode::GenWriteErr;
+        }
+    }
+    if quiet_count < 2 {
+        for line_number in &repaired_lines {
+            eprintln!("Info: repaired line {}", line_number);
+        }
+        if repaired_lines.is_empty() {
+            eprintln!("Info: no damage found in {}", file_name);
+        }
+    }
+    ExitCode::Success
+}
@@ -0,0 +1,108 @@
+#![forbid(unsafe_code)]
+// Results of verify-hash --results, written as JSON Lines with one object per file
+// Each line is written as soon as its file is done, so that the lines
+// before an error that stops verification are still there to read
+
+use crate::error_types::VerificationError;
+use crate::jsonl::inclusive_end;
+
+use merkle_tree::BlockRange;
+
+use serde_json::json;
+use strum_macros::IntoStaticStr;
+
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::io::Result as IOResult;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoStaticStr)]
+#[strum(serialize_all = "lowercase")]
+pub(crate) enum VerifyOutcome {
+    Ok,
+    Failed,
+    Skipped,
+    // Skipped by --cache because it verified before and has not changed
+    Unchanged
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileResult {
+    outcome: VerifyOutcome,
+    error: Option<String>,
+    // Byte range of the first mismatched entry of a long hash file
+    mismatched_bytes: Option<BlockRange>,
+    // From --reference, in the same positions as mismatched_bytes
+    first_differing_byte: Option<u64>
+}
+impl FileResult {
+    pub fn new(outcome: VerifyOutcome) -> Self {
+        Self {outcome, error: None, mismatched_bytes: None, first_differing_byte: None}
+    }
+    // For errors found before hashing, which have no byte range
+    pub fn failed(error: String) -> Self {
+        Self {error: Some(error), ..Self::new(VerifyOutcome::Failed)}
+    }
+    // From comparing with --reference instead of hashing
+    pub fn differing(error: String, first_differing_byte: u64) -> Self {
+        Self {first_differing_byte: Some(first_differing_byte), ..Self::failed(error)}
+    }
+    pub fn mismatched(err: &VerificationError, first_differing_byte: Option<u64>) -> Self {
+        let mismatched_bytes = match err {
+            VerificationError::MismatchedHash(byte_range, _) => *byte_range,
+            _ => None
+        };
+        Self {mismatched_bytes, first_differing_byte, ..Self::failed(err.to_string())}
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ResultsFile(LineWriter<File>);
+impl ResultsFile {
+    pub fn new(file: File) -> Self {
+        Self(LineWriter::new(file))
+    }
+    pub fn record(&mut self, file_index: usize, paths: &[PathBuf],
+            result: &FileResult) -> IOResult<()> {
+        let names: Vec<_> = paths.iter().map(|path| path.to_string_lossy()).collect();
+        let outcome: &str = result.outcome.into();
+        let mut record = json!({
+            "file_index": file_index,
+            "names": names,
+            "outcome": outcome
+        });
+        if let Some(error) = &result.error {
+            record["error"] = json!(error);
+        }
+        if let Some(byte_range) = result.mismatched_bytes {
+            record["mismatched_byte_range"] = json!([byte_range.start(), inclusive_end(byte_range)]);
+        }
+        if let Some(differing_byte) = result.first_differing_byte {
+            record["first_differing_byte"] = json!(differing_byte);
+        }
+        writeln!(self.0, "{}", record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::StoredAndComputed;
+    use merkle_tree::HashData;
+
+    #[test]
+    fn mismatch_range_recorded() {
+        let err = VerificationError::MismatchedHash(Some(BlockRange::new(0x4, 0x7, true)),
+            StoredAndComputed::new(HashData::try_new(&[0x00]).unwrap(),
+                HashData::try_new(&[0x01]).unwrap()));
+        let result = FileResult::mismatched(&err, Some(0x5));
+        assert_eq!(result.outcome, VerifyOutcome::Failed);
+        assert_eq!(result.mismatched_bytes, Some(BlockRange::new(0x4, 0x7, true)));
+        assert_eq!(result.error.as_deref(), Some(err.to_string().as_str()));
+        // Short hash files do not say which bytes changed
+        let err = VerificationError::MismatchedHash(None,
+            StoredAndComputed::new(HashData::try_new(&[0x00]).unwrap(),
+                HashData::try_new(&[0x01]).unwrap()));
+        assert_eq!(FileResult::mismatched(&err, None).mismatched_bytes, None);
+    }
+}
@@ -0,0 +1,75 @@
+#![forbid(unsafe_code)]
+// Positions of the entries of each file in a long hash file, for verify-hash --any-order
+
+use crate::error_types::SectionIndexErr;
+use crate::parse_functions::extract_long_hash_parts;
+
+use std::io::{BufRead, Seek, SeekFrom};
+use std::ops::Range;
+
+// Byte range of the entries of each file, indexed by file index
+// Reads from the current position to the end, and leaves the reader where it started
+// The entries of a file can be anywhere, but must be next to each other
+pub(crate) fn index_sections<R: BufRead+Seek>(reader: &mut R, file_count: usize,
+        hex_digit_count: usize) -> Result<Vec<Range<u64>>, SectionIndexErr> {
+    let start_pos = reader.stream_position()?;
+    let mut sections: Vec<Option<Range<u64>>> = vec![None; file_count];
+    let mut current_index: Option<usize> = None;
+    // Positions come from the reader, since section markers are left out of the lines
+    let mut line_start = start_pos;
+    loop {
+        let mut line = String::new();
+        let line_len = reader.read_line(&mut line)?;
+        if line_len == 0 {
+            break;
+        }
+        let line_end = reader.stream_position()?;
+        let read_index = match extract_long_hash_parts(&line, hex_digit_count) {
+            Ok((read_index, _)) => read_index,
+            Err(_) => return Err(SectionIndexErr::MalformedEntry(line))
+        };
+        let section = sections.get_mut(read_index)
+            .ok_or(SectionIndexErr::UnknownFile(read_index))?;
+        match (section, current_index == Some(read_index)) {
+            (Some(range), true) => range.end = line_end,
+            (Some(_), false) => return Err(SectionIndexErr::SplitSection(read_index)),
+            (section, _) => *section = Some(line_start..line_end)
+        }
+        current_index = Some(read_index);
+        line_start = line_end;
+    }
+    reader.seek(SeekFrom::Start(start_pos))?;
+    sections.into_iter()
+        .enumerate()
+        .map(|(file_index, section)| section.ok_or(SectionIndexErr::MissingSection(file_index)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const FIRST_ENTRY: &str = "  0 [0x00000000-0x00000000] [0x00000000-0x00000003] 01020304\n";
+    const SECOND_ENTRY: &str = "  1 [0x00000000-0x00000000] [0x00000000-0x00000003] 05060708\n";
+
+    #[test]
+    fn reordered_sections_indexed() {
+        let hash_file = format!("{}{}{}", SECOND_ENTRY, SECOND_ENTRY, FIRST_ENTRY);
+        let mut reader = Cursor::new(hash_file.as_bytes());
+        let sections = index_sections(&mut reader, 2, 8).unwrap();
+        let entry_len = FIRST_ENTRY.len() as u64;
+        assert_eq!(sections, [2*entry_len..3*entry_len, 0..2*entry_len]);
+        assert_eq!(reader.position(), 0);
+    }
+    #[test]
+    fn bad_sections_rejected() {
+        let split = format!("{}{}{}", FIRST_ENTRY, SECOND_ENTRY, FIRST_ENTRY);
+        assert_eq!(index_sections(&mut Cursor::new(split.as_bytes()), 2, 8),
+            Err(SectionIndexErr::SplitSection(0)));
+        assert_eq!(index_sections(&mut Cursor::new(FIRST_ENTRY.as_bytes()), 2, 8),
+            Err(SectionIndexErr::MissingSection(1)));
+        assert_eq!(index_sections(&mut Cursor::new(SECOND_ENTRY.as_bytes()), 1, 8),
+            Err(SectionIndexErr::UnknownFile(1)));
+    }
+}
@@ -0,0 +1,246 @@
+#![forbid(unsafe_code)]
+// Leaves out the section marker lines of hash files written with --sectioned,
+// so that entries are read the same way as in other hash files
+
+use crate::format_version::FEATURES_PREFIX;
+use crate::parse_functions::{entry_file_index, extract_empty_dir_name};
+use crate::parse_functions::{extract_quoted_filename, extract_section_marker};
+use crate::parse_functions::unquote_name_group;
+
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+
+// Only markers naming a listed file and followed by an entry of that file are
+// left out, so other lines starting with the marker prefix are read as malformed entries
+// Hash files without markers are read straight from the inner reader
+#[derive(Debug)]
+pub(crate) struct SectionedReader<R: BufRead+Seek> {
+    reader: R,
+    // Position of reader, which is past next_line if there is one
+    reader_pos: u64,
+    line: Vec<u8>,
+    line_pos: usize,
+    // Position of the start of line
+    line_start: u64,
+    // Line read after a marker to check it, with its position
+    next_line: Option<(u64, Vec<u8>)>,
+    // Names of each listed file and the start of the entries,
+    // when the features line declares section markers
+    sections: Option<(Vec<Vec<String>>, u64)>
+}
+impl<R: BufRead+Seek> SectionedReader<R> {
+    // The header and file list are read up front, since readers may seek
+    // straight to the entries
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let reader_pos = reader.stream_position()?;
+        let sections = read_sections(&mut reader, reader_pos)?;
+        reader.seek(SeekFrom::Start(reader_pos))?;
+        Ok(SectionedReader {reader, reader_pos, line: Vec::new(), line_pos: 0,
+            line_start: reader_pos, next_line: None, sections})
+    }
+    // Passes markers through from here on, for lint to report on them itself
+    pub fn keep_markers(&mut self) {
+        self.sections = None;
+    }
+    fn position(&self) -> u64 {
+        match (self.line_pos < self.line.len(), &self.next_line) {
+            (true, _) => self.line_start + self.line_pos as u64,
+            (false, Some((next_start, _))) => *next_start,
+            (false, None) => self.reader_pos
+        }
+    }
+    fn read_raw_line(&mut self) -> io::Result<(u64, Vec<u8>)> {
+        let line_start = self.reader_pos;
+        let mut line: Vec<u8> = Vec::new();
+        self.reader.read_until(b'\n', &mut line)?;
+        self.reader_pos += line.len() as u64;
+        Ok((line_start, line))
+    }
+    fn is_passthrough(&self) -> bool {
+        self.sections.is_none() && self.line_pos == self.line.len() && self.next_line.is_none()
+    }
+    // File index of a marker line in the entries that starts the section of its file
+    fn marker_section(&self, line_start: u64, line: &[u8]) -> Option<usize> {
+        let (sections, entries_start) = self.sections.as_ref()?;
+        if line_start < *entries_start {
+            return None;
+        }
+        let (file_index, names) = extract_section_marker(std::str::from_utf8(line).ok()?)?;
+        (sections.get(file_index) == Some(&names)).then_some(file_index)
+    }
+}
+
+// Names of each listed file and the start of the entries, for hash files
+// that declare section markers
+// Invalid UTF-8 is left for the header parser to report
+fn read_sections<R: BufRead>(reader: &mut R, start_pos: u64)
+        -> io::Result<Option<(Vec<Vec<String>>, u64)>> {
+    let mut declares_markers = false;
+    let mut in_file_list = false;
+    let mut sections: Vec<Vec<String>> = Vec::new();
+    let mut entries_start = start_pos;
+    loop {
+        let mut line_bytes: Vec<u8> = Vec::new();
+        let line_len = reader.read_until(b'\n', &mut line_bytes)?;
+        entries_start += line_len as u64;
+        let line = String::from_utf8_lossy(&line_bytes);
+        let trimmed_line = line.trim_end_matches(['\n', '\r']);
+        match trimmed_line {
+            // JSON Lines hash files have no markers
+            _ if line_len == 0 || line.starts_with('{') => return Ok(None),
+            "Hashes:" => return Ok(declares_markers.then_some((sections, entries_start))),
+            // The features line comes before the file list
+            "Files:" if !declares_markers => return Ok(None),
+            "Files:" => in_file_list = true,
+            _ if in_file_list => {
+                if extract_empty_dir_name(&line).is_ok() {
+                    continue;
+                }
+                // Malformed names still take up an index, but match no marker
+                let names = extract_quoted_filename(&line).ok()
+                    .and_then(|(quoted_name, _, _)| unquote_name_group(quoted_name).ok())
+                    .unwrap_or_default();
+                sections.push(names);
+            },
+            _ => {
+                declares_markers |= trimmed_line.strip_prefix(FEATURES_PREFIX)
+                    .is_some_and(|features| {
+                        features.split(", ").any(|feature| feature == "sectioned")
+                    });
+            }
+        }
+    }
+}
+impl<R: BufRead+Seek> Read for SectionedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read_len = available.len().min(buf.len());
+        buf[..read_len].copy_from_slice(&available[..read_len]);
+        self.consume(read_len);
+        Ok(read_len)
+    }
+}
+impl<R: BufRead+Seek> BufRead for SectionedReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.is_passthrough() {
+            return self.reader.fill_buf();
+        }
+        if self.line_pos == self.line.len() {
+            let (mut line_start, mut line) = match self.next_line.take() {
+                Some(next_line) => next_line,
+                None => self.read_raw_line()?
+            };
+            if let Some(file_index) = self.marker_section(line_start, &line) {
+                let (next_start, next_line) = self.read_raw_line()?;
+                let next_index = std::str::from_utf8(&next_line).ok()
+                    .and_then(entry_file_index);
+                match next_index == Some(file_index) {
+                    true => (line_start, line) = (next_start, next_line),
+                    false => self.next_line = Some((next_start, next_line))
+                }
+            }
+            self.line = line;
+            self.line_start = line_start;
+            self.line_pos = 0;
+        }
+        Ok(&self.line[self.line_pos..])
+    }
+    fn consume(&mut self, amt: usize) {
+        match self.is_passthrough() {
+            true => {
+                self.reader.consume(amt);
+                self.reader_pos += amt as u64;
+            },
+            false => self.line_pos += amt
+        }
+    }
+}
+impl<R: BufRead+Seek> Seek for SectionedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Current(0) => return Ok(self.position()),
+            SeekFrom::Current(offset) => match self.position().checked_add_signed(offset) {
+                Some(new_pos) => SeekFrom::Start(new_pos),
+                None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    "invalid seek to a negative or overflowing position"))
+            },
+            pos => pos
+        };
+        self.line.clear();
+        self.line_pos = 0;
+        self.next_line = None;
+        self.reader_pos = self.reader.seek(pos)?;
+        Ok(self.reader_pos)
+    }
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.position())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const HEADER: &str = concat!("merkle_tree_checksum v0.10.0\n",
+        "Features: sectioned\n",
+        "Hash function: crc32\n",
+        "Block size: 4\n",
+        "Branching factor: 2\n",
+        "Files:\n",
+        "\"a\" 0x4 bytes\n",
+        "\"b\" 0x4 bytes\n",
+        "Hashes:\n");
+    const FIRST_ENTRY: &str = "  0 [0x00000000-0x00000000] [0x00000000-0x00000003] 01020304\n";
+    const SECOND_ENTRY: &str = "  1 [0x00000000-0x00000000] [0x00000000-0x00000003] 05060708\n";
+
+    fn read_all(hash_file: &str) -> String {
+        let mut read_text = String::new();
+        SectionedReader::new(Cursor::new(hash_file)).unwrap()
+            .read_to_string(&mut read_text).unwrap();
+        read_text
+    }
+
+    #[test]
+    fn markers_left_out() {
+        let hash_file = format!("{}# file 0: \"a\"\n{}# file 1: \"b\"\n{}",
+            HEADER, FIRST_ENTRY, SECOND_ENTRY);
+        assert_eq!(read_all(&hash_file), format!("{}{}{}", HEADER, FIRST_ENTRY, SECOND_ENTRY));
+
+        // Positions are those of the hash file, and skip over the markers
+        let mut reader = SectionedReader::new(Cursor::new(hash_file.as_str())).unwrap();
+        let mut line = String::new();
+        while line != "Hashes:\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, FIRST_ENTRY);
+        let second_marker_start = HEADER.len() + "# file 0: \"a\"\n".len() + FIRST_ENTRY.len();
+        assert_eq!(reader.stream_position().unwrap(), second_marker_start as u64);
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, SECOND_ENTRY);
+        reader.seek_relative(-(SECOND_ENTRY.len() as i64)).unwrap();
+        assert_eq!(reader.stream_position().unwrap(),
+            (hash_file.len() - SECOND_ENTRY.len()) as u64);
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, SECOND_ENTRY);
+    }
+    #[test]
+    fn bad_markers_kept() {
+        // Markers of the wrong file, for the wrong name, or not followed by
+        // their entries are read as lines to be reported as malformed
+        for marker in ["# file 1: \"a\"\n", "# file 0: \"b\"\n", "# file 2: \"c\"\n"] {
+            let hash_file = format!("{}{}{}", HEADER, marker, FIRST_ENTRY);
+            assert_eq!(read_all(&hash_file), hash_file);
+        }
+        let hash_file = format!("{}# file 0: \"a\"\n{}", HEADER, SECOND_ENTRY);
+        assert_eq!(read_all(&hash_file), hash_file);
+        // Hash files need to declare markers for them to be left out
+        let undeclared = format!("{}# file 0: \"a\"\n{}", HEADER, FIRST_ENTRY)
+            .replace("Features: sectioned\n", "");
+        assert_eq!(read_all(&undeclared), undeclared);
+    }
+}
@@ -0,0 +1,280 @@
+#![forbid(unsafe_code)]
+// Checks stored parent hashes against stored child hashes, without reading the hashed files
+
+use crate::error_types::VerificationError;
+use crate::hash_enum::HashFunctions;
+use crate::parse_functions::extract_long_hash_parts;
+use crate::utils::{StoredAndComputed, TreeParams};
+
+use merkle_tree::{internal_node_input, branch_t, BlockRange, HashData, NodeEncoding};
+
+use std::io::BufRead;
+
+// Mirrors the padding subtrees hashed by merkle_tree with pad_tree
+fn padding_hash(hash_function: HashFunctions, block_interval: u64,
+        branch: branch_t, node_encoding: NodeEncoding, salt: &[u8]) -> HashData<64> {
+    if block_interval == 1 {
+        let mut leaf_input = vec![0x00];
+        leaf_input.extend_from_slice(salt);
+        hash_function.digest(&leaf_input)
+    } else {
+        let child_hash = padding_hash(hash_function,
+            block_interval / u64::from(branch), branch, node_encoding, salt);
+        let hash_input = internal_node_input(node_encoding,
+            (0..branch).map(|_| (0, child_hash.as_ref())));
+        hash_function.digest(&hash_input)
+    }
+}
+
+// Stored node awaiting its parent: block range, byte length, and hash
+type StackEntry = (BlockRange, u64, HashData<64>);
+
+// Checks that children are the consecutive subranges of a parent, then hashes them
+// children should be nonempty and in order
+#[allow(clippy::result_large_err)]
+fn parent_hash(parent_range: BlockRange, children: &[StackEntry],
+        tree_params: &TreeParams, salt: &[u8]) -> Result<HashData<64>, VerificationError> {
+    let hash_function = tree_params.hash_function;
+    let branch = tree_params.branch_factor;
+    let block_increment = parent_range.range() / u64::from(branch);
+    for (child_index, child) in children.iter().enumerate() {
+        let child_start = parent_range.start() + block_increment*child_index as u64;
+        let expected_range = BlockRange::new(child_start,
+            child_start + block_increment, false);
+        if child.0 != expected_range {
+            return Err(VerificationError::MismatchedBlockRange(
+                StoredAndComputed::new(child.0, expected_range)));
+        }
+    }
+
+    // A lone child is promoted in place of its parent
+    if tree_params.unbalanced && children.len() == 1 {
+        return Ok(children[0].2.clone());
+    }
+    let mut child_hashes: Vec<(u64, HashData<64>)> = children.iter()
+        .map(|(_, child_len, child_hash)| (*child_len, child_hash.clone()))
+        .collect();
+    if tree_params.duplicate_last {
+        // Repeated children do not cover any bytes
+        let last_child_hash = children.last().unwrap().2.clone();
+        child_hashes.resize(usize::from(branch), (0, last_child_hash));
+    } else if tree_params.pad_tree {
+        let child_padding = padding_hash(hash_function, block_increment,
+            branch, tree_params.node_encoding, salt);
+        child_hashes.resize(usize::from(branch), (0, child_padding));
+    }
+    let hash_input = internal_node_input(tree_params.node_encoding,
+        child_hashes.iter()
+            .map(|(child_len, child_hash)| (*child_len, child_hash.as_ref())));
+    Ok(hash_function.digest(&hash_input))
+}
+
+// Rebuilds the intermediate nodes left out with leaves_and_root, up to the root
+#[allow(clippy::result_large_err)]
+fn fold_leaves(root_range: BlockRange, leaves: Vec<StackEntry>,
+        tree_params: &TreeParams, salt: &[u8]) -> Result<HashData<64>, VerificationError> {
+    if leaves.is_empty() {
+        return Err(VerificationError::MissingChildEntries(root_range));
+    }
+    let branch = u64::from(tree_params.branch_factor);
+    let mut level = leaves;
+    let mut block_increment: u64 = 1;
+    while block_increment < root_range.range() {
+        let parent_len = block_increment*branch;
+        let mut parents: Vec<StackEntry> = Vec::with_capacity(level.len().div_ceil(branch as usize));
+        let mut children_start = 0;
+        while children_start < level.len() {
+            // Children of a parent share the same start rounded down to its length
+            let parent_start = (level[children_start].0.start() / parent_len) * parent_len;
+            let children_end = level[children_start..].iter()
+                .position(|child| child.0.start() >= parent_start + parent_len)
+                .map_or(level.len(), |offset| children_start + offset);
+            let children = &level[children_start..children_end];
+            let parent_range = BlockRange::new(parent_start, parent_start + parent_len, false);
+            let parent_hash = parent_hash(parent_range, children, tree_params, salt)?;
+            let parent_byte_len = children.iter().map(|child| child.1).sum();
+            parents.push((parent_range, parent_byte_len, parent_hash));
+            children_start = children_end;
+        }
+        level = parents;
+        block_increment = parent_len;
+    }
+    if level.len() != 1 || level[0].0 != root_range {
+        return Err(VerificationError::MismatchedBlockRange(
+            StoredAndComputed::new(level[level.len()-1].0, root_range)));
+    }
+    Ok(level.swap_remove(0).2)
+}
+
+// reader should be positioned just after the Files: line
+// Returns the first inconsistency, paired with its 1-indexed line number
+pub(crate) fn check_hash_list<R: BufRead>(mut reader: R, header_line_count: usize,
+        tree_params: &TreeParams, salt: &[u8]) -> Option<(usize, VerificationError)> {
+    let hash_function = tree_params.hash_function;
+    let branch = tree_params.branch_factor;
+    let hex_digit_count = 2*hash_function.hash_len();
+    let mut line_number = header_line_count;
+
+    macro_rules! next_line {
+        () => {{
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) => None,
+                Ok(_) => {
+                    line_number += 1;
+                    Some(line)
+                },
+                Err(_) => return Some((line_number+1, VerificationError::UnexpectedEof))
+            }
+        }}
+    }
+
+    // Skip over the file list
+    loop {
+        match next_line!() {
+            Some(line) if line == "Hashes:\n" || line == "Hashes:\r\n" => break,
+            Some(_) => {},
+            None => return Some((line_number, VerificationError::UnexpectedEof))
+        }
+    }
+
+    let mut current_file_id: Option<usize> = None;
+    let mut stack: Vec<StackEntry> = Vec::new();
+    while let Some(line) = next_line!() {
+        let (file_id, hash_range) = match extract_long_hash_parts(&line, hex_digit_count) {
+            Ok(parts) => parts,
+            Err(err) => return Some((line_number, err.into_verification_error(line)))
+        };
+        if current_file_id != Some(file_id) {
+            current_file_id = Some(file_id);
+            stack.clear();
+        }
+        let block_range = hash_range.block_range();
+        let byte_range = hash_range.byte_range();
+        let stored_hash = HashData::try_new(hash_range.hash_result()).unwrap();
+        // Leaves can only be checked against the hashed data
+        if block_range.range() == 1 {
+            stack.push((block_range, byte_range.range(), stored_hash));
+            continue;
+        }
+
+        let computed_result = if tree_params.leaves_and_root {
+            // The only internal node listed is the root, right after all leaves
+            fold_leaves(block_range, std::mem::take(&mut stack), tree_params, salt)
+        } else {
+            // Post-order puts the children of a node right before it
+            let mut children: Vec<StackEntry> = Vec::with_capacity(branch.into());
+            while children.len() < usize::from(branch)
+                    && stack.last().is_some_and(|top| top.0.start() >= block_range.start()) {
+                children.push(stack.pop().unwrap());
+            }
+            children.reverse();
+            if children.is_empty() {
+                return Some((line_number, VerificationError::MissingChildEntries(block_range)));
+            }
+            parent_hash(block_range, &children, tree_params, salt)
+        };
+        let computed_hash = match computed_result {
+            Ok(hash) => hash,
+            Err(err) => return Some((line_number, err))
+        };
+        if computed_hash != stored_hash {
+            return Some((line_number, VerificationError::MismatchedParentHash(block_range,
+                StoredAndComputed::new(stored_hash, computed_hash))));
+        }
+        stack.push((block_range, byte_range.range(), stored_hash));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TREE_PARAMS: TreeParams = TreeParams {
+        block_size: 4,
+        branch_factor: 2,
+        hash_function: HashFunctions::crc32,
+        node_encoding: NodeEncoding::Plain,
+        salted: false,
+        pad_tree: false,
+        unbalanced: false,
+        duplicate_last: false,
+        metadata_only: false,
+        trim_zeros: false,
+        region_offset: None,
+        absolute_ranges: false,
+        leaves_and_root: false,
+        content_defined: false,
+        normalize_eol: false,
+        relative_names: false,
+        nominal_ranges: false,
+        stride: None,
+        filtered: false
+    };
+
+    // "abcd1234ef" with block size 4 and branch factor 2
+    fn valid_list() -> String {
+        let hash = |data: &[u8]| TREE_PARAMS.hash_function.digest(data);
+        let leaves = [hash(b"\x00abcd"), hash(b"\x001234"), hash(b"\x00ef")];
+        let node_01 = hash(&[&[0x01][..], leaves[0].as_ref(), leaves[1].as_ref()].concat());
+        let node_23 = hash(&[&[0x01][..], leaves[2].as_ref()].concat());
+        let root = hash(&[&[0x01][..], node_01.as_ref(), node_23.as_ref()].concat());
+        format!(concat!(
+            "\"ten_bytes\" 0xa bytes\n",
+            "Hashes:\n",
+            "  0 [0x00000000-0x00000000] [0x00000000-0x00000003] {}\n",
+            "  0 [0x00000001-0x00000001] [0x00000004-0x00000007] {}\n",
+            "  0 [0x00000000-0x00000002) [0x00000000-0x00000007] {}\n",
+            "  0 [0x00000002-0x00000002] [0x00000008-0x00000009] {}\n",
+            "  0 [0x00000002-0x00000004) [0x00000008-0x00000009] {}\n",
+            "  0 [0x00000000-0x00000004) [0x00000000-0x00000009] {}\n"),
+            hex::encode(&leaves[0]), hex::encode(&leaves[1]), hex::encode(&node_01),
+            hex::encode(&leaves[2]), hex::encode(&node_23), hex::encode(&root))
+    }
+
+    fn check_str(list: &str) -> Option<(usize, VerificationError)> {
+        check_hash_list(list.as_bytes(), 5, &TREE_PARAMS, &[])
+    }
+
+    #[test]
+    fn self_consistent_list() {
+        assert_eq!(check_str(&valid_list()), None);
+    }
+    #[test]
+    fn edited_leaf_detected() {
+        let valid = valid_list();
+        let mut lines: Vec<String> = valid.lines().map(String::from).collect();
+        lines[3] = format!("{}00000000", &lines[3][..lines[3].len()-8]);
+        let report = check_str(&(lines.join("\n") + "\n"));
+        // The parent of the edited leaf is reported
+        assert!(matches!(report, Some((10, VerificationError::MismatchedParentHash(range, _)))
+            if range == BlockRange::new(0, 2, false)));
+    }
+    #[test]
+    fn leaves_folded_into_root() {
+        let tree_params = TreeParams {leaves_and_root: true, ..TREE_PARAMS};
+        let valid = valid_list();
+        let mut lines: Vec<String> = valid.lines().map(String::from).collect();
+        // Leave out both intermediate nodes
+        lines.remove(6);
+        lines.remove(4);
+        let list = lines.join("\n") + "\n";
+        assert_eq!(check_hash_list(list.as_bytes(), 5, &tree_params, &[]), None);
+
+        lines[3] = format!("{}00000000", &lines[3][..lines[3].len()-8]);
+        let list = lines.join("\n") + "\n";
+        let report = check_hash_list(list.as_bytes(), 5, &tree_params, &[]);
+        assert!(matches!(report, Some((11, VerificationError::MismatchedParentHash(range, _)))
+            if range == BlockRange::new(0, 4, false)));
+    }
+    #[test]
+    fn missing_child_detected() {
+        let valid = valid_list();
+        let mut lines: Vec<&str> = valid.lines().collect();
+        lines.remove(5);
+        let report = check_str(&(lines.join("\n") + "\n"));
+        assert_eq!(report, Some((11, VerificationError::MissingChildEntries(
+            BlockRange::new(2, 4, false)))));
+    }
+}
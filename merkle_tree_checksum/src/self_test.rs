@@ -0,0 +1,132 @@
+#![forbid(unsafe_code)]
+// Known answers for the self-test subcommand, which checks the hash functions
+// and tree hashing of a build on the machine it runs on
+
+use crate::exit_codes::ExitCode;
+use crate::format_functions::hash_hex;
+use crate::hash_enum::HashFunctions;
+use crate::merkle_hash_fn;
+use crate::utils::ChannelOrPb;
+
+use merkle_tree::{block_t, branch_t, HashRange, TreeOptions};
+
+use indicatif::ProgressBar;
+use clap::Command;
+
+use std::io::Cursor;
+
+// 1000 bytes are 15 whole blocks and a partial one, so the leaves
+// fill two levels of the tree and the last leaf is short
+const KAT_BLOCK_SIZE: block_t = 64;
+const KAT_BRANCH_FACTOR: branch_t = 4;
+const KAT_INPUT_LEN: u32 = 1000;
+
+// Counts up and wraps at a prime, so that no two blocks are the same
+fn kat_input() -> Vec<u8> {
+    (0..KAT_INPUT_LEN).map(|i| (i % 251) as u8).collect()
+}
+
+/*
+ * Roots of kat_input, as written by
+ * generate-hash --short -l 64 -b 4 -f <function>
+ * The roots of the functions in Python's hashlib and zlib, which are all
+ * but sha512_160, blake3, k12, and ascon, were also checked against a
+ * separate Python implementation of the tree
+ */
+const KNOWN_ROOTS: [(HashFunctions, &str); 18] = [
+    (HashFunctions::crc32,
+        "fe29415a"),
+    (HashFunctions::crc32le,
+        "0729147e"),
+    (HashFunctions::sha224,
+        "d653b4c72fe2fd90ad86700f309e3c6486f1afa8035c765e39595dcd"),
+    (HashFunctions::sha256,
+        "42d6774ae50709ec9fef06ac9017eb613f5a85b49dc230d827b51daf97bb2d20"),
+    (HashFunctions::sha384,
+        "6d1b88203fae40be16c060851cfd477b6de8e6ee8003dedaeec93b52d90ac9a5c95d6afac8195f6ae612ab2a1a64a9b1"),
+    (HashFunctions::sha512,
+        "07f06b1b0e0d18260f38a74b06087eef79cc9b57d602b37314fdc9c1493ed7bd386019e843c9b0e839dd88f97f74ab85eddda3e118bf4638c46035abec89561d"),
+    (HashFunctions::sha512_224,
+        "72c5c8a847c2b26c0154b62a02da651dbf90aa788bcf8689adda8980"),
+    (HashFunctions::sha512_256,
+        "2793feedd30aab489165a23176a7140003ed8810edb7aa5caf175fbf6c463903"),
+    (HashFunctions::sha512_160,
+        "66a6b75ae1982cbf30a943a562d92121404204dc"),
+    (HashFunctions::sha3_224,
+        "f4f8d093691b05f34a4192a43afa1dcfaada03cb9148bca6027d712f"),
+    (HashFunctions::sha3_256,
+        "1d869f71965c7a9504b8e77e300637105781a7c240966ff46f56d5d8edac85cd"),
+    (HashFunctions::sha3_384,
+        "a1fe6003e288cd78db5de235afff3df20b6e8a84480ae63994fd5ef508d1a4abe288a402b271d7e43b1aa11fb96260b0"),
+    (HashFunctions::sha3_512,
+        "3106e8534f60cf18af0b99fbe9b533663f97e1fe88e52aabeabc95a76b5953285defef86e16cb121f79262dd28e39bc1a4761722f33b5a4d996ea4b5e9792f0c"),
+    (HashFunctions::blake2b_512,
+        "58e20b3d339f88cc5f71db23d8ce36974772b2c59ab356a7ebd2ef152fe5bde463bd0c81db83f0d773d218ad326e8776439e996200983cacc066a61aa245a6ea"),
+    (HashFunctions::blake2s_256,
+        "484ab51df090319b6b8b30df87ec585c946658f69b9d0b5e7279325c5797e974"),
+    (HashFunctions::blake3,
+        "2817acefd78f65f323eda8c35c8b206e4e414e678b9fac916b72732185c7dfaa"),
+    (HashFunctions::k12,
+        "1c48accb279fd37285d58a728663318a8b53af553fab9649bc6ce930e796f663"),
+    (HashFunctions::ascon,
+        "01fd2f3329cdb6d3f375bec8788d0a7af307e342b62036ea8a903f676cef8d64")
+];
+
+pub(crate) const SELF_TEST_CMD_NAME: &str = "self-test";
+
+pub(crate) fn self_test_command() -> Command {
+    Command::new(SELF_TEST_CMD_NAME)
+        .about("Check the hash functions of this build against known answers")
+        .long_about(concat!("Hash a fixed input with each supported hash function, ",
+            "both with and without worker threads, and compare the roots ",
+            "against known answers. This catches miscompiled builds ",
+            "and faulty hardware acceleration on the machine it runs on."))
+}
+
+// Hashes the known-answer input with every hash function, with and without a thread pool
+pub(crate) fn run_self_test(quiet_count: u8) -> ExitCode {
+    let kat_input = kat_input();
+    let mut failed = false;
+    for (hash_function, known_root) in KNOWN_ROOTS {
+        let merkle_tree_thunk = merkle_hash_fn(hash_function);
+        let mut function_failed = false;
+        for thread_count in [0, 2] {
+            let consumer: ChannelOrPb<HashRange> = ProgressBar::hidden().into();
+            let hash_result = merkle_tree_thunk(Cursor::new(&kat_input), KAT_BLOCK_SIZE,
+                KAT_BRANCH_FACTOR, &TreeOptions::default(), consumer, thread_count);
+            let mismatch = match hash_result {
+                Ok(root) if hash_hex(&root, false) == known_root => continue,
+                Ok(root) => format!("root {} instead of {}", hash_hex(&root, false), known_root),
+                Err(err) => err.to_string()
+            };
+            eprintln!("Error: {} failed with {} threads: {}",
+                hash_function, thread_count, mismatch);
+            function_failed = true;
+        }
+        if !function_failed && quiet_count < 2 {
+            eprintln!("Info: {} passed", hash_function);
+        }
+        failed |= function_failed;
+    }
+    match failed {
+        true => ExitCode::VerifBadEntryErr,
+        false => ExitCode::Success
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::VariantArray;
+
+    #[test]
+    fn every_function_has_known_root() {
+        for hash_function in HashFunctions::VARIANTS {
+            let known_roots: Vec<_> = KNOWN_ROOTS.iter()
+                .filter(|(known_function, _)| known_function == hash_function)
+                .collect();
+            assert_eq!(known_roots.len(), 1, "{}", hash_function);
+            assert_eq!(known_roots[0].1.len(), 2*hash_function.hash_len());
+        }
+    }
+}
@@ -0,0 +1,215 @@
+// SHA-512/t (FIPS 180-4 section 5.3.6) for values of t that sha2 lacks
+#![forbid(unsafe_code)]
+
+use digest::{FixedOutput, OutputSizeUser, Update, Reset, HashMarker};
+use generic_array::{ArrayLength, GenericArray};
+use generic_array::typenum::{U20, U128, Unsigned};
+use sha2::compress512;
+
+const BLOCK_LEN: usize = 128;
+// Length is appended as a 128-bit integer
+const LEN_FIELD_LEN: usize = 16;
+
+// Plain SHA-512 over u64 state, with the initial hash value supplied
+#[derive(Clone)]
+struct Sha512Engine {
+    state: [u64; 8],
+    buffer: GenericArray<u8, U128>,
+    buffer_len: usize,
+    total_len: u128
+}
+impl Sha512Engine {
+    fn new(initial_state: [u64; 8]) -> Self {
+        Sha512Engine {
+            state: initial_state,
+            buffer: GenericArray::default(),
+            buffer_len: 0,
+            total_len: 0
+        }
+    }
+    fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u128;
+        if self.buffer_len > 0 {
+            let fill_len = (BLOCK_LEN - self.buffer_len).min(data.len());
+            self.buffer[self.buffer_len..self.buffer_len+fill_len]
+                .copy_from_slice(&data[..fill_len]);
+            self.buffer_len += fill_len;
+            data = &data[fill_len..];
+            if self.buffer_len < BLOCK_LEN {
+                return;
+            }
+            compress512(&mut self.state, std::slice::from_ref(&self.buffer));
+            self.buffer_len = 0;
+        }
+        let mut block_iter = data.chunks_exact(BLOCK_LEN);
+        for block in block_iter.by_ref() {
+            compress512(&mut self.state,
+                std::slice::from_ref(GenericArray::from_slice(block)));
+        }
+        let remainder = block_iter.remainder();
+        self.buffer[..remainder.len()].copy_from_slice(remainder);
+        self.buffer_len = remainder.len();
+    }
+    fn finalize(mut self) -> [u64; 8] {
+        let bit_len = self.total_len * 8;
+        // Pad with 0x80 then zeros, leaving room for the length in the last block
+        let pad_len = match self.buffer_len < BLOCK_LEN - LEN_FIELD_LEN {
+            true => BLOCK_LEN - LEN_FIELD_LEN - self.buffer_len,
+            false => 2*BLOCK_LEN - LEN_FIELD_LEN - self.buffer_len
+        };
+        let mut padding = vec![0x00; pad_len + LEN_FIELD_LEN];
+        padding[0] = 0x80;
+        padding[pad_len..].copy_from_slice(&bit_len.to_be_bytes());
+        self.update(&padding);
+        debug_assert_eq!(self.buffer_len, 0);
+        self.state
+    }
+}
+
+// Output length and initial hash value for a given t
+// The initial hash value comes from the SHA-512/t IV generation function
+pub trait Sha512TruncVariant {
+    type OutputSize: ArrayLength<u8> + 'static;
+    const INITIAL_STATE: [u64; 8];
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Trunc160;
+impl Sha512TruncVariant for Trunc160 {
+    type OutputSize = U20;
+    const INITIAL_STATE: [u64; 8] = [
+        0xe1776a8085525b56, 0xdac4843998441658,
+        0xf8553a5b1fe4a47e, 0x1998c32ebc7b9c3f,
+        0x0aa747d2a5dce640, 0x2cea11d48a14c833,
+        0x3e1a5a6ae6ff6965, 0x433559b2df0c8534
+    ];
+}
+
+#[derive(Clone)]
+pub struct Sha512Trunc<V: Sha512TruncVariant> {
+    engine: Sha512Engine,
+    _variant: std::marker::PhantomData<V>
+}
+impl<V: Sha512TruncVariant> Default for Sha512Trunc<V> {
+    fn default() -> Self {
+        Sha512Trunc {
+            engine: Sha512Engine::new(V::INITIAL_STATE),
+            _variant: std::marker::PhantomData
+        }
+    }
+}
+
+impl<V: Sha512TruncVariant> OutputSizeUser for Sha512Trunc<V> {
+    type OutputSize = V::OutputSize;
+}
+impl<V: Sha512TruncVariant> HashMarker for Sha512Trunc<V> {}
+
+impl<V: Sha512TruncVariant> FixedOutput for Sha512Trunc<V> {
+    #[inline]
+    fn finalize_into(self, out: &mut GenericArray<u8, Self::OutputSize>) {
+        let full_output: Vec<u8> = self.engine.finalize().iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect();
+        out.copy_from_slice(&full_output[..V::OutputSize::USIZE]);
+    }
+}
+
+impl<V: Sha512TruncVariant> Update for Sha512Trunc<V> {
+    #[inline]
+    fn update(&mut self, data: &[u8]) {
+        self.engine.update(data);
+    }
+}
+
+impl<V: Sha512TruncVariant> Reset for Sha512Trunc<V> {
+    #[inline]
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[allow(non_camel_case_types)]
+pub type Sha512_160 = Sha512Trunc<Trunc160>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use digest::Digest;
+    use generic_array::typenum::{U28, U32};
+    use sha2::{Sha512, Sha512_224, Sha512_256};
+
+    const SHA512_IV: [u64; 8] = [
+        0x6a09e667f3bcc908, 0xbb67ae8584caa73b,
+        0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+        0x510e527fade682d1, 0x9b05688c2b3e6c1f,
+        0x1f83d9abfb41bd6b, 0x5be0cd19137e2179
+    ];
+
+    // IV generation function from FIPS 180-4 section 5.3.6
+    fn sha512_t_iv(t_bits: usize) -> [u64; 8] {
+        let mut engine = Sha512Engine::new(
+            SHA512_IV.map(|word| word ^ 0xa5a5a5a5a5a5a5a5));
+        engine.update(format!("SHA-512/{}", t_bits).as_bytes());
+        engine.finalize()
+    }
+
+    #[derive(Clone, Copy, Debug, Default)]
+    struct Trunc224;
+    impl Sha512TruncVariant for Trunc224 {
+        type OutputSize = U28;
+        const INITIAL_STATE: [u64; 8] = [
+            0x8c3d37c819544da2, 0x73e1996689dcd4d6,
+            0x1dfab7ae32ff9c82, 0x679dd514582f9fcf,
+            0x0f6d2b697bd44da8, 0x77e36f7304c48942,
+            0x3f9d85a86a1d36c8, 0x1112e6ad91d692a1
+        ];
+    }
+    #[derive(Clone, Copy, Debug, Default)]
+    struct Trunc256;
+    impl Sha512TruncVariant for Trunc256 {
+        type OutputSize = U32;
+        const INITIAL_STATE: [u64; 8] = [
+            0x22312194fc2bf72c, 0x9f555fa3c84c64c2,
+            0x2393b86b6f53b151, 0x963877195940eabd,
+            0x96283ee2a88effe3, 0xbe5e1e2553863992,
+            0x2b0199fc2c85b8aa, 0x0eb72ddc81c52ca2
+        ];
+    }
+
+    // Covers empty input and inputs around the padding boundary
+    fn test_inputs() -> Vec<Vec<u8>> {
+        [0, 3, 111, 112, 127, 128, 129, 300].iter()
+            .map(|&len| (0..len).map(|i| (i % 251) as u8).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_iv_generation() {
+        assert_eq!(sha512_t_iv(160), Trunc160::INITIAL_STATE);
+        assert_eq!(sha512_t_iv(224), Trunc224::INITIAL_STATE);
+        assert_eq!(sha512_t_iv(256), Trunc256::INITIAL_STATE);
+    }
+    #[test]
+    fn test_engine_matches_sha512() {
+        for input in test_inputs() {
+            let mut engine = Sha512Engine::new(SHA512_IV);
+            // Split the input to exercise buffering across updates
+            let (first, second) = input.split_at(input.len()/3);
+            engine.update(first);
+            engine.update(second);
+            let engine_output: Vec<u8> = engine.finalize().iter()
+                .flat_map(|word| word.to_be_bytes())
+                .collect();
+            assert_eq!(engine_output, Sha512::digest(&input).as_slice());
+        }
+    }
+    #[test]
+    fn test_trunc_matches_sha2() {
+        for input in test_inputs() {
+            assert_eq!(Sha512Trunc::<Trunc224>::digest(&input),
+                Sha512_224::digest(&input));
+            assert_eq!(Sha512Trunc::<Trunc256>::digest(&input),
+                Sha512_256::digest(&input));
+        }
+    }
+}
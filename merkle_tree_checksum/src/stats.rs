@@ -0,0 +1,130 @@
+#![forbid(unsafe_code)]
+// Hashing time and throughput of each file, printed with --stats for benchmarking,
+// and counts of repeated blocks, printed with --stats-blocks
+
+use crate::format_functions::hash_hex;
+use crate::hash_enum::HashFunctions;
+use crate::utils::quote_name_group;
+
+use merkle_tree::BlockStats;
+
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+struct FileStats {
+    quoted_name: String,
+    byte_count: u64,
+    elapsed: Duration
+}
+
+// Throughput is in decimal megabytes, and is 0 for files hashed too fast to time
+fn write_timing(fmt: &mut fmt::Formatter<'_>, byte_count: u64,
+        elapsed: Duration) -> fmt::Result {
+    let secs = elapsed.as_secs_f64();
+    let mb_per_sec = match secs > 0.0 {
+        true => byte_count as f64 / 1e6 / secs,
+        false => 0.0
+    };
+    write!(fmt, "{} bytes in {:.6} s, {:.2} MB/s", byte_count, secs, mb_per_sec)
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct HashStats {
+    hash_function: HashFunctions,
+    thread_count: usize,
+    file_stats: Vec<FileStats>
+}
+impl HashStats {
+    pub fn new(hash_function: HashFunctions, thread_count: usize) -> Self {
+        HashStats {hash_function, thread_count, file_stats: Vec::new()}
+    }
+    pub fn record(&mut self, file_parts: &[PathBuf], byte_count: u64, elapsed: Duration) {
+        self.file_stats.push(FileStats {
+            quoted_name: quote_name_group(file_parts),
+            byte_count,
+            elapsed
+        });
+    }
+}
+impl fmt::Display for HashStats {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Lines are stable so that benchmark logs can be parsed
+        writeln!(fmt, "Stats:")?;
+        writeln!(fmt, "  Hash function: {}", self.hash_function)?;
+        writeln!(fmt, "  Threads: {}", self.thread_count)?;
+        for file_stats in &self.file_stats {
+            write!(fmt, "  File {}: ", file_stats.quoted_name)?;
+            write_timing(fmt, file_stats.byte_count, file_stats.elapsed)?;
+            writeln!(fmt)?;
+        }
+        write!(fmt, "  Total for {} files: ", self.file_stats.len())?;
+        write_timing(fmt, self.file_stats.iter().map(|stats| stats.byte_count).sum(),
+            self.file_stats.iter().map(|stats| stats.elapsed).sum())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BlockStatsSummary {
+    stats: BlockStats,
+    uppercase: bool
+}
+impl BlockStatsSummary {
+    pub fn new(stats: BlockStats, uppercase: bool) -> Self {
+        BlockStatsSummary {stats, uppercase}
+    }
+}
+impl fmt::Display for BlockStatsSummary {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(fmt, "Block stats:")?;
+        writeln!(fmt, "  Leaf nodes: {}", self.stats.leaf_count)?;
+        writeln!(fmt, "  Internal nodes: {}", self.stats.internal_count)?;
+        write!(fmt, "  Distinct leaf hashes: {}", self.stats.distinct_leaf_count)?;
+        if let Some((leaf_hash, leaf_count)) = &self.stats.most_repeated_leaf {
+            write!(fmt, "\n  Most repeated leaf hash: {} ({} leaves)",
+                hash_hex(leaf_hash, self.uppercase), leaf_count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use merkle_tree::HashData;
+
+    #[test]
+    fn stats_block_format() {
+        let mut stats = HashStats::new(HashFunctions::sha256, 4);
+        stats.record(&[PathBuf::from("a")], 3_000_000, Duration::from_millis(1500));
+        stats.record(&[PathBuf::from("b"), PathBuf::from("c")], 0, Duration::ZERO);
+        assert_eq!(stats.to_string(), concat!("Stats:\n",
+            "  Hash function: sha256\n",
+            "  Threads: 4\n",
+            "  File \"a\": 3000000 bytes in 1.500000 s, 2.00 MB/s\n",
+            "  File \"b\" + \"c\": 0 bytes in 0.000000 s, 0.00 MB/s\n",
+            "  Total for 2 files: 3000000 bytes in 1.500000 s, 2.00 MB/s"));
+    }
+    #[test]
+    fn block_stats_format() {
+        let stats = BlockStats {
+            leaf_count: 4,
+            internal_count: 3,
+            distinct_leaf_count: 2,
+            most_repeated_leaf: Some((HashData::try_new(&[0xab, 0xcd]).unwrap(), 3))
+        };
+        assert_eq!(BlockStatsSummary::new(stats.clone(), true).to_string(),
+            concat!("Block stats:\n",
+            "  Leaf nodes: 4\n",
+            "  Internal nodes: 3\n",
+            "  Distinct leaf hashes: 2\n",
+            "  Most repeated leaf hash: ABCD (3 leaves)"));
+        // Empty files have no leaves to repeat
+        assert_eq!(BlockStatsSummary::new(BlockStats::default(), false).to_string(),
+            concat!("Block stats:\n",
+            "  Leaf nodes: 0\n",
+            "  Internal nodes: 0\n",
+            "  Distinct leaf hashes: 0"));
+    }
+}
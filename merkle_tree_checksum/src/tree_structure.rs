@@ -0,0 +1,124 @@
+#![forbid(unsafe_code)]
+// Combined root over every file of a hash file and their relative path names
+
+use crate::hash_enum::HashFunctions;
+
+use merkle_tree::HashData;
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path};
+
+pub(crate) const STRUCTURE_HASH_PREFIX: &str = "Structure hash: ";
+pub(crate) const ROOT_PREFIX: &str = "Root: ";
+// A root line always fits in this many bytes, including a CRLF line ending
+const ROOT_LINE_MAX_LEN: usize = ROOT_PREFIX.len() + 2*64 + 2;
+
+// Path of a found file relative to the argument it was found under
+// A file given directly is named by its file name
+// Components are joined with / so that the name does not depend on the platform
+pub(crate) fn relative_name(path: &Path, arg_path: &Path) -> String {
+    let relative_path = match path.strip_prefix(arg_path) {
+        Ok(relative_path) if relative_path.as_os_str().is_empty() =>
+            Path::new(path.file_name().unwrap_or(path.as_os_str())),
+        Ok(relative_path) => relative_path,
+        Err(_) => path
+    };
+    relative_path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Hash of the sorted relative names, each followed by a newline
+pub(crate) fn structure_hash(hash_function: HashFunctions,
+        relative_names: &[String]) -> HashData<64> {
+    let mut sorted_names: Vec<&str> = relative_names.iter()
+        .map(String::as_str).collect();
+    sorted_names.sort_unstable();
+    let mut hash_input: Vec<u8> = Vec::new();
+    for name in sorted_names {
+        hash_input.extend_from_slice(name.as_bytes());
+        hash_input.push(b'\n');
+    }
+    hash_function.digest(&hash_input)
+}
+
+// Prefixed with 0x03 to keep it apart from node (0x00, 0x01) and metadata (0x02) hashes
+// File roots are in entry order
+pub(crate) fn combined_root(hash_function: HashFunctions, structure_hash: &[u8],
+        file_roots: &[HashData<64>]) -> HashData<64> {
+    let mut hash_input: Vec<u8> = vec![0x03];
+    hash_input.extend_from_slice(structure_hash);
+    for file_root in file_roots {
+        hash_input.extend_from_slice(file_root.as_ref());
+    }
+    hash_function.digest(&hash_input)
+}
+
+// Length of the first content_len bytes without a last Root: line,
+// and the root stored in that line if there is one
+// A malformed root line is left in, to be reported as a malformed entry
+// The reader is left at the start of the file
+pub(crate) fn split_root_line<R: Read+Seek>(reader: &mut R, content_len: u64)
+        -> io::Result<(u64, Option<HashData<64>>)> {
+    let tail_start = content_len.saturating_sub(ROOT_LINE_MAX_LEN as u64);
+    reader.seek(SeekFrom::Start(tail_start))?;
+    let mut tail: Vec<u8> = Vec::with_capacity(ROOT_LINE_MAX_LEN);
+    reader.by_ref().take(content_len - tail_start).read_to_end(&mut tail)?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let tail_trimmed = tail.strip_suffix(b"\n").unwrap_or(&tail);
+    let tail_trimmed = tail_trimmed.strip_suffix(b"\r").unwrap_or(tail_trimmed);
+    let last_line_start = match tail_trimmed.iter().rposition(|byte| *byte == b'\n') {
+        Some(newline_pos) => newline_pos + 1,
+        // The last line is too long to be a root line
+        None if tail_start > 0 => return Ok((content_len, None)),
+        None => 0
+    };
+    let root = tail_trimmed[last_line_start..].strip_prefix(ROOT_PREFIX.as_bytes())
+        .and_then(|root_hex| hex::decode(root_hex).ok())
+        .and_then(|root_bytes| HashData::try_new(&root_bytes).ok());
+    match root {
+        Some(root) => Ok((tail_start + last_line_start as u64, Some(root))),
+        None => Ok((content_len, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn relative_names_found() {
+        assert_eq!(relative_name(Path::new("dir/sub/a.txt"), Path::new("dir")), "sub/a.txt");
+        assert_eq!(relative_name(Path::new("dir/a.txt"), Path::new("dir/a.txt")), "a.txt");
+        assert_eq!(relative_name(Path::new("./dir/a.txt"), Path::new("./dir/")), "a.txt");
+    }
+    #[test]
+    fn structure_hash_sorts_names() {
+        let names = ["b".to_owned(), "a".to_owned()];
+        let sorted_names = ["a".to_owned(), "b".to_owned()];
+        assert_eq!(structure_hash(HashFunctions::sha256, &names),
+            structure_hash(HashFunctions::sha256, &sorted_names));
+        assert_eq!(structure_hash(HashFunctions::sha256, &sorted_names),
+            HashFunctions::sha256.digest(b"a\nb\n"));
+    }
+    #[test]
+    fn root_line_split() {
+        let contents = b"Hashes:\n00  \"a\"\nRoot: 0123abcd\nFile-Checksum: 00000000\n";
+        // The checksum footer has already been left out of content_len
+        let content_len = (contents.len() - 24) as u64;
+        let (len, root) = split_root_line(&mut Cursor::new(&contents[..]), content_len).unwrap();
+        assert_eq!(len, 16);
+        assert_eq!(root, Some(HashData::try_new(&[0x01, 0x23, 0xab, 0xcd]).unwrap()));
+
+        let malformed = b"Hashes:\nRoot: 0123abcx\n";
+        let (len, root) = split_root_line(&mut Cursor::new(&malformed[..]),
+            malformed.len() as u64).unwrap();
+        assert_eq!((len, root), (malformed.len() as u64, None));
+    }
+}
@@ -0,0 +1,178 @@
+#![forbid(unsafe_code)]
+// ASCII rendering of the Merkle tree of one file, for the tree subcommand
+
+use crate::exit_codes::ExitCode;
+use crate::hash_file_input::read_parsed_hash_file;
+use crate::hash_file_parser::HashEntry;
+use crate::utils::{display_name_group, quote_name_group};
+
+use merkle_tree::{BlockRange, HashRange};
+
+use clap::{Command, Arg, ArgAction};
+
+use std::io::Write;
+use std::path::PathBuf;
+
+// Index of an entry, with the nodes of its children in entry order
+struct TreeNode {
+    entry: usize,
+    children: Vec<TreeNode>
+}
+
+fn last_block(range: BlockRange) -> u64 {
+    match range.include_end() {
+        true => range.end(),
+        false => range.end() - 1
+    }
+}
+
+fn contains(outer: BlockRange, inner: BlockRange) -> bool {
+    outer.start() <= inner.start() && last_block(inner) <= last_block(outer)
+}
+
+// Entries are in the order of merkle_block_generator, so every node comes
+// right after its children, and the nodes left at the end are the roots
+fn build_tree(entries: &[HashRange]) -> Vec<TreeNode> {
+    let mut stack: Vec<TreeNode> = Vec::new();
+    for (entry_index, entry) in entries.iter().enumerate() {
+        let block_range = entry.block_range();
+        let first_child = stack.iter()
+            .rposition(|node| !contains(block_range, entries[node.entry].block_range()))
+            .map_or(0, |pos| pos + 1);
+        let children = stack.split_off(first_child);
+        stack.push(TreeNode {entry: entry_index, children});
+    }
+    stack
+}
+
+fn render_node(node: &TreeNode, entries: &[HashRange], hash_chars: usize,
+        prefix: &str, branch: &str, output: &mut String) {
+    let entry = &entries[node.entry];
+    let hash_hex = hex::encode(entry.hash_result());
+    let hash_prefix = &hash_hex[..hash_chars.min(hash_hex.len())];
+    output.push_str(&format!("{}{}{} {}\n", prefix, branch,
+        entry.block_range(), hash_prefix));
+    // Lines continue below a child that has later siblings
+    let child_prefix = match branch {
+        "+-- " => format!("{}|   ", prefix),
+        "`-- " => format!("{}    ", prefix),
+        _ => prefix.to_owned()
+    };
+    for (child_index, child) in node.children.iter().enumerate() {
+        let child_branch = match child_index + 1 == node.children.len() {
+            true => "`-- ",
+            false => "+-- "
+        };
+        render_node(child, entries, hash_chars, &child_prefix, child_branch, output);
+    }
+}
+
+// Lines of the tree, with the root first and children indented under their parent
+// Each node shows its block range and the first hash_chars hex digits of its hash
+pub(crate) fn render_tree(entries: &[HashRange], hash_chars: usize) -> String {
+    let mut output = String::new();
+    for root in build_tree(entries) {
+        render_node(&root, entries, hash_chars, "", "", &mut output);
+    }
+    output
+}
+
+pub(crate) const TREE_CMD_NAME: &str = "tree";
+
+pub(crate) fn tree_command() -> Command {
+    Command::new(TREE_CMD_NAME)
+        .about("Draw the Merkle tree of each file in a hash file")
+        .long_about(concat!("Draw the Merkle tree of each file in a long ",
+            "hash file to stdout, with the children of each node indented ",
+            "below it. Each node shows its block range and the start ",
+            "of its hash."))
+        .arg(Arg::new("maxnodes").long("max-nodes")
+            .action(ArgAction::Set)
+            .default_value("64")
+            .value_parser(clap::value_parser!(usize))
+            .help("Skip the trees of files with more nodes than this")
+            .long_help(concat!("Skip the trees of files with more nodes than this, ",
+                "with a warning, so that large files do not flood the terminal.")))
+        .arg(Arg::new("hashchars").long("hash-chars")
+            .action(ArgAction::Set)
+            .default_value("8")
+            .value_parser(clap::value_parser!(usize))
+            .help("Number of hex digits of each hash to show"))
+        .arg(Arg::new("FILE").required(true)
+            .action(ArgAction::Set)
+            .help("Long hash file to draw the trees of"))
+}
+
+// Prints the tree of each file in a long hash file
+pub(crate) fn show_hash_trees(file_name: &str, max_nodes: usize, hash_chars: usize,
+        quiet_count: u8) -> ExitCode {
+    let parsed_file = match read_parsed_hash_file(file_name) {
+        Ok(parsed_file) => parsed_file,
+        Err(exit_code) => return exit_code
+    };
+    if parsed_file.header.is_short_hash || parsed_file.header.tree_params.metadata_only {
+        eprintln!("Error: tree requires a hash file with the full tree");
+        return ExitCode::CmdlineErr;
+    }
+    let mut stdout = std::io::stdout().lock();
+    for (file_index, file_entry) in parsed_file.files.iter().enumerate() {
+        let file_nodes: Vec<HashRange> = parsed_file.entries.iter()
+            .filter_map(|entry| match entry {
+                HashEntry::Long(entry_index, hash_range) if *entry_index == file_index =>
+                    Some(hash_range.clone()),
+                _ => None
+            })
+            .collect();
+        let paths: Vec<PathBuf> = file_entry.names.iter().map(PathBuf::from).collect();
+        if file_nodes.len() > max_nodes {
+            if quiet_count < 2 {
+                eprintln!("Warning: skipping the tree of {} with {} nodes, more than --max-nodes of {}",
+                    display_name_group(&paths), file_nodes.len(), max_nodes);
+            }
+            continue;
+        }
+        let write_result = writeln!(stdout, "{}:", quote_name_group(&paths))
+            .and_then(|_| write!(stdout, "{}",
+                render_tree(&file_nodes, hash_chars)));
+        if let Err(err) = write_result {
+            eprintln!("Error writing tree: {}", err);
+            return ExitCode::GenWriteErr;
+        }
+    }
+    ExitCode::Success
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use merkle_tree::{hash_file_to_vec, HashData};
+    use sha2::Sha256;
+    use std::io::Cursor;
+
+    #[test]
+    fn tree_rendered_with_indents() {
+        let entries = hash_file_to_vec::<_, Sha256>(Cursor::new(b"abcdefghij"), 4, 2, 0).unwrap();
+        let rendered = render_tree(&entries, 8);
+        // Leave out the hash prefixes
+        let tree_lines: Vec<&str> = rendered.lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0)
+            .collect();
+        assert_eq!(tree_lines, [
+            "[0x00000000-0x00000003]",
+            "+-- [0x00000000-0x00000001]",
+            "|   +-- [0x00000000-0x00000000]",
+            "|   `-- [0x00000001-0x00000001]",
+            "`-- [0x00000002-0x00000003]",
+            "    `-- [0x00000002-0x00000002]"]);
+        let root_hash = hex::encode(entries.last().unwrap().hash_result());
+        assert!(rendered.starts_with(&format!("[0x00000000-0x00000003] {}\n", &root_hash[..8])));
+    }
+
+    #[test]
+    fn hash_prefixes_shown() {
+        let entry = HashRange::new(BlockRange::new(0, 0, true), BlockRange::new(0, 3, true),
+            HashData::try_new(&[0x01, 0x23, 0x45, 0x67]).unwrap());
+        assert_eq!(render_tree(std::slice::from_ref(&entry), 4), "[0x00000000-0x00000000] 0123\n");
+        assert_eq!(render_tree(&[entry], 16), "[0x00000000-0x00000000] 01234567\n");
+    }
+}
@@ -4,19 +4,32 @@ extern crate merkle_tree;
 
 use std::str::FromStr;
 use std::fmt;
-use crate::error_types::HeaderParsingErr;
-use crate::parse_functions::size_str_to_num;
+use std::io::Write;
+use std::io::Result as IOResult;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::{Arc, Mutex};
+use crate::error_types::{HeaderParsingErr, PathListError, PreHashError};
+use crate::parse_functions::{byte_count_str_to_num, size_str_to_num, parse_path_list};
+use crate::parse_functions::parse_nul_path_list;
+use crate::format_functions::{escape_chars, hash_hex};
+use crate::format_version::{FORMAT_VERSION, FEATURES_PREFIX};
+use crate::tree_structure::STRUCTURE_HASH_PREFIX;
 
+use clap::crate_name;
 use crossbeam_channel::Sender as CrossbeamSender;
-use indicatif::{ProgressDrawTarget, ProgressStyle, ProgressBar, MultiProgress};
+use indicatif::{ProgressDrawTarget, ProgressStyle, ProgressBar, MultiProgress, TermLike};
 
 use strum_macros::EnumString;
 
 use crate::hash_enum::HashFunctions;
 
-use merkle_tree::{block_t, branch_t, Consumer};
+use merkle_tree::{block_t, branch_t, BlockRange, Consumer, HashData, NodeEncoding, TreeOptions};
+use merkle_tree::{block_byte_range, strided_byte_range, strided_leaf_count};
 
-use std::path::{Path, PathBuf};
+use std::fs;
+use std::convert::TryFrom;
+use std::time::SystemTime;
+use std::path::{Component, Path, PathBuf};
 use walkdir::WalkDir;
 
 #[derive(Debug, Clone)]
@@ -57,6 +70,71 @@ impl<T> Drop for ChannelOrPb<T> {
     }
 }
 
+#[inline]
+pub(crate) fn end_exclusive(range: BlockRange) -> u64 {
+    match range.include_end() {
+        true => range.end().saturating_add(1),
+        false => range.end()
+    }
+}
+
+#[inline]
+pub(crate) fn offset_byte_range(byte_range: BlockRange, offset: u64) -> BlockRange {
+    BlockRange::new(byte_range.start() + offset, byte_range.end() + offset,
+        byte_range.include_end())
+}
+
+// Block range of the root of the tree over file_len bytes
+pub(crate) fn root_block_range(file_len: u64, block_size: block_t, branch: branch_t) -> BlockRange {
+    // An empty file still has a single leaf
+    let block_count = file_len.div_ceil(block_size.into()).max(1);
+    let mut root_len: u64 = 1;
+    while root_len < block_count {
+        root_len *= u64::from(branch);
+    }
+    BlockRange::new(0, root_len, false)
+}
+
+// Whether a node gets an entry in the hash list, given TreeParams::listed_root
+pub(crate) fn is_listed_node(block_range: BlockRange, listed_root: Option<BlockRange>) -> bool {
+    match listed_root {
+        Some(root_range) => block_range.range() == 1 || block_range == root_range,
+        None => true
+    }
+}
+
+// Node counts of the tree over one file, and the size of its long hash entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LongFormatSize {
+    pub leaf_nodes: u64,
+    pub internal_nodes: u64,
+    pub entry_bytes: u64
+}
+
+// Width of a range end written with {:#010x}
+fn range_end_width(value: u64) -> u64 {
+    let hex_digits = u64::from((u64::BITS - value.leading_zeros()).div_ceil(4)).max(1);
+    (2 + hex_digits).max(10)
+}
+
+// Every entry is sized as if it had the widest range ends in the tree, which is
+// exact unless the ranges go past 32 bits, and the file index is taken to fit in 3 digits
+pub(crate) fn long_format_size(file_size: u64, block_size: block_t, branch: branch_t,
+        hash_len: usize) -> LongFormatSize {
+    let leaf_nodes = file_size.div_ceil(block_size.into()).max(1);
+    let internal_nodes = merkle_tree::node_count(file_size, block_size, branch) - leaf_nodes;
+    let block_width = range_end_width(root_block_range(file_size, block_size, branch).end());
+    let byte_width = range_end_width(file_size.saturating_sub(1));
+    // "{:3} [{}-{}] [{}-{}] {}\n" with the hash in hex
+    let entry_len = 3 + 1 + (2*block_width + 3) + 1 + (2*byte_width + 3) + 1
+        + 2*hash_len as u64 + 1;
+    LongFormatSize {
+        leaf_nodes,
+        internal_nodes,
+        entry_bytes: (leaf_nodes + internal_nodes) * entry_len
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct StoredAndComputed<T> {
     stored: T,
@@ -77,6 +155,16 @@ impl<T> StoredAndComputed<T> {
 }
 impl<T: Copy> Copy for StoredAndComputed<T> {}
 
+// What byte ranges of a region count from, from --byte-range-base
+// Absolute ranges count from the start of the file, or of the concatenated files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(EnumString, strum_macros::Display)]
+#[strum(serialize_all = "lowercase")]
+pub(crate) enum ByteRangeBase {
+    Relative,
+    Absolute
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[derive(EnumString, strum_macros::Display)]
 // Don't use strum(ascii_case_insensitive) because we only accept two types
@@ -86,17 +174,96 @@ pub(crate) enum HeaderElement {
     #[strum(to_string = "Branching factor", serialize = "branch factor")]
     BranchFactor,
     #[strum(to_string = "Hash function", serialize = "hash function")]
-    HashFunction
+    HashFunction,
+    #[strum(to_string = "Node encoding", serialize = "node encoding")]
+    NodeEncoding,
+    #[strum(to_string = "Salted", serialize = "salted")]
+    Salted,
+    #[strum(to_string = "Metadata only", serialize = "metadata only")]
+    MetadataOnly,
+    #[strum(to_string = "Padded tree", serialize = "padded tree")]
+    PadTree,
+    #[strum(to_string = "Unbalanced tree", serialize = "unbalanced tree")]
+    Unbalanced,
+    #[strum(to_string = "Duplicated last child", serialize = "duplicated last child")]
+    DuplicateLast,
+    #[strum(to_string = "Trimmed trailing zeros", serialize = "trimmed trailing zeros")]
+    TrimZeros,
+    #[strum(to_string = "Region offset", serialize = "region offset")]
+    RegionOffset,
+    #[strum(to_string = "Byte range base", serialize = "byte range base")]
+    ByteRangeBase,
+    #[strum(to_string = "Leaves and root only", serialize = "leaves and root only")]
+    LeavesAndRoot,
+    #[strum(to_string = "Content-defined chunks", serialize = "content-defined chunks")]
+    ContentDefined,
+    #[strum(to_string = "Normalized line endings", serialize = "normalized line endings")]
+    NormalizeEol,
+    #[strum(to_string = "Names relative to hash file", serialize = "names relative to hash file")]
+    RelativeNames,
+    #[strum(to_string = "Nominal byte ranges", serialize = "nominal byte ranges")]
+    NominalRanges,
+    #[strum(to_string = "Stride", serialize = "stride")]
+    Stride,
+    #[strum(to_string = "Filtered", serialize = "filtered")]
+    Filtered
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct TreeParams {
     pub block_size: block_t,
     pub branch_factor: branch_t,
-    pub hash_function: HashFunctions
+    pub hash_function: HashFunctions,
+    pub node_encoding: NodeEncoding,
+    // The salt itself is supplied on the command line, not stored
+    pub salted: bool,
+    pub pad_tree: bool,
+    // Internal nodes with one child take its hash instead of hashing it
+    pub unbalanced: bool,
+    // Internal nodes repeat their last child hash up to the branch factor
+    pub duplicate_last: bool,
+    // Entries hash file metadata instead of file contents
+    pub metadata_only: bool,
+    // File list entries may record a shorter hashed length
+    pub trim_zeros: bool,
+    // Only the region starting here is hashed, with the entry length as its length
+    pub region_offset: Option<u64>,
+    // Byte ranges count from the start of the file instead of the region
+    pub absolute_ranges: bool,
+    // Internal nodes other than the root are left out of the hash list
+    pub leaves_and_root: bool,
+    // Leaves are content-defined chunks with the block size as their target size
+    pub content_defined: bool,
+    // CRLF line endings are read as LF, and lengths are of the normalized data
+    pub normalize_eol: bool,
+    // Relative file names are resolved against the directory of the hash file
+    pub relative_names: bool,
+    // Byte ranges end at a whole block even past the end of the file
+    pub nominal_ranges: bool,
+    // Leaves start this many bytes apart, overlapping when less than the block size
+    pub stride: Option<block_t>,
+    // Data was read through --filter-command, whose command is not recorded
+    pub filtered: bool
 }
 impl TreeParams {
-    pub fn from_lines(string_arr: &[String; 3]) -> Result<TreeParams, Vec<HeaderParsingErr>> {
+    // Parameters after the hash function are optional for older hash files
+    pub fn from_lines(string_arr: &[String]) -> Result<TreeParams, Vec<HeaderParsingErr>> {
+        let mut node_encoding = NodeEncoding::Plain;
+        let mut salted = false;
+        let mut pad_tree = false;
+        let mut unbalanced = false;
+        let mut duplicate_last = false;
+        let mut metadata_only = false;
+        let mut trim_zeros = false;
+        let mut region_offset = None;
+        let mut absolute_ranges = false;
+        let mut leaves_and_root = false;
+        let mut content_defined = false;
+        let mut normalize_eol = false;
+        let mut relative_names = false;
+        let mut nominal_ranges = false;
+        let mut stride = None;
+        let mut filtered = false;
         let mut block_size_opt: Option<block_t> = None;
         let mut branch_factor_opt: Option<branch_t> = None;
         let mut hash_function_opt: Option<HashFunctions> = None;
@@ -121,6 +288,7 @@ impl TreeParams {
                     }
                 },
                 Ok(HeaderElement::BranchFactor) => {
+                    // The library panics on branch factors below 2
                     match value.parse::<branch_t>() {
                         Ok(0) | Ok(1) | Err(_) => {
                             errors.push(HeaderParsingErr::BadParameterValue(
@@ -142,6 +310,182 @@ impl TreeParams {
                         }
                     }
                 },
+                Ok(HeaderElement::NodeEncoding) => {
+                    match value.parse::<NodeEncoding>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::NodeEncoding, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            node_encoding = val
+                        }
+                    }
+                },
+                Ok(HeaderElement::Salted) => {
+                    match value.parse::<bool>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::Salted, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            salted = val
+                        }
+                    }
+                },
+                Ok(HeaderElement::PadTree) => {
+                    match value.parse::<bool>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::PadTree, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            pad_tree = val
+                        }
+                    }
+                },
+                Ok(HeaderElement::Unbalanced) => {
+                    match value.parse::<bool>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::Unbalanced, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            unbalanced = val
+                        }
+                    }
+                },
+                Ok(HeaderElement::DuplicateLast) => {
+                    match value.parse::<bool>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::DuplicateLast, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            duplicate_last = val
+                        }
+                    }
+                },
+                Ok(HeaderElement::MetadataOnly) => {
+                    match value.parse::<bool>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::MetadataOnly, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            metadata_only = val
+                        }
+                    }
+                },
+                Ok(HeaderElement::TrimZeros) => {
+                    match value.parse::<bool>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::TrimZeros, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            trim_zeros = val
+                        }
+                    }
+                },
+                Ok(HeaderElement::RegionOffset) => {
+                    match byte_count_str_to_num(value) {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::RegionOffset, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            region_offset = Some(val)
+                        }
+                    }
+                },
+                Ok(HeaderElement::ByteRangeBase) => {
+                    match value.parse::<ByteRangeBase>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::ByteRangeBase, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            absolute_ranges = val == ByteRangeBase::Absolute
+                        }
+                    }
+                },
+                Ok(HeaderElement::LeavesAndRoot) => {
+                    match value.parse::<bool>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::LeavesAndRoot, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            leaves_and_root = val
+                        }
+                    }
+                },
+                Ok(HeaderElement::ContentDefined) => {
+                    match value.parse::<bool>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::ContentDefined, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            content_defined = val
+                        }
+                    }
+                },
+                Ok(HeaderElement::NormalizeEol) => {
+                    match value.parse::<bool>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::NormalizeEol, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            normalize_eol = val
+                        }
+                    }
+                },
+                Ok(HeaderElement::RelativeNames) => {
+                    match value.parse::<bool>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::RelativeNames, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            relative_names = val
+                        }
+                    }
+                },
+                Ok(HeaderElement::NominalRanges) => {
+                    match value.parse::<bool>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::NominalRanges, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            nominal_ranges = val
+                        }
+                    }
+                },
+                Ok(HeaderElement::Stride) => {
+                    match size_str_to_num(value) {
+                        Ok(0) | Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::Stride, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            stride = Some(val)
+                        }
+                    }
+                },
+                Ok(HeaderElement::Filtered) => {
+                    match value.parse::<bool>() {
+                        Err(_) => {
+                            errors.push(HeaderParsingErr::BadParameterValue(
+                                HeaderElement::Filtered, value.to_owned()));
+                        },
+                        Ok(val) => {
+                            filtered = val
+                        }
+                    }
+                },
                 Err(_) => {
                     errors.push(
                         HeaderParsingErr::UnexpectedParameter(key.to_owned()));
@@ -149,61 +493,721 @@ impl TreeParams {
                 }
             }
         }
-        if let (Some(block_size), Some(branch_factor), Some(hash_function)) = (block_size_opt, branch_factor_opt, hash_function_opt) {
+        // The library only finds content-defined chunks for power of 2 sizes
+        if let (Some(block_size), true) = (block_size_opt, content_defined) {
+            if !block_size.is_power_of_two() {
+                errors.push(HeaderParsingErr::BadParameterValue(
+                    HeaderElement::BlockSize, block_size.to_string()));
+            }
+        }
+        // Trailing zeros and metadata are checked on the data as stored
+        if normalize_eol && (trim_zeros || metadata_only) {
+            errors.push(HeaderParsingErr::BadParameterValue(
+                HeaderElement::NormalizeEol, String::from("true")));
+        }
+        if filtered && (trim_zeros || metadata_only) {
+            errors.push(HeaderParsingErr::BadParameterValue(
+                HeaderElement::Filtered, String::from("true")));
+        }
+        // Metadata hashes cover the path as given when hashing
+        if relative_names && metadata_only {
+            errors.push(HeaderParsingErr::BadParameterValue(
+                HeaderElement::RelativeNames, String::from("true")));
+        }
+        // Chunks have no nominal size, and child lengths are taken from the byte ranges
+        if nominal_ranges && (content_defined || node_encoding == NodeEncoding::ChildCount) {
+            errors.push(HeaderParsingErr::BadParameterValue(
+                HeaderElement::NominalRanges, String::from("true")));
+        }
+        // Leaves cannot skip bytes, and overlapping leaves have neither chunks,
+        // nominal sizes, nor child lengths that match their byte ranges
+        if let Some(stride_val) = stride {
+            if block_size_opt.is_some_and(|block_size| stride_val > block_size)
+                    || content_defined || nominal_ranges
+                    || node_encoding == NodeEncoding::ChildCount {
+                errors.push(HeaderParsingErr::BadParameterValue(
+                    HeaderElement::Stride, stride_val.to_string()));
+            }
+        }
+        // Lines are no longer fixed, so unexpected lines can accompany valid ones
+        if let (Some(block_size), Some(branch_factor), Some(hash_function), true) = (block_size_opt, branch_factor_opt, hash_function_opt, errors.is_empty()) {
             Ok(TreeParams {
                 block_size,
                 branch_factor,
-                hash_function
+                hash_function,
+                node_encoding,
+                salted,
+                pad_tree,
+                unbalanced,
+                duplicate_last,
+                metadata_only,
+                trim_zeros,
+                region_offset,
+                absolute_ranges,
+                leaves_and_root,
+                content_defined,
+                normalize_eol,
+                relative_names,
+                nominal_ranges,
+                stride,
+                filtered
             })
         } else {
-            if block_size_opt.is_none() {
-                errors.push(
-                    HeaderParsingErr::MissingParameter(HeaderElement::BlockSize));
-            }
-            if branch_factor_opt.is_none() {
-                errors.push(
-                    HeaderParsingErr::MissingParameter(HeaderElement::BranchFactor));
-            }
-            if hash_function_opt.is_none() {
-                errors.push(
-                    HeaderParsingErr::MissingParameter(HeaderElement::HashFunction));
+            // A parameter with a rejected value is present, so not also missing
+            let missing: Vec<HeaderElement> = [
+                (HeaderElement::BlockSize, block_size_opt.is_none()),
+                (HeaderElement::BranchFactor, branch_factor_opt.is_none()),
+                (HeaderElement::HashFunction, hash_function_opt.is_none())
+            ].iter()
+                .filter(|(element, is_none)| *is_none && !errors.iter().any(|err|
+                    matches!(err, HeaderParsingErr::BadParameterValue(bad_element, _)
+                        if bad_element == element)))
+                .map(|(element, _)| *element)
+                .collect();
+            for element in missing {
+                errors.push(HeaderParsingErr::MissingParameter(element));
             }
             assert!(!errors.is_empty());
             Err(errors)
         }
     }
+    // The salt is not part of TreeParams, so it is passed in separately
+    pub fn tree_options(&self, salt: &[u8]) -> TreeOptions {
+        TreeOptions {
+            node_encoding: self.node_encoding,
+            salt: salt.to_vec(),
+            pad_tree: self.pad_tree,
+            promote_lone_children: self.unbalanced,
+            duplicate_last: self.duplicate_last,
+            content_defined: self.content_defined,
+            nominal_ranges: self.nominal_ranges,
+            stride: self.stride,
+            // Hash files record the leaf of an empty file as [0x00000000-0x00000000]
+            empty_leaf_range: false,
+            // Not recorded in the header, so set from --verify-positions by callers
+            verify_positions: false,
+            // Set from --stack-size and --read-retries by callers
+            stack_size: None,
+            read_retries: 0
+        }
+    }
+    // With leaves_and_root, the block range of the only internal node listed
+    pub fn listed_root(&self, file_len: u64) -> Option<BlockRange> {
+        match self.leaves_and_root {
+            true => {
+                let (tree_len, tree_block_size) = self.tree_shape(file_len);
+                Some(root_block_range(tree_len, tree_block_size, self.branch_factor))
+            },
+            false => None
+        }
+    }
+    // Names of the format features these parameters need, from KNOWN_FEATURES
+    pub fn features(&self) -> Vec<&'static str> {
+        [
+            ("salt", self.salted),
+            ("node-encoding", self.node_encoding != NodeEncoding::Plain),
+            ("padded-tree", self.pad_tree),
+            ("unbalanced", self.unbalanced),
+            ("duplicate-last", self.duplicate_last),
+            ("metadata-only", self.metadata_only),
+            ("trim-zeros", self.trim_zeros),
+            ("region", self.region_offset.is_some()),
+            ("absolute-ranges", self.absolute_ranges),
+            ("leaves-and-root", self.leaves_and_root),
+            ("content-defined", self.content_defined),
+            ("normalize-eol", self.normalize_eol),
+            ("relative-names", self.relative_names),
+            ("nominal-ranges", self.nominal_ranges),
+            ("stride", self.stride.is_some()),
+            ("filtered", self.filtered)
+        ].iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(feature, _)| *feature)
+            .collect()
+    }
+    pub fn byte_range_base(&self) -> ByteRangeBase {
+        match self.absolute_ranges {
+            true => ByteRangeBase::Absolute,
+            false => ByteRangeBase::Relative
+        }
+    }
+    // Amount to add to byte ranges computed within the region
+    pub fn byte_range_offset(&self) -> u64 {
+        match self.absolute_ranges {
+            true => self.region_offset.unwrap_or(0),
+            false => 0
+        }
+    }
+    // File length that byte ranges are cut off at, which is rounded up to
+    // a whole block with nominal_ranges
+    pub fn range_file_len(&self, file_len: u64) -> u64 {
+        match self.nominal_ranges {
+            true => file_len.next_multiple_of(self.block_size.into()),
+            false => file_len
+        }
+    }
+    // File length and block size that give the shape of the tree with
+    // merkle_block_generator, which is one byte blocks over the leaves with a stride
+    // Content-defined chunks depend on the data, so are not accounted for
+    pub fn tree_shape(&self, file_len: u64) -> (u64, block_t) {
+        match self.stride {
+            Some(stride) => (strided_leaf_count(file_len, self.block_size, stride), 1),
+            None => (file_len, self.block_size)
+        }
+    }
+    // Byte range of the node with block_range in the tree over file_len bytes,
+    // before byte_range_offset is added
+    pub fn node_byte_range(&self, block_range: BlockRange, file_len: u64) -> BlockRange {
+        match self.stride {
+            Some(stride) => strided_byte_range(block_range, self.block_size, stride, file_len),
+            None => block_byte_range(block_range, self.block_size, self.range_file_len(file_len))
+        }
+    }
 }
 impl fmt::Display for TreeParams {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         writeln!(fmt, "Hash function: {}", self.hash_function)?;
         writeln!(fmt, "Block size: {}", self.block_size)?;
         writeln!(fmt, "Branching factor: {}", self.branch_factor)?;
+        // Omitted when plain to keep older versions able to read the file
+        if self.node_encoding != NodeEncoding::Plain {
+            writeln!(fmt, "Node encoding: {}", self.node_encoding)?;
+        }
+        if self.salted {
+            writeln!(fmt, "Salted: true")?;
+        }
+        if self.pad_tree {
+            writeln!(fmt, "Padded tree: true")?;
+        }
+        if self.unbalanced {
+            writeln!(fmt, "Unbalanced tree: true")?;
+        }
+        if self.duplicate_last {
+            writeln!(fmt, "Duplicated last child: true")?;
+        }
+        if self.metadata_only {
+            writeln!(fmt, "Metadata only: true")?;
+        }
+        if self.trim_zeros {
+            writeln!(fmt, "Trimmed trailing zeros: true")?;
+        }
+        // The base only changes byte ranges when there is a region, and is
+        // always written then so that readers don't rely on a default
+        if let Some(region_offset) = self.region_offset {
+            writeln!(fmt, "Region offset: {}", region_offset)?;
+            writeln!(fmt, "Byte range base: {}", self.byte_range_base())?;
+        }
+        if self.leaves_and_root {
+            writeln!(fmt, "Leaves and root only: true")?;
+        }
+        if self.content_defined {
+            writeln!(fmt, "Content-defined chunks: true")?;
+        }
+        if self.normalize_eol {
+            writeln!(fmt, "Normalized line endings: true")?;
+        }
+        if self.relative_names {
+            writeln!(fmt, "Names relative to hash file: true")?;
+        }
+        if self.nominal_ranges {
+            writeln!(fmt, "Nominal byte ranges: true")?;
+        }
+        if let Some(stride) = self.stride {
+            writeln!(fmt, "Stride: {}", stride)?;
+        }
+        if self.filtered {
+            writeln!(fmt, "Filtered: true")?;
+        }
         Ok(())
     }
 }
 
-pub(crate) fn str_to_files(file_str: &str) -> Option<Vec<PathBuf>> {
+// Hashes the path, length, and modification time of a file
+// The 0x02 prefix keeps these distinct from leaf and internal node hashes
+// Modification time as seconds and nanoseconds since the Unix epoch
+pub(crate) fn mtime_since_epoch(metadata: &fs::Metadata) -> IOResult<(i64, u32)> {
+    Ok(match metadata.modified()?.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(after_epoch) => (i64::try_from(after_epoch.as_secs()).unwrap(),
+            after_epoch.subsec_nanos()),
+        Err(err) => {
+            // Keep nanoseconds nonnegative for times before the epoch
+            let before_epoch = err.duration();
+            match before_epoch.subsec_nanos() {
+                0 => (-i64::try_from(before_epoch.as_secs()).unwrap(), 0),
+                nanos => (-i64::try_from(before_epoch.as_secs()).unwrap() - 1,
+                    1_000_000_000 - nanos)
+            }
+        }
+    })
+}
+
+pub(crate) fn metadata_hash(path: &Path, hash_function: HashFunctions)
+        -> IOResult<HashData<64>> {
+    let metadata = path.metadata()?;
+    let (mtime_secs, mtime_nanos) = mtime_since_epoch(&metadata)?;
+    let path_str = path.to_string_lossy();
+    let mut hash_input: Vec<u8> = vec![0x02];
+    hash_input.extend_from_slice(&(path_str.len() as u64).to_be_bytes());
+    hash_input.extend_from_slice(path_str.as_bytes());
+    hash_input.extend_from_slice(&metadata.len().to_be_bytes());
+    hash_input.extend_from_slice(&mtime_secs.to_be_bytes());
+    hash_input.extend_from_slice(&mtime_nanos.to_be_bytes());
+    Ok(hash_function.digest(&hash_input))
+}
+
+// Writes everything before the hash entries, ending with the "Hashes:" line
+// File entries are paired with the hashed length, if shorter than the file
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn write_hash_header<W: Write>(writer: &mut W,
+        tree_params: &TreeParams, comments: &[String],
+        structure_hash: Option<&HashData<64>>,
+        file_entries: &[(&[PathBuf], u64, Option<u64>)],
+        dir_list: &[PathBuf], short_output: bool, sectioned: bool,
+        uppercase: bool) -> IOResult<()> {
+    writeln!(writer, "{} v{}", crate_name!(), FORMAT_VERSION)?;
+    let mut features = tree_params.features();
+    if structure_hash.is_some() {
+        features.push("structure-hash");
+    }
+    if sectioned {
+        features.push("sectioned");
+    }
+    // Hash files with default parameters have no features line
+    if !features.is_empty() {
+        writeln!(writer, "{}{}", FEATURES_PREFIX, features.join(", "))?;
+    }
+    for comment in comments {
+        writeln!(writer, "Comment: {}",
+            enquote::enquote('"', &escape_chars(comment)))?;
+    }
+    // tree_params Display impl includes ending newline
+    write!(writer, "{}", tree_params)?;
+    if let Some(structure_hash) = structure_hash {
+        writeln!(writer, "{}{}", STRUCTURE_HASH_PREFIX, hash_hex(structure_hash, uppercase))?;
+    }
+
+    if !short_output {
+        writeln!(writer, "Files:")?;
+        let list_str: Vec<String> = file_entries.iter()
+            .map(|(paths, total_len, hashed_len)| {
+                match hashed_len {
+                    Some(hashed_len) => format!("{} {:#x} bytes ({:#x} hashed)",
+                        quote_name_group(paths), total_len, hashed_len),
+                    None => format!("{} {:#x} bytes", quote_name_group(paths), total_len)
+                }
+            })
+            .collect();
+        writeln!(writer, "{}", list_str.join("\n"))?;
+        for dir_path in dir_list {
+            // Names are checked to be UTF-8 when found, so nothing is lost
+            let escaped_str = escape_chars(&dir_path.to_string_lossy());
+            writeln!(writer, "{} empty directory",
+                enquote::enquote('"', &escaped_str))?;
+        }
+    }
+    writeln!(writer, "Hashes:")?;
+    writer.flush()
+}
+
+// Sidecar hash files are named after the input file, e.g. "a.txt.mrkl"
+// Inputs sharing a file name get a counter appended, e.g. "a.txt.1.mrkl"
+pub(crate) fn sidecar_paths(inputs: &[&Path], output_dir: &Path) -> Vec<PathBuf> {
+    let mut used_names: HashSet<String> = HashSet::new();
+    inputs.iter()
+        .map(|input| {
+            let base_name = input.file_name().unwrap().to_string_lossy();
+            let mut candidate = format!("{}.mrkl", base_name);
+            let mut counter: usize = 1;
+            while !used_names.insert(candidate.clone()) {
+                candidate = format!("{}.{}.mrkl", base_name, counter);
+                counter += 1;
+            }
+            output_dir.join(candidate)
+        })
+        .collect()
+}
+
+// Concatenated inputs are written as their quoted parts joined by " + "
+pub(crate) fn quote_name_group(parts: &[PathBuf]) -> String {
+    parts.iter()
+        .map(|part| {
+            // Names are checked to be UTF-8 when found, so nothing is lost
+            let escaped_str = escape_chars(&part.to_string_lossy());
+            enquote::enquote('"', &escaped_str)
+        })
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+// Used in messages and to match short hash entries to their inputs
+pub(crate) fn display_name_group<T: AsRef<Path>>(parts: &[T]) -> String {
+    parts.iter()
+        .map(|part| part.as_ref().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+// Lexical only, so that ./a and a compare equal even if a does not exist
+fn normalize_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+// Returns files on disk that are not recorded, and recorded files under
+// scan_dir that are not on disk, both sorted
+pub(crate) fn compare_scanned_files<'a>(recorded: impl IntoIterator<Item = &'a PathBuf>,
+        on_disk: &[PathBuf], scan_dir: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let scan_dir = normalize_path(scan_dir);
+    let recorded: BTreeSet<PathBuf> = recorded.into_iter()
+        .map(|path| normalize_path(path))
+        .filter(|path| path.starts_with(&scan_dir))
+        .collect();
+    let on_disk: BTreeSet<PathBuf> = on_disk.iter()
+        .map(|path| normalize_path(path))
+        .collect();
+    (
+        on_disk.difference(&recorded).cloned().collect(),
+        recorded.difference(&on_disk).cloned().collect()
+    )
+}
+
+// Path that leads from base_dir to path, with .. for each directory to leave
+// Both should be canonical, as symlinks and .. components are not resolved
+// path is returned as is if they share no root, as with different drives
+pub(crate) fn path_relative_to(path: &Path, base_dir: &Path) -> PathBuf {
+    let mut path_components = path.components().peekable();
+    let mut base_components = base_dir.components().peekable();
+    if path_components.peek() != base_components.peek() {
+        return path.to_path_buf();
+    }
+    while path_components.peek().is_some()
+            && path_components.peek() == base_components.peek() {
+        path_components.next();
+        base_components.next();
+    }
+    base_components.map(|_| Component::ParentDir)
+        .chain(path_components)
+        .collect()
+}
+
+// list_name is the part of an @list argument after the @, with - for stdin
+pub(crate) fn read_path_list(list_name: &str) -> Result<Vec<String>, PathListError> {
+    match list_name {
+        "-" => parse_path_list(std::io::stdin().lock()),
+        _ => parse_path_list(std::io::BufReader::new(fs::File::open(list_name)?))
+    }
+}
+
+// Same as read_path_list, for the NUL-delimited list of --files0-from
+pub(crate) fn read_nul_path_list(list_name: &str) -> Result<Vec<String>, PathListError> {
+    match list_name {
+        "-" => parse_nul_path_list(std::io::stdin().lock()),
+        _ => parse_nul_path_list(fs::File::open(list_name)?)
+    }
+}
+
+// Separated from the WalkDir iterator so that walk errors can be tested
+// Returns the files found and the errors that were skipped
+fn files_from_walk<I, E>(entries: I, skip_unreadable: bool)
+        -> Result<(Vec<PathBuf>, Vec<E>), E>
+where
+    I: IntoIterator<Item = Result<PathBuf, E>>
+{
     let mut file_list = Vec::<PathBuf>::new();
+    let mut skipped = Vec::<E>::new();
+    for entry in entries {
+        match entry {
+            Ok(entry_path) => {
+                if entry_path.is_file() {
+                    file_list.push(entry_path);
+                }
+            },
+            Err(err) if skip_unreadable => skipped.push(err),
+            Err(err) => return Err(err)
+        }
+    }
+    Ok((file_list, skipped))
+}
+
+// Depths are relative to file_str and only apply when it is a directory
+// Depth 1 refers to the entries directly inside the directory
+// Files are found as the iterator is advanced, and walk errors are passed on
+pub(crate) fn walk_files(file_str: &str, min_depth: usize, max_depth: Option<usize>)
+        -> Result<Box<dyn Iterator<Item = Result<PathBuf, walkdir::Error>>>, PreHashError> {
     let file_path = Path::new(&file_str);
     if file_path.is_file() {
-        file_list.push(file_path.to_path_buf());
+        Ok(Box::new(std::iter::once(Ok(file_path.to_path_buf()))))
     } else if file_path.is_dir() {
-        // Walk directory to find all the files in it
-        for entry in WalkDir::new(file_path).min_depth(1).follow_links(true) {
-            let entry_unwrap = entry.unwrap();
-            let entry_path = entry_unwrap.path();
-            if entry_path.is_file() {
-                file_list.push(entry_path.to_path_buf());
-            }
+        let mut walker = WalkDir::new(file_path).min_depth(min_depth)
+            .follow_links(true);
+        if let Some(depth) = max_depth {
+            walker = walker.max_depth(depth);
         }
+        Ok(Box::new(walker.into_iter()
+            .map(|entry| entry.map(walkdir::DirEntry::into_path))
+            .filter(|entry| entry.as_ref().map_or(true, |path| path.is_file()))))
     } else {
-        return None;
+        Err(PreHashError::FileNotFound)
+    }
+}
+
+// Names found in directories may not be UTF-8, unlike names given as arguments
+// These are rejected rather than written lossily, as they could not be verified
+pub(crate) fn check_utf8_name(path: &Path) -> Result<(), PreHashError> {
+    match path.to_str() {
+        Some(_) => Ok(()),
+        None => Err(PreHashError::NonUtf8Name(format!("{:?}", path)))
+    }
+}
+
+// Uses the same depth semantics as walk_files, with every file found up front
+pub(crate) fn str_to_files(file_str: &str, min_depth: usize,
+        max_depth: Option<usize>, skip_unreadable: bool)
+        -> Result<(Vec<PathBuf>, Vec<walkdir::Error>), PreHashError> {
+    let entries = walk_files(file_str, min_depth, max_depth)?;
+    let (file_list, skipped) = files_from_walk(entries, skip_unreadable)
+        .map_err(|err| PreHashError::UnreadableDirEntry(err.to_string()))?;
+    for file_path in &file_list {
+        check_utf8_name(file_path)?;
+    }
+    Ok((file_list, skipped))
+}
+
+// Uses the same depth semantics as str_to_files
+pub(crate) fn str_to_empty_dirs(file_str: &str, min_depth: usize,
+        max_depth: Option<usize>) -> Result<Vec<PathBuf>, PreHashError> {
+    let mut dir_list = Vec::<PathBuf>::new();
+    let file_path = Path::new(&file_str);
+    if file_path.is_dir() {
+        let mut walker = WalkDir::new(file_path).min_depth(min_depth)
+            .follow_links(true);
+        if let Some(depth) = max_depth {
+            walker = walker.max_depth(depth);
+        }
+        // Walk errors are reported by str_to_files on the same directory
+        for entry in walker.into_iter().filter_map(Result::ok) {
+            let entry_path = entry.path();
+            if entry_path.is_dir() && is_empty_dir(entry_path) {
+                check_utf8_name(entry_path)?;
+                dir_list.push(entry_path.to_path_buf());
+            }
+        }
+    }
+    Ok(dir_list)
+}
+
+// Unreadable directories are not considered empty
+pub(crate) fn is_empty_dir(dir_path: &Path) -> bool {
+    match fs::read_dir(dir_path) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => false
+    }
+}
+
+// Default redraw rates, unless --progress-hz is given
+pub(crate) const STDERR_PROGRESS_HZ: u8 = 5;
+// Redraws are appended, so draw less often to keep logs short
+pub(crate) const FILE_PROGRESS_HZ: u8 = 1;
+
+// Where progress bars are drawn, shared by the bars of every file,
+// and how many times per second they are redrawn
+// A rate of 0 only draws the bars when they start and finish
+#[derive(Debug, Clone)]
+pub(crate) enum ProgressOutput {
+    Hidden,
+    Stderr(u8),
+    File(Arc<Mutex<ProgressFile>>, u8)
+}
+impl ProgressOutput {
+    pub(crate) fn to_file(file: fs::File, refresh_hz: u8) -> Self {
+        Self::File(Arc::new(Mutex::new(ProgressFile {file, mid_line: false})), refresh_hz)
+    }
+    pub(crate) fn draw_target(&self) -> ProgressDrawTarget {
+        match self {
+            Self::Hidden => ProgressDrawTarget::hidden(),
+            Self::Stderr(0) => ProgressDrawTarget::term_like(
+                Box::new(EndpointsTerm::new(std::io::stderr()))),
+            Self::Stderr(refresh_hz) => ProgressDrawTarget::stderr_with_hz(*refresh_hz),
+            Self::File(progress_file, 0) => ProgressDrawTarget::term_like(
+                Box::new(EndpointsTerm::new(ProgressFileLines(Arc::clone(progress_file))))),
+            Self::File(progress_file, refresh_hz) => ProgressDrawTarget::term_like_with_hz(
+                Box::new(ProgressFileTerm(Arc::clone(progress_file))), *refresh_hz)
+        }
+    }
+}
+
+// Files can't move the cursor, so each redraw is written below the last
+#[derive(Debug)]
+pub(crate) struct ProgressFile {
+    file: fs::File,
+    // Whether the last write left an unfinished line
+    mid_line: bool
+}
+impl ProgressFile {
+    fn end_line(&mut self) -> IOResult<()> {
+        if self.mid_line {
+            writeln!(self.file)?;
+            self.mid_line = false;
+        }
+        Ok(())
+    }
+}
+impl Drop for ProgressFile {
+    fn drop(&mut self) {
+        let _ = self.end_line();
+    }
+}
+
+#[derive(Debug)]
+struct ProgressFileTerm(Arc<Mutex<ProgressFile>>);
+impl TermLike for ProgressFileTerm {
+    fn width(&self) -> u16 {
+        80
+    }
+    // Moving back up to redraw starts a new line instead
+    fn move_cursor_up(&self, _n: usize) -> IOResult<()> {
+        self.0.lock().unwrap().end_line()
+    }
+    fn move_cursor_down(&self, _n: usize) -> IOResult<()> {
+        Ok(())
+    }
+    fn move_cursor_right(&self, _n: usize) -> IOResult<()> {
+        Ok(())
+    }
+    fn move_cursor_left(&self, _n: usize) -> IOResult<()> {
+        Ok(())
+    }
+    fn write_line(&self, s: &str) -> IOResult<()> {
+        let mut progress_file = self.0.lock().unwrap();
+        writeln!(progress_file.file, "{}", s)?;
+        progress_file.mid_line = false;
+        Ok(())
+    }
+    fn write_str(&self, s: &str) -> IOResult<()> {
+        // Padding to the terminal width is only needed on a terminal
+        if s.trim().is_empty() {
+            return Ok(());
+        }
+        let mut progress_file = self.0.lock().unwrap();
+        write!(progress_file.file, "{}", s)?;
+        progress_file.mid_line = true;
+        Ok(())
+    }
+    fn clear_line(&self) -> IOResult<()> {
+        self.0.lock().unwrap().end_line()
+    }
+    fn flush(&self) -> IOResult<()> {
+        self.0.lock().unwrap().file.flush()
+    }
+}
+
+// Writes whole lines to the progress file, after any unfinished line
+#[derive(Debug)]
+struct ProgressFileLines(Arc<Mutex<ProgressFile>>);
+impl Write for ProgressFileLines {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        let mut progress_file = self.0.lock().unwrap();
+        progress_file.end_line()?;
+        progress_file.file.write(buf)
+    }
+    fn flush(&mut self) -> IOResult<()> {
+        self.0.lock().unwrap().file.flush()
+    }
+}
+
+// Lines of the last frame drawn, and the line the cursor is on
+#[derive(Debug, Default)]
+struct Frame {
+    lines: Vec<String>,
+    cursor: usize,
+    // The first frame, once it has been written
+    first_written: Option<Vec<String>>
+}
+impl Frame {
+    fn cursor_line(&mut self) -> &mut String {
+        if self.cursor >= self.lines.len() {
+            self.lines.resize(self.cursor + 1, String::new());
+        }
+        &mut self.lines[self.cursor]
+    }
+    fn write_to<W: Write>(&self, output: &mut W) -> IOResult<()> {
+        for line in &self.lines {
+            writeln!(output, "{}", line.trim_end())?;
+        }
+        output.flush()
+    }
+}
+
+// Draws the bars in memory, writing only the first frame and the last one,
+// which is written below the first once the bars are dropped
+#[derive(Debug)]
+struct EndpointsTerm<W: Write + Send + fmt::Debug> {
+    output: Mutex<W>,
+    frame: Mutex<Frame>
+}
+impl<W: Write + Send + fmt::Debug> EndpointsTerm<W> {
+    fn new(output: W) -> Self {
+        Self {output: Mutex::new(output), frame: Mutex::new(Frame::default())}
+    }
+}
+impl<W: Write + Send + fmt::Debug> Drop for EndpointsTerm<W> {
+    fn drop(&mut self) {
+        let frame = self.frame.get_mut().unwrap();
+        if frame.first_written.as_ref().is_some_and(|first| *first != frame.lines) {
+            let _ = frame.write_to(self.output.get_mut().unwrap());
+        }
+    }
+}
+impl<W: Write + Send + fmt::Debug> TermLike for EndpointsTerm<W> {
+    // Wide enough that lines never wrap, which would move the cursor by more lines
+    fn width(&self) -> u16 {
+        500
+    }
+    fn move_cursor_up(&self, n: usize) -> IOResult<()> {
+        let mut frame = self.frame.lock().unwrap();
+        frame.cursor = frame.cursor.saturating_sub(n);
+        Ok(())
+    }
+    fn move_cursor_down(&self, n: usize) -> IOResult<()> {
+        self.frame.lock().unwrap().cursor += n;
+        Ok(())
+    }
+    fn move_cursor_right(&self, _n: usize) -> IOResult<()> {
+        Ok(())
+    }
+    fn move_cursor_left(&self, _n: usize) -> IOResult<()> {
+        Ok(())
+    }
+    fn write_line(&self, s: &str) -> IOResult<()> {
+        let mut frame = self.frame.lock().unwrap();
+        frame.cursor_line().push_str(s);
+        frame.cursor += 1;
+        Ok(())
+    }
+    fn write_str(&self, s: &str) -> IOResult<()> {
+        self.frame.lock().unwrap().cursor_line().push_str(s);
+        Ok(())
+    }
+    fn clear_line(&self) -> IOResult<()> {
+        self.frame.lock().unwrap().cursor_line().clear();
+        Ok(())
+    }
+    // Each frame ends with a flush
+    fn flush(&self) -> IOResult<()> {
+        let mut frame = self.frame.lock().unwrap();
+        if frame.first_written.is_none() {
+            frame.write_to(&mut *self.output.lock().unwrap())?;
+            frame.first_written = Some(frame.lines.clone());
+        }
+        Ok(())
     }
-    return Some(file_list);
 }
 
 pub(crate) fn setup_pbs(pb_draw_target: ProgressDrawTarget, file_size: u64, pb_hash_len: u64) -> (ProgressBar, ProgressBar) {
-    let pb_holder = MultiProgress::with_draw_target(pb_draw_target);
+    // Both bars are drawn once to a discarded target first,
+    // so that every frame drawn to pb_draw_target has both of them
+    let pb_holder = MultiProgress::with_draw_target(ProgressDrawTarget::term_like(
+        Box::new(EndpointsTerm::new(std::io::sink()))));
 
     let pb_file = pb_holder.add(ProgressBar::new(file_size));
     let pb_hash = pb_holder.add(ProgressBar::new(pb_hash_len));
@@ -222,5 +1226,173 @@ pub(crate) fn setup_pbs(pb_draw_target: ProgressDrawTarget, file_size: u64, pb_h
     pb_file.set_message("File");
     pb_hash.set_message("Hash");
 
+    pb_file.tick();
+    pb_hash.tick();
+    pb_holder.set_draw_target(pb_draw_target);
     (pb_file, pb_hash)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn make_nested_tree() -> tempfile::TempDir {
+        // dir/a, dir/l1/b, dir/l1/l2/c
+        let tree_dir = tempdir().unwrap();
+        let level_2 = tree_dir.path().join("l1").join("l2");
+        fs::create_dir_all(&level_2).unwrap();
+        fs::write(tree_dir.path().join("a"), b"a").unwrap();
+        fs::write(tree_dir.path().join("l1").join("b"), b"b").unwrap();
+        fs::write(level_2.join("c"), b"c").unwrap();
+        tree_dir
+    }
+    fn file_names(paths: Vec<PathBuf>) -> Vec<String> {
+        let mut names: Vec<_> = paths.iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap().to_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    // Total length of the long entries actually written for data
+    fn written_entry_len(data: &[u8], block_size: block_t, branch: branch_t) -> u64 {
+        let entries = merkle_tree::hash_file_to_vec::<_, sha2::Sha256>(
+            std::io::Cursor::new(data), block_size, branch, 0).unwrap();
+        entries.iter()
+            .map(|entry| format!("{:3} {} {} {}\n", 0, entry.block_range(),
+                entry.byte_range(), hex::encode(entry.hash_result())).len() as u64)
+            .sum()
+    }
+
+    #[test]
+    fn long_format_size_matches_written_entries() {
+        let data = vec![0x5a; 1000];
+        for (file_size, block_size, branch) in [(0, 4, 2), (16, 4, 4), (21, 4, 4),
+                (1000, 16, 3), (1000, 1, 2)] {
+            let size = long_format_size(file_size, block_size, branch, 32);
+            let data = &data[..file_size as usize];
+            assert_eq!(size.entry_bytes, written_entry_len(data, block_size, branch),
+                "{} bytes with block size {} and branch {}", file_size, block_size, branch);
+            assert_eq!(size.leaf_nodes + size.internal_nodes,
+                merkle_tree::node_count(file_size, block_size, branch));
+        }
+    }
+
+    #[test]
+    fn long_format_size_examples() {
+        assert_eq!(long_format_size(16, 4, 4, 32),
+            LongFormatSize {leaf_nodes: 4, internal_nodes: 1, entry_bytes: 5*117});
+        assert_eq!(long_format_size(0, 4096, 4, 4),
+            LongFormatSize {leaf_nodes: 1, internal_nodes: 0, entry_bytes: 61});
+        // Ranges past 32 bits get wider
+        let large = long_format_size(1 << 40, 4096, 4, 32);
+        assert_eq!(large.leaf_nodes, 1 << 28);
+        assert_eq!(large.entry_bytes, (large.leaf_nodes + large.internal_nodes) * 121);
+    }
+
+    #[test]
+    fn str_to_files_depth_cutoff() {
+        let tree_dir = make_nested_tree();
+        let dir_str = tree_dir.path().to_str().unwrap();
+
+        let all_files = str_to_files(dir_str, 1, None, false).unwrap().0;
+        assert_eq!(file_names(all_files), ["a", "b", "c"]);
+        let top_files = str_to_files(dir_str, 1, Some(1), false).unwrap().0;
+        assert_eq!(file_names(top_files), ["a"]);
+        let two_levels = str_to_files(dir_str, 1, Some(2), false).unwrap().0;
+        assert_eq!(file_names(two_levels), ["a", "b"]);
+        let deep_files = str_to_files(dir_str, 3, None, false).unwrap().0;
+        assert_eq!(file_names(deep_files), ["c"]);
+    }
+
+    #[test]
+    fn files_from_walk_errors() {
+        let tree_dir = make_nested_tree();
+        let entries = || vec![
+            Ok(tree_dir.path().join("a")),
+            Err("unreadable l1"),
+            Ok(tree_dir.path().join("l1")),
+            Ok(tree_dir.path().join("l1").join("b"))
+        ];
+
+        assert_eq!(files_from_walk(entries(), false), Err("unreadable l1"));
+        let (files, skipped) = files_from_walk(entries(), true).unwrap();
+        assert_eq!(file_names(files), ["a", "b"]);
+        assert_eq!(skipped, ["unreadable l1"]);
+    }
+
+    #[test]
+    fn str_to_empty_dirs_finds_leaves() {
+        let tree_dir = make_nested_tree();
+        let empty_path = tree_dir.path().join("l1").join("empty");
+        fs::create_dir(&empty_path).unwrap();
+        let dir_str = tree_dir.path().to_str().unwrap();
+
+        assert_eq!(str_to_empty_dirs(dir_str, 1, None).unwrap(), [empty_path]);
+        assert!(str_to_empty_dirs(dir_str, 1, Some(1)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn path_relative_to_parents() {
+        assert_eq!(path_relative_to(Path::new("/data/bundle/a"), Path::new("/data/bundle")),
+            PathBuf::from("a"));
+        assert_eq!(path_relative_to(Path::new("/data/files/a"), Path::new("/data/hashes/out")),
+            PathBuf::from("../../files/a"));
+        assert_eq!(path_relative_to(Path::new("/a"), Path::new("/")), PathBuf::from("a"));
+        assert_eq!(path_relative_to(Path::new("/a"), Path::new("relative")), PathBuf::from("/a"));
+    }
+    #[test]
+    fn compare_scanned_files_categories() {
+        let recorded = [PathBuf::from("./dir/a"), PathBuf::from("dir/b"),
+            PathBuf::from("elsewhere/c")];
+        let on_disk = [PathBuf::from("dir/a"), PathBuf::from("dir/d")];
+        let (extra, missing) = compare_scanned_files(&recorded, &on_disk,
+            Path::new("./dir"));
+        assert_eq!(extra, [PathBuf::from("dir/d")]);
+        assert_eq!(missing, [PathBuf::from("dir/b")]);
+    }
+
+    #[test]
+    fn sidecar_paths_collisions() {
+        let inputs = [Path::new("x/a.1"), Path::new("x/a"),
+            Path::new("y/a"), Path::new("z/a")];
+        let sidecars = sidecar_paths(&inputs, Path::new("out"));
+        assert_eq!(sidecars, [Path::new("out/a.1.mrkl"), Path::new("out/a.mrkl"),
+            Path::new("out/a.2.mrkl"), Path::new("out/a.3.mrkl")]);
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> IOResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn endpoints_drawn_once() {
+        let output = SharedOutput::default();
+        let (pb_file, pb_hash) = setup_pbs(ProgressDrawTarget::term_like(
+            Box::new(EndpointsTerm::new(output.clone()))), 9, 4);
+        // Drawn for the first time at the first update
+        pb_file.inc(1);
+        for _ in 0..4 {
+            pb_file.inc(2);
+            pb_hash.inc(1);
+        }
+        pb_file.finish();
+        pb_hash.finish();
+        drop((pb_file, pb_hash));
+        let drawn = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let drawn_lines: Vec<&str> = drawn.lines().collect();
+        assert_eq!(drawn_lines.len(), 4, "{}", drawn);
+        assert!(drawn_lines[0].starts_with("File") && drawn_lines[0].contains(" 1 B/"));
+        assert!(drawn_lines[1].starts_with("Hash") && drawn_lines[1].contains(" 0/"));
+        assert!(drawn_lines[2].starts_with("File") && drawn_lines[2].contains(" 9 B/"));
+        assert!(drawn_lines[3].starts_with("Hash") && drawn_lines[3].contains(" 4/"));
+    }
+}
@@ -0,0 +1,170 @@
+#![forbid(unsafe_code)]
+// Skips files that verified successfully before and have not changed since
+
+use crate::parse_functions::unquote_name_group;
+use crate::utils::{mtime_since_epoch, quote_name_group};
+
+use clap::crate_name;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+
+/*
+ * Cache file format, one entry per line after the two header lines:
+ *   merkle_tree_checksum verify cache v1
+ *   Hash file: {stamp} "hash file name"
+ *   {ok|failed} {stamp} "file name"[ + "file name"...]
+ * A stamp is the modification times and lengths of the files, with the
+ * values for each part of a concatenated entry separated by commas
+ * Results are appended as files are verified, so later lines override
+ * earlier ones, and the file is rewritten without them when opened
+ * An unchanged stamp does not prove unchanged contents, since writes can
+ * preserve both the modification time and the length
+ */
+const CACHE_VERSION: &str = "v1";
+
+// Stamp of the files of an entry, compared to decide whether to skip it
+pub(crate) fn entry_stamp(paths: &[PathBuf]) -> IOResult<String> {
+    let mut mtimes: Vec<String> = Vec::with_capacity(paths.len());
+    let mut lens: Vec<String> = Vec::with_capacity(paths.len());
+    for path in paths {
+        let metadata = path.metadata()?;
+        let (mtime_secs, mtime_nanos) = mtime_since_epoch(&metadata)?;
+        mtimes.push(format!("{}.{:09}", mtime_secs, mtime_nanos));
+        lens.push(format!("{:#x}", metadata.len()));
+    }
+    Ok(format!("{} {}", mtimes.join(","), lens.join(",")))
+}
+
+#[derive(Debug)]
+pub(crate) struct VerifyCache {
+    file: File,
+    // Stamps of entries whose last verification succeeded, by quoted name
+    verified: BTreeMap<String, String>
+}
+impl VerifyCache {
+    // Entries recorded against a different or changed hash file are dropped
+    pub fn open(cache_path: &Path, hash_file_path: &Path) -> IOResult<Self> {
+        let hash_file_line = format!("Hash file: {} {}",
+            entry_stamp(&[hash_file_path.to_path_buf()])?,
+            quote_name_group(&[hash_file_path.to_path_buf()]));
+        let old_contents = match fs::read_to_string(cache_path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err)
+        };
+        let version_line = format!("{} verify cache {}", crate_name!(), CACHE_VERSION);
+
+        let mut verified = BTreeMap::new();
+        let mut old_lines = old_contents.lines();
+        if old_lines.next() == Some(version_line.as_str())
+                && old_lines.next() == Some(hash_file_line.as_str()) {
+            // Malformed lines are skipped, since the files get verified anyway
+            for line in old_lines {
+                let mut line_parts = line.splitn(4, ' ');
+                let (result, mtimes, lens, quoted_names) = match (line_parts.next(),
+                        line_parts.next(), line_parts.next(), line_parts.next()) {
+                    (Some(result), Some(mtimes), Some(lens), Some(quoted_names)) =>
+                        (result, mtimes, lens, quoted_names),
+                    _ => continue
+                };
+                if unquote_name_group(quoted_names).is_err() {
+                    continue;
+                }
+                match result {
+                    "ok" => {
+                        verified.insert(quoted_names.to_owned(),
+                            format!("{} {}", mtimes, lens));
+                    },
+                    "failed" => {
+                        verified.remove(quoted_names);
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        let mut new_contents = format!("{}\n{}\n", version_line, hash_file_line);
+        for (quoted_names, stamp) in verified.iter() {
+            new_contents += &format!("ok {} {}\n", stamp, quoted_names);
+        }
+        fs::write(cache_path, new_contents)?;
+        let file = OpenOptions::new().append(true).open(cache_path)?;
+        Ok(Self {file, verified})
+    }
+    pub fn is_verified(&self, paths: &[PathBuf], stamp: &str) -> bool {
+        self.verified.get(&quote_name_group(paths))
+            .is_some_and(|verified_stamp| verified_stamp == stamp)
+    }
+    // The stamp should be taken before hashing, in case the files change meanwhile
+    pub fn record(&mut self, paths: &[PathBuf], stamp: &str, is_ok: bool) -> IOResult<()> {
+        let quoted_names = quote_name_group(paths);
+        let result = match is_ok {
+            true => "ok",
+            false => "failed"
+        };
+        // Written at once so that an interrupted run leaves whole lines
+        self.file.write_all(format!("{} {} {}\n", result, stamp, quoted_names).as_bytes())?;
+        match is_ok {
+            true => self.verified.insert(quoted_names, stamp.to_owned()),
+            false => self.verified.remove(&quoted_names)
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn cache_keeps_latest_result() {
+        let test_dir = tempdir().unwrap();
+        let hash_path = test_dir.path().join("hash_out");
+        let data_path = test_dir.path().join("data");
+        let cache_path = test_dir.path().join("cache");
+        fs::write(&hash_path, b"hashes").unwrap();
+        fs::write(&data_path, b"data").unwrap();
+        let data_paths = [data_path];
+        let stamp = entry_stamp(&data_paths).unwrap();
+
+        let mut cache = VerifyCache::open(&cache_path, &hash_path).unwrap();
+        assert!(!cache.is_verified(&data_paths, &stamp));
+        cache.record(&data_paths, &stamp, true).unwrap();
+        assert!(cache.is_verified(&data_paths, &stamp));
+        drop(cache);
+
+        let mut cache = VerifyCache::open(&cache_path, &hash_path).unwrap();
+        assert!(cache.is_verified(&data_paths, &stamp));
+        assert!(!cache.is_verified(&data_paths, "0.000000000 0x4"));
+        cache.record(&data_paths, &stamp, false).unwrap();
+        drop(cache);
+
+        let cache = VerifyCache::open(&cache_path, &hash_path).unwrap();
+        assert!(!cache.is_verified(&data_paths, &stamp));
+    }
+
+    #[test]
+    fn cache_dropped_for_changed_hash_file() {
+        let test_dir = tempdir().unwrap();
+        let hash_path = test_dir.path().join("hash_out");
+        let data_path = test_dir.path().join("data");
+        let cache_path = test_dir.path().join("cache");
+        fs::write(&hash_path, b"hashes").unwrap();
+        fs::write(&data_path, b"data").unwrap();
+        let data_paths = [data_path];
+        let stamp = entry_stamp(&data_paths).unwrap();
+
+        let mut cache = VerifyCache::open(&cache_path, &hash_path).unwrap();
+        cache.record(&data_paths, &stamp, true).unwrap();
+        drop(cache);
+
+        fs::write(&hash_path, b"other hashes").unwrap();
+        let cache = VerifyCache::open(&cache_path, &hash_path).unwrap();
+        assert!(!cache.is_verified(&data_paths, &stamp));
+    }
+}
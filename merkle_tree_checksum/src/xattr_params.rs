@@ -0,0 +1,41 @@
+#![forbid(unsafe_code)]
+// Tree parameters stored in an extended attribute of a data file, for --params-from-xattr
+
+use crate::error_types::XattrParamsErr;
+use crate::utils::TreeParams;
+
+use std::path::Path;
+
+// The value has one "Key: value" line per parameter, as in a hash file header
+pub(crate) const PARAMS_XATTR_NAME: &str = "user.merkle.params";
+
+pub(crate) fn read_xattr_params(path: &Path) -> Result<TreeParams, XattrParamsErr> {
+    let value = String::from_utf8(read_params_xattr(path)?)
+        .map_err(|_| XattrParamsErr::NotUtf8)?;
+    let lines: Vec<String> = value.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_owned)
+        .collect();
+    TreeParams::from_lines(&lines).map_err(XattrParamsErr::BadValue)
+}
+
+#[cfg(unix)]
+fn read_params_xattr(path: &Path) -> Result<Vec<u8>, XattrParamsErr> {
+    if !xattr::SUPPORTED_PLATFORM {
+        return Err(XattrParamsErr::Unsupported);
+    }
+    // Symlinks to data files are hashed as the files they point to
+    match xattr::get_deref(path, PARAMS_XATTR_NAME) {
+        Ok(Some(value)) => Ok(value),
+        Ok(None) => Err(XattrParamsErr::Absent),
+        Err(err) if err.kind() == std::io::ErrorKind::Unsupported =>
+            Err(XattrParamsErr::Unsupported),
+        Err(err) => Err(XattrParamsErr::ReadError(err.kind()))
+    }
+}
+
+#[cfg(not(unix))]
+fn read_params_xattr(_path: &Path) -> Result<Vec<u8>, XattrParamsErr> {
+    Err(XattrParamsErr::Unsupported)
+}
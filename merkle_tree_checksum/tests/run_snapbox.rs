@@ -1,4 +1,5 @@
 use snapbox::cmd::{Command, cargo_bin};
+use snapbox::IntoData;
 
 use std::path::PathBuf;
 use tempfile::tempdir;
@@ -28,4 +29,3735 @@ fn gen_and_verify_roundtrip() {
         .args(&["verify-hash", "--", "hash_out"])
         .assert()
         .success();
-}
\ No newline at end of file
+}
+#[test]
+fn empty_dir_roundtrip() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    let data_dir = test_cwd.path().join("data");
+    std::fs::create_dir_all(data_dir.join("empty_dir")).unwrap();
+    std::fs::copy(ref_cwd.join("16_byte_file"), data_dir.join("16_byte_file")).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--record-empty-dirs", "-o", "hash_out", "-l", "4", "--", "data"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    assert!(hash_file.contains("empty_dir\" empty directory\n"));
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_out"])
+        .assert()
+        .success();
+
+    std::fs::remove_dir(data_dir.join("empty_dir")).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_out"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error with directory data/empty_dir: directory not found
+...
+"#]]);
+}
+
+#[test]
+fn concat_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    // Middle part is shorter than the block size
+    std::fs::write(test_cwd.path().join("part_a"), b"abcdefg").unwrap();
+    std::fs::write(test_cwd.path().join("part_b"), b"hi").unwrap();
+    std::fs::write(test_cwd.path().join("part_c"), b"jklmnopqrs").unwrap();
+    std::fs::write(test_cwd.path().join("joined"), b"abcdefghijklmnopqrs").unwrap();
+
+    for (suffix, short_flag) in [("long", &[][..]), ("short", &["--short"][..])] {
+        let concat_out = format!("concat_{}", suffix);
+        let joined_out = format!("joined_{}", suffix);
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["generate-hash", "--concat", "-o", &concat_out, "-l", "4"])
+            .args(short_flag)
+            .args(["--", "part_a", "part_b", "part_c"])
+            .assert()
+            .success();
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["generate-hash", "-o", &joined_out, "-l", "4"])
+            .args(short_flag)
+            .args(["--", "joined"])
+            .assert()
+            .success();
+        let concat_file = std::fs::read_to_string(test_cwd.path().join(&concat_out)).unwrap();
+        let joined_file = std::fs::read_to_string(test_cwd.path().join(&joined_out)).unwrap();
+        assert!(concat_file.contains("\"part_a\" + \"part_b\" + \"part_c\""));
+        assert_eq!(concat_file.lines().last().unwrap().split_whitespace().next(),
+            joined_file.lines().last().unwrap().split_whitespace().next());
+
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["verify-hash", "--", &concat_out])
+            .assert()
+            .success();
+    }
+}
+
+#[test]
+fn per_file_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::create_dir_all(test_cwd.path().join("x")).unwrap();
+    std::fs::create_dir_all(test_cwd.path().join("y")).unwrap();
+    std::fs::write(test_cwd.path().join("x/data"), b"first file").unwrap();
+    std::fs::write(test_cwd.path().join("y/data"), b"second file").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--per-file", "--output-dir", "out", "-l", "4", "--", "x", "y"])
+        .assert()
+        .success();
+    for sidecar_name in ["out/data.mrkl", "out/data.1.mrkl"] {
+        let sidecar = std::fs::read_to_string(test_cwd.path().join(sidecar_name)).unwrap();
+        assert_eq!(sidecar.lines().filter(|line| line.ends_with(" bytes")).count(), 1);
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["verify-hash", "--", sidecar_name])
+            .assert()
+            .success();
+    }
+}
+
+#[test]
+fn embed_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    let data_path = test_cwd.path().join("data");
+    let data_body = b"some data spanning several blocks";
+    std::fs::write(&data_path, data_body).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--embed", "-l", "4", "--", "data"])
+        .assert()
+        .success();
+    let embedded_data = std::fs::read(&data_path).unwrap();
+    // sha256 hash followed by a 16 byte footer
+    assert_eq!(embedded_data.len(), data_body.len() + 32 + 16);
+    assert_eq!(&embedded_data[..data_body.len()], data_body);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--embedded", "data"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Info: data hash matches
+
+"#]]);
+}
+
+#[test]
+fn embed_detects_corruption() {
+    let test_cwd = tempdir().unwrap();
+    let data_path = test_cwd.path().join("data");
+    std::fs::write(&data_path, b"some data spanning several blocks").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--embed", "-l", "4", "--", "data"])
+        .assert()
+        .success();
+    let mut embedded_data = std::fs::read(&data_path).unwrap();
+    embedded_data[5] ^= 0x01;
+    std::fs::write(&data_path, &embedded_data).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--embedded", "data"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error verifying file data: hash mismatch:
+...
+"#]]);
+
+    std::fs::write(test_cwd.path().join("plain"), b"no trailer").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--embedded", "plain"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error reading trailer of file plain: no embedded hash found
+
+"#]]);
+}
+
+#[test]
+fn print_exit_codes() {
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .args(["--print-exit-codes"])
+        .assert()
+        .success()
+        .stdout_eq(snapbox::str![[r#"
+SUCCESS=0
+CMDLINE_ERR=1
+DATA_READ_ERR=2
+GEN_WRITE_ERR=101
+VERIF_READ_ERR=101
+VERIF_BAD_HEADER_ERR=1
+VERIF_BAD_ENTRY_ERR=3
+PRE_HASH_ERR=1
+INTERRUPTED=130
+BUDGET_EXHAUSTED=4
+
+"#]]);
+}
+#[test]
+fn near_miss_hash_name() {
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .args(["generate-hash", "-f", "sha512trunc", "--", "Cargo.toml"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+error: invalid value 'sha512trunc' for '--hash-function <hash>'
+...
+  tip: a similar value exists: 'sha512trunc224'
+...
+"#]]);
+}
+#[test]
+fn min_depth_above_max_depth() {
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .args(["generate-hash", "--min-depth", "3", "--max-depth", "2",
+            "-o", "unused_hash_out", "--", "src"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+error: --min-depth 3 is greater than --max-depth 2
+...
+"#]]);
+}
+
+#[test]
+fn allow_append_verifies_prefix() {
+    let test_cwd = tempdir().unwrap();
+    let log_path = test_cwd.path().join("log");
+    std::fs::write(&log_path, b"first line\nsecond line\n").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_out", "-l", "4", "--", "log"])
+        .assert()
+        .success();
+    let mut log_file = std::fs::OpenOptions::new().append(true).open(&log_path).unwrap();
+    std::io::Write::write_all(&mut log_file, b"third line\n").unwrap();
+    drop(log_file);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_out"])
+        .assert()
+        .code(1);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--allow-append", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Warning: file log grew from 23 to 34 bytes, and appended data will not be verified
+...
+Info: log hash matches
+
+"#]]);
+
+    // Changes within the recorded prefix are still detected
+    let mut log_data = std::fs::read(&log_path).unwrap();
+    log_data[0] = b'F';
+    std::fs::write(&log_path, &log_data).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--allow-append", "--", "hash_out"])
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn child_count_encoding_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    let data_path = test_cwd.path().join("data");
+    std::fs::write(&data_path, b"abcdefghijklmnopqrstuvwxyz").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_out", "-l", "4",
+            "--node-encoding", "child-count", "--", "data"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    assert!(hash_file.contains("Node encoding: child-count\n"));
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_out"])
+        .assert()
+        .success();
+
+    // Without the encoding line the tree is rebuilt with plain nodes
+    let plain_file = hash_file.replace("Node encoding: child-count\n", "");
+    std::fs::write(test_cwd.path().join("hash_plain"), plain_file).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_plain"])
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn lint_reports_all_issues() {
+    let test_cwd = tempdir().unwrap();
+    let data_path = test_cwd.path().join("data");
+    std::fs::write(&data_path, b"abcdefghijklmnopqrstuvwxyz").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_out", "-l", "4", "-b", "2", "--", "data"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["lint", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Info: no problems found in hash_out
+
+"#]]);
+
+    // Drop the second leaf and misstate the file length
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    let mut lines: Vec<&str> = hash_file.lines().collect();
+    lines.remove(8);
+    let bad_file = lines.join("\n").replace("0x1a bytes", "0x1c bytes") + "\n";
+    std::fs::write(test_cwd.path().join("hash_bad"), bad_file).unwrap();
+    // The data file is not read, so removing it does not matter
+    std::fs::remove_file(&data_path).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["lint", "hash_bad"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error: line 9: file id 0 is missing 1 entries starting at block range [0x00000001-0x00000001]
+Error: line 17: file id 0 has mismatched byte range in entry:
+  stored:   [0x00000018-0x00000019]
+  computed: [0x00000018-0x0000001b]
+...
+"#]]);
+}
+
+#[test]
+fn salted_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("zeros"), [0u8; 32]).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_out", "-l", "4",
+            "--salt", "0123abcd", "--", "zeros"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    assert!(hash_file.contains("Salted: true\n"));
+    assert!(!hash_file.contains("0123abcd"));
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--salt", "0123abcd", "--", "hash_out"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_out"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error: hash file was generated with a salt, which must be given with --salt
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--salt", "4567", "--", "hash_out"])
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn format_version_matches_prelude() {
+    let output = Command::new(cargo_bin!("merkle_tree_checksum"))
+        .arg("--format-version")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let format_version = String::from_utf8(output).unwrap();
+
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"data").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_out", "--", "data"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    assert_eq!(hash_file.lines().next().unwrap(),
+        format!("merkle_tree_checksum v{}", format_version.trim_end()));
+}
+
+#[test]
+fn metadata_only_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    let data_path = test_cwd.path().join("data");
+    std::fs::write(&data_path, b"some data").unwrap();
+    std::fs::write(test_cwd.path().join("other"), b"other data").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_out", "--metadata-only",
+            "--", "data", "other"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    assert!(hash_file.contains("Metadata only: true\n"));
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Info: data metadata matches
+Info: other metadata matches
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["lint", "hash_out"])
+        .assert()
+        .success();
+
+    // Same length and contents, but a different modification time
+    let data_file = std::fs::File::options().write(true).open(&data_path).unwrap();
+    data_file.set_modified(std::time::SystemTime::UNIX_EPOCH).unwrap();
+    drop(data_file);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_out"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error verifying file data: metadata hash mismatch: modification time or length changed
+Info: other metadata matches
+
+"#]]);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_short", "--metadata-only", "--short",
+            "--", "data"])
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn padded_tree_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcd1234ef").unwrap();
+
+    for (hash_name, extra_args) in [("hash_plain", &[][..]), ("hash_padded", &["--pad-tree"][..])] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["generate-hash", "-o", hash_name, "-l", "4", "-b", "2"])
+            .args(extra_args)
+            .args(["--", "data"])
+            .assert()
+            .success();
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["verify-hash", "--", hash_name])
+            .assert()
+            .success();
+    }
+    let plain_file = std::fs::read_to_string(test_cwd.path().join("hash_plain")).unwrap();
+    let padded_file = std::fs::read_to_string(test_cwd.path().join("hash_padded")).unwrap();
+    assert!(padded_file.contains("Padded tree: true\n"));
+    assert!(padded_file.contains("Features: padded-tree\n"));
+    // Same entries, but nodes covering the padding leaf hash differently
+    assert_eq!(plain_file.lines().count() + 2, padded_file.lines().count());
+    assert_ne!(plain_file.lines().last(), padded_file.lines().last());
+}
+
+#[test]
+fn unbalanced_tree_matches_rfc6962() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcd1234ef").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_unbalanced", "-l", "4", "-b", "2"])
+        .args(["-f", "sha256", "--unbalanced", "--", "data"])
+        .assert()
+        .success();
+    for extra_args in [&[][..], &["--self-consistent"][..]] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .arg("verify-hash")
+            .args(extra_args)
+            .args(["--", "hash_unbalanced"])
+            .assert()
+            .success();
+    }
+    let unbalanced_file = std::fs::read_to_string(test_cwd.path().join("hash_unbalanced")).unwrap();
+    assert!(unbalanced_file.contains("Unbalanced tree: true\n"));
+    // RFC 6962 tree hash of the leaves "abcd", "1234", and "ef"
+    assert!(unbalanced_file.lines().last().unwrap().ends_with(
+        " fd2c79f07ff7e29ef18087e809a050be511c841e85a7d9fcbfaf0d7bb911a442"));
+}
+
+#[test]
+fn duplicate_last_matches_bitcoin_style() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcd1234ef").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_duplicate", "-l", "4", "-b", "2"])
+        .args(["-f", "sha256", "--duplicate-last", "--", "data"])
+        .assert()
+        .success();
+    for extra_args in [&[][..], &["--self-consistent"][..]] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .arg("verify-hash")
+            .args(extra_args)
+            .args(["--", "hash_duplicate"])
+            .assert()
+            .success();
+    }
+    let duplicate_file = std::fs::read_to_string(test_cwd.path().join("hash_duplicate")).unwrap();
+    assert!(duplicate_file.contains("Duplicated last child: true\n"));
+    // Leaves "abcd", "1234", and "ef", with "ef" paired with itself
+    assert!(duplicate_file.lines().last().unwrap().ends_with(
+        " e4b8b6be7733f1783c48431c9d0add052a6191d6f15ea34cd883e1c5c3701e20"));
+}
+
+#[test]
+fn direct_writer_matches_channel() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    // --jobs 0 writes entries directly instead of going through the channel
+    for (hash_name, jobs) in [("hash_direct", "0"), ("hash_channel", "2")] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["--jobs", jobs, "generate-hash", "-o", hash_name, "-l", "4", "-b", "2"])
+            .args(["--", "16_byte_file", "20_byte_file", "empty_file"])
+            .assert()
+            .success();
+    }
+    let direct_file = std::fs::read(test_cwd.path().join("hash_direct")).unwrap();
+    let channel_file = std::fs::read(test_cwd.path().join("hash_channel")).unwrap();
+    assert_eq!(direct_file, channel_file);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["--jobs", "0", "generate-hash", "--per-file", "--output-dir", ".", "-l", "4"])
+        .args(["--", "16_byte_file", "20_byte_file"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "20_byte_file.mrkl"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn self_consistent_detects_edits() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcd1234ef").unwrap();
+
+    for extra_args in [&[][..], &["--pad-tree", "--node-encoding", "child-count"][..]] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["generate-hash", "-o", "hash_out", "--overwrite", "-l", "4", "-b", "2"])
+            .args(["--salt", "5a17"])
+            .args(extra_args)
+            .args(["--", "data"])
+            .assert()
+            .success();
+        // The data file is not read
+        std::fs::remove_file(test_cwd.path().join("data")).unwrap();
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["verify-hash", "--self-consistent", "--salt", "5a17", "--", "hash_out"])
+            .assert()
+            .success()
+            .stderr_eq(snapbox::str![[r#"
+Info: hash_out is self-consistent
+
+"#]]);
+        std::fs::write(test_cwd.path().join("data"), b"abcd1234ef").unwrap();
+    }
+
+    // Replace the hash of the second leaf
+    let hash_path = test_cwd.path().join("hash_out");
+    let hash_file = std::fs::read_to_string(&hash_path).unwrap();
+    let edited_file: String = hash_file.lines()
+        .map(|line| match line.contains("[0x00000001-0x00000001]") {
+            true => format!("{}00000000", &line[..line.len()-8]),
+            false => line.to_owned()
+        } + "\n")
+        .collect();
+    std::fs::write(&hash_path, edited_file).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--self-consistent", "--salt", "5a17", "--", "hash_out"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error: line 14: parent hash over block range [0x00000000-0x00000001] does not match its children:
+  stored:   [..]
+  computed: [..]
+
+"#]]);
+}
+
+#[test]
+fn path_list_argument() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+    let path_list = "# reference files\n16_byte_file\n\n\"20_byte_file\"\n";
+    std::fs::write(test_cwd.path().join("paths.txt"), path_list).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_args", "-l", "4"])
+        .args(["--", "16_byte_file", "20_byte_file", "empty_file"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_list", "-l", "4"])
+        .args(["--", "@paths.txt", "empty_file"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_stdin", "-l", "4"])
+        .args(["--", "@-", "empty_file"])
+        .stdin(path_list)
+        .assert()
+        .success();
+    let args_file = std::fs::read(test_cwd.path().join("hash_args")).unwrap();
+    assert_eq!(std::fs::read(test_cwd.path().join("hash_list")).unwrap(), args_file);
+    assert_eq!(std::fs::read(test_cwd.path().join("hash_stdin")).unwrap(), args_file);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_missing", "-l", "4"])
+        .args(["--", "@missing.txt"])
+        .assert()
+        .failure()
+        .stderr_eq(snapbox::str![[r#"
+Error reading path list missing.txt: read error: entity not found
+
+"#]]);
+}
+
+#[test]
+fn exact_reports_extra_and_missing() {
+    let test_cwd = tempdir().unwrap();
+    let data_dir = test_cwd.path().join("data");
+    std::fs::create_dir(&data_dir).unwrap();
+    std::fs::write(data_dir.join("a"), b"aaaa").unwrap();
+    std::fs::write(data_dir.join("b"), b"bbbb").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "data/hash_out", "--", "data"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--exact", "--scan-dir", "./data", "--", "data/hash_out"])
+        .assert()
+        .success();
+
+    std::fs::remove_file(data_dir.join("b")).unwrap();
+    std::fs::write(data_dir.join("c"), b"cccc").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--exact", "--scan-dir", "data", "--", "data/hash_out"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error with file data/b: file not found
+Error: extra file data/c is not in the hash file
+Error: missing file data/b is in the hash file
+
+"#]]);
+}
+
+#[test]
+fn comment_lines_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcd1234ef").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_out", "-l", "4",
+            "--comment", "build: 42", "--comment", "two\nlines", "--", "data"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    let header_lines: Vec<&str> = hash_file.lines().skip(1).take(2).collect();
+    // Newlines are escaped like in file names, so the comment stays on one line
+    assert_eq!(header_lines, ["Comment: \"build: 42\"", "Comment: \"two\\\\nlines\""]);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Info: hash file comment: build: 42
+Info: hash file comment: two[..]nlines
+...
+Info: data hash matches
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--self-consistent", "--", "hash_out"])
+        .assert()
+        .success();
+
+    // Line numbers in reports count the comment lines
+    let edited_file = hash_file.replace("\"data\" 0xa bytes", "\"data\" 0x8 bytes");
+    std::fs::write(test_cwd.path().join("hash_out"), edited_file).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["lint", "--", "hash_out"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error: line 12: file id 0 has unexpected entry for block range [0x00000002-0x00000002]
+...
+"#]]);
+}
+
+#[test]
+fn trim_trailing_zeros_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    let mut image_data = b"sparse\x00data".to_vec();
+    image_data.resize(31, 0x00);
+    std::fs::write(test_cwd.path().join("image"), &image_data).unwrap();
+    std::fs::write(test_cwd.path().join("prefix"), &image_data[..11]).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--trim-trailing-zeros", "-o", "hash_trimmed",
+            "-l", "4", "-b", "2", "--", "image"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_prefix", "-l", "4", "-b", "2", "--", "prefix"])
+        .assert()
+        .success();
+    let trimmed_file = std::fs::read_to_string(test_cwd.path().join("hash_trimmed")).unwrap();
+    let prefix_file = std::fs::read_to_string(test_cwd.path().join("hash_prefix")).unwrap();
+    assert!(trimmed_file.contains("Trimmed trailing zeros: true\n"));
+    assert!(trimmed_file.contains("\"image\" 0x1f bytes (0xb hashed)\n"));
+    // Entries only cover the data before the trailing zeros
+    assert_eq!(trimmed_file.split("Hashes:\n").nth(1),
+        prefix_file.split("Hashes:\n").nth(1));
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_trimmed"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["lint", "--", "hash_trimmed"])
+        .assert()
+        .success();
+
+    image_data[20] = 0x01;
+    std::fs::write(test_cwd.path().join("image"), &image_data).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--", "hash_trimmed"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error with file image: trimmed trailing zeros now have nonzero data at byte 0x14
+...
+"#]]);
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupt_removes_incomplete_output() {
+    let test_cwd = tempdir().unwrap();
+    // Large enough that hashing is still running when interrupted
+    std::fs::write(test_cwd.path().join("big_file"), vec![0x5a; 4 << 20]).unwrap();
+    let output_path = test_cwd.path().join("hash_out");
+
+    let mut child = std::process::Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "--jobs", "0", "generate-hash", "-o", "hash_out", "-l", "4"])
+        .args(["--", "big_file"])
+        .spawn()
+        .unwrap();
+    while std::fs::metadata(&output_path).map_or(true, |meta| meta.len() == 0) {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let kill_status = std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(kill_status.success());
+
+    let exit_status = child.wait().unwrap();
+    assert_eq!(exit_status.code(), Some(130));
+    assert!(!output_path.exists());
+}
+
+#[test]
+fn progress_to_file() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcd1234ef").unwrap();
+
+    // Only the progress bars move to the file
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["--progress-to", "progress_log", "generate-hash",
+            "-o", "hash_out", "-l", "4", "--", "data"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+===================================== data =====================================
+
+"#]]);
+    let progress_log = std::fs::read_to_string(test_cwd.path().join("progress_log")).unwrap();
+    assert!(progress_log.lines().any(|line| line.starts_with("File ")
+        && line.contains("10 B/10 B")), "{}", progress_log);
+    assert!(progress_log.ends_with('\n'));
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["--progress-to", "progress_log", "verify-hash", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+...
+Info: data hash matches
+
+"#]]);
+}
+
+#[test]
+fn decompress_gzip_roundtrip() {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let test_cwd = tempdir().unwrap();
+    // Not a multiple of the block length, so the last block is short
+    let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(test_cwd.path().join("data"), &data).unwrap();
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&data).unwrap();
+    std::fs::write(test_cwd.path().join("data.gz"), encoder.finish().unwrap()).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "plain_hash", "-l", "64", "--", "data"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "gzip_hash", "-l", "64",
+            "--decompress", "auto", "--", "data.gz"])
+        .assert()
+        .success();
+    // Same tree over the same decompressed data, recorded with the decompressed length
+    let plain_hash = std::fs::read_to_string(test_cwd.path().join("plain_hash")).unwrap();
+    let gzip_hash = std::fs::read_to_string(test_cwd.path().join("gzip_hash")).unwrap();
+    assert!(gzip_hash.contains("\"data.gz\" 0x3e8 bytes\n"));
+    assert_eq!(plain_hash.replace("\"data\"", "\"data.gz\""), gzip_hash);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--decompress", "gzip", "--", "gzip_hash"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "gzip_hash"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error with file data.gz: mismatched file length:
+  expected: 1000
+...
+"#]]);
+}
+
+#[test]
+fn decompress_zstd_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(test_cwd.path().join("data"), &data).unwrap();
+    let compressed = zstd::encode_all(&data[..], 19).unwrap();
+    // Also detected by its magic bytes without the .zst extension
+    std::fs::write(test_cwd.path().join("data.zst"), &compressed).unwrap();
+    std::fs::write(test_cwd.path().join("data_zst"), &compressed).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "plain_hash", "-l", "64", "--", "data"])
+        .assert()
+        .success();
+    for (zstd_name, hash_name) in [("data.zst", "ext_hash"), ("data_zst", "zstd_hash")] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["generate-hash", "-o", hash_name, "-l", "64",
+                "--decompress", "auto", "--", zstd_name])
+            .assert()
+            .success();
+        let plain_hash = std::fs::read_to_string(test_cwd.path().join("plain_hash")).unwrap();
+        let zstd_hash = std::fs::read_to_string(test_cwd.path().join(hash_name)).unwrap();
+        assert!(zstd_hash.contains(&format!("\"{}\" 0x3e8 bytes\n", zstd_name)));
+        assert_eq!(plain_hash.replace("\"data\"", &format!("\"{}\"", zstd_name)), zstd_hash);
+    }
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--decompress", "zstd", "--", "zstd_hash"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "zstd_hash"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error with file data_zst: mismatched file length:
+  expected: 1000
+...
+"#]]);
+}
+
+#[test]
+fn verify_cache_skips_unchanged() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data_a"), b"abcd1234ef").unwrap();
+    std::fs::write(test_cwd.path().join("data_b"), b"5678").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_out", "-l", "4", "--", "data_a", "data_b"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--cache", "cache", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+...
+Info: data_a hash matches
+...
+Info: data_b hash matches
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--cache", "cache", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+...
+Info: data_a unchanged since last verified, skipped
+Info: data_b unchanged since last verified, skipped
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--cache", "cache", "--no-cache", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+...
+Info: data_a hash matches
+...
+Info: data_b hash matches
+
+"#]]);
+
+    // Same length, but a newer modification time
+    let data_b = std::fs::File::options().write(true)
+        .open(test_cwd.path().join("data_b")).unwrap();
+    std::io::Write::write_all(&mut &data_b, b"5679").unwrap();
+    data_b.set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(10)).unwrap();
+    drop(data_b);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--cache", "cache", "--", "hash_out"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+...
+Info: data_a unchanged since last verified, skipped
+...
+Error verifying file data_b: hash mismatch over byte range [0x00000000-0x00000003]:
+...
+"#]]);
+}
+
+#[test]
+fn sort_entries_ignores_argument_order() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::create_dir(test_cwd.path().join("dir_a")).unwrap();
+    std::fs::write(test_cwd.path().join("dir_a").join("data_c"), b"abcd1234ef").unwrap();
+    std::fs::write(test_cwd.path().join("dir_a").join("data_a"), b"5678").unwrap();
+    std::fs::write(test_cwd.path().join("data_b"), b"9abcdef0").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_forward", "-l", "4",
+            "--sort-entries", "--", "dir_a", "data_b"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_reverse", "-l", "4",
+            "--sort-entries", "--", "data_b", "dir_a"])
+        .assert()
+        .success();
+    let hash_forward = std::fs::read_to_string(test_cwd.path().join("hash_forward")).unwrap();
+    let hash_reverse = std::fs::read_to_string(test_cwd.path().join("hash_reverse")).unwrap();
+    assert_eq!(hash_forward, hash_reverse);
+
+    // Verification matches entries to hashes by their renumbered index
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "-q", "verify-hash", "--", "hash_forward"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn region_matches_extracted_data() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("image"), b"HEADERabcd1234efTRAILER").unwrap();
+    std::fs::write(test_cwd.path().join("partition"), b"abcd1234ef").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "region_hash", "-l", "4",
+            "--offset", "6", "--length", "10", "--", "image"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "partition_hash", "-l", "4", "--", "partition"])
+        .assert()
+        .success();
+    // Same entries as hashing the region on its own
+    let region_hash = std::fs::read_to_string(test_cwd.path().join("region_hash")).unwrap();
+    let partition_hash = std::fs::read_to_string(test_cwd.path().join("partition_hash")).unwrap();
+    assert_eq!(region_hash.replace("Features: region\n", "").replace("Region offset: 6\n", "")
+            .replace("Byte range base: relative\n", "").replace("\"image\"", "\"partition\""),
+        partition_hash);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "absolute_hash", "-l", "4",
+            "--offset", "6", "--length", "10", "--byte-range-base", "absolute", "--", "image"])
+        .assert()
+        .success();
+    let absolute_hash = std::fs::read_to_string(test_cwd.path().join("absolute_hash")).unwrap();
+    assert!(absolute_hash.contains("Byte range base: absolute\n"));
+    assert!(absolute_hash.contains(" [0x00000000-0x00000000] [0x00000006-0x00000009] "));
+
+    // Data outside of the region is not verified
+    std::fs::write(test_cwd.path().join("image"), b"OTHER!abcd1234ef").unwrap();
+    for hash_file in ["region_hash", "absolute_hash"] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-q", "-q", "verify-hash", "--", hash_file])
+            .assert()
+            .success();
+    }
+    std::fs::write(test_cwd.path().join("image"), b"OTHER!abcd1234eX").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "-q", "verify-hash", "--", "absolute_hash"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error verifying file image: hash mismatch over byte range [0x0000000e-0x0000000f]:
+...
+"#]]);
+}
+
+#[test]
+fn byte_range_base_concat() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("part_a"), b"HEADERabcd").unwrap();
+    std::fs::write(test_cwd.path().join("part_b"), b"1234efTRAILER").unwrap();
+
+    for base in ["relative", "absolute"] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["generate-hash", "-o", base, "-l", "4", "--concat",
+                "--offset", "6", "--length", "10", "--byte-range-base", base,
+                "--", "part_a", "part_b"])
+            .assert()
+            .success();
+    }
+    let relative_hash = std::fs::read_to_string(test_cwd.path().join("relative")).unwrap();
+    let absolute_hash = std::fs::read_to_string(test_cwd.path().join("absolute")).unwrap();
+    assert!(relative_hash.contains("Byte range base: relative\n"));
+    assert!(absolute_hash.contains("Byte range base: absolute\n"));
+    // The block spanning both parts, from the start of the region or of part_a
+    assert!(relative_hash.contains(" [0x00000001-0x00000001] [0x00000004-0x00000007] "));
+    assert!(absolute_hash.contains(" [0x00000001-0x00000001] [0x0000000a-0x0000000d] "));
+
+    // A change in that block is reported in the base recorded in each hash file
+    std::fs::write(test_cwd.path().join("part_b"), b"12X4efTRAILER").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "-q", "verify-hash", "--", "relative"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error verifying file part_a + part_b: hash mismatch over byte range [0x00000004-0x00000007]:
+...
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "-q", "verify-hash", "--", "absolute"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error verifying file part_a + part_b: hash mismatch over byte range [0x0000000a-0x0000000d]:
+...
+"#]]);
+}
+
+#[test]
+fn self_checksum_detects_damage() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::create_dir_all(test_cwd.path().join("x")).unwrap();
+    std::fs::create_dir_all(test_cwd.path().join("y")).unwrap();
+    std::fs::write(test_cwd.path().join("x/data"), b"first file").unwrap();
+    std::fs::write(test_cwd.path().join("y/data"), b"second file").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--self-checksum", "-o", "hash_out", "-l", "4", "--", "x", "y"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    assert!(hash_file.lines().last().unwrap().starts_with("File-Checksum: "));
+    for verify_args in [&["verify-hash", "--", "hash_out"][..],
+            &["lint", "--", "hash_out"][..]] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(verify_args)
+            .assert()
+            .success();
+    }
+
+    // Damage the last hex digit of the last hash entry
+    let mut damaged_file = hash_file.into_bytes();
+    let footer_start = damaged_file.len() - "File-Checksum: 01234567\n".len();
+    damaged_file[footer_start - 2] = match damaged_file[footer_start - 2] {
+        b'0' => b'1',
+        _ => b'0'
+    };
+    std::fs::write(test_cwd.path().join("hash_out"), damaged_file).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_out"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error checking hash file hash_out: hash file is damaged, mismatched checksum:
+...
+"#]]);
+
+    // Each hash file of --per-file gets its own checksum
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--self-checksum", "--per-file", "--output-dir", "out", "-l", "4", "--", "x", "y"])
+        .assert()
+        .success();
+    for sidecar_name in ["out/data.mrkl", "out/data.1.mrkl"] {
+        let sidecar = std::fs::read_to_string(test_cwd.path().join(sidecar_name)).unwrap();
+        assert_eq!(sidecar.lines().filter(|line| line.starts_with("File-Checksum: ")).count(), 1);
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["verify-hash", "--", sidecar_name])
+            .assert()
+            .success();
+    }
+}
+
+#[test]
+fn leaves_and_root_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    let data: Vec<u8> = (0..=200).collect();
+    std::fs::write(test_cwd.path().join("data"), &data).unwrap();
+
+    // With and without a thread pool, which write entries differently
+    for jobs in ["0", "2"] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["--jobs", jobs, "generate-hash", "--leaves-and-root", "-o", "hash_out"])
+            .args(["--overwrite", "-l", "16", "-b", "2", "--", "data"])
+            .assert()
+            .success();
+        let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+        assert!(hash_file.contains("Leaves and root only: true\n"));
+        // 13 leaves and the root
+        assert_eq!(hash_file.lines().filter(|line| line.starts_with("  0 ")).count(), 14);
+        for verify_args in [&["verify-hash", "--", "hash_out"][..],
+                &["verify-hash", "--self-consistent", "--", "hash_out"][..],
+                &["lint", "--", "hash_out"][..]] {
+            Command::new(cargo_bin!("merkle_tree_checksum"))
+                .current_dir(&test_cwd)
+                .args(verify_args)
+                .assert()
+                .success();
+        }
+    }
+
+    let mut edited_data = data.clone();
+    edited_data[100] ^= 0x01;
+    std::fs::write(test_cwd.path().join("data"), &edited_data).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_out"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+...
+Error verifying file data: hash mismatch over byte range [0x00000060-0x0000006f]:
+...
+"#]]);
+}
+
+#[test]
+fn config_defaults_and_override() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"data to hash with config defaults").unwrap();
+    std::fs::write(test_cwd.path().join("tree.toml"), concat!(
+        "hash-function = \"sha512\"\n",
+        "block-length = \"8\"\n",
+        "branch-factor = 8\n",
+        "jobs = 0\n")).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["--config", "tree.toml", "generate-hash", "-b", "2", "-o", "hash_out", "--", "data"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    assert!(hash_file.contains("Hash function: sha512\n"));
+    assert!(hash_file.contains("Block size: 8\n"));
+    // The command line branch factor overrides the config
+    assert!(hash_file.contains("Branching factor: 2\n"));
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_out"])
+        .assert()
+        .success();
+
+    std::fs::write(test_cwd.path().join("bad.toml"), "block-length = \"8Qi\"\n").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["--config", "bad.toml", "generate-hash", "-o", "hash_out", "--", "data"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+error: invalid config file bad.toml: invalid value "8Qi" for 'block-length'
+...
+"#]]);
+}
+
+#[test]
+fn diff_file_lists_changed_ranges() {
+    let test_cwd = tempdir().unwrap();
+    let data: Vec<u8> = (0..200u32).map(|i| (i * 7 % 251) as u8).collect();
+    std::fs::write(test_cwd.path().join("data"), &data).unwrap();
+    std::fs::write(test_cwd.path().join("other"), b"other file").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_out", "-l", "16", "--", "other", "data"])
+        .assert()
+        .success();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["diff-file", "hash_out", "data"])
+        .assert()
+        .success()
+        .stdout_eq("");
+
+    // Edits in two runs of blocks, then data appended past the last partial block
+    let mut edited_data = data.clone();
+    edited_data[20] ^= 0x01;
+    edited_data[40] ^= 0x01;
+    edited_data[100] ^= 0x01;
+    edited_data.extend_from_slice(b"appended");
+    std::fs::write(test_cwd.path().join("data"), &edited_data).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-j", "2", "diff-file", "hash_out", "data"])
+        .assert()
+        .success()
+        .stdout_eq(snapbox::str![[r#"
+changed [0x00000010-0x0000002f]
+changed [0x00000060-0x0000006f]
+changed [0x000000c0-0x000000c7]
+added [0x000000c8-0x000000cf]
+
+"#]]);
+
+    // Truncation removes the data past the new end
+    std::fs::write(test_cwd.path().join("data"), &data[..150]).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["diff-file", "hash_out", "data"])
+        .assert()
+        .success()
+        .stdout_eq(snapbox::str![[r#"
+changed [0x00000090-0x00000095]
+removed [0x00000096-0x000000c7]
+
+"#]]);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["diff-file", "hash_out", "missing"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error reading hash file hash_out: missing is not listed, and there is more than one file to compare against
+
+"#]]);
+}
+
+#[test]
+fn files0_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    let file_names = ["back\\nslash", "quote\"d", "plain"];
+    for file_name in file_names {
+        std::fs::write(test_cwd.path().join(file_name), file_name.as_bytes()).unwrap();
+    }
+    let files0_list: String = file_names.iter().map(|name| format!("{}\0", name)).collect();
+    std::fs::write(test_cwd.path().join("list0"), &files0_list).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--files0-from", "list0", "-o", "hash_out"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["info", "hash_out"])
+        .assert()
+        .success()
+        .stdout_eq(snapbox::str![[r#"
+"back\\nslash"
+"quote\"d"
+"plain"
+
+"#]].raw());
+    // Unquoted names are output exactly as they were read, with a backslash
+    // followed by n left as it is
+    let info_output = Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["info", "--files0", "hash_out"])
+        .assert()
+        .success()
+        .get_output().stdout.clone();
+    assert_eq!(info_output, files0_list.as_bytes());
+    // The same names are opened to verify the files
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "hash_out"])
+        .assert()
+        .success();
+
+    // Short hash files record names in their entries
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--short", "-o", "short_out", "--files0-from", "-"])
+        .stdin(files0_list.clone())
+        .assert()
+        .success();
+    let info_output = Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["info", "--files0", "short_out"])
+        .assert()
+        .success()
+        .get_output().stdout.clone();
+    assert_eq!(info_output, files0_list.as_bytes());
+}
+
+#[test]
+fn tree_structure_root_covers_names() {
+    let test_cwd = tempdir().unwrap();
+    for (dir_name, file_names) in [("tree_a", ["one", "two"]), ("tree_b", ["one", "renamed"])] {
+        std::fs::create_dir(test_cwd.path().join(dir_name)).unwrap();
+        for (file_name, contents) in file_names.iter().zip([b"first", b"other"]) {
+            std::fs::write(test_cwd.path().join(dir_name).join(file_name), contents).unwrap();
+        }
+    }
+    // Last line of the hash file, which is the Root: line with --hash-tree-structure
+    let root_line = |dir_name: &str, hash_structure: bool| {
+        let out_name = format!("{}_{}", dir_name, hash_structure);
+        let mut args = vec!["generate-hash", "-o", &out_name, "--sort-entries"];
+        if hash_structure {
+            args.push("--hash-tree-structure");
+        }
+        args.extend(["--", dir_name]);
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(&args)
+            .assert()
+            .success();
+        let hash_file = std::fs::read_to_string(test_cwd.path().join(&out_name)).unwrap();
+        hash_file.lines().last().unwrap().to_owned()
+    };
+
+    // Without structure hashing, only the contents are hashed
+    let last_entry = |line: String| line.rsplit(' ').next().unwrap().to_owned();
+    assert_eq!(last_entry(root_line("tree_a", false)), last_entry(root_line("tree_b", false)));
+    let root_a = root_line("tree_a", true);
+    let root_b = root_line("tree_b", true);
+    assert!(root_a.starts_with("Root: "));
+    assert!(root_b.starts_with("Root: "));
+    assert_ne!(root_a, root_b);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "tree_a_true"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+...
+Info: combined root matches
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["lint", "tree_a_true"])
+        .assert()
+        .success();
+
+    // Swapping in the other root is caught even though every file matches
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("tree_a_true")).unwrap();
+    std::fs::write(test_cwd.path().join("tree_a_true"),
+        hash_file.replace(&root_a, &root_b)).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "tree_a_true"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+...
+Error verifying combined root: [..]
+...
+"#]]);
+}
+
+#[test]
+fn jsonl_output_streams_entries() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"some data to hash in blocks").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_out", "-l", "8", "--format", "jsonl", "--", "data"])
+        .assert()
+        .success();
+
+    // Header first, then one object per node of the 4 block tree
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    let lines: Vec<&str> = hash_file.lines().collect();
+    assert_eq!(lines.len(), 6);
+    assert!(lines[0].starts_with("{\"program\":\"merkle_tree_checksum\",\"version\":"));
+    assert!(lines[0].contains("\"files\":[{\"names\":[\"data\"],\"length\":27}]"));
+    assert!(lines[1].starts_with(
+        "{\"file_index\":0,\"block_range\":[0,0],\"byte_range\":[0,7],\"hash\":\""));
+    assert!(lines[5].starts_with(
+        "{\"file_index\":0,\"block_range\":[0,3],\"byte_range\":[0,26],\"hash\":\""));
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "hash_out"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["lint", "hash_out"])
+        .assert()
+        .success();
+
+    std::fs::write(test_cwd.path().join("data"), b"some data to hash in blockz").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "hash_out"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+...
+Error verifying file data: hash mismatch over byte range [0x00000018-0x0000001a]:
+...
+"#]]);
+}
+
+#[test]
+fn stats_block_printed() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+    // Stats are printed even when everything else is quiet
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "--stats", "-j", "2", "generate-hash", "-f", "crc32", "-o", "hash_out",
+            "--", "16_byte_file", "20_byte_file"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Stats:
+  Hash function: crc32
+  Threads: 2
+  File "16_byte_file": 16 bytes in [..] s, [..] MB/s
+  File "20_byte_file": 20 bytes in [..] s, [..] MB/s
+  Total for 2 files: 36 bytes in [..] s, [..] MB/s
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![""]);
+}
+
+#[test]
+fn byte_diff_against_reference() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcdefghijklmnop").unwrap();
+    std::fs::write(test_cwd.path().join("reference"), b"abcdefghijklmnop").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_out", "-l", "4", "-b", "2", "--", "data"])
+        .assert()
+        .success();
+    std::fs::write(test_cwd.path().join("data"), b"abcdefXhijklmnop").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--byte-diff", "--reference", "reference", "--", "hash_out"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error verifying file data: hash mismatch over byte range [0x00000004-0x00000007]:
+...
+Info: first byte differing from reference reference is at 0x6
+
+"#]]);
+
+    // The reference is required, and must exist
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--byte-diff", "--", "hash_out"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+error: the following required arguments were not provided:
+  --reference <reference>
+...
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--byte-diff", "--reference", "missing", "--", "hash_out"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error: reference file missing not found
+
+"#]]);
+}
+
+#[test]
+fn compare_with_reference() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcdefghijklmnop").unwrap();
+    std::fs::write(test_cwd.path().join("other"), b"0123").unwrap();
+    std::fs::create_dir(test_cwd.path().join("reference")).unwrap();
+    std::fs::write(test_cwd.path().join("reference/data"), b"abcdefghijklmnop").unwrap();
+    std::fs::write(test_cwd.path().join("reference/other"), b"0123").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-o", "hash_out", "-l", "4", "--", "data", "other"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--reference", "reference", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Warning: Verification of long hashes may fail early [..]
+Comparing data with reference...
+Info: data matches reference reference/data
+Comparing other with reference...
+Info: other matches reference reference/other
+
+"#]]);
+
+    // Each differing file is reported, and the rest are still compared
+    std::fs::write(test_cwd.path().join("data"), b"abcdefXhijklmnop").unwrap();
+    std::fs::write(test_cwd.path().join("other"), b"0X23").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--reference", "reference",
+            "--results", "results.jsonl", "--", "hash_out"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error verifying file data: first byte differing from reference reference/data is at 0x6
+Error verifying file other: first byte differing from reference reference/other is at 0x1
+
+"#]]);
+    let results = std::fs::read_to_string(test_cwd.path().join("results.jsonl")).unwrap();
+    snapbox::assert_data_eq!(results, snapbox::str![[r#"
+{"file_index":0,"names":["data"],"outcome":"failed","error":"first byte differing from reference reference/data is at 0x6","first_differing_byte":6}
+{"file_index":1,"names":["other"],"outcome":"failed","error":"first byte differing from reference reference/other is at 0x1","first_differing_byte":1}
+
+"#]]);
+}
+
+#[test]
+fn max_file_size_skips_files() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::create_dir_all(test_cwd.path().join("dir")).unwrap();
+    std::fs::write(test_cwd.path().join("dir/large"), b"0123456789abcdef").unwrap();
+    std::fs::write(test_cwd.path().join("dir/small"), b"small").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "generate-hash", "--max-file-size", "10", "-o", "hash_skipped", "-l", "4"])
+        .args(["--", "dir"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Warning: skipping file dir/large of 16 bytes, which is larger than --max-file-size of 10 bytes
+...
+"#]]);
+    let skipped_file = std::fs::read_to_string(test_cwd.path().join("hash_skipped")).unwrap();
+    assert!(!skipped_file.contains("\"dir/large\""));
+    assert!(skipped_file.contains("\"dir/small\" 0x5 bytes\n"));
+
+    // The entries of the large file are read past, and the small file still verifies
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_all", "-l", "4", "--", "dir"])
+        .assert()
+        .success();
+    // Directory entries are listed in the order they are found
+    let verify_stderr = Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--max-file-size", "10", "--", "hash_all"])
+        .assert()
+        .success()
+        .get_output()
+        .stderr
+        .clone();
+    let verify_stderr = String::from_utf8(verify_stderr).unwrap();
+    assert!(verify_stderr.starts_with(concat!("Warning: skipping file dir/large of 16 bytes, ",
+        "which is larger than --max-file-size of 10 bytes\n")));
+    assert!(verify_stderr.contains("Info: dir/small hash matches\n"));
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--max-file-size", "10", "--max-file-size-fatal", "--", "hash_all"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error: file dir/large of 16 bytes is larger than --max-file-size of 10 bytes
+
+"#]]);
+}
+
+#[test]
+fn piped_stderr_has_no_progress_bars() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), vec![0x5a; 1 << 16]).unwrap();
+
+    // stderr is piped here, so progress bars are hidden without -q
+    for cmd_args in [&["generate-hash", "-l", "16", "-o", "hash_out"][..], &["verify-hash"][..]] {
+        let file_arg = match cmd_args[0] {
+            "generate-hash" => "data",
+            _ => "hash_out"
+        };
+        let stderr = Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(cmd_args)
+            .args(["--", file_arg])
+            .assert()
+            .success()
+            .get_output()
+            .stderr
+            .clone();
+        assert!(!stderr.contains(&0x1b), "escape sequence in {:?}", String::from_utf8_lossy(&stderr));
+        assert!(!String::from_utf8_lossy(&stderr).contains("/s"));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn sign_command_runs_on_finished_hash_file() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcd1234ef").unwrap();
+
+    // The "signature" is a copy, so it shows the hash file was complete
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-o", "hash_out", "-l", "4",
+            "--sign-command", r#"sh -c 'cp "$0" "$0.sig"'"#, "--", "data"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read(test_cwd.path().join("hash_out")).unwrap();
+    let signature = std::fs::read(test_cwd.path().join("hash_out.sig")).unwrap();
+    assert_eq!(hash_file, signature);
+
+    // A failed signature does not leave the hash file behind
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-o", "unsigned_out", "-l", "4",
+            "--sign-command", "false", "--", "data"])
+        .assert()
+        .code(101)
+        .stderr_eq(snapbox::str![[r#"
+Error: sign command for hash file unsigned_out exited with exit status: 1
+
+"#]]);
+    assert!(!test_cwd.path().join("unsigned_out").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn verify_command_runs_before_parsing() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcd1234ef").unwrap();
+    // Dummy verifier that accepts a hash file matching its "signature"
+    std::fs::write(test_cwd.path().join("check_sig"), "cmp -s \"$1\" \"$1.sig\"\n").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-o", "hash_out", "-l", "4", "--", "data"])
+        .assert()
+        .success();
+    std::fs::copy(test_cwd.path().join("hash_out"),
+        test_cwd.path().join("hash_out.sig")).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--verify-command", "sh check_sig",
+            "--", "hash_out"])
+        .assert()
+        .success();
+
+    // Nothing is read from the hash file once the verifier fails
+    std::fs::write(test_cwd.path().join("hash_out.sig"), b"forged").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--verify-command", "sh check_sig",
+            "--", "hash_out"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error: verify command for hash file hash_out exited with exit status: 1
+
+"#]]);
+}
+
+#[test]
+fn verify_hash_file_with_bom() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+
+    for input_file in INPUT_FILE_LIST.iter().chain(&["hash_out_bom"]) {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--", "hash_out_bom"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn content_defined_chunks_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+    std::fs::write(test_cwd.path().join("data"), &data).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--cdc", "-l", "1024", "-o", "hash_cdc",
+            "--", "data"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_cdc")).unwrap();
+    assert!(hash_file.contains("Content-defined chunks: true\n"));
+    for jobs in ["0", "2"] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-qq", "--jobs", jobs, "verify-hash", "--", "hash_cdc"])
+            .assert()
+            .success();
+    }
+
+    // Leaves past an insertion keep their hashes, unlike with fixed blocks
+    let mut inserted = data.clone();
+    inserted.insert(100, 0x5a);
+    std::fs::write(test_cwd.path().join("data"), &inserted).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--cdc", "-l", "1024", "-o", "hash_inserted",
+            "--", "data"])
+        .assert()
+        .success();
+    let leaf_hashes = |hash_file: &str| -> Vec<String> {
+        hash_file.lines()
+            .filter(|line| line.starts_with("  0 "))
+            .map(|line| line.rsplit(' ').next().unwrap().to_owned())
+            .collect()
+    };
+    let inserted_file = std::fs::read_to_string(test_cwd.path().join("hash_inserted")).unwrap();
+    let original_leaves = leaf_hashes(&hash_file);
+    let inserted_leaves = leaf_hashes(&inserted_file);
+    let shared = inserted_leaves.iter()
+        .filter(|hash| original_leaves.contains(hash))
+        .count();
+    assert!(shared > inserted_leaves.len() / 2);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--cdc", "-l", "1000", "-o", "hash_bad", "--", "data"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error: --cdc requires a block length that is a power of 2
+
+"#]]);
+}
+
+#[test]
+fn extract_one_file_reverifies() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+
+    for input_file in INPUT_FILE_LIST.iter().chain(&["hash_out", "hash_out_short"]) {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    // Entries of the second file are renumbered to index 0
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["extract", "--file", "20_byte_file", "-o", "hash_20", "hash_out"])
+        .assert()
+        .success();
+    let extracted = std::fs::read_to_string(test_cwd.path().join("hash_20")).unwrap();
+    assert!(extracted.contains("Files:\n\"20_byte_file\" 0x14 bytes\nHashes:\n"));
+    assert!(extracted.lines().skip_while(|line| *line != "Hashes:").skip(1)
+        .all(|line| line.starts_with("  0 ")));
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--", "hash_20"])
+        .assert()
+        .success();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["extract", "--file", "empty_file", "hash_out_short"])
+        .assert()
+        .success()
+        .stdout_eq(snapbox::str![[r#"
+merkle_tree_checksum v[..]
+Hash function: sha256
+Block size: 4
+Branching factor: 4
+Hashes:
+6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d  "empty_file"
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["extract", "--file", "empty_file", "-o", "hash_empty", "hash_out_short"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--", "hash_empty"])
+        .assert()
+        .success();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["extract", "--file", "missing_file", "-o", "hash_missing", "hash_out"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error: file missing_file is not in the hash file
+
+"#]]);
+    assert!(!test_cwd.path().join("hash_missing").exists());
+}
+
+#[test]
+fn root_only_skips_block_entries() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcd1234ef").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-o", "hash_out", "-l", "4", "-b", "2", "--", "data"])
+        .assert()
+        .success();
+
+    // Replace the hash of the second leaf, which only the full check detects
+    let hash_path = test_cwd.path().join("hash_out");
+    let hash_file = std::fs::read_to_string(&hash_path).unwrap();
+    let edited_file: String = hash_file.lines()
+        .map(|line| match line.contains("[0x00000001-0x00000001]") {
+            true => format!("{}00000000", &line[..line.len()-8]),
+            false => line.to_owned()
+        } + "\n")
+        .collect();
+    std::fs::write(&hash_path, edited_file).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--root-only", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Warning: --root-only does not check the hashes of individual blocks
+Hashing data...
+Info: data root hash matches
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--", "hash_out"])
+        .assert()
+        .code(3);
+
+    std::fs::write(test_cwd.path().join("data"), b"abcd1235ef").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--root-only", "--", "hash_out"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Warning: --root-only does not check the hashes of individual blocks
+Hashing data...
+Error verifying file data: [..]
+...
+"#]]);
+}
+
+#[test]
+fn mark_empty_short_entries() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("empty"), b"").unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcd").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-s", "--mark-empty", "-o", "hash_out",
+            "--", "empty", "data"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    assert!(hash_file.contains("\nEMPTY  \"empty\"\n"));
+    assert!(!hash_file.contains("EMPTY  \"data\""));
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Hashing empty...
+Info: empty hash matches
+Hashing data...
+Info: data hash matches
+
+"#]]);
+
+    // Both files are still checked after the first one fails
+    std::fs::write(test_cwd.path().join("empty"), b"xyz").unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abce").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--", "hash_out"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Hashing empty...
+Error verifying file empty: file was recorded as empty but is now 0x3 bytes long
+Hashing data...
+Error verifying file data: hash mismatch:
+  stored:   [..]
+  computed: [..]
+
+"#]]);
+}
+
+#[test]
+fn parity_repairs_damaged_character() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"some data to hash").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--with-parity", "--self-checksum",
+            "-o", "hash_out", "-l", "4", "--", "data"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read(test_cwd.path().join("hash_out")).unwrap();
+    for read_args in [&["verify-hash", "--", "hash_out"][..],
+            &["lint", "--", "hash_out"][..], &["info", "--", "hash_out"][..]] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(read_args)
+            .assert()
+            .success();
+    }
+
+    // Damage one character in the middle of the hash file
+    let mut damaged_file = hash_file.clone();
+    let damaged_index = damaged_file.len() / 2;
+    damaged_file[damaged_index] = match damaged_file[damaged_index] {
+        b'0' => b'1',
+        _ => b'0'
+    };
+    std::fs::write(test_cwd.path().join("hash_out"), &damaged_file).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_out"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error checking hash file hash_out: line [..]: failed its parity check, and may be fixed with repair
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["repair", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Info: repaired line [..]
+
+"#]]);
+    assert_eq!(std::fs::read(test_cwd.path().join("hash_out")).unwrap(), hash_file);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--", "hash_out"])
+        .assert()
+        .success();
+
+    // Hash files without parity cannot be repaired
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--overwrite", "-o", "hash_out", "--", "data"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["repair", "--", "hash_out"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error checking hash file hash_out: line 1: missing parity
+
+"#]]);
+}
+
+#[test]
+fn error_on_empty_aborts() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--error-on-empty", "-o", "hash_out", "-l", "4"])
+        .args(["--", "16_byte_file", "empty_file"])
+        .assert()
+        .code(2)
+        .stderr_eq(snapbox::str![[r#"
+Error with file empty_file: file is empty
+
+"#]]);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "generate-hash", "--skip-empty", "-o", "hash_skipped", "-l", "4"])
+        .args(["--", "16_byte_file", "empty_file"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Warning: skipping empty file empty_file
+...
+"#]]);
+    let skipped_file = std::fs::read_to_string(test_cwd.path().join("hash_skipped")).unwrap();
+    assert!(!skipped_file.contains("\"empty_file\""));
+    assert!(skipped_file.contains("\"16_byte_file\" 0x10 bytes\n"));
+
+    // Empty files are still hashed by default
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-o", "hash_all", "-l", "4"])
+        .args(["--", "16_byte_file", "empty_file"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_all")).unwrap();
+    assert!(hash_file.contains("\"empty_file\" 0x0 bytes\n"));
+}
+
+#[test]
+fn tree_draws_each_file() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&ref_cwd)
+        .args(["tree", "--max-nodes", "6", "--", "hash_out"])
+        .assert()
+        .success()
+        .stdout_eq(snapbox::str![[r#"
+"16_byte_file":
+[0x00000000-0x00000003] 883c8d07
++-- [0x00000000-0x00000000] aa7eaf18
++-- [0x00000001-0x00000001] acd63e32
++-- [0x00000002-0x00000002] d686db71
+`-- [0x00000003-0x00000003] d21c451a
+"empty_file":
+[0x00000000-0x00000000] 6e340b9c
+
+"#]])
+        .stderr_eq(snapbox::str![[r#"
+Warning: skipping the tree of 20_byte_file with 8 nodes, more than --max-nodes of 6
+
+"#]]);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&ref_cwd)
+        .args(["tree", "--hash-chars", "4", "--", "hash_out"])
+        .assert()
+        .success()
+        .stdout_eq(snapbox::str![[r#"
+...
+"20_byte_file":
+[0x00000000-0x0000000f] c41e
++-- [0x00000000-0x00000003] 883c
+|   +-- [0x00000000-0x00000000] aa7e
+|   +-- [0x00000001-0x00000001] acd6
+|   +-- [0x00000002-0x00000002] d686
+|   `-- [0x00000003-0x00000003] d21c
+`-- [0x00000004-0x00000007] 19ed
+    `-- [0x00000004-0x00000004] daf0
+...
+"#]]);
+}
+
+#[test]
+fn check_coverage_reports_overlap() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&ref_cwd)
+        .args(["-qq", "verify-hash", "--check-coverage", "--", "hash_out"])
+        .assert()
+        .success();
+    // Reported before any file is hashed
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&ref_cwd)
+        .args(["verify-hash", "--check-coverage", "--", "hash_out_badbyterange"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error verifying file 16_byte_file: leaf entries overlap over byte range [0x00000008-0x0000000a)
+
+"#]]);
+}
+
+#[test]
+fn dedup_hashes_identical_files_once() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+    std::fs::copy(ref_cwd.join("16_byte_file"), test_cwd.path().join("16_byte_copy")).unwrap();
+
+    // Only the first copy gets hashed
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "generate-hash", "--dedup", "-o", "hash_dedup", "-l", "4", "-b", "2"])
+        .args(["--", "16_byte_file", "20_byte_file", "16_byte_copy"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Hashing 16_byte_file...
+Done
+Hashing 20_byte_file...
+Done
+Info: 16_byte_copy has the same contents as 16_byte_file, hashes reused
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-o", "hash_plain", "-l", "4", "-b", "2"])
+        .args(["--", "16_byte_file", "20_byte_file", "16_byte_copy"])
+        .assert()
+        .success();
+    let dedup_file = std::fs::read_to_string(test_cwd.path().join("hash_dedup")).unwrap();
+    let plain_file = std::fs::read_to_string(test_cwd.path().join("hash_plain")).unwrap();
+    assert_eq!(dedup_file, plain_file);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "hash_dedup"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn normalize_eol_hashes_crlf_as_lf() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("crlf_file"), b"first line\r\nsecond line\r\n").unwrap();
+    std::fs::write(test_cwd.path().join("lf_file"), b"first line\nsecond line\n").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--normalize-eol", "-o", "crlf_hash", "-l", "4",
+            "--", "crlf_file"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--text", "-o", "lf_hash", "-l", "4", "--", "lf_file"])
+        .assert()
+        .success();
+    // Lengths and byte ranges are of the normalized data
+    let crlf_hash = std::fs::read_to_string(test_cwd.path().join("crlf_hash")).unwrap();
+    let lf_hash = std::fs::read_to_string(test_cwd.path().join("lf_hash")).unwrap();
+    assert!(crlf_hash.contains("Normalized line endings: true\n"));
+    assert!(crlf_hash.contains("\"crlf_file\" 0x17 bytes\n"));
+    assert_eq!(crlf_hash.replace("\"crlf_file\"", "\"lf_file\""), lf_hash);
+
+    // Verification normalizes from the header alone
+    std::fs::rename(test_cwd.path().join("crlf_file"), test_cwd.path().join("lf_file")).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--", "lf_hash"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn checkpoint_matches_plain_output() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    // --checkpoint sends entries through the write loop even without threads
+    for (output_name, thread_args) in [("hash_checkpoint", ["-j", "0"]), ("hash_checkpoint_mt", ["-j", "2"])] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-qq"])
+            .args(thread_args)
+            .args(["generate-hash", "--checkpoint", "3", "-o", output_name, "-l", "4", "-b", "2"])
+            .args(["--", "16_byte_file", "20_byte_file"])
+            .assert()
+            .success();
+    }
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-o", "hash_plain", "-l", "4", "-b", "2"])
+        .args(["--", "16_byte_file", "20_byte_file"])
+        .assert()
+        .success();
+    let plain_file = std::fs::read_to_string(test_cwd.path().join("hash_plain")).unwrap();
+    for output_name in ["hash_checkpoint", "hash_checkpoint_mt"] {
+        let checkpoint_file = std::fs::read_to_string(test_cwd.path().join(output_name)).unwrap();
+        assert_eq!(checkpoint_file, plain_file);
+    }
+
+    // A hash file cut off partway through its last file does not verify
+    let cut_len = plain_file.rfind("\n  1 ").unwrap() + 1;
+    std::fs::write(test_cwd.path().join("hash_cut"), &plain_file[..cut_len]).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "hash_cut"])
+        .assert()
+        .failure()
+        .stderr_eq(snapbox::str![[r#"
+Warning: Verification of long hashes may fail early if the hash file is malformed, even when --fail-fast is not specified
+Hashing 16_byte_file...
+Info: 16_byte_file hash matches
+Hashing 20_byte_file...
+Error verifying file 20_byte_file: unexpected EOF
+
+"#]]);
+}
+
+#[test]
+fn any_order_verifies_reordered_sections() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+    std::fs::copy(ref_cwd.join("hash_out_reordered"),
+        test_cwd.path().join("hash_out_reordered")).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--fail-fast", "hash_out_reordered"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+================================= 16_byte_file =================================
+Error verifying file 16_byte_file: found entry for different file
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--any-order", "--fail-fast", "hash_out_reordered"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Hashing 16_byte_file...
+Info: 16_byte_file hash matches
+Hashing 20_byte_file...
+Info: 20_byte_file hash matches
+Hashing empty_file...
+Info: empty_file hash matches
+
+"#]]);
+
+    // The entries of a file cannot be split up
+    let reordered = std::fs::read_to_string(test_cwd.path().join("hash_out_reordered")).unwrap();
+    let (header, entries) = reordered.split_once("Hashes:\n").unwrap();
+    let mut entry_lines: Vec<&str> = entries.lines().collect();
+    entry_lines.swap(0, 1);
+    std::fs::write(test_cwd.path().join("hash_out_split"),
+        format!("{}Hashes:\n{}\n", header, entry_lines.join("\n"))).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--any-order", "hash_out_split"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Warning: Verification of long hashes may fail early if the hash file is malformed, even when --fail-fast is not specified
+Error indexing hash file hash_out_split: entries for file 1 are not next to each other
+
+"#]]);
+}
+
+#[test]
+fn stream_matches_listed_short_output() {
+    let test_cwd = tempdir().unwrap();
+    // Enough files that listing them up front would be noticeable
+    for dir_index in 0..20 {
+        let dir_path = test_cwd.path().join("tree").join(format!("dir{}", dir_index));
+        std::fs::create_dir_all(&dir_path).unwrap();
+        for file_index in 0..100 {
+            std::fs::write(dir_path.join(format!("file{}", file_index)),
+                format!("{} {}\n", dir_index, file_index)).unwrap();
+        }
+    }
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--stream", "-s", "-o", "hash_stream", "--", "tree"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[""]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-s", "-o", "hash_listed", "--", "tree"])
+        .assert()
+        .success();
+    // Both follow the order of the directory walk
+    let stream_file = std::fs::read_to_string(test_cwd.path().join("hash_stream")).unwrap();
+    let listed_file = std::fs::read_to_string(test_cwd.path().join("hash_listed")).unwrap();
+    assert_eq!(stream_file, listed_file);
+    assert_eq!(stream_file.lines().filter(|line| line.ends_with('"')).count(), 2000);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "hash_stream"])
+        .assert()
+        .success();
+
+    // Only short hash files have no list of files before the hashes
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--stream", "-o", "hash_long", "--", "tree"])
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn max_nodes_bounds_recorded_lengths() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+    std::fs::copy(ref_cwd.join("hash_out"), test_cwd.path().join("hash_out")).unwrap();
+
+    // 20_byte_file has the largest tree, with 8 nodes
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--max-nodes", "8", "hash_out"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--max-nodes", "7", "hash_out"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error: file 20_byte_file has a recorded length of 0x14 bytes, which needs more than --max-nodes 7 tree nodes
+
+"#]]);
+}
+
+#[test]
+fn block_size_not_power_of_two_warns() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "generate-hash", "-l", "4097", "-o", "hash_odd", "--"])
+        .args(INPUT_FILE_LIST)
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Warning: block length 4097 is not a power of 2, so blocks will not line up with disk sectors or pages
+Hashing 16_byte_file...
+Done
+Hashing 20_byte_file...
+Done
+Hashing empty_file...
+Done
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-l", "4097", "-o", "hash_odd_quiet", "--"])
+        .args(INPUT_FILE_LIST)
+        .assert()
+        .success()
+        .stderr_eq("");
+
+    // An explicit byte suffix gives the same block size, which needs no warning
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-l", "4096B", "-o", "hash_suffix", "--"])
+        .args(INPUT_FILE_LIST)
+        .assert()
+        .success()
+        .stderr_eq("");
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-l", "4096", "-o", "hash_plain", "--"])
+        .args(INPUT_FILE_LIST)
+        .assert()
+        .success();
+    let hash_suffix = std::fs::read(test_cwd.path().join("hash_suffix")).unwrap();
+    let hash_plain = std::fs::read(test_cwd.path().join("hash_plain")).unwrap();
+    assert_eq!(hash_suffix, hash_plain);
+}
+
+#[test]
+fn throttle_limits_read_rate() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("4000_byte_file"), vec![0x5a; 4000]).unwrap();
+
+    // Reading 4000 bytes at 10000 bytes per second takes at least 0.4 seconds
+    let hash_start = std::time::Instant::now();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "--throttle", "10K", "generate-hash", "-l", "256",
+            "-o", "hash_throttled", "--", "4000_byte_file"])
+        .assert()
+        .success();
+    assert!(hash_start.elapsed() >= std::time::Duration::from_millis(400));
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "--throttle", "0", "generate-hash",
+            "-o", "hash_zero", "--", "4000_byte_file"])
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn dry_run_prints_long_format_size() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--dry-run", "-l", "4", "--"])
+        .args(INPUT_FILE_LIST)
+        .assert()
+        .success()
+        .stdout_eq(snapbox::str![[r#"
+"16_byte_file": 4 leaf nodes, 1 internal nodes, 585 bytes
+"20_byte_file": 5 leaf nodes, 3 internal nodes, 936 bytes
+"empty_file": 1 leaf nodes, 0 internal nodes, 117 bytes
+Total: 10 leaf nodes, 4 internal nodes, 1638 bytes
+
+"#]]);
+
+    // The sizes are those of the entries that get written
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-l", "4", "-o", "hash_out", "--"])
+        .args(INPUT_FILE_LIST)
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    let entries_start = hash_file.find("Hashes:\n").unwrap() + "Hashes:\n".len();
+    assert_eq!(hash_file.len() - entries_start, 1638);
+}
+
+#[test]
+fn file_jobs_match_sequential() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    // Repeating the files gives the later jobs a chance to finish first
+    let file_args: Vec<&str> = INPUT_FILE_LIST.iter().cycle().take(12).copied().collect();
+    for short_args in [&[][..], &["--short"][..]] {
+        for (hash_name, file_jobs) in [("hash_sequential", "1"), ("hash_parallel", "4")] {
+            Command::new(cargo_bin!("merkle_tree_checksum"))
+                .current_dir(&test_cwd)
+                .args(["-qq", "generate-hash", "--overwrite", "-o", hash_name, "-l", "4"])
+                .args(["--file-jobs", file_jobs])
+                .args(short_args)
+                .arg("--")
+                .args(&file_args)
+                .assert()
+                .success();
+        }
+        let sequential_file = std::fs::read(test_cwd.path().join("hash_sequential")).unwrap();
+        let parallel_file = std::fs::read(test_cwd.path().join("hash_parallel")).unwrap();
+        assert_eq!(sequential_file, parallel_file);
+    }
+}
+
+#[test]
+fn root_out_matches_short_hashes() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    // Long output takes the roots from the last entry of each file
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-o", "hash_out", "--root-out", "roots", "-l", "4"])
+        .arg("--")
+        .args(INPUT_FILE_LIST)
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-o", "hash_short", "--short", "-l", "4"])
+        .arg("--")
+        .args(INPUT_FILE_LIST)
+        .assert()
+        .success();
+
+    let roots = std::fs::read_to_string(test_cwd.path().join("roots")).unwrap();
+    let short_hashes = std::fs::read_to_string(test_cwd.path().join("hash_short")).unwrap();
+    let short_lines: Vec<&str> = short_hashes.lines().rev().take(INPUT_FILE_LIST.len()).collect();
+    let root_lines: Vec<&str> = roots.lines().collect();
+    assert_eq!(root_lines.len(), INPUT_FILE_LIST.len());
+    for ((root_line, short_line), input_file) in root_lines.iter()
+            .zip(short_lines.iter().rev()).zip(INPUT_FILE_LIST) {
+        // Same as the short entry, without quotes around the name
+        let (hash_hex, name) = root_line.split_once("  ").unwrap();
+        assert!(hash_hex.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(name, *input_file);
+        assert_eq!(*short_line, format!("{}  \"{}\"", hash_hex, name));
+    }
+}
+
+#[test]
+fn coreutils_matches_sha256sum() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--coreutils", "--short", "-f", "sha256", "-o", "sums"])
+        .arg("--")
+        .args(INPUT_FILE_LIST)
+        .assert()
+        .success();
+    // Output of sha256sum for the same files
+    let sums = std::fs::read_to_string(test_cwd.path().join("sums")).unwrap();
+    snapbox::assert_data_eq!(sums, snapbox::str![[r#"
+4cc2268a829ec7e442d514d4e4af1bb47224831138080c551161261275f5aa53  16_byte_file
+8cb19f93f0b29cea2bc4f334b9b3ab3c39c456fbd5f06eaa364959f5c111ca88  20_byte_file
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  empty_file
+
+"#]]);
+}
+
+#[test]
+fn max_failures_stops_early() {
+    let test_cwd = tempdir().unwrap();
+    let file_names = ["bad_1", "bad_2", "bad_3"];
+    for file_name in file_names {
+        std::fs::write(test_cwd.path().join(file_name), b"original").unwrap();
+    }
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--short", "-o", "hash_out", "--"])
+        .args(file_names)
+        .assert()
+        .success();
+    for file_name in file_names {
+        std::fs::write(test_cwd.path().join(file_name), b"modified").unwrap();
+    }
+
+    // The third file is never checked
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--max-failures", "2", "hash_out"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error verifying file bad_1: hash mismatch:
+...
+Error verifying file bad_2: hash mismatch:
+...
+Error: stopping because --max-failures was reached
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--max-failures", "4", "hash_out"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+...
+Error verifying file bad_3: hash mismatch:
+...
+"#]]);
+}
+
+#[test]
+fn hashes_from_command_output() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let hash_file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/verify_cmd/sha256_verify.in/hash_out");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+    std::fs::copy(hash_file_path, test_cwd.path().join("fixture_hashes")).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--hashes-from-command", "cat fixture_hashes"])
+        .assert()
+        .success();
+    // The verify command reads the same hash file from stdin
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--hashes-from-command", "cat fixture_hashes"])
+        .args(["--verify-command", "cmp fixture_hashes"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--hashes-from-command", "cat fixture_hashes; exit 4"])
+        .assert()
+        .code(101)
+        .stderr_eq(snapbox::str![[r#"
+Error: hashes command cat fixture_hashes; exit 4 exited with exit status: 4
+
+"#]]);
+}
+
+#[test]
+fn convert_long_to_short() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    for (out_name, extra_args) in [("hash_out", &[][..]), ("hash_short", &["--short"][..])] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-qq", "generate-hash", "-o", out_name, "-l", "4"])
+            .args(extra_args)
+            .arg("--")
+            .args(INPUT_FILE_LIST)
+            .assert()
+            .success();
+    }
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["convert", "--to-short", "-o", "hash_converted", "hash_out"])
+        .assert()
+        .success();
+    let converted = std::fs::read(test_cwd.path().join("hash_converted")).unwrap();
+    let short_hashes = std::fs::read(test_cwd.path().join("hash_short")).unwrap();
+    assert_eq!(converted, short_hashes);
+
+    // The other hashes of a long hash file cannot be recovered from a short one
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["convert", "--to-long", "hash_short"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error: short hash files cannot be converted to long hash files without the file data, so use generate-hash instead
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["convert", "--to-short", "hash_short"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error: unable to convert hash file to short: it is already a short hash file
+
+"#]]);
+}
+
+#[test]
+fn algorithm_id_in_info_and_root_out() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-o", "hash_out", "--root-out", "roots",
+            "--algorithm-id", "-l", "4"])
+        .arg("--")
+        .args(INPUT_FILE_LIST)
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["info", "--algorithm-id", "hash_out"])
+        .assert()
+        .success()
+        .stdout_eq(snapbox::str![[r#"
+Hash function: SHA-256
+OID: 2.16.840.1.101.3.4.2.1
+
+"#]]);
+    // Commented out so that checksum tools skip them
+    let roots = std::fs::read_to_string(test_cwd.path().join("roots")).unwrap();
+    let root_lines: Vec<&str> = roots.lines().collect();
+    assert_eq!(root_lines[..2], ["# Hash function: SHA-256", "# OID: 2.16.840.1.101.3.4.2.1"]);
+    assert_eq!(root_lines.len(), 2 + INPUT_FILE_LIST.len());
+}
+
+#[test]
+fn relative_to_output_bundle_moves() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    let bundle_dir = test_cwd.path().join("bundle");
+    std::fs::create_dir(&bundle_dir).unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), bundle_dir.join(input_file)).unwrap();
+    }
+
+    let input_paths: Vec<String> = INPUT_FILE_LIST.iter()
+        .map(|input_file| format!("bundle/{}", input_file))
+        .collect();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--relative-to-output", "-o", "bundle/hash_out", "-l", "4"])
+        .arg("--")
+        .args(&input_paths)
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(bundle_dir.join("hash_out")).unwrap();
+    assert!(hash_file.contains("\n\"16_byte_file\" 0x10 bytes\n"));
+
+    // Names resolve against the new location of the hash file
+    let moved_dir = test_cwd.path().join("elsewhere/moved");
+    std::fs::create_dir(test_cwd.path().join("elsewhere")).unwrap();
+    std::fs::rename(&bundle_dir, &moved_dir).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--", "elsewhere/moved/hash_out"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&moved_dir)
+        .args(["-qq", "verify-hash", "--", "hash_out"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--relative-to-cwd", "--", "elsewhere/moved/hash_out"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error with file 16_byte_file: file not found
+Error with file 20_byte_file: file not found
+Error with file empty_file: file not found
+
+"#]]);
+}
+
+#[test]
+fn tiny_block_length_warning() {
+    let test_cwd = tempdir().unwrap();
+    // Sparse, as only the length is needed for --dry-run
+    let big_file = std::fs::File::create(test_cwd.path().join("big_file")).unwrap();
+    big_file.set_len(16*1024*1024).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--dry-run", "-l", "1", "--", "big_file"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Warning: block length 1 gives file big_file 22369621 tree nodes, which will be slow to hash; consider a block length of at least 32
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--dry-run", "-l", "1", "--", "big_file"])
+        .assert()
+        .success()
+        .stderr_eq("");
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "--dry-run", "-l", "1K", "--", "big_file"])
+        .assert()
+        .success()
+        .stderr_eq("");
+}
+
+#[test]
+fn nominal_ranges_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcd1234efghij").unwrap();
+
+    for (hash_file, extra_args) in [("actual_hash", &[][..]), ("nominal_hash", &["--nominal-ranges"][..])] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-q", "-q", "generate-hash", "-l", "4", "-o", hash_file])
+            .args(extra_args)
+            .args(["--", "data"])
+            .assert()
+            .success();
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-q", "-q", "verify-hash", "--", hash_file])
+            .assert()
+            .success();
+    }
+    // The last leaf ends at the end of the file or at the end of its block
+    let actual_hash = std::fs::read_to_string(test_cwd.path().join("actual_hash")).unwrap();
+    assert!(actual_hash.contains(" [0x00000003-0x00000003] [0x0000000c-0x0000000d] "));
+    assert!(!actual_hash.contains("Nominal byte ranges"));
+    let nominal_hash = std::fs::read_to_string(test_cwd.path().join("nominal_hash")).unwrap();
+    assert!(nominal_hash.contains("Nominal byte ranges: true\n"));
+    assert!(nominal_hash.contains(" [0x00000003-0x00000003] [0x0000000c-0x0000000f] "));
+    assert!(nominal_hash.contains(" [0x00000000-0x00000003] [0x00000000-0x0000000f] "));
+
+    // Ranges have to match the header
+    let stripped_hash = nominal_hash.replace("Nominal byte ranges: true\n", "")
+        .replace("Features: nominal-ranges\n", "");
+    std::fs::write(test_cwd.path().join("stripped_hash"), stripped_hash).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "-q", "verify-hash", "--", "stripped_hash"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error verifying file data: byte range in entry does not match its block range:
+  stored:   [0x0000000c-0x0000000f]
+  derived:  [0x0000000c-0x0000000d]
+...
+"#]]);
+}
+
+#[test]
+fn migrate_old_format() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST.iter().chain(&["hash_out_v0_5"]) {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["migrate", "-o", "hash_migrated", "hash_out_v0_5"])
+        .assert()
+        .success();
+    let format_version = Command::new(cargo_bin!("merkle_tree_checksum"))
+        .arg("--format-version")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let format_version = String::from_utf8(format_version).unwrap();
+    // Only the version line changes, as the file has no options that need features
+    let old_hashes = std::fs::read_to_string(test_cwd.path().join("hash_out_v0_5")).unwrap();
+    let migrated = std::fs::read_to_string(test_cwd.path().join("hash_migrated")).unwrap();
+    assert_eq!(migrated.split_once('\n').unwrap().0,
+        format!("merkle_tree_checksum v{}", format_version.trim_end()));
+    assert_eq!(migrated.split_once('\n').unwrap().1, old_hashes.split_once('\n').unwrap().1);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--", "hash_migrated"])
+        .assert()
+        .success();
+
+    // Versions that cannot be read have no parser to migrate them with
+    std::fs::write(test_cwd.path().join("hash_out_v0_4"),
+        old_hashes.replace("v0.5.0", "v0.4.0")).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["migrate", "hash_out_v0_4"])
+        .assert()
+        .code(1)
+        .stdout_eq("")
+        .stderr_eq(snapbox::str![[r#"
+Error: unable to migrate hash file hash_out_v0_4: format version 0.4.0 is older than 0.5.0, the oldest format version that can be read, so generate the hash file again instead
+
+"#]]);
+}
+
+#[test]
+fn stride_roundtrip() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcdefghij").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "-q", "generate-hash", "-l", "4", "-b", "2", "--stride", "2",
+            "-o", "stride_hash", "--", "data"])
+        .assert()
+        .success();
+    // Leaves of four bytes start every two bytes
+    let stride_hash = std::fs::read_to_string(test_cwd.path().join("stride_hash")).unwrap();
+    assert!(stride_hash.contains("Stride: 2\n"));
+    assert!(stride_hash.contains(" [0x00000001-0x00000001] [0x00000002-0x00000005] "));
+    assert!(stride_hash.contains(" [0x00000003-0x00000003] [0x00000006-0x00000009] "));
+    assert!(stride_hash.contains(" [0x00000000-0x00000003] [0x00000000-0x00000009] "));
+    for args in [&["verify-hash", "--", "stride_hash"][..], &["lint", "--", "stride_hash"][..]] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-q", "-q"])
+            .args(args)
+            .assert()
+            .success();
+    }
+
+    // A byte in the overlap of two leaves is reported by the first of them
+    std::fs::write(test_cwd.path().join("data"), b"abcdeXghij").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "-q", "verify-hash", "--", "stride_hash"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error verifying file data: hash mismatch over byte range [0x00000002-0x00000005]:
+...
+"#]]);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-l", "4", "--stride", "5", "-o", "wide_hash", "--", "data"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error: --stride must be at most the block length
+
+"#]]);
+}
+
+#[test]
+fn crc32_warning_on_verify() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"abcd1234efghij").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-f", "crc32", "-o", "hash_out", "--", "data"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Warning: CRC32 is not cryptographically secure and will only prevent accidental corruption
+...
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--", "hash_out"])
+        .assert()
+        .success()
+        .stderr_eq("");
+}
+
+#[test]
+fn uppercase_hashes_verify() {
+    let ref_cwd = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/reference_files");
+    let test_cwd = tempdir().unwrap();
+    for input_file in INPUT_FILE_LIST {
+        std::fs::copy(ref_cwd.join(input_file), test_cwd.path().join(input_file)).unwrap();
+    }
+
+    for (hash_file, extra_args) in [("long_hash", &[][..]), ("short_hash", &["--short"][..])] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-qq", "generate-hash", "--uppercase", "-l", "4", "-o", hash_file])
+            .args(extra_args)
+            .arg("--")
+            .args(INPUT_FILE_LIST)
+            .assert()
+            .success();
+        let hash_text = std::fs::read_to_string(test_cwd.path().join(hash_file)).unwrap();
+        let hashes: Vec<&str> = hash_text.lines()
+            .skip_while(|line| *line != "Hashes:")
+            .skip(1)
+            .map(|line| line.split_whitespace().find(|part| part.len() == 64).unwrap())
+            .collect();
+        assert!(!hashes.is_empty());
+        for hash in hashes {
+            assert!(hash.bytes().all(|digit| matches!(digit, b'0'..=b'9' | b'A'..=b'F')), "{}", hash);
+        }
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-qq", "verify-hash", "--", hash_file])
+            .assert()
+            .success();
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_names_rejected() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let test_cwd = tempdir().unwrap();
+    std::fs::create_dir(test_cwd.path().join("dir")).unwrap();
+    std::fs::write(test_cwd.path().join("dir").join(OsStr::from_bytes(b"bad\xffname")),
+        b"data").unwrap();
+
+    for extra_args in [&[][..], &["--short", "--stream"][..]] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["generate-hash", "-o", "hash_out", "--overwrite"])
+            .args(extra_args)
+            .args(["--", "dir"])
+            .assert()
+            .code(2)
+            .stderr_eq(snapbox::str![[r#"
+Error with directory dir: name "dir/bad[..]name" is not valid UTF-8, so it cannot be stored in a hash file
+...
+"#]]);
+    }
+    std::fs::create_dir(test_cwd.path().join("dir").join(OsStr::from_bytes(b"empty\xff"))).unwrap();
+    std::fs::remove_file(test_cwd.path().join("dir").join(OsStr::from_bytes(b"bad\xffname"))).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["generate-hash", "-o", "hash_out", "--overwrite", "--record-empty-dirs", "--", "dir"])
+        .assert()
+        .code(2)
+        .stderr_eq(snapbox::str![[r#"
+Error with directory dir: name "dir/empty[..]" is not valid UTF-8, so it cannot be stored in a hash file
+...
+"#]]);
+}
+
+#[test]
+fn no_recurse_rejects_directories() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::create_dir(test_cwd.path().join("dir")).unwrap();
+    std::fs::write(test_cwd.path().join("dir").join("inner"), b"inner file").unwrap();
+    std::fs::write(test_cwd.path().join("outer"), b"outer file").unwrap();
+
+    // Directories are expanded by default
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--short", "-o", "recursed", "--", "outer", "dir"])
+        .assert()
+        .success();
+    let recursed = std::fs::read_to_string(test_cwd.path().join("recursed")).unwrap();
+    assert!(recursed.contains("  \"dir/inner\"\n"));
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--no-recurse", "--short", "-o", "not_recursed",
+            "--", "outer", "dir"])
+        .assert()
+        .code(2)
+        .stderr_eq(snapbox::str![[r#"
+Error with file dir: is a directory, which --no-recurse does not expand
+
+"#]]);
+    assert!(!test_cwd.path().join("not_recursed").exists());
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--no-recurse", "--short", "-o", "not_recursed",
+            "--", "outer"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn follow_verifies_appended_data() {
+    let test_cwd = tempdir().unwrap();
+    let log_path = test_cwd.path().join("log");
+    std::fs::write(&log_path, b"0123456789").unwrap();
+    let generate_hash = |block_len: &str| {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-qq", "generate-hash", "-l", block_len, "-b", "2",
+                "-o", "log.hash.tmp", "--", "log"])
+            .assert()
+            .success();
+        // Replaced by renaming, so that it is never read half-written
+        std::fs::rename(test_cwd.path().join("log.hash.tmp"),
+            test_cwd.path().join("log.hash")).unwrap();
+    };
+    generate_hash("4");
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            let mut log_file = std::fs::OpenOptions::new().append(true)
+                .open(&log_path).unwrap();
+            std::io::Write::write_all(&mut log_file, b"abcdefghijkl").unwrap();
+            drop(log_file);
+            generate_hash("4");
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            // Other leaves, so verification starts over
+            generate_hash("8");
+        });
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["verify-hash", "--follow", "--follow-interval", "20",
+                "--follow-idle-exit", "1500", "--", "log.hash"])
+            .assert()
+            .success()
+            .stderr_eq(snapbox::str![[r#"
+Info: verified the first 10 bytes of log, reading from byte 0
+Info: verified the first 22 bytes of log, reading from byte 8
+Info: hash file log.hash was rewritten, verifying log from the start
+Info: verified the first 22 bytes of log, reading from byte 0
+
+"#]]);
+    });
+
+    // Data that never catches up with the hash file is reported once idle
+    std::fs::write(&log_path, b"0123").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--follow", "--follow-interval", "20",
+            "--follow-idle-exit", "100", "--", "log.hash"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Error verifying file log: only 4 of its 22 recorded bytes were written
+
+"#]]);
+}
+
+#[test]
+fn verify_positions_passes() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("4000_byte_file"), vec![0x5a; 4000]).unwrap();
+
+    for jobs in ["0", "2"] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-qq", "--verify-positions", "-j", jobs, "generate-hash", "-l", "256",
+                "--overwrite", "-o", "hash_file", "--", "4000_byte_file"])
+            .assert()
+            .success();
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-qq", "--verify-positions", "-j", jobs, "verify-hash", "hash_file"])
+            .assert()
+            .success();
+    }
+}
+
+#[test]
+fn stack_size_deep_tree() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("4000_byte_file"), vec![0x5a; 4000]).unwrap();
+
+    for jobs in ["0", "2"] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-qq", "--stack-size", "16Mi", "-j", jobs, "generate-hash", "-l", "1",
+                "--overwrite", "-o", "hash_file", "--", "4000_byte_file"])
+            .assert()
+            .success();
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-qq", "--stack-size", "16Mi", "-j", jobs, "verify-hash", "hash_file"])
+            .assert()
+            .success();
+    }
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["--stack-size", "0", "verify-hash", "hash_file"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+error: invalid value '0' for '--stack-size <BYTES>': stack size must be greater than 0
+...
+"#]]);
+}
+
+#[test]
+fn results_file_lists_each_file() {
+    let test_cwd = tempdir().unwrap();
+    let file_names = ["good", "bad", "gone"];
+    for file_name in file_names {
+        std::fs::write(test_cwd.path().join(file_name), b"original").unwrap();
+    }
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--short", "-o", "hash_out", "--"])
+        .args(file_names)
+        .assert()
+        .success();
+    std::fs::write(test_cwd.path().join("bad"), b"modified").unwrap();
+    std::fs::remove_file(test_cwd.path().join("gone")).unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--results", "results.jsonl", "hash_out"])
+        .assert()
+        .code(3);
+    let results = std::fs::read_to_string(test_cwd.path().join("results.jsonl")).unwrap();
+    snapbox::assert_data_eq!(results, snapbox::str![[r#"
+{"file_index":0,"names":["good"],"outcome":"ok"}
+{"file_index":1,"names":["bad"],"outcome":"failed","error":"hash mismatch:[..]"}
+{"file_index":2,"names":["gone"],"outcome":"failed","error":"file not found"}
+
+"#]]);
+
+    // Long hash files stop at the first mismatch, after writing its result
+    std::fs::write(test_cwd.path().join("data"), vec![0x5a; 1000]).unwrap();
+    std::fs::write(test_cwd.path().join("reference"), vec![0x5a; 1000]).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-l", "256", "-o", "long_hash_out", "--", "data"])
+        .assert()
+        .success();
+    let mut modified_data = vec![0x5a; 1000];
+    modified_data[0x258] = 0x00;
+    std::fs::write(test_cwd.path().join("data"), modified_data).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--results", "results.jsonl",
+            "--byte-diff", "--reference", "reference", "long_hash_out"])
+        .assert()
+        .code(3);
+    let results = std::fs::read_to_string(test_cwd.path().join("results.jsonl")).unwrap();
+    snapbox::assert_data_eq!(results, snapbox::str![[r#"
+{"file_index":0,"names":["data"],"outcome":"failed","error":"hash mismatch over byte range [0x00000200-0x000002ff]:[..]","mismatched_byte_range":[512,767],"first_differing_byte":600}
+
+"#]]);
+}
+
+#[test]
+fn malformed_short_entry_skipped() {
+    let test_cwd = tempdir().unwrap();
+    let file_names = ["first", "second", "third"];
+    for file_name in file_names {
+        std::fs::write(test_cwd.path().join(file_name), file_name).unwrap();
+    }
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--short", "-o", "hash_out", "--"])
+        .args(file_names)
+        .assert()
+        .success();
+    // Corrupt the hex of the middle entry while keeping its name readable
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    let hash_file: Vec<String> = hash_file.lines().map(|line| match line.ends_with("\"second\"") {
+        true => format!("z{}", &line[1..]),
+        false => line.to_owned()
+    }).collect();
+    std::fs::write(test_cwd.path().join("hash_out"), hash_file.join("\n") + "\n").unwrap();
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--results", "results.jsonl", "hash_out"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r#"
+Hashing first...
+Info: first hash matches
+Hashing second...
+Error verifying file second: found malformed entry z[..]  "second"
+
+Hashing third...
+Info: third hash matches
+
+"#]]);
+    let results = std::fs::read_to_string(test_cwd.path().join("results.jsonl")).unwrap();
+    snapbox::assert_data_eq!(results, snapbox::str![[r#"
+{"file_index":0,"names":["first"],"outcome":"ok"}
+{"file_index":1,"names":["second"],"outcome":"failed","error":"found malformed entry z[..]"}
+{"file_index":2,"names":["third"],"outcome":"ok"}
+
+"#]]);
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--fail-fast", "--results", "results.jsonl", "hash_out"])
+        .assert()
+        .code(3);
+    let results = std::fs::read_to_string(test_cwd.path().join("results.jsonl")).unwrap();
+    assert_eq!(results.lines().count(), 2);
+}
+
+#[test]
+fn self_test_passes() {
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .args(["-q", "self-test"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Info: crc32 passed
+...
+Info: ascon passed
+
+"#]]);
+}
+
+#[test]
+fn tee_saves_and_hashes_stdin() {
+    let test_cwd = tempdir().unwrap();
+    let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-l", "256", "--tee", "saved", "-o", "tee_hash",
+            "--", "-"])
+        .stdin(data.clone())
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(test_cwd.path().join("saved")).unwrap(), data);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "tee_hash"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+...
+Info: saved hash matches
+
+"#]]);
+
+    // Same hash file as hashing the saved file
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-l", "256", "-o", "file_hash", "--", "saved"])
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(test_cwd.path().join("tee_hash")).unwrap(),
+        std::fs::read(test_cwd.path().join("file_hash")).unwrap());
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--tee", "other", "-o", "other_hash",
+            "--", "saved"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error: --tee reads the data from stdin, so the only file must be -
+
+"#]]);
+}
+
+#[test]
+fn sectioned_hash_file() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("a"), b"first file").unwrap();
+    std::fs::write(test_cwd.path().join("b"), b"second file").unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--sectioned", "-b", "4", "-o", "hash_out", "--", "a", "b"])
+        .assert()
+        .success();
+    let hash_file = std::fs::read_to_string(test_cwd.path().join("hash_out")).unwrap();
+    assert!(hash_file.contains("Features: sectioned\n"));
+    assert!(hash_file.contains("Hashes:\n# file 0: \"a\"\n  0 "));
+    assert!(hash_file.contains("\n# file 1: \"b\"\n  1 "));
+
+    for verify_args in [&["verify-hash"][..], &["verify-hash", "--any-order"], &["lint"]] {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .arg("-qq")
+            .args(verify_args)
+            .arg("hash_out")
+            .assert()
+            .success();
+    }
+
+    // A marker naming another file is not left out, so the entries after it are malformed
+    std::fs::write(test_cwd.path().join("hash_bad"),
+        hash_file.replace("# file 1: \"b\"", "# file 1: \"c\"")).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "hash_bad"])
+        .assert()
+        .failure();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["lint", "hash_bad"])
+        .assert()
+        .code(3)
+        .stderr_eq(snapbox::str![[r##"
+Error: line [..]: section marker "# file 1: /"c/"" does not name a listed file
+Error: line [..]: entries of file id 1 do not start with a section marker
+...
+"##]]);
+}
+
+#[test]
+fn filter_command_output_hashed() {
+    let test_cwd = tempdir().unwrap();
+    std::fs::write(test_cwd.path().join("data"), b"0123456789abcdef").unwrap();
+    let generate = |hash_file: &str, filter: Option<&str>| {
+        let filter_args = match filter {
+            Some(filter) => vec!["--filter-command", filter],
+            None => vec![]
+        };
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["-qq", "generate-hash", "-b", "4", "-o", hash_file])
+            .args(filter_args)
+            .args(["--", "data"])
+            .assert()
+            .success();
+        let hash_file = std::fs::read_to_string(test_cwd.path().join(hash_file)).unwrap();
+        hash_file.lines().last().unwrap().to_owned()
+    };
+    // Filters that pass the data through unchanged give the same root,
+    // and other filters give the same root every time
+    assert_eq!(generate("hash_plain", None), generate("hash_cat", Some("cat")));
+    let reversed_root = generate("hash_rev", Some("rev"));
+    assert_ne!(reversed_root, generate("hash_plain_again", None));
+    assert_eq!(reversed_root, generate("hash_rev_again", Some("rev")));
+    let hash_rev = std::fs::read_to_string(test_cwd.path().join("hash_rev")).unwrap();
+    assert!(hash_rev.contains("Features: filtered\n"));
+    assert!(!hash_rev.contains("rev\n"));
+
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--filter-command", "rev", "hash_rev"])
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--filter-command", "cat", "hash_rev"])
+        .assert()
+        .code(3);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "hash_rev"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error: hash file was generated with --filter-command, which verification also needs
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--filter-command", "rev", "hash_plain"])
+        .assert()
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error: --filter-command cannot be used with hash files generated without it
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["verify-hash", "--filter-command", "false", "hash_rev"])
+        .assert()
+        .code(2)
+        .stderr_eq(snapbox::str![[r#"
+...
+Error with file data: unable to read data: filter command exited with exit status: 1
+...
+"#]]);
+}
+
+#[test]
+fn max_total_bytes_skips_remaining_files() {
+    let test_cwd = tempdir().unwrap();
+    let file_names = ["a", "b", "c", "d"];
+    for file_name in file_names {
+        std::fs::write(test_cwd.path().join(file_name), [0x5a; 100]).unwrap();
+    }
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-o", "hash_out", "--"])
+        .args(file_names)
+        .assert()
+        .success();
+
+    // The second file reaches the budget, so it is still verified in full
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "verify-hash", "--max-total-bytes", "150", "hash_out"])
+        .assert()
+        .code(4)
+        .stderr_eq(snapbox::str![[r#"
+Warning: Verification of long hashes may fail early [..]
+Hashing a...
+Info: a hash matches
+Hashing b...
+Info: b hash matches
+Warning: budget of 150 bytes from --max-total-bytes exhausted, skipping the remaining files
+Warning: skipping file c
+Warning: skipping file d
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--max-total-bytes", "400", "hash_out"])
+        .assert()
+        .success();
+
+    // Failures before the budget runs out take precedence
+    std::fs::write(test_cwd.path().join("a"), [0x00; 100]).unwrap();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "--max-total-bytes", "150", "hash_out"])
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn max_total_bytes_leaves_out_remaining_files() {
+    let test_cwd = tempdir().unwrap();
+    let file_names = ["a", "b", "c"];
+    for file_name in file_names {
+        std::fs::write(test_cwd.path().join(file_name), [0x5a; 100]).unwrap();
+    }
+
+    // The budget runs out in the middle of b, which is still hashed in full
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-q", "generate-hash", "--max-total-bytes", "150", "-o", "hash_out", "--"])
+        .args(file_names)
+        .assert()
+        .code(4)
+        .stderr_eq(snapbox::str![[r#"
+Warning: budget of 150 bytes from --max-total-bytes exhausted, leaving out the remaining files
+Warning: skipping file c
+Hashing a...
+Done
+Hashing b...
+Done
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["info", "hash_out"])
+        .assert()
+        .success()
+        .stdout_eq(snapbox::str![[r#"
+"a"
+"b"
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "hash_out"])
+        .assert()
+        .success();
+
+    // Nothing is left out when the files fit
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--max-total-bytes", "300", "--overwrite",
+            "-o", "hash_out", "--"])
+        .args(file_names)
+        .assert()
+        .success();
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "--max-total-bytes", "150", "--stream", "--short",
+            "-o", "stream_out", "--"])
+        .args(file_names)
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn stats_blocks_counts_repeated_leaves() {
+    let test_cwd = tempdir().unwrap();
+    let mut file_data = vec![0x00; 60*4];
+    file_data.extend([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
+    std::fs::write(test_cwd.path().join("zeros"), file_data).unwrap();
+
+    // 60 zero blocks share one leaf hash, and 4 other blocks have their own
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "generate-hash", "-l", "4", "-b", "2", "-f", "crc32",
+            "--stats-blocks", "-o", "hash_out", "--", "zeros"])
+        .assert()
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Block stats:
+  Leaf nodes: 64
+  Internal nodes: 63
+  Distinct leaf hashes: 5
+  Most repeated leaf hash: [..] (60 leaves)
+
+"#]]);
+    Command::new(cargo_bin!("merkle_tree_checksum"))
+        .current_dir(&test_cwd)
+        .args(["-qq", "verify-hash", "hash_out"])
+        .assert()
+        .success();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn params_from_xattr() {
+    let test_cwd = tempdir().unwrap();
+    let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(test_cwd.path().join("data"), &data).unwrap();
+    std::fs::write(test_cwd.path().join("plain"), &data).unwrap();
+    std::fs::write(test_cwd.path().join("bad"), &data).unwrap();
+    let params = b"Hash function: sha512\nBranching factor: 2\nBlock size: 16\n";
+    match xattr::set(test_cwd.path().join("data"), "user.merkle.params", params) {
+        Ok(()) => {},
+        // Some filesystems that tempdir may be on have no user attributes
+        Err(err) if err.kind() == std::io::ErrorKind::Unsupported => return,
+        Err(err) => panic!("unable to set attribute: {}", err)
+    }
+    xattr::set(test_cwd.path().join("bad"), "user.merkle.params",
+        b"Hash function: sha512\nBlock size: 16\n").unwrap();
+
+    let generate = |out_name: &str, args: &[&str], file: &str| {
+        Command::new(cargo_bin!("merkle_tree_checksum"))
+            .current_dir(&test_cwd)
+            .args(["generate-hash", "-o", out_name])
+            .args(args)
+            .args(["--", file])
+            .assert()
+    };
+    let read_hash = |out_name: &str| {
+        std::fs::read_to_string(test_cwd.path().join(out_name)).unwrap()
+    };
+
+    generate("xattr_hash", &["--params-from-xattr"], "data").success();
+    generate("flag_hash", &["-f", "sha512", "-b", "2", "-l", "16"], "data").success();
+    assert_eq!(read_hash("xattr_hash"), read_hash("flag_hash"));
+
+    // Flags on the command line take precedence over the attribute
+    generate("override_hash", &["--params-from-xattr", "-l", "32"], "data").success();
+    generate("override_flag_hash", &["-f", "sha512", "-b", "2", "-l", "32"], "data").success();
+    assert_eq!(read_hash("override_hash"), read_hash("override_flag_hash"));
+
+    generate("plain_hash", &["--params-from-xattr"], "plain")
+        .success()
+        .stderr_eq(snapbox::str![[r#"
+Warning: no user.merkle.params attribute on plain: no tree parameters are stored, using the flag values
+...
+"#]]);
+    generate("default_hash", &[], "plain").success();
+    assert_eq!(read_hash("plain_hash"), read_hash("default_hash"));
+
+    generate("bad_hash", &["--params-from-xattr"], "bad")
+        .code(1)
+        .stderr_eq(snapbox::str![[r#"
+Error reading user.merkle.params attribute of bad: stored tree parameters are missing Branching factor
+
+"#]]);
+}
@@ -8,7 +8,7 @@ const HASH_FUNCTION_LIST: &[&str] = &["crc32",
     "sha224", "sha256", "sha384", "sha512" ,"sha512_224", "sha512_256",
     "sha3_224", "sha3_256", "sha3_384", "sha3_512",
     "blake2b512", "blake2s256",
-    "blake3"];
+    "blake3", "k12", "ascon"];
 
 // Hash functions with name aliases
 const HASH_FUNCTION_ALTNAME_LIST: &[&str] = &["sha512trunc224",
@@ -180,13 +180,14 @@ fn verify_cmd_tests() {
 fn verify_short_cmd_tests() {
     // We're only doing sha256 for now; update if doing parametric generation
     let test_dir = PathBuf::from("tests/verify_short_cmd");
-    cmd_test_helper(&test_dir, ["sha256_verify_short"], false);
+    cmd_test_helper(&test_dir,
+        ["sha256_verify_short", "sha256_verify_short_nonewline"], false);
 }
 
 #[test]
 fn verify_bad_cmd_tests() {
     // We're only doing sha256 for now; update if doing parametric generation
-    let suffix_list = ["badhash", "badlen", "badlen_last", "malformed", "short_badhash", "short_malformed"];
+    let suffix_list = ["badbranch", "badbyterange", "badhash", "badhashlen", "badlen", "badlen_last", "malformed", "short_badhash", "short_malformed", "unknownfeature", "hugelen", "truncated", "short_twolists"];
     let input_testcase_tuples = suffix_list
         .map(|s| (format!("hash_out_{}", s), format!("sha256_verify_{}", s)));
     for (input_name, testcase) in input_testcase_tuples.iter() {
@@ -196,7 +197,13 @@ fn verify_bad_cmd_tests() {
         toml_path.push(testcase.clone() + ".toml");
 
         let mut toml_content = VERIFY_BAD_TEMPLATE.replace("FILENAME", input_name);
-        let expected_status = match input_name.find("badlen") {
+        // Bad lengths and bad headers are caught before hashing
+        let expected_status = match input_name.find("badlen")
+                .or_else(|| input_name.find("badbranch"))
+                .or_else(|| input_name.find("unknownfeature"))
+                .or_else(|| input_name.find("hugelen"))
+                .or_else(|| input_name.find("truncated"))
+                .or_else(|| input_name.find("twolists")) {
             Some(_) => 1,
             None => 3
         };